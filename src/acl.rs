@@ -0,0 +1,416 @@
+use crate::cmd;
+use crate::glob::glob_match;
+use crate::resp::Value;
+use crate::sha256::sha256;
+use bytes::Bytes;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{LazyLock, Mutex};
+
+/// An ACL user: whether it can log in and with what, and which commands, keys and channels it
+/// may touch once it has.
+#[derive(Clone)]
+struct User {
+    enabled: bool,
+    /// Any password logs in, as with the default user when no requirepass is set.
+    nopass: bool,
+    /// SHA-256 hashes of the passwords that log in.
+    passwords: Vec<[u8; 32]>,
+    /// Commands the user may run, as resolved from `command_rules`.
+    allowed: BTreeSet<&'static str>,
+    /// The `+`/`-` rules that produced `allowed`, for ACL LIST and GETUSER.
+    command_rules: Vec<String>,
+    key_patterns: Vec<Vec<u8>>,
+    channel_patterns: Vec<Vec<u8>>,
+    /// The namespace the user's keys are confined to, if any: each is stored as
+    /// `<namespace>:<key>`, and it sees no others.
+    namespace: Option<String>,
+}
+
+impl User {
+    /// A user as ACL SETUSER first creates it: disabled, and allowed nothing.
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            allowed: BTreeSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            key_patterns: Vec::new(),
+            channel_patterns: Vec::new(),
+            namespace: None,
+        }
+    }
+
+    fn allow(&mut self, commands: Vec<&'static str>, allowed: bool) {
+        for command in commands {
+            if allowed {
+                self.allowed.insert(command);
+            } else {
+                self.allowed.remove(command);
+            }
+        }
+    }
+
+    /// Applies one ACL SETUSER rule, or explains why it's invalid.
+    fn apply(&mut self, rule: &str) -> Result<(), &'static str> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.key_patterns = vec![b"*".to_vec()],
+            "resetkeys" => self.key_patterns.clear(),
+            "allchannels" => self.channel_patterns = vec![b"*".to_vec()],
+            "resetchannels" => self.channel_patterns.clear(),
+            "resetnamespace" => self.namespace = None,
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => {
+                for rule in [
+                    "resetpass",
+                    "resetkeys",
+                    "resetchannels",
+                    "resetnamespace",
+                    "off",
+                    "-@all",
+                ] {
+                    self.apply(rule)?;
+                }
+            }
+            _ => match rule.split_once(':') {
+                Some((keyword, namespace)) if keyword.eq_ignore_ascii_case("namespace") => {
+                    if namespace.is_empty() || namespace.contains(char::is_whitespace) {
+                        return Err("Invalid namespace name");
+                    }
+                    self.namespace = Some(namespace.to_string());
+                }
+                _ => return self.apply_prefixed(rule),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn apply_prefixed(&mut self, rule: &str) -> Result<(), &'static str> {
+        let Some(first) = rule.chars().next() else {
+            return Err("Syntax error");
+        };
+        let rest = &rule[first.len_utf8()..];
+
+        match first {
+            '>' => {
+                let hash = sha256(rest.as_bytes());
+                if !self.passwords.contains(&hash) {
+                    self.passwords.push(hash);
+                }
+                self.nopass = false;
+            }
+            '<' => {
+                let hash = sha256(rest.as_bytes());
+                if !self.passwords.contains(&hash) {
+                    return Err("no such password");
+                }
+                self.passwords.retain(|p| *p != hash);
+            }
+            '#' | '!' => {
+                let hash = parse_hash(rest).ok_or(
+                    "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters",
+                )?;
+                if first == '!' {
+                    self.passwords.retain(|p| *p != hash);
+                } else if !self.passwords.contains(&hash) {
+                    self.passwords.push(hash);
+                    self.nopass = false;
+                }
+            }
+            '~' => self.key_patterns.push(rest.as_bytes().to_vec()),
+            '&' => self.channel_patterns.push(rest.as_bytes().to_vec()),
+            '+' | '-' => {
+                let allowed = first == '+';
+                let commands = match rest.strip_prefix('@') {
+                    Some(category) => cmd::category_commands(&category.to_lowercase()),
+                    None => cmd::category_commands("all").and_then(|all| {
+                        let name = rest.to_lowercase();
+                        all.into_iter().find(|c| *c == name).map(|c| vec![c])
+                    }),
+                }
+                .ok_or("Unknown command or category name in ACL")?;
+                self.allow(commands, allowed);
+
+                // +@all and -@all make every earlier rule moot
+                if rest.eq_ignore_ascii_case("@all") {
+                    self.command_rules.clear();
+                }
+                self.command_rules.push(rule.to_lowercase());
+            }
+            _ => return Err("Syntax error"),
+        }
+
+        Ok(())
+    }
+
+    /// The user's rules as ACL LIST shows them, minus the `user <name>` prefix.
+    fn describe(&self) -> String {
+        let mut parts = vec![if self.enabled { "on" } else { "off" }.to_string()];
+        if self.nopass {
+            parts.push("nopass".to_string());
+        }
+        parts.extend(self.passwords.iter().map(|hash| format!("#{}", hex(hash))));
+        parts.extend(patterns(&self.key_patterns, '~'));
+        if self.channel_patterns.is_empty() {
+            parts.push("resetchannels".to_string());
+        } else {
+            parts.extend(patterns(&self.channel_patterns, '&'));
+        }
+        if let Some(namespace) = &self.namespace {
+            parts.push(format!("namespace:{namespace}"));
+        }
+        parts.extend(self.command_rules.iter().cloned());
+
+        parts.join(" ")
+    }
+}
+
+fn patterns(patterns: &[Vec<u8>], prefix: char) -> impl Iterator<Item = String> {
+    patterns
+        .iter()
+        .map(move |pattern| format!("{prefix}{}", String::from_utf8_lossy(pattern)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// A password hash given as 64 lowercase hex digits.
+fn parse_hash(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(hash)
+}
+
+/// Users by name. The `default` user always exists; connections start out logged in as it
+/// while it's enabled and needs no password.
+static USERS: LazyLock<Mutex<BTreeMap<String, User>>> =
+    LazyLock::new(|| Mutex::new(default_users()));
+
+/// The users there are to start with: `default`, who can do anything without a password.
+fn default_users() -> BTreeMap<String, User> {
+    let mut default = User::new();
+    for rule in ["on", "nopass", "allkeys", "allchannels", "+@all"] {
+        default.apply(rule).expect("default rules are valid");
+    }
+
+    BTreeMap::from([("default".to_string(), default)])
+}
+
+/// Forgets every user but `default`, and puts its rules back as they started.
+pub fn reset() {
+    *USERS.lock().unwrap() = default_users();
+}
+
+/// Sets the default user's password as requirepass does, or with `None` lets it log in without
+/// one.
+pub fn set_requirepass(password: Option<Vec<u8>>) {
+    let mut users = USERS.lock().unwrap();
+    let default = users
+        .get_mut("default")
+        .expect("default user always exists");
+
+    default.passwords.clear();
+    match password {
+        Some(password) => {
+            default.nopass = false;
+            default.passwords.push(sha256(&password));
+        }
+        None => default.nopass = true,
+    }
+}
+
+/// The user a new connection is logged in as before any AUTH, if any.
+pub fn initial_user() -> Option<String> {
+    let users = USERS.lock().unwrap();
+    let default = &users["default"];
+
+    (default.enabled && default.nopass).then(|| "default".to_string())
+}
+
+/// Whether the `default` user has a password, so that single-argument AUTH makes sense.
+pub fn default_has_password() -> bool {
+    !USERS.lock().unwrap()["default"].nopass
+}
+
+/// Whether `user` exists and `password` logs in as it.
+pub fn authenticate(user: &str, password: &[u8]) -> bool {
+    let users = USERS.lock().unwrap();
+    let Some(user) = users.get(user) else {
+        return false;
+    };
+
+    // Compare every hash without stopping early, so timing doesn't give away which matched
+    let hash = sha256(password);
+    let matched = user.passwords.iter().fold(false, |matched, stored| {
+        matched
+            | (stored
+                .iter()
+                .zip(&hash)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0)
+    });
+
+    user.enabled && (user.nopass || matched)
+}
+
+pub fn exists(user: &str) -> bool {
+    USERS.lock().unwrap().contains_key(user)
+}
+
+/// Checks `user` may run command `name` with `args`, against its commands, keys and channels.
+pub fn check(user: &str, name: &str, args: &[Vec<u8>]) -> Result<(), Value> {
+    let users = USERS.lock().unwrap();
+    let Some(acl) = users.get(user) else {
+        return Err(Value::error("NOAUTH Authentication required."));
+    };
+
+    // Unknown commands are left for the dispatcher to reject as such
+    if !cmd::is_command(name) {
+        return Ok(());
+    }
+    if !acl.allowed.contains(name) {
+        return Err(Value::error(format!(
+            "NOPERM User {user} has no permissions to run the '{name}' command"
+        )));
+    }
+
+    let key_allowed = |key: &[u8]| {
+        acl.key_patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, key, false))
+    };
+    if !cmd::command_keys(name, args).into_iter().all(key_allowed) {
+        return Err(Value::error("NOPERM No permissions to access a key"));
+    }
+
+    let channel_allowed = |channel: &Vec<u8>| {
+        acl.channel_patterns.iter().any(|pattern| {
+            // A pattern subscription has to be to one of the user's patterns verbatim, since
+            // it could match channels outside them
+            if name == "psubscribe" {
+                pattern == channel || pattern == b"*"
+            } else {
+                glob_match(pattern, channel, false)
+            }
+        })
+    };
+    let channels = match name {
+        "subscribe" | "ssubscribe" | "psubscribe" => args,
+        "publish" | "spublish" => &args[..args.len().min(1)],
+        _ => &[],
+    };
+    if !channels.iter().all(channel_allowed) {
+        return Err(Value::error("NOPERM No permissions to access a channel"));
+    }
+
+    Ok(())
+}
+
+/// The prefix `user`'s keys are stored under, if it's confined to a namespace.
+pub fn namespace(user: &str) -> Option<Vec<u8>> {
+    let users = USERS.lock().unwrap();
+    let namespace = users.get(user)?.namespace.as_ref()?;
+
+    Some(format!("{namespace}:").into_bytes())
+}
+
+/// ACL SETUSER: creates `name` if needed and applies `rules`, all or nothing.
+pub fn set_user(name: &str, rules: &[Vec<u8>]) -> Result<(), Value> {
+    let mut users = USERS.lock().unwrap();
+    let mut user = users.get(name).cloned().unwrap_or_else(User::new);
+
+    for rule in rules {
+        let rule = String::from_utf8_lossy(rule);
+        user.apply(&rule).map_err(|reason| {
+            Value::error(format!(
+                "ERR Error in ACL SETUSER modifier '{rule}': {reason}"
+            ))
+        })?;
+    }
+    users.insert(name.to_string(), user);
+
+    Ok(())
+}
+
+/// ACL GETUSER's reply for `name`, or `None` if there's no such user.
+pub fn get_user(name: &str) -> Option<Value> {
+    let users = USERS.lock().unwrap();
+    let user = users.get(name)?;
+
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    let mut flags = vec![bulk(if user.enabled { "on" } else { "off" })];
+    if user.nopass {
+        flags.push(bulk("nopass"));
+    }
+    let joined = |patterns: &[Vec<u8>], prefix| {
+        bulk(
+            &self::patterns(patterns, prefix)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    };
+
+    Some(Value::Array(vec![
+        bulk("flags"),
+        Value::Array(flags),
+        bulk("passwords"),
+        Value::Array(user.passwords.iter().map(|hash| bulk(&hex(hash))).collect()),
+        bulk("commands"),
+        bulk(&user.command_rules.join(" ")),
+        bulk("keys"),
+        joined(&user.key_patterns, '~'),
+        bulk("channels"),
+        joined(&user.channel_patterns, '&'),
+        bulk("namespace"),
+        bulk(user.namespace.as_deref().unwrap_or_default()),
+        bulk("selectors"),
+        Value::Array(Vec::new()),
+    ]))
+}
+
+/// ACL LIST: one `user <name> <rules>` line per user.
+pub fn list() -> Vec<String> {
+    USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, user)| format!("user {name} {}", user.describe()))
+        .collect()
+}
+
+pub fn user_names() -> Vec<String> {
+    USERS.lock().unwrap().keys().cloned().collect()
+}
+
+/// ACL DELUSER: removes the named users, returning how many existed.
+pub fn delete(names: &[Vec<u8>]) -> Result<usize, Value> {
+    let mut users = USERS.lock().unwrap();
+    if names.iter().any(|name| name == b"default") {
+        return Err(Value::error("ERR The 'default' user cannot be removed"));
+    }
+
+    Ok(names
+        .iter()
+        .filter(|name| users.remove(&*String::from_utf8_lossy(name)).is_some())
+        .count())
+}
@@ -0,0 +1,266 @@
+use crate::blocking::BlockedClients;
+use crate::client;
+use crate::cmd::info;
+use crate::config;
+use crate::db::{DBData, DBVal, Db};
+use crate::http::{self, Request};
+use crate::json::Json;
+use crate::pubsub::PubSub;
+use crate::resp::Value;
+use crate::slowlog;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+
+/// The most elements of a collection `/keys` shows; its `length` still counts them all.
+const MAX_ELEMENTS: usize = 1000;
+
+/// SLOWLOG GET's fields, in the order it gives them.
+const SLOWLOG_FIELDS: &[&str] = &[
+    "id",
+    "timestamp",
+    "duration",
+    "command",
+    "client_addr",
+    "client_name",
+];
+
+/// Parameters whose values `/config` leaves out.
+const SECRET_PARAMETERS: &[&str] = &["requirepass"];
+
+/// What the server's handing out for the admin API.
+#[derive(Clone)]
+struct State {
+    db: Db,
+    pubsub: Arc<PubSub>,
+    blocked: Arc<BlockedClients>,
+}
+
+/// Answers the admin API's read-only JSON endpoints on `listener`: `/info`, `/clients`,
+/// `/slowlog`, `/config` and `/keys/<name>`. Runs for as long as the server does.
+pub async fn serve(
+    listener: TcpListener,
+    db: Db,
+    pubsub: Arc<PubSub>,
+    blocked: Arc<BlockedClients>,
+) {
+    let state = State {
+        db,
+        pubsub,
+        blocked,
+    };
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(answer(stream, state.clone()));
+    }
+}
+
+async fn answer(mut stream: TcpStream, state: State) {
+    let Some(request) = http::read_request(&mut stream).await else {
+        return;
+    };
+
+    let (status, body) = match route(&request, &state).await {
+        Ok(body) => ("200 OK", body),
+        Err((status, message)) => (status, Json::object([("error", Json::str(message))])),
+    };
+    http::respond(
+        &mut stream,
+        status,
+        "application/json",
+        &format!("{body}\n"),
+    )
+    .await;
+}
+
+type Response = Result<Json, (&'static str, String)>;
+
+async fn route(request: &Request, state: &State) -> Response {
+    let not_found = || {
+        Err((
+            "404 Not Found",
+            format!("No such endpoint {}", request.path),
+        ))
+    };
+
+    if request.method != "GET" {
+        return Err((
+            "405 Method Not Allowed",
+            format!(
+                "The admin API is read-only, so {} isn't allowed",
+                request.method
+            ),
+        ));
+    }
+    match request.path.as_str() {
+        "/info" => Ok(server_info(request, state).await),
+        "/clients" => Ok(clients()),
+        "/slowlog" => slowlog(request),
+        "/config" => Ok(config()),
+        path => match path.strip_prefix("/keys/") {
+            Some(key) if !key.is_empty() => lookup(request, state, &http::decode(key)).await,
+            _ => not_found(),
+        },
+    }
+}
+
+/// INFO, as an object of sections, each an object of fields. `?section=` picks the sections as
+/// INFO's arguments do, comma separated.
+async fn server_info(request: &Request, state: &State) -> Json {
+    let sections: Vec<Vec<u8>> = request
+        .param("section")
+        .map(|sections| sections.split(',').map(|s| s.as_bytes().to_vec()).collect())
+        .unwrap_or_default();
+    let dbs = state.db.lock_all().await;
+    let Value::BulkString(text) = info::info(&dbs, &state.pubsub, &state.blocked, &sections) else {
+        return Json::Object(Vec::new());
+    };
+    drop(dbs);
+
+    let mut result: Vec<(String, Json)> = Vec::new();
+    for line in String::from_utf8_lossy(&text).lines() {
+        if let Some(title) = line.strip_prefix("# ") {
+            result.push((title.to_lowercase(), Json::Object(Vec::new())));
+        } else if let (Some((name, value)), Some((_, Json::Object(fields)))) =
+            (line.split_once(':'), result.last_mut())
+        {
+            fields.push((name.to_string(), Json::str(value)));
+        }
+    }
+    Json::Object(result)
+}
+
+/// CLIENT LIST, with an object for each connection.
+fn clients() -> Json {
+    let clients = client::list(|_, _| true).into_iter().map(|line| {
+        let fields = line
+            .split(' ')
+            .filter_map(|field| field.split_once('='))
+            .map(|(name, value)| (name.to_string(), Json::str(value)))
+            .collect();
+        Json::Object(fields)
+    });
+    Json::Array(clients.collect())
+}
+
+/// SLOWLOG GET, newest entry first, for up to `?count=` entries or all of them.
+fn slowlog(request: &Request) -> Response {
+    let count = match request.param("count") {
+        Some(count) => count
+            .parse()
+            .map_err(|_| ("400 Bad Request", format!("Invalid count {count}")))?,
+        None => usize::MAX,
+    };
+    let Value::Array(entries) = slowlog::get(count) else {
+        return Ok(Json::Array(Vec::new()));
+    };
+
+    let entries = entries.into_iter().map(|entry| match entry {
+        Value::Array(fields) => Json::Object(
+            SLOWLOG_FIELDS
+                .iter()
+                .map(|name| name.to_string())
+                .zip(fields.into_iter().map(Json::from))
+                .collect(),
+        ),
+        entry => Json::from(entry),
+    });
+    Ok(Json::Array(entries.collect()))
+}
+
+/// CONFIG GET *, with passwords left out.
+fn config() -> Json {
+    let parameters = config::matching(&[b"*".to_vec()])
+        .into_iter()
+        .map(|(name, value)| {
+            let value = if SECRET_PARAMETERS.contains(&name) && !value.is_empty() {
+                Json::str("(hidden)")
+            } else {
+                Json::str(value)
+            };
+            (name.to_string(), value)
+        });
+    Json::Object(parameters.collect())
+}
+
+/// A key in database `?db=`, or 0: its type, encoding, TTL in milliseconds (-1 for none) and
+/// value.
+async fn lookup(request: &Request, state: &State, key: &[u8]) -> Response {
+    let databases = config::get().databases;
+    let index = match request.param("db") {
+        Some(db) => db
+            .parse()
+            .ok()
+            .filter(|index| *index < databases)
+            .ok_or(("400 Bad Request", format!("Invalid db {db}")))?,
+        None => 0,
+    };
+
+    let dbs = state.db.lock_keys(&[key]).await;
+    // Looking doesn't count as an access, nor does it reap an expired key
+    let Some(val) = dbs[index].get_unexpired(key) else {
+        return Err((
+            "404 Not Found",
+            format!("No such key {}", String::from_utf8_lossy(key)),
+        ));
+    };
+
+    Ok(Json::object([
+        ("key", Json::str(String::from_utf8_lossy(key))),
+        ("db", Json::Int(index as i64)),
+        ("type", Json::str(val.data().type_name())),
+        ("encoding", Json::str(val.data().encoding())),
+        ("ttl", Json::Int(val.ttl().map_or(-1, |ttl| ttl as i64))),
+        ("length", Json::Int(length(val) as i64)),
+        ("value", value(val.data())),
+    ]))
+}
+
+fn length(val: &DBData) -> usize {
+    match val.data() {
+        DBVal::String(s) => s.len(),
+        DBVal::Int(n) => n.to_string().len(),
+        DBVal::List(list) => list.len(),
+        DBVal::Hash(hash) => hash.len(),
+        DBVal::Set(set) => set.len(),
+        DBVal::ZSet(zset) => zset.len(),
+        DBVal::Stream(stream) => stream.len(),
+    }
+}
+
+/// A value as JSON: strings and the elements of collections as strings, hashes as objects,
+/// sorted sets as `[member, score]` pairs in order, and streams as `[id, fields]` pairs.
+fn value(val: &DBVal) -> Json {
+    let bytes = |bytes: &[u8]| Json::str(String::from_utf8_lossy(bytes));
+    let fields = |fields: &mut dyn Iterator<Item = (&Vec<u8>, &Vec<u8>)>| {
+        let fields = fields.map(|(name, value)| (String::from_utf8_lossy(name), bytes(value)));
+        Json::object(fields.take(MAX_ELEMENTS))
+    };
+
+    match val {
+        DBVal::String(s) => bytes(s),
+        DBVal::Int(n) => Json::str(n.to_string()),
+        DBVal::List(list) => {
+            Json::Array(list.iter().take(MAX_ELEMENTS).map(|e| bytes(e)).collect())
+        }
+        DBVal::Hash(hash) => fields(&mut hash.iter()),
+        DBVal::Set(set) => Json::Array(set.iter().take(MAX_ELEMENTS).map(|m| bytes(&m)).collect()),
+        DBVal::ZSet(zset) => Json::Array(
+            zset.iter()
+                .take(MAX_ELEMENTS)
+                .map(|(member, score)| Json::Array(vec![bytes(member), Json::Float(score)]))
+                .collect(),
+        ),
+        DBVal::Stream(stream) => Json::Array(
+            stream
+                .iter()
+                .take(MAX_ELEMENTS)
+                .map(|(id, entry)| {
+                    let entry = fields(&mut entry.iter().map(|(name, value)| (name, value)));
+                    Json::Array(vec![Json::str(id.to_string()), entry])
+                })
+                .collect(),
+        ),
+    }
+}
@@ -0,0 +1,67 @@
+/// What jemalloc knows of the memory it manages, which the system allocator can't say.
+pub struct Stats {
+    /// Bytes the server has allocated.
+    pub allocated: u64,
+    /// Bytes in the pages those allocations are in, which counts their fragmentation.
+    pub active: u64,
+    /// Bytes in pages the allocator holds that are resident, which also counts its own
+    /// metadata and the dirty pages it's yet to return.
+    pub resident: u64,
+}
+
+/// The allocator, as INFO's `mem_allocator` names it.
+#[cfg(feature = "jemalloc")]
+pub fn name() -> String {
+    let version = tikv_jemalloc_ctl::version::read().unwrap_or_default();
+    // Like "5.3.0-0-g54eaed1d8b56b1aa528be3bdd1877e59c56fa90c"
+    format!("jemalloc-{}", version.split('-').next().unwrap_or_default())
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn name() -> String {
+    "libc".to_string()
+}
+
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> Option<Stats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // The counters are only brought up to date when the epoch moves on
+    epoch::advance().ok()?;
+    Some(Stats {
+        allocated: stats::allocated::read().ok()? as u64,
+        active: stats::active::read().ok()? as u64,
+        resident: stats::resident::read().ok()? as u64,
+    })
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn stats() -> Option<Stats> {
+    None
+}
+
+/// Hands the pages freed allocations leave behind back to the OS, as MEMORY PURGE asks.
+#[cfg(feature = "jemalloc")]
+pub fn purge() {
+    // 4096 is MALLCTL_ARENAS_ALL, for every arena at once
+    // SAFETY: purging takes no arguments and returns nothing
+    unsafe {
+        tikv_jemalloc_sys::mallctl(
+            c"arena.4096.purge".as_ptr(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            0,
+        );
+    }
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn purge() {
+    // glibc's allocator can give back what's free at the top of its heaps
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    // SAFETY: malloc_trim only returns free memory
+    unsafe {
+        libc::malloc_trim(0);
+    }
+}
@@ -0,0 +1,732 @@
+use crate::cmd::format_float;
+use crate::config;
+use crate::crypt;
+use crate::db::{self, DBVal, Keyspace};
+use crate::dump::dump_value;
+use crate::resp::{self, Value};
+use crate::snapshot::{self, Frozen};
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU8, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How many elements a rewritten collection puts in each command.
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// When writes are flushed to disk, from `appendfsync`: after every write, once a second, or
+/// whenever the OS gets round to it.
+const FSYNC_ALWAYS: u8 = 0;
+const FSYNC_EVERYSEC: u8 = 1;
+const FSYNC_NO: u8 = 2;
+
+static FSYNC: AtomicU8 = AtomicU8::new(FSYNC_EVERYSEC);
+
+/// The open append-only file, while `appendonly` is on.
+static AOF: Mutex<Option<Aof>> = Mutex::new(None);
+
+/// Whether a BGREWRITEAOF is writing a new file out right now.
+static REWRITING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the last rewrite succeeded, and how many seconds it took, or -1 if there hasn't been
+/// one.
+pub static LAST_REWRITE_OK: AtomicBool = AtomicBool::new(true);
+pub static LAST_REWRITE_SECS: AtomicI64 = AtomicI64::new(-1);
+
+/// The file's size in bytes, and its size when it was last loaded or rewritten.
+pub static CURRENT_SIZE: AtomicU64 = AtomicU64::new(0);
+pub static BASE_SIZE: AtomicU64 = AtomicU64::new(0);
+
+struct Aof {
+    file: File,
+    /// What the file starts with if it's encrypted, which every write to it is sealed under.
+    header: Option<Vec<u8>>,
+    /// The database the file's commands last selected, if any.
+    db: Option<usize>,
+    /// Whether anything has been written since the last fsync.
+    unsynced: bool,
+    /// Commands written since a rewrite started, which the rewritten file needs on its end.
+    rewrite: Option<Buffer>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    commands: Vec<u8>,
+    db: Option<usize>,
+}
+
+impl Buffer {
+    /// Adds `command`, selecting `db` first if the commands before it left another selected.
+    fn push(&mut self, db: usize, command: &[u8]) {
+        if self.db != Some(db) {
+            write_command(
+                &mut self.commands,
+                vec![b"select".to_vec(), db.to_string().into_bytes()],
+            );
+            self.db = Some(db);
+        }
+        self.commands.extend_from_slice(command);
+    }
+}
+
+/// Where the append-only file lives: `appendfilename` in `dir`.
+pub fn path() -> PathBuf {
+    let config = config::get();
+    Path::new(&config.dir).join(&config.appendfilename)
+}
+
+pub fn set_fsync(policy: &str) {
+    let policy = match policy {
+        "always" => FSYNC_ALWAYS,
+        "no" => FSYNC_NO,
+        _ => FSYNC_EVERYSEC,
+    };
+    FSYNC.store(policy, Ordering::Relaxed);
+}
+
+pub fn is_on() -> bool {
+    AOF.lock().unwrap().is_some()
+}
+
+pub fn rewrite_in_progress() -> bool {
+    REWRITING.load(Ordering::Relaxed)
+}
+
+/// Whether the existing file is written the way writes would be appended to it: in the clear,
+/// or with the encryption key now in use. One that isn't needs starting afresh.
+pub fn written_as_configured() -> io::Result<bool> {
+    let mut start = Vec::new();
+    File::open(path())?
+        .take(crypt::HEADER_MAX as u64)
+        .read_to_end(&mut start)?;
+    Ok(crypt::written_as_configured(&start))
+}
+
+/// `bytes` as they're written to a file started with `header`: sealed as a record of their own
+/// if it's encrypted, or as they are.
+fn sealed<'a>(header: Option<&[u8]>, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+    match header {
+        Some(header) => Cow::Owned(crypt::seal(header, bytes)),
+        None => Cow::Borrowed(bytes),
+    }
+}
+
+/// Creates `path` to write a new file to, starting it with the header of the key it's written
+/// with if encryption is on, which is returned.
+fn create(path: &Path) -> io::Result<(File, Option<Vec<u8>>)> {
+    let mut file = File::create(path)?;
+    let header = crypt::header();
+    if let Some(header) = &header {
+        file.write_all(header)?;
+    }
+    Ok((file, header))
+}
+
+/// Starts appending to the existing file, which has just been loaded.
+pub fn open() -> io::Result<()> {
+    let file = OpenOptions::new().append(true).open(path())?;
+    let size = file.metadata()?.len();
+    CURRENT_SIZE.store(size, Ordering::Relaxed);
+    BASE_SIZE.store(size, Ordering::Relaxed);
+    *AOF.lock().unwrap() = Some(Aof {
+        file,
+        header: crypt::header(),
+        db: None,
+        unsynced: false,
+        rewrite: None,
+    });
+
+    Ok(())
+}
+
+/// Starts a new file holding the dataset as it stands, then appends to it from there. Used
+/// when there's no file to load, or when `appendonly` is turned on at runtime.
+pub fn start(dbs: &[Keyspace]) -> io::Result<()> {
+    let path = path();
+    let temporary = path.with_file_name(format!("temp-rewriteaof-{}.aof", std::process::id()));
+    let (mut file, header) = create(&temporary)?;
+    file.write_all(&sealed(header.as_deref(), &base(&snapshot::freeze(dbs))))?;
+    file.sync_all()?;
+    fs::rename(&temporary, &path)?;
+
+    open()?;
+    info!("Append only file created with the dataset as it stands");
+
+    Ok(())
+}
+
+/// Freezes the dataset, then turns it into the fewest commands that rebuild it and writes them
+/// out on another thread, so clients don't wait for either. Writes made meanwhile are kept
+/// aside and added to the end before the new file replaces the old one. Returns false without
+/// doing anything if a rewrite is already running.
+pub fn bgrewrite(dbs: &[Keyspace]) -> bool {
+    if REWRITING.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+
+    let frozen = snapshot::freeze(dbs);
+    if let Some(aof) = AOF.lock().unwrap().as_mut() {
+        aof.rewrite = Some(Buffer::default());
+    }
+    let path = path();
+    let temporary = path.with_file_name(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+    info!("Background append only file rewriting started");
+
+    let started = Instant::now();
+    tokio::task::spawn_blocking(move || {
+        let result = rewrite(&path, &temporary, &base(&frozen));
+        match &result {
+            Ok(()) => info!("Background AOF rewrite finished successfully"),
+            Err(e) => {
+                warn!("Background AOF rewrite error: {e}");
+                let _ = fs::remove_file(&temporary);
+                if let Some(aof) = AOF.lock().unwrap().as_mut() {
+                    aof.rewrite = None;
+                }
+            }
+        }
+        LAST_REWRITE_OK.store(result.is_ok(), Ordering::Relaxed);
+        LAST_REWRITE_SECS.store(started.elapsed().as_secs() as i64, Ordering::Relaxed);
+        REWRITING.store(false, Ordering::Relaxed);
+    });
+
+    true
+}
+
+/// Writes `commands` and whatever was buffered behind them to `temporary`, then moves it over
+/// the file at `path` and carries on appending to it.
+fn rewrite(path: &Path, temporary: &Path, commands: &[u8]) -> io::Result<()> {
+    let (mut file, header) = create(temporary)?;
+    file.write_all(&sealed(header.as_deref(), commands))?;
+    file.sync_data()?;
+
+    // Held until the swap, so no write lands in the old file after the buffer's been copied
+    let mut aof = AOF.lock().unwrap();
+    let mut db = None;
+    if let Some(aof) = aof.as_mut() {
+        match aof.rewrite.take() {
+            Some(buffer) if !buffer.commands.is_empty() => {
+                file.write_all(&sealed(header.as_deref(), &buffer.commands))?;
+                db = buffer.db;
+            }
+            Some(_) => {}
+            // Turned off and on again meanwhile, which started a newer file than this one
+            None => {
+                fs::remove_file(temporary)?;
+                return Ok(());
+            }
+        }
+    }
+    file.sync_data()?;
+    fs::rename(temporary, path)?;
+
+    let size = file.metadata()?.len();
+    CURRENT_SIZE.store(size, Ordering::Relaxed);
+    BASE_SIZE.store(size, Ordering::Relaxed);
+    if let Some(aof) = aof.as_mut() {
+        aof.file = file;
+        aof.header = header;
+        aof.db = db;
+        aof.unsynced = false;
+    }
+
+    Ok(())
+}
+
+/// Stops appending, when `appendonly` is turned off.
+pub fn stop() {
+    if let Some(aof) = AOF.lock().unwrap().take() {
+        let _ = aof.file.sync_data();
+    }
+}
+
+/// Loads the file at `path` into `dbs`, returning false if there's no file. A snapshot preamble
+/// is read straight in and the commands after it are replayed through `run`. A command cut short
+/// at the end, as by a crash mid-write, is dropped from the file with a warning.
+pub fn load(
+    path: &Path,
+    dbs: &mut [Keyspace],
+    mut run: impl FnMut(&mut [Keyspace], Vec<Vec<u8>>) -> anyhow::Result<()>,
+) -> anyhow::Result<bool> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let started = Instant::now();
+    replay(path, bytes, dbs, &mut run, true)?;
+
+    info!(
+        "DB loaded from append only file: {:.3} seconds",
+        started.elapsed().as_secs_f64()
+    );
+
+    Ok(true)
+}
+
+/// Loads the append-only files Redis 7 and later keep in `appenddirname`, for a server taking
+/// over from one, returning false if there aren't any. Their manifest,
+/// `<appendfilename>.manifest`, names a base file, which is a snapshot or commands, and files of
+/// the commands written after it, and each is loaded like [`load`]'s. Only the last can have
+/// been cut short by a crash.
+///
+/// The files are left as they are: the server carries on in a file of its own.
+pub fn load_redis(
+    dbs: &mut [Keyspace],
+    mut run: impl FnMut(&mut [Keyspace], Vec<Vec<u8>>) -> anyhow::Result<()>,
+) -> anyhow::Result<bool> {
+    let config = config::get();
+    let dir = Path::new(&config.dir).join(&config.appenddirname);
+    let path = dir.join(format!("{}.manifest", config.appendfilename));
+    let manifest = match fs::read_to_string(&path) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let files = parse_manifest(&manifest)
+        .map_err(|e| anyhow::anyhow!("bad manifest {}: {e}", path.display()))?;
+    let started = Instant::now();
+
+    for (i, file) in files.iter().enumerate() {
+        let path = dir.join(file);
+        let bytes =
+            fs::read(&path).map_err(|e| anyhow::anyhow!("can't read {}: {e}", path.display()))?;
+        replay(&path, bytes, dbs, &mut run, i + 1 == files.len())?;
+    }
+
+    info!(
+        "DB loaded from append only directory {}: {:.3} seconds",
+        dir.display(),
+        started.elapsed().as_secs_f64()
+    );
+
+    Ok(true)
+}
+
+/// The files a Redis manifest has loaded, in order: the base, then the increments by sequence
+/// number. History files, ones a rewrite has replaced but not yet deleted, are left out.
+fn parse_manifest(manifest: &str) -> Result<Vec<String>, String> {
+    let mut base = None;
+    let mut increments = Vec::new();
+    for line in manifest.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = config::split_line(line)?;
+        if fields.len() % 2 != 0 {
+            return Err(format!("invalid line '{line}'"));
+        }
+        let (mut file, mut seq, mut kind) = (None, None, None);
+        // Fields newer versions may add are skipped, as Redis skips them
+        for field in fields.chunks(2) {
+            match field[0].as_str() {
+                "file" => file = Some(field[1].clone()),
+                "seq" => seq = field[1].parse::<u64>().ok(),
+                "type" => kind = Some(field[1].clone()),
+                _ => {}
+            }
+        }
+        let (Some(file), Some(seq), Some(kind)) = (file, seq, kind) else {
+            return Err(format!("invalid line '{line}'"));
+        };
+        if file.contains(['/', '\\']) {
+            return Err(format!("'{file}' isn't a file in the directory"));
+        }
+
+        match kind.as_str() {
+            "b" if base.is_some() => return Err("more than one base file".to_string()),
+            "b" => base = Some(file),
+            "i" => increments.push((seq, file)),
+            "h" => {}
+            kind => return Err(format!("unknown file type '{kind}'")),
+        }
+    }
+    if base.is_none() && increments.is_empty() {
+        return Err("no files to load".to_string());
+    }
+
+    increments.sort();
+    Ok(base
+        .into_iter()
+        .chain(increments.into_iter().map(|(_, file)| file))
+        .collect())
+}
+
+/// A command read from a file, and the offset it was read at.
+type Located = (usize, Vec<Vec<u8>>);
+
+/// Replays `bytes`, read from `path`, into `dbs`. `last` is whether it's the last file being
+/// loaded, the only one writes could have been under way to when the server stopped.
+fn replay(
+    path: &Path,
+    mut bytes: Vec<u8>,
+    dbs: &mut [Keyspace],
+    run: &mut impl FnMut(&mut [Keyspace], Vec<Vec<u8>>) -> anyhow::Result<()>,
+    last: bool,
+) -> anyhow::Result<()> {
+    let encrypted = crypt::is_encrypted(&bytes);
+    if encrypted {
+        let opened = crypt::decrypt(&bytes)?;
+        if opened.len < bytes.len() {
+            cut_short(path, opened.len, last)?;
+        }
+        bytes = opened.plaintext;
+    }
+
+    let mut pos = 0;
+    if bytes.starts_with(b"REDIS") {
+        (pos, _) = snapshot::decode(&bytes, dbs)?;
+    }
+    // The commands of a transaction Redis wrote, each with where it is, held back until its
+    // EXEC, and where it started
+    let mut transaction: Option<(usize, Vec<Located>)> = None;
+    let mut run = |dbs: &mut [Keyspace], at: usize, command| {
+        run(dbs, command).map_err(|e| anyhow::anyhow!("Bad file format at offset {at}: {e}"))
+    };
+    while pos < bytes.len() {
+        // Annotations, like the `#TS:<unix time>` lines of Redis's aof-timestamp-enabled
+        if bytes[pos] == b'#' {
+            match bytes[pos..].iter().position(|&byte| byte == b'\n') {
+                Some(end) => pos += end + 1,
+                None => pos = bytes.len(),
+            }
+            continue;
+        }
+
+        let Some((command, len)) = parse_command(&bytes[pos..])
+            .map_err(|_| anyhow::anyhow!("bad file format at offset {pos}"))?
+        else {
+            // Each record holds whole commands, so a crash can't have cut one short
+            anyhow::ensure!(
+                !encrypted,
+                "command cut short at offset {pos} of the decrypted file"
+            );
+            // One inside a transaction goes back to where it started, below
+            if transaction.is_none() {
+                cut_short(path, pos, last)?;
+            }
+            break;
+        };
+        if command[0].eq_ignore_ascii_case(b"multi") {
+            transaction = Some((pos, Vec::new()));
+        } else if command[0].eq_ignore_ascii_case(b"exec") {
+            for (at, command) in transaction
+                .take()
+                .map(|(_, queued)| queued)
+                .unwrap_or_default()
+            {
+                run(dbs, at, command)?;
+            }
+        } else if let Some((_, queued)) = &mut transaction {
+            queued.push((pos, command));
+        } else {
+            run(dbs, pos, command)?;
+        }
+        pos += len;
+    }
+
+    // One the file ends partway through never took effect
+    if let Some((start, _)) = transaction {
+        warn!(
+            "Revert incomplete MULTI/EXEC transaction in AOF file {}",
+            path.display()
+        );
+        cut_short(path, start, last)?;
+    }
+
+    Ok(())
+}
+
+/// Drops what's after `len` bytes of the file at `path`, which a crash cut short, if it's the
+/// last being loaded. Any other was finished before the one after it was started.
+fn cut_short(path: &Path, len: usize, last: bool) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        last,
+        "{} is cut short at offset {len} but isn't the last file",
+        path.display()
+    );
+    warn!(
+        "!!! Warning: short read while loading the AOF file {}!!! Truncating the AOF at offset {len}",
+        path.display()
+    );
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(len as u64)?;
+    Ok(())
+}
+
+/// Parses the command at the start of `bytes`, returning it and how many bytes it took up, or
+/// None if they end partway through it.
+pub fn parse_command(bytes: &[u8]) -> anyhow::Result<Option<(Vec<Vec<u8>>, usize)>> {
+    // The parser's own errors quote everything after the fault, which may be the rest of the file
+    let parsed = resp::parse_message(bytes).map_err(|_| anyhow::anyhow!("expected a command"))?;
+    let (parts, len) = match parsed {
+        Some((Value::Array(parts), len)) if !parts.is_empty() => (parts, len),
+        Some(_) => anyhow::bail!("expected a command"),
+        None => return Ok(None),
+    };
+    let command = parts
+        .into_iter()
+        .map(|part| match part {
+            Value::BulkString(part) => Ok(part.into()),
+            _ => anyhow::bail!("expected a command's arguments as bulk strings"),
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Some((command, len)))
+}
+
+/// Appends a command that changed the dataset in database `db`. It's in the form
+/// [`crate::propagate`] gives it, which has the same effect whenever it's replayed.
+pub fn feed(db: usize, command: &[Vec<u8>]) {
+    let mut aof = AOF.lock().unwrap();
+    let Some(aof) = aof.as_mut() else {
+        return;
+    };
+
+    let mut encoded = Vec::new();
+    write_command(&mut encoded, command.to_vec());
+    if let Some(buffer) = aof.rewrite.as_mut() {
+        buffer.push(db, &encoded);
+    }
+
+    let mut out = Buffer {
+        commands: Vec::new(),
+        db: aof.db,
+    };
+    out.push(db, &encoded);
+    aof.db = out.db;
+    let written = sealed(aof.header.as_deref(), &out.commands);
+    if let Err(e) = aof.file.write_all(&written) {
+        warn!("Error writing to the AOF file: {e}");
+        return;
+    }
+    CURRENT_SIZE.fetch_add(written.len() as u64, Ordering::Relaxed);
+    if FSYNC.load(Ordering::Relaxed) != FSYNC_ALWAYS {
+        aof.unsynced = true;
+    } else if let Err(e) = aof.file.sync_data() {
+        // A client may already have been told the write is safe
+        warn!(
+            "Can't recover from AOF write error when the AOF fsync policy is 'always': {e}. Exiting..."
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Flushes the file to disk once a second under `appendfsync everysec`. Runs for as long as the
+/// server does.
+pub async fn sync_every_second() {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if FSYNC.load(Ordering::Relaxed) != FSYNC_EVERYSEC {
+            continue;
+        }
+
+        // Synced through a second handle, so writers don't wait on the disk
+        let file = match AOF.lock().unwrap().as_mut() {
+            Some(aof) if aof.unsynced => {
+                aof.unsynced = false;
+                aof.file.try_clone()
+            }
+            _ => continue,
+        };
+        let synced = tokio::task::spawn_blocking(move || file?.sync_data()).await;
+        if let Ok(Err(e)) = synced {
+            warn!("Error syncing the AOF file: {e}");
+        }
+    }
+}
+
+/// What a new file starts with: the dataset as a snapshot, or as commands if
+/// `aof-use-rdb-preamble` is off.
+fn base(frozen: &Frozen) -> Vec<u8> {
+    if config::get().aof_use_rdb_preamble {
+        snapshot::encode(frozen, true)
+    } else {
+        dataset_commands(frozen)
+    }
+}
+
+/// The commands that rebuild the frozen databases and function libraries from nothing.
+fn dataset_commands(frozen: &Frozen) -> Vec<u8> {
+    let mut out = Vec::new();
+    let bulk = |s: &str| s.as_bytes().to_vec();
+
+    for library in &frozen.libraries {
+        write_command(
+            &mut out,
+            vec![bulk("function"), bulk("load"), library.code.clone()],
+        );
+    }
+
+    let _now = db::unix_millis();
+    for (index, db) in frozen
+        .dbs
+        .iter()
+        .enumerate()
+        .filter(|(_, db)| !db.is_empty())
+    {
+        write_command(&mut out, vec![bulk("select"), bulk(&index.to_string())]);
+
+        for (key, val) in db.iter().filter(|(_, val)| !val.is_expired()) {
+            let expires_at = val.expires_at().map(|at| at.to_string());
+            // Batches a collection's elements into commands starting with `prefix`
+            let mut batched = |prefix: &[&str], items: Vec<Vec<u8>>, per_item: usize| {
+                for chunk in items.chunks(ITEMS_PER_COMMAND * per_item) {
+                    let mut command: Vec<Vec<u8>> = prefix.iter().map(|s| bulk(s)).collect();
+                    command.insert(1, key.to_vec());
+                    command.extend_from_slice(chunk);
+                    write_command(&mut out, command);
+                }
+            };
+
+            match (val.data(), expires_at) {
+                (DBVal::String(_) | DBVal::Int(_), expires_at) => {
+                    let value = val.data().string_bytes().unwrap_or_default().into_owned();
+                    let mut command = vec![bulk("set"), key.to_vec(), value];
+                    if let Some(at) = expires_at {
+                        command.extend([bulk("pxat"), bulk(&at)]);
+                    }
+                    write_command(&mut out, command);
+                }
+                // Only RESTORE can give any other type a TTL
+                (data, Some(at)) => write_command(
+                    &mut out,
+                    vec![
+                        bulk("restore"),
+                        key.to_vec(),
+                        bulk(&at),
+                        dump_value(data),
+                        bulk("absttl"),
+                    ],
+                ),
+                (DBVal::List(list), None) => batched(&["rpush"], list.iter().cloned().collect(), 1),
+                (DBVal::Set(set), None) => {
+                    batched(&["sadd"], set.iter().map(|m| m.into_owned()).collect(), 1)
+                }
+                (DBVal::ZSet(zset), None) => {
+                    let items = zset
+                        .iter()
+                        .flat_map(|(member, score)| {
+                            [format_float(score).into_bytes(), member.to_vec()]
+                        })
+                        .collect();
+                    batched(&["zadd"], items, 2);
+                }
+                (DBVal::Hash(hash), None) => {
+                    let items = hash
+                        .iter()
+                        .flat_map(|(field, value)| [field.clone(), value.clone()])
+                        .collect();
+                    batched(&["hset"], items, 2);
+                    for (field, at) in hash.expires() {
+                        write_command(
+                            &mut out,
+                            vec![
+                                bulk("hpexpireat"),
+                                key.to_vec(),
+                                bulk(&at.to_string()),
+                                bulk("fields"),
+                                bulk("1"),
+                                field.clone(),
+                            ],
+                        );
+                    }
+                }
+                (DBVal::Stream(stream), None) => {
+                    let xadd = |id: String, fields: &[(Vec<u8>, Vec<u8>)]| {
+                        let mut command = vec![bulk("xadd"), key.to_vec(), bulk(&id)];
+                        for (field, value) in fields {
+                            command.extend([field.clone(), value.clone()]);
+                        }
+                        command
+                    };
+                    for (id, fields) in stream.iter() {
+                        write_command(&mut out, xadd(id.to_string(), fields));
+                    }
+
+                    // Adding an entry at the last ID and taking it away again leaves the stream
+                    // remembering it
+                    let last_id = stream.last_id().to_string();
+                    let placeholder = [(bulk("x"), bulk("y"))];
+                    match stream.iter().next_back() {
+                        None => {
+                            let mut command = xadd(last_id, &placeholder);
+                            command.splice(2..2, [bulk("maxlen"), bulk("0")]);
+                            write_command(&mut out, command);
+                        }
+                        Some((id, _)) if *id < stream.last_id() => {
+                            write_command(&mut out, xadd(last_id.clone(), &placeholder));
+                            write_command(
+                                &mut out,
+                                vec![bulk("xdel"), key.to_vec(), bulk(&last_id)],
+                            );
+                        }
+                        Some(_) => {}
+                    }
+
+                    for (name, group) in stream.groups() {
+                        write_command(
+                            &mut out,
+                            vec![
+                                bulk("xgroup"),
+                                bulk("create"),
+                                key.to_vec(),
+                                name.clone(),
+                                bulk(&group.last_delivered.to_string()),
+                            ],
+                        );
+                        for consumer in group.consumers().keys() {
+                            write_command(
+                                &mut out,
+                                vec![
+                                    bulk("xgroup"),
+                                    bulk("createconsumer"),
+                                    key.to_vec(),
+                                    name.clone(),
+                                    consumer.clone(),
+                                ],
+                            );
+                        }
+                        for (id, pending) in group.pending() {
+                            write_command(
+                                &mut out,
+                                vec![
+                                    bulk("xclaim"),
+                                    key.to_vec(),
+                                    name.clone(),
+                                    pending.consumer.clone(),
+                                    bulk("0"),
+                                    bulk(&id.to_string()),
+                                    bulk("time"),
+                                    bulk(&pending.delivered_at.to_string()),
+                                    bulk("retrycount"),
+                                    bulk(&pending.delivery_count.to_string()),
+                                    bulk("force"),
+                                    bulk("justid"),
+                                ],
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn write_command(out: &mut Vec<u8>, command: Vec<Vec<u8>>) {
+    let command = Value::Array(
+        command
+            .into_iter()
+            .map(|part| Value::BulkString(part.into()))
+            .collect(),
+    );
+    out.extend_from_slice(&command.serialise(false));
+}
@@ -0,0 +1,195 @@
+use crate::blocking::{self, BlockedClients, Outcome};
+use crate::client::{self, Client};
+use crate::cmd;
+use crate::db::{self, Db};
+use crate::json::Json;
+use crate::propagate;
+use crate::pubsub::PubSub;
+use crate::replication;
+use crate::server;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// How big the log may grow before it's rotated, or 0 to let it grow.
+pub static MAX_SIZE: AtomicU64 = AtomicU64::new(0);
+/// How many entries the stream is trimmed to, or 0 to keep them all.
+pub static STREAM_MAXLEN: AtomicU64 = AtomicU64::new(0);
+
+/// Whether there's a log or a stream, so that writes needn't build entries otherwise.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The open log, while `audit-log` names one.
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+
+/// The stream's key, while `audit-stream` names one.
+static STREAM: Mutex<String> = Mutex::new(String::new());
+
+/// Entries waiting to be added to the stream, as XADD's field/value pairs.
+static QUEUE: Mutex<Vec<Vec<Vec<u8>>>> = Mutex::new(Vec::new());
+static QUEUED: Notify = Notify::const_new();
+
+struct Log {
+    path: String,
+    file: File,
+    /// Bytes in the file, which is rotated once they reach `MAX_SIZE`.
+    size: u64,
+}
+
+impl Log {
+    fn open(path: &str) -> std::io::Result<Log> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Log {
+            path: path.to_string(),
+            file,
+            size,
+        })
+    }
+
+    /// Moves the file aside, suffixed with the time in Unix milliseconds, and starts a new
+    /// one. Rotated files are left for the operator to archive: none are ever deleted.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        fs::rename(&self.path, format!("{}.{}", self.path, db::unix_millis()))?;
+        *self = Log::open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Applies a new `audit-log`, opening the file if it's changed.
+pub fn set_log(path: &str) {
+    let mut log = LOG.lock().unwrap();
+    if log.as_ref().map_or("", |log| log.path.as_str()) != path {
+        *log = match path {
+            "" => None,
+            path => Log::open(path)
+                .inspect_err(|e| warn!("Can't open the audit log {path}: {e}"))
+                .ok(),
+        };
+    }
+    enable(log.is_some(), !STREAM.lock().unwrap().is_empty());
+}
+
+/// Applies a new `audit-stream`.
+pub fn set_stream(key: &str) {
+    *STREAM.lock().unwrap() = key.to_string();
+    enable(LOG.lock().unwrap().is_some(), !key.is_empty());
+}
+
+fn enable(log: bool, stream: bool) {
+    ENABLED.store(log || stream, Ordering::Relaxed);
+}
+
+/// Records write command `name` with `args`, which `client` just ran on database `db`, with
+/// whether it failed. Writes applied from the master were recorded by the master.
+pub fn record(client: &Client, db: usize, name: &str, args: &[Vec<u8>], failed: bool) {
+    if !ENABLED.load(Ordering::Relaxed) || !cmd::is_write(name) || replication::applying() {
+        return;
+    }
+
+    let (addr, _) = client::peer(client.id);
+    let user = client.user.as_deref().unwrap_or_default();
+    let keys = cmd::command_keys(name, args);
+    let status = if failed { "error" } else { "ok" };
+
+    if let Some(log) = LOG.lock().unwrap().as_mut() {
+        let line = Json::object([
+            ("time", Json::Int(db::unix_millis() as i64)),
+            ("client", Json::str(addr.as_str())),
+            ("user", Json::str(user)),
+            ("db", Json::Int(db as i64)),
+            ("command", Json::str(name)),
+            (
+                "keys",
+                Json::Array(
+                    keys.iter()
+                        .map(|key| Json::str(String::from_utf8_lossy(key)))
+                        .collect(),
+                ),
+            ),
+            ("status", Json::str(status)),
+        ]);
+        write(log, format!("{line}\n").as_bytes());
+    }
+
+    if !STREAM.lock().unwrap().is_empty() {
+        let mut fields = vec![
+            b"client".to_vec(),
+            addr.into_bytes(),
+            b"user".to_vec(),
+            user.as_bytes().to_vec(),
+            b"db".to_vec(),
+            db.to_string().into_bytes(),
+            b"command".to_vec(),
+            name.as_bytes().to_vec(),
+            b"status".to_vec(),
+            status.as_bytes().to_vec(),
+        ];
+        for key in keys {
+            fields.extend([b"key".to_vec(), key.to_vec()]);
+        }
+        QUEUE.lock().unwrap().push(fields);
+        QUEUED.notify_one();
+    }
+}
+
+fn write(log: &mut Log, line: &[u8]) {
+    if let Err(e) = log.file.write_all(line) {
+        warn!("Failed to write to the audit log {}: {e}", log.path);
+        return;
+    }
+    log.size += line.len() as u64;
+
+    let max_size = MAX_SIZE.load(Ordering::Relaxed);
+    if max_size > 0
+        && log.size >= max_size
+        && let Err(e) = log.rotate()
+    {
+        warn!("Failed to rotate the audit log {}: {e}", log.path);
+    }
+}
+
+/// Adds queued entries to `audit-stream` in database 0 with XADD, which is passed on to
+/// replicas and the append-only file like any other. Runs for as long as the server does.
+pub async fn add_to_stream(db: Db, pubsub: Arc<PubSub>, blocked: Arc<BlockedClients>) {
+    loop {
+        QUEUED.notified().await;
+        let entries = std::mem::take(&mut *QUEUE.lock().unwrap());
+        let key = STREAM.lock().unwrap().clone();
+        // A replica gets the entries from its master
+        if key.is_empty() || replication::master().is_some() {
+            continue;
+        }
+
+        let mut dbs = db.lock_keys(&[key.as_bytes()]).await;
+        let nowhere = SocketAddr::from(([0, 0, 0, 0], 0));
+        let mut client = Client::new(pubsub.clone(), nowhere, nowhere);
+        let mut xadd = vec![key.as_bytes().to_vec()];
+        match STREAM_MAXLEN.load(Ordering::Relaxed) {
+            0 => {}
+            maxlen => xadd.extend([b"maxlen".to_vec(), maxlen.to_string().into_bytes()]),
+        }
+        xadd.push(b"*".to_vec());
+
+        for fields in entries {
+            let mut args = xadd.clone();
+            args.extend(fields);
+            let reply = match server::run(&mut client, &pubsub, &blocked, &mut dbs, "xadd", &args) {
+                Outcome::Reply(reply) => reply,
+                Outcome::Block(block) => block.attempt_now(&mut dbs[client.db]),
+            };
+            if let Some(e) = reply.error_message() {
+                warn!("Failed to add to the audit stream {key}: {e}");
+                continue;
+            }
+            propagate::propagate(client.db, "xadd", &args, &reply);
+            for key in blocking::ready_keys("xadd", &args) {
+                blocked.signal(client.db, key);
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use redis::resp::{RespHandler, Value};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tokio::net::TcpStream;
+
+/// Where to find the server.
+#[derive(clap::Args, Debug)]
+pub struct Server {
+    /// The server's host
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// The server's port
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+
+    /// A password to AUTH with
+    #[arg(short = 'a', long, value_name = "PASSWORD")]
+    pass: Option<String>,
+}
+
+impl Server {
+    /// Connects to the server, logging in if there's a password.
+    pub async fn connect(&self) -> anyhow::Result<RespHandler> {
+        let addr = format!("{}:{}", self.host, self.port);
+        let stream = TcpStream::connect(&addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not connect to Redis at {addr}: {e}"))?;
+        let mut handler = RespHandler::new(stream);
+
+        if let Some(pass) = &self.pass {
+            run(&mut handler, &[b"AUTH", pass.as_bytes()]).await?;
+        }
+        Ok(handler)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportOptions {
+    #[command(flatten)]
+    server: Server,
+
+    /// Write one JSON array rather than a record per line
+    #[arg(long)]
+    json: bool,
+
+    /// The file to write, or - for standard output
+    file: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportOptions {
+    #[command(flatten)]
+    server: Server,
+
+    /// Overwrite keys that already exist, rather than loading nothing if any do
+    #[arg(long)]
+    replace: bool,
+
+    /// The file to read, in either format EXPORT writes, or - for standard input
+    file: PathBuf,
+}
+
+/// Runs `export`: the server's EXPORT, written out here.
+pub fn export(options: ExportOptions) -> anyhow::Result<()> {
+    let format = if options.json { "json" } else { "jsonl" };
+    let reply = call(&options.server, &[b"EXPORT", format.as_bytes()])?;
+    let Value::BulkString(text) = reply else {
+        anyhow::bail!("Unexpected reply to EXPORT: {reply:?}");
+    };
+
+    if options.file == Path::new("-") {
+        std::io::stdout().write_all(&text)?;
+    } else {
+        std::fs::write(&options.file, &text)?;
+        eprintln!("Exported to {}", options.file.display());
+    }
+    Ok(())
+}
+
+/// Runs `import`: the file read here, then loaded with the server's IMPORT.
+pub fn import(options: ImportOptions) -> anyhow::Result<()> {
+    let mut text = Vec::new();
+    if options.file == Path::new("-") {
+        std::io::stdin().read_to_end(&mut text)?;
+    } else {
+        text = std::fs::read(&options.file)?;
+    }
+
+    let mut command: Vec<&[u8]> = vec![b"IMPORT", b"DATA", &text];
+    if options.replace {
+        command.push(b"REPLACE");
+    }
+    match call(&options.server, &command)? {
+        Value::Integer(keys) => {
+            eprintln!("Imported {keys} keys");
+            Ok(())
+        }
+        reply => anyhow::bail!("Unexpected reply to IMPORT: {reply:?}"),
+    }
+}
+
+/// Runs one command on the server, failing on an error reply.
+fn call(server: &Server, args: &[&[u8]]) -> anyhow::Result<Value> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let mut handler = server.connect().await?;
+        run(&mut handler, args).await
+    })
+}
+
+async fn run(handler: &mut RespHandler, args: &[&[u8]]) -> anyhow::Result<Value> {
+    let command = args
+        .iter()
+        .map(|arg| Value::BulkString(arg.to_vec().into()))
+        .collect();
+    handler.write(Value::Array(command)).await?;
+    let reply = handler
+        .read()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Server closed the connection"))?;
+    match reply.error_message() {
+        Some(e) => anyhow::bail!("{} failed: {e}", String::from_utf8_lossy(args[0])),
+        None => Ok(reply),
+    }
+}
@@ -0,0 +1,306 @@
+use redis::resp::{RespHandler, Value};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// The tests `--tests` can pick from, by name, and the ones run by default.
+const TESTS: &[&str] = &[
+    "ping",
+    "set",
+    "get",
+    "incr",
+    "lpush",
+    "rpush",
+    "lpop",
+    "rpop",
+    "sadd",
+    "hset",
+    "spop",
+    "zadd",
+    "zpopmin",
+    "lrange_100",
+    "mset",
+];
+const DEFAULT_TESTS: &str =
+    "ping,set,get,incr,lpush,rpush,lpop,rpop,sadd,hset,spop,zadd,zpopmin,lrange_100,mset";
+
+/// Keys MSET sets at once, as redis-benchmark does.
+const MSET_KEYS: usize = 10;
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// The server's host
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// The server's port
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+
+    /// A password to AUTH with
+    #[arg(short = 'a', long, value_name = "PASSWORD")]
+    pass: Option<String>,
+
+    /// Connections to run commands on at once
+    #[arg(short, long, default_value_t = 50)]
+    clients: usize,
+
+    /// Commands to run for each test
+    #[arg(short = 'n', long, default_value_t = 100_000)]
+    requests: u64,
+
+    /// Commands each connection sends before reading their replies
+    #[arg(short = 'P', long, default_value_t = 1)]
+    pipeline: u64,
+
+    /// Spreads commands over this many keys at random, rather than using one key for all
+    #[arg(short = 'r', long, default_value_t = 0)]
+    keyspace: u64,
+
+    /// Bytes in each value SET, pushed and so on
+    #[arg(short = 'd', long, default_value_t = 3)]
+    data_size: usize,
+
+    /// The tests to run, comma separated: ping, set, get, incr, lpush, rpush, lpop, rpop,
+    /// sadd, hset, spop, zadd, zpopmin, lrange_100 and mset
+    #[arg(short, long, default_value = DEFAULT_TESTS)]
+    tests: String,
+
+    /// Only print each test's throughput and median latency
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+/// What one test measured.
+struct Report {
+    elapsed: Duration,
+    /// Each command's latency: the time from sending its pipeline to reading its reply.
+    latencies: Vec<Duration>,
+    errors: u64,
+}
+
+/// Runs `bench`: each test in turn, printing its throughput and latency percentiles.
+pub fn run(options: Options) -> anyhow::Result<()> {
+    let mut tests = Vec::new();
+    for name in options.tests.split(',').map(str::trim) {
+        match TESTS.iter().find(|test| **test == name) {
+            Some(test) => tests.push(*test),
+            None => anyhow::bail!("Unknown test '{name}'. Tests are: {}", TESTS.join(", ")),
+        }
+    }
+    anyhow::ensure!(
+        options.clients > 0 && options.pipeline > 0,
+        "--clients and --pipeline must be at least 1"
+    );
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let options = Arc::new(options);
+    for test in tests {
+        let report = runtime.block_on(run_test(options.clone(), test))?;
+        print(&options, test, report);
+    }
+    Ok(())
+}
+
+async fn run_test(options: Arc<Options>, test: &'static str) -> anyhow::Result<Report> {
+    let mut connections = Vec::new();
+    for _ in 0..options.clients {
+        connections.push(connect(&options).await?);
+    }
+
+    let issued = Arc::new(AtomicU64::new(0));
+    let started = Instant::now();
+    let clients: Vec<_> = connections
+        .into_iter()
+        .enumerate()
+        .map(|(i, connection)| {
+            let client = Client {
+                handler: connection,
+                options: options.clone(),
+                issued: issued.clone(),
+                seed: (i as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+            };
+            tokio::spawn(client.run(test))
+        })
+        .collect();
+
+    let mut report = Report {
+        elapsed: Duration::ZERO,
+        latencies: Vec::with_capacity(options.requests as usize),
+        errors: 0,
+    };
+    for client in clients {
+        let (latencies, errors) = client.await??;
+        report.latencies.extend(latencies);
+        report.errors += errors;
+    }
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+async fn connect(options: &Options) -> anyhow::Result<RespHandler> {
+    let addr = format!("{}:{}", options.host, options.port);
+    let stream = TcpStream::connect(&addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not connect to Redis at {addr}: {e}"))?;
+    stream.set_nodelay(true)?;
+    let mut handler = RespHandler::new(stream);
+
+    if let Some(pass) = &options.pass {
+        handler.write(command(&[b"AUTH", pass.as_bytes()])).await?;
+        let reply = handler.read().await?;
+        if let Some(e) = reply.as_ref().and_then(Value::error_message) {
+            anyhow::bail!("AUTH failed: {e}");
+        }
+    }
+    Ok(handler)
+}
+
+/// One connection's share of a test, taking pipelines' worth of commands until they've all
+/// been issued.
+struct Client {
+    handler: RespHandler,
+    options: Arc<Options>,
+    /// Commands issued by all of the test's connections so far.
+    issued: Arc<AtomicU64>,
+    /// State for picking random keys.
+    seed: u64,
+}
+
+impl Client {
+    async fn run(mut self, test: &str) -> anyhow::Result<(Vec<Duration>, u64)> {
+        let (requests, pipeline) = (self.options.requests, self.options.pipeline);
+        let mut latencies = Vec::new();
+        let mut errors = 0;
+        loop {
+            let taken = self.issued.fetch_add(pipeline, Ordering::Relaxed);
+            if taken >= requests {
+                break;
+            }
+            let count = pipeline.min(requests - taken);
+
+            let mut batch = Vec::new();
+            for _ in 0..count {
+                batch.extend(self.command(test).serialise(false));
+            }
+            let sent = Instant::now();
+            self.handler.write_bytes(&batch).await?;
+            for _ in 0..count {
+                let reply = self
+                    .handler
+                    .read()
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("Server closed the connection"))?;
+                latencies.push(sent.elapsed());
+                if reply.error_message().is_some() {
+                    errors += 1;
+                }
+            }
+        }
+        Ok((latencies, errors))
+    }
+
+    /// The next command `test` runs, with fresh random keys if there's a keyspace to pick from.
+    fn command(&mut self, test: &str) -> Value {
+        let data = vec![b'x'; self.options.data_size];
+        let key = |client: &mut Client, prefix: &str| match client.options.keyspace {
+            0 => format!("{prefix}:__rand_int__").into_bytes(),
+            keyspace => format!("{prefix}:{:012}", client.random() % keyspace).into_bytes(),
+        };
+
+        match test {
+            "ping" => command(&[b"PING"]),
+            "set" => command(&[b"SET", &key(self, "key"), &data]),
+            "get" => command(&[b"GET", &key(self, "key")]),
+            "incr" => command(&[b"INCR", &key(self, "counter")]),
+            "lpush" => command(&[b"LPUSH", b"mylist", &data]),
+            "rpush" => command(&[b"RPUSH", b"mylist", &data]),
+            "lpop" => command(&[b"LPOP", b"mylist"]),
+            "rpop" => command(&[b"RPOP", b"mylist"]),
+            "sadd" => command(&[b"SADD", b"myset", &key(self, "element")]),
+            "hset" => command(&[b"HSET", b"myhash", &key(self, "element"), &data]),
+            "spop" => command(&[b"SPOP", b"myset"]),
+            "zadd" => command(&[b"ZADD", b"myzset", b"0", &key(self, "element")]),
+            "zpopmin" => command(&[b"ZPOPMIN", b"myzset"]),
+            "lrange_100" => command(&[b"LRANGE", b"mylist", b"0", b"99"]),
+            _ => {
+                let mut args = vec![b"MSET".to_vec()];
+                for _ in 0..MSET_KEYS {
+                    args.extend([key(self, "key"), data.clone()]);
+                }
+                command(&args.iter().map(Vec::as_slice).collect::<Vec<_>>())
+            }
+        }
+    }
+
+    /// xorshift64*: plenty for spreading keys around.
+    fn random(&mut self) -> u64 {
+        self.seed ^= self.seed >> 12;
+        self.seed ^= self.seed << 25;
+        self.seed ^= self.seed >> 27;
+        self.seed.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+fn command(args: &[&[u8]]) -> Value {
+    Value::Array(
+        args.iter()
+            .map(|arg| Value::BulkString(arg.to_vec().into()))
+            .collect(),
+    )
+}
+
+fn print(options: &Options, test: &str, mut report: Report) {
+    report.latencies.sort_unstable();
+    let millis = |latency: Duration| latency.as_secs_f64() * 1000.0;
+    let percentile = |p: f64| {
+        let at = ((report.latencies.len() as f64 * p / 100.0).ceil() as usize).saturating_sub(1);
+        report.latencies.get(at).copied().map_or(0.0, millis)
+    };
+    let throughput = report.latencies.len() as f64 / report.elapsed.as_secs_f64();
+    let name = test.to_uppercase();
+
+    if options.quiet {
+        println!(
+            "{name}: {throughput:.2} requests per second, p50={:.3} msec",
+            percentile(50.0)
+        );
+        return;
+    }
+
+    let average = match report.latencies.len() {
+        0 => 0.0,
+        n => report.latencies.iter().copied().map(millis).sum::<f64>() / n as f64,
+    };
+    println!("====== {name} ======");
+    println!(
+        "  {} requests completed in {:.2} seconds",
+        report.latencies.len(),
+        report.elapsed.as_secs_f64()
+    );
+    println!("  {} parallel clients", options.clients);
+    println!("  {} bytes payload", options.data_size);
+    println!("  pipeline {}", options.pipeline);
+    if report.errors > 0 {
+        println!("  {} errors", report.errors);
+    }
+    println!();
+    println!("Summary:");
+    println!("  throughput summary: {throughput:.2} requests per second");
+    println!("  latency summary (msec):");
+    println!(
+        "  {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "avg", "min", "p50", "p95", "p99", "p99.9", "max"
+    );
+    println!(
+        "  {average:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+        percentile(0.0),
+        percentile(50.0),
+        percentile(95.0),
+        percentile(99.0),
+        percentile(99.9),
+        percentile(100.0)
+    );
+    println!();
+}
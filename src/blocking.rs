@@ -0,0 +1,264 @@
+use crate::db::{self, Db, Keyspace};
+use crate::resp::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+struct Waiter {
+    id: u64,
+    notify: Arc<Notify>,
+}
+
+/// A key together with the index of the database it's in.
+type DbKey = (usize, Vec<u8>);
+
+/// Tries to serve a blocking command, or `None` if it still has to wait.
+type Attempt = Box<dyn FnMut(&mut Keyspace) -> Option<Value> + Send>;
+
+/// A blocking command waiting to be served: `attempt` is retried whenever one of `keys`
+/// is signalled, and the command replies with `timeout` if `deadline` passes first.
+pub struct Block {
+    keys: Vec<Vec<u8>>,
+    deadline: Option<Instant>,
+    timeout: Value,
+    attempt: Attempt,
+}
+
+impl Block {
+    pub fn new(
+        keys: Vec<Vec<u8>>,
+        deadline: Option<Instant>,
+        timeout: Value,
+        attempt: impl FnMut(&mut Keyspace) -> Option<Value> + Send + 'static,
+    ) -> Self {
+        Self {
+            keys,
+            deadline,
+            timeout,
+            attempt: Box::new(attempt),
+        }
+    }
+
+    /// Serves the command without waiting, as inside a transaction, where a blocking command
+    /// behaves as if its timeout had already passed.
+    pub fn attempt_now(mut self, db: &mut Keyspace) -> Value {
+        (self.attempt)(db).unwrap_or(self.timeout)
+    }
+
+    /// Calls `served` with the reply whenever the command is served, still holding the lock it
+    /// was served under.
+    pub fn on_served(mut self, mut served: impl FnMut(&Value) + Send + 'static) -> Self {
+        let mut attempt = self.attempt;
+        self.attempt = Box::new(move |db| {
+            let reply = attempt(db);
+            if let Some(reply) = &reply {
+                served(reply);
+            }
+            reply
+        });
+        self
+    }
+
+    /// Replies with what `map` makes of the reply, whenever the command is served.
+    pub fn map_reply(mut self, mut map: impl FnMut(Value) -> Value + Send + 'static) -> Self {
+        let mut attempt = self.attempt;
+        self.attempt = Box::new(move |db| attempt(db).map(&mut map));
+        self
+    }
+}
+
+/// What running a command produced: either its reply, or a wait for keys to become servable.
+pub enum Outcome {
+    Reply(Value),
+    Block(Block),
+}
+
+impl From<Value> for Outcome {
+    fn from(reply: Value) -> Self {
+        Outcome::Reply(reply)
+    }
+}
+
+/// Registry of clients parked in a blocking command, queued per database and key in arrival
+/// order so the longest-waiting client is served first.
+#[derive(Default)]
+pub struct BlockedClients {
+    next_id: AtomicU64,
+    waiters: Mutex<HashMap<DbKey, VecDeque<Waiter>>>,
+}
+
+impl BlockedClients {
+    /// Wakes the longest-waiting client blocked on `key` in database `index`. Call this after
+    /// anything that may have made the key servable; a woken client that finds nothing simply
+    /// waits again.
+    pub fn signal(&self, index: usize, key: &[u8]) {
+        let waiters = self.waiters.lock().unwrap();
+
+        if let Some(waiter) = waiters
+            .get(&(index, key.to_vec()))
+            .and_then(|queue| queue.front())
+        {
+            waiter.notify.notify_one();
+        }
+    }
+
+    /// Wakes the longest-waiting client on every key in database `index`, for when its whole
+    /// contents changed at once.
+    pub fn signal_db(&self, index: usize) {
+        let waiters = self.waiters.lock().unwrap();
+
+        for ((db, _), queue) in waiters.iter() {
+            if *db == index
+                && let Some(waiter) = queue.front()
+            {
+                waiter.notify.notify_one();
+            }
+        }
+    }
+
+    /// How many clients are blocked, each counted once however many keys it waits on.
+    pub fn count(&self) -> usize {
+        let waiters = self.waiters.lock().unwrap();
+
+        waiters
+            .values()
+            .flatten()
+            .map(|waiter| waiter.id)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    fn register(&self, id: u64, index: usize, keys: &[Vec<u8>], notify: &Arc<Notify>) {
+        let mut waiters = self.waiters.lock().unwrap();
+
+        for key in keys {
+            waiters
+                .entry((index, key.clone()))
+                .or_default()
+                .push_back(Waiter {
+                    id,
+                    notify: notify.clone(),
+                });
+        }
+    }
+
+    fn unregister(&self, id: u64, index: usize, keys: &[Vec<u8>]) {
+        let mut waiters = self.waiters.lock().unwrap();
+
+        for key in keys {
+            let entry = (index, key.clone());
+            if let Some(queue) = waiters.get_mut(&entry) {
+                queue.retain(|waiter| waiter.id != id);
+                if queue.is_empty() {
+                    waiters.remove(&entry);
+                }
+            }
+        }
+    }
+
+    /// Waits out a [`Block`] on database `index`, replying with its timeout reply if the
+    /// deadline passes first. A client that disconnects in the meantime gets the timeout reply
+    /// too, which goes nowhere.
+    pub async fn wait(
+        &self,
+        db: &Db,
+        index: usize,
+        block: Block,
+        closed: impl Future<Output = ()>,
+    ) -> Value {
+        let Block {
+            keys,
+            deadline,
+            timeout,
+            mut attempt,
+        } = block;
+
+        self.block_on(db, index, &keys, deadline, closed, |db| attempt(db))
+            .await
+            .unwrap_or(timeout)
+    }
+
+    /// Repeatedly runs `attempt` with every shard locked until it produces a reply, parking on
+    /// `keys` in between. Returns `None` if `deadline` passes or `closed` resolves first.
+    async fn block_on<T>(
+        &self,
+        db: &Db,
+        index: usize,
+        keys: &[Vec<u8>],
+        deadline: Option<Instant>,
+        closed: impl Future<Output = ()>,
+        mut attempt: impl FnMut(&mut Keyspace) -> Option<T>,
+    ) -> Option<T> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let notify = Arc::new(Notify::new());
+        let mut registered = false;
+
+        tokio::pin!(closed);
+
+        let result = loop {
+            {
+                let mut dbs = db.lock_all().await;
+                db::select(index);
+
+                let reply = attempt(&mut dbs[index]);
+                db::bump_versions(&mut dbs);
+                if reply.is_some() {
+                    break reply;
+                }
+
+                // Registering while still holding the lock means no push can slip in between
+                // the failed attempt and the registration
+                if !registered {
+                    self.register(id, index, keys, &notify);
+                    registered = true;
+                }
+            }
+
+            let timed_out = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = timed_out => break None,
+                _ = &mut closed => break None,
+            }
+        };
+
+        if registered {
+            self.unregister(id, index, keys);
+
+            // We may have consumed a wakeup meant for whoever is queued behind us
+            for key in keys {
+                self.signal(index, key);
+            }
+        }
+
+        result
+    }
+}
+
+/// The keys a command may have made servable for blocked clients, i.e. the lists it pushed
+/// onto, the sorted sets it added to or the streams it appended to.
+pub fn ready_keys<'a>(command: &str, args: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+    let arg = |i: usize| args.get(i).map(|arg| arg.as_slice());
+
+    let key = match command {
+        "lpush" | "rpush" | "linsert" | "restore" | "zadd" | "zincrby" | "zunionstore"
+        | "zinterstore" | "zdiffstore" | "geoadd" | "geosearchstore" | "xadd" => arg(0),
+        "lmove" | "rpoplpush" | "blmove" | "brpoplpush" => arg(1),
+        "sort" => args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case(b"store"))
+            .and_then(|i| arg(i + 1)),
+        _ => None,
+    };
+
+    key.into_iter().collect()
+}
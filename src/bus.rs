@@ -0,0 +1,126 @@
+use crate::cluster;
+use crate::resp::{RespHandler, Value};
+use crate::stats;
+use std::sync::LazyLock;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+/// How often the nodes are looked over and links opened to any without one.
+const CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// How often each link pings its node, at most.
+const PING_PERIOD: Duration = Duration::from_secs(1);
+
+/// Messages every link passes on to its node, like FAILs.
+static BROADCAST: LazyLock<broadcast::Sender<Value>> = LazyLock::new(|| broadcast::channel(64).0);
+
+fn node_timeout() -> Duration {
+    Duration::from_millis(cluster::NODE_TIMEOUT.load(Ordering::Relaxed))
+}
+
+/// Takes other nodes' links to this one on `listener`, answering their pings. Runs for as long
+/// as the server does.
+pub async fn serve(listener: TcpListener) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(answer(stream));
+    }
+}
+
+async fn answer(stream: TcpStream) {
+    let (Ok(peer), Ok(local)) = (stream.peer_addr(), stream.local_addr()) else {
+        return;
+    };
+    let mut handler = RespHandler::new(stream);
+    while let Ok(Some(message)) = handler.read().await {
+        stats::CLUSTER_MESSAGES_RECEIVED.fetch_add(1, Ordering::Relaxed);
+        let Some(reply) = cluster::receive(message, peer.ip(), local.ip()) else {
+            continue;
+        };
+        if handler.write(reply).await.is_err() {
+            return;
+        }
+        stats::CLUSTER_MESSAGES_SENT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Keeps a link open to every node, and tells them all about any node found to have failed and
+/// about this node's bid to take over from its master if that's the one. Runs for as long as
+/// the server does.
+pub async fn check_nodes() {
+    let mut interval = tokio::time::interval(CHECK_PERIOD);
+    loop {
+        interval.tick().await;
+        for message in cluster::check_nodes()
+            .into_iter()
+            .chain(cluster::failover())
+        {
+            let _ = BROADCAST.send(message);
+        }
+        for (id, host, cport) in cluster::unlinked() {
+            tokio::spawn(async move {
+                let id = link(id, &host, cport).await;
+                cluster::unlink(&id);
+            });
+        }
+    }
+}
+
+/// Pings the node known as `id` on its bus at `host`:`cport`, or sends it a MEET while it's in
+/// the handshake, until the link fails or turns out not to be needed. Returns the ID the node
+/// ended up known by, which the handshake may have changed.
+async fn link(mut id: String, host: &str, cport: u16) -> String {
+    let timeout = node_timeout();
+    let Ok(Ok(stream)) = tokio::time::timeout(timeout, TcpStream::connect((host, cport))).await
+    else {
+        return id;
+    };
+    let (Ok(peer), Ok(local)) = (stream.peer_addr(), stream.local_addr()) else {
+        return id;
+    };
+    let mut handler = RespHandler::new(stream);
+    let mut broadcasts = BROADCAST.subscribe();
+    let mut pings = tokio::time::interval((timeout / 4).clamp(CHECK_PERIOD, PING_PERIOD));
+    let mut heard = Instant::now();
+
+    loop {
+        // Whether a message went out, or the error sending it
+        let sent = tokio::select! {
+            _ = pings.tick() => {
+                let kind = if cluster::in_handshake(&id) { "meet" } else { "ping" };
+                handler.write(cluster::message(kind)).await.map(|()| true)
+            }
+            message = broadcasts.recv() => match message {
+                Ok(message) => handler.write(message).await.map(|()| true),
+                Err(_) => Ok(false),
+            },
+            // A node that goes quiet for half the node timeout gets a fresh link
+            _ = tokio::time::sleep_until(heard + timeout / 2) => return id,
+            message = handler.read() => {
+                let Ok(Some(message)) = message else {
+                    return id;
+                };
+                heard = Instant::now();
+                stats::CLUSTER_MESSAGES_RECEIVED.fetch_add(1, Ordering::Relaxed);
+                let Some(answered) = cluster::answered(&id, &message) else {
+                    return id;
+                };
+                id = answered;
+                cluster::receive(message, peer.ip(), local.ip());
+                Ok(false)
+            }
+        };
+        match sent {
+            Ok(true) => {
+                stats::CLUSTER_MESSAGES_SENT.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(false) => {}
+            Err(_) => return id,
+        }
+    }
+}
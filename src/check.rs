@@ -0,0 +1,191 @@
+//! The checks behind the `check-rdb` and `check-aof` subcommands, which look over a snapshot or
+//! append-only file without starting a server, as redis-check-rdb and redis-check-aof do.
+
+use crate::aof;
+use crate::config;
+use crate::crypt;
+use crate::db::{self, Locked};
+use crate::snapshot;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+/// Where an RDB file's version starts and ends, after its magic string.
+const RDB_VERSION: std::ops::Range<usize> = 5..9;
+
+/// Versions from this one on end with a checksum.
+const RDB_CHECKSUM_VERSION: u32 = 5;
+
+/// Reads the snapshot at `path` the way the server would load it, checking its checksum and
+/// that nothing is cut short or left over. Exits with status 1 if it isn't sound.
+pub fn rdb(path: &Path) -> anyhow::Result<()> {
+    let (bytes, whole) = read(path)?;
+    println!("[offset 0] Checking RDB file {}", path.display());
+
+    let decoded = match whole {
+        Some(whole) if whole < fs::metadata(path)?.len() as usize => Err(anyhow::anyhow!(
+            "encrypted file cut short at offset {whole}"
+        )),
+        _ => decode_rdb(&bytes),
+    };
+    match decoded {
+        Ok((keys, checksum)) => {
+            println!("[info] {keys} keys read");
+            println!("[info] {checksum}");
+            println!("\\o/ RDB looks OK! \\o/");
+            Ok(())
+        }
+        Err(e) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[info] {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The file at `path`, decrypted with the key in `REDIS_ENCRYPTION_KEY` if it's encrypted, in
+/// which case how many of its bytes were whole records comes with it. Exits with status 1 if it
+/// can't be decrypted.
+fn read(path: &Path) -> anyhow::Result<(Vec<u8>, Option<usize>)> {
+    let bytes =
+        fs::read(path).map_err(|e| anyhow::anyhow!("Cannot open {}: {e}", path.display()))?;
+    if !crypt::is_encrypted(&bytes) {
+        return Ok((bytes, None));
+    }
+
+    println!(
+        "{} is encrypted, checking what it decrypts to",
+        path.display()
+    );
+    crypt::install(&config::get())?;
+    match crypt::decrypt(&bytes) {
+        Ok(opened) => Ok((opened.plaintext, Some(opened.len))),
+        Err(e) => {
+            println!("Can't decrypt {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Decodes a whole snapshot, returning how many keys it held (leaving out those that have
+/// expired) and what became of its checksum.
+fn decode_rdb(bytes: &[u8]) -> anyhow::Result<(usize, &'static str)> {
+    let (len, keys) = snapshot::decode(bytes, &mut databases()?)?;
+    if len != bytes.len() {
+        anyhow::bail!(
+            "{} trailing bytes after the end of the file",
+            bytes.len() - len
+        );
+    }
+
+    let version: u32 = std::str::from_utf8(&bytes[RDB_VERSION])?.parse()?;
+    let checksum = if version < RDB_CHECKSUM_VERSION {
+        "No checksum in this version"
+    } else if bytes[len - 8..] == [0; 8] {
+        "Checksum disabled"
+    } else {
+        "Checksum OK"
+    };
+    Ok((keys, checksum))
+}
+
+/// Empty databases to decode snapshots into, as many as the server has by default.
+fn databases() -> anyhow::Result<Locked> {
+    let databases = db::new_databases(config::get().databases);
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    Ok(runtime.block_on(databases.lock_all()))
+}
+
+/// Checks the append-only file at `path`: its snapshot preamble, if it has one, then that the
+/// rest is whole commands. A command cut short at the end, as by a crash mid-write, is cut off
+/// with `fix`. Anything else wrong is left for the operator, as cutting the file there could
+/// throw away any number of writes after it. Exits with status 1 if the file isn't sound, or
+/// wasn't made so.
+pub fn aof(path: &Path, fix: bool) -> anyhow::Result<()> {
+    let (bytes, whole) = read(path)?;
+    let size = fs::metadata(path)?.len() as usize;
+
+    let mut pos = 0;
+    if bytes.starts_with(b"REDIS") {
+        println!("The AOF appears to start with an RDB preamble. Checking it...");
+        match snapshot::decode(&bytes, &mut databases()?) {
+            Ok((len, _)) => {
+                println!("RDB preamble is OK, proceeding with the AOF tail...");
+                pos = len;
+            }
+            Err(e) => {
+                println!("RDB preamble of AOF file is not sane, aborting: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut commands = 0;
+    let mut truncated = false;
+    let mut error = None;
+    while pos < bytes.len() {
+        match aof::parse_command(&bytes[pos..]) {
+            Ok(Some((_, len))) => {
+                pos += len;
+                commands += 1;
+            }
+            Ok(None) => {
+                truncated = true;
+                break;
+            }
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+
+    // In an encrypted file it's a record that a crash cuts short, never a command within one
+    if let Some(whole) = whole {
+        if truncated {
+            error = Some(anyhow::anyhow!("command cut short inside a record"));
+        } else if error.is_none() {
+            truncated = whole < size;
+            pos = whole;
+        }
+    }
+
+    let diff = size - pos;
+    println!(
+        "AOF analyzed: filename={}, size={size}, ok_up_to={pos}, commands={commands}, diff={diff}",
+        path.display()
+    );
+    if let Some(e) = error {
+        println!("Bad file format at offset {pos}: {e}");
+        println!(
+            "AOF {} is not valid, and can't be fixed by cutting off a truncated tail",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+    if !truncated {
+        println!("AOF {} is valid", path.display());
+        return Ok(());
+    }
+
+    match whole {
+        Some(_) => println!("The last record is cut short at offset {pos}"),
+        None => println!("The last command is cut short at offset {pos}"),
+    }
+    if !fix {
+        println!(
+            "AOF {} is not valid. Use the --fix option to try fixing it.",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+    println!(
+        "This will shrink the AOF {} from {size} bytes, with {diff} bytes, to {pos} bytes",
+        path.display()
+    );
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(pos as u64)?;
+    println!("Successfully truncated AOF {}", path.display());
+    Ok(())
+}
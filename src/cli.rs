@@ -0,0 +1,484 @@
+use redis::config::split_line;
+use redis::resp::{RespHandler, Value, parse_message};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+/// Commands after which the server keeps sending replies of its own accord.
+const STREAMING: &[&str] = &["subscribe", "psubscribe", "ssubscribe", "monitor"];
+
+/// Where history is kept between sessions, under the home directory, unless
+/// `REDISCLI_HISTFILE` says otherwise.
+const HISTORY_FILE: &str = ".rediscli_history";
+
+/// How much of standard input `--pipe` reads and sends at a time.
+const PIPE_CHUNK: usize = 64 * 1024;
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// The server's host
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// The server's port
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+
+    /// A password to AUTH with
+    #[arg(short = 'a', long, value_name = "PASSWORD")]
+    pass: Option<String>,
+
+    /// The database to SELECT
+    #[arg(short = 'n', long, default_value_t = 0)]
+    db: usize,
+
+    /// Print replies as they are, rather than formatted as redis-cli does. The default when
+    /// standard output isn't a terminal
+    #[arg(long, conflicts_with = "no_raw")]
+    raw: bool,
+
+    /// Format replies even when standard output isn't a terminal
+    #[arg(long)]
+    no_raw: bool,
+
+    /// Send the RESP commands read from standard input as they are, for mass insertion, and
+    /// report how many replies and errors came back
+    #[arg(long, conflicts_with = "command")]
+    pipe: bool,
+
+    /// Report the biggest key of each type in the database, by length or number of elements,
+    /// with how many keys of each type there are and how long they have left to live
+    #[arg(long, conflicts_with_all = ["command", "pipe", "memkeys"])]
+    bigkeys: bool,
+
+    /// Report the keys taking up the most memory in the database, one of each type, with how
+    /// many keys of each type there are and how long they have left to live
+    #[arg(long, conflicts_with_all = ["command", "pipe"])]
+    memkeys: bool,
+
+    /// Have --bigkeys or --memkeys look at this many keys picked at random, rather than every
+    /// key
+    #[arg(long, value_name = "COUNT")]
+    sample: Option<usize>,
+
+    /// A command to run, rather than starting a prompt
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+/// A connection to the server, with what the prompt shows of it.
+struct Connection {
+    handler: RespHandler,
+    addr: String,
+    db: usize,
+    raw: bool,
+}
+
+impl Connection {
+    async fn open(options: &Options) -> anyhow::Result<Connection> {
+        let addr = format!("{}:{}", options.host, options.port);
+        let stream = TcpStream::connect(&addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not connect to Redis at {addr}: {e}"))?;
+        let raw = options.raw || !(options.no_raw || std::io::stdout().is_terminal());
+        let mut connection = Connection {
+            handler: RespHandler::new(stream),
+            addr,
+            db: 0,
+            raw,
+        };
+
+        if let Some(pass) = &options.pass {
+            connection.setup(&["AUTH", pass]).await?;
+        }
+        if options.db != 0 {
+            connection
+                .setup(&["SELECT", &options.db.to_string()])
+                .await?;
+            connection.db = options.db;
+        }
+        Ok(connection)
+    }
+
+    /// Runs a command the session can't go on without succeeding.
+    async fn setup(&mut self, args: &[&str]) -> anyhow::Result<()> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let reply = self.call(&args).await?;
+        if let Some(e) = reply.error_message() {
+            anyhow::bail!("{} failed: {e}", args[0].to_uppercase());
+        }
+        Ok(())
+    }
+
+    async fn call(&mut self, args: &[String]) -> anyhow::Result<Value> {
+        let command = args
+            .iter()
+            .map(|arg| Value::BulkString(arg.clone().into()))
+            .collect();
+        self.handler.write(Value::Array(command)).await?;
+        self.handler
+            .read()
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Server closed the connection"))
+    }
+
+    /// Runs a command and prints its reply, and anything the server goes on to send after a
+    /// SUBSCRIBE or MONITOR.
+    async fn run(&mut self, args: &[String]) -> anyhow::Result<()> {
+        let reply = self.call(args).await?;
+        let name = args[0].to_lowercase();
+        if name == "select" && reply.error_message().is_none() {
+            self.db = args
+                .get(1)
+                .and_then(|db| db.parse().ok())
+                .unwrap_or(self.db);
+        }
+        self.print(&reply);
+
+        if STREAMING.contains(&name.as_str()) && reply.error_message().is_none() {
+            while let Some(reply) = self.handler.read().await? {
+                self.print(&reply);
+            }
+        }
+        Ok(())
+    }
+
+    fn print(&self, reply: &Value) {
+        if self.raw {
+            println!("{}", raw(reply));
+        } else {
+            println!("{}", pretty(reply));
+        }
+    }
+
+    fn prompt(&self) -> String {
+        match self.db {
+            0 => format!("{}> ", self.addr),
+            db => format!("{}[{db}]> ", self.addr),
+        }
+    }
+}
+
+/// Runs `cli`: one command if it's given one, a prompt if not, or a `--pipe`.
+pub fn run(options: Options) -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let mut connection = runtime.block_on(Connection::open(&options))?;
+
+    if options.pipe {
+        runtime.block_on(pipe(connection))
+    } else if options.bigkeys || options.memkeys {
+        runtime.block_on(keys_report(connection, options.memkeys, options.sample))
+    } else if !options.command.is_empty() {
+        runtime.block_on(connection.run(&options.command))
+    } else {
+        repl(&runtime, &options, connection)
+    }
+}
+
+/// Reads commands from a prompt until EOF or `quit`, keeping a history of them.
+fn repl(runtime: &Runtime, options: &Options, connection: Connection) -> anyhow::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = std::env::var_os("REDISCLI_HISTFILE")
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(HISTORY_FILE)));
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    // After losing the connection, it's opened again for the next command
+    let mut connection = Some(connection);
+    loop {
+        let prompt = match &connection {
+            Some(connection) => connection.prompt(),
+            None => "not connected> ".to_string(),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let args = match split_line(&line) {
+            Ok(args) if args.is_empty() => continue,
+            Ok(args) => args,
+            Err(_) => {
+                println!("Invalid argument(s)");
+                continue;
+            }
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        if matches!(args[0].to_lowercase().as_str(), "quit" | "exit") {
+            break;
+        }
+
+        let result = runtime.block_on(async {
+            let connection = match &mut connection {
+                Some(connection) => connection,
+                None => connection.insert(Connection::open(options).await?),
+            };
+            connection.run(&args).await
+        });
+        if let Err(e) = result {
+            println!("Error: {e}");
+            connection = None;
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+/// Streams standard input to the server as it is, reading replies as they come rather than
+/// after it's all been sent, so that neither side's buffers grow with the input. An ECHO sent
+/// after it marks the last reply, and the replies and errors before that are counted.
+async fn pipe(connection: Connection) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let marker = format!("pipe-marker-{}-{now}", std::process::id());
+    let echo = Value::Array(vec![
+        Value::BulkString("ECHO".into()),
+        Value::BulkString(marker.clone().into()),
+    ]);
+    let (stream, mut buf) = connection.handler.into_parts();
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    let send = async {
+        let mut stdin = tokio::io::stdin();
+        let mut chunk = vec![0; PIPE_CHUNK];
+        loop {
+            let len = stdin.read(&mut chunk).await?;
+            if len == 0 {
+                break;
+            }
+            writer.write_all(&chunk[..len]).await?;
+        }
+        writer.write_all(&echo.serialise(false)).await?;
+        writer.flush().await?;
+        eprintln!("All data transferred. Waiting for the last reply...");
+        anyhow::Ok(())
+    };
+
+    let receive = async {
+        let (mut replies, mut errors) = (0, 0);
+        loop {
+            let Some((reply, len)) = parse_message(&buf)? else {
+                if reader.read_buf(&mut buf).await? == 0 {
+                    anyhow::bail!("Server closed the connection before the last reply");
+                }
+                continue;
+            };
+            let _ = buf.split_to(len);
+            if let Value::BulkString(s) = &reply
+                && s == marker.as_bytes()
+            {
+                return Ok((replies, errors));
+            }
+            replies += 1;
+            if let Some(e) = reply.error_message() {
+                errors += 1;
+                eprintln!("{e}");
+            }
+        }
+    };
+
+    let ((), (replies, errors)) = tokio::try_join!(send, receive)?;
+    eprintln!("Last reply received from server.");
+    eprintln!("errors: {errors}, replies: {replies}");
+
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// How `--bigkeys` measures a type's keys.
+fn size_unit(type_name: &str) -> &'static str {
+    match type_name {
+        "string" => "bytes",
+        "list" => "items",
+        "hash" => "fields",
+        "stream" => "entries",
+        _ => "members",
+    }
+}
+
+/// Reports on the database's keys from MEMORY ANALYZE, the way redis-cli's `--bigkeys` does,
+/// or its `--memkeys` if `by_memory`.
+async fn keys_report(
+    mut connection: Connection,
+    by_memory: bool,
+    sample: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut command = vec!["MEMORY".to_string(), "ANALYZE".to_string()];
+    if let Some(count) = sample {
+        command.extend(["SAMPLE".to_string(), count.to_string()]);
+    }
+    let reply = connection.call(&command).await?;
+    if let Some(e) = reply.error_message() {
+        anyhow::bail!("MEMORY ANALYZE failed: {e}");
+    }
+    let report = pairs(reply);
+    let field = |pairs: &[(String, Value)], name: &str| {
+        pairs
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or(Value::Null)
+    };
+    let int = |value: Value| match value {
+        Value::Integer(n) => n,
+        _ => 0,
+    };
+
+    let what = if by_memory {
+        "biggest keys by memory"
+    } else {
+        "biggest keys"
+    };
+    match sample {
+        Some(count) => println!("\n# Sampling {count} keys at random to find the {what}"),
+        None => println!("\n# Scanning the entire keyspace to find the {what}"),
+    }
+    println!("# as well as average sizes per key type.\n");
+    println!("-------- summary -------\n");
+    let keys = int(field(&report, "keys"));
+    println!("Sampled {keys} keys in the keyspace!\n");
+
+    let types = pairs(field(&report, "types"));
+    for (type_name, summary) in &types {
+        let summary = pairs(summary.clone());
+        let (key, size) = match field(&summary, if by_memory { "heaviest" } else { "biggest" }) {
+            Value::Array(pair) if pair.len() == 2 => (pair[0].clone(), int(pair[1].clone())),
+            _ => continue,
+        };
+        let Value::BulkString(key) = key else {
+            continue;
+        };
+        let unit = if by_memory {
+            "bytes"
+        } else {
+            size_unit(type_name)
+        };
+        println!(
+            "Biggest {type_name:>6} found {} has {size} {unit}",
+            quote(&key)
+        );
+    }
+    println!();
+    for (type_name, summary) in &types {
+        let summary = pairs(summary.clone());
+        let count = int(field(&summary, "keys"));
+        let (total, unit) = if by_memory {
+            (int(field(&summary, "bytes")), "bytes")
+        } else {
+            (int(field(&summary, "size")), size_unit(type_name))
+        };
+        println!(
+            "{count} {type_name}s with {total} {unit} ({:.2}% of keys, avg size {:.2})",
+            count as f64 * 100.0 / keys.max(1) as f64,
+            total as f64 / count.max(1) as f64
+        );
+    }
+
+    println!("\n-------- TTLs -------\n");
+    for (group, count) in pairs(field(&report, "ttl")) {
+        println!("{group}: {} keys", int(count));
+    }
+    Ok(())
+}
+
+/// The fields of a map reply, which comes as a flat array of names and values under RESP2.
+fn pairs(reply: Value) -> Vec<(String, Value)> {
+    let items = match reply {
+        Value::Map(pairs) => return pairs.into_iter().map(|(k, v)| (raw(&k), v)).collect(),
+        Value::Array(items) => items,
+        _ => return Vec::new(),
+    };
+    let mut items = items.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(name), Some(value)) = (items.next(), items.next()) {
+        pairs.push((raw(&name), value));
+    }
+    pairs
+}
+
+/// A reply the way redis-cli shows it at a terminal: strings quoted, integers and nils
+/// labelled, and arrays numbered, nested ones indented under their number.
+fn pretty(reply: &Value) -> String {
+    match reply {
+        Value::SimpleString(s) => s.clone(),
+        Value::Error(msg) => format!("(error) {msg}"),
+        Value::BulkString(s) => quote(s),
+        Value::Integer(n) => format!("(integer) {n}"),
+        Value::Null | Value::NullArray => "(nil)".to_string(),
+        Value::Array(items) | Value::Push(items) => numbered(items),
+        Value::Map(pairs) => numbered(
+            &pairs
+                .iter()
+                .flat_map(|(k, v)| [k.clone(), v.clone()])
+                .collect::<Vec<_>>(),
+        ),
+    }
+}
+
+fn numbered(items: &[Value]) -> String {
+    if items.is_empty() {
+        return "(empty array)".to_string();
+    }
+    let width = items.len().to_string().len();
+    let mut text = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let number = format!("{:>width$}) ", i + 1);
+        let indent = " ".repeat(number.len());
+        for (j, line) in pretty(item).lines().enumerate() {
+            let prefix = if j == 0 { &number } else { &indent };
+            text.push(format!("{prefix}{line}"));
+        }
+    }
+    text.join("\n")
+}
+
+/// A reply as it is, an array's elements a line each, as redis-cli gives it to a pipe.
+fn raw(reply: &Value) -> String {
+    match reply {
+        Value::SimpleString(s) | Value::Error(s) => s.clone(),
+        Value::BulkString(s) => String::from_utf8_lossy(s).into(),
+        Value::Integer(n) => n.to_string(),
+        Value::Null | Value::NullArray => String::new(),
+        Value::Array(items) | Value::Push(items) => {
+            items.iter().map(raw).collect::<Vec<_>>().join("\n")
+        }
+        Value::Map(pairs) => pairs
+            .iter()
+            .flat_map(|(k, v)| [raw(k), raw(v)])
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// `bytes` in double quotes, with anything unprintable escaped as redis-cli does.
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b' '..=b'~' => quoted.push(b as char),
+            b => quoted.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,662 @@
+use crate::acl;
+use crate::cmd::{self, peek};
+use crate::config;
+use crate::db::{self, Keyspace};
+use crate::pubsub::{Kind, Mailbox, PubSub, Subscriber};
+use crate::ratelimit;
+use crate::replication;
+use crate::resp::Value;
+use crate::tracking;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::debug;
+
+/// Commands queued between MULTI and EXEC. A command rejected while queueing poisons the whole
+/// transaction, so EXEC then refuses to run any of it.
+#[derive(Default)]
+pub struct Transaction {
+    queued: Vec<(String, Vec<Vec<u8>>)>,
+    aborted: bool,
+}
+
+impl Transaction {
+    /// Vets and queues a command, whose arguments have been counted, replying `QUEUED` or with
+    /// the reason it was rejected.
+    pub fn queue(&mut self, name: &str, args: Vec<Vec<u8>>) -> Value {
+        // Subscriptions reply outside the normal request/response flow, so they can't be queued
+        if cmd::pubsub::is_subscribe(name) {
+            self.aborted = true;
+            return Value::error("ERR Command not allowed inside a transaction");
+        }
+        if let Some(refusal) = replication::refusal(name) {
+            self.aborted = true;
+            return refusal;
+        }
+
+        self.queued.push((name.to_string(), args));
+
+        Value::SimpleString("QUEUED".to_string())
+    }
+
+    /// Makes EXEC refuse to run the transaction, for a command rejected before it was queued.
+    pub fn abort(&mut self) {
+        self.aborted = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// The keys of every command queued, which a cluster needs in one slot to run them together.
+    pub fn keys(&self) -> Vec<&[u8]> {
+        self.queued
+            .iter()
+            .flat_map(|(name, args)| cmd::command_keys(name, args))
+            .collect()
+    }
+
+    /// Whether EXEC would run anything CLIENT PAUSE WRITE holds up.
+    pub fn may_write(&self) -> bool {
+        self.queued.iter().any(|(name, _)| cmd::may_write(name))
+    }
+
+    /// Whether EXEC would run anything that's refused while over `maxmemory`.
+    pub fn may_grow(&self) -> bool {
+        self.queued.iter().any(|(name, _)| cmd::may_grow(name))
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    pub fn into_commands(self) -> Vec<(String, Vec<Vec<u8>>)> {
+        self.queued
+    }
+}
+
+/// What CLIENT LIST reports about a connection, which the connection keeps up to date between
+/// commands.
+struct Entry {
+    addr: String,
+    laddr: String,
+    name: Option<String>,
+    created: Instant,
+    last_interaction: Instant,
+    /// Whether the connection is running a command, blocked or not, rather than waiting for
+    /// the next one.
+    busy: bool,
+    last_command: String,
+    db: usize,
+    user: String,
+    subscriptions: [usize; 3],
+    /// Commands queued, or `None` outside MULTI.
+    multi: Option<usize>,
+    watched: usize,
+    /// RESP version, 2 or 3, as chosen with HELLO.
+    protocol: u8,
+    /// Set to `true` by CLIENT KILL, which the connection's task is waiting on.
+    kill: watch::Sender<bool>,
+    /// Where to send frames for the connection to write out unprompted.
+    mailbox: Mailbox,
+    /// Set by MONITOR, after which every command run is copied to `mailbox`.
+    monitor: bool,
+    /// Set by CLIENT NO-EVICT and NO-TOUCH.
+    no_evict: bool,
+    no_touch: bool,
+}
+
+impl Entry {
+    /// The entry as one CLIENT LIST line, without the newline.
+    fn describe(&self, id: u64) -> String {
+        let mut flags = String::new();
+        if self.subscriptions.iter().any(|count| *count > 0) {
+            flags.push('P');
+        }
+        if self.multi.is_some() {
+            flags.push('x');
+        }
+        if self.monitor {
+            flags.push('O');
+        }
+        if self.no_evict {
+            flags.push('e');
+        }
+        if self.no_touch {
+            flags.push('T');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        let [sub, psub, ssub] = self.subscriptions;
+
+        format!(
+            "id={id} addr={} laddr={} name={} age={} idle={} flags={flags} db={} sub={sub} \
+             psub={psub} ssub={ssub} multi={} watch={} omem={} cmd={} user={} resp={}",
+            self.addr,
+            self.laddr,
+            self.name.as_deref().unwrap_or_default(),
+            self.created.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            self.db,
+            self.multi.map_or(-1, |queued| queued as i64),
+            self.watched,
+            self.mailbox.pending(),
+            self.last_command,
+            self.user,
+            self.protocol,
+        )
+    }
+}
+
+/// Every open connection by ID, shared by all of them for CLIENT LIST and KILL.
+static CLIENTS: Mutex<BTreeMap<u64, Entry>> = Mutex::new(BTreeMap::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Client connections open, counted from when they're accepted, which `maxclients` caps.
+static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+/// How many connections are in MONITOR mode, so commands needn't be formatted for nobody.
+static MONITORS: AtomicUsize = AtomicUsize::new(0);
+
+/// A connection's place among the `maxclients` allowed, given up when it's dropped.
+pub struct Admission(());
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        CONNECTED.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Lets a new connection in, unless `max` are already connected.
+pub fn admit(max: usize) -> Option<Admission> {
+    CONNECTED
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |connected| {
+            (connected < max).then_some(connected + 1)
+        })
+        .ok()
+        .map(|_| Admission(()))
+}
+
+/// Which connections CLIENT KILL applies to.
+#[derive(Default)]
+pub struct KillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    /// The connection running CLIENT KILL, if it should be spared.
+    pub skip: Option<u64>,
+}
+
+/// Tells every connection matching `filter` to close, returning how many there were.
+pub fn kill(filter: &KillFilter) -> usize {
+    let clients = CLIENTS.lock().unwrap();
+
+    clients
+        .iter()
+        .filter(|(id, entry)| {
+            filter.id.is_none_or(|wanted| **id == wanted)
+                && filter.addr.as_ref().is_none_or(|addr| entry.addr == *addr)
+                && filter
+                    .laddr
+                    .as_ref()
+                    .is_none_or(|laddr| entry.laddr == *laddr)
+                && filter.skip != Some(**id)
+        })
+        .inspect(|(_, entry)| {
+            entry.kill.send_replace(true);
+        })
+        .count()
+}
+
+/// Pushes `frame` to connection `id` to write out, returning whether there's such a connection.
+pub fn push(id: u64, frame: Value) -> bool {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .get(&id)
+        .is_some_and(|entry| entry.mailbox.send(frame))
+}
+
+pub fn exists(id: u64) -> bool {
+    CLIENTS.lock().unwrap().contains_key(&id)
+}
+
+/// The RESP version connection `id` speaks, or `None` if there's no such connection.
+pub fn protocol(id: u64) -> Option<u8> {
+    CLIENTS.lock().unwrap().get(&id).map(|entry| entry.protocol)
+}
+
+/// How many connections are open, and how many of those are subscribed to something.
+pub fn counts() -> (usize, usize) {
+    let clients = CLIENTS.lock().unwrap();
+    let subscribed = clients
+        .values()
+        .filter(|entry| entry.subscriptions.iter().any(|n| *n > 0))
+        .count();
+
+    (clients.len(), subscribed)
+}
+
+/// Connection `id`'s address and name, or empty strings for a connection that's gone.
+pub fn peer(id: u64) -> (String, String) {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .get(&id)
+        .map(|entry| (entry.addr.clone(), entry.name.clone().unwrap_or_default()))
+        .unwrap_or_default()
+}
+
+/// CLIENT LIST lines for the connections `filter` accepts, in ID order.
+pub fn list(filter: impl Fn(u64, bool) -> bool) -> Vec<String> {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(id, entry)| filter(**id, entry.subscriptions.iter().any(|n| *n > 0)))
+        .map(|(id, entry)| entry.describe(*id))
+        .collect()
+}
+
+/// Sends every connection in MONITOR mode a line for command `name` with `args`, run by
+/// connection `id` on database `db`.
+pub fn feed_monitors(id: u64, db: usize, name: &str, args: &[Vec<u8>]) {
+    if MONITORS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let clients = CLIENTS.lock().unwrap();
+    let Some(source) = clients.get(&id) else {
+        return;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} [{db} {}] {}",
+        now.as_secs(),
+        now.subsec_micros(),
+        source.addr,
+        quote(name.as_bytes())
+    );
+    for arg in args {
+        line.push(' ');
+        line.push_str(&quote(arg));
+    }
+
+    for entry in clients.values().filter(|entry| entry.monitor) {
+        let _ = entry.mailbox.send(Value::SimpleString(line.clone()));
+    }
+}
+
+/// `bytes` in double quotes, with anything unprintable escaped the way MONITOR shows it.
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b' '..=b'~' => quoted.push(b as char),
+            b => quoted.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// A CLIENT PAUSE in effect: until when, and whether it holds up every command or only those
+/// that may write.
+#[derive(Clone, Copy)]
+struct Pause {
+    until: tokio::time::Instant,
+    all: bool,
+}
+
+static PAUSE: LazyLock<watch::Sender<Option<Pause>>> = LazyLock::new(|| watch::channel(None).0);
+
+/// CLIENT PAUSE: holds up commands for `timeout`, or only those that may write unless `all`.
+/// An earlier pause that would last longer keeps its end time.
+pub fn pause(timeout: Duration, all: bool) {
+    let until = tokio::time::Instant::now() + timeout;
+
+    PAUSE.send_modify(|pause| {
+        let until = pause.map_or(until, |pause| pause.until.max(until));
+        *pause = Some(Pause { until, all });
+    });
+}
+
+pub fn unpause() {
+    PAUSE.send_replace(None);
+}
+
+/// How often connections are checked for having sat idle past `timeout`.
+const IDLE_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Closes connections left idle for longer than `timeout` seconds, if it isn't 0. Those
+/// running a command, blocked ones and replicas included, and those waiting on pub/sub messages
+/// or MONITOR output aren't idle. Runs for as long as the server does.
+pub async fn close_idle() {
+    let mut interval = tokio::time::interval(IDLE_CHECK_PERIOD);
+    loop {
+        interval.tick().await;
+        let timeout = config::get().timeout;
+        if timeout == 0 {
+            continue;
+        }
+
+        let limit = Duration::from_secs(timeout);
+        for entry in CLIENTS.lock().unwrap().values() {
+            if !entry.busy
+                && !entry.monitor
+                && entry.subscriptions.iter().all(|count| *count == 0)
+                && entry.last_interaction.elapsed() > limit
+            {
+                debug!("Closing idle client {}", entry.addr);
+                entry.kill.send_replace(true);
+            }
+        }
+    }
+}
+
+/// Waits out any CLIENT PAUSE that applies to a command, which `may_write` says whether WRITE
+/// mode holds up.
+pub async fn wait_unpaused(may_write: bool) {
+    let mut pause = PAUSE.subscribe();
+
+    loop {
+        let Some(Pause { until, all }) = *pause.borrow_and_update() else {
+            return;
+        };
+        if tokio::time::Instant::now() >= until || !(all || may_write) {
+            return;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(until) => {}
+            _ = pause.changed() => {}
+        }
+    }
+}
+
+/// What CLIENT REPLY has asked to be done with the connection's replies.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReplyMode {
+    On,
+    Off,
+    /// Drop just the next command's reply.
+    Skip,
+}
+
+pub struct KillSignal(watch::Receiver<bool>);
+
+impl KillSignal {
+    /// Resolves once CLIENT KILL picks the connection, at once if it already has.
+    pub async fn killed(&mut self) {
+        // The sender lives in the registry until the connection's Client drops
+        let _ = self.0.wait_for(|killed| *killed).await;
+    }
+
+    pub fn is_killed(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// State that lives as long as one connection, as opposed to a single command.
+pub struct Client {
+    /// The connection's CLIENT ID, unique for the life of the server.
+    pub id: u64,
+    pub subscriber: Subscriber,
+    /// Index of the database SELECT last chose.
+    pub db: usize,
+    /// RESP version, 2 or 3, as chosen with HELLO.
+    pub protocol: u8,
+    /// The ACL user the connection runs commands as, or `None` until it AUTHs if the default
+    /// user needs a password.
+    pub user: Option<String>,
+    /// Set between MULTI and EXEC/DISCARD.
+    pub transaction: Option<Transaction>,
+    pub reply_mode: ReplyMode,
+    /// The port a replica takes connections on, as it says with REPLCONF listening-port.
+    pub listening_port: u16,
+    /// Whether a replica said it can take a snapshot that ends with a marker rather than
+    /// starting with its length.
+    pub capa_eof: bool,
+    /// The replication offset just past the connection's last write, which WAIT waits for
+    /// replicas to reach.
+    pub repl_offset: u64,
+    /// Set by ASKING, letting the next command at a slot this node is taking over run here.
+    pub asking: bool,
+    /// What's left of the connection's `client-rate-limit`.
+    pub rate: ratelimit::Bucket,
+    /// Set by CLIENT NO-TOUCH, after which the keys the connection reads keep their access
+    /// times and frequencies, unless it TOUCHes them.
+    pub no_touch: bool,
+    /// WATCHed keys with their database and the version each had at the time, or `None` if it
+    /// didn't exist.
+    watched: Vec<(usize, Vec<u8>, Option<u64>)>,
+    killed: watch::Receiver<bool>,
+}
+
+impl Client {
+    /// Registers a connection from `addr` accepted on `laddr`.
+    pub fn new(pubsub: Arc<PubSub>, addr: SocketAddr, laddr: SocketAddr) -> Self {
+        Self::register(pubsub, addr.to_string(), laddr.to_string())
+    }
+
+    /// Registers a connection accepted on the Unix socket at `path`, which stands in for the
+    /// address of both ends, with a port of 0 as Redis gives it.
+    pub fn unix(pubsub: Arc<PubSub>, path: &str) -> Self {
+        let addr = format!("{path}:0");
+        Self::register(pubsub, addr.clone(), addr)
+    }
+
+    fn register(pubsub: Arc<PubSub>, addr: String, laddr: String) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let user = acl::initial_user();
+        let (kill, killed) = watch::channel(false);
+        let subscriber = Subscriber::new(pubsub);
+
+        let now = Instant::now();
+        CLIENTS.lock().unwrap().insert(
+            id,
+            Entry {
+                addr,
+                laddr,
+                name: None,
+                created: now,
+                last_interaction: now,
+                busy: false,
+                last_command: "NULL".to_string(),
+                db: 0,
+                user: user.clone().unwrap_or_else(|| "default".to_string()),
+                subscriptions: [0; 3],
+                multi: None,
+                watched: 0,
+                protocol: 2,
+                kill,
+                mailbox: subscriber.mailbox(),
+                monitor: false,
+                no_evict: false,
+                no_touch: false,
+            },
+        );
+
+        Self {
+            id,
+            subscriber,
+            db: 0,
+            protocol: 2,
+            user,
+            transaction: None,
+            reply_mode: ReplyMode::On,
+            listening_port: 0,
+            capa_eof: false,
+            repl_offset: 0,
+            asking: false,
+            rate: ratelimit::Bucket::default(),
+            no_touch: false,
+            watched: Vec::new(),
+            killed,
+        }
+    }
+
+    /// Notes in the connection's CLIENT LIST entry that it's starting to run `command`.
+    pub fn record(&self, command: &str) {
+        if let Some(entry) = CLIENTS.lock().unwrap().get_mut(&self.id) {
+            entry.last_interaction = Instant::now();
+            entry.busy = true;
+            entry.last_command = command.to_string();
+        }
+    }
+
+    /// Brings the connection's CLIENT LIST entry up to date with the state its commands have
+    /// left it in.
+    pub fn sync(&self) {
+        let mut clients = CLIENTS.lock().unwrap();
+        let Some(entry) = clients.get_mut(&self.id) else {
+            return;
+        };
+
+        if entry.busy {
+            entry.last_interaction = Instant::now();
+            entry.busy = false;
+        }
+        entry.db = self.db;
+        entry.user = self.user.clone().unwrap_or_else(|| "default".to_string());
+        entry.subscriptions = [Kind::Channel, Kind::Pattern, Kind::ShardChannel]
+            .map(|kind| self.subscriber.count(kind));
+        entry.multi = self.transaction.as_ref().map(Transaction::len);
+        entry.watched = self.watched.len();
+        entry.protocol = self.protocol;
+    }
+
+    /// Whether to drop the reply to the command about to run, because of a CLIENT REPLY SKIP
+    /// before it. Replies after that one are sent again.
+    pub fn take_skip(&mut self) -> bool {
+        let skipped = self.reply_mode == ReplyMode::Skip;
+        if skipped {
+            self.reply_mode = ReplyMode::On;
+        }
+
+        skipped
+    }
+
+    /// A handle on whether CLIENT KILL has picked this connection.
+    pub fn kill_signal(&self) -> KillSignal {
+        KillSignal(self.killed.clone())
+    }
+
+    pub fn name(&self) -> Option<String> {
+        CLIENTS.lock().unwrap().get(&self.id)?.name.clone()
+    }
+
+    /// Sets or, with `None`, clears the name CLIENT GETNAME and LIST report.
+    pub fn set_name(&self, name: Option<String>) {
+        if let Some(entry) = CLIENTS.lock().unwrap().get_mut(&self.id) {
+            entry.name = name;
+        }
+    }
+
+    /// CLIENT NO-EVICT: whether the connection is held to its output buffer limits, which an
+    /// administrator's connection can be let off so it isn't dropped mid-diagnosis.
+    pub fn set_no_evict(&self, no_evict: bool) {
+        self.subscriber.output().set_exempt(no_evict);
+        if let Some(entry) = CLIENTS.lock().unwrap().get_mut(&self.id) {
+            entry.no_evict = no_evict;
+        }
+    }
+
+    /// CLIENT NO-TOUCH: whether the keys the connection reads have their access recorded.
+    pub fn set_no_touch(&mut self, no_touch: bool) {
+        self.no_touch = no_touch;
+        if let Some(entry) = CLIENTS.lock().unwrap().get_mut(&self.id) {
+            entry.no_touch = no_touch;
+        }
+    }
+
+    /// Puts the connection in MONITOR mode.
+    pub fn monitor(&self) {
+        if let Some(entry) = CLIENTS.lock().unwrap().get_mut(&self.id)
+            && !entry.monitor
+        {
+            entry.monitor = true;
+            MONITORS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// RESET: puts the connection back the way it was when it connected, apart from its name.
+    /// Transactions, watches, subscriptions, tracking, MONITOR mode, NO-EVICT and NO-TOUCH are
+    /// dropped, and it's logged back in as the default user if that needs no password.
+    pub fn reset(&mut self) {
+        self.db = 0;
+        self.protocol = 2;
+        self.user = acl::initial_user();
+        self.transaction = None;
+        self.reply_mode = ReplyMode::On;
+        self.watched.clear();
+        self.subscriber.unsubscribe_all();
+        tracking::disable(self.id);
+        self.set_no_evict(false);
+        self.set_no_touch(false);
+
+        if let Some(entry) = CLIENTS.lock().unwrap().get_mut(&self.id)
+            && entry.monitor
+        {
+            entry.monitor = false;
+            MONITORS.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The connection's own CLIENT LIST line, for CLIENT INFO.
+    pub fn info(&self) -> String {
+        CLIENTS.lock().unwrap()[&self.id].describe(self.id)
+    }
+
+    /// Remembers the current version of each key in the selected database, so EXEC can tell if
+    /// any were written since.
+    pub fn watch(&mut self, dbs: &mut [Keyspace], keys: &[Vec<u8>]) {
+        db::bump_versions(dbs);
+
+        db::select(self.db);
+        for key in keys {
+            let version = peek(&mut dbs[self.db], key).map(|val| val.version());
+            self.watched.push((self.db, key.clone(), version));
+        }
+    }
+
+    pub fn unwatch(&mut self) {
+        self.watched.clear();
+    }
+
+    /// Whether a watched key was written, deleted, created or expired since it was watched.
+    pub fn watched_changed(&self, dbs: &mut [Keyspace]) -> bool {
+        db::bump_versions(dbs);
+
+        self.watched.iter().any(|(index, key, version)| {
+            db::select(*index);
+            peek(&mut dbs[*index], key).map(|val| val.version()) != *version
+        })
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(entry) = CLIENTS.lock().unwrap().remove(&self.id)
+            && entry.monitor
+        {
+            MONITORS.fetch_sub(1, Ordering::Relaxed);
+        }
+        tracking::disable(self.id);
+    }
+}
@@ -0,0 +1,1190 @@
+use crate::crc16::crc16;
+use crate::db;
+use crate::rand;
+use crate::replication;
+use crate::resp::Value;
+use crate::stats;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// How many hash slots the keyspace is split into.
+pub const SLOTS: u16 = 16384;
+
+/// Whether the server runs as a node of a cluster, as `cluster-enabled` says. It only takes
+/// effect at startup.
+pub static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Milliseconds a node may go without answering pings before it's suspected of having failed.
+pub static NODE_TIMEOUT: AtomicU64 = AtomicU64::new(15_000);
+
+/// A node of the cluster, as far as this one knows it.
+#[derive(Clone)]
+pub struct Node {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    /// The port of its cluster bus.
+    pub cport: u16,
+    /// The ID of the master it replicates, if it's a replica.
+    pub master: Option<String>,
+    /// The epoch it last claimed its slots in. Of two nodes claiming a slot, the one with the
+    /// later epoch gets it.
+    pub config_epoch: u64,
+    /// How far it's got through its master's replication stream, or its own if it's a master.
+    pub repl_offset: u64,
+    /// Set while all that's known is its address, met with CLUSTER MEET or heard of from another
+    /// node, until it answers with its ID. Until then its ID is a made-up one.
+    pub handshake: bool,
+    /// Set when it hasn't answered this node's pings for longer than the node timeout.
+    pub pfail: bool,
+    /// When a majority of masters found it had failed, if they have since it was last back.
+    pub failed_at: Option<Instant>,
+    /// When it last answered a ping, or was first known if it never has.
+    pub pong_received: Instant,
+    /// Masters that say it looks to have failed, with when each last said so.
+    fail_reports: HashMap<String, Instant>,
+    /// When this node last voted for one of its replicas to take over from it.
+    voted_at: Option<Instant>,
+    /// Whether there's a link open to its bus.
+    linked: bool,
+}
+
+impl Node {
+    fn new(id: String, host: String, port: u16, cport: u16) -> Self {
+        Self {
+            id,
+            host,
+            port,
+            cport,
+            master: None,
+            config_epoch: 0,
+            repl_offset: 0,
+            handshake: false,
+            pfail: false,
+            failed_at: None,
+            pong_received: Instant::now(),
+            fail_reports: HashMap::new(),
+            voted_at: None,
+            linked: false,
+        }
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.master.is_none()
+    }
+}
+
+struct Cluster {
+    /// This node's ID.
+    myself: String,
+    /// The latest epoch any node is known to have got to.
+    current_epoch: u64,
+    /// Every node known, this one included, by ID.
+    nodes: HashMap<String, Node>,
+    /// The ID of the node serving each slot, if one does.
+    slots: Vec<Option<String>>,
+    /// Slots this node is handing over, with the ID of the node taking each.
+    migrating: HashMap<u16, String>,
+    /// Slots this node is taking over, with the ID of the node handing each.
+    importing: HashMap<u16, String>,
+    /// The last epoch this node voted in a failover election in.
+    last_vote_epoch: u64,
+    /// The election this node is holding to take over from its failed master, if it is.
+    election: Option<Election>,
+}
+
+/// A replica's bid to take over from its failed master, which it wins with the votes of most of
+/// the masters.
+struct Election {
+    /// When to ask for votes, later the further behind the master the replica is.
+    starts_at: Instant,
+    /// The epoch the votes were asked for in, once they have been.
+    epoch: Option<u64>,
+    /// The masters that voted for this node.
+    votes: HashSet<String>,
+}
+
+impl Cluster {
+    fn myself(&self) -> &Node {
+        &self.nodes[&self.myself]
+    }
+
+    fn myself_mut(&mut self) -> &mut Node {
+        self.nodes
+            .get_mut(&self.myself)
+            .expect("a node knows itself")
+    }
+
+    /// The flags CLUSTER NODES shows for `node`, which gossip carries too.
+    fn flags(&self, node: &Node) -> String {
+        let mut flags = Vec::new();
+        if node.id == self.myself {
+            flags.push("myself");
+        }
+        flags.push(if node.is_master() { "master" } else { "slave" });
+        if node.failed_at.is_some() {
+            flags.push("fail");
+        } else if node.pfail {
+            flags.push("fail?");
+        }
+        if node.handshake {
+            flags.push("handshake");
+        }
+
+        flags.join(",")
+    }
+
+    /// How many masters serve at least one slot, a majority of which it takes to agree that a
+    /// node has failed.
+    fn size(&self) -> usize {
+        let owners: HashSet<&String> = self.slots.iter().flatten().collect();
+        owners.len()
+    }
+}
+
+static CLUSTER: LazyLock<Mutex<Cluster>> = LazyLock::new(|| {
+    Mutex::new(Cluster {
+        myself: replication::random_id(),
+        current_epoch: 0,
+        nodes: HashMap::new(),
+        slots: vec![None; SLOTS as usize],
+        migrating: HashMap::new(),
+        importing: HashMap::new(),
+        last_vote_epoch: 0,
+        election: None,
+    })
+});
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn node_timeout() -> Duration {
+    Duration::from_millis(NODE_TIMEOUT.load(Ordering::Relaxed))
+}
+
+/// Makes this server a node serving no slots yet, taking clients on `port` and other nodes on
+/// `cport`. Its own address is learned from the first node that meets it.
+pub fn init(port: u16, cport: u16) {
+    let mut cluster = CLUSTER.lock().unwrap();
+    let node = Node::new(cluster.myself.clone(), String::new(), port, cport);
+    cluster.nodes.insert(node.id.clone(), node);
+}
+
+/// The slot `key` hashes to. A key with a `{tag}` in it hashes only the tag, the first one, so
+/// that keys sharing a tag share a slot; an empty `{}` doesn't count.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|&b| b == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        let close = rest.iter().position(|&b| b == b'}')?;
+        (close > 0).then(|| &rest[..close])
+    });
+
+    crc16(tag.unwrap_or(key)) % SLOTS
+}
+
+/// Assigns `slots` to this node, as long as none of them is served by any node yet.
+pub fn add_slots(slots: &[u16]) -> Result<(), Value> {
+    let mut cluster = CLUSTER.lock().unwrap();
+    check_once(slots)?;
+    if let Some(slot) = slots
+        .iter()
+        .find(|&&slot| cluster.slots[slot as usize].is_some())
+    {
+        return Err(Value::error(format!("ERR Slot {slot} is already busy")));
+    }
+
+    for &slot in slots {
+        cluster.slots[slot as usize] = Some(cluster.myself.clone());
+        cluster.importing.remove(&slot);
+    }
+
+    Ok(())
+}
+
+/// Leaves `slots` unassigned, as long as they all have a node serving them.
+pub fn del_slots(slots: &[u16]) -> Result<(), Value> {
+    let mut cluster = CLUSTER.lock().unwrap();
+    check_once(slots)?;
+    if let Some(slot) = slots
+        .iter()
+        .find(|&&slot| cluster.slots[slot as usize].is_none())
+    {
+        return Err(Value::error(format!(
+            "ERR Slot {slot} is already unassigned"
+        )));
+    }
+
+    for &slot in slots {
+        cluster.slots[slot as usize] = None;
+        cluster.migrating.remove(&slot);
+        cluster.importing.remove(&slot);
+    }
+
+    Ok(())
+}
+
+fn check_once(slots: &[u16]) -> Result<(), Value> {
+    let mut seen = vec![false; SLOTS as usize];
+    for &slot in slots {
+        if std::mem::replace(&mut seen[slot as usize], true) {
+            return Err(Value::error(format!(
+                "ERR Slot {slot} specified multiple times"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// How CLUSTER SETSLOT changes a slot.
+pub enum SlotState {
+    /// Hand the slot over to the node with this ID, sending ASK for keys already gone there.
+    Migrating(String),
+    /// Take the slot over from the node with this ID, serving clients that send ASKING first.
+    Importing(String),
+    /// Stop migrating or importing the slot.
+    Stable,
+    /// Give the slot to the node with this ID, once it's been moved there.
+    Node(String),
+}
+
+/// Puts `slot` in `state`. `holds_keys` says whether this node still has keys in the slot,
+/// which it can't give away until they're moved.
+pub fn set_slot(slot: u16, state: SlotState, holds_keys: bool) -> Result<(), Value> {
+    let mut cluster = CLUSTER.lock().unwrap();
+    let known = |cluster: &Cluster, id: &str| {
+        if cluster.nodes.contains_key(id) {
+            Ok(())
+        } else {
+            Err(Value::error(format!("ERR I don't know about node {id}")))
+        }
+    };
+    let owned = cluster.slots[slot as usize].as_deref() == Some(cluster.myself.as_str());
+
+    match state {
+        SlotState::Migrating(id) => {
+            if !owned {
+                return Err(Value::error(format!(
+                    "ERR I'm not the owner of hash slot {slot}"
+                )));
+            }
+            known(&cluster, &id)?;
+            if id == cluster.myself {
+                return Err(Value::error("ERR I can't migrate a slot to myself"));
+            }
+            cluster.migrating.insert(slot, id);
+        }
+        SlotState::Importing(id) => {
+            if owned {
+                return Err(Value::error(format!(
+                    "ERR I'm already the owner of hash slot {slot}"
+                )));
+            }
+            known(&cluster, &id)?;
+            if id == cluster.myself {
+                return Err(Value::error("ERR I can't import a slot from myself"));
+            }
+            cluster.importing.insert(slot, id);
+        }
+        SlotState::Stable => {
+            cluster.migrating.remove(&slot);
+            cluster.importing.remove(&slot);
+        }
+        SlotState::Node(id) => {
+            known(&cluster, &id)?;
+            if owned && id != cluster.myself && holds_keys {
+                return Err(Value::error(format!(
+                    "ERR Can't assign hashslot {slot} to a different node while I still hold \
+                     keys for this hash slot."
+                )));
+            }
+            if id != cluster.myself {
+                cluster.migrating.remove(&slot);
+            } else if cluster.importing.remove(&slot).is_some() {
+                // Taking the slot over for good, in an epoch of its own so that every other node
+                // lets the claim win over the old owner's
+                cluster.current_epoch += 1;
+                let epoch = cluster.current_epoch;
+                cluster.myself_mut().config_epoch = epoch;
+            }
+            cluster.slots[slot as usize] = Some(id);
+        }
+    }
+
+    Ok(())
+}
+
+/// The redirect to answer command `name` on `keys` with instead of running it, if this node
+/// can't serve it: MOVED to the node serving a key's slot, or ASK to the node taking over a slot
+/// for a key that's already gone there. `exists` says whether a key is still here, and `asking`
+/// whether the client sent ASKING first, after an ASK that led it here. Keys in different slots
+/// get CROSSSLOT, as no one node can be sure to serve them all.
+pub fn redirect(
+    name: &str,
+    keys: &[&[u8]],
+    asking: bool,
+    exists: impl Fn(&[u8]) -> bool,
+) -> Option<Value> {
+    // Only keys in one slot can be served together, by the one node serving it
+    if keys.iter().any(|key| key_slot(key) != key_slot(keys[0])) {
+        return Some(Value::error(
+            "CROSSSLOT Keys in request don't hash to the same slot",
+        ));
+    }
+
+    let cluster = CLUSTER.lock().unwrap();
+    let redirect = |kind: &str, slot: u16, id: &str| {
+        let node = &cluster.nodes[id];
+        Value::error(format!("{kind} {slot} {}:{}", node.host, node.port))
+    };
+
+    // The slot of a key that's missing where it's being moved from or to
+    let mut missing = None;
+    let mut found = 0;
+    for key in keys {
+        let slot = key_slot(key);
+        let Some(owner) = cluster.slots[slot as usize].as_deref() else {
+            return Some(Value::error("CLUSTERDOWN Hash slot not served"));
+        };
+        let importing = asking && cluster.importing.contains_key(&slot);
+        if owner != cluster.myself && !importing {
+            return Some(redirect("MOVED", slot, owner));
+        }
+        let migrating = owner == cluster.myself && cluster.migrating.contains_key(&slot);
+        if !migrating && !importing {
+            continue;
+        }
+        if exists(key) {
+            found += 1;
+        } else {
+            missing = Some(slot);
+        }
+    }
+
+    // MIGRATE is how the keys get moved, so it's let through whether they're here or not
+    let slot = missing.filter(|_| name != "migrate")?;
+    let try_again = || {
+        Some(Value::error(
+            "TRYAGAIN Multiple keys request during rehashing of slot",
+        ))
+    };
+    match cluster.migrating.get(&slot) {
+        // Some of the keys are here and some there, so neither node can serve it yet
+        Some(_) if found > 0 => try_again(),
+        Some(target) => Some(redirect("ASK", slot, target)),
+        None if keys.len() > 1 => try_again(),
+        None => None,
+    }
+}
+
+/// Starts a handshake with the node whose bus is at `host`:`cport`, unless one's already under
+/// way. It joins the cluster once it answers.
+pub fn meet(host: String, port: u16, cport: u16) {
+    handshake(&mut CLUSTER.lock().unwrap(), host, port, cport);
+}
+
+fn handshake(cluster: &mut Cluster, host: String, port: u16, cport: u16) {
+    let started = cluster
+        .nodes
+        .values()
+        .any(|node| node.handshake && node.host == host && node.cport == cport);
+    if !started {
+        let mut node = Node::new(replication::random_id(), host, port, cport);
+        node.handshake = true;
+        cluster.nodes.insert(node.id.clone(), node);
+    }
+}
+
+/// The fields every message on the bus starts with: its kind, then who sent it and what it
+/// serves. The rest depends on the kind.
+fn header(cluster: &Cluster, kind: &str) -> Vec<Vec<u8>> {
+    let me = cluster.myself();
+    let mut bitmap = vec![0u8; SLOTS as usize / 8];
+    for (slot, owner) in cluster.slots.iter().enumerate() {
+        if owner.as_deref() == Some(&cluster.myself) {
+            bitmap[slot / 8] |= 1 << (slot % 8);
+        }
+    }
+
+    vec![
+        kind.as_bytes().to_vec(),
+        me.id.clone().into_bytes(),
+        me.port.to_string().into_bytes(),
+        me.cport.to_string().into_bytes(),
+        cluster.flags(me).into_bytes(),
+        me.master.clone().unwrap_or("-".to_string()).into_bytes(),
+        cluster.current_epoch.to_string().into_bytes(),
+        me.config_epoch.to_string().into_bytes(),
+        replication::offset().to_string().into_bytes(),
+        bitmap,
+    ]
+}
+
+const HEADER_LEN: usize = 10;
+
+/// A PING, PONG or MEET, which carry gossip about every other node this one knows.
+pub fn message(kind: &str) -> Value {
+    gossip_message(&CLUSTER.lock().unwrap(), kind)
+}
+
+fn gossip_message(cluster: &Cluster, kind: &str) -> Value {
+    let mut fields = header(cluster, kind);
+    for node in cluster.nodes.values() {
+        if node.id == cluster.myself || node.handshake {
+            continue;
+        }
+        fields.extend([
+            node.id.clone().into_bytes(),
+            node.host.clone().into_bytes(),
+            node.port.to_string().into_bytes(),
+            node.cport.to_string().into_bytes(),
+            cluster.flags(node).into_bytes(),
+        ]);
+    }
+
+    Value::Array(
+        fields
+            .into_iter()
+            .map(|field| Value::BulkString(field.into()))
+            .collect(),
+    )
+}
+
+/// A FAIL, telling every node that `id` has failed.
+fn fail_message(cluster: &Cluster, id: &str) -> Value {
+    let mut fields = header(cluster, "fail");
+    fields.push(id.as_bytes().to_vec());
+
+    Value::Array(
+        fields
+            .into_iter()
+            .map(|field| Value::BulkString(field.into()))
+            .collect(),
+    )
+}
+
+/// An AUTH-REQUEST, asking the masters to vote for this node to take over from its master, or
+/// the AUTH-ACK that gives a vote. Neither carries more than the header.
+fn auth_message(cluster: &Cluster, kind: &str) -> Value {
+    Value::Array(
+        header(cluster, kind)
+            .into_iter()
+            .map(|field| Value::BulkString(field.into()))
+            .collect(),
+    )
+}
+
+/// A message from the bus, split into its fields.
+fn fields(message: Value) -> Option<Vec<Vec<u8>>> {
+    let Value::Array(items) = message else {
+        return None;
+    };
+    let fields: Vec<_> = items
+        .into_iter()
+        .map(|item| match item {
+            Value::BulkString(field) => Some(field.into()),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    (fields.len() >= HEADER_LEN).then_some(fields)
+}
+
+fn text(field: &[u8]) -> String {
+    String::from_utf8_lossy(field).into_owned()
+}
+
+fn number<T: std::str::FromStr>(field: &[u8]) -> Option<T> {
+    std::str::from_utf8(field).ok()?.parse().ok()
+}
+
+/// Takes in a message from another node's link to this one, which came from `peer` and
+/// arrived at `local`. Returns the PONG to answer a PING or MEET with, or the vote to answer an
+/// AUTH-REQUEST with if this node gives it one.
+pub fn receive(received: Value, peer: IpAddr, local: IpAddr) -> Option<Value> {
+    let fields = fields(received)?;
+    let kind = text(&fields[0]);
+    let sender = text(&fields[1]);
+    let port = number(&fields[2])?;
+    let cport = number(&fields[3])?;
+    let flags = text(&fields[4]);
+    let master = Some(text(&fields[5])).filter(|id| id != "-");
+    let current_epoch = number(&fields[6])?;
+    let config_epoch = number(&fields[7])?;
+    let repl_offset = number(&fields[8])?;
+    let bitmap = &fields[9];
+
+    let mut cluster = CLUSTER.lock().unwrap();
+    let cluster = &mut *cluster;
+    cluster.current_epoch = cluster.current_epoch.max(current_epoch);
+    // Nodes go by the address others reach them at, which this one learns from whoever meets it
+    let peer = peer.to_canonical().to_string();
+    if kind == "meet" && cluster.myself().host.is_empty() {
+        cluster.myself_mut().host = local.to_canonical().to_string();
+    }
+    if kind == "meet" && !cluster.nodes.contains_key(&sender) && sender != cluster.myself {
+        let node = Node::new(sender.clone(), peer.clone(), port, cport);
+        cluster.nodes.insert(sender.clone(), node);
+        info!("Node {sender} ({peer}:{port}) met this one");
+    }
+
+    let known = sender != cluster.myself && cluster.nodes.contains_key(&sender);
+    if let Some(node) = cluster.nodes.get_mut(&sender)
+        && known
+    {
+        node.host = peer;
+        node.port = port;
+        node.cport = cport;
+        node.master = master;
+        node.config_epoch = config_epoch;
+        node.repl_offset = repl_offset;
+        let is_master = !flags.split(',').any(|flag| flag == "slave");
+
+        if is_master {
+            claim_slots(cluster, &sender, bitmap);
+            resolve_epoch_collision(cluster, &sender);
+        }
+        gossip(cluster, &sender, is_master, &fields[HEADER_LEN..], &kind);
+    }
+
+    match kind.as_str() {
+        "ping" | "meet" => Some(gossip_message(cluster, "pong")),
+        "auth-request" if known => vote(cluster, &sender, current_epoch),
+        "auth-ack" if known => {
+            count_vote(cluster, &sender, current_epoch);
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Gives `sender` the slots in `bitmap` that it claims in a later epoch than whoever has them,
+/// or that nobody has. A node left with no slots to serve or replicate by that becomes a
+/// replica of `sender`, which will have taken over from it or its master.
+fn claim_slots(cluster: &mut Cluster, sender: &str, bitmap: &[u8]) {
+    let epoch = cluster.nodes[sender].config_epoch;
+    // The node whose slots this one serves, itself or its master
+    let serving = cluster
+        .myself()
+        .master
+        .clone()
+        .unwrap_or(cluster.myself.clone());
+    let mut lost = false;
+    for slot in 0..(SLOTS as usize).min(bitmap.len() * 8) {
+        if bitmap[slot / 8] & (1 << (slot % 8)) == 0 {
+            continue;
+        }
+        let owner = cluster.slots[slot].as_deref();
+        if owner == Some(sender) {
+            continue;
+        }
+        // Nodes taking a slot over get it with CLUSTER SETSLOT rather than by hearing it's
+        // theirs
+        if cluster.importing.contains_key(&(slot as u16)) {
+            continue;
+        }
+        let taken = owner.and_then(|owner| cluster.nodes.get(owner));
+        if taken.is_none_or(|owner| owner.config_epoch < epoch) {
+            lost |= owner == Some(serving.as_str());
+            cluster.slots[slot] = Some(sender.to_string());
+            cluster.migrating.remove(&(slot as u16));
+        }
+    }
+
+    if lost
+        && !cluster
+            .slots
+            .iter()
+            .flatten()
+            .any(|owner| *owner == serving)
+    {
+        info!("Configuration change detected. Reconfiguring myself as a replica of {sender}");
+        follow(cluster, sender);
+    }
+}
+
+/// Makes this node a replica of `id`, syncing with it from now on.
+fn follow(cluster: &mut Cluster, id: &str) {
+    let master = &cluster.nodes[id];
+    let address = (master.host.clone(), master.port);
+    cluster.myself_mut().master = Some(id.to_string());
+    cluster.migrating.clear();
+    cluster.importing.clear();
+    cluster.election = None;
+    replication::follow(Some(address));
+}
+
+/// Makes this node a replica of the master `id`, as long as it serves no slots nor has any keys
+/// of its own, which `empty` says.
+pub fn replicate(id: &str, empty: bool) -> Result<(), Value> {
+    let mut cluster = CLUSTER.lock().unwrap();
+    let Some(node) = cluster.nodes.get(id).filter(|node| !node.handshake) else {
+        return Err(Value::error(format!("ERR Unknown node {id}")));
+    };
+    if id == cluster.myself {
+        return Err(Value::error("ERR Can't replicate myself"));
+    }
+    if !node.is_master() {
+        return Err(Value::error(
+            "ERR I can only replicate a master, not a replica.",
+        ));
+    }
+    let serves = cluster
+        .slots
+        .iter()
+        .flatten()
+        .any(|owner| *owner == cluster.myself);
+    if cluster.myself().is_master() && (serves || !empty) {
+        return Err(Value::error(
+            "ERR To set a master the node must be empty and without assigned slots.",
+        ));
+    }
+
+    follow(&mut cluster, id);
+    Ok(())
+}
+
+/// Gives `sender` this node's vote to take over from its failed master, in the election it
+/// asked for in `epoch`, unless this node isn't a master with slots of its own or already voted
+/// in that epoch or for that master lately. Returns the AUTH-ACK that gives the vote.
+fn vote(cluster: &mut Cluster, sender: &str, epoch: u64) -> Option<Value> {
+    let me = cluster.myself();
+    let serves = cluster
+        .slots
+        .iter()
+        .flatten()
+        .any(|owner| *owner == cluster.myself);
+    if !me.is_master() || !serves {
+        return None;
+    }
+    if epoch < cluster.current_epoch || cluster.last_vote_epoch == cluster.current_epoch {
+        return None;
+    }
+    let master_id = cluster.nodes[sender].master.clone()?;
+    let master = cluster.nodes.get_mut(&master_id)?;
+    if master.failed_at.is_none()
+        || master
+            .voted_at
+            .is_some_and(|at| at.elapsed() < node_timeout() * 2)
+    {
+        return None;
+    }
+
+    master.voted_at = Some(Instant::now());
+    cluster.last_vote_epoch = cluster.current_epoch;
+    info!("Failover auth granted to {sender} for epoch {epoch}");
+    Some(auth_message(cluster, "auth-ack"))
+}
+
+/// Counts the vote of `sender`, given in `epoch`, towards this node's election.
+fn count_vote(cluster: &mut Cluster, sender: &str, epoch: u64) {
+    let serves = cluster.slots.iter().flatten().any(|owner| owner == sender);
+    if let Some(election) = &mut cluster.election
+        && election.epoch.is_some_and(|asked| epoch >= asked)
+        && cluster.nodes[sender].is_master()
+        && serves
+    {
+        election.votes.insert(sender.to_string());
+    }
+}
+
+/// Runs this node's election to take over from its master once the master has failed, run every
+/// so often: waits a while, asks the masters to vote, and takes the master's slots once most of
+/// them have. Returns the messages to broadcast, asking for votes or claiming the slots.
+pub fn failover() -> Vec<Value> {
+    let mut cluster = CLUSTER.lock().unwrap();
+    let cluster = &mut *cluster;
+    let failed = cluster.myself().master.clone().filter(|master| {
+        cluster.nodes[master].failed_at.is_some()
+            && cluster.slots.iter().flatten().any(|owner| owner == master)
+    });
+    let Some(master) = failed else {
+        cluster.election = None;
+        return Vec::new();
+    };
+
+    // Votes are waited on for twice the node timeout, and asked for again after twice that
+    let timeout = node_timeout().max(Duration::from_secs(1)) * 2;
+    let now = Instant::now();
+    if cluster
+        .election
+        .as_ref()
+        .is_none_or(|election| now > election.starts_at + timeout * 2)
+    {
+        // Replicas further behind wait longer, so the one that's furthest ahead is likely to win
+        let offset = replication::offset();
+        let rank = cluster
+            .nodes
+            .values()
+            .filter(|node| node.master.as_ref() == Some(&master) && node.repl_offset > offset)
+            .count();
+        let delay = Duration::from_millis(500 + rand::below(500) as u64 + rank as u64 * 1000);
+        info!(
+            "Start of election delayed for {} milliseconds (rank #{rank}, offset {offset}).",
+            delay.as_millis()
+        );
+        cluster.election = Some(Election {
+            starts_at: now + delay,
+            epoch: None,
+            votes: HashSet::new(),
+        });
+        return Vec::new();
+    }
+
+    let needed = cluster.size() / 2 + 1;
+    let election = cluster
+        .election
+        .as_mut()
+        .expect("an election was just checked for");
+    match election.epoch {
+        _ if now < election.starts_at => Vec::new(),
+        None => {
+            cluster.current_epoch += 1;
+            election.epoch = Some(cluster.current_epoch);
+            info!(
+                "Starting a failover election for epoch {}.",
+                cluster.current_epoch
+            );
+            vec![auth_message(cluster, "auth-request")]
+        }
+        Some(epoch) if election.votes.len() >= needed && now <= election.starts_at + timeout => {
+            info!("Failover election won for epoch {epoch}, taking over from {master}");
+            promote(cluster, &master, epoch);
+            vec![gossip_message(cluster, "pong")]
+        }
+        Some(_) => Vec::new(),
+    }
+}
+
+/// Makes this node a master serving the slots of `master`, the one it replicated, in `epoch`.
+fn promote(cluster: &mut Cluster, master: &str, epoch: u64) {
+    for owner in cluster.slots.iter_mut().flatten() {
+        if owner == master {
+            *owner = cluster.myself.clone();
+        }
+    }
+    let me = cluster.myself_mut();
+    me.master = None;
+    me.config_epoch = me.config_epoch.max(epoch);
+    cluster.election = None;
+    replication::follow(None);
+}
+
+/// Gives this node an epoch of its own when it shares one with `sender`, as both being masters,
+/// so that they never tie claiming a slot. Of the two, the one with the smaller ID moves on.
+fn resolve_epoch_collision(cluster: &mut Cluster, sender: &str) {
+    let me = cluster.myself();
+    if !me.is_master()
+        || me.config_epoch != cluster.nodes[sender].config_epoch
+        || sender <= cluster.myself.as_str()
+    {
+        return;
+    }
+
+    cluster.current_epoch += 1;
+    let epoch = cluster.current_epoch;
+    cluster.myself_mut().config_epoch = epoch;
+    warn!("configEpoch collision with node {sender}. configEpoch set to {epoch}");
+}
+
+/// Takes in what `sender` says about the other nodes, in `entries` of five fields each, or the
+/// node it says has failed if this is a FAIL.
+fn gossip(cluster: &mut Cluster, sender: &str, from_master: bool, entries: &[Vec<u8>], kind: &str) {
+    if kind == "fail" {
+        if let Some(id) = entries.first().map(|id| text(id))
+            && id != cluster.myself
+            && let Some(node) = cluster.nodes.get_mut(&id)
+            && node.failed_at.is_none()
+        {
+            node.failed_at = Some(Instant::now());
+            info!("FAIL message received from {sender} about {id}");
+        }
+        return;
+    }
+
+    for entry in entries.chunks_exact(5) {
+        let id = text(&entry[0]);
+        let failing = text(&entry[4])
+            .split(',')
+            .any(|flag| flag == "fail" || flag == "fail?");
+        if id == cluster.myself {
+            continue;
+        }
+
+        match cluster.nodes.get_mut(&id) {
+            Some(node) if from_master && failing => {
+                node.fail_reports.insert(sender.to_string(), Instant::now());
+            }
+            Some(node) => {
+                node.fail_reports.remove(sender);
+            }
+            // A node this one hasn't met yet, which it goes on to
+            None => {
+                let host = text(&entry[1]);
+                if let (Some(port), Some(cport)) = (number(&entry[2]), number(&entry[3]))
+                    && !failing
+                    && !host.is_empty()
+                {
+                    handshake(cluster, host, port, cport);
+                }
+            }
+        }
+    }
+}
+
+/// Notes a PONG from `sender` on the link to the node this one knows as `id`. A node in the
+/// handshake takes the ID it gave. Returns the ID to keep the link going under, or `None` if the
+/// link has no further use, as when it turns out to be to a node known already.
+pub fn answered(id: &str, message: &Value) -> Option<String> {
+    let Value::Array(items) = message else {
+        return Some(id.to_string());
+    };
+    let Some(Value::BulkString(sender)) = items.get(1) else {
+        return Some(id.to_string());
+    };
+    let sender = text(sender);
+
+    let mut cluster = CLUSTER.lock().unwrap();
+    let node = cluster.nodes.get(id)?;
+    if node.handshake {
+        let mut node = cluster.nodes.remove(id).expect("node was just found");
+        if cluster.nodes.contains_key(&sender) || sender == cluster.myself {
+            return None;
+        }
+        info!(
+            "Handshake with node {sender} ({}:{}) completed",
+            node.host, node.port
+        );
+        node.id = sender.clone();
+        node.handshake = false;
+        cluster.nodes.insert(sender.clone(), node);
+    } else if sender != id {
+        return None;
+    }
+
+    let serves = cluster.slots.iter().flatten().any(|owner| *owner == sender);
+    let node = cluster.nodes.get_mut(&sender).expect("node was just found");
+    node.pong_received = Instant::now();
+    node.pfail = false;
+    // A master that still serves slots stays failed until it's had long enough to be replaced
+    if let Some(failed_at) = node.failed_at
+        && (!serves || !node.is_master() || failed_at.elapsed() > node_timeout() * 2)
+    {
+        node.failed_at = None;
+        info!("Clear FAIL state for node {sender}: it is reachable again.");
+    }
+
+    Some(sender)
+}
+
+/// Nodes to open links to, as `(id, host, cport)`, which count as linked from now on.
+pub fn unlinked() -> Vec<(String, String, u16)> {
+    let mut cluster = CLUSTER.lock().unwrap();
+    let myself = cluster.myself.clone();
+    cluster
+        .nodes
+        .values_mut()
+        .filter(|node| node.id != myself && !node.linked && !node.host.is_empty())
+        .map(|node| {
+            node.linked = true;
+            (node.id.clone(), node.host.clone(), node.cport)
+        })
+        .collect()
+}
+
+/// Notes that the link to `id` has gone.
+pub fn unlink(id: &str) {
+    if let Some(node) = CLUSTER.lock().unwrap().nodes.get_mut(id) {
+        node.linked = false;
+    }
+}
+
+/// Whether `id` is only known by its address so far.
+pub fn in_handshake(id: &str) -> bool {
+    CLUSTER
+        .lock()
+        .unwrap()
+        .nodes
+        .get(id)
+        .is_some_and(|node| node.handshake)
+}
+
+/// Looks over the nodes, run every so often: drops handshakes that never completed, suspects
+/// nodes that stopped answering of failing, and finds them failed once most masters agree.
+/// Returns the FAIL messages to broadcast about them.
+pub fn check_nodes() -> Vec<Value> {
+    let timeout = node_timeout();
+    let mut cluster = CLUSTER.lock().unwrap();
+    let myself = cluster.myself.clone();
+    cluster.nodes.retain(|_, node| {
+        !node.handshake || node.pong_received.elapsed() <= timeout.max(Duration::from_secs(1))
+    });
+
+    let needed = cluster.size() / 2 + 1;
+    let counts_itself = cluster.myself().is_master();
+    let mut failed = Vec::new();
+    for node in cluster.nodes.values_mut() {
+        if node.id == myself || node.handshake {
+            continue;
+        }
+        if node.pong_received.elapsed() > timeout && !node.pfail {
+            node.pfail = true;
+            debug!("*** NODE {} possibly failing", node.id);
+        }
+        node.fail_reports
+            .retain(|_, at| at.elapsed() <= timeout * 2);
+
+        let reports = node.fail_reports.len() + usize::from(counts_itself);
+        if node.pfail && node.failed_at.is_none() && reports >= needed {
+            node.failed_at = Some(Instant::now());
+            info!("Marking node {} as failing (quorum reached).", node.id);
+            failed.push(node.id.clone());
+        }
+    }
+
+    failed.iter().map(|id| fail_message(&cluster, id)).collect()
+}
+
+/// The slots each node serves, by ID, as inclusive ranges in order.
+fn slot_ranges(cluster: &Cluster) -> HashMap<&str, Vec<(u16, u16)>> {
+    let mut ranges: HashMap<&str, Vec<(u16, u16)>> = HashMap::new();
+    for (slot, owner) in cluster.slots.iter().enumerate() {
+        let Some(owner) = owner else {
+            continue;
+        };
+        let slot = slot as u16;
+        let owned = ranges.entry(owner).or_default();
+        match owned.last_mut() {
+            Some((_, end)) if *end + 1 == slot => *end = slot,
+            _ => owned.push((slot, slot)),
+        }
+    }
+
+    ranges
+}
+
+/// The nodes serving slots, with the replicas of each.
+fn masters(cluster: &Cluster) -> Vec<(&Node, Vec<&Node>)> {
+    let ranges = slot_ranges(cluster);
+    let mut masters: Vec<&Node> = cluster
+        .nodes
+        .values()
+        .filter(|node| node.is_master() && !node.handshake && ranges.contains_key(node.id.as_str()))
+        .collect();
+    masters.sort_by_key(|node| ranges[node.id.as_str()][0]);
+
+    masters
+        .into_iter()
+        .map(|master| {
+            let replicas = cluster
+                .nodes
+                .values()
+                .filter(|node| node.master.as_deref() == Some(&master.id))
+                .collect();
+            (master, replicas)
+        })
+        .collect()
+}
+
+pub fn myid() -> String {
+    CLUSTER.lock().unwrap().myself.clone()
+}
+
+/// CLUSTER INFO's report: whether every slot is served, and by how healthy a set of nodes.
+pub fn info() -> String {
+    let cluster = CLUSTER.lock().unwrap();
+    let (mut assigned, mut pfail, mut fail) = (0, 0, 0);
+    for owner in cluster.slots.iter().flatten() {
+        assigned += 1;
+        match cluster.nodes.get(owner) {
+            Some(node) if node.failed_at.is_some() => fail += 1,
+            Some(node) if node.pfail => pfail += 1,
+            _ => {}
+        }
+    }
+    let ok = assigned == SLOTS as usize && fail == 0;
+    let known = cluster
+        .nodes
+        .values()
+        .filter(|node| !node.handshake)
+        .count();
+
+    [
+        format!("cluster_state:{}", if ok { "ok" } else { "fail" }),
+        format!("cluster_slots_assigned:{assigned}"),
+        format!("cluster_slots_ok:{}", assigned - pfail - fail),
+        format!("cluster_slots_pfail:{pfail}"),
+        format!("cluster_slots_fail:{fail}"),
+        format!("cluster_known_nodes:{known}"),
+        format!("cluster_size:{}", cluster.size()),
+        format!("cluster_current_epoch:{}", cluster.current_epoch),
+        format!("cluster_my_epoch:{}", cluster.myself().config_epoch),
+        format!(
+            "cluster_stats_messages_sent:{}",
+            stats::CLUSTER_MESSAGES_SENT.load(Ordering::Relaxed)
+        ),
+        format!(
+            "cluster_stats_messages_received:{}",
+            stats::CLUSTER_MESSAGES_RECEIVED.load(Ordering::Relaxed)
+        ),
+    ]
+    .iter()
+    .map(|line| format!("{line}\r\n"))
+    .collect()
+}
+
+/// CLUSTER NODES' report: a line for every node known, in the format of Redis' nodes.conf.
+pub fn nodes() -> String {
+    let cluster = CLUSTER.lock().unwrap();
+    let ranges = slot_ranges(&cluster);
+    let now = db::unix_millis();
+
+    let mut out = String::new();
+    for node in cluster.nodes.values() {
+        let myself = node.id == cluster.myself;
+        let pong_received = if myself {
+            0
+        } else {
+            now.saturating_sub(node.pong_received.elapsed().as_millis() as u64)
+        };
+        let link = if myself || node.linked {
+            "connected"
+        } else {
+            "disconnected"
+        };
+        out.push_str(&format!(
+            "{} {}:{}@{} {} {} 0 {pong_received} {} {link}",
+            node.id,
+            node.host,
+            node.port,
+            node.cport,
+            cluster.flags(node),
+            node.master.as_deref().unwrap_or("-"),
+            node.config_epoch,
+        ));
+        for &(start, end) in ranges.get(node.id.as_str()).into_iter().flatten() {
+            if start == end {
+                out.push_str(&format!(" {start}"));
+            } else {
+                out.push_str(&format!(" {start}-{end}"));
+            }
+        }
+        if myself {
+            let mut moving: Vec<_> = cluster
+                .migrating
+                .iter()
+                .map(|(slot, id)| (*slot, format!(" [{slot}->-{id}]")))
+                .chain(
+                    cluster
+                        .importing
+                        .iter()
+                        .map(|(slot, id)| (*slot, format!(" [{slot}-<-{id}]"))),
+                )
+                .collect();
+            moving.sort();
+            out.extend(moving.into_iter().map(|(_, entry)| entry));
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// How to reach `node`, as CLUSTER SLOTS lists it: address, port and ID.
+fn endpoint(node: &Node) -> Value {
+    Value::Array(vec![
+        Value::BulkString(node.host.clone().into()),
+        Value::Integer(node.port as i64),
+        Value::BulkString(node.id.clone().into()),
+    ])
+}
+
+/// CLUSTER SLOTS' report: each range of slots with the node serving it, then its replicas.
+pub fn slots() -> Value {
+    let cluster = CLUSTER.lock().unwrap();
+    let ranges = slot_ranges(&cluster);
+
+    let mut entries = Vec::new();
+    for (master, replicas) in masters(&cluster) {
+        for &(start, end) in &ranges[master.id.as_str()] {
+            let mut entry = vec![
+                Value::Integer(start as i64),
+                Value::Integer(end as i64),
+                endpoint(master),
+            ];
+            entry.extend(
+                replicas
+                    .iter()
+                    .filter(|replica| replica.failed_at.is_none())
+                    .map(|replica| endpoint(replica)),
+            );
+            entries.push((start, Value::Array(entry)));
+        }
+    }
+    entries.sort_by_key(|(start, _)| *start);
+
+    Value::Array(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// CLUSTER SHARDS' report: each master with the slots it serves and its replicas.
+pub fn shards() -> Value {
+    let cluster = CLUSTER.lock().unwrap();
+    let ranges = slot_ranges(&cluster);
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    let describe = |node: &Node| {
+        let offset = if node.id == cluster.myself {
+            replication::offset()
+        } else {
+            0
+        };
+        let health = if node.failed_at.is_some() {
+            "fail"
+        } else {
+            "online"
+        };
+        Value::Map(vec![
+            (bulk("id"), bulk(&node.id)),
+            (bulk("port"), Value::Integer(node.port as i64)),
+            (bulk("ip"), bulk(&node.host)),
+            (bulk("endpoint"), bulk(&node.host)),
+            (
+                bulk("role"),
+                bulk(if node.is_master() {
+                    "master"
+                } else {
+                    "replica"
+                }),
+            ),
+            (bulk("replication-offset"), Value::Integer(offset as i64)),
+            (bulk("health"), bulk(health)),
+        ])
+    };
+
+    let shards = masters(&cluster)
+        .into_iter()
+        .map(|(master, replicas)| {
+            let slots = ranges[master.id.as_str()]
+                .iter()
+                .flat_map(|&(start, end)| {
+                    [Value::Integer(start as i64), Value::Integer(end as i64)]
+                })
+                .collect();
+            let nodes = std::iter::once(master)
+                .chain(replicas)
+                .map(describe)
+                .collect();
+            Value::Map(vec![
+                (bulk("slots"), Value::Array(slots)),
+                (bulk("nodes"), Value::Array(nodes)),
+            ])
+        })
+        .collect();
+
+    Value::Array(shards)
+}
@@ -0,0 +1,82 @@
+use crate::acl;
+use crate::cmd::{self, lower, registry::Spec, wrong_args};
+use crate::resp::Value;
+use bytes::Bytes;
+
+/// The ACL commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::server("auth", -2, [0, 0, 0], |ctx, args| {
+        auth(&mut ctx.client.user, args)
+    }),
+    Spec::server("acl", -2, [0, 0, 0], |ctx, args| {
+        acl(ctx.client.user.as_deref().unwrap_or("default"), args)
+    }),
+];
+
+/// AUTH [username] password, logging the connection in as `user` on success.
+pub fn auth(user: &mut Option<String>, args: &[Vec<u8>]) -> Value {
+    let (name, password) = match args {
+        [password] => {
+            if !acl::default_has_password() {
+                return Value::error(
+                    "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?",
+                );
+            }
+            ("default".to_string(), password)
+        }
+        [name, password] => (String::from_utf8_lossy(name).into_owned(), password),
+        _ => return wrong_args("auth"),
+    };
+
+    if acl::authenticate(&name, password) {
+        *user = Some(name);
+        Value::SimpleString("OK".to_string())
+    } else {
+        Value::error("WRONGPASS invalid username-password pair or user is disabled.")
+    }
+}
+
+/// ACL SETUSER username [rule ...] | GETUSER username | DELUSER username [username ...] | LIST
+/// | USERS | WHOAMI | CAT [category]
+pub fn acl(user: &str, args: &[Vec<u8>]) -> Value {
+    if args.is_empty() {
+        return wrong_args("acl");
+    }
+
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    let subcommand = lower(&args[0]);
+    match (subcommand.as_str(), &args[1..]) {
+        ("setuser", [name, rules @ ..]) => {
+            match acl::set_user(&String::from_utf8_lossy(name), rules) {
+                Ok(()) => Value::SimpleString("OK".to_string()),
+                Err(e) => e,
+            }
+        }
+        ("getuser", [name]) => acl::get_user(&String::from_utf8_lossy(name)).unwrap_or(Value::Null),
+        ("deluser", names) if !names.is_empty() => match acl::delete(names) {
+            Ok(deleted) => Value::Integer(deleted as i64),
+            Err(e) => e,
+        },
+        ("list", []) => Value::Array(acl::list().iter().map(|line| bulk(line)).collect()),
+        ("users", []) => Value::Array(acl::user_names().iter().map(|name| bulk(name)).collect()),
+        ("whoami", []) => bulk(user),
+        ("cat", []) => Value::Array(cmd::category_names().into_iter().map(bulk).collect()),
+        ("cat", [category]) => {
+            let category = lower(category);
+            match cmd::category_commands(&category) {
+                Some(commands) if category != "all" => {
+                    Value::Array(commands.into_iter().map(bulk).collect())
+                }
+                _ => Value::error(format!("ERR Unknown category '{category}'")),
+            }
+        }
+        ("setuser" | "getuser" | "deluser" | "list" | "users" | "whoami" | "cat", _) => {
+            Value::error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try ACL HELP."
+            ))
+        }
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try ACL HELP."
+        )),
+    }
+}
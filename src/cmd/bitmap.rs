@@ -0,0 +1,656 @@
+use crate::cmd::{
+    lookup, lower, not_an_integer, parse_int, registry::Spec, syntax_error, wrong_args, wrong_type,
+};
+use crate::db::{DBData, DBVal, Keyspace, StringMut};
+use crate::notify::{self, Class};
+use crate::resp::Value;
+use bytes::Bytes;
+use std::borrow::Cow;
+
+/// The bitmap commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("setbit", 4, [1, 1, 1], setbit),
+    Spec::keyspace("getbit", 3, [1, 1, 1], getbit),
+    Spec::keyspace("bitcount", -2, [1, 1, 1], bitcount),
+    Spec::keyspace("bitpos", -3, [1, 1, 1], bitpos).at_most(6),
+    Spec::keyspace("bitop", -4, [2, -1, 1], bitop),
+    Spec::keyspace("bitfield", -2, [1, 1, 1], bitfield),
+    Spec::keyspace("bitfield_ro", -2, [1, 1, 1], bitfield_ro),
+];
+
+/// Largest bit offset SETBIT and friends accept, matching Redis' 512MB string limit.
+const MAX_BIT_OFFSET: u64 = 512 * 1024 * 1024 * 8 - 1;
+
+/// Reads the string at `key` as raw bytes. A missing key reads as empty.
+fn read_bytes<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<Cow<'a, [u8]>, Value> {
+    match lookup(db, key) {
+        None => Ok(Cow::Borrowed(&[])),
+        Some(val) => val.data().string_bytes().ok_or_else(wrong_type),
+    }
+}
+
+/// The string at `key` as a mutable byte buffer, creating an empty one if needed. Integers
+/// are converted to their byte form first, since bit edits can make them non-numeric.
+pub fn get_or_create_bytes<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<StringMut<'a>, Value> {
+    match lookup(db, key) {
+        None => {
+            db.insert(key.to_vec(), DBData::new(DBVal::String(Bytes::new()), None));
+        }
+        Some(val) => {
+            if let DBVal::Int(n) = val.data() {
+                *val.data_mut() = DBVal::String(n.to_string().into());
+            }
+        }
+    }
+
+    match db.get_mut(key).map(|val| val.data_mut()) {
+        Some(DBVal::String(bytes)) => Ok(StringMut::new(bytes)),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// Parses a bit offset, rejecting anything past the maximum string size.
+pub fn parse_bit_offset(arg: &[u8]) -> Option<u64> {
+    parse_int::<u64>(arg).filter(|&offset| offset <= MAX_BIT_OFFSET)
+}
+
+fn bit_offset_error() -> Value {
+    Value::error("ERR bit offset is not an integer or out of range")
+}
+
+/// Bit `offset` of `bytes`, counting from the most significant bit of the first byte.
+pub fn get_bit(bytes: &[u8], offset: u64) -> bool {
+    let byte = (offset / 8) as usize;
+    let bit = 7 - (offset % 8) as u32;
+
+    bytes.get(byte).is_some_and(|b| (b >> bit) & 1 == 1)
+}
+
+/// Sets bit `offset`, growing `bytes` with zeroes as needed, and returns the old bit.
+pub fn set_bit(bytes: &mut Vec<u8>, offset: u64, on: bool) -> bool {
+    let byte = (offset / 8) as usize;
+    let bit = 7 - (offset % 8) as u32;
+
+    if bytes.len() <= byte {
+        bytes.resize(byte + 1, 0);
+    }
+    let old = (bytes[byte] >> bit) & 1 == 1;
+    if on {
+        bytes[byte] |= 1 << bit;
+    } else {
+        bytes[byte] &= !(1 << bit);
+    }
+
+    old
+}
+
+fn setbit(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(offset) = parse_bit_offset(&args[1]) else {
+        return bit_offset_error();
+    };
+    let on = match args[2].as_slice() {
+        b"0" => false,
+        b"1" => true,
+        _ => return Value::error("ERR bit is not an integer or out of range"),
+    };
+
+    match get_or_create_bytes(db, &args[0]) {
+        Ok(mut bytes) => {
+            let old = set_bit(&mut bytes, offset, on);
+            notify::emit(Class::String, "setbit", &args[0]);
+            Value::Integer(old as i64)
+        }
+        Err(e) => e,
+    }
+}
+
+fn getbit(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(offset) = parse_bit_offset(&args[1]) else {
+        return bit_offset_error();
+    };
+
+    match read_bytes(db, &args[0]) {
+        Ok(bytes) => Value::Integer(get_bit(&bytes, offset) as i64),
+        Err(e) => e,
+    }
+}
+
+/// Parses the `start end [BYTE|BIT]` tail of BITCOUNT and BITPOS into an inclusive bit range
+/// over a string of `len` bytes. `Ok(None)` means the range is empty.
+fn bit_range(
+    start: i64,
+    end: i64,
+    unit: Option<&Vec<u8>>,
+    len: usize,
+) -> Result<Option<(u64, u64)>, Value> {
+    let bits = match unit.map(|unit| lower(unit)).as_deref() {
+        None | Some("byte") => false,
+        Some("bit") => true,
+        Some(_) => return Err(syntax_error()),
+    };
+
+    let total = if bits { len as i64 * 8 } else { len as i64 };
+    let resolve = |i: i64| if i < 0 { (i + total).max(0) } else { i };
+    let (start, end) = (resolve(start), resolve(end).min(total - 1));
+
+    if start > end || total == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(if bits {
+        (start as u64, end as u64)
+    } else {
+        (start as u64 * 8, end as u64 * 8 + 7)
+    }))
+}
+
+fn bitcount(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let range = match &args[1..] {
+        [] => None,
+        [start, end, unit @ ..] if unit.len() <= 1 => {
+            let (Some(start), Some(end)) = (parse_int::<i64>(start), parse_int::<i64>(end)) else {
+                return not_an_integer();
+            };
+            Some((start, end, unit.first()))
+        }
+        _ => return syntax_error(),
+    };
+
+    let bytes = match read_bytes(db, &args[0]) {
+        Ok(bytes) => bytes,
+        Err(e) => return e,
+    };
+
+    let (first, last) = match range {
+        None if bytes.is_empty() => return Value::Integer(0),
+        None => (0, bytes.len() as u64 * 8 - 1),
+        Some((start, end, unit)) => match bit_range(start, end, unit, bytes.len()) {
+            Ok(Some(range)) => range,
+            Ok(None) => return Value::Integer(0),
+            Err(e) => return e,
+        },
+    };
+
+    Value::Integer(count_bits(&bytes, first, last) as i64)
+}
+
+/// Counts set bits in the inclusive bit range `first..=last`, a byte at a time where possible.
+fn count_bits(bytes: &[u8], first: u64, last: u64) -> u64 {
+    let mut count = 0;
+    let mut bit = first;
+
+    while bit <= last {
+        if bit.is_multiple_of(8) && bit + 7 <= last {
+            count += bytes[(bit / 8) as usize].count_ones() as u64;
+            bit += 8;
+        } else {
+            count += get_bit(bytes, bit) as u64;
+            bit += 1;
+        }
+    }
+
+    count
+}
+
+/// The first bit equal to `target` in the inclusive range `first..=last`, skipping whole
+/// bytes that can't contain one.
+fn find_bit(bytes: &[u8], first: u64, last: u64, target: bool) -> Option<u64> {
+    let skip = if target { 0x00 } else { 0xff };
+    let mut bit = first;
+
+    while bit <= last {
+        if bit.is_multiple_of(8) && bit + 7 <= last && bytes[(bit / 8) as usize] == skip {
+            bit += 8;
+            continue;
+        }
+        if get_bit(bytes, bit) == target {
+            return Some(bit);
+        }
+        bit += 1;
+    }
+
+    None
+}
+
+fn bitpos(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let target = match args[1].as_slice() {
+        b"0" => false,
+        b"1" => true,
+        _ => return Value::error("ERR The bit argument must be 1 or 0."),
+    };
+
+    let mut bounds = [0, -1];
+    for (bound, arg) in bounds.iter_mut().zip(&args[2..]) {
+        let Some(n) = parse_int::<i64>(arg) else {
+            return not_an_integer();
+        };
+        *bound = n;
+    }
+    let end_given = args.len() > 3;
+
+    let bytes = match read_bytes(db, &args[0]) {
+        Ok(bytes) => bytes,
+        Err(e) => return e,
+    };
+
+    if bytes.is_empty() {
+        return Value::Integer(if target { -1 } else { 0 });
+    }
+
+    let (first, last) = match bit_range(bounds[0], bounds[1], args.get(4), bytes.len()) {
+        Ok(Some(range)) => range,
+        Ok(None) => return Value::Integer(-1),
+        Err(e) => return e,
+    };
+
+    if let Some(pos) = find_bit(&bytes, first, last, target) {
+        return Value::Integer(pos as i64);
+    }
+
+    // Looking for a clear bit past an open-ended range: the string is conceptually padded
+    // with zeroes, so the first one is just beyond the end
+    if !target && !end_given {
+        return Value::Integer(last as i64 + 1);
+    }
+
+    Value::Integer(-1)
+}
+
+fn bitop(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let op = lower(&args[0]);
+    let (dest, keys) = (&args[1], &args[2..]);
+    match op.as_str() {
+        "and" | "or" | "xor" => {}
+        "not" if keys.len() == 1 => {}
+        "not" => return Value::error("ERR BITOP NOT must be called with a single source key."),
+        _ => return syntax_error(),
+    }
+
+    let mut sources = Vec::with_capacity(keys.len());
+    for key in keys {
+        match read_bytes(db, key) {
+            Ok(bytes) => sources.push(bytes.into_owned()),
+            Err(e) => return e,
+        }
+    }
+
+    // Shorter sources behave as if padded with zero bytes
+    let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+    let byte = |source: &Vec<u8>, i: usize| source.get(i).copied().unwrap_or(0);
+    let result: Vec<u8> = (0..len)
+        .map(|i| {
+            let mut bytes = sources.iter().map(|source| byte(source, i));
+            let first = bytes.next().unwrap_or(0);
+            match op.as_str() {
+                "and" => bytes.fold(first, |acc, b| acc & b),
+                "or" => bytes.fold(first, |acc, b| acc | b),
+                "xor" => bytes.fold(first, |acc, b| acc ^ b),
+                _ => !first,
+            }
+        })
+        .collect();
+
+    if result.is_empty() {
+        if db.remove(dest).is_some() {
+            notify::emit(Class::Generic, "del", dest);
+        }
+    } else {
+        db.insert(
+            dest.clone(),
+            DBData::new(DBVal::String(result.into()), None),
+        );
+        notify::emit(Class::String, "set", dest);
+    }
+
+    Value::Integer(len as i64)
+}
+
+/// A BITFIELD integer type such as `i5` or `u8`.
+#[derive(Clone, Copy)]
+struct FieldType {
+    signed: bool,
+    bits: u32,
+}
+
+impl FieldType {
+    /// Signed widths go up to 64 bits, unsigned ones only to 63, as in Redis.
+    fn parse(arg: &[u8]) -> Option<Self> {
+        let (signed, bits) = match arg.split_first()? {
+            (b'i' | b'I', bits) => (true, bits),
+            (b'u' | b'U', bits) => (false, bits),
+            _ => return None,
+        };
+        let bits = parse_int::<u32>(bits)?;
+        let max = if signed { 64 } else { 63 };
+
+        (1..=max)
+            .contains(&bits)
+            .then_some(FieldType { signed, bits })
+    }
+
+    fn min(self) -> i128 {
+        if self.signed {
+            -(1 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    fn max(self) -> i128 {
+        if self.signed {
+            (1 << (self.bits - 1)) - 1
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
+
+    /// Reads the field at bit `offset`, sign-extending signed types.
+    fn read(self, bytes: &[u8], offset: u64) -> i64 {
+        let raw = (0..self.bits as u64).fold(0u64, |acc, i| {
+            (acc << 1) | get_bit(bytes, offset + i) as u64
+        });
+
+        if self.signed && self.bits < 64 && raw >> (self.bits - 1) & 1 == 1 {
+            (raw | (u64::MAX << self.bits)) as i64
+        } else {
+            raw as i64
+        }
+    }
+
+    fn write(self, bytes: &mut Vec<u8>, offset: u64, value: i64) {
+        for i in 0..self.bits as u64 {
+            let bit = (value as u64 >> (self.bits as u64 - 1 - i)) & 1 == 1;
+            set_bit(bytes, offset + i, bit);
+        }
+    }
+
+    /// Applies `overflow` to a value that may not fit this type. `None` means FAIL tripped.
+    fn fit(self, value: i128, overflow: Overflow) -> Option<i64> {
+        if (self.min()..=self.max()).contains(&value) {
+            return Some(value as i64);
+        }
+
+        match overflow {
+            Overflow::Fail => None,
+            Overflow::Sat => Some(value.clamp(self.min(), self.max()) as i64),
+            Overflow::Wrap => {
+                let wrapped = value.rem_euclid(1 << self.bits);
+                Some(if wrapped > self.max() {
+                    (wrapped - (1 << self.bits)) as i64
+                } else {
+                    wrapped as i64
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+enum FieldOp {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+/// Parses a field offset: a bit offset, or `#n` meaning the n-th field of this type's width.
+fn parse_field_offset(arg: &[u8], ty: FieldType) -> Option<u64> {
+    let offset = match arg.strip_prefix(b"#") {
+        Some(index) => parse_int::<u64>(index)?.checked_mul(ty.bits as u64)?,
+        None => parse_int::<u64>(arg)?,
+    };
+
+    (offset + ty.bits as u64 - 1 <= MAX_BIT_OFFSET).then_some(offset)
+}
+
+fn bitfield_generic(db: &mut Keyspace, args: &[Vec<u8>], cmd: &str, read_only: bool) -> Value {
+    if args.is_empty() {
+        return wrong_args(cmd);
+    }
+
+    // Validate everything before running anything, as a bad op should leave the key untouched
+    let mut ops = Vec::new();
+    let mut overflow = Overflow::Wrap;
+    let mut i = 1;
+    while i < args.len() {
+        let op = lower(&args[i]);
+        let arity = match op.as_str() {
+            "overflow" => 1,
+            "get" => 2,
+            "set" | "incrby" => 3,
+            _ => return syntax_error(),
+        };
+        let operands = match args.get(i + 1..=i + arity) {
+            Some(operands) => operands,
+            None => return syntax_error(),
+        };
+        i += operands.len() + 1;
+
+        if op == "overflow" {
+            overflow = match lower(&operands[0]).as_str() {
+                "wrap" => Overflow::Wrap,
+                "sat" => Overflow::Sat,
+                "fail" => Overflow::Fail,
+                _ => return Value::error("ERR Invalid OVERFLOW type specified"),
+            };
+            continue;
+        }
+        if read_only && op != "get" {
+            return Value::error("ERR BITFIELD_RO only supports the GET subcommand");
+        }
+
+        let Some(ty) = FieldType::parse(&operands[0]) else {
+            return Value::error(
+                "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.",
+            );
+        };
+        let Some(offset) = parse_field_offset(&operands[1], ty) else {
+            return bit_offset_error();
+        };
+        let field_op = match (
+            op.as_str(),
+            operands.get(2).map(|arg| parse_int::<i64>(arg)),
+        ) {
+            ("get", _) => FieldOp::Get,
+            (_, Some(None)) | (_, None) => return not_an_integer(),
+            ("set", Some(Some(value))) => FieldOp::Set(value),
+            (_, Some(Some(increment))) => FieldOp::IncrBy(increment),
+        };
+
+        ops.push((field_op, ty, offset, overflow));
+    }
+
+    // Only writes create the key
+    let writes = ops.iter().any(|(op, ..)| !matches!(op, FieldOp::Get));
+    if !writes {
+        let bytes = match read_bytes(db, &args[0]) {
+            Ok(bytes) => bytes,
+            Err(e) => return e,
+        };
+        return Value::Array(
+            ops.iter()
+                .map(|(_, ty, offset, _)| Value::Integer(ty.read(&bytes, *offset)))
+                .collect(),
+        );
+    }
+
+    let mut bytes = match get_or_create_bytes(db, &args[0]) {
+        Ok(bytes) => bytes,
+        Err(e) => return e,
+    };
+
+    let reply = ops
+        .into_iter()
+        .map(|(op, ty, offset, overflow)| {
+            let old = ty.read(&bytes, offset);
+            let new = match op {
+                FieldOp::Get => return Value::Integer(old),
+                // Unsigned SETs take the value's two's complement bits, like Redis
+                FieldOp::Set(value) if !ty.signed => ty.fit(value as u64 as i128, overflow),
+                FieldOp::Set(value) => ty.fit(value as i128, overflow),
+                FieldOp::IncrBy(increment) => ty.fit(old as i128 + increment as i128, overflow),
+            };
+
+            let Some(new) = new else {
+                return Value::Null;
+            };
+            ty.write(&mut bytes, offset, new);
+
+            match op {
+                FieldOp::Set(_) => Value::Integer(old),
+                _ => Value::Integer(new),
+            }
+        })
+        .collect();
+    notify::emit(Class::String, "setbit", &args[0]);
+
+    Value::Array(reply)
+}
+
+fn bitfield(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    bitfield_generic(db, args, "bitfield", false)
+}
+
+fn bitfield_ro(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    bitfield_generic(db, args, "bitfield_ro", true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn integers(reply: Value) -> Vec<i64> {
+        let Value::Array(values) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        values
+            .into_iter()
+            .map(|value| match value {
+                Value::Integer(n) => n,
+                value => panic!("expected an integer, got {value:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sets_counts_and_finds_bits() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            setbit(db, &args(&["k", "7", "1"])),
+            Value::Integer(0)
+        ));
+        assert!(matches!(
+            setbit(db, &args(&["k", "7", "1"])),
+            Value::Integer(1)
+        ));
+        setbit(db, &args(&["k", "15", "1"]));
+        assert!(matches!(getbit(db, &args(&["k", "7"])), Value::Integer(1)));
+        // Past the end reads as unset
+        assert!(matches!(
+            getbit(db, &args(&["k", "100"])),
+            Value::Integer(0)
+        ));
+
+        assert!(matches!(bitcount(db, &args(&["k"])), Value::Integer(2)));
+        assert!(matches!(
+            bitcount(db, &args(&["k", "1", "1"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(bitpos(db, &args(&["k", "1"])), Value::Integer(7)));
+        assert!(matches!(bitpos(db, &args(&["k", "0"])), Value::Integer(0)));
+        assert!(
+            setbit(db, &args(&["k", "x", "1"]))
+                .error_message()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn combines_strings_bit_by_bit() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        // 0b11110000 and 0b00111100
+        for bit in 0..4 {
+            setbit(db, &args(&["a", &bit.to_string(), "1"]));
+            setbit(db, &args(&["b", &(bit + 2).to_string(), "1"]));
+        }
+
+        assert!(matches!(
+            bitop(db, &args(&["and", "dest", "a", "b"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(bitcount(db, &args(&["dest"])), Value::Integer(2)));
+        assert!(matches!(
+            bitpos(db, &args(&["dest", "1"])),
+            Value::Integer(2)
+        ));
+        bitop(db, &args(&["xor", "dest", "a", "b"]));
+        assert!(matches!(bitcount(db, &args(&["dest"])), Value::Integer(4)));
+        bitop(db, &args(&["not", "dest", "a"]));
+        assert!(matches!(
+            bitpos(db, &args(&["dest", "1"])),
+            Value::Integer(4)
+        ));
+        assert!(
+            bitop(db, &args(&["not", "dest", "a", "b"]))
+                .error_message()
+                .is_some()
+        );
+
+        // Nothing to combine leaves nothing behind
+        assert!(matches!(
+            bitop(db, &args(&["or", "dest", "missing"])),
+            Value::Integer(0)
+        ));
+        assert!(db.get(b"dest".as_slice()).is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_writes_and_increments_fields() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert_eq!(
+            integers(bitfield(db, &args(&["a", "set", "u8", "0", "240"]))),
+            [0]
+        );
+        bitfield(db, &args(&["b", "set", "u8", "0", "60"]));
+
+        let byte =
+            |db: &mut Keyspace, key| integers(bitfield_ro(db, &args(&[key, "get", "u8", "0"])));
+        assert_eq!(byte(db, "b"), [60]);
+        assert!(
+            bitfield_ro(db, &args(&["a", "set", "u8", "0", "1"]))
+                .error_message()
+                .is_some()
+        );
+
+        // Wraps by default, and fails the one operation with OVERFLOW FAIL
+        assert_eq!(
+            integers(bitfield(db, &args(&["a", "incrby", "u8", "0", "20"]))),
+            [4]
+        );
+        let Value::Array(replies) = bitfield(
+            db,
+            &args(&["a", "overflow", "fail", "incrby", "u8", "0", "255"]),
+        ) else {
+            panic!("BITFIELD replies with an array");
+        };
+        assert!(matches!(replies[0], Value::Null));
+    }
+}
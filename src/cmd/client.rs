@@ -0,0 +1,442 @@
+use crate::acl;
+use crate::client::{self, Client, KillFilter, ReplyMode, Transaction};
+use crate::cmd::registry::{Context, Spec};
+use crate::cmd::{info, lower, not_an_integer, parse_int, syntax_error, wrong_args};
+use crate::resp::Value;
+use crate::server::{self, Connection, Step, Steps};
+use crate::tracking::{self, Tracking};
+use bytes::Bytes;
+use std::time::Duration;
+
+/// The connection commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::server("ping", -1, [0, 0, 0], ping),
+    Spec::server("echo", 2, [0, 0, 0], |_, args| echo(args)),
+    Spec::server("hello", -1, [0, 0, 0], |ctx, args| hello(ctx.client, args)),
+    Spec::server("client", -2, [0, 0, 0], |ctx, args| {
+        client(ctx.client, args)
+    }),
+    Spec::server("monitor", 1, [0, 0, 0], monitor),
+    Spec::connection("quit", -1, [0, 0, 0], quit),
+    Spec::connection("reset", 1, [0, 0, 0], |conn, _| {
+        conn.client.reset();
+        Step::Reply(Value::SimpleString("RESET".to_string())).now()
+    }),
+    Spec::connection("multi", 1, [0, 0, 0], |conn, _| multi(conn.client).now()),
+    Spec::connection("exec", 1, [0, 0, 0], exec),
+    Spec::connection("discard", 1, [0, 0, 0], |conn, _| {
+        discard(conn.client).now()
+    }),
+    Spec::connection("watch", -2, [1, -1, 1], watch),
+    // EXEC has already dropped the watches by the time a queued UNWATCH runs
+    Spec::server("unwatch", 1, [0, 0, 0], |_, _| {
+        Value::SimpleString("OK".to_string())
+    })
+    .on_connection(|conn, _| {
+        conn.client.unwatch();
+        Step::Reply(Value::SimpleString("OK".to_string())).now()
+    }),
+];
+
+/// QUIT: the client's told OK, and the connection closed.
+fn quit<'a>(conn: &'a mut Connection<'_>, _: &'a [Vec<u8>]) -> Steps<'a> {
+    Box::pin(async move {
+        let _ = conn
+            .handler
+            .write(Value::SimpleString("OK".to_string()))
+            .await;
+        Step::Close
+    })
+}
+
+/// MULTI: the client's commands are queued from here on, until EXEC or DISCARD.
+fn multi(client: &mut Client) -> Step {
+    if client.transaction.is_some() {
+        return Step::Reply(Value::error("ERR MULTI calls can not be nested"));
+    }
+
+    client.transaction = Some(Transaction::default());
+    Step::Reply(Value::SimpleString("OK".to_string()))
+}
+
+/// EXEC: runs the queued commands, unless one was refused while they were being queued.
+fn exec<'a>(conn: &'a mut Connection<'_>, _: &'a [Vec<u8>]) -> Steps<'a> {
+    Box::pin(async move {
+        let reply = match conn.client.transaction.take() {
+            None => Value::error("ERR EXEC without MULTI"),
+            Some(transaction) if transaction.is_aborted() => {
+                conn.client.unwatch();
+                Value::error("EXECABORT Transaction discarded because of previous errors.")
+            }
+            Some(transaction) => {
+                server::exec(conn.client, conn.pubsub, conn.db, conn.blocked, transaction).await
+            }
+        };
+        Step::Reply(reply)
+    })
+}
+
+/// DISCARD: drops the queued commands, and the keys the client was watching.
+fn discard(client: &mut Client) -> Step {
+    if client.transaction.take().is_none() {
+        return Step::Reply(Value::error("ERR DISCARD without MULTI"));
+    }
+
+    client.unwatch();
+    Step::Reply(Value::SimpleString("OK".to_string()))
+}
+
+/// WATCH key [key ...]: EXEC runs nothing if any of the keys changes before it.
+fn watch<'a>(conn: &'a mut Connection<'_>, args: &'a [Vec<u8>]) -> Steps<'a> {
+    Box::pin(async move {
+        if conn.client.transaction.is_some() {
+            return Step::Reply(Value::error("ERR WATCH inside MULTI is not allowed"));
+        }
+
+        conn.client
+            .watch(&mut conn.db.lock_for("watch", args).await, args);
+        Step::Reply(Value::SimpleString("OK".to_string()))
+    })
+}
+
+/// PING [message]. A subscribed connection gets a `pong` frame carrying the message instead.
+fn ping(ctx: &mut Context<'_>, args: &[Vec<u8>]) -> Value {
+    if ctx.client.subscriber.is_subscribed() {
+        return Value::Array(vec![
+            Value::BulkString(Bytes::from_static(b"pong")),
+            Value::BulkString(args.first().cloned().unwrap_or_default().into()),
+        ]);
+    }
+
+    Value::SimpleString("PONG".to_string())
+}
+
+/// ECHO message
+fn echo(args: &[Vec<u8>]) -> Value {
+    Value::BulkString(args.first().map_or(
+        Bytes::from_static(b"You did not provide an argument to ECHO back"),
+        |message| Bytes::copy_from_slice(message),
+    ))
+}
+
+/// MONITOR: streams every command the server runs from now on to this connection.
+fn monitor(ctx: &mut Context<'_>, _: &[Vec<u8>]) -> Value {
+    ctx.client.monitor();
+
+    Value::SimpleString("OK".to_string())
+}
+
+/// CLIENT ID | SETNAME name | GETNAME | LIST [TYPE type] [ID id ...] | INFO
+/// | KILL addr | KILL [ID id] [ADDR addr] [LADDR addr] [SKIPME yes|no]
+/// | PAUSE timeout [WRITE|ALL] | UNPAUSE | REPLY ON|OFF|SKIP | NO-EVICT ON|OFF | NO-TOUCH ON|OFF
+/// | TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST] [NOLOOP] | GETREDIR
+pub fn client(client: &mut Client, args: &[Vec<u8>]) -> Value {
+    if args.is_empty() {
+        return wrong_args("client");
+    }
+
+    let subcommand = lower(&args[0]);
+    match (subcommand.as_str(), &args[1..]) {
+        ("id", []) => Value::Integer(client.id as i64),
+        ("setname", [name]) => match client_name(name) {
+            Ok(name) => {
+                client.set_name(name);
+                Value::SimpleString("OK".to_string())
+            }
+            Err(e) => e,
+        },
+        ("getname", []) => client
+            .name()
+            .map_or(Value::Null, |name| Value::BulkString(name.into())),
+        ("list", options) => list(options),
+        ("info", []) => Value::BulkString(format!("{}\n", client.info()).into()),
+        // The old form kills by address alone, and says so if there was no such client
+        ("kill", [addr]) => {
+            let filter = KillFilter {
+                addr: Some(String::from_utf8_lossy(addr).into_owned()),
+                ..KillFilter::default()
+            };
+            if client::kill(&filter) == 0 {
+                Value::error("ERR No such client")
+            } else {
+                Value::SimpleString("OK".to_string())
+            }
+        }
+        ("kill", filters) if !filters.is_empty() => match kill_filter(client.id, filters) {
+            Ok(filter) => Value::Integer(client::kill(&filter) as i64),
+            Err(e) => e,
+        },
+        ("pause", [timeout, mode @ ..]) if mode.len() <= 1 => {
+            let Some(timeout) = parse_int::<i64>(timeout) else {
+                return Value::error("ERR timeout is not an integer or out of range");
+            };
+            if timeout < 0 {
+                return Value::error("ERR timeout is negative");
+            }
+            let all = match mode.first().map(|mode| lower(mode)).as_deref() {
+                None | Some("all") => true,
+                Some("write") => false,
+                Some(_) => return syntax_error(),
+            };
+
+            client::pause(Duration::from_millis(timeout as u64), all);
+            Value::SimpleString("OK".to_string())
+        }
+        ("unpause", []) => {
+            client::unpause();
+            Value::SimpleString("OK".to_string())
+        }
+        // The connection mutes itself before replying unless the mode is back to ON
+        ("reply", [mode]) => {
+            client.reply_mode = match lower(mode).as_str() {
+                "on" => ReplyMode::On,
+                "off" => ReplyMode::Off,
+                "skip" => ReplyMode::Skip,
+                _ => return syntax_error(),
+            };
+            Value::SimpleString("OK".to_string())
+        }
+        ("no-evict", [mode]) => match on_off(mode) {
+            Some(on) => {
+                client.set_no_evict(on);
+                Value::SimpleString("OK".to_string())
+            }
+            None => syntax_error(),
+        },
+        ("no-touch", [mode]) => match on_off(mode) {
+            Some(on) => {
+                client.set_no_touch(on);
+                Value::SimpleString("OK".to_string())
+            }
+            None => syntax_error(),
+        },
+        ("tracking", [mode, options @ ..]) => match lower(mode).as_str() {
+            "on" => match tracking_options(options) {
+                Ok(options) => {
+                    tracking::enable(client.id, options);
+                    Value::SimpleString("OK".to_string())
+                }
+                Err(e) => e,
+            },
+            "off" if options.is_empty() => {
+                tracking::disable(client.id);
+                Value::SimpleString("OK".to_string())
+            }
+            _ => syntax_error(),
+        },
+        ("getredir", []) => Value::Integer(tracking::redirect(client.id)),
+        (
+            "id" | "setname" | "getname" | "info" | "kill" | "pause" | "unpause" | "reply"
+            | "no-evict" | "no-touch" | "tracking" | "getredir",
+            _,
+        ) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try CLIENT HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try CLIENT HELP."
+        )),
+    }
+}
+
+fn on_off(mode: &[u8]) -> Option<bool> {
+    match lower(mode).as_str() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// A name for CLIENT SETNAME or HELLO SETNAME, where an empty one clears the name.
+fn client_name(name: &[u8]) -> Result<Option<String>, Value> {
+    // Names show up space-separated in CLIENT LIST, so they can't contain spaces
+    if !name.iter().all(|b| (b'!'..=b'~').contains(b)) {
+        return Err(Value::error(
+            "ERR Client names cannot contain spaces, newlines or special characters.",
+        ));
+    }
+
+    Ok((!name.is_empty()).then(|| String::from_utf8_lossy(name).into_owned()))
+}
+
+/// Parses the options after CLIENT TRACKING ON.
+fn tracking_options(options: &[Vec<u8>]) -> Result<Tracking, Value> {
+    let mut tracking = Tracking::default();
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match lower(option).as_str() {
+            "redirect" => {
+                let id = options.next().ok_or_else(syntax_error)?;
+                let id = parse_int::<u64>(id).ok_or_else(not_an_integer)?;
+                if !client::exists(id) {
+                    return Err(Value::error(
+                        "ERR The client ID you want redirect to does not exist",
+                    ));
+                }
+                tracking.redirect = Some(id);
+            }
+            "prefix" => {
+                let prefix = options.next().ok_or_else(syntax_error)?;
+                tracking.prefixes.push(prefix.clone());
+            }
+            "bcast" => tracking.bcast = true,
+            "noloop" => tracking.noloop = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    if !tracking.prefixes.is_empty() && !tracking.bcast {
+        return Err(Value::error(
+            "ERR PREFIX option requires BCAST mode to be enabled",
+        ));
+    }
+
+    Ok(tracking)
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]: switches protocol, and
+/// optionally logs in and names the connection, all or nothing.
+pub fn hello(client: &mut Client, args: &[Vec<u8>]) -> Value {
+    let protocol = match args.first() {
+        None => client.protocol,
+        Some(version) => match parse_int::<i64>(version) {
+            Some(version @ (2 | 3)) => version as u8,
+            Some(_) => return Value::error("NOPROTO unsupported protocol version"),
+            None => {
+                return Value::error("ERR Protocol version is not an integer or out of range");
+            }
+        },
+    };
+
+    let mut user = client.user.clone();
+    let mut name = None;
+    let mut options = args.iter().skip(1);
+    while let Some(option) = options.next() {
+        match (lower(option).as_str(), options.len()) {
+            ("auth", 2..) => {
+                let username = String::from_utf8_lossy(options.next().unwrap()).into_owned();
+                let password = options.next().unwrap();
+                if !acl::authenticate(&username, password) {
+                    return Value::error(
+                        "WRONGPASS invalid username-password pair or user is disabled.",
+                    );
+                }
+                user = Some(username);
+            }
+            ("setname", 1..) => match client_name(options.next().unwrap()) {
+                Ok(new_name) => name = Some(new_name),
+                Err(e) => return e,
+            },
+            _ => {
+                return Value::error(format!(
+                    "ERR Syntax error in HELLO option '{}'",
+                    String::from_utf8_lossy(option)
+                ));
+            }
+        }
+    }
+
+    if user.is_none() {
+        return Value::error(
+            "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time",
+        );
+    }
+    client.user = user;
+    client.protocol = protocol;
+    if let Some(name) = name {
+        client.set_name(name);
+    }
+
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    Value::Map(vec![
+        (bulk("server"), bulk("redis")),
+        (bulk("version"), bulk(env!("CARGO_PKG_VERSION"))),
+        (bulk("proto"), Value::Integer(protocol as i64)),
+        (bulk("id"), Value::Integer(client.id as i64)),
+        (bulk("mode"), bulk(info::mode())),
+        (bulk("role"), bulk("master")),
+        (bulk("modules"), Value::Array(Vec::new())),
+    ])
+}
+
+/// CLIENT LIST [TYPE normal|pubsub|master|replica] [ID id [id ...]]
+fn list(options: &[Vec<u8>]) -> Value {
+    let mut pubsub = None;
+    let mut ids: Option<Vec<u64>> = None;
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match lower(option).as_str() {
+            "type" => {
+                let Some(kind) = options.next() else {
+                    return syntax_error();
+                };
+                pubsub = match lower(kind).as_str() {
+                    "normal" => Some(false),
+                    "pubsub" => Some(true),
+                    // There's no replication, so nothing is ever a master or replica
+                    "master" | "replica" | "slave" => return Value::BulkString(Bytes::new()),
+                    kind => return Value::error(format!("ERR Unknown client type '{kind}'")),
+                };
+            }
+            "id" => {
+                let mut wanted = Vec::new();
+                for id in options.by_ref() {
+                    match parse_int::<u64>(id) {
+                        Some(id) if id > 0 => wanted.push(id),
+                        _ => return Value::error("ERR Invalid client ID"),
+                    }
+                }
+                if wanted.is_empty() {
+                    return syntax_error();
+                }
+                ids = Some(wanted);
+            }
+            _ => return syntax_error(),
+        }
+    }
+
+    let lines = client::list(|id, subscribed| {
+        pubsub.is_none_or(|pubsub| pubsub == subscribed)
+            && ids.as_ref().is_none_or(|ids| ids.contains(&id))
+    });
+
+    Value::BulkString(
+        lines
+            .into_iter()
+            .map(|line| line + "\n")
+            .collect::<String>()
+            .into(),
+    )
+}
+
+/// Parses CLIENT KILL's `<filter> <value>` pairs. Without `SKIPME no`, `me` is spared.
+fn kill_filter(me: u64, filters: &[Vec<u8>]) -> Result<KillFilter, Value> {
+    if !filters.len().is_multiple_of(2) {
+        return Err(syntax_error());
+    }
+
+    let mut filter = KillFilter {
+        skip: Some(me),
+        ..KillFilter::default()
+    };
+    for pair in filters.chunks(2) {
+        let value = &pair[1];
+        match lower(&pair[0]).as_str() {
+            "id" => match parse_int::<u64>(value) {
+                Some(id) if id > 0 => filter.id = Some(id),
+                _ => return Err(Value::error("ERR client-id should be greater than 0")),
+            },
+            "addr" => filter.addr = Some(String::from_utf8_lossy(value).into_owned()),
+            "laddr" => filter.laddr = Some(String::from_utf8_lossy(value).into_owned()),
+            "skipme" => match lower(value).as_str() {
+                "yes" => filter.skip = Some(me),
+                "no" => filter.skip = None,
+                _ => return Err(syntax_error()),
+            },
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    Ok(filter)
+}
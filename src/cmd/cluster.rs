@@ -0,0 +1,187 @@
+use crate::client::Client;
+use crate::cluster::{self, SlotState};
+use crate::cmd::{lower, parse_int, registry::Spec};
+use crate::db::Keyspace;
+use crate::resp::Value;
+use bytes::Bytes;
+use std::net::IpAddr;
+
+/// The cluster commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::server("cluster", -2, [0, 0, 0], |ctx, args| {
+        cluster(&ctx.dbs[0], args)
+    }),
+    Spec::server("asking", 1, [0, 0, 0], |ctx, _| asking(ctx.client)),
+];
+
+fn disabled() -> Value {
+    Value::error("ERR This instance has cluster support disabled")
+}
+
+/// CLUSTER ADDSLOTS slot [slot ...] | ADDSLOTSRANGE start end [start end ...] | DELSLOTS slot
+/// [slot ...] | DELSLOTSRANGE start end [start end ...] | KEYSLOT key | MEET ip port
+/// [cluster-bus-port] | INFO | MYID | NODES | SLOTS | SHARDS | SETSLOT slot IMPORTING node-id |
+/// MIGRATING node-id | NODE node-id | STABLE | GETKEYSINSLOT slot count | COUNTKEYSINSLOT slot |
+/// REPLICATE node-id.
+/// The keys are the ones in `db`, the only database a cluster uses.
+pub fn cluster(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    if !cluster::enabled() {
+        return disabled();
+    }
+    let subcommand = lower(&args[0]);
+
+    let changed = match (subcommand.as_str(), &args[1..]) {
+        ("keyslot", [key]) => return Value::Integer(cluster::key_slot(key) as i64),
+        ("info", []) => return Value::BulkString(cluster::info().into()),
+        ("myid", []) => return Value::BulkString(cluster::myid().into()),
+        ("nodes", []) => return Value::BulkString(cluster::nodes().into()),
+        ("slots", []) => return cluster::slots(),
+        ("shards", []) => return cluster::shards(),
+        ("countkeysinslot", [slot_arg]) => {
+            return match slot(slot_arg) {
+                Ok(slot) => Value::Integer(keys_in_slot(db, slot).count() as i64),
+                Err(e) => e,
+            };
+        }
+        ("getkeysinslot", [slot_arg, count]) => {
+            let Some(count) = parse_int::<usize>(count) else {
+                return Value::error("ERR Invalid number of keys");
+            };
+            return match slot(slot_arg) {
+                Ok(slot) => Value::Array(
+                    keys_in_slot(db, slot)
+                        .take(count)
+                        .map(|key| Value::BulkString(key.clone()))
+                        .collect(),
+                ),
+                Err(e) => e,
+            };
+        }
+        ("setslot", [slot_arg, state, id @ ..]) if id.len() <= 1 => {
+            set_slot(db, slot_arg, state, id.first())
+        }
+        ("replicate", [id]) => cluster::replicate(&String::from_utf8_lossy(id), db.is_empty()),
+        ("meet", [ip, port, cport @ ..]) if cport.len() <= 1 => meet(ip, port, cport.first()),
+        ("addslots", slots) if !slots.is_empty() => {
+            slot_list(slots).and_then(|slots| cluster::add_slots(&slots))
+        }
+        ("addslotsrange", ranges) if !ranges.is_empty() && ranges.len().is_multiple_of(2) => {
+            slot_ranges(ranges).and_then(|slots| cluster::add_slots(&slots))
+        }
+        ("delslots", slots) if !slots.is_empty() => {
+            slot_list(slots).and_then(|slots| cluster::del_slots(&slots))
+        }
+        ("delslotsrange", ranges) if !ranges.is_empty() && ranges.len().is_multiple_of(2) => {
+            slot_ranges(ranges).and_then(|slots| cluster::del_slots(&slots))
+        }
+        (
+            "keyslot" | "info" | "myid" | "nodes" | "slots" | "shards" | "countkeysinslot"
+            | "getkeysinslot" | "setslot" | "replicate" | "meet" | "addslots" | "addslotsrange"
+            | "delslots" | "delslotsrange",
+            _,
+        ) => {
+            return Value::error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try \
+                 CLUSTER HELP."
+            ));
+        }
+        _ => {
+            return Value::error(format!(
+                "ERR unknown subcommand '{subcommand}'. Try CLUSTER HELP."
+            ));
+        }
+    };
+
+    match changed {
+        Ok(()) => Value::SimpleString("OK".to_string()),
+        Err(e) => e,
+    }
+}
+
+/// Starts a handshake with the node at `ip`, whose cluster bus is on `cport` if given or 10000
+/// above `port` if not.
+fn meet(ip: &[u8], port: &[u8], cport: Option<&Vec<u8>>) -> Result<(), Value> {
+    let ip = String::from_utf8_lossy(ip);
+    let invalid = || {
+        Value::error(format!(
+            "ERR Invalid node address specified: {ip}:{}",
+            String::from_utf8_lossy(port)
+        ))
+    };
+    let host = ip.parse::<IpAddr>().map_err(|_| invalid())?;
+    let port = parse_int::<u16>(port).ok_or_else(invalid)?;
+    let cport = match cport {
+        Some(cport) => parse_int::<u16>(cport).ok_or_else(invalid)?,
+        None => port.checked_add(10000).ok_or_else(invalid)?,
+    };
+
+    cluster::meet(host.to_canonical().to_string(), port, cport);
+    Ok(())
+}
+
+/// The unexpired keys in `db` that hash to `slot`.
+fn keys_in_slot(db: &Keyspace, slot: u16) -> impl Iterator<Item = &Bytes> {
+    db.iter()
+        .filter(move |(key, val)| !val.is_expired() && cluster::key_slot(key) == slot)
+        .map(|(key, _)| key)
+}
+
+fn set_slot(
+    db: &Keyspace,
+    slot_arg: &[u8],
+    state: &[u8],
+    id: Option<&Vec<u8>>,
+) -> Result<(), Value> {
+    let slot = slot(slot_arg)?;
+    let id = id.map(|id| String::from_utf8_lossy(id).into_owned());
+    let state = match (lower(state).as_str(), id) {
+        ("migrating", Some(id)) => SlotState::Migrating(id),
+        ("importing", Some(id)) => SlotState::Importing(id),
+        ("node", Some(id)) => SlotState::Node(id),
+        ("stable", None) => SlotState::Stable,
+        _ => {
+            return Err(Value::error(
+                "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+            ));
+        }
+    };
+
+    cluster::set_slot(slot, state, keys_in_slot(db, slot).next().is_some())
+}
+
+/// ASKING: lets the next command run here if its slot is being moved to this node, after an
+/// ASK redirect sent the client here.
+pub fn asking(client: &mut Client) -> Value {
+    if !cluster::enabled() {
+        return disabled();
+    }
+    client.asking = true;
+
+    Value::SimpleString("OK".to_string())
+}
+
+fn slot(arg: &[u8]) -> Result<u16, Value> {
+    parse_int::<u16>(arg)
+        .filter(|&slot| slot < cluster::SLOTS)
+        .ok_or_else(|| Value::error("ERR Invalid or out of range slot"))
+}
+
+fn slot_list(args: &[Vec<u8>]) -> Result<Vec<u16>, Value> {
+    args.iter().map(|arg| slot(arg)).collect()
+}
+
+/// The slots in each inclusive `start end` pair.
+fn slot_ranges(args: &[Vec<u8>]) -> Result<Vec<u16>, Value> {
+    let mut slots = Vec::new();
+    for pair in args.chunks(2) {
+        let (start, end) = (slot(&pair[0])?, slot(&pair[1])?);
+        if start > end {
+            return Err(Value::error(format!(
+                "ERR start slot number {start} is greater than end slot number {end}"
+            )));
+        }
+        slots.extend(start..=end);
+    }
+
+    Ok(slots)
+}
@@ -0,0 +1,128 @@
+use crate::cmd::registry::{self, Spec};
+use crate::cmd::{self, lower};
+use crate::resp::Value;
+use bytes::Bytes;
+
+/// COMMAND itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("command", -1, [0, 0, 0], |_, args| {
+    command(args)
+})];
+
+/// Data-type categories and the DOCS group each stands for. A command in none of them is in
+/// the `server` group.
+const GROUPS: &[(&str, &str)] = &[
+    ("string", "string"),
+    ("bitmap", "bitmap"),
+    ("list", "list"),
+    ("hash", "hash"),
+    ("set", "set"),
+    ("sortedset", "sorted-set"),
+    ("stream", "stream"),
+    ("geo", "geo"),
+    ("hyperloglog", "hyperloglog"),
+    ("pubsub", "pubsub"),
+    ("scripting", "scripting"),
+    ("transaction", "transactions"),
+    ("connection", "connection"),
+    ("keyspace", "generic"),
+];
+
+/// COMMAND | COUNT | INFO [name ...] | DOCS [name ...] | GETKEYS command [arg ...]
+pub fn command(args: &[Vec<u8>]) -> Value {
+    let Some(subcommand) = args.first().map(|arg| lower(arg)) else {
+        return Value::Array(cmd::command_names().map(info).collect());
+    };
+
+    match (subcommand.as_str(), &args[1..]) {
+        ("count", []) => Value::Integer(cmd::command_names().count() as i64),
+        ("info", []) => Value::Array(cmd::command_names().map(info).collect()),
+        ("info", names) => Value::Array(
+            names
+                .iter()
+                .map(|name| {
+                    let name = lower(name);
+                    cmd::command_names()
+                        .find(|command| *command == name)
+                        .map_or(Value::NullArray, info)
+                })
+                .collect(),
+        ),
+        ("docs", []) => Value::Map(cmd::command_names().map(docs).collect()),
+        ("docs", names) => Value::Map(
+            names
+                .iter()
+                .filter_map(|name| {
+                    let name = lower(name);
+                    cmd::command_names().find(|command| *command == name)
+                })
+                .map(docs)
+                .collect(),
+        ),
+        ("getkeys", [name, rest @ ..]) => getkeys(&lower(name), rest),
+        ("count" | "getkeys", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try COMMAND HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try COMMAND HELP."
+        )),
+    }
+}
+
+/// A command's COMMAND INFO entry: name, arity, flags, first key, last key, key step, ACL
+/// categories, and the tips, key specs and subcommands there aren't any of here.
+fn info(name: &'static str) -> Value {
+    let command = registry::get(name).expect("name is a command");
+    let [first, last, step] = command.keys();
+    let status = |flag: &str| Value::SimpleString(flag.to_string());
+
+    Value::Array(vec![
+        Value::BulkString(Bytes::copy_from_slice(name.as_bytes())),
+        Value::Integer(command.arity() as i64),
+        Value::Array(command.flags().into_iter().map(status).collect()),
+        Value::Integer(first as i64),
+        Value::Integer(last as i64),
+        Value::Integer(step as i64),
+        Value::Array(
+            cmd::command_categories(name)
+                .into_iter()
+                .map(|category| status(&format!("@{category}")))
+                .collect(),
+        ),
+        Value::Array(Vec::new()),
+        Value::Array(Vec::new()),
+        Value::Array(Vec::new()),
+    ])
+}
+
+/// A command's COMMAND DOCS entry. All that's known about a command here is its group.
+fn docs(name: &'static str) -> (Value, Value) {
+    let categories = cmd::command_categories(name);
+    let group = GROUPS
+        .iter()
+        .find(|(category, _)| categories.contains(category))
+        .map_or("server", |(_, group)| group);
+
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    (bulk(name), Value::Map(vec![(bulk("group"), bulk(group))]))
+}
+
+/// COMMAND GETKEYS: the keys command `name` would touch if called with `args`.
+fn getkeys(name: &str, args: &[Vec<u8>]) -> Value {
+    if !cmd::is_command(name) {
+        return Value::error("ERR Invalid command specified");
+    }
+    if cmd::check_arity(name, args).is_err() {
+        return Value::error("ERR Invalid number of arguments specified for command");
+    }
+
+    let keys = cmd::command_keys(name, args);
+    if keys.is_empty() {
+        return Value::error("ERR The command has no key arguments");
+    }
+
+    Value::Array(
+        keys.into_iter()
+            .map(|key| Value::BulkString(Bytes::copy_from_slice(key)))
+            .collect(),
+    )
+}
@@ -0,0 +1,73 @@
+use crate::aof;
+use crate::cmd::{lower, registry::Spec, wrong_args};
+use crate::config;
+use crate::db::Keyspace;
+use crate::resp::Value;
+use crate::stats;
+use bytes::Bytes;
+use tracing::warn;
+
+/// CONFIG itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("config", -2, [0, 0, 0], |ctx, args| {
+    config(ctx.dbs, args)
+})];
+
+/// CONFIG GET parameter [parameter ...] | SET parameter value [parameter value ...] | REWRITE |
+/// RESETSTAT.
+/// Turning `appendonly` on starts the append only file from `dbs`.
+pub fn config(dbs: &[Keyspace], args: &[Vec<u8>]) -> Value {
+    if args.is_empty() {
+        return wrong_args("config");
+    }
+
+    let subcommand = lower(&args[0]);
+    match (subcommand.as_str(), &args[1..]) {
+        ("get", patterns) if !patterns.is_empty() => Value::Map(
+            config::matching(patterns)
+                .into_iter()
+                .map(|(name, value)| {
+                    (
+                        Value::BulkString(Bytes::copy_from_slice(name.as_bytes())),
+                        Value::BulkString(value.into()),
+                    )
+                })
+                .collect(),
+        ),
+        ("set", pairs) if !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+            let changes: Vec<(String, String)> = pairs
+                .chunks(2)
+                .map(|pair| {
+                    (
+                        lower(&pair[0]),
+                        String::from_utf8_lossy(&pair[1]).into_owned(),
+                    )
+                })
+                .collect();
+
+            if let Err(e) = config::set(&changes) {
+                return Value::error(e);
+            }
+            if config::get().appendonly
+                && !aof::is_on()
+                && let Err(e) = aof::start(dbs)
+            {
+                warn!("Can't start the append only file: {e}");
+            }
+            Value::SimpleString("OK".to_string())
+        }
+        ("rewrite", []) => match config::rewrite() {
+            Ok(()) => Value::SimpleString("OK".to_string()),
+            Err(e) => Value::error(e),
+        },
+        ("resetstat", []) => {
+            stats::reset();
+            Value::SimpleString("OK".to_string())
+        }
+        ("get" | "set" | "rewrite" | "resetstat", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try CONFIG HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try CONFIG HELP."
+        )),
+    }
+}
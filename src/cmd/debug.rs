@@ -0,0 +1,121 @@
+use crate::cmd::memory::value_bytes;
+use crate::cmd::{lower, peek, registry::Spec};
+use crate::db::{self, Keyspace};
+use crate::dump::dump_value;
+use crate::glob::glob_match;
+use crate::rand;
+use crate::resp::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// DEBUG itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("debug", -2, [0, 0, 0], |ctx, args| {
+    debug(ctx.dbs, ctx.client.db, args)
+})];
+
+/// How many random pattern and string pairs DEBUG STRINGMATCH-LEN tries.
+const FUZZ_ROUNDS: usize = 100_000;
+
+/// DEBUG SLEEP seconds | OBJECT key | SET-ACTIVE-EXPIRE 0|1 | JMAP | STRINGMATCH-LEN
+pub fn debug(dbs: &mut [Keyspace], selected: usize, args: &[Vec<u8>]) -> Value {
+    let subcommand = lower(&args[0]);
+    let ok = || Value::SimpleString("OK".to_string());
+
+    match (subcommand.as_str(), &args[1..]) {
+        ("sleep", [seconds]) => {
+            let Some(seconds) = std::str::from_utf8(seconds)
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .and_then(|s| Duration::try_from_secs_f64(s).ok())
+            else {
+                return Value::error("ERR value is not a valid float");
+            };
+            // Stalls the whole server, which is what tests use it for
+            std::thread::sleep(seconds);
+            ok()
+        }
+        ("object", [key]) => match peek(&mut dbs[selected], key) {
+            Some(val) => {
+                // The DUMP payload ends in a 2 byte version and an 8 byte checksum
+                let serialized = dump_value(val.data()).len() - 10;
+                Value::SimpleString(format!(
+                    "Value at:{:p} refcount:1 encoding:{} serializedlength:{serialized} lru_seconds_idle:{}",
+                    val.data(),
+                    val.data().encoding(),
+                    val.idle().as_secs()
+                ))
+            }
+            None => Value::error("ERR no such key"),
+        },
+        ("set-active-expire", [flag]) => match flag.as_slice() {
+            b"0" | b"1" => {
+                db::ACTIVE_EXPIRE.store(flag == b"1", Ordering::Relaxed);
+                ok()
+            }
+            _ => Value::error("ERR value is not an integer or out of range"),
+        },
+        ("jmap", []) => Value::BulkString(histogram(dbs).into()),
+        ("stringmatch-len", []) => {
+            fuzz_glob();
+            Value::SimpleString("Apparently Redis did not crash: test passed".to_string())
+        }
+        ("sleep" | "object" | "set-active-expire" | "jmap" | "stringmatch-len", _) => {
+            Value::error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try DEBUG HELP."
+            ))
+        }
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try DEBUG HELP."
+        )),
+    }
+}
+
+/// A heap histogram in the style of `jmap -histo`: how many values of each type there are and
+/// roughly how many bytes they take, biggest first.
+fn histogram(dbs: &[Keyspace]) -> String {
+    let mut types: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for (key, val) in dbs.iter().flat_map(Keyspace::iter) {
+        let (count, bytes) = types.entry(val.data().type_name()).or_default();
+        *count += 1;
+        *bytes += key.len() + value_bytes(val.data(), usize::MAX);
+    }
+
+    let mut rows: Vec<_> = types.into_iter().collect();
+    rows.sort_by_key(|&(_, (_, bytes))| std::cmp::Reverse(bytes));
+
+    let mut text = format!(
+        " {:>4} {:>12} {:>14}  type\n",
+        "num", "#instances", "#bytes"
+    );
+    text.push_str(&"-".repeat(text.len() - 1));
+    text.push('\n');
+    let (mut total_count, mut total_bytes) = (0, 0);
+    for (index, (type_name, (count, bytes))) in rows.into_iter().enumerate() {
+        text.push_str(&format!(
+            " {:>3}: {count:>12} {bytes:>14}  {type_name}\n",
+            index + 1
+        ));
+        total_count += count;
+        total_bytes += bytes;
+    }
+    text.push_str(&format!(" Total {total_count:>11} {total_bytes:>14}\n"));
+
+    text
+}
+
+/// Throws random patterns at the glob matcher, the way Redis fuzzes its own. A pattern that
+/// sends it into a loop or out of bounds takes the server down with it.
+fn fuzz_glob() {
+    let random = |max_len: usize| -> Vec<u8> {
+        const ALPHABET: &[u8] = b"ab*?[]^-\\";
+        (0..rand::below(max_len + 1))
+            .map(|_| ALPHABET[rand::below(ALPHABET.len())])
+            .collect()
+    };
+
+    for _ in 0..FUZZ_ROUNDS {
+        let (pattern, string) = (random(16), random(16));
+        glob_match(&pattern, &string, rand::below(2) == 1);
+    }
+}
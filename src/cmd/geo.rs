@@ -0,0 +1,497 @@
+use crate::cmd::zset::{get_zset, parse_score, zadd};
+use crate::cmd::{format_float, lower, not_an_integer, parse_int, registry::Spec, syntax_error};
+use crate::db::{DBData, DBVal, Keyspace};
+use crate::geo;
+use crate::notify::{self, Class};
+use crate::resp::Value;
+use crate::zset::ZSet;
+
+/// The geo commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("geoadd", -5, [1, 1, 1], geoadd),
+    Spec::keyspace("geopos", -2, [1, 1, 1], geopos),
+    Spec::keyspace("geodist", -4, [1, 1, 1], geodist).at_most(5),
+    Spec::keyspace("geohash", -2, [1, 1, 1], geohash),
+    Spec::keyspace("geosearch", -7, [1, 1, 1], geosearch),
+    Spec::keyspace("geosearchstore", -8, [1, 2, 1], geosearchstore),
+];
+
+/// Metres per unit for the distance units geo commands accept.
+fn parse_unit(arg: &[u8]) -> Result<f64, Value> {
+    match lower(arg).as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(Value::error(
+            "ERR unsupported unit provided. please use M, KM, FT, MI",
+        )),
+    }
+}
+
+fn parse_lon_lat(lon: &[u8], lat: &[u8]) -> Result<(f64, f64), Value> {
+    let (Some(lon), Some(lat)) = (parse_score(lon), parse_score(lat)) else {
+        return Err(Value::error("ERR value is not a valid float"));
+    };
+
+    if !geo::is_valid(lon, lat) {
+        return Err(Value::error(format!(
+            "ERR invalid longitude,latitude pair {lon:.6},{lat:.6}"
+        )));
+    }
+
+    Ok((lon, lat))
+}
+
+fn format_distance(meters: f64, unit: f64) -> Value {
+    Value::BulkString(format!("{:.4}", meters / unit).into())
+}
+
+fn coord_value((lon, lat): (f64, f64)) -> Value {
+    Value::Array(vec![
+        Value::BulkString(format_float(lon).into()),
+        Value::BulkString(format_float(lat).into()),
+    ])
+}
+
+/// The decoded position of `member`, if the key and member exist.
+fn member_position(
+    db: &mut Keyspace,
+    key: &[u8],
+    member: &[u8],
+) -> Result<Option<(f64, f64)>, Value> {
+    Ok(get_zset(db, key)?
+        .and_then(|zset| zset.score(member))
+        .map(|score| geo::decode(score as u64)))
+}
+
+fn geoadd(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut zadd_args = vec![args[0].clone()];
+    let mut i = 1;
+    while let Some(flag) = args.get(i).map(|arg| lower(arg))
+        && matches!(flag.as_str(), "nx" | "xx" | "ch")
+    {
+        zadd_args.push(args[i].clone());
+        i += 1;
+    }
+
+    let triplets = &args[i..];
+    if triplets.is_empty() || !triplets.len().is_multiple_of(3) {
+        return syntax_error();
+    }
+
+    // GEOADD is a ZADD whose scores are the members' geohashes
+    for triplet in triplets.chunks(3) {
+        let (lon, lat) = match parse_lon_lat(&triplet[0], &triplet[1]) {
+            Ok(position) => position,
+            Err(e) => return e,
+        };
+        zadd_args.push(geo::encode(lon, lat).to_string().into_bytes());
+        zadd_args.push(triplet[2].clone());
+    }
+
+    zadd(db, &zadd_args)
+}
+
+fn geopos(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut positions = Vec::with_capacity(args.len() - 1);
+    for member in &args[1..] {
+        match member_position(db, &args[0], member) {
+            Ok(Some(position)) => positions.push(coord_value(position)),
+            Ok(None) => positions.push(Value::NullArray),
+            Err(e) => return e,
+        }
+    }
+
+    Value::Array(positions)
+}
+
+fn geodist(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let unit = match args.get(3).map(|arg| parse_unit(arg)).transpose() {
+        Ok(unit) => unit.unwrap_or(1.0),
+        Err(e) => return e,
+    };
+
+    let positions = (
+        member_position(db, &args[0], &args[1]),
+        member_position(db, &args[0], &args[2]),
+    );
+    match positions {
+        (Ok(Some(from)), Ok(Some(to))) => format_distance(geo::distance(from, to), unit),
+        (Err(e), _) | (_, Err(e)) => e,
+        _ => Value::Null,
+    }
+}
+
+fn geohash(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut hashes = Vec::with_capacity(args.len() - 1);
+    for member in &args[1..] {
+        match member_position(db, &args[0], member) {
+            Ok(Some((lon, lat))) => hashes.push(Value::BulkString(geo::to_base32(lon, lat).into())),
+            Ok(None) => hashes.push(Value::Null),
+            Err(e) => return e,
+        }
+    }
+
+    Value::Array(hashes)
+}
+
+enum Origin {
+    Member(Vec<u8>),
+    LonLat(f64, f64),
+}
+
+enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+#[derive(PartialEq)]
+enum Sort {
+    None,
+    Asc,
+    Desc,
+}
+
+/// A parsed GEOSEARCH/GEOSEARCHSTORE query.
+struct Search {
+    origin: Origin,
+    shape: Shape,
+    /// Metres per unit of the shape's dimensions, also used for WITHDIST.
+    unit: f64,
+    sort: Sort,
+    count: Option<usize>,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+
+impl Search {
+    fn parse(args: &[Vec<u8>], store: bool) -> Result<Self, Value> {
+        let mut origin = None;
+        let mut shape = None;
+        let mut search = Search {
+            origin: Origin::LonLat(0.0, 0.0),
+            shape: Shape::Radius(0.0),
+            unit: 1.0,
+            sort: Sort::None,
+            count: None,
+            any: false,
+            with_coord: false,
+            with_dist: false,
+            with_hash: false,
+            store_dist: false,
+        };
+
+        let one_origin = || {
+            Value::error(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch",
+            )
+        };
+        let one_shape =
+            || Value::error("ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch");
+        let dimension = |arg: &[u8]| {
+            parse_score(arg)
+                .filter(|n| *n >= 0.0)
+                .ok_or_else(|| Value::error("ERR need numeric radius"))
+        };
+
+        let mut i = 0;
+        while i < args.len() {
+            let rest = &args[i + 1..];
+            match lower(&args[i]).as_str() {
+                "frommember" if !rest.is_empty() => {
+                    if origin.is_some() {
+                        return Err(one_origin());
+                    }
+                    origin = Some(Origin::Member(rest[0].clone()));
+                    i += 1;
+                }
+                "fromlonlat" if rest.len() >= 2 => {
+                    if origin.is_some() {
+                        return Err(one_origin());
+                    }
+                    let (lon, lat) = parse_lon_lat(&rest[0], &rest[1])?;
+                    origin = Some(Origin::LonLat(lon, lat));
+                    i += 2;
+                }
+                "byradius" if rest.len() >= 2 => {
+                    if shape.is_some() {
+                        return Err(one_shape());
+                    }
+                    shape = Some(Shape::Radius(dimension(&rest[0])?));
+                    search.unit = parse_unit(&rest[1])?;
+                    i += 2;
+                }
+                "bybox" if rest.len() >= 3 => {
+                    if shape.is_some() {
+                        return Err(one_shape());
+                    }
+                    let (Some(width), Some(height)) = (
+                        parse_score(&rest[0]).filter(|n| *n >= 0.0),
+                        parse_score(&rest[1]).filter(|n| *n >= 0.0),
+                    ) else {
+                        return Err(Value::error("ERR need numeric width and height"));
+                    };
+                    shape = Some(Shape::Box { width, height });
+                    search.unit = parse_unit(&rest[2])?;
+                    i += 3;
+                }
+                "asc" => search.sort = Sort::Asc,
+                "desc" => search.sort = Sort::Desc,
+                "count" if !rest.is_empty() => {
+                    search.count = match parse_int::<i64>(&rest[0]) {
+                        Some(n) if n > 0 => Some(n as usize),
+                        Some(_) => return Err(Value::error("ERR COUNT must be > 0")),
+                        None => return Err(not_an_integer()),
+                    };
+                    i += 1;
+                    if rest.get(1).is_some_and(|arg| lower(arg) == "any") {
+                        search.any = true;
+                        i += 1;
+                    }
+                }
+                "withcoord" if !store => search.with_coord = true,
+                "withdist" if !store => search.with_dist = true,
+                "withhash" if !store => search.with_hash = true,
+                "storedist" if store => search.store_dist = true,
+                _ => return Err(syntax_error()),
+            }
+            i += 1;
+        }
+
+        search.origin = origin.ok_or_else(one_origin)?;
+        search.shape = shape.ok_or_else(one_shape)?;
+
+        Ok(search)
+    }
+
+    /// Whether `point` falls in the search shape around `center`, returning its distance.
+    fn distance_within(&self, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        match self.shape {
+            Shape::Radius(radius) => {
+                let distance = geo::distance(center, point);
+                (distance <= radius * self.unit).then_some(distance)
+            }
+            Shape::Box { width, height } => {
+                // The latitude check is cheaper, so it goes first
+                if geo::lat_distance(point.1, center.1) > height * self.unit / 2.0 {
+                    return None;
+                }
+                if geo::distance((center.0, point.1), point) > width * self.unit / 2.0 {
+                    return None;
+                }
+                Some(geo::distance(center, point))
+            }
+        }
+    }
+
+    /// Runs the query, returning `(member, distance, geohash)` for every match in the
+    /// requested order and quantity.
+    fn run(&self, db: &mut Keyspace, key: &[u8]) -> Result<Vec<(Vec<u8>, f64, u64)>, Value> {
+        let empty = ZSet::new();
+        let zset = match get_zset(db, key)? {
+            Some(zset) => &*zset,
+            None => &empty,
+        };
+
+        let center = match &self.origin {
+            Origin::LonLat(lon, lat) => (*lon, *lat),
+            Origin::Member(member) => match zset.score(member) {
+                Some(score) => geo::decode(score as u64),
+                None => {
+                    return Err(Value::error("ERR could not decode requested zset member"));
+                }
+            },
+        };
+
+        let mut found = Vec::new();
+        for (member, score) in zset.iter() {
+            let hash = score as u64;
+            if let Some(distance) = self.distance_within(center, geo::decode(hash)) {
+                found.push((member.to_vec(), distance, hash));
+                // ANY settles for the first matches rather than the nearest
+                if self.any && Some(found.len()) == self.count {
+                    break;
+                }
+            }
+        }
+
+        // Like Redis, a COUNT without ANY implies nearest-first
+        let sort = match self.sort {
+            Sort::None if self.count.is_some() && !self.any => &Sort::Asc,
+            ref sort => sort,
+        };
+        match sort {
+            Sort::Asc => found.sort_by(|a, b| a.1.total_cmp(&b.1)),
+            Sort::Desc => found.sort_by(|a, b| b.1.total_cmp(&a.1)),
+            Sort::None => {}
+        }
+        if let Some(count) = self.count {
+            found.truncate(count);
+        }
+
+        Ok(found)
+    }
+}
+
+fn geosearch(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let search = match Search::parse(&args[1..], false) {
+        Ok(search) => search,
+        Err(e) => return e,
+    };
+    let found = match search.run(db, &args[0]) {
+        Ok(found) => found,
+        Err(e) => return e,
+    };
+
+    let plain = !(search.with_coord || search.with_dist || search.with_hash);
+    let reply = found
+        .into_iter()
+        .map(|(member, distance, hash)| {
+            if plain {
+                return Value::BulkString(member.into());
+            }
+
+            let mut item = vec![Value::BulkString(member.into())];
+            if search.with_dist {
+                item.push(format_distance(distance, search.unit));
+            }
+            if search.with_hash {
+                item.push(Value::Integer(hash as i64));
+            }
+            if search.with_coord {
+                item.push(coord_value(geo::decode(hash)));
+            }
+            Value::Array(item)
+        })
+        .collect();
+
+    Value::Array(reply)
+}
+
+fn geosearchstore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let search = match Search::parse(&args[2..], true) {
+        Ok(search) => search,
+        Err(e) => return e,
+    };
+    let found = match search.run(db, &args[1]) {
+        Ok(found) => found,
+        Err(e) => return e,
+    };
+
+    let mut zset = ZSet::new();
+    for (member, distance, hash) in found {
+        let score = if search.store_dist {
+            distance / search.unit
+        } else {
+            hash as f64
+        };
+        zset.insert(member, score);
+    }
+    let len = zset.len();
+
+    if zset.is_empty() {
+        if db.remove(&args[0]).is_some() {
+            notify::emit(Class::Generic, "del", &args[0]);
+        }
+    } else {
+        db.insert(args[0].clone(), DBData::new(DBVal::ZSet(zset), None));
+        notify::emit(Class::ZSet, "geosearchstore", &args[0]);
+    }
+
+    Value::Integer(len as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn bulk(reply: &Value) -> String {
+        match reply {
+            Value::BulkString(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            reply => panic!("expected a bulk string, got {reply:?}"),
+        }
+    }
+
+    fn elements(reply: Value) -> Vec<String> {
+        let Value::Array(values) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        values.iter().map(bulk).collect()
+    }
+
+    /// Redis's own example, with the answers its documentation gives.
+    const SICILY: &[&str] = &[
+        "sicily",
+        "13.361389",
+        "38.115556",
+        "palermo",
+        "15.087269",
+        "37.502669",
+        "catania",
+    ];
+
+    #[tokio::test]
+    async fn measures_between_members() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(geoadd(db, &args(SICILY)), Value::Integer(2)));
+        assert_eq!(
+            bulk(&geodist(db, &args(&["sicily", "palermo", "catania"]))),
+            "166274.1516"
+        );
+        assert_eq!(
+            bulk(&geodist(db, &args(&["sicily", "palermo", "catania", "km"]))),
+            "166.2742"
+        );
+        assert!(matches!(
+            geodist(db, &args(&["sicily", "palermo", "nowhere"])),
+            Value::Null
+        ));
+        assert_eq!(
+            elements(geohash(db, &args(&["sicily", "palermo", "catania"]))),
+            ["sqc8b49rny0", "sqdtr74hyu0"]
+        );
+        assert!(
+            geoadd(db, &args(&["sicily", "200", "0", "nowhere"]))
+                .error_message()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn searches_around_a_point() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        geoadd(db, &args(SICILY));
+        let search = |radius: &str| {
+            args(&[
+                "sicily",
+                "fromlonlat",
+                "15",
+                "37",
+                "byradius",
+                radius,
+                "km",
+                "asc",
+            ])
+        };
+        assert_eq!(
+            elements(geosearch(db, &search("200"))),
+            ["catania", "palermo"]
+        );
+        assert_eq!(elements(geosearch(db, &search("100"))), ["catania"]);
+    }
+}
@@ -0,0 +1,827 @@
+use crate::cmd::scan::{parse_scan_options, scan_batch, scan_reply};
+use crate::cmd::{
+    format_float, lookup, lower, not_an_integer, parse_int, registry::Spec, syntax_error,
+    wrong_args, wrong_type,
+};
+use crate::db::{self, DBData, DBVal, Keyspace, unix_millis};
+use crate::hash::Hash;
+use crate::notify::{self, Class};
+use crate::propagate;
+use crate::rand;
+use crate::replication;
+use crate::resp::Value;
+use bytes::Bytes;
+
+/// The hash commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("hset", -4, [1, 1, 1], hset),
+    Spec::keyspace("hmset", -4, [1, 1, 1], hmset),
+    Spec::keyspace("hget", 3, [1, 1, 1], hget),
+    Spec::keyspace("hgetall", 2, [1, 1, 1], hgetall),
+    Spec::keyspace("hdel", -3, [1, 1, 1], hdel),
+    Spec::keyspace("hexists", 3, [1, 1, 1], hexists),
+    Spec::keyspace("hlen", 2, [1, 1, 1], hlen),
+    Spec::keyspace("hkeys", 2, [1, 1, 1], hkeys),
+    Spec::keyspace("hvals", 2, [1, 1, 1], hvals),
+    Spec::keyspace("hsetnx", 4, [1, 1, 1], hsetnx),
+    Spec::keyspace("hmget", -3, [1, 1, 1], hmget),
+    Spec::keyspace("hincrby", 4, [1, 1, 1], hincrby),
+    Spec::keyspace("hincrbyfloat", 4, [1, 1, 1], hincrbyfloat),
+    Spec::keyspace("hrandfield", -2, [1, 1, 1], hrandfield).at_most(4),
+    Spec::keyspace("hscan", -3, [1, 1, 1], hscan),
+    Spec::keyspace("hexpire", -6, [1, 1, 1], hexpire),
+    Spec::keyspace("hpexpire", -6, [1, 1, 1], hpexpire),
+    Spec::keyspace("hexpireat", -6, [1, 1, 1], hexpireat),
+    Spec::keyspace("hpexpireat", -6, [1, 1, 1], hpexpireat),
+    Spec::keyspace("httl", -5, [1, 1, 1], httl),
+    Spec::keyspace("hpttl", -5, [1, 1, 1], hpttl),
+    Spec::keyspace("hexpiretime", -5, [1, 1, 1], hexpiretime),
+    Spec::keyspace("hpexpiretime", -5, [1, 1, 1], hpexpiretime),
+    Spec::keyspace("hpersist", -5, [1, 1, 1], hpersist),
+];
+
+/// Fetches the hash stored at `key`. `Ok(None)` means the key doesn't exist. Fields whose TTL
+/// has elapsed are dropped first, along with the key if that leaves it empty, except on a
+/// replica, which leaves that to its master's stream.
+pub fn get_hash<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<Option<&'a mut Hash>, Value> {
+    if !replication::is_replica()
+        && let Some(DBVal::Hash(hash)) = lookup(db, key).map(|val| val.data_mut())
+        && let expired = hash.purge_expired(unix_millis())
+        && !expired.is_empty()
+    {
+        notify::emit(Class::Hash, "hexpired", key);
+        propagate::fields_expired(db::selected(), key, &expired);
+        if hash.is_empty() {
+            db.remove(key);
+            notify::emit(Class::Generic, "del", key);
+        }
+    }
+
+    match lookup(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data_mut() {
+            DBVal::Hash(hash) => Ok(Some(hash)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+fn get_or_create_hash<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<&'a mut Hash, Value> {
+    if get_hash(db, key)?.is_none() {
+        db.insert(key.to_vec(), DBData::new(DBVal::Hash(Hash::new()), None));
+    }
+
+    // Not `get_hash` again: a field that ran out since would drop the key if it were the last
+    match db.get_mut(key).map(|val| val.data_mut()) {
+        Some(DBVal::Hash(hash)) => Ok(hash),
+        _ => unreachable!("hash was just looked up or created"),
+    }
+}
+
+/// Drops `key` if its hash has no fields left.
+fn remove_if_empty(db: &mut Keyspace, key: &[u8]) {
+    if let Some(DBVal::Hash(hash)) = db.get(key).map(|val| val.data())
+        && hash.is_empty()
+    {
+        db.remove(key);
+        notify::emit(Class::Generic, "del", key);
+    }
+}
+
+fn hset(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return wrong_args("hset");
+    }
+
+    let hash = match get_or_create_hash(db, &args[0]) {
+        Ok(hash) => hash,
+        Err(e) => return e,
+    };
+
+    let added = args[1..]
+        .chunks(2)
+        .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+        .count();
+    notify::emit(Class::Hash, "hset", &args[0]);
+
+    Value::Integer(added as i64)
+}
+
+fn hmset(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return wrong_args("hmset");
+    }
+
+    match hset(db, args) {
+        Value::Integer(_) => Value::SimpleString("OK".to_string()),
+        e => e,
+    }
+}
+
+fn hget(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => match hash.get(&args[1]) {
+            Some(value) => Value::BulkString(value.clone().into()),
+            None => Value::Null,
+        },
+        Ok(None) => Value::Null,
+        Err(e) => e,
+    }
+}
+
+fn hgetall(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => Value::Array(
+            hash.iter()
+                .flat_map(|(field, value)| {
+                    [
+                        Value::BulkString(field.clone().into()),
+                        Value::BulkString(value.clone().into()),
+                    ]
+                })
+                .collect(),
+        ),
+        Ok(None) => Value::Array(Vec::new()),
+        Err(e) => e,
+    }
+}
+
+fn hdel(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let hash = match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return Value::Integer(0),
+        Err(e) => return e,
+    };
+
+    let removed = args[1..]
+        .iter()
+        .filter(|field| hash.remove(field).is_some())
+        .count();
+
+    if removed > 0 {
+        notify::emit(Class::Hash, "hdel", &args[0]);
+    }
+    remove_if_empty(db, &args[0]);
+
+    Value::Integer(removed as i64)
+}
+
+fn hexists(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => Value::Integer(hash.contains_key(&args[1]) as i64),
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+fn hlen(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => Value::Integer(hash.len() as i64),
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+fn hkeys(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => Value::Array(
+            hash.keys()
+                .map(|field| Value::BulkString(field.clone().into()))
+                .collect(),
+        ),
+        Ok(None) => Value::Array(Vec::new()),
+        Err(e) => e,
+    }
+}
+
+fn hvals(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => Value::Array(
+            hash.values()
+                .map(|value| Value::BulkString(value.clone().into()))
+                .collect(),
+        ),
+        Ok(None) => Value::Array(Vec::new()),
+        Err(e) => e,
+    }
+}
+
+fn hsetnx(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let hash = match get_or_create_hash(db, &args[0]) {
+        Ok(hash) => hash,
+        Err(e) => return e,
+    };
+
+    if hash.contains_key(&args[1]) {
+        return Value::Integer(0);
+    }
+
+    hash.insert(args[1].clone(), args[2].clone());
+    notify::emit(Class::Hash, "hset", &args[0]);
+
+    Value::Integer(1)
+}
+
+fn hmget(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let hash = match get_hash(db, &args[0]) {
+        Ok(hash) => hash,
+        Err(e) => return e,
+    };
+
+    Value::Array(
+        args[1..]
+            .iter()
+            .map(
+                |field| match hash.as_ref().and_then(|hash| hash.get(field)) {
+                    Some(value) => Value::BulkString(value.clone().into()),
+                    None => Value::Null,
+                },
+            )
+            .collect(),
+    )
+}
+
+fn hincrby(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(increment) = parse_int::<i64>(&args[2]) else {
+        return not_an_integer();
+    };
+
+    let hash = match get_or_create_hash(db, &args[0]) {
+        Ok(hash) => hash,
+        Err(e) => return e,
+    };
+
+    let current = match hash.get(&args[1]) {
+        Some(value) => match parse_int::<i64>(value) {
+            Some(n) => n,
+            None => return Value::error("ERR hash value is not an integer"),
+        },
+        None => 0,
+    };
+
+    let Some(new) = current.checked_add(increment) else {
+        return Value::error("ERR increment or decrement would overflow");
+    };
+
+    hash.update(args[1].clone(), new.to_string().into_bytes());
+    notify::emit(Class::Hash, "hincrby", &args[0]);
+
+    Value::Integer(new)
+}
+
+fn hincrbyfloat(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(increment) = parse_int::<f64>(&args[2]).filter(|n| n.is_finite()) else {
+        return Value::error("ERR value is not a valid float");
+    };
+
+    let hash = match get_or_create_hash(db, &args[0]) {
+        Ok(hash) => hash,
+        Err(e) => return e,
+    };
+
+    let current = match hash.get(&args[1]) {
+        Some(value) => match parse_int::<f64>(value) {
+            Some(n) => n,
+            None => return Value::error("ERR hash value is not a float"),
+        },
+        None => 0.0,
+    };
+
+    let new = current + increment;
+    if !new.is_finite() {
+        return Value::error("ERR increment would produce NaN or Infinity");
+    }
+
+    let formatted = format_float(new).into_bytes();
+    hash.update(args[1].clone(), formatted.clone());
+    notify::emit(Class::Hash, "hincrbyfloat", &args[0]);
+
+    Value::BulkString(formatted.into())
+}
+
+fn hrandfield(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let count = match args.get(1).map(|arg| parse_int::<i64>(arg)) {
+        None => None,
+        Some(Some(n)) => Some(n),
+        Some(None) => return not_an_integer(),
+    };
+
+    let with_values = match args.get(2) {
+        None => false,
+        Some(arg) if arg.eq_ignore_ascii_case(b"withvalues") => true,
+        Some(_) => return syntax_error(),
+    };
+
+    let hash = match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) if count.is_some() => return Value::Array(Vec::new()),
+        Ok(None) => return Value::Null,
+        Err(e) => return e,
+    };
+
+    let entries: Vec<(&Vec<u8>, &Vec<u8>)> = hash.iter().collect();
+
+    let Some(count) = count else {
+        let (field, _) = entries[rand::below(entries.len())];
+        return Value::BulkString(field.clone().into());
+    };
+
+    let picked = rand::sample_indexes(entries.len(), count);
+
+    let mut reply = Vec::new();
+    for i in picked {
+        let (field, value) = entries[i];
+        reply.push(Value::BulkString(field.clone().into()));
+        if with_values {
+            reply.push(Value::BulkString(value.clone().into()));
+        }
+    }
+
+    Value::Array(reply)
+}
+
+fn hscan(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let opts = match parse_scan_options(&args[1..], true) {
+        Ok(opts) => opts,
+        Err(e) => return e,
+    };
+
+    let hash = match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return scan_reply(0, Vec::new()),
+        Err(e) => return e,
+    };
+
+    let (cursor, batch) = scan_batch(
+        hash.iter().map(|(field, value)| (field.as_slice(), value)),
+        opts.cursor,
+        opts.count,
+    );
+
+    let mut items = Vec::new();
+    for (field, value) in batch {
+        if !opts.matches(field) {
+            continue;
+        }
+
+        items.push(Value::BulkString(Bytes::copy_from_slice(field)));
+        if !opts.no_values {
+            items.push(Value::BulkString(value.clone().into()));
+        }
+    }
+
+    scan_reply(cursor, items)
+}
+
+/// Parses the trailing `FIELDS numfields field...` block shared by the field TTL commands.
+fn parse_fields(args: &[Vec<u8>]) -> Result<&[Vec<u8>], Value> {
+    if !args
+        .first()
+        .is_some_and(|arg| arg.eq_ignore_ascii_case(b"fields"))
+    {
+        return Err(Value::error(
+            "ERR Mandatory argument FIELDS is missing or not at the right position",
+        ));
+    }
+
+    let Some(count) = args
+        .get(1)
+        .and_then(|arg| parse_int::<i64>(arg))
+        .filter(|count| *count > 0)
+    else {
+        return Err(Value::error(
+            "ERR Parameter `numFields` should be greater than 0",
+        ));
+    };
+
+    if args.len() - 2 != count as usize {
+        return Err(Value::error(
+            "ERR The `numfields` parameter must match the number of arguments",
+        ));
+    }
+
+    Ok(&args[2..])
+}
+
+/// When a field's or a key's new deadline may replace its current one.
+#[derive(Clone, Copy)]
+pub enum ExpireCondition {
+    Always,
+    Nx,
+    Xx,
+    Gt,
+    Lt,
+}
+
+impl ExpireCondition {
+    /// The condition `arg` names, if it's one of `NX`, `XX`, `GT` and `LT`.
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        match lower(arg).as_str() {
+            "nx" => Some(ExpireCondition::Nx),
+            "xx" => Some(ExpireCondition::Xx),
+            "gt" => Some(ExpireCondition::Gt),
+            "lt" => Some(ExpireCondition::Lt),
+            _ => None,
+        }
+    }
+
+    /// Fields and keys without a deadline count as never expiring, so `GT` never applies to them and `LT`
+    /// always does.
+    pub fn allows(self, current: Option<u64>, new: u64) -> bool {
+        match (self, current) {
+            (ExpireCondition::Always, _) => true,
+            (ExpireCondition::Nx, current) => current.is_none(),
+            (ExpireCondition::Xx, current) => current.is_some(),
+            (ExpireCondition::Gt, current) => current.is_some_and(|at| new > at),
+            (ExpireCondition::Lt, current) => current.is_none_or(|at| new < at),
+        }
+    }
+}
+
+/// Shared implementation of `HEXPIRE`, `HPEXPIRE`, `HEXPIREAT` and `HPEXPIREAT`. `unit` is the
+/// number of milliseconds in one unit of the time argument.
+fn hexpire_generic(
+    db: &mut Keyspace,
+    args: &[Vec<u8>],
+    command: &str,
+    unit: u64,
+    absolute: bool,
+) -> Value {
+    if args.len() < 4 {
+        return wrong_args(command);
+    }
+
+    let Some(time) = parse_int::<i64>(&args[1]) else {
+        return not_an_integer();
+    };
+
+    let invalid_time = || Value::error(format!("ERR invalid expire time in '{command}' command"));
+    if time < 0 {
+        return invalid_time();
+    }
+
+    let (condition, rest) = match ExpireCondition::parse(&args[2]) {
+        Some(condition) => (condition, &args[3..]),
+        None => (ExpireCondition::Always, &args[2..]),
+    };
+
+    let fields = match parse_fields(rest) {
+        Ok(fields) => fields,
+        Err(e) => return e,
+    };
+
+    let now = unix_millis();
+    let Some(at) = (time as u64).checked_mul(unit).and_then(|ms| {
+        if absolute {
+            Some(ms)
+        } else {
+            ms.checked_add(now)
+        }
+    }) else {
+        return invalid_time();
+    };
+
+    let hash = match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return Value::Array(vec![Value::Integer(-2); fields.len()]),
+        Err(e) => return e,
+    };
+
+    let mut reply = Vec::with_capacity(fields.len());
+    for field in fields {
+        let status = if !hash.contains_key(field) {
+            -2
+        } else if !condition.allows(hash.expire_at(field), at) {
+            0
+        } else if at <= now {
+            hash.remove(field);
+            2
+        } else {
+            hash.set_expire(field, at);
+            1
+        };
+
+        reply.push(Value::Integer(status));
+    }
+
+    if reply
+        .iter()
+        .any(|status| matches!(status, Value::Integer(1)))
+    {
+        notify::emit(Class::Hash, "hexpire", &args[0]);
+    }
+    if reply
+        .iter()
+        .any(|status| matches!(status, Value::Integer(2)))
+    {
+        notify::emit(Class::Hash, "hdel", &args[0]);
+    }
+    remove_if_empty(db, &args[0]);
+
+    Value::Array(reply)
+}
+
+fn hexpire(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    hexpire_generic(db, args, "hexpire", 1000, false)
+}
+
+fn hpexpire(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    hexpire_generic(db, args, "hpexpire", 1, false)
+}
+
+fn hexpireat(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    hexpire_generic(db, args, "hexpireat", 1000, true)
+}
+
+fn hpexpireat(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    hexpire_generic(db, args, "hpexpireat", 1, true)
+}
+
+/// Shared implementation of `HTTL`, `HPTTL`, `HEXPIRETIME` and `HPEXPIRETIME`. Replies -2 for
+/// missing fields and -1 for fields without a deadline.
+fn httl_generic(
+    db: &mut Keyspace,
+    args: &[Vec<u8>],
+    command: &str,
+    unit: u64,
+    absolute: bool,
+) -> Value {
+    if args.len() < 4 {
+        return wrong_args(command);
+    }
+
+    let fields = match parse_fields(&args[1..]) {
+        Ok(fields) => fields,
+        Err(e) => return e,
+    };
+
+    let hash = match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return Value::Array(vec![Value::Integer(-2); fields.len()]),
+        Err(e) => return e,
+    };
+
+    let now = unix_millis();
+    Value::Array(
+        fields
+            .iter()
+            .map(|field| {
+                if !hash.contains_key(field) {
+                    return Value::Integer(-2);
+                }
+
+                let Some(at) = hash.expire_at(field) else {
+                    return Value::Integer(-1);
+                };
+
+                let ms = if absolute { at } else { at.saturating_sub(now) };
+                Value::Integer(ms.div_ceil(unit) as i64)
+            })
+            .collect(),
+    )
+}
+
+fn httl(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    httl_generic(db, args, "httl", 1000, false)
+}
+
+fn hpttl(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    httl_generic(db, args, "hpttl", 1, false)
+}
+
+fn hexpiretime(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    httl_generic(db, args, "hexpiretime", 1000, true)
+}
+
+fn hpexpiretime(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    httl_generic(db, args, "hpexpiretime", 1, true)
+}
+
+fn hpersist(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let fields = match parse_fields(&args[1..]) {
+        Ok(fields) => fields,
+        Err(e) => return e,
+    };
+
+    let hash = match get_hash(db, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return Value::Array(vec![Value::Integer(-2); fields.len()]),
+        Err(e) => return e,
+    };
+
+    let reply: Vec<Value> = fields
+        .iter()
+        .map(|field| {
+            Value::Integer(if !hash.contains_key(field) {
+                -2
+            } else if hash.persist(field) {
+                1
+            } else {
+                -1
+            })
+        })
+        .collect();
+
+    if reply
+        .iter()
+        .any(|status| matches!(status, Value::Integer(1)))
+    {
+        notify::emit(Class::Hash, "hpersist", &args[0]);
+    }
+
+    Value::Array(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn integers(reply: Value) -> Vec<i64> {
+        let Value::Array(values) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        values
+            .into_iter()
+            .map(|value| match value {
+                Value::Integer(n) => n,
+                value => panic!("expected an integer, got {value:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn set_get_and_delete_fields() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            hset(db, &args(&["h", "a", "1", "b", "2"])),
+            Value::Integer(2)
+        ));
+        assert!(matches!(
+            hset(db, &args(&["h", "a", "3"])),
+            Value::Integer(0)
+        ));
+        assert!(matches!(hget(db, &args(&["h", "a"])), Value::BulkString(v) if v == b"3"[..]));
+        assert!(matches!(hget(db, &args(&["h", "c"])), Value::Null));
+        assert!(matches!(
+            hdel(db, &args(&["h", "a", "c"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(hlen(db, &args(&["h"])), Value::Integer(1)));
+
+        // The hash goes with its last field
+        hdel(db, &args(&["h", "b"]));
+        assert!(db.get(b"h".as_slice()).is_none());
+    }
+
+    #[tokio::test]
+    async fn increments_only_numbers() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            hincrby(db, &args(&["h", "n", "5"])),
+            Value::Integer(5)
+        ));
+        assert!(matches!(
+            hincrby(db, &args(&["h", "n", "-7"])),
+            Value::Integer(-2)
+        ));
+        assert!(matches!(
+            hincrbyfloat(db, &args(&["h", "n", "0.5"])),
+            Value::BulkString(v) if v == b"-1.5"[..]
+        ));
+
+        hset(db, &args(&["h", "s", "text"]));
+        assert!(
+            hincrby(db, &args(&["h", "s", "1"]))
+                .error_message()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn sets_fields_only_once_and_picks_them_at_random() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            hsetnx(db, &args(&["h", "a", "1"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(
+            hsetnx(db, &args(&["h", "a", "2"])),
+            Value::Integer(0)
+        ));
+        hset(db, &args(&["h", "b", "2"]));
+        let Value::Array(values) = hmget(db, &args(&["h", "a", "missing"])) else {
+            panic!("HMGET replies with an array");
+        };
+        assert!(matches!(&values[..], [Value::BulkString(v), Value::Null] if *v == b"1"[..]));
+
+        let Value::Array(picked) = hrandfield(db, &args(&["h", "5", "withvalues"])) else {
+            panic!("HRANDFIELD with a count replies with an array");
+        };
+        // A positive count never repeats a field, so the whole hash comes back
+        assert_eq!(picked.len(), 4);
+        let Value::Array(picked) = hrandfield(db, &args(&["h", "-5"])) else {
+            panic!("HRANDFIELD with a count replies with an array");
+        };
+        assert_eq!(picked.len(), 5);
+        assert!(matches!(hrandfield(db, &args(&["missing"])), Value::Null));
+    }
+
+    #[tokio::test]
+    async fn scans_every_matching_field() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        for i in 0..20 {
+            let field = format!("{}{i}", if i % 2 == 0 { "even" } else { "odd" });
+            hset(db, &args(&["h", &field, "v"]));
+        }
+
+        let mut cursor = "0".to_string();
+        let mut fields = Vec::new();
+        loop {
+            let reply = hscan(
+                db,
+                &args(&["h", &cursor, "MATCH", "even*", "COUNT", "3", "NOVALUES"]),
+            );
+            let Value::Array(mut parts) = reply else {
+                panic!("HSCAN replies with a cursor and a batch");
+            };
+            let Value::Array(batch) = parts.pop().unwrap() else {
+                panic!("HSCAN replies with a batch");
+            };
+            let Some(Value::BulkString(next)) = parts.pop() else {
+                panic!("HSCAN replies with a cursor");
+            };
+            fields.extend(batch.into_iter().map(|field| match field {
+                Value::BulkString(field) => String::from_utf8_lossy(&field).into_owned(),
+                field => panic!("expected a field, got {field:?}"),
+            }));
+            cursor = String::from_utf8_lossy(&next).into_owned();
+            if cursor == "0" {
+                break;
+            }
+        }
+        fields.sort();
+        fields.dedup();
+        assert_eq!(fields.len(), 10);
+        assert!(fields.iter().all(|field| field.starts_with("even")));
+    }
+
+    #[tokio::test]
+    async fn expires_fields_on_their_own() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        hset(db, &args(&["h", "a", "1", "b", "2"]));
+
+        assert_eq!(
+            integers(hexpire(db, &args(&["h", "100", "fields", "2", "a", "c"]))),
+            [1, -2]
+        );
+        // NX only sets a deadline where there is none, GT only pushes one back
+        assert_eq!(
+            integers(hexpire(
+                db,
+                &args(&["h", "200", "nx", "fields", "2", "a", "b"])
+            )),
+            [0, 1]
+        );
+        assert_eq!(
+            integers(hpexpire(
+                db,
+                &args(&["h", "1000", "gt", "fields", "1", "a"])
+            )),
+            [0]
+        );
+        let ttl = integers(httl(db, &args(&["h", "fields", "2", "a", "b"])));
+        assert!(ttl[0] > 90 && ttl[0] <= 100);
+        assert!(ttl[1] > 190 && ttl[1] <= 200);
+
+        assert_eq!(
+            integers(hpersist(db, &args(&["h", "fields", "2", "a", "c"]))),
+            [1, -2]
+        );
+        assert_eq!(integers(hpttl(db, &args(&["h", "fields", "1", "a"]))), [-1]);
+
+        // A deadline already past deletes the field, and the hash with its last one
+        assert_eq!(
+            integers(hpexpireat(db, &args(&["h", "1", "fields", "2", "a", "b"]))),
+            [2, 2]
+        );
+        assert!(db.get(b"h".as_slice()).is_none());
+    }
+}
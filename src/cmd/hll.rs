@@ -0,0 +1,169 @@
+use crate::cmd::{lookup, registry::Spec};
+use crate::db::{DBData, DBVal, Keyspace, StringMut};
+use crate::hll;
+use crate::notify::{self, Class};
+use crate::resp::Value;
+
+/// The HyperLogLog commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("pfadd", -2, [1, 1, 1], pfadd),
+    Spec::keyspace("pfcount", -2, [1, -1, 1], pfcount),
+    Spec::keyspace("pfmerge", -2, [1, -1, 1], pfmerge),
+];
+
+fn invalid_hll() -> Value {
+    Value::error("WRONGTYPE Key is not a valid HyperLogLog string value.")
+}
+
+/// The HLL at `key` as a mutable sketch. `Ok(None)` means the key doesn't exist.
+fn get_hll<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<Option<StringMut<'a>>, Value> {
+    match lookup(db, key).map(|val| val.data_mut()) {
+        None => Ok(None),
+        Some(DBVal::String(bytes)) if hll::is_valid(bytes) => Ok(Some(StringMut::new(bytes))),
+        Some(_) => Err(invalid_hll()),
+    }
+}
+
+fn get_or_create_hll<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<StringMut<'a>, Value> {
+    if get_hll(db, key)?.is_none() {
+        db.insert(
+            key.to_vec(),
+            DBData::new(DBVal::String(hll::new().into()), None),
+        );
+    }
+
+    Ok(get_hll(db, key)?.expect("HLL was just created"))
+}
+
+fn pfadd(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let created = match get_hll(db, &args[0]) {
+        Ok(hll) => hll.is_none(),
+        Err(e) => return e,
+    };
+    let mut sketch = match get_or_create_hll(db, &args[0]) {
+        Ok(sketch) => sketch,
+        Err(e) => return e,
+    };
+
+    let mut changed = created;
+    for element in &args[1..] {
+        changed |= hll::add(&mut sketch, element);
+    }
+    if changed {
+        notify::emit(Class::String, "pfadd", &args[0]);
+    }
+
+    Value::Integer(changed as i64)
+}
+
+fn pfcount(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    // A single key can use, and refresh, the sketch's cached cardinality
+    if let [key] = args {
+        return match get_hll(db, key) {
+            Ok(Some(mut sketch)) => {
+                let count = hll::cached_count(&sketch).unwrap_or_else(|| {
+                    let count = hll::count(&sketch);
+                    hll::set_cached_count(&mut sketch, count);
+                    count
+                });
+                Value::Integer(count as i64)
+            }
+            Ok(None) => Value::Integer(0),
+            Err(e) => e,
+        };
+    }
+
+    let mut union = hll::new();
+    for key in args {
+        match get_hll(db, key) {
+            Ok(Some(sketch)) => hll::merge(&mut union, &sketch),
+            Ok(None) => {}
+            Err(e) => return e,
+        }
+    }
+
+    Value::Integer(hll::count(&union) as i64)
+}
+
+fn pfmerge(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut union = hll::new();
+    for key in args {
+        match get_hll(db, key) {
+            Ok(Some(sketch)) => hll::merge(&mut union, &sketch),
+            Ok(None) => {}
+            Err(e) => return e,
+        }
+    }
+
+    match get_or_create_hll(db, &args[0]) {
+        Ok(mut dest) => {
+            hll::merge(&mut dest, &union);
+            notify::emit(Class::String, "pfadd", &args[0]);
+            Value::SimpleString("OK".to_string())
+        }
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn count(reply: Value) -> i64 {
+        match reply {
+            Value::Integer(n) => n,
+            reply => panic!("expected an integer, got {reply:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_distinct_elements() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert_eq!(count(pfadd(db, &args(&["h", "a", "b", "c"]))), 1);
+        assert_eq!(count(pfadd(db, &args(&["h", "a", "b"]))), 0);
+        // Creating an empty sketch is a change too
+        assert_eq!(count(pfadd(db, &args(&["empty"]))), 1);
+        assert_eq!(count(pfcount(db, &args(&["h"]))), 3);
+        assert_eq!(count(pfcount(db, &args(&["missing"]))), 0);
+
+        let elements: Vec<_> = (0..1000).map(|n| n.to_string()).collect();
+        let mut add = vec!["big"];
+        add.extend(elements.iter().map(String::as_str));
+        pfadd(db, &args(&add));
+        let estimate = count(pfcount(db, &args(&["big"])));
+        assert!((980..=1020).contains(&estimate), "{estimate}");
+    }
+
+    #[tokio::test]
+    async fn merges_sketches_and_refuses_other_strings() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        pfadd(db, &args(&["x", "a", "b", "c"]));
+        pfadd(db, &args(&["y", "c", "d"]));
+        assert_eq!(count(pfcount(db, &args(&["x", "y"]))), 4);
+        assert!(matches!(
+            pfmerge(db, &args(&["z", "x", "y"])),
+            Value::SimpleString(_)
+        ));
+        assert_eq!(count(pfcount(db, &args(&["z"]))), 4);
+
+        db.insert(
+            b"s".to_vec(),
+            DBData::new(DBVal::String(b"not a sketch".to_vec().into()), None),
+        );
+        assert_eq!(
+            pfcount(db, &args(&["s"])).error_message(),
+            invalid_hll().error_message()
+        );
+    }
+}
@@ -0,0 +1,531 @@
+use crate::alloc;
+use crate::aof;
+use crate::blocking::BlockedClients;
+use crate::client;
+use crate::cluster;
+use crate::cmd::{lower, registry::Spec};
+use crate::config;
+use crate::db::{self, DBData, Keyspace};
+use crate::failover;
+use crate::lazyfree;
+use crate::pubsub::{Kind, PubSub};
+use crate::replication::{self, Link};
+use crate::resp::Value;
+use crate::snapshot;
+use crate::stats;
+use crate::tracking;
+use std::fmt::Write;
+use std::fs;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+/// INFO itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("info", -1, [0, 0, 0], |ctx, args| {
+    info(ctx.dbs, ctx.pubsub, ctx.blocked, args)
+})];
+
+/// Sections in the order INFO lists them. All but commandstats are shown by default.
+const SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "cpu",
+    "commandstats",
+    "cluster",
+    "keyspace",
+];
+
+/// A random ID for this run of the server.
+static RUN_ID: LazyLock<String> = LazyLock::new(replication::random_id);
+
+/// Whether the server runs on its own or as a node of a cluster, as INFO and HELLO report it.
+pub fn mode() -> &'static str {
+    if cluster::enabled() {
+        "cluster"
+    } else {
+        "standalone"
+    }
+}
+
+/// INFO [section ...]: `default` or no section gives every section but commandstats, and
+/// `all` or `everything` gives all of them. Unknown sections are left out.
+pub fn info(
+    dbs: &[Keyspace],
+    pubsub: &PubSub,
+    blocked: &BlockedClients,
+    args: &[Vec<u8>],
+) -> Value {
+    let requested: Vec<String> = args.iter().map(|arg| lower(arg)).collect();
+    let wanted = |section: &str| {
+        if requested.is_empty() || requested.iter().any(|r| r == "default") {
+            section != "commandstats"
+        } else {
+            requested
+                .iter()
+                .any(|r| r == section || r == "all" || r == "everything")
+        }
+    };
+
+    let mut text = String::new();
+    for section in SECTIONS.iter().filter(|section| wanted(section)) {
+        if !text.is_empty() {
+            text.push_str("\r\n");
+        }
+        let mut title = section.to_string();
+        title[..1].make_ascii_uppercase();
+        writeln!(text, "# {title}\r").unwrap();
+
+        let fields = match *section {
+            "server" => server(),
+            "clients" => clients(blocked),
+            "memory" => memory(),
+            "persistence" => persistence(),
+            "stats" => stats(pubsub),
+            "replication" => replication(),
+            "cpu" => cpu(),
+            "commandstats" => commandstats(),
+            "cluster" => cluster_section(),
+            _ => keyspace(dbs),
+        };
+        for (name, value) in fields {
+            writeln!(text, "{name}:{value}\r").unwrap();
+        }
+    }
+
+    Value::BulkString(text.into())
+}
+
+fn server() -> Vec<(String, String)> {
+    let config = config::get();
+    let uptime = stats::STARTED.elapsed().as_secs();
+
+    let mut section = fields([
+        ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("redis_mode", mode().to_string()),
+        ("os", std::env::consts::OS.to_string()),
+        ("arch_bits", (usize::BITS).to_string()),
+        ("process_id", std::process::id().to_string()),
+        ("run_id", RUN_ID.clone()),
+        ("tcp_port", config.port.to_string()),
+    ]);
+    section.extend(listeners(&config));
+    section.extend(fields([
+        ("io_backend", config.io_backend.clone()),
+        ("runtime", runtime().to_string()),
+        ("io_threads", io_threads().to_string()),
+        ("uptime_in_seconds", uptime.to_string()),
+        ("uptime_in_days", (uptime / 86400).to_string()),
+        (
+            "executable",
+            std::env::current_exe()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        ),
+    ]));
+
+    section
+}
+
+/// The kind of runtime the server is running on, multi-threaded or all on one thread.
+fn runtime() -> &'static str {
+    match Handle::current().runtime_flavor() {
+        RuntimeFlavor::CurrentThread => "current_thread",
+        _ => "multi_thread",
+    }
+}
+
+/// Threads the runtime runs tasks on.
+fn io_threads() -> usize {
+    Handle::current().metrics().num_workers()
+}
+
+/// The client listeners, in the `name=tcp,bind=...,port=...` form Redis gives them.
+fn listeners(config: &config::ServerConfig) -> Vec<(String, String)> {
+    let listener = |name: &str, port: u16| {
+        let mut listener = format!("name={name}");
+        for addr in crate::server::bound_addrs() {
+            if addr.port() == port {
+                let _ = write!(listener, ",bind={}", addr.ip());
+            }
+        }
+        let _ = write!(listener, ",port={port}");
+        listener
+    };
+
+    let mut listeners = Vec::new();
+    // A port of 0 left after binding means there's no plaintext listener, just a TLS one
+    if config.port != 0 {
+        listeners.push(listener("tcp", config.port));
+    }
+    if config.tls_port != 0 {
+        listeners.push(listener("tls", config.tls_port));
+    }
+    if !config.unixsocket.is_empty() {
+        listeners.push(format!("name=unix,bind={}", config.unixsocket));
+    }
+    listeners
+        .into_iter()
+        .enumerate()
+        .map(|(n, listener)| (format!("listener{n}"), listener))
+        .collect()
+}
+
+fn clients(blocked: &BlockedClients) -> Vec<(String, String)> {
+    let (connected, subscribed) = client::counts();
+
+    fields([
+        ("connected_clients", connected.to_string()),
+        ("maxclients", config::get().maxclients.to_string()),
+        ("blocked_clients", blocked.count().to_string()),
+        ("tracking_clients", tracking::count().to_string()),
+        ("pubsub_clients", subscribed.to_string()),
+    ])
+}
+
+fn memory() -> Vec<(String, String)> {
+    let config = config::get();
+    let rss = resident_bytes();
+    let used = db::used_memory();
+
+    let mut info = fields([
+        ("used_memory", used.to_string()),
+        ("used_memory_human", human_bytes(used)),
+        ("used_memory_rss", rss.to_string()),
+        ("used_memory_rss_human", human_bytes(rss)),
+        ("maxmemory", config.maxmemory.to_string()),
+        ("maxmemory_human", human_bytes(config.maxmemory)),
+        ("maxmemory_policy", config.maxmemory_policy.clone()),
+        (
+            "mem_fragmentation_ratio",
+            format!("{:.2}", rss as f64 / used.max(1) as f64),
+        ),
+        (
+            "mem_fragmentation_bytes",
+            (rss as i64 - used as i64).to_string(),
+        ),
+        ("mem_allocator", alloc::name()),
+        ("lazyfree_pending_objects", lazyfree::pending().to_string()),
+    ]);
+    // Only jemalloc can say how much of the resident memory is its own
+    if let Some(allocator) = alloc::stats() {
+        let ratio = |of: u64, to: u64| format!("{:.2}", of as f64 / to.max(1) as f64);
+        let bytes = |of: u64, to: u64| (of as i64 - to as i64).to_string();
+        let (allocated, active, resident) =
+            (allocator.allocated, allocator.active, allocator.resident);
+        info.extend(fields([
+            ("allocator_allocated", allocated.to_string()),
+            ("allocator_active", active.to_string()),
+            ("allocator_resident", resident.to_string()),
+            ("allocator_frag_ratio", ratio(active, allocated)),
+            ("allocator_frag_bytes", bytes(active, allocated)),
+            ("allocator_rss_ratio", ratio(resident, active)),
+            ("allocator_rss_bytes", bytes(resident, active)),
+            ("rss_overhead_ratio", ratio(rss, resident)),
+            ("rss_overhead_bytes", bytes(rss, resident)),
+        ]));
+    }
+
+    info
+}
+
+fn persistence() -> Vec<(String, String)> {
+    let config = config::get();
+
+    let mut info = fields([
+        ("loading", "0".to_string()),
+        (
+            "rdb_changes_since_last_save",
+            snapshot::changes_since_save().to_string(),
+        ),
+        (
+            "rdb_bgsave_in_progress",
+            (snapshot::in_progress() as u8).to_string(),
+        ),
+        (
+            "rdb_last_save_time",
+            snapshot::LAST_SAVE.load(Ordering::Relaxed).to_string(),
+        ),
+        (
+            "rdb_last_bgsave_status",
+            if snapshot::LAST_BGSAVE_OK.load(Ordering::Relaxed) {
+                "ok".to_string()
+            } else {
+                "err".to_string()
+            },
+        ),
+        (
+            "rdb_last_bgsave_time_sec",
+            snapshot::LAST_BGSAVE_SECS
+                .load(Ordering::Relaxed)
+                .to_string(),
+        ),
+        ("aof_enabled", (config.appendonly as u8).to_string()),
+        (
+            "aof_rewrite_in_progress",
+            (aof::rewrite_in_progress() as u8).to_string(),
+        ),
+        (
+            "aof_last_rewrite_time_sec",
+            aof::LAST_REWRITE_SECS.load(Ordering::Relaxed).to_string(),
+        ),
+        (
+            "aof_last_bgrewrite_status",
+            if aof::LAST_REWRITE_OK.load(Ordering::Relaxed) {
+                "ok".to_string()
+            } else {
+                "err".to_string()
+            },
+        ),
+    ]);
+    // Sizes only mean anything while there's a file being appended to
+    if config.appendonly {
+        info.extend(fields([
+            (
+                "aof_current_size",
+                aof::CURRENT_SIZE.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "aof_base_size",
+                aof::BASE_SIZE.load(Ordering::Relaxed).to_string(),
+            ),
+        ]));
+    }
+
+    info
+}
+
+fn stats(pubsub: &PubSub) -> Vec<(String, String)> {
+    let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    fields([
+        (
+            "total_connections_received",
+            counter(&stats::CONNECTIONS_RECEIVED).to_string(),
+        ),
+        (
+            "total_commands_processed",
+            counter(&stats::COMMANDS_PROCESSED).to_string(),
+        ),
+        (
+            "rejected_connections",
+            counter(&stats::REJECTED_CONNECTIONS).to_string(),
+        ),
+        (
+            "throttled_commands",
+            counter(&stats::THROTTLED_COMMANDS).to_string(),
+        ),
+        (
+            "shadow_divergences",
+            counter(&stats::SHADOW_DIVERGENCES).to_string(),
+        ),
+        ("expired_keys", counter(&stats::EXPIRED_KEYS).to_string()),
+        ("evicted_keys", counter(&stats::EVICTED_KEYS).to_string()),
+        ("lazyfreed_objects", lazyfree::freed().to_string()),
+        ("keyspace_hits", counter(&stats::KEYSPACE_HITS).to_string()),
+        (
+            "keyspace_misses",
+            counter(&stats::KEYSPACE_MISSES).to_string(),
+        ),
+        (
+            "pubsub_channels",
+            pubsub.channels(Kind::Channel, None).len().to_string(),
+        ),
+        ("pubsub_patterns", pubsub.pattern_count().to_string()),
+    ])
+}
+
+fn replication() -> Vec<(String, String)> {
+    let replicas = replication::replicas();
+
+    let mut info = match replication::master() {
+        None => fields([("role", "master".to_string())]),
+        Some((host, port)) => {
+            let (link, last_io) = replication::link();
+            let mut info = fields([
+                ("role", "slave".to_string()),
+                ("master_host", host),
+                ("master_port", port.to_string()),
+                (
+                    "master_link_status",
+                    if link == Link::Up { "up" } else { "down" }.to_string(),
+                ),
+                (
+                    "master_last_io_seconds_ago",
+                    last_io.map_or(-1, |secs| secs as i64).to_string(),
+                ),
+                (
+                    "master_sync_in_progress",
+                    ((link == Link::Syncing) as u8).to_string(),
+                ),
+            ]);
+            // Failover tooling goes by how long a replica's been cut off from its master
+            if link != Link::Up {
+                let down_for = replication::link_down_for().map_or(-1, |secs| secs as i64);
+                info.push((
+                    "master_link_down_since_seconds".to_string(),
+                    down_for.to_string(),
+                ));
+            }
+            info.extend(fields([
+                ("slave_repl_offset", replication::offset().to_string()),
+                ("slave_priority", config::get().replica_priority.to_string()),
+                (
+                    "slave_read_only",
+                    (replication::READ_ONLY.load(Ordering::Relaxed) as u8).to_string(),
+                ),
+            ]));
+            info
+        }
+    };
+    info.push(("connected_slaves".to_string(), replicas.len().to_string()));
+    if replication::MIN_REPLICAS.load(Ordering::Relaxed) > 0 {
+        info.push((
+            "min_slaves_good_slaves".to_string(),
+            replication::good_replicas().to_string(),
+        ));
+    }
+    for (i, replica) in replicas.iter().enumerate() {
+        info.push((
+            format!("slave{i}"),
+            format!(
+                "ip={},port={},state={},offset={},lag={}",
+                replica.addr.ip().to_canonical(),
+                replica.port,
+                replica.state,
+                replica.offset,
+                replica.lag
+            ),
+        ));
+    }
+    let (replid2, second_offset) = replication::second_id();
+    let backlog = replication::backlog();
+    let (first_byte, histlen) = backlog.unwrap_or((0, 0));
+    info.extend(fields([
+        (
+            "master_failover_state",
+            failover::state().name().to_string(),
+        ),
+        ("master_replid", replication::replid()),
+        ("master_replid2", replid2),
+        ("master_repl_offset", replication::offset().to_string()),
+        (
+            "second_repl_offset",
+            second_offset.map_or(-1, |offset| offset as i64).to_string(),
+        ),
+        ("repl_backlog_active", (backlog.is_some() as u8).to_string()),
+        (
+            "repl_backlog_size",
+            config::get().repl_backlog_size.to_string(),
+        ),
+        ("repl_backlog_first_byte_offset", first_byte.to_string()),
+        ("repl_backlog_histlen", histlen.to_string()),
+    ]));
+
+    info
+}
+
+fn cpu() -> Vec<(String, String)> {
+    let (user, system) = cpu_seconds();
+
+    fields([
+        ("used_cpu_sys", format!("{system:.6}")),
+        ("used_cpu_user", format!("{user:.6}")),
+    ])
+}
+
+fn cluster_section() -> Vec<(String, String)> {
+    fields([("cluster_enabled", u8::from(cluster::enabled()).to_string())])
+}
+
+fn commandstats() -> Vec<(String, String)> {
+    stats::commands()
+        .into_iter()
+        .map(|(name, stats)| {
+            let per_call = stats.usec as f64 / stats.calls as f64;
+            (
+                format!("cmdstat_{name}"),
+                format!(
+                    "calls={},usec={},usec_per_call={per_call:.2},failed_calls={},\
+                     keyspace_hits={},keyspace_misses={}",
+                    stats.calls,
+                    stats.usec,
+                    stats.failed_calls,
+                    stats.keyspace_hits,
+                    stats.keyspace_misses
+                ),
+            )
+        })
+        .collect()
+}
+
+/// A line per database holding any keys, with how many have a TTL and their average remaining
+/// TTL in milliseconds.
+fn keyspace(dbs: &[Keyspace]) -> Vec<(String, String)> {
+    dbs.iter()
+        .enumerate()
+        .filter(|(_, db)| !db.is_empty())
+        .map(|(index, db)| {
+            let ttls: Vec<u64> = db.values().filter_map(DBData::ttl).collect();
+            let avg_ttl = match ttls.len() {
+                0 => 0,
+                n => ttls.iter().sum::<u64>() / n as u64,
+            };
+
+            (
+                format!("db{index}"),
+                format!("keys={},expires={},avg_ttl={avg_ttl}", db.len(), ttls.len()),
+            )
+        })
+        .collect()
+}
+
+fn fields<const N: usize>(fields: [(&str, String); N]) -> Vec<(String, String)> {
+    fields
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
+
+/// A byte count the way Redis shows one for people, e.g. `1.50M`.
+fn human_bytes(bytes: u64) -> String {
+    let units = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+    match units.iter().find(|(size, _)| bytes >= *size) {
+        Some((size, unit)) => format!("{:.2}{unit}", bytes as f64 / *size as f64),
+        None => format!("{bytes}B"),
+    }
+}
+
+/// The process' resident set size, from /proc where there is one, or 0 where there isn't.
+pub fn resident_bytes() -> u64 {
+    fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+        .map_or(0, |pages| pages * 4096)
+}
+
+/// User and system CPU time used by the process so far, from /proc where there is one. The
+/// times there are in clock ticks, which are 1/100s on every Linux platform that matters.
+fn cpu_seconds() -> (f64, f64) {
+    let Ok(stat) = fs::read_to_string("/proc/self/stat") else {
+        return (0.0, 0.0);
+    };
+    // The command name can contain spaces, so count fields from after its closing paren
+    let fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .map_or("", |(_, rest)| rest)
+        .split_whitespace()
+        .collect();
+    let ticks = |index: usize| {
+        fields
+            .get(index)
+            .and_then(|field| field.parse::<f64>().ok())
+            .unwrap_or_default()
+    };
+
+    // utime and stime are fields 14 and 15 of the line, and these start at field 3
+    (ticks(11) / 100.0, ticks(12) / 100.0)
+}
@@ -0,0 +1,755 @@
+use crate::cluster;
+use crate::cmd::hash::ExpireCondition;
+use crate::cmd::registry::{Context, Spec};
+use crate::cmd::scan::{parse_scan_options, scan_batch, scan_reply};
+use crate::cmd::{lookup, lower, not_an_integer, parse_int, peek, syntax_error, wrong_args};
+use crate::db::{self, DBData, Keyspace, unix_millis};
+use crate::dump::{dump_value, restore_value};
+use crate::evict;
+use crate::glob::glob_match;
+use crate::lazyfree;
+use crate::namespace;
+use crate::notify::{self, Class};
+use crate::propagate;
+use crate::resp::Value;
+use crate::tracking;
+use bytes::Bytes;
+use std::time::Duration;
+
+/// The keyspace commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::server("select", 2, [0, 0, 0], |ctx, args| {
+        select(&mut ctx.client.db, ctx.dbs.len(), args)
+    }),
+    Spec::server("swapdb", 3, [0, 0, 0], swapdb),
+    Spec::server("move", 3, [1, 1, 1], move_),
+    Spec::keyspace("del", -2, [1, -1, 1], del),
+    Spec::keyspace("unlink", -2, [1, -1, 1], unlink),
+    Spec::server("flushall", -1, [0, 0, 0], |ctx, args| {
+        flush(ctx.dbs, 0, args)
+    }),
+    Spec::server("flushdb", -1, [0, 0, 0], |ctx, args| {
+        let selected = ctx.client.db;
+        flush(&mut ctx.dbs[selected..=selected], selected, args)
+    }),
+    Spec::read("keys", 2, [0, 0, 0], keys),
+    Spec::read("scan", -2, [0, 0, 0], scan),
+    Spec::read("dbsize", 1, [0, 0, 0], dbsize),
+    Spec::keyspace("touch", -2, [1, -1, 1], touch),
+    Spec::keyspace("dump", 2, [1, 1, 1], dump),
+    Spec::keyspace("restore", -4, [1, 1, 1], restore),
+    Spec::keyspace("object", -2, [2, 2, 1], object),
+    Spec::keyspace("expire", -3, [1, 1, 1], expire).at_most(4),
+    Spec::keyspace("pexpire", -3, [1, 1, 1], pexpire).at_most(4),
+    Spec::keyspace("expireat", -3, [1, 1, 1], expireat).at_most(4),
+    Spec::keyspace("pexpireat", -3, [1, 1, 1], pexpireat).at_most(4),
+    Spec::keyspace("persist", 2, [1, 1, 1], persist),
+    Spec::read("ttl", 2, [1, 1, 1], ttl),
+    Spec::read("pttl", 2, [1, 1, 1], pttl),
+    Spec::read("expiretime", 2, [1, 1, 1], expiretime),
+    Spec::read("pexpiretime", 2, [1, 1, 1], pexpiretime),
+    Spec::read("type", 2, [1, 1, 1], type_),
+];
+
+/// The database index in `arg`, which must be below the configured `count`.
+fn db_index(arg: &[u8], count: usize) -> Result<usize, Value> {
+    let index: i64 = parse_int(arg).ok_or_else(not_an_integer)?;
+
+    usize::try_from(index)
+        .ok()
+        .filter(|index| *index < count)
+        .ok_or_else(|| Value::error("ERR DB index is out of range"))
+}
+
+/// SELECT index, switching the connection's `selected` database.
+pub fn select(selected: &mut usize, count: usize, args: &[Vec<u8>]) -> Value {
+    match db_index(&args[0], count) {
+        // A cluster only has the one database to share out between its nodes
+        Ok(index) if index != 0 && cluster::enabled() => {
+            Value::error("ERR SELECT is not allowed in cluster mode")
+        }
+        Ok(index) => {
+            *selected = index;
+            Value::SimpleString("OK".to_string())
+        }
+        Err(e) => e,
+    }
+}
+
+/// SWAPDB index1 index2. Every connection sees the swap, whichever database it selected, and
+/// clients blocked on either database may find what they're waiting for.
+fn swapdb(ctx: &mut Context<'_>, args: &[Vec<u8>]) -> Value {
+    let dbs = &mut *ctx.dbs;
+    if cluster::enabled() {
+        return Value::error("ERR SWAPDB is not allowed in cluster mode");
+    }
+
+    let Some(first) = parse_int::<i64>(&args[0]) else {
+        return Value::error("ERR invalid first DB index");
+    };
+    let Some(second) = parse_int::<i64>(&args[1]) else {
+        return Value::error("ERR invalid second DB index");
+    };
+    let in_range = |index: i64| usize::try_from(index).ok().filter(|i| *i < dbs.len());
+    let (Some(first), Some(second)) = (in_range(first), in_range(second)) else {
+        return Value::error("ERR DB index is out of range");
+    };
+
+    dbs.swap(first, second);
+    db::mark_dirty();
+    ctx.blocked.signal_db(first);
+    ctx.blocked.signal_db(second);
+
+    Value::SimpleString("OK".to_string())
+}
+
+/// MOVE key db, from the selected database. Fails rather than overwrite a key in the
+/// destination.
+fn move_(ctx: &mut Context<'_>, args: &[Vec<u8>]) -> Value {
+    let (dbs, from) = (&mut *ctx.dbs, ctx.client.db);
+    if cluster::enabled() {
+        return Value::error("ERR MOVE is not allowed in cluster mode");
+    }
+
+    let to = match db_index(&args[1], dbs.len()) {
+        Ok(to) => to,
+        Err(e) => return e,
+    };
+    if to == from {
+        return Value::error("ERR source and destination objects are the same");
+    }
+
+    let key = &args[0];
+    if peek(&mut dbs[from], key).is_none() {
+        return Value::Integer(0);
+    }
+    db::select(to);
+    let taken = peek(&mut dbs[to], key).is_some();
+    db::select(from);
+    if taken {
+        return Value::Integer(0);
+    }
+
+    let val = dbs[from].remove(key).expect("key was just found");
+    dbs[to].insert(key.clone(), val);
+
+    notify::emit(Class::Generic, "move_from", key);
+    db::select(to);
+    notify::emit(Class::Generic, "move_to", key);
+    db::select(from);
+    ctx.blocked.signal(to, key);
+
+    Value::Integer(1)
+}
+
+fn del(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut deleted = 0;
+    for key in args {
+        if peek(db, key).is_some() {
+            db.remove(key);
+            notify::emit(Class::Generic, "del", key);
+            deleted += 1;
+        }
+    }
+
+    Value::Integer(deleted)
+}
+
+/// UNLINK key [key ...]: DEL, but leaving big values to be freed in the background.
+fn unlink(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut unlinked = 0;
+    for key in args {
+        if peek(db, key).is_some() {
+            let val = db.remove(key).expect("key was just found");
+            lazyfree::free(val);
+            notify::emit(Class::Generic, "del", key);
+            unlinked += 1;
+        }
+    }
+
+    Value::Integer(unlinked)
+}
+
+/// FLUSHALL [ASYNC | SYNC] and FLUSHDB [ASYNC | SYNC], emptying `dbs`, the first of which is
+/// database `first`. ASYNC leaves the keys to be freed in the background.
+fn flush(dbs: &mut [Keyspace], first: usize, args: &[Vec<u8>]) -> Value {
+    let lazily = match args {
+        [] => false,
+        [mode] => match lower(mode).as_str() {
+            "async" => true,
+            "sync" => false,
+            _ => return syntax_error(),
+        },
+        _ => return syntax_error(),
+    };
+
+    if let Some(prefix) = namespace::current() {
+        for (index, keyspace) in dbs.iter_mut().enumerate() {
+            flush_namespace(keyspace, first + index, &prefix, lazily);
+        }
+        return Value::SimpleString("OK".to_string());
+    }
+
+    for keyspace in dbs {
+        if lazily {
+            keyspace.clear_lazily();
+        } else {
+            keyspace.clear();
+        }
+    }
+    db::mark_dirty();
+    tracking::invalidate_all();
+
+    Value::SimpleString("OK".to_string())
+}
+
+/// Flushes only the keys under `prefix` from `keyspace`, database `index`, for a client confined
+/// to that namespace. They're passed on as deleted, since a FLUSHDB would empty the rest.
+fn flush_namespace(keyspace: &mut Keyspace, index: usize, prefix: &[u8], lazily: bool) {
+    let keys: Vec<Vec<u8>> = keyspace
+        .iter()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .map(|(key, _)| key.to_vec())
+        .collect();
+    if keys.is_empty() {
+        return;
+    }
+
+    let selected = db::selected();
+    db::select(index);
+    for key in &keys {
+        let val = keyspace.remove(key).expect("key was just found");
+        if lazily {
+            lazyfree::free(val);
+        }
+        notify::emit(Class::Generic, "del", key);
+    }
+    db::select(selected);
+    propagate::deleted(index, keys);
+}
+
+/// KEYS pattern: the keys matching `pattern` that the client can see.
+fn keys(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    Value::Array(
+        db.visible()
+            .filter(|(key, val)| !val.is_expired() && glob_match(&args[0], key, false))
+            .map(|(key, _)| Value::BulkString(Bytes::copy_from_slice(key)))
+            .collect(),
+    )
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count], over the keys the client can see.
+fn scan(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    let opts = match parse_scan_options(args, false) {
+        Ok(opts) => opts,
+        Err(e) => return e,
+    };
+
+    let (cursor, batch) = scan_batch(
+        db.visible().filter(|(_, val)| !val.is_expired()),
+        opts.cursor,
+        opts.count,
+    );
+
+    scan_reply(
+        cursor,
+        batch
+            .into_iter()
+            .filter(|(key, _)| opts.matches(key))
+            .map(|(key, _)| Value::BulkString(Bytes::copy_from_slice(key)))
+            .collect(),
+    )
+}
+
+/// DBSIZE, counting only the keys in the client's namespace if it's confined to one.
+fn dbsize(db: &Keyspace, _: &[Vec<u8>]) -> Value {
+    let size = match namespace::current() {
+        Some(_) => db.visible().count(),
+        None => db.len(),
+    };
+
+    Value::Integer(size as i64)
+}
+
+fn touch(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let touched = args.iter().filter(|key| lookup(db, key).is_some()).count();
+
+    Value::Integer(touched as i64)
+}
+
+fn dump(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match lookup(db, &args[0]) {
+        Some(val) => Value::BulkString(dump_value(val.data()).into()),
+        None => Value::Null,
+    }
+}
+
+fn restore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut replace = false;
+    let mut abs_ttl = false;
+    let mut idle = None;
+    let mut freq = None;
+
+    let mut i = 3;
+    while i < args.len() {
+        match lower(&args[i]).as_str() {
+            "replace" => replace = true,
+            "absttl" => abs_ttl = true,
+            "idletime" => {
+                i += 1;
+                match args.get(i).and_then(|arg| parse_int::<i64>(arg)) {
+                    Some(secs) if secs >= 0 => idle = Some(Duration::from_secs(secs as u64)),
+                    Some(_) => return Value::error("ERR Invalid IDLETIME value, must be >= 0"),
+                    None => return syntax_error(),
+                }
+            }
+            "freq" => {
+                i += 1;
+                match args.get(i).and_then(|arg| parse_int::<i64>(arg)) {
+                    Some(count) => match u8::try_from(count) {
+                        Ok(count) => freq = Some(count),
+                        Err(_) => {
+                            return Value::error("ERR Invalid FREQ value, must be >= 0 and <= 255");
+                        }
+                    },
+                    None => return syntax_error(),
+                }
+            }
+            _ => return syntax_error(),
+        }
+        i += 1;
+    }
+
+    let ttl = match parse_int::<i64>(&args[1]) {
+        Some(ttl) if ttl >= 0 => ttl as u64,
+        Some(_) => return Value::error("ERR Invalid TTL value, must be >= 0"),
+        None => return not_an_integer(),
+    };
+
+    let Ok(data) = restore_value(&args[2]) else {
+        return Value::error("ERR DUMP payload version or checksum are wrong");
+    };
+
+    if !replace && lookup(db, &args[0]).is_some() {
+        return Value::error("BUSYKEY Target key name already exists.");
+    }
+
+    let expires_at = match (ttl, abs_ttl) {
+        (0, _) => None,
+        (deadline, true) => Some(deadline),
+        (ttl, false) => Some(unix_millis().saturating_add(ttl)),
+    };
+
+    if expires_at.is_some_and(|at| at <= unix_millis()) {
+        if db.remove(&args[0]).is_some() {
+            notify::emit(Class::Generic, "del", &args[0]);
+        }
+        return Value::SimpleString("OK".to_string());
+    }
+
+    let mut entry = DBData::new(data, expires_at);
+    if let Some(idle) = idle {
+        entry.set_idle(idle);
+    }
+    if let Some(freq) = freq {
+        entry.set_freq(freq);
+    }
+    db.insert(args[0].clone(), entry);
+    notify::emit(Class::Generic, "restore", &args[0]);
+
+    Value::SimpleString("OK".to_string())
+}
+
+/// Shared implementation of `EXPIRE`, `PEXPIRE`, `EXPIREAT` and `PEXPIREAT`. `unit` is the
+/// number of milliseconds in one unit of the time argument.
+fn expire_generic(
+    db: &mut Keyspace,
+    args: &[Vec<u8>],
+    command: &str,
+    unit: i64,
+    absolute: bool,
+) -> Value {
+    let Some(time) = parse_int::<i64>(&args[1]) else {
+        return not_an_integer();
+    };
+
+    let condition = match args.get(2) {
+        None => ExpireCondition::Always,
+        Some(arg) => match ExpireCondition::parse(arg) {
+            Some(condition) => condition,
+            None => {
+                return Value::error(format!(
+                    "ERR Unsupported option {}",
+                    String::from_utf8_lossy(arg)
+                ));
+            }
+        },
+    };
+
+    // Like Redis, a deadline before the epoch is just one that has passed
+    let now = unix_millis();
+    let base = if absolute { 0 } else { now as i64 };
+    let Some(at) = time.checked_mul(unit).and_then(|ms| ms.checked_add(base)) else {
+        return Value::error(format!("ERR invalid expire time in '{command}' command"));
+    };
+    let at = at.max(0) as u64;
+
+    let Some(val) = lookup(db, &args[0]) else {
+        return Value::Integer(0);
+    };
+    if !condition.allows(val.expires_at(), at) {
+        return Value::Integer(0);
+    }
+
+    if at <= now {
+        db.remove(&args[0]);
+        notify::emit(Class::Generic, "del", &args[0]);
+    } else {
+        db.set_expires_at(&args[0], Some(at));
+        notify::emit(Class::Generic, "expire", &args[0]);
+    }
+
+    Value::Integer(1)
+}
+
+fn expire(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    expire_generic(db, args, "expire", 1000, false)
+}
+
+fn pexpire(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    expire_generic(db, args, "pexpire", 1, false)
+}
+
+fn expireat(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    expire_generic(db, args, "expireat", 1000, true)
+}
+
+fn pexpireat(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    expire_generic(db, args, "pexpireat", 1, true)
+}
+
+fn persist(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match lookup(db, &args[0]) {
+        Some(val) if val.expires_at().is_some() => {
+            db.set_expires_at(&args[0], None);
+            notify::emit(Class::Generic, "persist", &args[0]);
+            Value::Integer(1)
+        }
+        _ => Value::Integer(0),
+    }
+}
+
+/// Shared implementation of `TTL`, `PTTL`, `EXPIRETIME` and `PEXPIRETIME`. Replies -2 for a
+/// missing key and -1 for one without a deadline; seconds are rounded to the nearest, as Redis
+/// does.
+fn ttl_generic(db: &Keyspace, args: &[Vec<u8>], unit: u64, absolute: bool) -> Value {
+    let Some(val) = db.get_shared(&args[0]) else {
+        return Value::Integer(-2);
+    };
+    let Some(at) = val.expires_at() else {
+        return Value::Integer(-1);
+    };
+
+    let ms = if absolute {
+        at
+    } else {
+        at.saturating_sub(unix_millis())
+    };
+    Value::Integer(((ms + unit / 2) / unit) as i64)
+}
+
+fn ttl(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    ttl_generic(db, args, 1000, false)
+}
+
+fn pttl(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    ttl_generic(db, args, 1, false)
+}
+
+fn expiretime(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    ttl_generic(db, args, 1000, true)
+}
+
+fn pexpiretime(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    ttl_generic(db, args, 1, true)
+}
+
+fn object(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(subcommand) = args.first().map(|arg| lower(arg)) else {
+        return wrong_args("object");
+    };
+
+    if subcommand == "help" {
+        return Value::Array(
+            [
+                "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "ENCODING <key>",
+                "    Return the kind of internal representation used in order to store the value",
+                "    associated with a <key>.",
+                "FREQ <key>",
+                "    Return the access frequency index of the <key>. The returned integer is",
+                "    proportional to the logarithm of the recent access frequency of the key.",
+                "IDLETIME <key>",
+                "    Return the idle time of the <key>, that is the approximated number of",
+                "    seconds elapsed since the last access to the key.",
+                "REFCOUNT <key>",
+                "    Return the number of references of the value associated with the specified",
+                "    <key>.",
+            ]
+            .into_iter()
+            .map(|line| Value::SimpleString(line.to_string()))
+            .collect(),
+        );
+    }
+
+    if args.len() != 2 {
+        return Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try OBJECT HELP."
+        ));
+    }
+
+    let Some(val) = peek(db, &args[1]) else {
+        return Value::Null;
+    };
+
+    match subcommand.as_str() {
+        "encoding" => Value::BulkString(Bytes::copy_from_slice(val.data().encoding().as_bytes())),
+        "idletime" => Value::Integer(val.idle().as_secs() as i64),
+        "refcount" => Value::Integer(1),
+        "freq" if evict::by_frequency() => Value::Integer(val.freq() as i64),
+        "freq" => Value::error(
+            "ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.",
+        ),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try OBJECT HELP."
+        )),
+    }
+}
+
+fn type_(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    let name = match db.get_shared(&args[0]) {
+        Some(val) => val.data().type_name(),
+        None => "none",
+    };
+
+    Value::SimpleString(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::registry::TestClient;
+    use crate::db::DBVal;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn bulk(reply: Value) -> String {
+        match reply {
+            Value::BulkString(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Value::SimpleString(s) => s,
+            reply => panic!("expected a string, got {reply:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn touching_counts_keys_and_resets_their_idle_time() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        let mut entry = DBData::new(DBVal::String(Bytes::from_static(b"v")), None);
+        entry.set_idle(Duration::from_secs(100));
+        db.insert(b"k".to_vec(), entry);
+
+        assert!(matches!(
+            object(db, &args(&["idletime", "k"])),
+            Value::Integer(100)
+        ));
+        assert!(matches!(
+            touch(db, &args(&["k", "missing", "k"])),
+            Value::Integer(2)
+        ));
+        assert!(matches!(
+            object(db, &args(&["idletime", "k"])),
+            Value::Integer(0)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dumps_and_restores_a_value() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        db.insert(
+            b"k".to_vec(),
+            DBData::new(DBVal::List(["a", "b"].map(|s| s.into()).into()), None),
+        );
+
+        let Value::BulkString(payload) = dump(db, &args(&["k"])) else {
+            panic!("DUMP replies with a payload");
+        };
+        let with = |parts: &[&str], payload: &[u8]| {
+            let mut args = args(parts);
+            args.push(payload.to_vec());
+            args
+        };
+        assert!(matches!(dump(db, &args(&["missing"])), Value::Null));
+
+        assert_eq!(
+            restore(db, &with(&["k", "0"], &payload)).error_message(),
+            Some("BUSYKEY Target key name already exists.".to_string())
+        );
+        assert!(matches!(
+            restore(db, &with(&["copy", "60000"], &payload)),
+            Value::SimpleString(_)
+        ));
+        let copy = db.get(b"copy".as_slice()).unwrap();
+        assert!(matches!(copy.data(), DBVal::List(items) if items.len() == 2));
+        assert!(copy.expires_at().is_some());
+
+        // A payload that doesn't check out is refused
+        let mut corrupt = payload.to_vec();
+        corrupt[0] ^= 1;
+        let bad = with(&["other", "0"], &corrupt);
+        assert!(restore(db, &bad).error_message().is_some());
+    }
+
+    #[tokio::test]
+    async fn moves_keys_between_databases() {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(2);
+        let mut dbs = storage.lock_all().await;
+        let mut run = |parts: &[&str]| client.run(&mut dbs, parts);
+
+        run(&["set", "k", "zero"]);
+        assert!(matches!(run(&["move", "k", "1"]), Value::Integer(1)));
+        assert!(matches!(run(&["get", "k"]), Value::Null));
+        assert_eq!(
+            run(&["move", "k", "0"]).error_message(),
+            Some("ERR source and destination objects are the same".to_string())
+        );
+        assert!(run(&["select", "2"]).error_message().is_some());
+
+        assert_eq!(bulk(run(&["select", "1"])), "OK");
+        assert_eq!(bulk(run(&["get", "k"])), "zero");
+        // A key already in the way isn't overwritten
+        run(&["select", "0"]);
+        run(&["set", "k", "new"]);
+        assert!(matches!(run(&["move", "k", "1"]), Value::Integer(0)));
+
+        // Swapping leaves the client where it was, looking at the other's keys
+        assert_eq!(bulk(run(&["swapdb", "0", "1"])), "OK");
+        assert_eq!(bulk(run(&["get", "k"])), "zero");
+    }
+
+    #[tokio::test]
+    async fn deletes_types_and_lists_keys() {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        client.run(&mut dbs, &["set", "s", "v"]);
+        client.run(&mut dbs, &["rpush", "l", "x"]);
+        client.run(&mut dbs, &["sadd", "other", "x"]);
+        let db = &mut dbs[0];
+
+        assert_eq!(bulk(type_(db, &args(&["l"]))), "list");
+        assert_eq!(bulk(type_(db, &args(&["missing"]))), "none");
+        let Value::Array(keys) = keys(db, &args(&["[ls]"])) else {
+            panic!("KEYS replies with an array");
+        };
+        let mut keys: Vec<_> = keys.into_iter().map(bulk).collect();
+        keys.sort();
+        assert_eq!(keys, ["l", "s"]);
+
+        assert!(matches!(
+            del(db, &args(&["s", "l", "missing"])),
+            Value::Integer(2)
+        ));
+        assert!(matches!(dbsize(db, &[]), Value::Integer(1)));
+    }
+
+    #[tokio::test]
+    async fn counts_and_flushes_each_database_on_its_own() {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(2);
+        let mut dbs = storage.lock_all().await;
+        let mut run = |parts: &[&str]| client.run(&mut dbs, parts);
+
+        run(&["set", "k", "zero"]);
+        run(&["select", "1"]);
+        run(&["set", "k", "one"]);
+        run(&["set", "other", "one"]);
+        assert!(matches!(run(&["dbsize"]), Value::Integer(2)));
+        assert_eq!(bulk(run(&["flushdb"])), "OK");
+        assert!(matches!(run(&["dbsize"]), Value::Integer(0)));
+        run(&["select", "0"]);
+        assert!(matches!(run(&["dbsize"]), Value::Integer(1)));
+    }
+
+    #[tokio::test]
+    async fn sets_reads_and_drops_deadlines() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        let value = || DBData::new(DBVal::String(Bytes::from_static(b"v")), None);
+        db.insert(b"k".to_vec(), value());
+        db.insert(b"other".to_vec(), value());
+
+        assert!(matches!(ttl(db, &args(&["k"])), Value::Integer(-1)));
+        assert!(matches!(ttl(db, &args(&["missing"])), Value::Integer(-2)));
+        assert!(matches!(
+            expire(db, &args(&["missing", "100"])),
+            Value::Integer(0)
+        ));
+
+        // The deadline is filed where the expire cycle finds it
+        assert!(matches!(
+            expire(db, &args(&["k", "100"])),
+            Value::Integer(1)
+        ));
+        let at = db.get(b"k".as_slice()).unwrap().expires_at().unwrap();
+        assert_eq!(db.next_deadline(), Some(at));
+        assert!(matches!(ttl(db, &args(&["k"])), Value::Integer(100)));
+        assert!(
+            matches!(pttl(db, &args(&["k"])), Value::Integer(ms) if ms > 99_000 && ms <= 100_000)
+        );
+        assert!(matches!(pexpiretime(db, &args(&["k"])), Value::Integer(ms) if ms as u64 == at));
+
+        // GT only pushes a deadline back, NX only sets one where there is none
+        assert!(matches!(
+            pexpire(db, &args(&["k", "1000", "gt"])),
+            Value::Integer(0)
+        ));
+        assert!(matches!(
+            expire(db, &args(&["other", "50", "nx"])),
+            Value::Integer(1)
+        ));
+        assert!(
+            expire(db, &args(&["k", "1", "sometimes"]))
+                .error_message()
+                .is_some()
+        );
+        assert!(matches!(
+            expireat(db, &args(&["k", "4102444800"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(
+            expiretime(db, &args(&["k"])),
+            Value::Integer(4102444800)
+        ));
+        assert!(db.next_deadline() < Some(4102444800000));
+
+        assert!(matches!(persist(db, &args(&["other"])), Value::Integer(1)));
+        assert!(matches!(persist(db, &args(&["other"])), Value::Integer(0)));
+        assert_eq!(db.next_deadline(), Some(4102444800000));
+
+        // A deadline already past deletes the key there and then
+        assert!(matches!(
+            pexpireat(db, &args(&["k", "1"])),
+            Value::Integer(1)
+        ));
+        assert!(db.get(b"k".as_slice()).is_none());
+        assert_eq!(db.next_deadline(), None);
+    }
+}
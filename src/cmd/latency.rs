@@ -0,0 +1,32 @@
+use crate::cmd::{lower, registry::Spec};
+use crate::latency;
+use crate::resp::Value;
+
+/// LATENCY itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("latency", -2, [0, 0, 0], |_, args| {
+    latency(args)
+})];
+
+/// LATENCY LATEST | HISTORY event | RESET [event ...] | HISTOGRAM [command ...]
+pub fn latency(args: &[Vec<u8>]) -> Value {
+    let subcommand = lower(&args[0]);
+
+    match (subcommand.as_str(), &args[1..]) {
+        ("latest", []) => latency::latest(),
+        ("history", [event]) => latency::history(&lower(event)),
+        ("reset", events) => {
+            let events: Vec<String> = events.iter().map(|event| lower(event)).collect();
+            Value::Integer(latency::reset(&events) as i64)
+        }
+        ("histogram", names) => {
+            let names: Vec<String> = names.iter().map(|name| lower(name)).collect();
+            latency::histograms(&names)
+        }
+        ("latest" | "history", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try LATENCY HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try LATENCY HELP."
+        )),
+    }
+}
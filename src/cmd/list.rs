@@ -0,0 +1,586 @@
+use crate::blocking::{Block, Outcome};
+use crate::cmd::{
+    lookup, lower, normalize_range, not_an_integer, parse_int, parse_timeout, read, registry::Spec,
+    syntax_error, wrong_args, wrong_type,
+};
+use crate::db::{DBData, DBVal, Keyspace};
+use crate::notify::{self, Class};
+use crate::resp::Value;
+use std::collections::VecDeque;
+
+/// The list commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("lpush", -3, [1, 1, 1], lpush),
+    Spec::keyspace("rpush", -3, [1, 1, 1], rpush),
+    Spec::keyspace("lpop", -2, [1, 1, 1], lpop).at_most(3),
+    Spec::keyspace("rpop", -2, [1, 1, 1], rpop).at_most(3),
+    Spec::read("lrange", 4, [1, 1, 1], lrange),
+    Spec::read("llen", 2, [1, 1, 1], llen),
+    Spec::keyspace("linsert", 5, [1, 1, 1], linsert),
+    Spec::keyspace("lset", 4, [1, 1, 1], lset),
+    Spec::keyspace("lrem", 4, [1, 1, 1], lrem),
+    Spec::keyspace("ltrim", 4, [1, 1, 1], ltrim),
+    Spec::keyspace("lpos", -3, [1, 1, 1], lpos),
+    Spec::keyspace("lmove", 5, [1, 2, 1], lmove),
+    Spec::keyspace("rpoplpush", 3, [1, 2, 1], rpoplpush),
+    Spec::blocking("blpop", -3, [1, -2, 1], |_, args| blpop(args)),
+    Spec::blocking("brpop", -3, [1, -2, 1], |_, args| brpop(args)),
+    Spec::blocking("blmove", 6, [1, 2, 1], |_, args| blmove(args)),
+    Spec::blocking("brpoplpush", 4, [1, 2, 1], |_, args| brpoplpush(args)),
+];
+
+/// Fetches the list stored at `key`. `Ok(None)` means the key doesn't exist.
+fn get_list<'a>(
+    db: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut VecDeque<Vec<u8>>>, Value> {
+    match lookup(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data_mut() {
+            DBVal::List(list) => Ok(Some(list)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+/// Like [`get_list`], for a command that only reads.
+fn read_list<'a>(db: &'a Keyspace, key: &[u8]) -> Result<Option<&'a VecDeque<Vec<u8>>>, Value> {
+    match read(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data() {
+            DBVal::List(list) => Ok(Some(list)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+fn get_or_create_list<'a>(
+    db: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<&'a mut VecDeque<Vec<u8>>, Value> {
+    if get_list(db, key)?.is_none() {
+        db.insert(
+            key.to_vec(),
+            DBData::new(DBVal::List(VecDeque::new()), None),
+        );
+    }
+
+    Ok(get_list(db, key)?.expect("list was just created"))
+}
+
+/// Drops `key` if popping left its list empty, since Redis never keeps empty collections.
+fn remove_if_empty(db: &mut Keyspace, key: &[u8]) {
+    if let Some(DBVal::List(list)) = db.get(key).map(|val| val.data())
+        && list.is_empty()
+    {
+        db.remove(key);
+        notify::emit(Class::Generic, "del", key);
+    }
+}
+
+fn push(db: &mut Keyspace, args: &[Vec<u8>], command: &str, left: bool) -> Value {
+    if args.len() < 2 {
+        return wrong_args(command);
+    }
+
+    let list = match get_or_create_list(db, &args[0]) {
+        Ok(list) => list,
+        Err(e) => return e,
+    };
+
+    for element in &args[1..] {
+        if left {
+            list.push_front(element.clone());
+        } else {
+            list.push_back(element.clone());
+        }
+    }
+    notify::emit(Class::List, command, &args[0]);
+
+    Value::Integer(list.len() as i64)
+}
+
+fn lpush(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    push(db, args, "lpush", true)
+}
+
+fn rpush(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    push(db, args, "rpush", false)
+}
+
+fn pop(db: &mut Keyspace, args: &[Vec<u8>], command: &str, left: bool) -> Value {
+    let count = match args.get(1).map(|arg| parse_int::<i64>(arg)) {
+        None => None,
+        Some(Some(n)) if n >= 0 => Some(n as usize),
+        Some(_) => return Value::error("ERR value is out of range, must be positive"),
+    };
+
+    let list = match get_list(db, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return Value::NullArray,
+        Ok(None) => return Value::Null,
+        Err(e) => return e,
+    };
+
+    let mut popped = Vec::new();
+    for _ in 0..count.unwrap_or(1) {
+        let element = if left {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+
+        match element {
+            Some(element) => popped.push(Value::BulkString(element.into())),
+            None => break,
+        }
+    }
+
+    if !popped.is_empty() {
+        notify::emit(Class::List, command, &args[0]);
+    }
+    remove_if_empty(db, &args[0]);
+
+    match count {
+        Some(_) => Value::Array(popped),
+        None => popped.pop().unwrap_or(Value::Null),
+    }
+}
+
+fn lpop(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    pop(db, args, "lpop", true)
+}
+
+fn rpop(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    pop(db, args, "rpop", false)
+}
+
+fn lrange(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    let (Some(start), Some(stop)) = (parse_int::<i64>(&args[1]), parse_int::<i64>(&args[2])) else {
+        return not_an_integer();
+    };
+
+    let list = match read_list(db, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Value::Array(Vec::new()),
+        Err(e) => return e,
+    };
+
+    match normalize_range(start, stop, list.len()) {
+        Some((start, stop)) => Value::Array(
+            list.range(start..=stop)
+                .map(|element| Value::BulkString(element.clone().into()))
+                .collect(),
+        ),
+        None => Value::Array(Vec::new()),
+    }
+}
+
+fn llen(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    match read_list(db, &args[0]) {
+        Ok(Some(list)) => Value::Integer(list.len() as i64),
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+fn linsert(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let after = match lower(&args[1]).as_str() {
+        "before" => false,
+        "after" => true,
+        _ => return syntax_error(),
+    };
+
+    let list = match get_list(db, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Value::Integer(0),
+        Err(e) => return e,
+    };
+
+    match list.iter().position(|element| *element == args[2]) {
+        Some(pos) => {
+            list.insert(if after { pos + 1 } else { pos }, args[3].clone());
+            let len = list.len();
+            notify::emit(Class::List, "linsert", &args[0]);
+
+            Value::Integer(len as i64)
+        }
+        None => Value::Integer(-1),
+    }
+}
+
+fn lset(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(index) = parse_int::<i64>(&args[1]) else {
+        return not_an_integer();
+    };
+
+    let list = match get_list(db, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Value::error("ERR no such key"),
+        Err(e) => return e,
+    };
+
+    let len = list.len() as i64;
+    let index = if index < 0 { len + index } else { index };
+    if !(0..len).contains(&index) {
+        return Value::error("ERR index out of range");
+    }
+
+    list[index as usize] = args[2].clone();
+    notify::emit(Class::List, "lset", &args[0]);
+
+    Value::SimpleString("OK".to_string())
+}
+
+fn lrem(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(count) = parse_int::<i64>(&args[1]) else {
+        return not_an_integer();
+    };
+
+    let list = match get_list(db, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Value::Integer(0),
+        Err(e) => return e,
+    };
+
+    let limit = if count == 0 {
+        usize::MAX
+    } else {
+        count.unsigned_abs() as usize
+    };
+
+    let mut removed = 0;
+    if count >= 0 {
+        list.retain(|element| {
+            let remove = removed < limit && *element == args[2];
+            removed += remove as usize;
+            !remove
+        });
+    } else {
+        let mut i = list.len();
+        while i > 0 && removed < limit {
+            i -= 1;
+            if list[i] == args[2] {
+                list.remove(i);
+                removed += 1;
+            }
+        }
+    }
+
+    if removed > 0 {
+        notify::emit(Class::List, "lrem", &args[0]);
+    }
+    remove_if_empty(db, &args[0]);
+
+    Value::Integer(removed as i64)
+}
+
+fn ltrim(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let (Some(start), Some(stop)) = (parse_int::<i64>(&args[1]), parse_int::<i64>(&args[2])) else {
+        return not_an_integer();
+    };
+
+    let list = match get_list(db, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Value::SimpleString("OK".to_string()),
+        Err(e) => return e,
+    };
+
+    match normalize_range(start, stop, list.len()) {
+        Some((start, stop)) => {
+            list.truncate(stop + 1);
+            list.drain(..start);
+        }
+        None => list.clear(),
+    }
+
+    notify::emit(Class::List, "ltrim", &args[0]);
+    remove_if_empty(db, &args[0]);
+
+    Value::SimpleString("OK".to_string())
+}
+
+fn lpos(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut rank: i64 = 1;
+    let mut count = None;
+    let mut max_len = 0;
+
+    let mut i = 2;
+    while i < args.len() {
+        let option = lower(&args[i]);
+        let Some(n) = args.get(i + 1).map(|arg| parse_int::<i64>(arg)) else {
+            return syntax_error();
+        };
+        let Some(n) = n else {
+            return not_an_integer();
+        };
+
+        match option.as_str() {
+            "rank" if n == 0 => {
+                return Value::error(
+                    "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list",
+                );
+            }
+            "rank" => rank = n,
+            "count" if n < 0 => return Value::error("ERR COUNT can't be negative"),
+            "count" => count = Some(n as usize),
+            "maxlen" if n < 0 => return Value::error("ERR MAXLEN can't be negative"),
+            "maxlen" => max_len = n as usize,
+            _ => return syntax_error(),
+        }
+        i += 2;
+    }
+
+    let list = match get_list(db, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return Value::Array(Vec::new()),
+        Ok(None) => return Value::Null,
+        Err(e) => return e,
+    };
+
+    let limit = if max_len == 0 { list.len() } else { max_len };
+    let wanted = match count {
+        Some(0) => usize::MAX,
+        Some(n) => n,
+        None => 1,
+    };
+    let mut skip = rank.unsigned_abs() as usize - 1;
+
+    let positions: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+        Box::new(0..list.len())
+    } else {
+        Box::new((0..list.len()).rev())
+    };
+
+    let mut matches = Vec::new();
+    for pos in positions.take(limit) {
+        if list[pos] != args[1] {
+            continue;
+        }
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+
+        matches.push(Value::Integer(pos as i64));
+        if matches.len() >= wanted {
+            break;
+        }
+    }
+
+    match count {
+        Some(_) => Value::Array(matches),
+        None => matches.pop().unwrap_or(Value::Null),
+    }
+}
+
+fn parse_side(arg: &[u8]) -> Option<bool> {
+    match lower(arg).as_str() {
+        "left" => Some(true),
+        "right" => Some(false),
+        _ => None,
+    }
+}
+
+/// Pops from one end of `src` and pushes onto one end of `dst`, which may be the same list.
+/// Returns the moved element, or `None` if `src` doesn't exist.
+fn move_element(
+    db: &mut Keyspace,
+    src: &[u8],
+    dst: &[u8],
+    from_left: bool,
+    to_left: bool,
+) -> Result<Option<Vec<u8>>, Value> {
+    let Some(list) = get_list(db, src)? else {
+        return Ok(None);
+    };
+    if list.is_empty() {
+        return Ok(None);
+    }
+
+    // Type-check the destination before popping so a WRONGTYPE leaves `src` untouched
+    get_list(db, dst)?;
+
+    let list = get_list(db, src)?.expect("source list exists");
+    let element = if from_left {
+        list.pop_front()
+    } else {
+        list.pop_back()
+    }
+    .expect("source list is non-empty");
+    notify::emit(Class::List, if from_left { "lpop" } else { "rpop" }, src);
+
+    let dst_list = get_or_create_list(db, dst)?;
+    if to_left {
+        dst_list.push_front(element.clone());
+    } else {
+        dst_list.push_back(element.clone());
+    }
+    notify::emit(Class::List, if to_left { "lpush" } else { "rpush" }, dst);
+
+    // Only now, so rotating a single-element list in place keeps the key and its TTL
+    remove_if_empty(db, src);
+
+    Ok(Some(element))
+}
+
+fn lmove(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let (Some(from_left), Some(to_left)) = (parse_side(&args[2]), parse_side(&args[3])) else {
+        return syntax_error();
+    };
+
+    match move_element(db, &args[0], &args[1], from_left, to_left) {
+        Ok(Some(element)) => Value::BulkString(element.into()),
+        Ok(None) => Value::Null,
+        Err(e) => e,
+    }
+}
+
+fn rpoplpush(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match move_element(db, &args[0], &args[1], false, true) {
+        Ok(Some(element)) => Value::BulkString(element.into()),
+        Ok(None) => Value::Null,
+        Err(e) => e,
+    }
+}
+
+/// BLPOP and BRPOP: pops from the first non-empty list, replying `[key, element]`.
+fn blocking_pop(args: &[Vec<u8>], command: &str, left: bool) -> Outcome {
+    if args.len() < 2 {
+        return wrong_args(command).into();
+    }
+
+    let (keys, timeout) = args.split_at(args.len() - 1);
+    let deadline = match parse_timeout(&timeout[0]) {
+        Ok(deadline) => deadline,
+        Err(e) => return e.into(),
+    };
+
+    let keys = keys.to_vec();
+    Outcome::Block(Block::new(
+        keys.clone(),
+        deadline,
+        Value::NullArray,
+        move |db| {
+            for key in &keys {
+                let list = match get_list(db, key) {
+                    Ok(Some(list)) => list,
+                    Ok(None) => continue,
+                    Err(e) => return Some(e),
+                };
+
+                let element = if left {
+                    list.pop_front()
+                } else {
+                    list.pop_back()
+                };
+
+                if let Some(element) = element {
+                    notify::emit(Class::List, if left { "lpop" } else { "rpop" }, key);
+                    remove_if_empty(db, key);
+
+                    return Some(Value::Array(vec![
+                        Value::BulkString(key.clone().into()),
+                        Value::BulkString(element.into()),
+                    ]));
+                }
+            }
+
+            None
+        },
+    ))
+}
+
+fn blpop(args: &[Vec<u8>]) -> Outcome {
+    blocking_pop(args, "blpop", true)
+}
+
+fn brpop(args: &[Vec<u8>]) -> Outcome {
+    blocking_pop(args, "brpop", false)
+}
+
+fn blocking_move(args: &[Vec<u8>], from_left: bool, to_left: bool, timeout: &[u8]) -> Outcome {
+    let deadline = match parse_timeout(timeout) {
+        Ok(deadline) => deadline,
+        Err(e) => return e.into(),
+    };
+
+    let (src, dst) = (args[0].clone(), args[1].clone());
+    Outcome::Block(Block::new(
+        vec![src.clone()],
+        deadline,
+        Value::Null,
+        move |db| match move_element(db, &src, &dst, from_left, to_left) {
+            Ok(Some(element)) => Some(Value::BulkString(element.into())),
+            Ok(None) => None,
+            Err(e) => Some(e),
+        },
+    ))
+}
+
+fn blmove(args: &[Vec<u8>]) -> Outcome {
+    let (Some(from_left), Some(to_left)) = (parse_side(&args[2]), parse_side(&args[3])) else {
+        return syntax_error().into();
+    };
+
+    blocking_move(args, from_left, to_left, &args[4])
+}
+
+fn brpoplpush(args: &[Vec<u8>]) -> Outcome {
+    blocking_move(args, false, true, &args[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn elements(reply: Value) -> Vec<Vec<u8>> {
+        let Value::Array(values) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        values
+            .into_iter()
+            .map(|value| match value {
+                Value::BulkString(bytes) => bytes.into(),
+                value => panic!("expected a bulk string, got {value:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn push_range_and_pop() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            lpush(db, &args(&["l", "b", "a"])),
+            Value::Integer(2)
+        ));
+        assert!(matches!(rpush(db, &args(&["l", "c"])), Value::Integer(3)));
+        assert_eq!(
+            elements(lrange(db, &args(&["l", "0", "-1"]))),
+            args(&["a", "b", "c"])
+        );
+        assert!(matches!(lpop(db, &args(&["l"])), Value::BulkString(a) if a == b"a"[..]));
+        assert!(matches!(llen(db, &args(&["l"])), Value::Integer(2)));
+    }
+
+    #[tokio::test]
+    async fn blocking_pop_serves_at_once_when_it_can() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        rpush(db, &args(&["l", "x"]));
+        let Outcome::Block(block) = blpop(&args(&["l", "0"])) else {
+            panic!("BLPOP always goes through a block");
+        };
+        assert_eq!(elements(block.attempt_now(db)), args(&["l", "x"]));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::cmd::{lower, parse_int, registry::Spec};
+use crate::rand;
+use crate::resp::Value;
+use std::f64::consts::PI;
+
+/// LOLWUT itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("lolwut", -1, [0, 0, 0], |_, args| {
+    lolwut(args)
+})];
+
+/// LOLWUT [VERSION version [columns squares-per-row squares-per-col]]: computer art. Version 5,
+/// the default, draws Georg Nees' Schotter; other versions only say which server this is.
+pub fn lolwut(args: &[Vec<u8>]) -> Value {
+    let version_line = format!("Redis ver. {}\n", env!("CARGO_PKG_VERSION"));
+
+    let (version, rest) = match args {
+        [] => (5, &args[..0]),
+        [option, version, rest @ ..] if lower(option) == "version" => {
+            match parse_int::<i64>(version) {
+                Some(version) => (version, rest),
+                None => return Value::error("ERR value is not an integer or out of range"),
+            }
+        }
+        _ => return Value::error("ERR syntax error"),
+    };
+    if version != 5 {
+        return Value::BulkString(version_line.into());
+    }
+
+    let mut params = [66, 8, 12];
+    for (param, arg) in params.iter_mut().zip(rest) {
+        match parse_int(arg) {
+            Some(n) => *param = n,
+            None => return Value::error("ERR value is not an integer or out of range"),
+        }
+    }
+    let [columns, per_row, per_col] = params;
+    let columns = columns.clamp(1, 1000);
+    let per_row = per_row.clamp(1, 200);
+    let per_col = per_col.clamp(1, 200);
+
+    let mut art = schotter(columns, per_row, per_col).render();
+    art.push_str("\nGeorg Nees - schotter, plotter on paper, 1968. ");
+    art.push_str(&version_line);
+
+    Value::BulkString(art.into())
+}
+
+/// Rows of squares that start out neat and get more disordered towards the bottom, drawn to
+/// fill `columns` characters across.
+fn schotter(columns: usize, per_row: usize, per_col: usize) -> Canvas {
+    // Each braille character is two dots wide
+    let width = columns * 2;
+    let padding = if width > 4 { 2 } else { 0 };
+    let side = (width - padding * 2) as f64 / per_row as f64;
+    let height = (side * per_col as f64) as usize + padding * 2;
+    let mut canvas = Canvas::new(width, height);
+
+    // A random number in -1..1, scaled by how far down the square is
+    let jitter = |row: usize| {
+        let r = rand::next_u64() as f64 / u64::MAX as f64 / per_col as f64 * row as f64;
+        if rand::below(2) == 1 { -r } else { r }
+    };
+
+    for row in 0..per_col {
+        for column in 0..per_row {
+            let mut x = column as f64 * side + side / 2.0 + padding as f64;
+            let mut y = row as f64 * side + side / 2.0 + padding as f64;
+            let mut angle = 0.0;
+            // The first two rows are left straight
+            if row > 1 {
+                angle = jitter(row);
+                x += jitter(row) * side / 3.0;
+                y += jitter(row) * side / 3.0;
+            }
+            canvas.square(x, y, side, angle);
+        }
+    }
+
+    canvas
+}
+
+/// A grid of dots, shown as braille characters that each hold a 2x4 block of them.
+struct Canvas {
+    width: usize,
+    height: usize,
+    dots: Vec<bool>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            dots: vec![false; width * height],
+        }
+    }
+
+    fn set(&mut self, x: i64, y: i64) {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            self.dots[y as usize * self.width + x as usize] = true;
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.dots[y * self.width + x]
+    }
+
+    /// Bresenham's line from one point to another.
+    fn line(&mut self, (mut x0, mut y0): (i64, i64), (x1, y1): (i64, i64)) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+
+        loop {
+            self.set(x0, y0);
+            if x0 == x1 && y0 == y1 {
+                break;
+            }
+            let e2 = err * 2;
+            if e2 >= dy {
+                err += dy;
+                x0 += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y0 += sy;
+            }
+        }
+    }
+
+    /// The outline of a square centred on `(x, y)`, turned `angle` radians.
+    fn square(&mut self, x: f64, y: f64, side: f64, angle: f64) {
+        // The corners sit half a diagonal from the centre
+        let radius = (side / 2.0_f64.sqrt()).round();
+        let corners: Vec<(i64, i64)> = (0..4)
+            .map(|i| {
+                let k = PI / 4.0 + angle + PI / 2.0 * i as f64;
+                (
+                    (k.sin() * radius + x).round() as i64,
+                    (k.cos() * radius + y).round() as i64,
+                )
+            })
+            .collect();
+
+        for i in 0..4 {
+            self.line(corners[i], corners[(i + 1) % 4]);
+        }
+    }
+
+    fn render(&self) -> String {
+        // Which bit of a braille character stands for each dot of its 2x4 block
+        const BITS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let mut text = String::new();
+        for top in (0..self.height).step_by(4) {
+            for left in (0..self.width).step_by(2) {
+                let mut bits = 0;
+                for (dy, row) in BITS.iter().enumerate() {
+                    for (dx, bit) in row.iter().enumerate() {
+                        if self.get(left + dx, top + dy) {
+                            bits |= bit;
+                        }
+                    }
+                }
+                text.push(char::from_u32(0x2800 + bits).expect("braille block"));
+            }
+            text.push('\n');
+        }
+
+        text
+    }
+}
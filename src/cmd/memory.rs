@@ -0,0 +1,431 @@
+use crate::alloc;
+use crate::cmd::info::resident_bytes;
+use crate::cmd::{lower, parse_int, peek, registry::Spec};
+use crate::db::{DBData, DBVal, Keyspace};
+use crate::rand;
+use crate::resp::Value;
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+/// MEMORY itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("memory", -2, [2, 2, 1], |ctx, args| {
+    memory(ctx.dbs, ctx.client.db, args)
+})];
+
+/// How many elements of a collection MEMORY USAGE looks at by default before extrapolating.
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// How many of the biggest keys MEMORY DOCTOR names.
+const BIGGEST_KEYS: usize = 5;
+
+/// MEMORY USAGE key [SAMPLES count] | STATS | DOCTOR | PURGE | ANALYZE [SAMPLE count]
+pub fn memory(dbs: &mut [Keyspace], selected: usize, args: &[Vec<u8>]) -> Value {
+    let subcommand = lower(&args[0]);
+
+    match (subcommand.as_str(), &args[1..]) {
+        ("usage", [key, rest @ ..]) => {
+            let samples = match rest {
+                [] => DEFAULT_SAMPLES,
+                [option, count] if lower(option) == "samples" => match parse_int::<usize>(count) {
+                    // 0 looks at every element
+                    Some(0) => usize::MAX,
+                    Some(count) => count,
+                    None => return Value::error("ERR value is out of range, must be positive"),
+                },
+                _ => return Value::error("ERR syntax error"),
+            };
+
+            match peek(&mut dbs[selected], key) {
+                Some(val) => Value::Integer(key_bytes(key, val, samples) as i64),
+                None => Value::Null,
+            }
+        }
+        ("stats", []) => stats(dbs),
+        ("analyze", []) => analyze(&dbs[selected], None),
+        ("analyze", [option, count]) if lower(option) == "sample" => {
+            match parse_int::<usize>(count) {
+                Some(count) => analyze(&dbs[selected], Some(count)),
+                None => Value::error("ERR value is out of range, must be positive"),
+            }
+        }
+        ("doctor", []) => Value::BulkString(doctor(dbs).into()),
+        ("purge", []) => {
+            alloc::purge();
+            Value::SimpleString("OK".to_string())
+        }
+        ("usage" | "stats" | "doctor" | "purge" | "analyze", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try MEMORY HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try MEMORY HELP."
+        )),
+    }
+}
+
+/// MEMORY STATS: what the keyspace takes up overall, per database and per type.
+fn stats(dbs: &[Keyspace]) -> Value {
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    let int = |n: usize| Value::Integer(n as i64);
+
+    let rss = resident_bytes() as usize;
+    let mut overhead = 0;
+    let mut dataset = 0;
+    let mut keys = 0;
+    let mut by_db = Vec::new();
+    let mut by_type: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for (index, db) in dbs.iter().enumerate() {
+        if db.is_empty() {
+            continue;
+        }
+
+        let table = table_bytes(db);
+        overhead += table;
+        keys += db.len();
+        for (key, val) in db.iter() {
+            let bytes = data_bytes(key, val, DEFAULT_SAMPLES);
+            dataset += bytes;
+            let (count, total) = by_type.entry(val.data().type_name()).or_default();
+            *count += 1;
+            *total += bytes;
+        }
+        by_db.push((
+            bulk(&format!("db.{index}")),
+            Value::Map(vec![(bulk("overhead.hashtable.main"), int(table))]),
+        ));
+    }
+
+    let total = overhead + dataset;
+    let percentage = |part: usize, of: usize| match of {
+        0 => 0.0,
+        of => part as f64 * 100.0 / of as f64,
+    };
+
+    let mut fields = vec![
+        (bulk("total.allocated"), int(total)),
+        (bulk("overhead.total"), int(overhead)),
+        (bulk("keys.count"), int(keys)),
+        (
+            bulk("keys.bytes-per-key"),
+            int(total.checked_div(keys).unwrap_or_default()),
+        ),
+        (bulk("dataset.bytes"), int(dataset)),
+        (
+            bulk("dataset.percentage"),
+            bulk(&format!("{:.2}", percentage(dataset, total))),
+        ),
+        (bulk("rss.bytes"), int(rss)),
+        (
+            bulk("fragmentation"),
+            bulk(&format!("{:.2}", rss as f64 / total.max(1) as f64)),
+        ),
+    ];
+    if let Some(allocator) = alloc::stats() {
+        let (allocated, active, resident) = (
+            allocator.allocated as usize,
+            allocator.active as usize,
+            allocator.resident as usize,
+        );
+        let ratio = |of: usize, to: usize| bulk(&format!("{:.2}", of as f64 / to.max(1) as f64));
+        fields.extend([
+            (bulk("allocator.allocated"), int(allocated)),
+            (bulk("allocator.active"), int(active)),
+            (bulk("allocator.resident"), int(resident)),
+            (
+                bulk("allocator-fragmentation.ratio"),
+                ratio(active, allocated),
+            ),
+            (
+                bulk("allocator-fragmentation.bytes"),
+                int(active.saturating_sub(allocated)),
+            ),
+            (bulk("allocator.rss-ratio"), ratio(resident, active)),
+            (
+                bulk("allocator.rss-bytes"),
+                int(resident.saturating_sub(active)),
+            ),
+            (bulk("rss-overhead.ratio"), ratio(rss, resident)),
+            (
+                bulk("rss-overhead.bytes"),
+                int(rss.saturating_sub(resident)),
+            ),
+        ]);
+    }
+    fields.extend(by_db);
+    for (type_name, (count, bytes)) in by_type {
+        fields.push((
+            bulk(&format!("type.{type_name}")),
+            Value::Map(vec![
+                (bulk("keys"), int(count)),
+                (bulk("bytes"), int(bytes)),
+            ]),
+        ));
+    }
+
+    Value::Map(fields)
+}
+
+/// How long keys have left to live, as MEMORY ANALYZE groups them: the names of the groups,
+/// each with the most milliseconds its keys have left.
+const TTL_GROUPS: &[(&str, u64)] = &[
+    ("under-1m", 60_000),
+    ("under-1h", 3_600_000),
+    ("under-1d", 86_400_000),
+    ("under-7d", 7 * 86_400_000),
+    ("over-7d", u64::MAX),
+];
+
+/// What one type's keys add up to in MEMORY ANALYZE.
+#[derive(Default)]
+struct TypeSummary<'a> {
+    keys: usize,
+    /// Bytes for strings, and elements for collections.
+    size: usize,
+    bytes: usize,
+    /// The key with the biggest size, and that size.
+    biggest: Option<(&'a [u8], usize)>,
+    /// The key taking up the most memory, and how much.
+    heaviest: Option<(&'a [u8], usize)>,
+}
+
+/// MEMORY ANALYZE [SAMPLE count]: the selected database's keys, or `count` of them picked at
+/// random, summed up by type with the biggest key of each by size and by memory, along with
+/// how long they have left to live, for `cli --bigkeys` and `--memkeys`.
+fn analyze(db: &Keyspace, sample: Option<usize>) -> Value {
+    let bulk = |s: &[u8]| Value::BulkString(Bytes::copy_from_slice(s));
+    let int = |n: usize| Value::Integer(n as i64);
+
+    let mut picked = match sample {
+        Some(count) => rand::sample_indexes(db.len(), count as i64),
+        None => (0..db.len()).collect(),
+    };
+    picked.sort_unstable();
+    let mut picked = picked.into_iter().peekable();
+
+    let mut by_type: BTreeMap<&str, TypeSummary> = BTreeMap::new();
+    let mut persistent = 0;
+    let mut ttls = vec![0; TTL_GROUPS.len()];
+    for (index, (key, val)) in db.iter().enumerate() {
+        if picked.next_if_eq(&index).is_none() || val.is_expired() {
+            continue;
+        }
+        let size = match val.data() {
+            DBVal::String(s) => s.len(),
+            DBVal::Int(n) => n.to_string().len(),
+            DBVal::List(list) => list.len(),
+            DBVal::Hash(hash) => hash.len(),
+            DBVal::Set(set) => set.len(),
+            DBVal::ZSet(zset) => zset.len(),
+            DBVal::Stream(stream) => stream.len(),
+        };
+        let bytes = key_bytes(key, val, DEFAULT_SAMPLES);
+        let summary = by_type.entry(val.data().type_name()).or_default();
+        summary.keys += 1;
+        summary.size += size;
+        summary.bytes += bytes;
+        if summary.biggest.is_none_or(|(_, biggest)| size > biggest) {
+            summary.biggest = Some((key, size));
+        }
+        if summary
+            .heaviest
+            .is_none_or(|(_, heaviest)| bytes > heaviest)
+        {
+            summary.heaviest = Some((key, bytes));
+        }
+        match val.ttl() {
+            None => persistent += 1,
+            Some(ttl) => {
+                let group = TTL_GROUPS.iter().position(|&(_, under)| ttl < under);
+                ttls[group.unwrap_or(TTL_GROUPS.len() - 1)] += 1;
+            }
+        }
+    }
+
+    let keys = by_type.values().map(|summary| summary.keys).sum();
+    let types = by_type
+        .into_iter()
+        .map(|(type_name, summary)| {
+            let (biggest, size) = summary.biggest.unwrap_or_default();
+            let (heaviest, bytes) = summary.heaviest.unwrap_or_default();
+            let fields = vec![
+                (bulk(b"keys"), int(summary.keys)),
+                (bulk(b"size"), int(summary.size)),
+                (bulk(b"bytes"), int(summary.bytes)),
+                (
+                    bulk(b"biggest"),
+                    Value::Array(vec![bulk(biggest), int(size)]),
+                ),
+                (
+                    bulk(b"heaviest"),
+                    Value::Array(vec![bulk(heaviest), int(bytes)]),
+                ),
+            ];
+            (bulk(type_name.as_bytes()), Value::Map(fields))
+        })
+        .collect();
+    let mut ttl = vec![(bulk(b"persistent"), int(persistent))];
+    for (&(name, _), count) in TTL_GROUPS.iter().zip(ttls) {
+        ttl.push((bulk(name.as_bytes()), int(count)));
+    }
+
+    Value::Map(vec![
+        (bulk(b"keys"), int(keys)),
+        (bulk(b"types"), Value::Map(types)),
+        (bulk(b"ttl"), Value::Map(ttl)),
+    ])
+}
+
+/// MEMORY DOCTOR: a report on anything that looks off, and the keys taking up the most room,
+/// not counting their slots in the keyspace.
+fn doctor(dbs: &[Keyspace]) -> String {
+    let mut biggest: Vec<(usize, usize, &[u8], &'static str)> = Vec::new();
+    let mut total = 0;
+    for (index, db) in dbs.iter().enumerate() {
+        total += table_bytes(db);
+        for (key, val) in db.iter() {
+            let bytes = data_bytes(key, val, DEFAULT_SAMPLES);
+            total += bytes;
+            biggest.push((bytes, index, key, val.data().type_name()));
+        }
+    }
+
+    if biggest.is_empty() {
+        return "Hi Sam, this instance is empty, so there's nothing for me to look at. Come back once it holds some data.\n".to_string();
+    }
+
+    biggest.sort_unstable_by_key(|&(bytes, ..)| std::cmp::Reverse(bytes));
+    biggest.truncate(BIGGEST_KEYS);
+
+    let mut issues = Vec::new();
+    let rss = resident_bytes() as usize;
+    // Only worth mentioning once there's enough data for the ratio to mean something
+    if total > 10 << 20 && rss as f64 / total as f64 > 1.5 {
+        issues.push(format!(
+            "High fragmentation: the process takes up {rss} bytes for {total} bytes of data. Memory freed by deleted keys hasn't been given back to the OS yet."
+        ));
+    }
+    let (bytes, _, key, _) = biggest[0];
+    if biggest.len() > 1 && bytes * 2 > total {
+        issues.push(format!(
+            "Big key: '{}' holds {:.0}% of the dataset on its own.",
+            String::from_utf8_lossy(key),
+            bytes as f64 * 100.0 / total as f64
+        ));
+    }
+
+    let mut report = if issues.is_empty() {
+        "Hi Sam, I can't find any memory issue in your instance.\n".to_string()
+    } else {
+        let mut report =
+            "Sam, I detected a few issues in this instance memory implementation:\n\n".to_string();
+        for issue in issues {
+            report.push_str(&format!(" * {issue}\n"));
+        }
+        report
+    };
+
+    report.push_str("\nThe biggest keys are:\n\n");
+    for (bytes, index, key, type_name) in biggest {
+        report.push_str(&format!(
+            " * db{index} '{}' ({type_name}): {bytes} bytes\n",
+            String::from_utf8_lossy(key)
+        ));
+    }
+
+    report
+}
+
+/// Bytes used by a key: its name, its value and its slot in the keyspace.
+pub fn key_bytes(key: &[u8], val: &DBData, samples: usize) -> usize {
+    size_of::<(Vec<u8>, DBData)>() + data_bytes(key, val, samples)
+}
+
+/// Bytes used by a key's name and value, leaving out its slot in the keyspace.
+fn data_bytes(key: &[u8], val: &DBData, samples: usize) -> usize {
+    key.len() + value_bytes(val.data(), samples)
+}
+
+/// Estimated bytes a value takes up beyond its slot in the keyspace. Collections are sized from
+/// up to `samples` of their elements, scaled up to the whole collection.
+pub fn value_bytes(val: &DBVal, samples: usize) -> usize {
+    let bytes = |member: &[u8]| size_of::<Vec<u8>>() + member.len();
+    // Hash tables and ordered indexes need about another pointer's worth per element
+    let indexed = |encoding: &str| match encoding {
+        "listpack" | "intset" => 0,
+        _ => size_of::<usize>(),
+    };
+
+    match val {
+        DBVal::String(s) => s.len(),
+        DBVal::Int(_) => 0,
+        DBVal::List(list) => extrapolate(list.len(), list.iter().map(|item| bytes(item)), samples),
+        DBVal::Hash(hash) => {
+            let fields = hash
+                .iter()
+                .map(|(field, value)| bytes(field) + bytes(value) + indexed(hash.encoding()));
+            let expires = hash.expires().len() * (size_of::<(Vec<u8>, u64)>() + size_of::<usize>());
+            extrapolate(hash.len(), fields, samples) + expires
+        }
+        DBVal::Set(set) if set.encoding() == "intset" => set.len() * size_of::<i64>(),
+        DBVal::Set(set) => extrapolate(
+            set.len(),
+            set.iter()
+                .map(|member| bytes(&member) + indexed(set.encoding())),
+            samples,
+        ),
+        DBVal::ZSet(zset) => {
+            // The indexed form keeps each member twice, once per index
+            let copies = if zset.encoding() == "listpack" { 1 } else { 2 };
+            let members = zset.iter().map(|(member, _)| {
+                copies * (bytes(member) + size_of::<f64>()) + indexed(zset.encoding())
+            });
+            extrapolate(zset.len(), members, samples)
+        }
+        DBVal::Stream(stream) => {
+            let entries = stream.iter().map(|(id, fields)| {
+                size_of_val(id)
+                    + size_of::<Vec<(Vec<u8>, Vec<u8>)>>()
+                    + fields
+                        .iter()
+                        .map(|(field, value)| bytes(field) + bytes(value))
+                        .sum::<usize>()
+            });
+            let groups: usize = stream
+                .groups()
+                .map(|(name, group)| {
+                    bytes(name)
+                        + group
+                            .pending()
+                            .values()
+                            .map(|entry| size_of_val(entry) + entry.consumer.len() + 16)
+                            .sum::<usize>()
+                        + group
+                            .consumers()
+                            .iter()
+                            .map(|(name, consumer)| {
+                                bytes(name) + size_of_val(consumer) + consumer.pending.len() * 16
+                            })
+                            .sum::<usize>()
+                })
+                .sum();
+            extrapolate(stream.len(), entries, samples) + groups
+        }
+    }
+}
+
+/// Scales the average size of the first `samples` of `sizes` up to `len` elements.
+fn extrapolate(len: usize, sizes: impl Iterator<Item = usize>, samples: usize) -> usize {
+    let (count, sum) = sizes
+        .take(samples)
+        .fold((0, 0), |(count, sum), size| (count + 1, sum + size));
+    match count {
+        0 => 0,
+        count => sum * len / count,
+    }
+}
+
+/// What a keyspace's table costs on top of the keys and values in it: an entry per key, and
+/// about as much again in the tree nodes holding them.
+fn table_bytes(db: &Keyspace) -> usize {
+    db.len() * size_of::<(Bytes, DBData)>() * 2
+}
@@ -0,0 +1,202 @@
+use crate::cluster;
+use crate::cmd::{
+    lower, not_an_integer, parse_int, peek, registry::Spec, syntax_error, wrong_args,
+};
+use crate::db::Keyspace;
+use crate::dump::dump_value;
+use crate::notify::{self, Class};
+use crate::resp::{self, Value};
+use bytes::Bytes;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// MIGRATE itself.
+pub const COMMANDS: &[Spec] = &[Spec::keyspace("migrate", -6, [3, 3, 1], migrate)];
+
+/// MIGRATE's options, as the command spells them.
+struct Migration<'a> {
+    host: String,
+    port: u16,
+    db: i64,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+    /// The arguments to AUTH the target with, if any.
+    auth: Option<Vec<Vec<u8>>>,
+    keys: Vec<&'a Vec<u8>>,
+}
+
+/// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [AUTH password]
+/// [AUTH2 username password] [KEYS key [key ...]]: RESTOREs keys on another server and, unless
+/// COPY, deletes them here once it has every one of them. It blocks for as long as that takes,
+/// up to `timeout` milliseconds per step, so no write can come between the keys going and going
+/// away.
+pub fn migrate(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let migration = match parse(args) {
+        Ok(migration) => migration,
+        Err(e) => return e,
+    };
+
+    let mut restores = Vec::new();
+    for key in &migration.keys {
+        let Some(val) = peek(db, key) else {
+            continue;
+        };
+        let ttl = val.ttl().map_or(0, |ttl| ttl.max(1));
+        let mut restore = vec![
+            b"RESTORE".to_vec(),
+            (*key).clone(),
+            ttl.to_string().into_bytes(),
+            dump_value(val.data()),
+        ];
+        if migration.replace {
+            restore.push(b"REPLACE".to_vec());
+        }
+        restores.push(restore);
+    }
+    if restores.is_empty() {
+        return Value::SimpleString("NOKEY".to_string());
+    }
+
+    let mut commands = Vec::new();
+    if let Some(auth) = &migration.auth {
+        commands.push(auth.clone());
+    }
+    commands.push(vec![
+        b"SELECT".to_vec(),
+        migration.db.to_string().into_bytes(),
+    ]);
+    for restore in &restores {
+        // The target may only be importing the key's slot, so far
+        if cluster::enabled() {
+            commands.push(vec![b"ASKING".to_vec()]);
+        }
+        commands.push(restore.clone());
+    }
+
+    let replies = match send(&migration, &commands) {
+        Ok(replies) => replies,
+        Err(e) => return Value::error(format!("IOERR error or timeout {e}")),
+    };
+
+    if let Some(e) = replies.iter().find_map(|reply| reply.error_message()) {
+        return Value::error(format!("ERR Target instance replied with error: {e}"));
+    }
+
+    if !migration.copy {
+        for restore in &restores {
+            let key = &restore[1];
+            db.remove(key);
+            notify::emit(Class::Generic, "del", key);
+        }
+    }
+
+    Value::SimpleString("OK".to_string())
+}
+
+fn parse(args: &[Vec<u8>]) -> Result<Migration<'_>, Value> {
+    let [host, port, key, db, timeout, options @ ..] = args else {
+        return Err(wrong_args("migrate"));
+    };
+    let port = parse_int::<u16>(port).ok_or_else(not_an_integer)?;
+    let db = parse_int::<i64>(db).ok_or_else(not_an_integer)?;
+    let timeout = parse_int::<i64>(timeout).ok_or_else(not_an_integer)?;
+
+    let mut migration = Migration {
+        host: String::from_utf8_lossy(host).into_owned(),
+        port,
+        db,
+        // Like Redis, a timeout that isn't positive means a second
+        timeout: Duration::from_millis(if timeout > 0 { timeout as u64 } else { 1000 }),
+        copy: false,
+        replace: false,
+        auth: None,
+        keys: vec![key],
+    };
+    let mut i = 0;
+    while i < options.len() {
+        match (lower(&options[i]).as_str(), &options[i + 1..]) {
+            ("copy", _) => migration.copy = true,
+            ("replace", _) => migration.replace = true,
+            ("auth", [password, ..]) => {
+                migration.auth = Some(vec![b"AUTH".to_vec(), password.clone()]);
+                i += 1;
+            }
+            ("auth2", [username, password, ..]) => {
+                migration.auth = Some(vec![b"AUTH".to_vec(), username.clone(), password.clone()]);
+                i += 2;
+            }
+            ("keys", keys) => {
+                if !key.is_empty() {
+                    return Err(Value::error(
+                        "ERR When using MIGRATE KEYS option, the key argument must be set to \
+                         the empty string",
+                    ));
+                }
+                migration.keys = keys.iter().collect();
+                break;
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    Ok(migration)
+}
+
+/// Sends `commands` to the target in one go and reads a reply to each, saying what failed if
+/// any of that doesn't work out.
+fn send(migration: &Migration, commands: &[Vec<Vec<u8>>]) -> Result<Vec<Value>, &'static str> {
+    const CONNECTING: &str = "connecting to the client";
+    const WRITING: &str = "writing to target instance";
+    const READING: &str = "reading to target instance";
+
+    let addr = (migration.host.as_str(), migration.port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or(CONNECTING)?;
+    let mut stream =
+        TcpStream::connect_timeout(&addr, migration.timeout).map_err(|_| CONNECTING)?;
+    stream
+        .set_write_timeout(Some(migration.timeout))
+        .and_then(|()| stream.set_read_timeout(Some(migration.timeout)))
+        .map_err(|_| CONNECTING)?;
+
+    let mut request = Vec::new();
+    for command in commands {
+        let parts = command
+            .iter()
+            .map(|part| Value::BulkString(Bytes::copy_from_slice(part)))
+            .collect();
+        request.extend(Value::Array(parts).serialise(false));
+    }
+    stream.write_all(&request).map_err(|_| WRITING)?;
+
+    let mut replies = Vec::with_capacity(commands.len());
+    let mut buf = Vec::new();
+    while replies.len() < commands.len() {
+        match resp::parse_message(&buf) {
+            Ok(Some((reply, len))) => {
+                buf.drain(..len);
+                replies.push(reply);
+            }
+            Ok(None) => read_more(&mut stream, &mut buf).map_err(|_| READING)?,
+            Err(_) => return Err(READING),
+        }
+    }
+
+    Ok(replies)
+}
+
+fn read_more(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<()> {
+    let mut chunk = [0; 4096];
+    match stream.read(&mut chunk)? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        read => {
+            buf.extend_from_slice(&chunk[..read]);
+            Ok(())
+        }
+    }
+}
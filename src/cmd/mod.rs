@@ -0,0 +1,1062 @@
+pub mod acl;
+pub mod bitmap;
+pub mod client;
+pub mod cluster;
+pub mod command;
+pub mod config;
+pub mod debug;
+pub mod geo;
+pub mod hash;
+pub mod hll;
+pub mod info;
+pub mod keyspace;
+pub mod latency;
+pub mod list;
+pub mod lolwut;
+pub mod memory;
+pub mod migrate;
+pub mod pubsub;
+pub mod registry;
+pub mod replication;
+pub mod scan;
+pub mod scripting;
+pub mod set;
+pub mod slowlog;
+pub mod snapshot;
+pub mod sort;
+pub mod stream;
+pub mod string;
+pub mod zset;
+
+use crate::db::{DBData, DBVal, Keyspace};
+use crate::plugin::Plugin;
+use crate::resp::Value;
+use crate::script;
+use crate::stats;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub fn wrong_args(command: &str) -> Value {
+    Value::error(format!(
+        "ERR wrong number of arguments for '{command}' command"
+    ))
+}
+
+pub fn syntax_error() -> Value {
+    Value::error("ERR syntax error")
+}
+
+pub fn not_an_integer() -> Value {
+    Value::error("ERR value is not an integer or out of range")
+}
+
+pub fn wrong_type() -> Value {
+    Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+/// Commands whose keys can't be found from fixed positions alone, so a caller has to look at
+/// the arguments: [`command_keys`] knows how.
+const MOVABLE_KEYS: &[&str] = &[
+    "zunion",
+    "zinter",
+    "zdiff",
+    "sintercard",
+    "zmpop",
+    "bzmpop",
+    "eval",
+    "evalsha",
+    "fcall",
+    "fcall_ro",
+    "zunionstore",
+    "zinterstore",
+    "zdiffstore",
+    "sort",
+    "xread",
+    "xreadgroup",
+];
+
+/// ACL categories and the commands in each, as `ACL CAT` lists them. `slow` isn't listed: it's
+/// every command that isn't `fast`.
+const CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "keyspace",
+        &[
+            "select",
+            "swapdb",
+            "move",
+            "del",
+            "unlink",
+            "flushall",
+            "flushdb",
+            "migrate",
+            "touch",
+            "dump",
+            "restore",
+            "object",
+            "type",
+            "sort",
+            "export",
+            "import",
+            "keys",
+            "scan",
+            "dbsize",
+            "expire",
+            "pexpire",
+            "expireat",
+            "pexpireat",
+            "persist",
+            "ttl",
+            "pttl",
+            "expiretime",
+            "pexpiretime",
+        ],
+    ),
+    (
+        "read",
+        &[
+            "get",
+            "mget",
+            "getbit",
+            "bitcount",
+            "bitpos",
+            "bitfield_ro",
+            "lcs",
+            "pfcount",
+            "touch",
+            "dump",
+            "object",
+            "memory",
+            "type",
+            "keys",
+            "scan",
+            "dbsize",
+            "ttl",
+            "pttl",
+            "expiretime",
+            "pexpiretime",
+            "lrange",
+            "llen",
+            "lpos",
+            "hget",
+            "hgetall",
+            "hexists",
+            "hlen",
+            "hkeys",
+            "hvals",
+            "hmget",
+            "hrandfield",
+            "hscan",
+            "httl",
+            "hpttl",
+            "hexpiretime",
+            "hpexpiretime",
+            "smembers",
+            "sismember",
+            "smismember",
+            "scard",
+            "sinter",
+            "sunion",
+            "sdiff",
+            "sintercard",
+            "srandmember",
+            "sscan",
+            "zscore",
+            "zcard",
+            "zrange",
+            "zrevrange",
+            "zrangebyscore",
+            "zrevrangebyscore",
+            "zrangebylex",
+            "zrevrangebylex",
+            "zrank",
+            "zrevrank",
+            "zcount",
+            "zlexcount",
+            "zunion",
+            "zinter",
+            "zdiff",
+            "xlen",
+            "xrange",
+            "xrevrange",
+            "xread",
+            "xpending",
+            "xinfo",
+            "geopos",
+            "geodist",
+            "geohash",
+            "geosearch",
+            "fcall_ro",
+        ],
+    ),
+    (
+        "write",
+        &[
+            "set",
+            "mset",
+            "msetnx",
+            "setex",
+            "psetex",
+            "setnx",
+            "getset",
+            "getdel",
+            "getex",
+            "incr",
+            "decr",
+            "incrby",
+            "decrby",
+            "append",
+            "setbit",
+            "bitop",
+            "bitfield",
+            "pfadd",
+            "pfmerge",
+            "swapdb",
+            "move",
+            "del",
+            "unlink",
+            "flushall",
+            "flushdb",
+            "migrate",
+            "restore",
+            "sort",
+            "expire",
+            "pexpire",
+            "expireat",
+            "pexpireat",
+            "persist",
+            "lpush",
+            "rpush",
+            "lpop",
+            "rpop",
+            "linsert",
+            "lset",
+            "lrem",
+            "ltrim",
+            "lmove",
+            "rpoplpush",
+            "blpop",
+            "brpop",
+            "blmove",
+            "brpoplpush",
+            "hset",
+            "hmset",
+            "hdel",
+            "hsetnx",
+            "hincrby",
+            "hincrbyfloat",
+            "hexpire",
+            "hpexpire",
+            "hexpireat",
+            "hpexpireat",
+            "hpersist",
+            "sadd",
+            "srem",
+            "sinterstore",
+            "sunionstore",
+            "sdiffstore",
+            "spop",
+            "smove",
+            "zadd",
+            "zincrby",
+            "zrem",
+            "zpopmin",
+            "zpopmax",
+            "zmpop",
+            "bzpopmin",
+            "bzpopmax",
+            "bzmpop",
+            "zunionstore",
+            "zinterstore",
+            "zdiffstore",
+            "xadd",
+            "xtrim",
+            "xdel",
+            "xgroup",
+            "xreadgroup",
+            "xack",
+            "xclaim",
+            "xautoclaim",
+            "geoadd",
+            "geosearchstore",
+            "import",
+        ],
+    ),
+    (
+        "set",
+        &[
+            "sadd",
+            "srem",
+            "smembers",
+            "sismember",
+            "smismember",
+            "scard",
+            "sinter",
+            "sunion",
+            "sdiff",
+            "sinterstore",
+            "sunionstore",
+            "sdiffstore",
+            "sintercard",
+            "spop",
+            "srandmember",
+            "smove",
+            "sscan",
+            "sort",
+        ],
+    ),
+    (
+        "sortedset",
+        &[
+            "zadd",
+            "zincrby",
+            "zrem",
+            "zscore",
+            "zcard",
+            "zrange",
+            "zrevrange",
+            "zrangebyscore",
+            "zrevrangebyscore",
+            "zrangebylex",
+            "zrevrangebylex",
+            "zrank",
+            "zrevrank",
+            "zcount",
+            "zlexcount",
+            "zpopmin",
+            "zpopmax",
+            "zmpop",
+            "bzpopmin",
+            "bzpopmax",
+            "bzmpop",
+            "zunion",
+            "zinter",
+            "zdiff",
+            "zunionstore",
+            "zinterstore",
+            "zdiffstore",
+            "sort",
+        ],
+    ),
+    (
+        "list",
+        &[
+            "lpush",
+            "rpush",
+            "lpop",
+            "rpop",
+            "lrange",
+            "llen",
+            "linsert",
+            "lset",
+            "lrem",
+            "ltrim",
+            "lpos",
+            "lmove",
+            "rpoplpush",
+            "blpop",
+            "brpop",
+            "blmove",
+            "brpoplpush",
+            "sort",
+        ],
+    ),
+    (
+        "hash",
+        &[
+            "hset",
+            "hmset",
+            "hget",
+            "hgetall",
+            "hdel",
+            "hexists",
+            "hlen",
+            "hkeys",
+            "hvals",
+            "hsetnx",
+            "hmget",
+            "hincrby",
+            "hincrbyfloat",
+            "hrandfield",
+            "hscan",
+            "hexpire",
+            "hpexpire",
+            "hexpireat",
+            "hpexpireat",
+            "httl",
+            "hpttl",
+            "hexpiretime",
+            "hpexpiretime",
+            "hpersist",
+        ],
+    ),
+    (
+        "string",
+        &[
+            "set", "get", "mset", "msetnx", "setex", "psetex", "setnx", "getset", "getdel",
+            "getex", "incr", "decr", "incrby", "decrby", "append", "lcs", "mget",
+        ],
+    ),
+    (
+        "bitmap",
+        &[
+            "setbit",
+            "getbit",
+            "bitcount",
+            "bitpos",
+            "bitop",
+            "bitfield",
+            "bitfield_ro",
+        ],
+    ),
+    ("hyperloglog", &["pfadd", "pfcount", "pfmerge"]),
+    (
+        "geo",
+        &[
+            "geoadd",
+            "geopos",
+            "geodist",
+            "geohash",
+            "geosearch",
+            "geosearchstore",
+        ],
+    ),
+    (
+        "stream",
+        &[
+            "xadd",
+            "xtrim",
+            "xdel",
+            "xlen",
+            "xrange",
+            "xrevrange",
+            "xread",
+            "xgroup",
+            "xreadgroup",
+            "xack",
+            "xpending",
+            "xclaim",
+            "xautoclaim",
+            "xinfo",
+        ],
+    ),
+    (
+        "pubsub",
+        &[
+            "subscribe",
+            "unsubscribe",
+            "psubscribe",
+            "punsubscribe",
+            "ssubscribe",
+            "sunsubscribe",
+            "publish",
+            "spublish",
+            "pubsub",
+        ],
+    ),
+    (
+        "admin",
+        &[
+            "acl",
+            "config",
+            "monitor",
+            "slowlog",
+            "latency",
+            "debug",
+            "shutdown",
+            "save",
+            "bgsave",
+            "bgrewriteaof",
+            "lastsave",
+            "replconf",
+            "psync",
+            "sync",
+            "replicaof",
+            "slaveof",
+            "failover",
+            "cluster",
+            "export",
+            "import",
+        ],
+    ),
+    (
+        "fast",
+        &[
+            "ping",
+            "lastsave",
+            "echo",
+            "hello",
+            "quit",
+            "reset",
+            "lolwut",
+            "auth",
+            "select",
+            "swapdb",
+            "move",
+            "unlink",
+            "touch",
+            "type",
+            "dbsize",
+            "expire",
+            "pexpire",
+            "expireat",
+            "pexpireat",
+            "persist",
+            "ttl",
+            "pttl",
+            "expiretime",
+            "pexpiretime",
+            "get",
+            "getdel",
+            "getex",
+            "getset",
+            "setnx",
+            "incr",
+            "decr",
+            "incrby",
+            "decrby",
+            "append",
+            "mget",
+            "getbit",
+            "bitfield_ro",
+            "pfadd",
+            "lpush",
+            "rpush",
+            "lpop",
+            "rpop",
+            "llen",
+            "hset",
+            "hmset",
+            "hget",
+            "hdel",
+            "hexists",
+            "hlen",
+            "hsetnx",
+            "hmget",
+            "hincrby",
+            "hincrbyfloat",
+            "hexpire",
+            "hpexpire",
+            "hexpireat",
+            "hpexpireat",
+            "httl",
+            "hpttl",
+            "hexpiretime",
+            "hpexpiretime",
+            "hpersist",
+            "sadd",
+            "srem",
+            "sismember",
+            "smismember",
+            "scard",
+            "spop",
+            "smove",
+            "zadd",
+            "zincrby",
+            "zrem",
+            "zscore",
+            "zcard",
+            "zrank",
+            "zrevrank",
+            "zcount",
+            "zlexcount",
+            "zpopmin",
+            "zpopmax",
+            "xadd",
+            "xdel",
+            "xlen",
+            "xack",
+            "xclaim",
+            "xautoclaim",
+            "publish",
+            "spublish",
+            "multi",
+            "discard",
+            "watch",
+            "unwatch",
+            "asking",
+        ],
+    ),
+    (
+        "blocking",
+        &[
+            "blpop",
+            "brpop",
+            "blmove",
+            "brpoplpush",
+            "bzpopmin",
+            "bzpopmax",
+            "bzmpop",
+            "xread",
+            "xreadgroup",
+            "wait",
+        ],
+    ),
+    (
+        "dangerous",
+        &[
+            "swapdb",
+            "flushall",
+            "flushdb",
+            "keys",
+            "restore",
+            "migrate",
+            "sort",
+            "acl",
+            "config",
+            "info",
+            "monitor",
+            "slowlog",
+            "latency",
+            "debug",
+            "shutdown",
+            "save",
+            "bgsave",
+            "bgrewriteaof",
+            "lastsave",
+            "replconf",
+            "psync",
+            "sync",
+            "replicaof",
+            "slaveof",
+            "failover",
+            "export",
+            "import",
+        ],
+    ),
+    (
+        "connection",
+        &[
+            "ping", "echo", "auth", "select", "client", "hello", "command", "quit", "reset",
+            "wait", "asking",
+        ],
+    ),
+    (
+        "transaction",
+        &["multi", "exec", "discard", "watch", "unwatch"],
+    ),
+    (
+        "scripting",
+        &["eval", "evalsha", "script", "fcall", "fcall_ro", "function"],
+    ),
+];
+
+/// Every category name, including `slow`.
+pub fn category_names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = CATEGORIES.iter().map(|(name, _)| *name).collect();
+    names.push("slow");
+
+    names
+}
+
+/// The commands in `category`, or `None` if there's no such category.
+pub fn category_commands(category: &str) -> Option<Vec<&'static str>> {
+    let in_fast = |name: &str| in_category(name, "fast");
+
+    match category {
+        "all" => Some(command_names().collect()),
+        "slow" => Some(command_names().filter(|name| !in_fast(name)).collect()),
+        category => CATEGORIES
+            .iter()
+            .find(|(name, _)| *name == category)
+            .map(|(_, commands)| {
+                let mut commands = commands.to_vec();
+                commands.extend(command_names().filter(|name| {
+                    registry::plugin(name).is_some_and(|p| p.in_category(category))
+                }));
+                commands
+            }),
+    }
+}
+
+/// Whether `name` is one of the commands that run in constant or logarithmic time.
+pub fn is_fast(name: &str) -> bool {
+    in_category(name, "fast")
+}
+
+fn in_category(name: &str, category: &str) -> bool {
+    CATEGORIES
+        .iter()
+        .any(|(cat, commands)| *cat == category && commands.contains(&name))
+        || registry::plugin(name).is_some_and(|plugin| plugin.in_category(category))
+}
+
+/// The keys `args` name for command `name`, which ACL key patterns are checked against.
+pub fn command_keys<'a>(name: &str, args: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+    key_positions(name, args)
+        .into_iter()
+        .map(|at| args[at].as_slice())
+        .collect()
+}
+
+/// Where in `args` command `name` has its keys, for [`command_keys`] and for putting a
+/// namespace's prefix on them. Too few `args` for the command just find fewer keys.
+pub fn key_positions(name: &str, args: &[Vec<u8>]) -> Vec<usize> {
+    let all = |from: usize| (from..args.len()).collect::<Vec<_>>();
+    let at = |at: usize| {
+        (at < args.len())
+            .then_some(at)
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    // `numkeys key [key ...]` with numkeys at `at`
+    let counted = |at: usize| {
+        let count = args
+            .get(at)
+            .and_then(|n| parse_int::<usize>(n))
+            .unwrap_or(0);
+        (at + 1..args.len()).take(count).collect::<Vec<_>>()
+    };
+
+    match name {
+        "ping" | "echo" | "auth" | "select" | "swapdb" | "flushall" | "flushdb" | "multi"
+        | "exec" | "discard" | "unwatch" | "script" | "function" | "acl" | "client" | "hello"
+        | "config" | "info" | "command" | "monitor" | "slowlog" | "latency" | "debug"
+        | "shutdown" | "save" | "bgsave" | "bgrewriteaof" | "lastsave" | "replconf" | "psync"
+        | "sync" | "replicaof" | "slaveof" | "wait" | "failover" | "cluster" | "asking"
+        | "quit" | "reset" | "lolwut" | "publish" | "spublish" | "pubsub" | "export" | "import"
+        | "keys" | "scan" | "dbsize" => Vec::new(),
+        name if pubsub::is_subscribe(name) => Vec::new(),
+        "mget" | "del" | "unlink" | "touch" | "watch" | "pfcount" | "pfmerge" | "sinter"
+        | "sunion" | "sdiff" | "sinterstore" | "sunionstore" | "sdiffstore" => all(0),
+        "mset" | "msetnx" => (0..args.len()).step_by(2).collect(),
+        "bitop" => all(1),
+        "blpop" | "brpop" | "bzpopmin" | "bzpopmax" => (0..args.len().saturating_sub(1)).collect(),
+        "lmove" | "rpoplpush" | "blmove" | "brpoplpush" | "smove" | "lcs" | "geosearchstore" => {
+            (0..args.len().min(2)).collect()
+        }
+        "zunion" | "zinter" | "zdiff" | "sintercard" | "zmpop" => counted(0),
+        "bzmpop" | "eval" | "evalsha" | "fcall" | "fcall_ro" => counted(1),
+        "zunionstore" | "zinterstore" | "zdiffstore" => {
+            let mut keys = at(0);
+            keys.extend(counted(1));
+            keys
+        }
+        "object" | "xgroup" | "xinfo" => at(1),
+        "migrate" => match args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case(b"keys"))
+        {
+            Some(keys) if args.get(2).is_some_and(|key| key.is_empty()) => all(keys + 1),
+            _ => at(2),
+        },
+        "memory" if args.first().is_some_and(|sub| lower(sub) == "usage") => at(1),
+        "sort" => {
+            let store = args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"store"))
+                .map(|i| i + 1)
+                .filter(|i| *i < args.len());
+            at(0).into_iter().chain(store).collect()
+        }
+        "xread" | "xreadgroup" => {
+            let Some(streams) = args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"streams"))
+            else {
+                return Vec::new();
+            };
+            let count = (args.len() - streams - 1) / 2;
+            (streams + 1..streams + 1 + count).collect()
+        }
+        name => match registry::plugin(name) {
+            Some(plugin) => plugin.key_positions(args),
+            None => at(0),
+        },
+    }
+}
+
+/// Whether command `name` only ever reads keys, so it can share their locks with others that
+/// do.
+pub fn reads_only(name: &str) -> bool {
+    registry::get(name).is_some_and(|command| command.reads_only())
+}
+
+/// Whether command `name` touches no keys but the ones [`command_keys`] finds in it, so only
+/// their shards need locking. SORT's patterns and scripts can reach any key, and the rest of
+/// MEMORY's subcommands look over them all.
+pub fn keeps_to_keys(name: &str) -> bool {
+    !matches!(
+        name,
+        "sort" | "eval" | "evalsha" | "fcall" | "fcall_ro" | "memory"
+    ) && registry::plugin(name).is_none_or(Plugin::keeps_to_keys)
+}
+
+/// Whether `name` can modify the keyspace, which read-only scripts may not do.
+pub fn is_write(name: &str) -> bool {
+    in_category(name, "write")
+}
+
+/// Whether `name` may add to what the dataset takes up, which is refused while it's over
+/// `maxmemory` and nothing more can be evicted. Writes that can only take things away aren't.
+pub fn may_grow(name: &str) -> bool {
+    (is_write(name) || matches!(name, "eval" | "evalsha" | "fcall"))
+        && !matches!(
+            name,
+            "del"
+                | "unlink"
+                | "flushall"
+                | "flushdb"
+                | "getdel"
+                | "lpop"
+                | "rpop"
+                | "blpop"
+                | "brpop"
+                | "lrem"
+                | "ltrim"
+                | "hdel"
+                | "srem"
+                | "spop"
+                | "zrem"
+                | "zpopmin"
+                | "zpopmax"
+                | "bzpopmin"
+                | "bzpopmax"
+                | "zmpop"
+                | "bzmpop"
+                | "xdel"
+                | "xtrim"
+                | "xack"
+                | "hpersist"
+                | "persist"
+                | "move"
+                | "swapdb"
+                | "migrate"
+        )
+}
+
+/// Whether `name` only reads keys, which client-side caching tracks.
+pub fn is_read(name: &str) -> bool {
+    in_category(name, "read")
+}
+
+/// Whether `name` may change the dataset or be seen by other clients, which CLIENT PAUSE WRITE
+/// holds up along with plain writes.
+pub fn may_write(name: &str) -> bool {
+    is_write(name)
+        || matches!(
+            name,
+            "eval" | "evalsha" | "fcall" | "publish" | "spublish" | "script" | "function"
+        )
+}
+
+/// Every command's name, in the order COMMAND lists them.
+pub fn command_names() -> impl Iterator<Item = &'static str> {
+    registry::all().map(|command| command.name())
+}
+
+/// The flags COMMAND INFO shows for `name`, worked out from its categories.
+pub fn command_flags(name: &str) -> Vec<&'static str> {
+    let mut flags = Vec::new();
+    if is_write(name) {
+        flags.push("write");
+    }
+    if is_read(name) {
+        flags.push("readonly");
+    }
+    if in_category(name, "admin") {
+        flags.push("admin");
+    }
+    if in_category(name, "pubsub") {
+        flags.push("pubsub");
+    }
+    if !script::allowed(name) {
+        flags.push("noscript");
+    }
+    if in_category(name, "fast") {
+        flags.push("fast");
+    }
+    if in_category(name, "blocking") {
+        flags.push("blocking");
+    }
+    if MOVABLE_KEYS.contains(&name) {
+        flags.push("movablekeys");
+    }
+
+    flags
+}
+
+/// The ACL categories `name` is in, `slow` included where it applies.
+pub fn command_categories(name: &str) -> Vec<&'static str> {
+    let mut categories: Vec<&str> = CATEGORIES
+        .iter()
+        .filter(|(_, commands)| commands.contains(&name))
+        .map(|(category, _)| *category)
+        .collect();
+    if let Some(plugin) = registry::plugin(name) {
+        categories.extend(plugin.categories());
+    }
+    if !categories.contains(&"fast") {
+        categories.push("slow");
+    }
+
+    categories
+}
+
+pub fn is_command(name: &str) -> bool {
+    registry::get(name).is_some()
+}
+
+/// Commands `rename-command` moved: the names clients now call them by, and the real names
+/// that no longer work.
+#[derive(Default)]
+struct Renames {
+    aliases: HashMap<String, String>,
+    hidden: HashSet<String>,
+}
+
+static RENAMES: RwLock<Option<Renames>> = RwLock::new(None);
+
+/// Sets up the `rename-command` rules, each from a command's real name to its new one, where
+/// an empty new name disables the command.
+pub fn set_renames(rules: &[(String, String)]) {
+    let mut renames = Renames::default();
+    for (command, new_name) in rules {
+        renames.hidden.insert(command.clone());
+        if !new_name.is_empty() {
+            renames.aliases.insert(new_name.clone(), command.clone());
+        }
+    }
+
+    *RENAMES.write().unwrap() = Some(renames);
+}
+
+/// The real name of the command a client asked for as `name`, or `None` if it was renamed or
+/// disabled and isn't to be found under that name any more.
+pub fn resolve(name: &str) -> Option<&str> {
+    let renames = RENAMES.read().unwrap();
+    let Some(renames) = renames.as_ref() else {
+        return Some(name);
+    };
+
+    match renames.aliases.get(name) {
+        // The real names are all registered, so there's always a static copy
+        Some(command) => registry::get(command).map(|command| command.name()),
+        None if renames.hidden.contains(name) => None,
+        None => Some(name),
+    }
+}
+
+/// The error for a command that doesn't exist.
+pub fn unknown_command(name: &str, args: &[Vec<u8>]) -> Value {
+    let mut message = format!("ERR unknown command '{name}', with args beginning with: ");
+    for arg in args {
+        message.push_str(&format!("'{}' ", String::from_utf8_lossy(arg)));
+    }
+
+    Value::error(message)
+}
+
+/// Checks `name` exists and is being called with a plausible number of arguments, the way a
+/// transaction vets commands as they're queued.
+pub fn check_arity(name: &str, args: &[Vec<u8>]) -> Result<(), Value> {
+    registry::check(name, args).map(|_| ())
+}
+
+thread_local! {
+    /// Whether the running command's lookups count as accesses to the keys they find, which
+    /// they don't for a connection that's turned on CLIENT NO-TOUCH. Set on the thread that
+    /// runs it, like the selected database.
+    static TOUCHING: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Says whether the command about to run records its accesses to keys.
+pub fn set_touching(touching: bool) {
+    TOUCHING.set(touching);
+}
+
+/// Looks up `key`, dropping it first if its TTL has already elapsed. Live keys have their
+/// access time refreshed.
+pub fn lookup<'a>(db: &'a mut Keyspace, key: &[u8]) -> Option<&'a mut DBData> {
+    let val = peek(db, key);
+    stats::record_lookup(val.is_some());
+    let val = val?;
+    if TOUCHING.get() {
+        val.touch();
+    }
+
+    Some(val)
+}
+
+/// Like [`lookup`], but leaves the access time alone so introspection doesn't warm up keys.
+pub fn peek<'a>(db: &'a mut Keyspace, key: &[u8]) -> Option<&'a mut DBData> {
+    db.get_live(key)
+}
+
+/// Like [`lookup`], for a command that only reads: a key whose TTL has elapsed is dropped once
+/// its locks are let go of.
+pub fn read<'a>(db: &'a Keyspace, key: &[u8]) -> Option<&'a DBData> {
+    let val = db.get_shared(key);
+    stats::record_lookup(val.is_some());
+    let val = val?;
+    if TOUCHING.get() {
+        val.touch();
+    }
+
+    Some(val)
+}
+
+/// Renders a string value as a bulk string, or `None` for the collection types.
+pub fn db_val_to_value(val: &DBVal) -> Option<Value> {
+    val.string_value().map(Value::BulkString)
+}
+
+pub fn parse_int<T: FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+/// Lowercased copy of an argument, for matching keywords and subcommands.
+pub fn lower(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).to_lowercase()
+}
+
+/// Resolves Redis-style inclusive `start..=stop` indexes, where negative values count from the
+/// end, against a collection of `len` items. `None` means the range selects nothing.
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+
+    if start > stop || start >= len {
+        return None;
+    }
+
+    Some((start as usize, stop as usize))
+}
+
+/// Parses a blocking command's timeout in (fractional) seconds. Zero means wait forever.
+pub fn parse_timeout(arg: &[u8]) -> Result<Option<Instant>, Value> {
+    let Some(secs) = parse_int::<f64>(arg).filter(|secs| secs.is_finite()) else {
+        return Err(Value::error("ERR timeout is not a float or out of range"));
+    };
+
+    if secs < 0.0 {
+        return Err(Value::error("ERR timeout is negative"));
+    }
+
+    if secs == 0.0 {
+        return Ok(None);
+    }
+
+    Ok(Some(Instant::now() + Duration::from_secs_f64(secs)))
+}
+
+/// Formats a float the way Redis replies with them: integral values lose the fractional part.
+pub fn format_float(n: f64) -> String {
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    n.to_string()
+}
@@ -0,0 +1,191 @@
+use crate::cmd::{lower, registry::Spec, wrong_args};
+use crate::pubsub::{Kind, PubSub, Subscriber};
+use crate::resp::Value;
+use crate::server::Step;
+use bytes::Bytes;
+
+/// The Pub/Sub commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::connection("subscribe", -2, [0, 0, 0], |conn, args| {
+        Step::Replies(subscribe(&mut conn.client.subscriber, args)).now()
+    }),
+    Spec::connection("unsubscribe", -1, [0, 0, 0], |conn, args| {
+        Step::Replies(unsubscribe(&mut conn.client.subscriber, args)).now()
+    }),
+    Spec::connection("psubscribe", -2, [0, 0, 0], |conn, args| {
+        Step::Replies(psubscribe(&mut conn.client.subscriber, args)).now()
+    }),
+    Spec::connection("punsubscribe", -1, [0, 0, 0], |conn, args| {
+        Step::Replies(punsubscribe(&mut conn.client.subscriber, args)).now()
+    }),
+    Spec::connection("ssubscribe", -2, [0, 0, 0], |conn, args| {
+        Step::Replies(ssubscribe(&mut conn.client.subscriber, args)).now()
+    }),
+    Spec::connection("sunsubscribe", -1, [0, 0, 0], |conn, args| {
+        Step::Replies(sunsubscribe(&mut conn.client.subscriber, args)).now()
+    }),
+    Spec::server("publish", 3, [0, 0, 0], |ctx, args| {
+        publish(ctx.pubsub, args)
+    }),
+    Spec::server("spublish", 3, [0, 0, 0], |ctx, args| {
+        spublish(ctx.pubsub, args)
+    }),
+    Spec::server("pubsub", -2, [0, 0, 0], |ctx, args| {
+        pubsub(ctx.pubsub, args)
+    }),
+];
+
+/// Whether `command` changes the connection's subscriptions, which it has to reply to frame by
+/// frame rather than with a single value.
+pub fn is_subscribe(command: &str) -> bool {
+    matches!(
+        command,
+        "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe" | "ssubscribe" | "sunsubscribe"
+    )
+}
+
+/// Whether `command` may run on a connection in subscribed mode.
+pub fn allowed_while_subscribed(command: &str) -> bool {
+    is_subscribe(command) || matches!(command, "ping" | "quit" | "reset")
+}
+
+/// The `[kind, channel, count]` frame confirming a (un)subscription.
+fn confirmation(kind: &str, channel: Option<&[u8]>, count: usize) -> Value {
+    Value::Push(vec![
+        Value::BulkString(Bytes::copy_from_slice(kind.as_bytes())),
+        channel.map_or(Value::Null, |channel| {
+            Value::BulkString(Bytes::copy_from_slice(channel))
+        }),
+        Value::Integer(count as i64),
+    ])
+}
+
+/// Replies with one confirmation per channel, each carrying the running subscription count.
+fn subscribe_generic(
+    subscriber: &mut Subscriber,
+    args: &[Vec<u8>],
+    command: &str,
+    kind: Kind,
+) -> Vec<Value> {
+    if args.is_empty() {
+        return vec![wrong_args(command)];
+    }
+
+    args.iter()
+        .map(|name| {
+            subscriber.subscribe(kind, name);
+            confirmation(command, Some(name), subscriber.subscription_count(kind))
+        })
+        .collect()
+}
+
+/// Without arguments, unsubscribes from everything of that kind.
+fn unsubscribe_generic(
+    subscriber: &mut Subscriber,
+    args: &[Vec<u8>],
+    command: &str,
+    kind: Kind,
+) -> Vec<Value> {
+    let names = if args.is_empty() {
+        subscriber.subscriptions(kind)
+    } else {
+        args.to_vec()
+    };
+
+    if names.is_empty() {
+        return vec![confirmation(
+            command,
+            None,
+            subscriber.subscription_count(kind),
+        )];
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            subscriber.unsubscribe(kind, name);
+            confirmation(command, Some(name), subscriber.subscription_count(kind))
+        })
+        .collect()
+}
+
+pub fn subscribe(subscriber: &mut Subscriber, args: &[Vec<u8>]) -> Vec<Value> {
+    subscribe_generic(subscriber, args, "subscribe", Kind::Channel)
+}
+
+pub fn unsubscribe(subscriber: &mut Subscriber, args: &[Vec<u8>]) -> Vec<Value> {
+    unsubscribe_generic(subscriber, args, "unsubscribe", Kind::Channel)
+}
+
+pub fn psubscribe(subscriber: &mut Subscriber, args: &[Vec<u8>]) -> Vec<Value> {
+    subscribe_generic(subscriber, args, "psubscribe", Kind::Pattern)
+}
+
+pub fn punsubscribe(subscriber: &mut Subscriber, args: &[Vec<u8>]) -> Vec<Value> {
+    unsubscribe_generic(subscriber, args, "punsubscribe", Kind::Pattern)
+}
+
+pub fn ssubscribe(subscriber: &mut Subscriber, args: &[Vec<u8>]) -> Vec<Value> {
+    subscribe_generic(subscriber, args, "ssubscribe", Kind::ShardChannel)
+}
+
+pub fn sunsubscribe(subscriber: &mut Subscriber, args: &[Vec<u8>]) -> Vec<Value> {
+    unsubscribe_generic(subscriber, args, "sunsubscribe", Kind::ShardChannel)
+}
+
+pub fn publish(pubsub: &PubSub, args: &[Vec<u8>]) -> Value {
+    Value::Integer(pubsub.publish(&args[0], &args[1]) as i64)
+}
+
+pub fn spublish(pubsub: &PubSub, args: &[Vec<u8>]) -> Value {
+    if args.len() != 2 {
+        return wrong_args("spublish");
+    }
+
+    Value::Integer(pubsub.spublish(&args[0], &args[1]) as i64)
+}
+
+pub fn pubsub(pubsub: &PubSub, args: &[Vec<u8>]) -> Value {
+    if args.is_empty() {
+        return wrong_args("pubsub");
+    }
+
+    let subcommand = lower(&args[0]);
+    match (subcommand.as_str(), &args[1..]) {
+        ("channels", [] | [_]) => channels_reply(pubsub, Kind::Channel, args.get(1)),
+        ("shardchannels", [] | [_]) => channels_reply(pubsub, Kind::ShardChannel, args.get(1)),
+        ("numsub", channels) => numsub_reply(pubsub, Kind::Channel, channels),
+        ("shardnumsub", channels) => numsub_reply(pubsub, Kind::ShardChannel, channels),
+        ("numpat", []) => Value::Integer(pubsub.pattern_count() as i64),
+        ("channels" | "shardchannels" | "numpat", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try PUBSUB HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try PUBSUB HELP."
+        )),
+    }
+}
+
+fn channels_reply(pubsub: &PubSub, kind: Kind, pattern: Option<&Vec<u8>>) -> Value {
+    Value::Array(
+        pubsub
+            .channels(kind, pattern.map(Vec::as_slice))
+            .into_iter()
+            .map(|channel| Value::BulkString(channel.into()))
+            .collect(),
+    )
+}
+
+fn numsub_reply(pubsub: &PubSub, kind: Kind, channels: &[Vec<u8>]) -> Value {
+    Value::Array(
+        channels
+            .iter()
+            .flat_map(|channel| {
+                [
+                    Value::BulkString(channel.clone().into()),
+                    Value::Integer(pubsub.subscriber_count(kind, channel) as i64),
+                ]
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,751 @@
+use crate::blocking::{BlockedClients, Outcome};
+use crate::client::Client;
+use crate::cmd::{
+    self, acl, bitmap, client, cluster, command, config, debug, geo, hash, hll, info, keyspace,
+    latency, list, lolwut, memory, migrate, pubsub, replication, scripting, set, slowlog, snapshot,
+    sort, stream, string, unknown_command, wrong_args, zset,
+};
+use crate::db::Keyspace;
+use crate::plugin::{CommandPlugin, Plugin};
+use crate::pubsub::PubSub;
+use crate::resp::Value;
+use crate::server::{Connection, Steps};
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+/// What a command runs with: the client that sent it, the server's shared state, and every
+/// database, with at least the shards holding the command's keys locked.
+pub struct Context<'a> {
+    pub client: &'a mut Client,
+    pub pubsub: &'a PubSub,
+    pub blocked: &'a BlockedClients,
+    pub dbs: &'a mut [Keyspace],
+}
+
+impl Context<'_> {
+    /// The database the client has selected.
+    pub fn db(&mut self) -> &mut Keyspace {
+        &mut self.dbs[self.client.db]
+    }
+}
+
+/// A command the server knows: what COMMAND INFO says about it, and how it's run.
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The Redis arity. Positive means exactly that many arguments counting the command name,
+    /// negative means at least that many.
+    fn arity(&self) -> i32;
+
+    /// The `[first, last, step]` key positions as in COMMAND INFO, counting the name as 0, with
+    /// a negative `last` counting from the end. `[0, 0, 0]` means no keys at fixed positions.
+    fn keys(&self) -> [i32; 3];
+
+    /// The most arguments it takes, counting its name, for a command whose arity has no upper
+    /// bound of its own.
+    fn max_arity(&self) -> Option<i32> {
+        None
+    }
+
+    /// Whether it only ever reads keys, so it can run with read locks.
+    fn reads_only(&self) -> bool {
+        false
+    }
+
+    /// The flags COMMAND INFO shows, worked out from the command's ACL categories.
+    fn flags(&self) -> Vec<&'static str> {
+        cmd::command_flags(self.name())
+    }
+
+    /// What a connection runs in place of [`Command::execute`], for a command that changes what
+    /// the connection does.
+    fn connection(&self) -> Option<ConnectionHandler> {
+        None
+    }
+
+    /// Runs the command with `args`, the arguments after its name, which [`check`] has already
+    /// found to be the right number.
+    fn execute(&self, ctx: &mut Context<'_>, args: &[Vec<u8>]) -> Outcome;
+}
+
+/// How a [`Spec`] runs its command.
+#[derive(Clone, Copy)]
+pub enum Handler {
+    /// Only reads the selected database, so it can run with its keys' shards shared.
+    Read(fn(&Keyspace, &[Vec<u8>]) -> Value),
+    /// Needs nothing but the selected database.
+    Keyspace(fn(&mut Keyspace, &[Vec<u8>]) -> Value),
+    /// Needs nothing but the selected database, but may have to wait for a key to be served.
+    Blocking(fn(&mut Keyspace, &[Vec<u8>]) -> Outcome),
+    /// Needs the client or the rest of the server.
+    Server(fn(&mut Context<'_>, &[Vec<u8>]) -> Value),
+    /// Changes what the connection itself does, so only a connection runs it, with the
+    /// [`ConnectionHandler`] its spec has: the subscribe family, transaction control, and the
+    /// commands that close the connection or hand it over.
+    Connection,
+}
+
+/// How a connection runs a command that changes what it does, with the arguments after its
+/// name.
+pub type ConnectionHandler = for<'a, 'b> fn(&'a mut Connection<'b>, &'a [Vec<u8>]) -> Steps<'a>;
+
+/// A command as each module lists its own.
+pub struct Spec {
+    pub name: &'static str,
+    pub arity: i32,
+    pub keys: [i32; 3],
+    /// The most arguments it takes, counting its name, or 0 for as many as its arity allows.
+    pub max: i32,
+    pub handler: Handler,
+    /// What a connection runs instead of the handler.
+    pub connection: Option<ConnectionHandler>,
+}
+
+impl Spec {
+    pub const fn read(
+        name: &'static str,
+        arity: i32,
+        keys: [i32; 3],
+        run: fn(&Keyspace, &[Vec<u8>]) -> Value,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            keys,
+            max: 0,
+            handler: Handler::Read(run),
+            connection: None,
+        }
+    }
+
+    pub const fn keyspace(
+        name: &'static str,
+        arity: i32,
+        keys: [i32; 3],
+        run: fn(&mut Keyspace, &[Vec<u8>]) -> Value,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            keys,
+            max: 0,
+            handler: Handler::Keyspace(run),
+            connection: None,
+        }
+    }
+
+    pub const fn blocking(
+        name: &'static str,
+        arity: i32,
+        keys: [i32; 3],
+        run: fn(&mut Keyspace, &[Vec<u8>]) -> Outcome,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            keys,
+            max: 0,
+            handler: Handler::Blocking(run),
+            connection: None,
+        }
+    }
+
+    pub const fn server(
+        name: &'static str,
+        arity: i32,
+        keys: [i32; 3],
+        run: fn(&mut Context<'_>, &[Vec<u8>]) -> Value,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            keys,
+            max: 0,
+            handler: Handler::Server(run),
+            connection: None,
+        }
+    }
+
+    pub const fn connection(
+        name: &'static str,
+        arity: i32,
+        keys: [i32; 3],
+        run: ConnectionHandler,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            keys,
+            max: 0,
+            handler: Handler::Connection,
+            connection: Some(run),
+        }
+    }
+
+    /// Caps the arguments a command with a negative arity takes at `max`, counting its name.
+    pub const fn at_most(self, max: i32) -> Self {
+        Self { max, ..self }
+    }
+
+    /// Has a connection run `run` for the command, rather than the handler, which is left for
+    /// where it can't change what a connection does, like a transaction's queue.
+    pub const fn on_connection(self, run: ConnectionHandler) -> Self {
+        Self {
+            connection: Some(run),
+            ..self
+        }
+    }
+}
+
+impl Command for Spec {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i32 {
+        self.arity
+    }
+
+    fn keys(&self) -> [i32; 3] {
+        self.keys
+    }
+
+    fn max_arity(&self) -> Option<i32> {
+        (self.max > 0).then_some(self.max)
+    }
+
+    fn reads_only(&self) -> bool {
+        matches!(self.handler, Handler::Read(_))
+    }
+
+    fn connection(&self) -> Option<ConnectionHandler> {
+        self.connection
+    }
+
+    fn execute(&self, ctx: &mut Context<'_>, args: &[Vec<u8>]) -> Outcome {
+        match self.handler {
+            Handler::Read(run) => run(ctx.db(), args).into(),
+            Handler::Keyspace(run) => run(ctx.db(), args).into(),
+            Handler::Blocking(run) => run(ctx.db(), args),
+            Handler::Server(run) => run(ctx, args).into(),
+            Handler::Connection => Value::error(format!("Invalid command: {}", self.name)).into(),
+        }
+    }
+}
+
+/// Every module's commands, in the order COMMAND lists them.
+static TABLES: &[&[Spec]] = &[
+    client::COMMANDS,
+    acl::COMMANDS,
+    pubsub::COMMANDS,
+    scripting::COMMANDS,
+    config::COMMANDS,
+    info::COMMANDS,
+    command::COMMANDS,
+    slowlog::COMMANDS,
+    latency::COMMANDS,
+    debug::COMMANDS,
+    snapshot::COMMANDS,
+    replication::COMMANDS,
+    cluster::COMMANDS,
+    lolwut::COMMANDS,
+    string::COMMANDS,
+    bitmap::COMMANDS,
+    hll::COMMANDS,
+    keyspace::COMMANDS,
+    migrate::COMMANDS,
+    memory::COMMANDS,
+    sort::COMMANDS,
+    list::COMMANDS,
+    hash::COMMANDS,
+    set::COMMANDS,
+    zset::COMMANDS,
+    stream::COMMANDS,
+    geo::COMMANDS,
+];
+
+struct Registry {
+    commands: Vec<&'static dyn Command>,
+    by_name: HashMap<&'static str, &'static dyn Command>,
+}
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(|| {
+    let commands: Vec<&'static dyn Command> = TABLES
+        .iter()
+        .flat_map(|table| table.iter())
+        .map(|spec| spec as &'static dyn Command)
+        .collect();
+    let mut by_name = HashMap::new();
+    for &command in &commands {
+        let name = command.name();
+        assert!(
+            by_name.insert(name, command).is_none(),
+            "command '{name}' is registered twice"
+        );
+    }
+
+    Registry { commands, by_name }
+});
+
+/// The commands the embedding program added to the server it last built, looked up after the
+/// server's own. There are only ever a handful.
+static PLUGINS: RwLock<Vec<&'static Plugin>> = RwLock::new(Vec::new());
+
+/// Builds the registry, which is otherwise built the first time a command is looked up, with
+/// `plugins` in place of whatever the server before added.
+pub fn build(plugins: Vec<Box<dyn CommandPlugin>>) -> anyhow::Result<()> {
+    LazyLock::force(&REGISTRY);
+
+    let mut added: Vec<&'static Plugin> = Vec::new();
+    for plugin in plugins {
+        let plugin = Plugin::new(plugin)?;
+        let name = plugin.name();
+        if REGISTRY.by_name.contains_key(name) || added.iter().any(|p| p.name() == name) {
+            anyhow::bail!("There's already a command called '{name}'");
+        }
+        added.push(Box::leak(Box::new(plugin)));
+    }
+    *PLUGINS.write().unwrap() = added;
+    Ok(())
+}
+
+/// The command the embedding program added as `name`, if it added one.
+pub fn plugin(name: &str) -> Option<&'static Plugin> {
+    PLUGINS
+        .read()
+        .unwrap()
+        .iter()
+        .copied()
+        .find(|plugin| plugin.name() == name)
+}
+
+/// The command called `name`, by its real name.
+pub fn get(name: &str) -> Option<&'static dyn Command> {
+    match REGISTRY.by_name.get(name) {
+        Some(&command) => Some(command),
+        None => plugin(name).map(|plugin| plugin as &'static dyn Command),
+    }
+}
+
+/// Every command, in the order COMMAND lists them: the server's own, then those added to it.
+pub fn all() -> impl Iterator<Item = &'static dyn Command> {
+    let plugins = PLUGINS.read().unwrap().clone();
+    REGISTRY.commands.iter().copied().chain(
+        plugins
+            .into_iter()
+            .map(|plugin| plugin as &'static dyn Command),
+    )
+}
+
+/// The command called `name`, if there's such a command and `args` are a plausible number of
+/// arguments for it.
+pub fn check(name: &str, args: &[Vec<u8>]) -> Result<&'static dyn Command, Value> {
+    let Some(command) = get(name) else {
+        return Err(unknown_command(name, args));
+    };
+    if !takes(command, args) {
+        return Err(wrong_args(name));
+    }
+
+    Ok(command)
+}
+
+/// Whether `args`, the arguments after `command`'s name, are as many as it takes.
+fn takes(command: &dyn Command, args: &[Vec<u8>]) -> bool {
+    let (arity, given) = (command.arity(), args.len() as i32 + 1);
+    if arity >= 0 {
+        return given == arity;
+    }
+
+    given >= -arity && command.max_arity().is_none_or(|max| given <= max)
+}
+
+/// Runs command `name` for `ctx`'s client with `args`, which [`check`] has already found to be
+/// the right number. `None` means there's no such command.
+pub fn run(ctx: &mut Context<'_>, name: &str, args: &[Vec<u8>]) -> Option<Outcome> {
+    Some(get(name)?.execute(ctx, args))
+}
+
+/// A client, with the server state around it, for unit tests to run commands as.
+#[cfg(test)]
+pub(crate) struct TestClient {
+    client: Client,
+    pubsub: std::sync::Arc<PubSub>,
+    blocked: BlockedClients,
+}
+
+#[cfg(test)]
+impl TestClient {
+    pub(crate) fn new() -> TestClient {
+        let pubsub = std::sync::Arc::new(PubSub::default());
+        let addr = "127.0.0.1:6379".parse().unwrap();
+
+        TestClient {
+            client: Client::new(pubsub.clone(), addr, addr),
+            pubsub,
+            blocked: BlockedClients::default(),
+        }
+    }
+
+    /// What the client's commands run with, against `dbs`.
+    pub(crate) fn context<'a>(&'a mut self, dbs: &'a mut [Keyspace]) -> Context<'a> {
+        Context {
+            client: &mut self.client,
+            pubsub: &self.pubsub,
+            blocked: &self.blocked,
+            dbs,
+        }
+    }
+
+    /// Runs `parts`, a command's name and then its arguments, against `dbs`, returning the
+    /// reply, or the error a connection would give if they aren't a command. The command
+    /// mustn't block.
+    pub(crate) fn run(&mut self, dbs: &mut [Keyspace], parts: &[&str]) -> Value {
+        let args: Vec<_> = parts[1..]
+            .iter()
+            .map(|arg| arg.as_bytes().to_vec())
+            .collect();
+        let command = match check(parts[0], &args) {
+            Ok(command) => command,
+            Err(e) => return e,
+        };
+        match command.execute(&mut self.context(dbs), &args) {
+            Outcome::Reply(reply) => reply,
+            Outcome::Block(_) => panic!("{} blocked", parts[0]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use bytes::Bytes;
+    use std::collections::HashSet;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn reply(outcome: Option<Outcome>) -> Value {
+        match outcome {
+            Some(Outcome::Reply(reply)) => reply,
+            Some(Outcome::Block(_)) => panic!("the command blocked"),
+            None => panic!("no such command"),
+        }
+    }
+
+    fn assert_reply(outcome: Option<Outcome>, expected: Value) {
+        assert_eq!(format!("{:?}", reply(outcome)), format!("{expected:?}"));
+    }
+
+    #[test]
+    fn every_command_is_registered_once() {
+        let names: Vec<_> = all().map(|command| command.name()).collect();
+        let unique: HashSet<_> = names.iter().collect();
+
+        assert_eq!(names.len(), unique.len());
+        for name in names {
+            assert_eq!(get(name).map(|command| command.name()), Some(name));
+        }
+    }
+
+    #[test]
+    fn every_categorised_command_is_registered() {
+        for category in cmd::category_names() {
+            for name in cmd::category_commands(category).unwrap() {
+                assert!(
+                    get(name).is_some(),
+                    "'{name}' in @{category} isn't a command"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn keys_are_within_arity() {
+        for command in all() {
+            let [first, last, step] = command.keys();
+            if first == 0 {
+                assert_eq!([last, step], [0, 0], "{}", command.name());
+                continue;
+            }
+            assert!(step > 0, "{}", command.name());
+            // A fixed arity has to leave room for the keys
+            if command.arity() > 0 {
+                assert!(first < command.arity(), "{}", command.name());
+                assert!(last < command.arity(), "{}", command.name());
+            }
+        }
+    }
+
+    #[test]
+    fn check_rejects_unknown_commands_and_wrong_arity() {
+        assert!(check("nosuchcommand", &[]).is_err());
+        assert!(check("get", &[]).is_err());
+        assert!(check("get", &args(&["a", "b"])).is_err());
+        assert!(check("set", &args(&["a"])).is_err());
+
+        assert_eq!(check("get", &args(&["a"])).unwrap().name(), "get");
+        assert_eq!(
+            check("set", &args(&["a", "b", "ex", "1"])).unwrap().name(),
+            "set"
+        );
+        assert_eq!(check("ping", &[]).unwrap().name(), "ping");
+    }
+
+    /// Stand-ins for arguments: keys that hold each type, numbers that are and aren't in range,
+    /// and the keywords commands look for.
+    const JUNK: &[&str] = &[
+        "s",
+        "l",
+        "h",
+        "z",
+        "t",
+        "missing",
+        "0",
+        "1",
+        "-1",
+        "3",
+        "1.5",
+        "nan",
+        "+inf",
+        "-inf",
+        "(1",
+        "[a",
+        "-",
+        "+",
+        "*",
+        "",
+        "x",
+        "99999999999999999999",
+        "withscores",
+        "limit",
+        "count",
+        "ex",
+        "nx",
+        "get",
+        "rev",
+        "byscore",
+        "bylex",
+        "keepttl",
+        "numkeys",
+    ];
+
+    fn junk(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|_| JUNK[crate::rand::below(JUNK.len())].as_bytes().to_vec())
+            .collect()
+    }
+
+    /// How many arguments `command` takes at most, if there's a limit.
+    fn most_args(command: &dyn Command) -> Option<usize> {
+        match (command.arity(), command.max_arity()) {
+            (arity, _) if arity >= 0 => Some(arity as usize - 1),
+            (_, max) => max.map(|max| max as usize - 1),
+        }
+    }
+
+    /// What the junk is drawn from, fixed so a failing case comes up again on the next run.
+    const SEED: u64 = 0x6a75_6e6b;
+
+    type Case = (&'static str, Vec<Vec<u8>>);
+
+    /// Every command with too few arguments, and too many for those with a limit.
+    fn wrong_counts() -> Vec<Case> {
+        crate::rand::reseed(SEED);
+        let mut cases = Vec::new();
+        for command in all() {
+            let least = command.arity().unsigned_abs() as usize - 1;
+            let too_many = most_args(command).map_or(0..0, |most| most + 1..most + 4);
+            for count in (0..least).chain(too_many) {
+                cases.push((command.name(), junk(count)));
+            }
+        }
+        cases
+    }
+
+    /// The commands that only need a database, each with junk for arguments a number of times.
+    fn malformed() -> Vec<Case> {
+        crate::rand::reseed(SEED);
+        let specs = TABLES
+            .iter()
+            .flat_map(|table| table.iter())
+            .filter(|spec| matches!(spec.handler, Handler::Keyspace(_) | Handler::Read(_)));
+        let mut cases = Vec::new();
+        for spec in specs {
+            let least = spec.arity.unsigned_abs() as usize - 1;
+            let most = most_args(spec).unwrap_or(least + 4);
+            for _ in 0..20 {
+                let count = least + crate::rand::below(most - least + 1);
+                cases.push((spec.name, junk(count)));
+            }
+        }
+        cases
+    }
+
+    /// Keys of every type for junk to trip over, put back before each case in case it deleted
+    /// them.
+    const FILL: &[&[&str]] = &[
+        &["set", "s", "1"],
+        &["rpush", "l", "a", "1"],
+        &["hset", "h", "f", "1"],
+        &["zadd", "z", "1", "a"],
+        &["sadd", "t", "a"],
+    ];
+
+    /// The replies to `cases`, checked and then run against a database of their own after
+    /// [`FILL`] each time.
+    async fn run_directly(cases: &[Case]) -> Vec<Value> {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let mut ctx = client.context(&mut dbs);
+
+        let mut replies = Vec::new();
+        for (name, args) in cases {
+            for fill in FILL {
+                run(&mut ctx, fill[0], &self::args(&fill[1..]));
+            }
+            replies.push(match check(name, args) {
+                Ok(_) => reply(run(&mut ctx, name, args)),
+                Err(e) => e,
+            });
+        }
+        replies
+    }
+
+    /// Whether `reply` is an error the way Redis gives them, a code and then what went wrong.
+    fn is_well_formed_error(reply: &Value) -> bool {
+        let Some(e) = reply.error_message() else {
+            return false;
+        };
+        e.split_once(' ').is_some_and(|(code, _)| {
+            !code.is_empty() && code.bytes().all(|c| c.is_ascii_uppercase())
+        })
+    }
+
+    #[tokio::test]
+    async fn every_command_rejects_argument_counts_outside_its_arity() {
+        let cases = wrong_counts();
+        for ((name, args), reply) in cases.iter().zip(run_directly(&cases).await) {
+            assert!(check(name, args).is_err(), "{name} with {}", args.len());
+            assert_eq!(
+                format!("{reply:?}"),
+                format!("{:?}", wrong_args(name)),
+                "{name} with {args:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn data_commands_reply_to_malformed_arguments() {
+        let cases = malformed();
+        for ((name, args), reply) in cases.iter().zip(run_directly(&cases).await) {
+            // Anything it replies but a malformed error will do, as long as it doesn't panic
+            let error = reply.error_message().is_some();
+            assert!(
+                !error || is_well_formed_error(&reply),
+                "{name} {args:?}: {reply:?}"
+            );
+        }
+    }
+
+    /// The same cases sent by a client over a connection, where its ACL rules and namespace
+    /// look for their keys before they run, have to get the same errors.
+    #[tokio::test]
+    async fn connections_reply_to_the_same_cases_alike() {
+        let server = crate::TestServer::start().unwrap();
+        let stream = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+        let mut connection = crate::resp::RespHandler::new(stream);
+        let mut send = async |parts: Vec<Vec<u8>>| {
+            let parts = parts.into_iter().map(|part| Value::BulkString(part.into()));
+            connection
+                .write(Value::Array(parts.collect()))
+                .await
+                .unwrap();
+            connection.read().await.unwrap().unwrap()
+        };
+        let user = [
+            "acl",
+            "setuser",
+            "fuzz",
+            "on",
+            ">secret",
+            "~*",
+            "+@all",
+            "namespace:f",
+        ];
+        send(args(&user)).await;
+        send(args(&["auth", "fuzz", "secret"])).await;
+
+        for (name, args) in wrong_counts() {
+            let mut parts = vec![name.as_bytes().to_vec()];
+            parts.extend(args.iter().cloned());
+            let reply = send(parts).await;
+            assert_eq!(
+                format!("{reply:?}"),
+                format!("{:?}", wrong_args(name)),
+                "{name} with {args:?}"
+            );
+        }
+
+        let cases = malformed();
+        for ((name, args), direct) in cases.iter().zip(run_directly(&cases).await) {
+            for fill in FILL {
+                send(self::args(fill)).await;
+            }
+            let mut parts = vec![name.as_bytes().to_vec()];
+            parts.extend(args.iter().cloned());
+            let reply = send(parts).await;
+            assert_eq!(
+                reply.error_message(),
+                direct.error_message(),
+                "{name} {args:?}: {reply:?}, where run directly {direct:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_commands_against_the_selected_database() {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(2);
+        let mut dbs = storage.lock_all().await;
+        let mut ctx = client.context(&mut dbs);
+
+        let ok = || Value::SimpleString("OK".to_string());
+        assert_reply(run(&mut ctx, "set", &args(&["k", "v"])), ok());
+        assert_reply(
+            run(&mut ctx, "get", &args(&["k"])),
+            Value::BulkString(Bytes::from_static(b"v")),
+        );
+        assert_reply(run(&mut ctx, "select", &args(&["1"])), ok());
+        assert_reply(run(&mut ctx, "get", &args(&["k"])), Value::Null);
+        assert_reply(
+            run(&mut ctx, "ping", &[]),
+            Value::SimpleString("PONG".to_string()),
+        );
+        assert!(run(&mut ctx, "nosuchcommand", &[]).is_none());
+    }
+
+    #[tokio::test]
+    async fn connection_commands_are_left_to_the_connection() {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let mut ctx = client.context(&mut dbs);
+
+        for name in ["multi", "exec", "subscribe", "quit", "psync"] {
+            assert!(get(name).unwrap().connection().is_some(), "{name}");
+            let reply = reply(run(&mut ctx, name, &[]));
+            assert!(reply.error_message().is_some(), "{name}");
+        }
+        // Those a transaction can queue run from the queue as well
+        assert!(get("unwatch").unwrap().connection().is_some());
+        assert_reply(
+            run(&mut ctx, "unwatch", &[]),
+            Value::SimpleString("OK".to_string()),
+        );
+        assert!(get("get").unwrap().connection().is_none());
+    }
+}
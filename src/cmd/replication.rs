@@ -0,0 +1,261 @@
+use crate::client::Client;
+use crate::cluster;
+use crate::cmd::{lower, not_an_integer, parse_int, registry::Spec, syntax_error};
+use crate::failover;
+use crate::replication;
+use crate::resp::Value;
+use crate::server::{self, Connection, Step, Steps};
+use std::time::Duration;
+use tracing::info;
+
+/// The replication commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::server("replconf", -1, [0, 0, 0], |ctx, args| {
+        replconf(ctx.client, args)
+    }),
+    Spec::connection("psync", -3, [0, 0, 0], |conn, args| {
+        Box::pin(sync(conn, args, true))
+    }),
+    Spec::connection("sync", 1, [0, 0, 0], |conn, args| {
+        Box::pin(sync(conn, args, false))
+    }),
+    Spec::server("replicaof", 3, [0, 0, 0], |_, args| replicaof(args)),
+    Spec::server("slaveof", 3, [0, 0, 0], |_, args| replicaof(args)),
+    Spec::server("wait", 3, [0, 0, 0], |ctx, args| wait(ctx.client, args))
+        .on_connection(wait_for_replicas),
+    Spec::server("failover", -1, [0, 0, 0], |_, args| failover(args)),
+];
+
+/// REPLCONF option value [option value ...]: what a replica tells its master about itself
+/// before it syncs.
+pub fn replconf(client: &mut Client, args: &[Vec<u8>]) -> Value {
+    if !args.len().is_multiple_of(2) {
+        return syntax_error();
+    }
+
+    for pair in args.chunks(2) {
+        match lower(&pair[0]).as_str() {
+            "listening-port" => match parse_int(&pair[1]) {
+                Some(port) => client.listening_port = port,
+                None => return not_an_integer(),
+            },
+            "capa" if lower(&pair[1]) == "eof" => client.capa_eof = true,
+            // Every other capability a replica might ask for is one the stream already has,
+            // and acknowledgements only mean something once it's streaming
+            "ip-address" | "capa" | "ack" => {}
+            option => {
+                return Value::error(format!("ERR Unrecognized REPLCONF option: {option}"));
+            }
+        }
+    }
+
+    Value::SimpleString("OK".to_string())
+}
+
+/// WAIT numreplicas timeout: how many replicas to wait for to have the client's writes, and for
+/// how long, `None` meaning for as long as it takes.
+pub fn wait_args(args: &[Vec<u8>]) -> Result<(usize, Option<Duration>), Value> {
+    if replication::is_replica() {
+        return Err(Value::error(
+            "ERR WAIT cannot be used with replica instances. Please also note that writes to \
+             replicas are just local and are not propagated.",
+        ));
+    }
+    let [numreplicas, timeout] = args else {
+        return Err(syntax_error());
+    };
+    let Some(numreplicas) = parse_int::<i64>(numreplicas) else {
+        return Err(not_an_integer());
+    };
+    let Some(timeout) = parse_int::<i64>(timeout) else {
+        return Err(Value::error(
+            "ERR timeout is not an integer or out of range",
+        ));
+    };
+    if timeout < 0 {
+        return Err(Value::error("ERR timeout is negative"));
+    }
+
+    let timeout = (timeout > 0).then(|| Duration::from_millis(timeout as u64));
+    Ok((numreplicas.max(0) as usize, timeout))
+}
+
+/// WAIT numreplicas timeout: replies once that many replicas have the client's writes, or the
+/// timeout's up, with how many do.
+fn wait_for_replicas<'a>(conn: &'a mut Connection<'_>, args: &'a [Vec<u8>]) -> Steps<'a> {
+    Box::pin(async move {
+        let (wanted, timeout) = match wait_args(args) {
+            Ok(wait) => wait,
+            Err(e) => return Step::Reply(e),
+        };
+        let acked = replication::wait_for_acks(conn.client.repl_offset, wanted, timeout);
+        tokio::select! {
+            acked = acked => Step::Reply(Value::Integer(acked as i64)),
+            _ = conn.handler.closed() => Step::Close,
+            _ = conn.killed.killed() => Step::Close,
+        }
+    })
+}
+
+/// PSYNC replid offset, or SYNC, the older form: the connection becomes a replica's, carrying
+/// nothing but the replication stream from here on.
+async fn sync(conn: &mut Connection<'_>, args: &[Vec<u8>], psync: bool) -> Step {
+    // Accounted for now, as it isn't done with until the replica goes
+    let name = if psync { "psync" } else { "sync" };
+    conn.account(name, args, false);
+    // A master failing over to this replica asks it to take over first
+    if let [replid, _, flag] = args
+        && lower(flag) == "failover"
+    {
+        if !replication::is_replica() || replid.as_slice() != replication::replid().as_bytes() {
+            return Step::Reply(Value::error(
+                "ERR PSYNC FAILOVER replid must match my replid.",
+            ));
+        }
+        replication::follow(None);
+        info!(
+            "MASTER MODE enabled (failover request from '{}')",
+            conn.addr
+        );
+    }
+    // PSYNC replid offset asks to carry on from where the replica got to
+    let resume = match args {
+        [replid, offset, ..] if psync => {
+            let offset = String::from_utf8_lossy(offset).parse().ok();
+            offset.map(|offset| (String::from_utf8_lossy(replid), offset))
+        }
+        _ => None,
+    };
+    let client = &*conn.client;
+    let sync = replication::attach(
+        client.id,
+        conn.addr,
+        client.listening_port,
+        resume
+            .as_ref()
+            .map(|(replid, offset)| (replid.as_ref(), *offset)),
+    );
+    server::serve_replica(conn.handler, client, conn.addr, conn.killed, psync, sync).await;
+    replication::detach(client.id);
+    Step::Close
+}
+
+/// WAIT numreplicas timeout inside a transaction, where it can't block, so it only says how
+/// many replicas already have the client's writes.
+pub fn wait(client: &Client, args: &[Vec<u8>]) -> Value {
+    match wait_args(args) {
+        Ok(_) => Value::Integer(replication::acked(client.repl_offset) as i64),
+        Err(e) => e,
+    }
+}
+
+/// FAILOVER [TO host port [FORCE]] [ABORT] [TIMEOUT ms]: hand the master's role over to a
+/// replica without losing writes, or call off a failover under way.
+fn failover(args: &[Vec<u8>]) -> Value {
+    // A cluster's nodes fail over by electing a replica, and replicate with CLUSTER REPLICATE
+    if cluster::enabled() {
+        return Value::error("ERR FAILOVER not allowed in cluster mode.");
+    }
+    let mut target = None;
+    let mut force = false;
+    let mut abort = false;
+    let mut timeout = None;
+    let mut i = 0;
+    while i < args.len() {
+        match (lower(&args[i]).as_str(), &args[i + 1..]) {
+            ("to", [host, port, ..]) if target.is_none() => {
+                let Some(port) = parse_int::<u16>(port) else {
+                    return not_an_integer();
+                };
+                target = Some((String::from_utf8_lossy(host).into_owned(), port));
+                i += 3;
+            }
+            ("force", _) if !force => {
+                force = true;
+                i += 1;
+            }
+            ("abort", _) if !abort => {
+                abort = true;
+                i += 1;
+            }
+            ("timeout", [ms, ..]) if timeout.is_none() => {
+                let Some(ms) = parse_int::<i64>(ms) else {
+                    return not_an_integer();
+                };
+                if ms <= 0 {
+                    return Value::error("ERR FAILOVER timeout must be greater than 0");
+                }
+                timeout = Some(Duration::from_millis(ms as u64));
+                i += 2;
+            }
+            _ => return syntax_error(),
+        }
+    }
+
+    if abort {
+        if target.is_some() || force || timeout.is_some() {
+            return syntax_error();
+        }
+        if !failover::in_progress() {
+            return Value::error("ERR No failover in progress.");
+        }
+        failover::abort();
+        info!("FAILOVER aborted by user request.");
+        return Value::SimpleString("OK".to_string());
+    }
+
+    if force && (target.is_none() || timeout.is_none()) {
+        return Value::error(
+            "ERR FAILOVER with force option requires both a timeout and target HOST and IP.",
+        );
+    }
+    if replication::is_replica() {
+        return Value::error("ERR FAILOVER is not valid when server is a replica.");
+    }
+    let replicas = replication::replicas();
+    if replicas.is_empty() {
+        return Value::error("ERR FAILOVER requires connected replicas.");
+    }
+    if failover::in_progress() {
+        return Value::error("ERR FAILOVER already in progress.");
+    }
+    if let Some((host, port)) = &target
+        && !replicas.iter().any(|replica| {
+            replica.addr.ip().to_canonical().to_string() == *host && replica.port == *port
+        })
+    {
+        return Value::error("ERR FAILOVER target HOST and PORT is not a replica.");
+    }
+
+    failover::start(target, timeout, force);
+
+    Value::SimpleString("OK".to_string())
+}
+
+/// REPLICAOF host port | NO ONE: follow a master, replacing the dataset with its own, or stop
+/// following one and take writes again.
+fn replicaof(args: &[Vec<u8>]) -> Value {
+    if cluster::enabled() {
+        return Value::error("ERR REPLICAOF not allowed in cluster mode.");
+    }
+    let [host, port] = args else {
+        return syntax_error();
+    };
+    if lower(host) == "no" && lower(port) == "one" {
+        if replication::follow(None) {
+            info!("MASTER MODE enabled (user request)");
+        }
+        return Value::SimpleString("OK".to_string());
+    }
+
+    let Some(port) = parse_int(port) else {
+        return not_an_integer();
+    };
+    let host = String::from_utf8_lossy(host).into_owned();
+    if !replication::follow(Some((host.clone(), port))) {
+        return Value::SimpleString("OK Already connected to specified master".to_string());
+    }
+    info!("REPLICAOF {host}:{port} enabled (user request)");
+
+    Value::SimpleString("OK".to_string())
+}
@@ -0,0 +1,108 @@
+use crate::cmd::{lower, not_an_integer, parse_int, syntax_error};
+use crate::glob::glob_match;
+use crate::resp::Value;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+pub struct ScanOptions {
+    pub cursor: u64,
+    pub pattern: Option<Vec<u8>>,
+    pub count: usize,
+    pub no_values: bool,
+}
+
+impl ScanOptions {
+    pub fn matches(&self, item: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, item, false))
+    }
+}
+
+/// Parses `cursor [MATCH pattern] [COUNT count]`, plus `NOVALUES` where the command allows it.
+pub fn parse_scan_options(args: &[Vec<u8>], allow_no_values: bool) -> Result<ScanOptions, Value> {
+    let Some(cursor) = args.first().and_then(|arg| parse_int::<u64>(arg)) else {
+        return Err(Value::error("ERR invalid cursor"));
+    };
+
+    let mut opts = ScanOptions {
+        cursor,
+        pattern: None,
+        count: 10,
+        no_values: false,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match (lower(&args[i]).as_str(), args.get(i + 1)) {
+            ("match", Some(pattern)) => {
+                opts.pattern = Some(pattern.clone());
+                i += 1;
+            }
+            ("count", Some(count)) => {
+                match parse_int::<i64>(count) {
+                    Some(n) if n >= 1 => opts.count = n as usize,
+                    Some(_) => return Err(syntax_error()),
+                    None => return Err(not_an_integer()),
+                }
+                i += 1;
+            }
+            ("novalues", _) if allow_no_values => opts.no_values = true,
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    Ok(opts)
+}
+
+fn cursor_hash(item: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Selects the next batch of roughly `count` items in a fixed hash order, starting from
+/// `cursor`. Because the order only depends on each item's own hash, items present for the
+/// whole iteration are returned exactly once even if the collection changes in between calls.
+/// Returns the cursor for the next call, which is 0 once the iteration is complete.
+pub fn scan_batch<'a, T>(
+    items: impl Iterator<Item = (&'a [u8], T)>,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<(&'a [u8], T)>) {
+    let mut candidates: Vec<(u64, &[u8], T)> = items
+        .filter_map(|(item, extra)| {
+            let hash = cursor_hash(item);
+            (hash >= cursor).then_some((hash, item, extra))
+        })
+        .collect();
+
+    let mut next = 0;
+    if candidates.len() > count {
+        candidates.select_nth_unstable_by_key(count - 1, |c| c.0);
+        let boundary = candidates[count - 1].0;
+
+        // Items sharing the boundary hash all go out together, or one of them could be skipped
+        if candidates.iter().any(|c| c.0 > boundary) {
+            next = boundary.checked_add(1).unwrap_or(0);
+        }
+        candidates.retain(|c| c.0 <= boundary);
+    }
+
+    candidates.sort_unstable_by_key(|c| c.0);
+
+    (
+        next,
+        candidates
+            .into_iter()
+            .map(|(_, item, extra)| (item, extra))
+            .collect(),
+    )
+}
+
+pub fn scan_reply(cursor: u64, items: Vec<Value>) -> Value {
+    Value::Array(vec![
+        Value::BulkString(cursor.to_string().into()),
+        Value::Array(items),
+    ])
+}
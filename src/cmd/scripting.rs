@@ -0,0 +1,325 @@
+use crate::acl;
+use crate::blocking::{self, Outcome};
+use crate::cmd::registry::{Context, Spec};
+use crate::cmd::{lower, not_an_integer, parse_int, syntax_error, wrong_args};
+use crate::db;
+use crate::dump;
+use crate::function::{self, RestorePolicy};
+use crate::glob::glob_match;
+use crate::namespace;
+use crate::resp::Value;
+use crate::script::{self, Call};
+use bytes::Bytes;
+
+/// The scripting commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::server("eval", -3, [0, 0, 0], |ctx, args| {
+        run_script(ctx, |call| eval(args, call))
+    }),
+    Spec::server("evalsha", -3, [0, 0, 0], |ctx, args| {
+        run_script(ctx, |call| evalsha(args, call))
+    }),
+    Spec::server("script", -2, [0, 0, 0], |_, args| script(args)),
+    Spec::server("fcall", -3, [0, 0, 0], |ctx, args| {
+        run_script(ctx, |call| fcall(args, false, call))
+    }),
+    Spec::server("fcall_ro", -3, [0, 0, 0], |ctx, args| {
+        run_script(ctx, |call| fcall(args, true, call))
+    }),
+    Spec::server("function", -2, [0, 0, 0], |_, args| function(args)),
+];
+
+/// Runs a script with `run`, which gets what the script needs to call commands: each is checked
+/// against the client's ACL and run the way the client's own commands are. A script's SELECT
+/// lasts only as long as the script.
+fn run_script(ctx: &mut Context<'_>, run: impl FnOnce(&mut Call<'_>) -> Value) -> Value {
+    let Context {
+        client,
+        pubsub,
+        blocked,
+        dbs,
+    } = ctx;
+    let selected = client.db;
+    let mut call = |name: &str, args: &[Vec<u8>]| {
+        let user = client.user.clone().unwrap_or_default();
+        if let Err(e) = acl::check(&user, name, args) {
+            return e;
+        }
+        let args = &namespace::qualified(client.user.as_deref(), name, args);
+        let reply = match crate::server::execute(client, pubsub, blocked, dbs, name, args) {
+            Outcome::Reply(reply) => reply,
+            Outcome::Block(block) => block.attempt_now(&mut dbs[client.db]),
+        };
+        // Clients blocked on a key the script pushed to wait for the lock to be released
+        for key in blocking::ready_keys(name, args) {
+            blocked.signal(client.db, key);
+        }
+        reply
+    };
+
+    let reply = run(&mut call);
+    client.db = selected;
+    reply
+}
+
+/// Reads the `numkeys` in `numkeys key [key ...] arg [arg ...]`, checking there are that many
+/// keys after it.
+fn numkeys(args: &[Vec<u8>]) -> Result<usize, Value> {
+    let numkeys: i64 = parse_int(&args[0]).ok_or_else(not_an_integer)?;
+
+    if numkeys < 0 {
+        return Err(Value::error("ERR Number of keys can't be negative"));
+    }
+    if numkeys as usize > args.len() - 1 {
+        return Err(Value::error(
+            "ERR Number of keys can't be greater than number of args",
+        ));
+    }
+
+    Ok(numkeys as usize)
+}
+
+/// EVAL script numkeys [key ...] [arg ...]. `call` runs the commands the script issues; the
+/// caller holds the keyspace lock throughout, so no other client sees the script half done.
+pub fn eval(args: &[Vec<u8>], call: &mut Call<'_>) -> Value {
+    if args.len() < 2 {
+        return wrong_args("eval");
+    }
+
+    let (keys, argv) = match numkeys(&args[1..]) {
+        Ok(numkeys) => args[2..].split_at(numkeys),
+        Err(e) => return e,
+    };
+    script::cache(&args[0]);
+
+    script::run(&args[0], keys, argv, call)
+}
+
+/// EVALSHA sha1 numkeys [key ...] [arg ...]: EVAL for a script already in the cache.
+pub fn evalsha(args: &[Vec<u8>], call: &mut Call<'_>) -> Value {
+    if args.len() < 2 {
+        return wrong_args("evalsha");
+    }
+
+    let (keys, argv) = match numkeys(&args[1..]) {
+        Ok(numkeys) => args[2..].split_at(numkeys),
+        Err(e) => return e,
+    };
+    let Some(body) = script::cached(&String::from_utf8_lossy(&args[0])) else {
+        return Value::error("NOSCRIPT No matching script. Please use EVAL.");
+    };
+
+    script::run(&body, keys, argv, call)
+}
+
+/// SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC] | KILL. A KILL that gets
+/// this far found no script running, since one holds every lock: while it's busy, connections
+/// answer KILL themselves.
+fn script(args: &[Vec<u8>]) -> Value {
+    if args.is_empty() {
+        return wrong_args("script");
+    }
+
+    let subcommand = lower(&args[0]);
+    match (subcommand.as_str(), &args[1..]) {
+        ("load", [body]) => Value::BulkString(script::cache(body).into()),
+        ("exists", shas) if !shas.is_empty() => Value::Array(
+            shas.iter()
+                .map(|sha| {
+                    let found = script::cached(&String::from_utf8_lossy(sha)).is_some();
+                    Value::Integer(found as i64)
+                })
+                .collect(),
+        ),
+        // Dropping the cache is quick, so ASYNC doesn't need a background thread
+        ("flush", [] | [_]) => {
+            if let Some(mode) = args.get(1)
+                && !matches!(lower(mode).as_str(), "async" | "sync")
+            {
+                return syntax_error();
+            }
+
+            script::flush();
+            Value::SimpleString("OK".to_string())
+        }
+        ("kill", []) => script::kill(false),
+        ("load" | "exists" | "flush" | "kill", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try SCRIPT HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try SCRIPT HELP."
+        )),
+    }
+}
+
+/// FCALL function numkeys [key ...] [arg ...], and FCALL_RO when `read_only`, which only runs
+/// functions flagged `no-writes`.
+pub fn fcall(args: &[Vec<u8>], read_only: bool, call: &mut Call<'_>) -> Value {
+    if args.len() < 2 {
+        return wrong_args(if read_only { "fcall_ro" } else { "fcall" });
+    }
+
+    let (keys, argv) = match numkeys(&args[1..]) {
+        Ok(numkeys) => args[2..].split_at(numkeys),
+        Err(e) => return e,
+    };
+    let name = String::from_utf8_lossy(&args[0]);
+    let Some((function, code)) = function::find(&name) else {
+        return Value::error("ERR Function not found");
+    };
+    if read_only && !function.is_read_only() {
+        return Value::error("ERR Can not execute a script with write flag using *_ro command.");
+    }
+
+    script::call_function(&code, &name, keys, argv, function.is_read_only(), call)
+}
+
+/// FUNCTION LOAD [REPLACE] code | DELETE library | FLUSH [ASYNC|SYNC]
+/// | LIST [LIBRARYNAME pattern] [WITHCODE] | DUMP | RESTORE payload [FLUSH|APPEND|REPLACE]
+/// | KILL, which like SCRIPT KILL only gets here with no function running.
+fn function(args: &[Vec<u8>]) -> Value {
+    if args.is_empty() {
+        return wrong_args("function");
+    }
+
+    let subcommand = lower(&args[0]);
+    let reply = match (subcommand.as_str(), &args[1..]) {
+        ("load", [code]) => load_reply(function::load(code, false)),
+        ("load", [option, code]) if lower(option) == "replace" => {
+            load_reply(function::load(code, true))
+        }
+        ("load", [_, _]) => Value::error(format!(
+            "ERR Unknown option given: {}",
+            String::from_utf8_lossy(&args[1])
+        )),
+        ("delete", [library]) => {
+            if function::delete(&String::from_utf8_lossy(library)) {
+                Value::SimpleString("OK".to_string())
+            } else {
+                Value::error("ERR Library not found")
+            }
+        }
+        ("flush", [] | [_]) => {
+            if let Some(mode) = args.get(1)
+                && !matches!(lower(mode).as_str(), "async" | "sync")
+            {
+                return syntax_error();
+            }
+
+            function::flush();
+            Value::SimpleString("OK".to_string())
+        }
+        ("list", options) => list(options),
+        ("kill", []) => script::kill(true),
+        ("dump", []) => {
+            let codes: Vec<Vec<u8>> = function::libraries()
+                .into_iter()
+                .map(|library| library.code)
+                .collect();
+            Value::BulkString(dump::dump_functions(&codes).into())
+        }
+        ("restore", [payload, options @ ..]) if options.len() <= 1 => {
+            let policy = match options.first().map(|option| lower(option)).as_deref() {
+                None | Some("append") => RestorePolicy::Append,
+                Some("replace") => RestorePolicy::Replace,
+                Some("flush") => RestorePolicy::Flush,
+                Some(_) => {
+                    return Value::error(
+                        "ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.",
+                    );
+                }
+            };
+            let Ok(codes) = dump::restore_functions(payload) else {
+                return Value::error("ERR payload version or checksum are wrong");
+            };
+
+            match function::restore(&codes, policy) {
+                Ok(()) => Value::SimpleString("OK".to_string()),
+                Err(e) => e,
+            }
+        }
+        ("load" | "delete" | "flush" | "dump" | "restore" | "kill", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try FUNCTION HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try FUNCTION HELP."
+        )),
+    };
+    if matches!(subcommand.as_str(), "load" | "delete" | "flush" | "restore")
+        && reply.error_message().is_none()
+    {
+        db::mark_dirty();
+    }
+
+    reply
+}
+
+fn load_reply(loaded: Result<String, Value>) -> Value {
+    match loaded {
+        Ok(name) => Value::BulkString(name.into()),
+        Err(e) => e,
+    }
+}
+
+/// FUNCTION LIST [LIBRARYNAME pattern] [WITHCODE]
+fn list(options: &[Vec<u8>]) -> Value {
+    let mut pattern = None;
+    let mut with_code = false;
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match lower(option).as_str() {
+            "withcode" if !with_code => with_code = true,
+            "libraryname" if pattern.is_none() => match options.next() {
+                Some(p) => pattern = Some(p),
+                None => return Value::error("ERR library name argument was not given"),
+            },
+            _ => {
+                return Value::error(format!(
+                    "ERR Unknown argument {}",
+                    String::from_utf8_lossy(option)
+                ));
+            }
+        }
+    }
+
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    Value::Array(
+        function::libraries()
+            .into_iter()
+            .filter(|library| {
+                pattern.is_none_or(|pattern| glob_match(pattern, library.name.as_bytes(), false))
+            })
+            .map(|library| {
+                let functions = library
+                    .functions
+                    .iter()
+                    .map(|f| {
+                        Value::Array(vec![
+                            bulk("name"),
+                            bulk(&f.name),
+                            bulk("description"),
+                            f.description.as_deref().map_or(Value::Null, bulk),
+                            bulk("flags"),
+                            Value::Array(f.flags.iter().map(|flag| bulk(flag)).collect()),
+                        ])
+                    })
+                    .collect();
+
+                let mut fields = vec![
+                    bulk("library_name"),
+                    bulk(&library.name),
+                    bulk("engine"),
+                    bulk("LUA"),
+                    bulk("functions"),
+                    Value::Array(functions),
+                ];
+                if with_code {
+                    fields.push(bulk("library_code"));
+                    fields.push(Value::BulkString(library.code.into()));
+                }
+                Value::Array(fields)
+            })
+            .collect(),
+    )
+}
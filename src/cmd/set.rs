@@ -0,0 +1,553 @@
+use crate::cmd::scan::{parse_scan_options, scan_batch, scan_reply};
+use crate::cmd::{
+    lookup, not_an_integer, parse_int, read, registry::Spec, syntax_error, wrong_args, wrong_type,
+};
+use crate::db::{DBData, DBVal, Keyspace};
+use crate::notify::{self, Class};
+use crate::rand;
+use crate::resp::Value;
+use crate::set::Set;
+use bytes::Bytes;
+use std::borrow::Cow;
+
+/// The set commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("sadd", -3, [1, 1, 1], sadd),
+    Spec::keyspace("srem", -3, [1, 1, 1], srem),
+    Spec::read("smembers", 2, [1, 1, 1], smembers),
+    Spec::read("sismember", 3, [1, 1, 1], sismember),
+    Spec::read("smismember", -3, [1, 1, 1], smismember),
+    Spec::read("scard", 2, [1, 1, 1], scard),
+    Spec::read("sinter", -2, [1, -1, 1], sinter),
+    Spec::read("sunion", -2, [1, -1, 1], sunion),
+    Spec::read("sdiff", -2, [1, -1, 1], sdiff),
+    Spec::keyspace("sinterstore", -3, [1, -1, 1], sinterstore),
+    Spec::keyspace("sunionstore", -3, [1, -1, 1], sunionstore),
+    Spec::keyspace("sdiffstore", -3, [1, -1, 1], sdiffstore),
+    Spec::read("sintercard", -3, [0, 0, 0], sintercard),
+    Spec::keyspace("spop", -2, [1, 1, 1], spop).at_most(3),
+    Spec::keyspace("srandmember", -2, [1, 1, 1], srandmember).at_most(3),
+    Spec::keyspace("smove", 4, [1, 2, 1], smove),
+    Spec::keyspace("sscan", -3, [1, 1, 1], sscan),
+];
+
+/// Fetches the set stored at `key`. `Ok(None)` means the key doesn't exist.
+pub fn get_set<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<Option<&'a mut Set>, Value> {
+    match lookup(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data_mut() {
+            DBVal::Set(set) => Ok(Some(set)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+/// Like [`get_set`], for a command that only reads.
+fn read_set<'a>(db: &'a Keyspace, key: &[u8]) -> Result<Option<&'a Set>, Value> {
+    match read(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data() {
+            DBVal::Set(set) => Ok(Some(set)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+fn get_or_create_set<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<&'a mut Set, Value> {
+    if get_set(db, key)?.is_none() {
+        db.insert(key.to_vec(), DBData::new(DBVal::Set(Set::new()), None));
+    }
+
+    Ok(get_set(db, key)?.expect("set was just created"))
+}
+
+/// Drops `key` if its set has no members left.
+fn remove_if_empty(db: &mut Keyspace, key: &[u8]) {
+    if let Some(DBVal::Set(set)) = db.get(key).map(|val| val.data())
+        && set.is_empty()
+    {
+        db.remove(key);
+        notify::emit(Class::Generic, "del", key);
+    }
+}
+
+fn members_reply(members: impl Iterator<Item = impl AsRef<[u8]>>) -> Value {
+    Value::Array(
+        members
+            .map(|member| Value::BulkString(Bytes::copy_from_slice(member.as_ref())))
+            .collect(),
+    )
+}
+
+fn sadd(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let set = match get_or_create_set(db, &args[0]) {
+        Ok(set) => set,
+        Err(e) => return e,
+    };
+
+    let added = args[1..]
+        .iter()
+        .filter(|member| set.insert(member.to_vec()))
+        .count();
+    if added > 0 {
+        notify::emit(Class::Set, "sadd", &args[0]);
+    }
+
+    Value::Integer(added as i64)
+}
+
+fn srem(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let set = match get_set(db, &args[0]) {
+        Ok(Some(set)) => set,
+        Ok(None) => return Value::Integer(0),
+        Err(e) => return e,
+    };
+
+    let removed = args[1..].iter().filter(|member| set.remove(member)).count();
+    if removed > 0 {
+        notify::emit(Class::Set, "srem", &args[0]);
+    }
+
+    remove_if_empty(db, &args[0]);
+
+    Value::Integer(removed as i64)
+}
+
+fn smembers(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    match read_set(db, &args[0]) {
+        Ok(Some(set)) => members_reply(set.iter()),
+        Ok(None) => Value::Array(Vec::new()),
+        Err(e) => e,
+    }
+}
+
+fn sismember(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    match read_set(db, &args[0]) {
+        Ok(Some(set)) => Value::Integer(set.contains(&args[1]) as i64),
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+fn smismember(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    let set = match read_set(db, &args[0]) {
+        Ok(set) => set,
+        Err(e) => return e,
+    };
+
+    Value::Array(
+        args[1..]
+            .iter()
+            .map(|member| {
+                Value::Integer(set.as_ref().is_some_and(|set| set.contains(member)) as i64)
+            })
+            .collect(),
+    )
+}
+
+fn scard(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    match read_set(db, &args[0]) {
+        Ok(Some(set)) => Value::Integer(set.len() as i64),
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+/// Type-checks `keys` and borrows each set, with `None` standing in for missing keys.
+fn load_sets<'a>(db: &'a Keyspace, keys: &[Vec<u8>]) -> Result<Vec<Option<&'a Set>>, Value> {
+    keys.iter().map(|key| read_set(db, key)).collect()
+}
+
+enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+fn combine(db: &Keyspace, keys: &[Vec<u8>], op: SetOp) -> Result<Set, Value> {
+    let sets = load_sets(db, keys)?;
+    let empty = Set::new();
+    let first = sets[0].unwrap_or(&empty);
+
+    Ok(match op {
+        SetOp::Inter => {
+            // Probe from the smallest set, as every member must be in it anyway
+            let Some(smallest) = sets
+                .iter()
+                .map(|set| set.unwrap_or(&empty))
+                .min_by_key(|set| set.len())
+            else {
+                return Ok(Set::new());
+            };
+
+            smallest
+                .iter()
+                .filter(|member| {
+                    sets.iter()
+                        .all(|set| set.is_some_and(|s| s.contains(member)))
+                })
+                .map(Cow::into_owned)
+                .collect()
+        }
+        SetOp::Union => sets
+            .iter()
+            .flatten()
+            .flat_map(|set| set.iter())
+            .map(Cow::into_owned)
+            .collect(),
+        SetOp::Diff => first
+            .iter()
+            .filter(|member| !sets[1..].iter().flatten().any(|set| set.contains(member)))
+            .map(Cow::into_owned)
+            .collect(),
+    })
+}
+
+fn combine_reply(db: &Keyspace, args: &[Vec<u8>], command: &str, op: SetOp) -> Value {
+    if args.is_empty() {
+        return wrong_args(command);
+    }
+
+    match combine(db, args, op) {
+        Ok(set) => members_reply(set.iter()),
+        Err(e) => e,
+    }
+}
+
+fn combine_store(db: &mut Keyspace, args: &[Vec<u8>], command: &str, op: SetOp) -> Value {
+    if args.len() < 2 {
+        return wrong_args(command);
+    }
+
+    let set = match combine(db, &args[1..], op) {
+        Ok(set) => set,
+        Err(e) => return e,
+    };
+    let len = set.len();
+
+    if set.is_empty() {
+        if db.remove(&args[0]).is_some() {
+            notify::emit(Class::Generic, "del", &args[0]);
+        }
+    } else {
+        db.insert(args[0].clone(), DBData::new(DBVal::Set(set), None));
+        notify::emit(Class::Set, command, &args[0]);
+    }
+
+    Value::Integer(len as i64)
+}
+
+fn sinter(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_reply(db, args, "sinter", SetOp::Inter)
+}
+
+fn sunion(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_reply(db, args, "sunion", SetOp::Union)
+}
+
+fn sdiff(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_reply(db, args, "sdiff", SetOp::Diff)
+}
+
+fn sinterstore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_store(db, args, "sinterstore", SetOp::Inter)
+}
+
+fn sunionstore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_store(db, args, "sunionstore", SetOp::Union)
+}
+
+fn sdiffstore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_store(db, args, "sdiffstore", SetOp::Diff)
+}
+
+fn sintercard(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    let num_keys = match parse_int::<i64>(&args[0]) {
+        Some(n) if n > 0 => n as usize,
+        Some(_) => return Value::error("ERR numkeys should be greater than 0"),
+        None => return not_an_integer(),
+    };
+    if num_keys > args.len() - 1 {
+        return Value::error("ERR Number of keys can't be greater than number of args");
+    }
+
+    let keys = &args[1..=num_keys];
+    let limit = match &args[num_keys + 1..] {
+        [] => 0,
+        [option, limit] if option.eq_ignore_ascii_case(b"limit") => match parse_int::<i64>(limit) {
+            Some(n) if n >= 0 => n as usize,
+            Some(_) => return Value::error("ERR LIMIT can't be negative"),
+            None => return not_an_integer(),
+        },
+        _ => return syntax_error(),
+    };
+
+    let sets = match load_sets(db, keys) {
+        Ok(sets) => sets,
+        Err(e) => return e,
+    };
+
+    // Any missing key makes the intersection empty
+    let Some(sets) = sets.into_iter().collect::<Option<Vec<_>>>() else {
+        return Value::Integer(0);
+    };
+    let smallest = sets
+        .iter()
+        .min_by_key(|set| set.len())
+        .expect("at least one key");
+
+    let mut count = 0;
+    for member in smallest.iter() {
+        if sets.iter().all(|set| set.contains(&member)) {
+            count += 1;
+            if count == limit {
+                break;
+            }
+        }
+    }
+
+    Value::Integer(count as i64)
+}
+
+fn spop(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let count = match args.get(1).map(|arg| parse_int::<i64>(arg)) {
+        None => None,
+        Some(Some(n)) if n >= 0 => Some(n),
+        Some(_) => return Value::error("ERR value is out of range, must be positive"),
+    };
+
+    let set = match get_set(db, &args[0]) {
+        Ok(Some(set)) => set,
+        Ok(None) if count.is_some() => return Value::Array(Vec::new()),
+        Ok(None) => return Value::Null,
+        Err(e) => return e,
+    };
+
+    let members: Vec<Vec<u8>> = {
+        let all: Vec<Cow<[u8]>> = set.iter().collect();
+        rand::sample_indexes(all.len(), count.unwrap_or(1))
+            .into_iter()
+            .map(|i| all[i].to_vec())
+            .collect()
+    };
+
+    for member in &members {
+        set.remove(member);
+    }
+    if !members.is_empty() {
+        notify::emit(Class::Set, "spop", &args[0]);
+    }
+    remove_if_empty(db, &args[0]);
+
+    match count {
+        Some(_) => members_reply(members.iter()),
+        None => members
+            .into_iter()
+            .next()
+            .map(|member| Value::BulkString(member.into()))
+            .unwrap_or(Value::Null),
+    }
+}
+
+fn srandmember(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let count = match args.get(1).map(|arg| parse_int::<i64>(arg)) {
+        None => None,
+        Some(Some(n)) => Some(n),
+        Some(None) => return not_an_integer(),
+    };
+
+    let set = match get_set(db, &args[0]) {
+        Ok(Some(set)) => set,
+        Ok(None) if count.is_some() => return Value::Array(Vec::new()),
+        Ok(None) => return Value::Null,
+        Err(e) => return e,
+    };
+
+    let all: Vec<Cow<[u8]>> = set.iter().collect();
+    let picked = rand::sample_indexes(all.len(), count.unwrap_or(1));
+
+    match count {
+        Some(_) => members_reply(picked.into_iter().map(|i| &all[i])),
+        None => Value::BulkString(Bytes::copy_from_slice(&all[picked[0]])),
+    }
+}
+
+fn smove(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let (src, dst, member) = (&args[0], &args[1], &args[2]);
+
+    // Both keys are type-checked before anything is moved
+    let src_set = match get_set(db, src) {
+        Ok(Some(set)) => set,
+        Ok(None) => return Value::Integer(0),
+        Err(e) => return e,
+    };
+    if !src_set.contains(member) {
+        return match get_set(db, dst) {
+            Ok(_) => Value::Integer(0),
+            Err(e) => e,
+        };
+    }
+    if let Err(e) = get_set(db, dst) {
+        return e;
+    }
+
+    if src == dst {
+        return Value::Integer(1);
+    }
+
+    if let Ok(Some(set)) = get_set(db, src) {
+        set.remove(member);
+    }
+    notify::emit(Class::Set, "srem", src);
+    remove_if_empty(db, src);
+
+    match get_or_create_set(db, dst) {
+        Ok(set) => {
+            set.insert(member.clone());
+            notify::emit(Class::Set, "sadd", dst);
+            Value::Integer(1)
+        }
+        Err(e) => e,
+    }
+}
+
+fn sscan(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let opts = match parse_scan_options(&args[1..], false) {
+        Ok(opts) => opts,
+        Err(e) => return e,
+    };
+
+    let set = match get_set(db, &args[0]) {
+        Ok(Some(set)) => set,
+        Ok(None) => return scan_reply(0, Vec::new()),
+        Err(e) => return e,
+    };
+
+    let members: Vec<Cow<[u8]>> = set.iter().collect();
+    let (cursor, batch) = scan_batch(
+        members.iter().map(|member| (member.as_ref(), ())),
+        opts.cursor,
+        opts.count,
+    );
+
+    scan_reply(
+        cursor,
+        batch
+            .into_iter()
+            .filter(|(member, _)| opts.matches(member))
+            .map(|(member, _)| Value::BulkString(Bytes::copy_from_slice(member)))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    /// The members a reply names, sorted, since sets have no order of their own.
+    fn members(reply: Value) -> Vec<Vec<u8>> {
+        let Value::Array(values) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        let mut members: Vec<_> = values
+            .into_iter()
+            .map(|value| match value {
+                Value::BulkString(bytes) => bytes.into(),
+                value => panic!("expected a bulk string, got {value:?}"),
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    #[tokio::test]
+    async fn add_remove_and_check_members() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            sadd(db, &args(&["s", "a", "b", "a"])),
+            Value::Integer(2)
+        ));
+        assert!(matches!(
+            sismember(db, &args(&["s", "a"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(
+            srem(db, &args(&["s", "a", "c"])),
+            Value::Integer(1)
+        ));
+        assert_eq!(members(smembers(db, &args(&["s"]))), args(&["b"]));
+        assert!(matches!(scard(db, &args(&["missing"])), Value::Integer(0)));
+    }
+
+    #[tokio::test]
+    async fn combines_sets() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        sadd(db, &args(&["x", "a", "b", "c"]));
+        sadd(db, &args(&["y", "b", "c", "d"]));
+        assert_eq!(members(sinter(db, &args(&["x", "y"]))), args(&["b", "c"]));
+        assert_eq!(
+            members(sunion(db, &args(&["x", "y"]))),
+            args(&["a", "b", "c", "d"])
+        );
+        assert_eq!(members(sdiff(db, &args(&["x", "y"]))), args(&["a"]));
+
+        assert!(matches!(
+            sinterstore(db, &args(&["z", "x", "missing"])),
+            Value::Integer(0)
+        ));
+        assert!(db.get(b"z".as_slice()).is_none());
+        assert!(matches!(
+            sintercard(db, &args(&["2", "x", "y", "LIMIT", "1"])),
+            Value::Integer(1)
+        ));
+    }
+
+    #[tokio::test]
+    async fn pops_picks_and_moves_members() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        sadd(db, &args(&["s", "a", "b", "c"]));
+
+        // A negative count may pick the same member more than once
+        let Value::Array(picked) = srandmember(db, &args(&["s", "-5"])) else {
+            panic!("SRANDMEMBER with a count replies with an array");
+        };
+        assert_eq!(picked.len(), 5);
+        assert_eq!(members(srandmember(db, &args(&["s", "5"]))).len(), 3);
+
+        assert_eq!(members(spop(db, &args(&["s", "2"]))).len(), 2);
+        assert!(matches!(scard(db, &args(&["s"])), Value::Integer(1)));
+
+        let Value::BulkString(last) = spop(db, &args(&["s"])) else {
+            panic!("SPOP without a count replies with the member");
+        };
+        assert!(db.get(b"s".as_slice()).is_none());
+
+        sadd(db, &args(&["s", "x"]));
+        let mut moving = args(&["s", "t"]);
+        moving.push(last.to_vec());
+        assert!(matches!(smove(db, &moving), Value::Integer(0)));
+        assert!(matches!(
+            smove(db, &args(&["s", "t", "x"])),
+            Value::Integer(1)
+        ));
+        assert_eq!(members(smembers(db, &args(&["t"]))), args(&["x"]));
+
+        let Value::Array(reply) = sscan(db, &args(&["t", "0"])) else {
+            panic!("SSCAN replies with a cursor and a batch");
+        };
+        assert_eq!(members(reply[1].clone()), args(&["x"]));
+    }
+}
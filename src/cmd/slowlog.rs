@@ -0,0 +1,34 @@
+use crate::cmd::{lower, parse_int, registry::Spec};
+use crate::resp::Value;
+use crate::slowlog;
+
+/// SLOWLOG itself.
+pub const COMMANDS: &[Spec] = &[Spec::server("slowlog", -2, [0, 0, 0], |_, args| {
+    slowlog(args)
+})];
+
+/// SLOWLOG GET [count] | LEN | RESET
+pub fn slowlog(args: &[Vec<u8>]) -> Value {
+    let subcommand = lower(&args[0]);
+
+    match (subcommand.as_str(), &args[1..]) {
+        ("get", []) => slowlog::get(10),
+        ("get", [count]) => match parse_int::<i64>(count) {
+            // -1 asks for the whole log
+            Some(-1) => slowlog::get(usize::MAX),
+            Some(count) if count >= 0 => slowlog::get(count as usize),
+            _ => Value::error("ERR count should be greater than or equal to -1"),
+        },
+        ("len", []) => Value::Integer(slowlog::len() as i64),
+        ("reset", []) => {
+            slowlog::reset();
+            Value::SimpleString("OK".to_string())
+        }
+        ("get" | "len" | "reset", _) => Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try SLOWLOG HELP."
+        )),
+        _ => Value::error(format!(
+            "ERR unknown subcommand '{subcommand}'. Try SLOWLOG HELP."
+        )),
+    }
+}
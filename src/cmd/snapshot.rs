@@ -0,0 +1,184 @@
+use crate::aof;
+use crate::cmd::registry::{Context, Spec};
+use crate::cmd::{lookup, lower, syntax_error};
+use crate::config;
+use crate::db::{self, DBData, Keyspace};
+use crate::dump::dump_value;
+use crate::export::{self, Format};
+use crate::notify::{self, Class};
+use crate::propagate;
+use crate::resp::Value;
+use crate::server::{Connection, Step, Steps};
+use crate::shutdown;
+use crate::snapshot;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+/// The persistence commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::connection("shutdown", -1, [0, 0, 0], shutdown),
+    Spec::server("save", 1, [0, 0, 0], |ctx, _| save(ctx.dbs)),
+    Spec::server("bgsave", -1, [0, 0, 0], |ctx, args| bgsave(ctx.dbs, args)),
+    Spec::server("bgrewriteaof", 1, [0, 0, 0], |ctx, _| bgrewriteaof(ctx.dbs)),
+    Spec::server("lastsave", 1, [0, 0, 0], |_, _| lastsave()),
+    Spec::server("export", -1, [0, 0, 0], |ctx, args| export(ctx.dbs, args)),
+    Spec::server("import", -3, [0, 0, 0], import),
+];
+
+/// SAVE
+pub fn save(dbs: &[Keyspace]) -> Value {
+    if snapshot::in_progress() {
+        return Value::error("ERR Background save already in progress");
+    }
+
+    match snapshot::save(dbs) {
+        Ok(()) => Value::SimpleString("OK".to_string()),
+        Err(_) => Value::error("ERR Failed saving the DB, check the server logs"),
+    }
+}
+
+/// BGSAVE [SCHEDULE]
+pub fn bgsave(dbs: &[Keyspace], args: &[Vec<u8>]) -> Value {
+    match args {
+        [] => {}
+        // Nothing else ever holds a save up, so there's never a reason to schedule one
+        [option] if lower(option) == "schedule" => {}
+        _ => return Value::error("ERR syntax error"),
+    }
+
+    if snapshot::bgsave(dbs) {
+        Value::SimpleString("Background saving started".to_string())
+    } else {
+        Value::error("ERR Background save already in progress")
+    }
+}
+
+/// SHUTDOWN [NOSAVE|SAVE]: stops the server. There's no reply unless it fails: the connection
+/// just closes when the server exits.
+fn shutdown<'a>(_: &'a mut Connection<'_>, args: &'a [Vec<u8>]) -> Steps<'a> {
+    Box::pin(async move {
+        match shutdown::parse_args(args) {
+            Ok(save) => {
+                shutdown::request(save);
+                shutdown::failed().await;
+                Step::Reply(Value::error("ERR Errors trying to SHUTDOWN. Check logs."))
+            }
+            Err(e) => Step::Reply(e),
+        }
+    })
+}
+
+/// LASTSAVE: when the dataset was last saved, in unix seconds.
+pub fn lastsave() -> Value {
+    Value::Integer(snapshot::LAST_SAVE.load(Ordering::Relaxed) as i64)
+}
+
+/// BGREWRITEAOF
+pub fn bgrewriteaof(dbs: &[Keyspace]) -> Value {
+    if aof::bgrewrite(dbs) {
+        Value::SimpleString("Background append only file rewriting started".to_string())
+    } else {
+        Value::error("ERR Background append only file rewriting already in progress")
+    }
+}
+
+/// Where EXPORT and IMPORT find `path`: in `dir`, unless it's absolute.
+fn in_dir(path: &[u8]) -> PathBuf {
+    Path::new(&config::get().dir).join(String::from_utf8_lossy(path).as_ref())
+}
+
+/// EXPORT [JSON | JSONL] [FILE path]: every key as JSON, in the reply or written to a file, in
+/// which case the reply is how many keys there were.
+pub fn export(dbs: &[Keyspace], args: &[Vec<u8>]) -> Value {
+    let mut format = Format::Jsonl;
+    let mut file = None;
+    let mut i = 0;
+    while i < args.len() {
+        match lower(&args[i]).as_str() {
+            "json" => format = Format::Json,
+            "jsonl" => format = Format::Jsonl,
+            "file" if i + 1 < args.len() => {
+                i += 1;
+                file = Some(in_dir(&args[i]));
+            }
+            _ => return syntax_error(),
+        }
+        i += 1;
+    }
+
+    let (text, count) = export::export(dbs, format);
+    let Some(path) = file else {
+        return Value::BulkString(text.into_bytes().into());
+    };
+    match fs::write(&path, text) {
+        Ok(()) => Value::Integer(count as i64),
+        Err(e) => Value::error(format!("ERR Can't write {}: {e}", path.display())),
+    }
+}
+
+/// IMPORT FILE path | DATA export [REPLACE]: loads the keys an EXPORT wrote out, returning how
+/// many. Unless REPLACE is given, nothing is loaded if any of them already exists. Each key is
+/// passed on to replicas and the append-only file as a RESTORE.
+fn import(ctx: &mut Context<'_>, args: &[Vec<u8>]) -> Value {
+    let replace = match args {
+        [_, _] => false,
+        [_, _, option] if lower(option) == "replace" => true,
+        _ => return syntax_error(),
+    };
+    let text = match lower(&args[0]).as_str() {
+        "file" => {
+            let path = in_dir(&args[1]);
+            match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => return Value::error(format!("ERR Can't read {}: {e}", path.display())),
+            }
+        }
+        "data" => String::from_utf8_lossy(&args[1]).into_owned(),
+        _ => return syntax_error(),
+    };
+    let records = match export::parse(&text) {
+        Ok(records) => records,
+        Err(e) => return Value::error(format!("ERR Invalid export: {e}")),
+    };
+
+    for record in &records {
+        if record.db >= ctx.dbs.len() {
+            return Value::error(format!("ERR DB index {} is out of range", record.db));
+        }
+        if !replace && lookup(&mut ctx.dbs[record.db], &record.key).is_some() {
+            return Value::error("BUSYKEY Target key name already exists.");
+        }
+    }
+
+    let now = db::unix_millis();
+    let ok = Value::SimpleString("OK".to_string());
+    let mut imported = 0;
+    for record in records {
+        // Keys that have expired since they were exported are left out, as when loading a
+        // snapshot
+        if record.expires_at.is_some_and(|at| at <= now) {
+            continue;
+        }
+        let restore = [
+            record.key.clone(),
+            record.expires_at.unwrap_or(0).to_string().into_bytes(),
+            dump_value(&record.val),
+            b"replace".to_vec(),
+            b"absttl".to_vec(),
+        ];
+
+        db::select(record.db);
+        ctx.dbs[record.db].insert(
+            record.key.clone(),
+            DBData::new(record.val, record.expires_at),
+        );
+        notify::emit(Class::Generic, "restore", &record.key);
+        ctx.blocked.signal(record.db, &record.key);
+        propagate::propagate(record.db, "restore", &restore, &ok);
+        imported += 1;
+    }
+    db::select(ctx.client.db);
+
+    Value::Integer(imported)
+}
@@ -0,0 +1,308 @@
+use crate::cmd::{
+    lookup, lower, not_an_integer, parse_int, registry::Spec, syntax_error, wrong_args, wrong_type,
+};
+use crate::db::{DBData, DBVal, Keyspace};
+use crate::notify::{self, Class};
+use crate::resp::Value;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// SORT itself.
+pub const COMMANDS: &[Spec] = &[Spec::keyspace("sort", -2, [1, 1, 1], sort)];
+
+struct SortOptions {
+    by: Option<Vec<u8>>,
+    limit: Option<(i64, i64)>,
+    get: Vec<Vec<u8>>,
+    desc: bool,
+    alpha: bool,
+    store: Option<Vec<u8>>,
+}
+
+fn parse_sort_options(args: &[Vec<u8>]) -> Result<SortOptions, Value> {
+    let mut opts = SortOptions {
+        by: None,
+        limit: None,
+        get: Vec::new(),
+        desc: false,
+        alpha: false,
+        store: None,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let remaining = args.len() - i - 1;
+
+        match lower(&args[i]).as_str() {
+            "asc" => opts.desc = false,
+            "desc" => opts.desc = true,
+            "alpha" => opts.alpha = true,
+            "by" if remaining >= 1 => {
+                opts.by = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "get" if remaining >= 1 => {
+                opts.get.push(args[i + 1].clone());
+                i += 1;
+            }
+            "store" if remaining >= 1 => {
+                opts.store = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "limit" if remaining >= 2 => {
+                let (Some(offset), Some(count)) =
+                    (parse_int(&args[i + 1]), parse_int(&args[i + 2]))
+                else {
+                    return Err(not_an_integer());
+                };
+                opts.limit = Some((offset, count));
+                i += 2;
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    Ok(opts)
+}
+
+/// Substitutes `element` for the first `*` in `pattern` and fetches the resulting key. `#`
+/// refers to the element itself, and a `->field` suffix reads that field of a hash.
+fn lookup_pattern(db: &mut Keyspace, pattern: &[u8], element: &[u8]) -> Option<Vec<u8>> {
+    if pattern == b"#" {
+        return Some(element.to_vec());
+    }
+
+    let star = pattern.iter().position(|&b| b == b'*')?;
+
+    // The field separator only counts if it comes after the `*`
+    let (key_pattern, field) = match pattern.windows(2).rposition(|w| w == b"->") {
+        Some(arrow) if arrow > star && arrow + 2 < pattern.len() => {
+            (&pattern[..arrow], Some(&pattern[arrow + 2..]))
+        }
+        _ => (pattern, None),
+    };
+
+    let mut key = key_pattern[..star].to_vec();
+    key.extend_from_slice(element);
+    key.extend_from_slice(&key_pattern[star + 1..]);
+
+    match (lookup(db, &key)?.data(), field) {
+        (DBVal::Hash(hash), Some(field)) => hash.get(field).cloned(),
+        (val, None) => val.string_bytes().map(|s| s.into_owned()),
+        _ => None,
+    }
+}
+
+pub fn sort(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    if args.is_empty() {
+        return wrong_args("sort");
+    }
+
+    let opts = match parse_sort_options(&args[1..]) {
+        Ok(opts) => opts,
+        Err(e) => return e,
+    };
+
+    let mut elements: Vec<Vec<u8>> = match lookup(db, &args[0]).map(|val| val.data()) {
+        None => Vec::new(),
+        Some(DBVal::List(list)) => list.iter().cloned().collect(),
+        Some(DBVal::Set(set)) => set.iter().map(Cow::into_owned).collect(),
+        Some(DBVal::ZSet(zset)) => zset.iter().map(|(member, _)| member.to_vec()).collect(),
+        Some(_) => return wrong_type(),
+    };
+
+    // A BY pattern without a `*` can never name a distinct key per element, which Redis
+    // treats as a request to skip sorting entirely
+    let dont_sort = opts.by.as_ref().is_some_and(|by| !by.contains(&b'*'));
+
+    if !dont_sort {
+        let mut keyed = Vec::with_capacity(elements.len());
+        for element in elements {
+            let weight = match &opts.by {
+                Some(by) => lookup_pattern(db, by, &element),
+                None => Some(element.clone()),
+            };
+
+            let score = if opts.alpha {
+                0.0
+            } else {
+                match weight.as_deref().map(std::str::from_utf8) {
+                    None => 0.0,
+                    Some(Ok(w)) => match w.trim().parse::<f64>() {
+                        Ok(score) if !score.is_nan() => score,
+                        _ => {
+                            return Value::error(
+                                "ERR One or more scores can't be converted into double",
+                            );
+                        }
+                    },
+                    Some(Err(_)) => {
+                        return Value::error(
+                            "ERR One or more scores can't be converted into double",
+                        );
+                    }
+                }
+            };
+
+            keyed.push((element, weight.unwrap_or_default(), score));
+        }
+
+        keyed.sort_by(|a, b| {
+            let ord = if opts.alpha {
+                a.1.cmp(&b.1)
+            } else {
+                a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal)
+            };
+            // Ties fall back to the element itself so the output is deterministic
+            let ord = ord.then_with(|| a.0.cmp(&b.0));
+
+            if opts.desc { ord.reverse() } else { ord }
+        });
+
+        elements = keyed.into_iter().map(|(element, _, _)| element).collect();
+    }
+
+    if let Some((offset, count)) = opts.limit {
+        let len = elements.len() as i64;
+        let start = offset.clamp(0, len);
+        let end = if count < 0 {
+            len
+        } else {
+            start.saturating_add(count).min(len)
+        };
+
+        elements = elements[start as usize..end as usize].to_vec();
+    }
+
+    let result: Vec<Value> = if opts.get.is_empty() {
+        elements
+            .into_iter()
+            .map(|element| Value::BulkString(element.into()))
+            .collect()
+    } else {
+        let mut result = Vec::with_capacity(elements.len() * opts.get.len());
+        for element in &elements {
+            for pattern in &opts.get {
+                result.push(match lookup_pattern(db, pattern, element) {
+                    Some(v) => Value::BulkString(v.into()),
+                    None => Value::Null,
+                });
+            }
+        }
+        result
+    };
+
+    if let Some(dest) = opts.store {
+        let len = result.len();
+
+        if len == 0 {
+            if db.remove(&dest).is_some() {
+                notify::emit(Class::Generic, "del", &dest);
+            }
+        } else {
+            let list = result
+                .into_iter()
+                .map(|v| match v {
+                    Value::BulkString(s) => s.into(),
+                    _ => Vec::new(),
+                })
+                .collect();
+            db.insert(dest.clone(), DBData::new(DBVal::List(list), None));
+            notify::emit(Class::List, "sortstore", &dest);
+        }
+
+        return Value::Integer(len as i64);
+    }
+
+    Value::Array(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::registry::TestClient;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn elements(reply: Value) -> Vec<String> {
+        let Value::Array(values) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        values
+            .into_iter()
+            .map(|value| match value {
+                Value::BulkString(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Value::Null => "nil".to_string(),
+                value => panic!("expected a bulk string, got {value:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sorts_numbers_and_strings() {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        client.run(&mut dbs, &["rpush", "n", "10", "2", "-1.5", "3"]);
+        client.run(&mut dbs, &["sadd", "w", "banana", "apple", "cherry"]);
+        let db = &mut dbs[0];
+
+        assert_eq!(elements(sort(db, &args(&["n"]))), ["-1.5", "2", "3", "10"]);
+        assert_eq!(
+            elements(sort(db, &args(&["n", "desc", "limit", "1", "2"]))),
+            ["3", "2"]
+        );
+        assert_eq!(
+            elements(sort(db, &args(&["w", "alpha"]))),
+            ["apple", "banana", "cherry"]
+        );
+        assert_eq!(
+            sort(db, &args(&["w"])).error_message(),
+            Some("ERR One or more scores can't be converted into double".to_string())
+        );
+        assert!(elements(sort(db, &args(&["missing"]))).is_empty());
+    }
+
+    #[tokio::test]
+    async fn sorts_by_and_gets_from_other_keys() {
+        let mut client = TestClient::new();
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        client.run(&mut dbs, &["rpush", "ids", "1", "2", "3"]);
+        for (id, weight, name) in [("1", "30", "one"), ("2", "10", "two"), ("3", "20", "three")] {
+            client.run(&mut dbs, &["set", &format!("weight_{id}"), weight]);
+            client.run(&mut dbs, &["hset", &format!("user_{id}"), "name", name]);
+        }
+        let db = &mut dbs[0];
+
+        assert_eq!(
+            elements(sort(db, &args(&["ids", "by", "weight_*"]))),
+            ["2", "3", "1"]
+        );
+        assert_eq!(
+            elements(sort(
+                db,
+                &args(&["ids", "by", "weight_*", "get", "#", "get", "user_*->name"])
+            )),
+            ["2", "two", "3", "three", "1", "one"]
+        );
+        // A pattern without a `*` leaves them in the order they're in
+        assert_eq!(
+            elements(sort(db, &args(&["ids", "by", "nosort", "desc"]))),
+            ["1", "2", "3"]
+        );
+
+        assert!(matches!(
+            sort(db, &args(&["ids", "by", "weight_*", "store", "sorted"])),
+            Value::Integer(3)
+        ));
+        assert_eq!(
+            elements(client.run(&mut dbs, &["lrange", "sorted", "0", "-1"])),
+            ["2", "3", "1"]
+        );
+    }
+}
@@ -0,0 +1,1608 @@
+use crate::blocking::{Block, Outcome};
+use crate::cmd::{
+    lookup, lower, not_an_integer, parse_int, read, registry::Spec, syntax_error, wrong_args,
+    wrong_type,
+};
+use crate::db::{self, DBData, DBVal, Keyspace, unix_millis};
+use crate::notify::{self, Class};
+use crate::resp::Value;
+use crate::stream::{Consumer, ConsumerGroup, Fields, Stream, StreamId};
+use std::ops::Bound;
+use std::time::Duration;
+
+/// The stream commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("xadd", -5, [1, 1, 1], xadd),
+    Spec::keyspace("xtrim", -4, [1, 1, 1], xtrim),
+    Spec::keyspace("xdel", -3, [1, 1, 1], xdel),
+    Spec::keyspace("xlen", 2, [1, 1, 1], xlen),
+    Spec::keyspace("xrange", -4, [1, 1, 1], xrange),
+    Spec::keyspace("xrevrange", -4, [1, 1, 1], xrevrange),
+    Spec::blocking("xread", -4, [0, 0, 0], xread),
+    Spec::keyspace("xgroup", -2, [1, 1, 1], xgroup),
+    Spec::blocking("xreadgroup", -7, [0, 0, 0], xreadgroup),
+    Spec::keyspace("xack", -4, [1, 1, 1], xack),
+    Spec::keyspace("xpending", -3, [1, 1, 1], xpending),
+    Spec::keyspace("xclaim", -6, [1, 1, 1], xclaim),
+    Spec::keyspace("xautoclaim", -6, [1, 1, 1], xautoclaim),
+    Spec::read("xinfo", -2, [2, 2, 1], xinfo),
+];
+
+/// Fetches the stream stored at `key`. `Ok(None)` means the key doesn't exist.
+pub fn get_stream<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<Option<&'a mut Stream>, Value> {
+    match lookup(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data_mut() {
+            DBVal::Stream(stream) => Ok(Some(stream)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+/// Like [`get_stream`], for a command that only reads.
+fn read_stream<'a>(db: &'a Keyspace, key: &[u8]) -> Result<Option<&'a Stream>, Value> {
+    match read(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data() {
+            DBVal::Stream(stream) => Ok(Some(stream)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+fn get_or_create_stream<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<&'a mut Stream, Value> {
+    if get_stream(db, key)?.is_none() {
+        db.insert(
+            key.to_vec(),
+            DBData::new(DBVal::Stream(Stream::new()), None),
+        );
+    }
+
+    Ok(get_stream(db, key)?.expect("stream was just created"))
+}
+
+pub fn invalid_id() -> Value {
+    Value::error("ERR Invalid stream ID specified as stream command argument")
+}
+
+pub fn entry_value(id: &StreamId, fields: &Fields) -> Value {
+    let fields = fields
+        .iter()
+        .flat_map(|(field, value)| {
+            [
+                Value::BulkString(field.clone().into()),
+                Value::BulkString(value.clone().into()),
+            ]
+        })
+        .collect();
+
+    Value::Array(vec![
+        Value::BulkString(id.to_string().into()),
+        Value::Array(fields),
+    ])
+}
+
+pub fn entries_value<'a>(entries: impl Iterator<Item = (&'a StreamId, &'a Fields)>) -> Value {
+    Value::Array(
+        entries
+            .map(|(id, fields)| entry_value(id, fields))
+            .collect(),
+    )
+}
+
+/// The ID argument of XADD.
+enum AddId {
+    /// `*`: pick the next ID from the clock.
+    Auto,
+    /// `ms-*`: fixed milliseconds, next free sequence number.
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl AddId {
+    fn parse(arg: &[u8]) -> Option<Self> {
+        if arg == b"*" {
+            return Some(AddId::Auto);
+        }
+        if let Some(ms) = arg.strip_suffix(b"-*") {
+            return parse_int(ms).map(AddId::AutoSeq);
+        }
+
+        StreamId::parse(arg, 0).map(AddId::Explicit)
+    }
+
+    /// Resolves the ID for a new entry after `last`, enforcing that IDs only ever grow.
+    fn resolve(&self, last: StreamId) -> Result<StreamId, Value> {
+        let too_small = || {
+            Value::error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            )
+        };
+
+        match *self {
+            AddId::Auto => {
+                let now = unix_millis();
+                if now > last.ms {
+                    Ok(StreamId::new(now, 0))
+                } else {
+                    // The clock went backwards or we're still in the same millisecond
+                    last.next().ok_or_else(too_small)
+                }
+            }
+            AddId::AutoSeq(ms) if ms == last.ms => last.next().ok_or_else(too_small),
+            AddId::AutoSeq(ms) if ms > last.ms => Ok(StreamId::new(ms, 0)),
+            AddId::AutoSeq(_) => Err(too_small()),
+            AddId::Explicit(id) if id == StreamId::MIN => Err(Value::error(
+                "ERR The ID specified in XADD must be greater than 0-0",
+            )),
+            AddId::Explicit(id) if id <= last => Err(too_small()),
+            AddId::Explicit(id) => Ok(id),
+        }
+    }
+}
+
+/// How XADD and XTRIM bound a stream.
+enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+struct Trim {
+    strategy: TrimStrategy,
+    limit: usize,
+}
+
+impl Trim {
+    fn apply(&self, stream: &mut Stream) -> usize {
+        match self.strategy {
+            TrimStrategy::MaxLen(max_len) => stream.trim(|_, len| len > max_len, self.limit),
+            TrimStrategy::MinId(min_id) => stream.trim(|id, _| *id < min_id, self.limit),
+        }
+    }
+}
+
+/// The options shared by XADD and XTRIM: `[NOMKSTREAM]` (XADD only) and
+/// `[MAXLEN|MINID [=|~] threshold [LIMIT count]]`, in any order.
+#[derive(Default)]
+struct AddTrimArgs {
+    no_mkstream: bool,
+    trim: Option<Trim>,
+}
+
+impl AddTrimArgs {
+    /// Parses options from the front of `args`, returning them with how many arguments they
+    /// took. XADD stops at the first non-option, which is its ID; XTRIM takes everything.
+    fn parse(args: &[Vec<u8>], xadd: bool) -> Result<(Self, usize), Value> {
+        let mut parsed = AddTrimArgs::default();
+        let mut strategy = None;
+        let mut approx = false;
+        let mut limit = None;
+
+        let mut i = 0;
+        while i < args.len() {
+            let option = lower(&args[i]);
+            let value = args.get(i + 1);
+            match (option.as_str(), value) {
+                ("nomkstream", _) if xadd => parsed.no_mkstream = true,
+                ("maxlen" | "minid", Some(mut value)) => {
+                    i += 1;
+                    if matches!(value.as_slice(), b"=" | b"~") {
+                        approx = value.as_slice() == b"~";
+                        i += 1;
+                        let Some(threshold) = args.get(i) else {
+                            return Err(syntax_error());
+                        };
+                        value = threshold;
+                    }
+
+                    strategy = Some(if option == "maxlen" {
+                        match parse_int::<i64>(value) {
+                            Some(n) if n >= 0 => TrimStrategy::MaxLen(n as usize),
+                            Some(_) => {
+                                return Err(Value::error("ERR The MAXLEN argument must be >= 0."));
+                            }
+                            None => return Err(not_an_integer()),
+                        }
+                    } else {
+                        TrimStrategy::MinId(StreamId::parse(value, 0).ok_or_else(invalid_id)?)
+                    });
+                }
+                ("limit", Some(value)) => {
+                    i += 1;
+                    limit = match parse_int::<i64>(value) {
+                        Some(n) if n >= 0 => Some(n as usize),
+                        Some(_) => {
+                            return Err(Value::error("ERR The LIMIT argument must be >= 0."));
+                        }
+                        None => return Err(not_an_integer()),
+                    };
+                }
+                _ if xadd => break,
+                _ => return Err(syntax_error()),
+            }
+            i += 1;
+        }
+
+        if limit.is_some() && !approx {
+            return Err(Value::error(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option",
+            ));
+        }
+
+        // Approximate trimming caps the work per call, like Redis' default of 100 nodes'
+        // worth of entries. Entries here aren't grouped into nodes, so it can always trim
+        // exactly up to that cap.
+        let limit = match (approx, limit) {
+            (false, _) | (true, Some(0)) => usize::MAX,
+            (true, Some(limit)) => limit,
+            (true, None) => 100 * 100,
+        };
+        parsed.trim = strategy.map(|strategy| Trim { strategy, limit });
+
+        Ok((parsed, i))
+    }
+}
+
+fn xadd(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let (options, consumed) = match AddTrimArgs::parse(&args[1..], true) {
+        Ok(parsed) => parsed,
+        Err(e) => return e,
+    };
+    let i = 1 + consumed;
+
+    let Some(id) = args.get(i).and_then(|arg| AddId::parse(arg)) else {
+        return invalid_id();
+    };
+
+    let pairs = &args[i + 1..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return wrong_args("xadd");
+    }
+
+    let last = match get_stream(db, &args[0]) {
+        Ok(Some(stream)) => stream.last_id(),
+        Ok(None) if options.no_mkstream => return Value::Null,
+        Ok(None) => StreamId::MIN,
+        Err(e) => return e,
+    };
+    let id = match id.resolve(last) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+
+    let stream = match get_or_create_stream(db, &args[0]) {
+        Ok(stream) => stream,
+        Err(e) => return e,
+    };
+    let fields = pairs
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    stream.append(id, fields);
+    notify::emit(Class::Stream, "xadd", &args[0]);
+
+    if let Some(trim) = &options.trim
+        && trim.apply(stream) > 0
+    {
+        notify::emit(Class::Stream, "xtrim", &args[0]);
+    }
+
+    Value::BulkString(id.to_string().into())
+}
+
+fn xtrim(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let trim = match AddTrimArgs::parse(&args[1..], false) {
+        Ok((
+            AddTrimArgs {
+                trim: Some(trim), ..
+            },
+            _,
+        )) => trim,
+        Ok(_) => return syntax_error(),
+        Err(e) => return e,
+    };
+
+    match get_stream(db, &args[0]) {
+        Ok(Some(stream)) => {
+            let trimmed = trim.apply(stream);
+            if trimmed > 0 {
+                notify::emit(Class::Stream, "xtrim", &args[0]);
+            }
+            Value::Integer(trimmed as i64)
+        }
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+fn xdel(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut ids = Vec::with_capacity(args.len() - 1);
+    for arg in &args[1..] {
+        match StreamId::parse(arg, 0) {
+            Some(id) => ids.push(id),
+            None => return invalid_id(),
+        }
+    }
+
+    // Consumer groups keep their pending entries; readers see them as deleted
+    match get_stream(db, &args[0]) {
+        Ok(Some(stream)) => {
+            let deleted = ids.iter().filter(|id| stream.remove(id)).count();
+            if deleted > 0 {
+                notify::emit(Class::Stream, "xdel", &args[0]);
+            }
+            Value::Integer(deleted as i64)
+        }
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+fn xlen(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match get_stream(db, &args[0]) {
+        Ok(Some(stream)) => Value::Integer(stream.len() as i64),
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+/// Parses an XRANGE interval end: `-`, `+`, an ID (a bare `ms` covers the whole
+/// millisecond), or an exclusive `(id`.
+fn parse_range_bound(arg: &[u8], is_start: bool) -> Result<Bound<StreamId>, Value> {
+    match arg {
+        b"-" => return Ok(Bound::Included(StreamId::MIN)),
+        b"+" => return Ok(Bound::Included(StreamId::MAX)),
+        _ => {}
+    }
+
+    let missing_seq = if is_start { 0 } else { u64::MAX };
+    let Some(rest) = arg.strip_prefix(b"(") else {
+        return StreamId::parse(arg, missing_seq)
+            .map(Bound::Included)
+            .ok_or_else(invalid_id);
+    };
+
+    let id = StreamId::parse(rest, missing_seq).ok_or_else(invalid_id)?;
+    if is_start && id == StreamId::MAX {
+        return Err(Value::error("ERR invalid start ID for the interval"));
+    }
+    if !is_start && id == StreamId::MIN {
+        return Err(Value::error("ERR invalid end ID for the interval"));
+    }
+
+    Ok(Bound::Excluded(id))
+}
+
+/// Whether an interval selects nothing. `BTreeMap::range` panics on inverted intervals, so
+/// check this first.
+fn range_is_empty(range: (Bound<StreamId>, Bound<StreamId>)) -> bool {
+    match range {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start >= end,
+        _ => false,
+    }
+}
+
+fn range_generic(db: &mut Keyspace, args: &[Vec<u8>], cmd: &str, rev: bool) -> Value {
+    if args.len() != 3 && args.len() != 5 {
+        return wrong_args(cmd);
+    }
+
+    let (start, end) = if rev {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+    let range = match (
+        parse_range_bound(start, true),
+        parse_range_bound(end, false),
+    ) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+
+    let count = match args.get(3..) {
+        Some([option, count]) if lower(option) == "count" => match parse_int::<i64>(count) {
+            Some(count) => usize::try_from(count).unwrap_or(0),
+            None => return not_an_integer(),
+        },
+        Some([]) | None => usize::MAX,
+        Some(_) => return syntax_error(),
+    };
+
+    let stream = match get_stream(db, &args[0]) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Value::Array(Vec::new()),
+        Err(e) => return e,
+    };
+
+    if range_is_empty(range) {
+        return Value::Array(Vec::new());
+    }
+
+    if rev {
+        entries_value(stream.range(range).rev().take(count))
+    } else {
+        entries_value(stream.range(range).take(count))
+    }
+}
+
+fn xrange(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    range_generic(db, args, "xrange", false)
+}
+
+fn xrevrange(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    range_generic(db, args, "xrevrange", true)
+}
+
+/// Parses a `BLOCK` timeout in milliseconds. Zero means wait forever.
+fn parse_block(arg: &[u8]) -> Result<Option<tokio::time::Instant>, Value> {
+    match parse_int::<i64>(arg) {
+        None => Err(Value::error(
+            "ERR timeout is not an integer or out of range",
+        )),
+        Some(ms) if ms < 0 => Err(Value::error("ERR timeout is negative")),
+        Some(0) => Ok(None),
+        Some(ms) => Ok(Some(
+            tokio::time::Instant::now() + Duration::from_millis(ms as u64),
+        )),
+    }
+}
+
+/// Where a reader starts in one stream: after a given ID, or after whatever is last right now.
+enum ReadFrom {
+    After(StreamId),
+    Last,
+}
+
+/// Reads entries after each stream's start ID, replying `[[key, entries], ...]` for the
+/// streams that had any, or `None` if none did.
+fn read_streams(
+    db: &mut Keyspace,
+    keys: &[Vec<u8>],
+    after: &[StreamId],
+    count: usize,
+) -> Result<Option<Value>, Value> {
+    let mut reply = Vec::new();
+
+    for (key, after) in keys.iter().zip(after) {
+        let Some(stream) = get_stream(db, key)? else {
+            continue;
+        };
+
+        let entries: Vec<_> = stream
+            .range((Bound::Excluded(*after), Bound::Unbounded))
+            .take(count)
+            .collect();
+        if !entries.is_empty() {
+            reply.push(Value::Array(vec![
+                Value::BulkString(key.clone().into()),
+                entries_value(entries.into_iter()),
+            ]));
+        }
+    }
+
+    Ok((!reply.is_empty()).then_some(Value::Array(reply)))
+}
+
+fn xread(db: &mut Keyspace, args: &[Vec<u8>]) -> Outcome {
+    let mut count = usize::MAX;
+    let mut block = None;
+    let mut i = 0;
+    let streams = loop {
+        match (lower(&args[i]).as_str(), args.get(i + 1)) {
+            ("streams", _) => break &args[i + 1..],
+            ("count", Some(arg)) => match parse_int::<i64>(arg) {
+                // Like Redis, zero or negative counts mean no limit
+                Some(n) if n > 0 => count = n as usize,
+                Some(_) => count = usize::MAX,
+                None => return not_an_integer().into(),
+            },
+            ("block", Some(arg)) => match parse_block(arg) {
+                Ok(deadline) => block = Some(deadline),
+                Err(e) => return e.into(),
+            },
+            _ => return syntax_error().into(),
+        }
+        i += 2;
+        if i >= args.len() {
+            return syntax_error().into();
+        }
+    };
+
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Value::error(
+            "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
+        )
+        .into();
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+
+    let mut from = Vec::with_capacity(ids.len());
+    for id in ids {
+        if id.as_slice() == b"$" {
+            from.push(ReadFrom::Last);
+        } else {
+            match StreamId::parse(id, 0) {
+                Some(id) => from.push(ReadFrom::After(id)),
+                None => return invalid_id().into(),
+            }
+        }
+    }
+
+    // `$` means "only what arrives from now on", so pin it to the current last ID up front
+    let mut after = Vec::with_capacity(from.len());
+    for (key, from) in keys.iter().zip(from) {
+        after.push(match from {
+            ReadFrom::After(id) => id,
+            ReadFrom::Last => match get_stream(db, key) {
+                Ok(stream) => stream.map_or(StreamId::MIN, |stream| stream.last_id()),
+                Err(e) => return e.into(),
+            },
+        });
+    }
+
+    let Some(deadline) = block else {
+        return match read_streams(db, keys, &after, count) {
+            Ok(reply) => reply.unwrap_or(Value::NullArray),
+            Err(e) => e,
+        }
+        .into();
+    };
+
+    let keys = keys.to_vec();
+    Outcome::Block(Block::new(
+        keys.clone(),
+        deadline,
+        Value::NullArray,
+        move |db| read_streams(db, &keys, &after, count).unwrap_or_else(Some),
+    ))
+}
+
+fn no_such_group(key: &[u8], group: &[u8]) -> Value {
+    Value::error(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    ))
+}
+
+/// Parses a group's starting ID, where `$` means the stream's current last ID.
+fn parse_group_id(arg: &[u8], stream: Option<&Stream>) -> Result<StreamId, Value> {
+    if arg == b"$" {
+        return Ok(stream.map_or(StreamId::MIN, |stream| stream.last_id()));
+    }
+
+    StreamId::parse(arg, 0).ok_or_else(invalid_id)
+}
+
+/// Checks trailing `[ENTRIESREAD n]` options. The counter itself isn't tracked, so the value
+/// is only validated.
+fn parse_entries_read(args: &[Vec<u8>]) -> Result<(), Value> {
+    match args {
+        [] => Ok(()),
+        [option, n] if lower(option) == "entriesread" => match parse_int::<i64>(n) {
+            Some(n) if n >= -1 => Ok(()),
+            Some(_) => Err(Value::error(
+                "ERR value for ENTRIESREAD must be positive or -1",
+            )),
+            None => Err(not_an_integer()),
+        },
+        _ => Err(syntax_error()),
+    }
+}
+
+fn xgroup(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(subcommand) = args.first().map(|arg| lower(arg)) else {
+        return wrong_args("xgroup");
+    };
+
+    let arity_ok = match subcommand.as_str() {
+        "create" => (4..=7).contains(&args.len()),
+        "setid" => (4..=6).contains(&args.len()),
+        "destroy" => args.len() == 3,
+        "createconsumer" | "delconsumer" => args.len() == 4,
+        _ => {
+            return Value::error(format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                String::from_utf8_lossy(&args[0])
+            ));
+        }
+    };
+    if !arity_ok {
+        return Value::error(format!(
+            "ERR wrong number of arguments for 'xgroup|{subcommand}' command"
+        ));
+    }
+
+    let (key, group) = (&args[1], &args[2]);
+    let now = unix_millis();
+
+    if subcommand == "create" {
+        let (mkstream, rest) = match args.get(4) {
+            Some(arg) if lower(arg) == "mkstream" => (true, &args[5..]),
+            _ => (false, &args[4..]),
+        };
+        if let Err(e) = parse_entries_read(rest) {
+            return e;
+        }
+
+        let stream = match get_stream(db, key) {
+            Ok(Some(stream)) => stream,
+            Ok(None) if mkstream => match get_or_create_stream(db, key) {
+                Ok(stream) => stream,
+                Err(e) => return e,
+            },
+            Ok(None) => return missing_key_for_xgroup(),
+            Err(e) => return e,
+        };
+
+        let id = match parse_group_id(&args[3], Some(stream)) {
+            Ok(id) => id,
+            Err(e) => return e,
+        };
+
+        return if stream.create_group(group.clone(), ConsumerGroup::new(id)) {
+            notify::emit(Class::Stream, "xgroup-create", key);
+            Value::SimpleString("OK".to_string())
+        } else {
+            Value::error("BUSYGROUP Consumer Group name already exists")
+        };
+    }
+
+    let stream = match get_stream(db, key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return missing_key_for_xgroup(),
+        Err(e) => return e,
+    };
+
+    if subcommand == "destroy" {
+        let destroyed = stream.destroy_group(group);
+        if destroyed {
+            notify::emit(Class::Stream, "xgroup-destroy", key);
+        }
+        return Value::Integer(destroyed as i64);
+    }
+
+    let setid = match (subcommand == "setid", parse_entries_read(&args[4..])) {
+        (true, Err(e)) => return e,
+        (true, Ok(())) => match parse_group_id(&args[3], Some(stream)) {
+            Ok(id) => Some(id),
+            Err(e) => return e,
+        },
+        (false, _) => None,
+    };
+
+    let Some(consumer_group) = stream.group_mut(group) else {
+        return Value::error(format!(
+            "NOGROUP No such consumer group '{}' for key name '{}'",
+            String::from_utf8_lossy(group),
+            String::from_utf8_lossy(key)
+        ));
+    };
+
+    match subcommand.as_str() {
+        "createconsumer" => {
+            let created = consumer_group.create_consumer(&args[3], now);
+            if created {
+                notify::emit(Class::Stream, "xgroup-createconsumer", key);
+            }
+            Value::Integer(created as i64)
+        }
+        "delconsumer" => {
+            let pending = consumer_group.delete_consumer(&args[3]).unwrap_or(0);
+            notify::emit(Class::Stream, "xgroup-delconsumer", key);
+            Value::Integer(pending as i64)
+        }
+        _ => {
+            consumer_group.last_delivered = setid.expect("SETID parsed its ID");
+            notify::emit(Class::Stream, "xgroup-setid", key);
+            Value::SimpleString("OK".to_string())
+        }
+    }
+}
+
+fn missing_key_for_xgroup() -> Value {
+    Value::error(
+        "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+    )
+}
+
+/// Where a group reader starts in one stream: new entries (`>`) or its own pending history.
+enum GroupReadFrom {
+    New,
+    History(StreamId),
+}
+
+/// The options of one XREADGROUP call.
+struct GroupRead {
+    group: Vec<u8>,
+    consumer: Vec<u8>,
+    count: usize,
+    no_ack: bool,
+    keys: Vec<Vec<u8>>,
+    from: Vec<GroupReadFrom>,
+}
+
+impl GroupRead {
+    /// Serves every stream, replying `[[key, entries], ...]`. Streams read with `>` that have
+    /// nothing new are left out, so `None` means there's nothing to deliver yet.
+    fn attempt(&self, db: &mut Keyspace) -> Result<Option<Value>, Value> {
+        let now = unix_millis();
+        let mut reply = Vec::new();
+
+        for (key, from) in self.keys.iter().zip(&self.from) {
+            let Some((group, entries)) =
+                get_stream(db, key)?.and_then(|stream| stream.group_with_entries(&self.group))
+            else {
+                return Err(Value::error(format!(
+                    "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(&self.group)
+                )));
+            };
+            group.touch_consumer(&self.consumer, now);
+
+            let served = match from {
+                GroupReadFrom::New => {
+                    let new: Vec<_> = entries
+                        .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                        .take(self.count)
+                        .collect();
+                    if new.is_empty() {
+                        continue;
+                    }
+
+                    for (id, _) in &new {
+                        group.last_delivered = **id;
+                        if !self.no_ack {
+                            group.assign(**id, &self.consumer, now, 1);
+                        }
+                    }
+                    entries_value(new.into_iter())
+                }
+                GroupReadFrom::History(after) => {
+                    let consumer = group
+                        .consumer(&self.consumer)
+                        .expect("consumer was touched");
+                    // Entries deleted since delivery still show up, with nil fields
+                    let history = consumer
+                        .pending
+                        .range((Bound::Excluded(*after), Bound::Unbounded))
+                        .take(self.count)
+                        .map(|id| match entries.get(id) {
+                            Some(fields) => entry_value(id, fields),
+                            None => Value::Array(vec![
+                                Value::BulkString(id.to_string().into()),
+                                Value::NullArray,
+                            ]),
+                        })
+                        .collect();
+                    Value::Array(history)
+                }
+            };
+
+            reply.push(Value::Array(vec![
+                Value::BulkString(key.clone().into()),
+                served,
+            ]));
+        }
+        // Group state isn't covered by keyspace notifications, so it's counted as a change here
+        if !reply.is_empty() {
+            db::mark_dirty();
+        }
+
+        Ok((!reply.is_empty()).then_some(Value::Array(reply)))
+    }
+}
+
+fn xreadgroup(db: &mut Keyspace, args: &[Vec<u8>]) -> Outcome {
+    if lower(&args[0]) != "group" {
+        return syntax_error().into();
+    }
+
+    let mut count = usize::MAX;
+    let mut block = None;
+    let mut no_ack = false;
+    let mut i = 3;
+    let streams = loop {
+        let Some(arg) = args.get(i) else {
+            return syntax_error().into();
+        };
+        match (lower(arg).as_str(), args.get(i + 1)) {
+            ("streams", _) => break &args[i + 1..],
+            ("noack", _) => {
+                no_ack = true;
+                i += 1;
+                continue;
+            }
+            ("count", Some(arg)) => match parse_int::<i64>(arg) {
+                Some(n) if n > 0 => count = n as usize,
+                Some(_) => count = usize::MAX,
+                None => return not_an_integer().into(),
+            },
+            ("block", Some(arg)) => match parse_block(arg) {
+                Ok(deadline) => block = Some(deadline),
+                Err(e) => return e.into(),
+            },
+            _ => return syntax_error().into(),
+        }
+        i += 2;
+    };
+
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Value::error(
+            "ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.",
+        )
+        .into();
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+
+    let mut from = Vec::with_capacity(ids.len());
+    for id in ids {
+        if id.as_slice() == b">" {
+            from.push(GroupReadFrom::New);
+        } else {
+            match StreamId::parse(id, 0) {
+                Some(id) => from.push(GroupReadFrom::History(id)),
+                None => return invalid_id().into(),
+            }
+        }
+    }
+
+    let read = GroupRead {
+        group: args[1].clone(),
+        consumer: args[2].clone(),
+        count,
+        no_ack,
+        keys: keys.to_vec(),
+        from,
+    };
+
+    // Pending history is always answered straight away; only `>` reads ever block
+    let history = read
+        .from
+        .iter()
+        .any(|from| matches!(from, GroupReadFrom::History(_)));
+
+    let deadline = match block {
+        Some(deadline) if !history => deadline,
+        _ => {
+            return match read.attempt(db) {
+                Ok(reply) => reply.unwrap_or(Value::NullArray),
+                Err(e) => e,
+            }
+            .into();
+        }
+    };
+
+    Outcome::Block(Block::new(
+        read.keys.clone(),
+        deadline,
+        Value::NullArray,
+        move |db| read.attempt(db).unwrap_or_else(Some),
+    ))
+}
+
+fn xack(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut ids = Vec::with_capacity(args.len() - 2);
+    for arg in &args[2..] {
+        match StreamId::parse(arg, 0) {
+            Some(id) => ids.push(id),
+            None => return invalid_id(),
+        }
+    }
+
+    let group = match get_stream(db, &args[0]) {
+        Ok(Some(stream)) => stream.group_mut(&args[1]),
+        Ok(None) => None,
+        Err(e) => return e,
+    };
+    let Some(group) = group else {
+        return Value::Integer(0);
+    };
+
+    let acked = ids.iter().filter(|id| group.ack(id)).count();
+    if acked > 0 {
+        db::mark_dirty();
+    }
+
+    Value::Integer(acked as i64)
+}
+
+fn xpending(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    // Extended form: [IDLE min-idle-time] start end count [consumer]
+    let mut rest = &args[2..];
+    let mut min_idle = 0;
+    if rest.first().is_some_and(|arg| lower(arg) == "idle") {
+        let Some(idle) = rest.get(1).and_then(|arg| parse_int::<i64>(arg)) else {
+            return not_an_integer();
+        };
+        min_idle = idle.max(0) as u64;
+        rest = &rest[2..];
+        if rest.is_empty() {
+            return syntax_error();
+        }
+    }
+
+    let extended = match rest {
+        [] => None,
+        [start, end, count, consumer @ ..] if consumer.len() <= 1 => {
+            let range = match (
+                parse_range_bound(start, true),
+                parse_range_bound(end, false),
+            ) {
+                (Ok(start), Ok(end)) => (start, end),
+                (Err(e), _) | (_, Err(e)) => return e,
+            };
+            let Some(count) = parse_int::<i64>(count) else {
+                return not_an_integer();
+            };
+            Some((range, count.max(0) as usize, consumer.first()))
+        }
+        _ => return syntax_error(),
+    };
+
+    let group = match get_stream(db, &args[0]) {
+        Ok(Some(stream)) => stream.group(&args[1]),
+        Ok(None) => None,
+        Err(e) => return e,
+    };
+    let Some(group) = group else {
+        return no_such_group(&args[0], &args[1]);
+    };
+
+    let Some((range, count, consumer)) = extended else {
+        let pending = group.pending();
+        let (Some((first, _)), Some((last, _))) =
+            (pending.first_key_value(), pending.last_key_value())
+        else {
+            return Value::Array(vec![
+                Value::Integer(0),
+                Value::Null,
+                Value::Null,
+                Value::NullArray,
+            ]);
+        };
+
+        let consumers = group
+            .consumers()
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| {
+                Value::Array(vec![
+                    Value::BulkString(name.clone().into()),
+                    Value::BulkString(consumer.pending.len().to_string().into()),
+                ])
+            })
+            .collect();
+
+        return Value::Array(vec![
+            Value::Integer(pending.len() as i64),
+            Value::BulkString(first.to_string().into()),
+            Value::BulkString(last.to_string().into()),
+            Value::Array(consumers),
+        ]);
+    };
+
+    if range_is_empty(range) {
+        return Value::Array(Vec::new());
+    }
+
+    let now = unix_millis();
+    let entries = group
+        .pending()
+        .range(range)
+        .filter(|(_, entry)| consumer.is_none_or(|consumer| entry.consumer == *consumer))
+        .filter(|(_, entry)| now.saturating_sub(entry.delivered_at) >= min_idle)
+        .take(count)
+        .map(|(id, entry)| {
+            Value::Array(vec![
+                Value::BulkString(id.to_string().into()),
+                Value::BulkString(entry.consumer.clone().into()),
+                Value::Integer(now.saturating_sub(entry.delivered_at) as i64),
+                Value::Integer(entry.delivery_count as i64),
+            ])
+        })
+        .collect();
+
+    Value::Array(entries)
+}
+
+fn parse_min_idle(arg: &[u8], cmd: &str) -> Result<u64, Value> {
+    match parse_int::<i64>(arg) {
+        Some(ms) => Ok(ms.max(0) as u64),
+        None => Err(Value::error(format!(
+            "ERR Invalid min-idle-time argument for {cmd}"
+        ))),
+    }
+}
+
+fn xclaim(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let (key, group_name, consumer) = (&args[0], &args[1], &args[2]);
+    let min_idle = match parse_min_idle(&args[3], "XCLAIM") {
+        Ok(ms) => ms,
+        Err(e) => return e,
+    };
+
+    // IDs run until the first argument that isn't one; the options follow
+    let mut ids = Vec::new();
+    let mut i = 4;
+    while let Some(id) = args.get(i).and_then(|arg| StreamId::parse(arg, 0)) {
+        ids.push(id);
+        i += 1;
+    }
+    if ids.is_empty() {
+        return invalid_id();
+    }
+
+    let now = unix_millis();
+    let mut delivered_at = now;
+    let mut retry_count = None;
+    let mut force = false;
+    let mut just_id = false;
+    let mut last_id = None;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match (lower(&args[i]).as_str(), value) {
+            ("force", _) => force = true,
+            ("justid", _) => just_id = true,
+            ("idle", Some(value)) => match parse_int::<i64>(value) {
+                Some(idle) => {
+                    delivered_at = now.saturating_sub(idle.max(0) as u64);
+                    i += 1;
+                }
+                None => return Value::error("ERR Invalid IDLE option argument for XCLAIM"),
+            },
+            ("time", Some(value)) => match parse_int::<i64>(value) {
+                Some(time) => {
+                    delivered_at = time.max(0) as u64;
+                    i += 1;
+                }
+                None => return Value::error("ERR Invalid TIME option argument for XCLAIM"),
+            },
+            ("retrycount", Some(value)) => match parse_int::<i64>(value) {
+                Some(count) => {
+                    retry_count = Some(count.max(0) as u64);
+                    i += 1;
+                }
+                None => return Value::error("ERR Invalid RETRYCOUNT option argument for XCLAIM"),
+            },
+            ("lastid", Some(value)) => match StreamId::parse(value, 0) {
+                Some(id) => {
+                    last_id = Some(id);
+                    i += 1;
+                }
+                None => return invalid_id(),
+            },
+            _ => {
+                return Value::error(format!(
+                    "ERR Unrecognized XCLAIM option '{}'",
+                    String::from_utf8_lossy(&args[i])
+                ));
+            }
+        }
+        i += 1;
+    }
+
+    let group = match get_stream(db, key) {
+        Ok(Some(stream)) => stream.group_with_entries(group_name),
+        Ok(None) => None,
+        Err(e) => return e,
+    };
+    let Some((group, entries)) = group else {
+        return no_such_group(key, group_name);
+    };
+
+    if let Some(last_id) = last_id
+        && last_id > group.last_delivered
+    {
+        group.last_delivered = last_id;
+    }
+
+    let mut claimed = Vec::new();
+    for id in ids {
+        let pending = match group.pending().get(&id) {
+            Some(pending) => pending.clone(),
+            // FORCE creates the pending entry, as long as the entry itself still exists
+            None if force && entries.contains_key(&id) => {
+                group.assign(id, consumer, now, 1);
+                group.pending()[&id].clone()
+            }
+            None => continue,
+        };
+
+        let Some(fields) = entries.get(&id) else {
+            // Deleted from the stream since delivery, so there's nothing left to claim
+            group.ack(&id);
+            continue;
+        };
+        if min_idle > 0 && now.saturating_sub(pending.delivered_at) < min_idle {
+            continue;
+        }
+
+        let count = match retry_count {
+            Some(count) => count,
+            None if just_id => pending.delivery_count,
+            None => pending.delivery_count + 1,
+        };
+        group.assign(id, consumer, delivered_at, count);
+
+        claimed.push(if just_id {
+            Value::BulkString(id.to_string().into())
+        } else {
+            entry_value(&id, fields)
+        });
+    }
+
+    if !claimed.is_empty() {
+        db::mark_dirty();
+    }
+
+    Value::Array(claimed)
+}
+
+fn xautoclaim(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let (key, group_name, consumer) = (&args[0], &args[1], &args[2]);
+    let min_idle = match parse_min_idle(&args[3], "XAUTOCLAIM") {
+        Ok(ms) => ms,
+        Err(e) => return e,
+    };
+    let start = match parse_range_bound(&args[4], true) {
+        Ok(start) => start,
+        Err(e) => return e,
+    };
+
+    let mut count = 100;
+    let mut just_id = false;
+    let mut i = 5;
+    while i < args.len() {
+        match (lower(&args[i]).as_str(), args.get(i + 1)) {
+            ("justid", _) => just_id = true,
+            ("count", Some(value)) => {
+                match parse_int::<i64>(value) {
+                    Some(n) if n > 0 && n <= i64::MAX / 10 => count = n as usize,
+                    _ => return Value::error("ERR COUNT must be > 0"),
+                }
+                i += 1;
+            }
+            _ => return syntax_error(),
+        }
+        i += 1;
+    }
+
+    let group = match get_stream(db, key) {
+        Ok(Some(stream)) => stream.group_with_entries(group_name),
+        Ok(None) => None,
+        Err(e) => return e,
+    };
+    let Some((group, entries)) = group else {
+        return no_such_group(key, group_name);
+    };
+
+    let now = unix_millis();
+    let mut claimed = Vec::new();
+    let mut deleted = Vec::new();
+    // Like Redis, bound the work done per call by how many entries are examined
+    let mut attempts = count * 10;
+
+    let candidates: Vec<_> = group
+        .pending()
+        .range((start, Bound::Unbounded))
+        .map(|(id, pending)| (*id, pending.clone()))
+        .collect();
+    let mut candidates = candidates.into_iter().peekable();
+
+    while attempts > 0 && claimed.len() < count {
+        let Some((id, pending)) = candidates.next() else {
+            break;
+        };
+        attempts -= 1;
+
+        let Some(fields) = entries.get(&id) else {
+            group.ack(&id);
+            deleted.push(Value::BulkString(id.to_string().into()));
+            continue;
+        };
+        if now.saturating_sub(pending.delivered_at) < min_idle {
+            continue;
+        }
+
+        let count = if just_id {
+            pending.delivery_count
+        } else {
+            pending.delivery_count + 1
+        };
+        group.assign(id, consumer, now, count);
+
+        claimed.push(if just_id {
+            Value::BulkString(id.to_string().into())
+        } else {
+            entry_value(&id, fields)
+        });
+    }
+
+    let cursor = candidates.peek().map_or(StreamId::MIN, |(id, _)| *id);
+    if !claimed.is_empty() || !deleted.is_empty() {
+        db::mark_dirty();
+    }
+
+    Value::Array(vec![
+        Value::BulkString(cursor.to_string().into()),
+        Value::Array(claimed),
+        Value::Array(deleted),
+    ])
+}
+
+fn field(name: &str, value: Value) -> (Value, Value) {
+    (Value::BulkString(name.to_string().into()), value)
+}
+
+fn id_value(id: StreamId) -> Value {
+    Value::BulkString(id.to_string().into())
+}
+
+/// How many entries are past what `group` has been delivered, which is how far behind it is.
+fn lag(stream: &Stream, group: &ConsumerGroup) -> usize {
+    stream
+        .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+        .count()
+}
+
+fn xinfo(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    let subcommand = lower(&args[0]);
+    if subcommand == "help" {
+        return Value::Array(
+            [
+                "XINFO <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "CONSUMERS <key> <groupname>",
+                "    Show consumers of <groupname>.",
+                "GROUPS <key>",
+                "    Show the stream consumer groups.",
+                "STREAM <key> [FULL [COUNT <count>]",
+                "    Show information about the stream.",
+            ]
+            .into_iter()
+            .map(|line| Value::SimpleString(line.to_string()))
+            .collect(),
+        );
+    }
+
+    let arity_ok = match subcommand.as_str() {
+        "stream" => args.len() >= 2,
+        "groups" => args.len() == 2,
+        "consumers" => args.len() == 3,
+        _ => {
+            return Value::error(format!(
+                "ERR unknown subcommand '{}'. Try XINFO HELP.",
+                String::from_utf8_lossy(&args[0])
+            ));
+        }
+    };
+    if !arity_ok {
+        return Value::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try XINFO HELP."
+        ));
+    }
+
+    let key = &args[1];
+    let stream = match read_stream(db, key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Value::error("ERR no such key"),
+        Err(e) => return e,
+    };
+    let now = unix_millis();
+
+    match subcommand.as_str() {
+        "stream" => match &args[2..] {
+            [] => stream_info(stream),
+            [full] if lower(full) == "full" => stream_info_full(stream, 10),
+            [full, option, count] if lower(full) == "full" && lower(option) == "count" => {
+                match parse_int::<i64>(count) {
+                    Some(count) => stream_info_full(stream, count.max(0) as usize),
+                    None => not_an_integer(),
+                }
+            }
+            _ => syntax_error(),
+        },
+        "groups" => Value::Array(
+            stream
+                .groups()
+                .map(|(name, group)| {
+                    Value::Map(vec![
+                        field("name", Value::BulkString(name.clone().into())),
+                        field("consumers", Value::Integer(group.consumers().len() as i64)),
+                        field("pending", Value::Integer(group.pending().len() as i64)),
+                        field("last-delivered-id", id_value(group.last_delivered)),
+                        field("lag", Value::Integer(lag(stream, group) as i64)),
+                    ])
+                })
+                .collect(),
+        ),
+        _ => {
+            let Some(group) = stream.group(&args[2]) else {
+                return Value::error(format!(
+                    "NOGROUP No such consumer group '{}' for key name '{}'",
+                    String::from_utf8_lossy(&args[2]),
+                    String::from_utf8_lossy(key)
+                ));
+            };
+            Value::Array(
+                group
+                    .consumers()
+                    .iter()
+                    .map(|(name, consumer)| {
+                        Value::Map(vec![
+                            field("name", Value::BulkString(name.clone().into())),
+                            field("pending", Value::Integer(consumer.pending.len() as i64)),
+                            field("idle", idle(consumer, now)),
+                        ])
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Milliseconds since `consumer` last read or claimed anything.
+fn idle(consumer: &Consumer, now: u64) -> Value {
+    Value::Integer(now.saturating_sub(consumer.seen_at) as i64)
+}
+
+/// XINFO STREAM: the stream's length, last ID, how many groups read it, and its first and
+/// last entries.
+fn stream_info(stream: &Stream) -> Value {
+    let entry = |entry: Option<(&StreamId, &Fields)>| {
+        entry.map_or(Value::Null, |(id, fields)| entry_value(id, fields))
+    };
+
+    Value::Map(vec![
+        field("length", Value::Integer(stream.len() as i64)),
+        field("last-generated-id", id_value(stream.last_id())),
+        field("groups", Value::Integer(stream.groups().count() as i64)),
+        field("first-entry", entry(stream.iter().next())),
+        field("last-entry", entry(stream.iter().next_back())),
+    ])
+}
+
+/// XINFO STREAM FULL: the first `count` entries, or all of them for 0, and every group with
+/// its pending entries and consumers.
+fn stream_info_full(stream: &Stream, count: usize) -> Value {
+    let count = if count == 0 { usize::MAX } else { count };
+    let groups = stream
+        .groups()
+        .map(|(name, group)| {
+            let pending = group
+                .pending()
+                .iter()
+                .take(count)
+                .map(|(id, entry)| {
+                    Value::Array(vec![
+                        id_value(*id),
+                        Value::BulkString(entry.consumer.clone().into()),
+                        Value::Integer(entry.delivered_at as i64),
+                        Value::Integer(entry.delivery_count as i64),
+                    ])
+                })
+                .collect();
+            let consumers = group
+                .consumers()
+                .iter()
+                .map(|(name, consumer)| {
+                    let pending = consumer
+                        .pending
+                        .iter()
+                        .take(count)
+                        .filter_map(|id| Some((id, group.pending().get(id)?)))
+                        .map(|(id, entry)| {
+                            Value::Array(vec![
+                                id_value(*id),
+                                Value::Integer(entry.delivered_at as i64),
+                                Value::Integer(entry.delivery_count as i64),
+                            ])
+                        })
+                        .collect();
+                    Value::Map(vec![
+                        field("name", Value::BulkString(name.clone().into())),
+                        field("seen-time", Value::Integer(consumer.seen_at as i64)),
+                        field("pel-count", Value::Integer(consumer.pending.len() as i64)),
+                        field("pending", Value::Array(pending)),
+                    ])
+                })
+                .collect();
+
+            Value::Map(vec![
+                field("name", Value::BulkString(name.clone().into())),
+                field("last-delivered-id", id_value(group.last_delivered)),
+                field("lag", Value::Integer(lag(stream, group) as i64)),
+                field("pel-count", Value::Integer(group.pending().len() as i64)),
+                field("pending", Value::Array(pending)),
+                field("consumers", Value::Array(consumers)),
+            ])
+        })
+        .collect();
+
+    Value::Map(vec![
+        field("length", Value::Integer(stream.len() as i64)),
+        field("last-generated-id", id_value(stream.last_id())),
+        field("entries", entries_value(stream.iter().take(count))),
+        field("groups", Value::Array(groups)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    /// The IDs of the entries in an XRANGE-like reply.
+    fn ids(reply: Value) -> Vec<String> {
+        let Value::Array(entries) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                Value::Array(entry) => match &entry[0] {
+                    Value::BulkString(id) => String::from_utf8_lossy(id).into_owned(),
+                    id => panic!("expected an ID, got {id:?}"),
+                },
+                entry => panic!("expected an entry, got {entry:?}"),
+            })
+            .collect()
+    }
+
+    /// The reply a command gave without blocking.
+    fn replied(outcome: Outcome) -> Value {
+        match outcome {
+            Outcome::Reply(reply) => reply,
+            Outcome::Block(_) => panic!("blocked with a reply to give"),
+        }
+    }
+
+    #[tokio::test]
+    async fn add_and_range() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        for id in ["1-1", "1-2", "2-0"] {
+            xadd(db, &args(&["s", id, "f", "v"]));
+        }
+        // The sequence number after the last one the milliseconds had
+        assert!(matches!(
+            xadd(db, &args(&["s", "2-*", "f", "v"])),
+            Value::BulkString(id) if id == b"2-1"[..]
+        ));
+        assert_eq!(
+            xadd(db, &args(&["s", "1-5", "f", "v"])).error_message(),
+            Some(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top \
+                 item"
+                    .to_string()
+            )
+        );
+
+        assert!(matches!(xlen(db, &args(&["s"])), Value::Integer(4)));
+        assert_eq!(
+            ids(xrange(db, &args(&["s", "1-2", "+"]))),
+            ["1-2", "2-0", "2-1"]
+        );
+        assert_eq!(
+            ids(xrevrange(db, &args(&["s", "+", "-", "count", "1"]))),
+            ["2-1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_past_an_id_or_waits_on_the_last() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        for id in ["1-0", "2-0", "3-0"] {
+            xadd(db, &args(&["s", id, "f", "v"]));
+        }
+
+        let Value::Array(mut streams) = replied(xread(
+            db,
+            &args(&["count", "1", "streams", "s", "missing", "1-0", "0"]),
+        )) else {
+            panic!("XREAD replies with the streams it read");
+        };
+        assert_eq!(streams.len(), 1);
+        let Value::Array(mut stream) = streams.remove(0) else {
+            panic!("XREAD replies with the key and its entries");
+        };
+        assert_eq!(ids(stream.remove(1)), ["2-0"]);
+
+        assert!(matches!(
+            replied(xread(db, &args(&["streams", "s", "$"]))),
+            Value::NullArray
+        ));
+        // Only BLOCK waits, and then only for what arrives after `$`
+        let Outcome::Block(block) = xread(db, &args(&["block", "0", "streams", "s", "$"])) else {
+            panic!("XREAD BLOCK served entries that were already there");
+        };
+        assert!(matches!(block.attempt_now(db), Value::NullArray));
+        assert!(
+            replied(xread(db, &args(&["streams", "s"])))
+                .error_message()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn groups_track_what_they_have_been_given() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        xadd(db, &args(&["s", "1-0", "f", "v"]));
+        xadd(db, &args(&["s", "2-0", "f", "v"]));
+        assert!(matches!(
+            xgroup(db, &args(&["create", "s", "g", "0"])),
+            Value::SimpleString(_)
+        ));
+        let read = args(&["group", "g", "c", "streams", "s", ">"]);
+        let reply = replied(xreadgroup(db, &read));
+        assert!(matches!(reply, Value::Array(streams) if streams.len() == 1));
+
+        assert!(matches!(
+            xack(db, &args(&["s", "g", "1-0", "9-0"])),
+            Value::Integer(1)
+        ));
+        let Value::Array(summary) = xpending(db, &args(&["s", "g"])) else {
+            panic!("XPENDING replies with a summary");
+        };
+        assert!(matches!(summary[0], Value::Integer(1)));
+
+        // Another consumer can take over what is still pending
+        let claimed = xclaim(db, &args(&["s", "g", "other", "0", "2-0", "justid"]));
+        assert!(matches!(&claimed, Value::Array(ids) if ids.len() == 1));
+        let Value::Array(reply) = xautoclaim(db, &args(&["s", "g", "c", "0", "0"])) else {
+            panic!("XAUTOCLAIM replies with a cursor and the entries it claimed");
+        };
+        assert_eq!(ids(reply[1].clone()), ["2-0"]);
+    }
+
+    #[tokio::test]
+    async fn trims_and_deletes_entries() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        for id in ["1-0", "2-0", "3-0", "4-0"] {
+            xadd(db, &args(&["s", id, "f", "v"]));
+        }
+
+        assert!(matches!(
+            xtrim(db, &args(&["s", "maxlen", "3"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(
+            xtrim(db, &args(&["s", "minid", "3-0"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(
+            xdel(db, &args(&["s", "3-0", "9-0"])),
+            Value::Integer(1)
+        ));
+        assert_eq!(ids(xrange(db, &args(&["s", "-", "+"]))), ["4-0"]);
+
+        // XADD trims as it adds, and a deleted top still can't be reused
+        xadd(db, &args(&["s", "maxlen", "1", "5-0", "f", "v"]));
+        assert_eq!(ids(xrange(db, &args(&["s", "-", "+"]))), ["5-0"]);
+        xdel(db, &args(&["s", "5-0"]));
+        assert!(
+            xadd(db, &args(&["s", "5-0", "f", "v"]))
+                .error_message()
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,605 @@
+use crate::cmd::bitmap::get_or_create_bytes;
+use crate::cmd::{
+    db_val_to_value, lookup, lower, not_an_integer, parse_int, read, registry::Spec, syntax_error,
+    wrong_args, wrong_type,
+};
+use crate::config;
+use crate::db::{DBData, DBVal, Keyspace, unix_millis};
+use crate::notify::{self, Class};
+use crate::resp::Value;
+use bytes::Bytes;
+
+/// The string commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("set", -3, [1, 1, 1], set),
+    Spec::read("get", 2, [1, 1, 1], get),
+    Spec::keyspace("mset", -3, [1, -1, 2], mset),
+    Spec::keyspace("msetnx", -3, [1, -1, 2], msetnx),
+    Spec::keyspace("setex", 4, [1, 1, 1], setex),
+    Spec::keyspace("psetex", 4, [1, 1, 1], psetex),
+    Spec::keyspace("setnx", 3, [1, 1, 1], setnx),
+    Spec::keyspace("getset", 3, [1, 1, 1], getset),
+    Spec::keyspace("getdel", 2, [1, 1, 1], getdel),
+    Spec::keyspace("getex", -2, [1, 1, 1], getex),
+    Spec::keyspace("incr", 2, [1, 1, 1], |db, args| incr_by(db, &args[0], 1)),
+    Spec::keyspace("decr", 2, [1, 1, 1], |db, args| incr_by(db, &args[0], -1)),
+    Spec::keyspace("incrby", 3, [1, 1, 1], incrby),
+    Spec::keyspace("decrby", 3, [1, 1, 1], decrby),
+    Spec::keyspace("append", 3, [1, 1, 1], append),
+    Spec::keyspace("lcs", -3, [1, 2, 1], lcs),
+    Spec::read("mget", -2, [1, -1, 1], mget),
+];
+
+enum Condition {
+    Always,
+    IfMissing,
+    IfExists,
+}
+
+enum Expiry {
+    In(u64),
+    At(u64),
+    Keep,
+}
+
+struct SetOptions {
+    condition: Condition,
+    expiry: Option<Expiry>,
+    get: bool,
+}
+
+fn parse_set_options(args: &[Vec<u8>]) -> Result<SetOptions, Value> {
+    let mut opts = SetOptions {
+        condition: Condition::Always,
+        expiry: None,
+        get: false,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        match lower(&args[i]).as_str() {
+            "nx" if matches!(opts.condition, Condition::Always) => {
+                opts.condition = Condition::IfMissing
+            }
+            "xx" if matches!(opts.condition, Condition::Always) => {
+                opts.condition = Condition::IfExists
+            }
+            "get" => opts.get = true,
+            "keepttl" if opts.expiry.is_none() => opts.expiry = Some(Expiry::Keep),
+            unit @ ("ex" | "px" | "exat" | "pxat") if opts.expiry.is_none() => {
+                i += 1;
+                let Some(raw) = args.get(i) else {
+                    return Err(syntax_error());
+                };
+                let n = parse_int::<i64>(raw).ok_or_else(not_an_integer)?;
+                if n <= 0 {
+                    return Err(Value::error("ERR invalid expire time in 'set' command"));
+                }
+                let n = n as u64;
+
+                opts.expiry = Some(match unit {
+                    "ex" => Expiry::In(n.saturating_mul(1000)),
+                    "px" => Expiry::In(n),
+                    "exat" => Expiry::At(n.saturating_mul(1000)),
+                    _ => Expiry::At(n),
+                });
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    Ok(opts)
+}
+
+fn set(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let opts = match parse_set_options(&args[2..]) {
+        Ok(opts) => opts,
+        Err(e) => return e,
+    };
+
+    let key = &args[0];
+    let old = lookup(db, key);
+    let exists = old.is_some();
+
+    let old_value = match &old {
+        Some(val) => match db_val_to_value(val.data()) {
+            Some(value) => value,
+            None if opts.get => return wrong_type(),
+            None => Value::Null,
+        },
+        None => Value::Null,
+    };
+
+    let expires_at = match (&opts.expiry, &old) {
+        (Some(Expiry::Keep), Some(val)) => val.expires_at(),
+        (Some(Expiry::In(ms)), _) => Some(unix_millis().saturating_add(*ms)),
+        (Some(Expiry::At(deadline)), _) => Some(*deadline),
+        _ => None,
+    };
+
+    let should_write = match opts.condition {
+        Condition::Always => true,
+        Condition::IfMissing => !exists,
+        Condition::IfExists => exists,
+    };
+
+    if should_write {
+        if expires_at.is_some_and(|at| at <= unix_millis()) {
+            // An absolute deadline in the past still counts as a write, it just leaves nothing behind
+            if db.remove(key).is_some() {
+                notify::emit(Class::Generic, "del", key);
+            }
+        } else {
+            db.insert(key.clone(), DBData::new(DBVal::parse(&args[1]), expires_at));
+            notify::emit(Class::String, "set", key);
+            if matches!(opts.expiry, Some(Expiry::In(_) | Expiry::At(_))) {
+                notify::emit(Class::Generic, "expire", key);
+            }
+        }
+    }
+
+    if opts.get {
+        old_value
+    } else if should_write {
+        Value::SimpleString("OK".to_string())
+    } else {
+        Value::Null
+    }
+}
+
+fn get(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    match read(db, &args[0]) {
+        Some(val) => db_val_to_value(val.data()).unwrap_or_else(wrong_type),
+        None => Value::Null,
+    }
+}
+
+fn mget(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    Value::Array(
+        args.iter()
+            .map(|key| match read(db, key) {
+                Some(val) => db_val_to_value(val.data()).unwrap_or(Value::Null),
+                None => Value::Null,
+            })
+            .collect(),
+    )
+}
+
+fn mset(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return wrong_args("mset");
+    }
+
+    for pair in args.chunks(2) {
+        db.insert(pair[0].clone(), DBData::new(DBVal::parse(&pair[1]), None));
+        notify::emit(Class::String, "set", &pair[0]);
+    }
+
+    Value::SimpleString("OK".to_string())
+}
+
+fn msetnx(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return wrong_args("msetnx");
+    }
+
+    if args.chunks(2).any(|pair| lookup(db, &pair[0]).is_some()) {
+        return Value::Integer(0);
+    }
+
+    mset(db, args);
+
+    Value::Integer(1)
+}
+
+fn set_with_ttl(db: &mut Keyspace, args: &[Vec<u8>], command: &str, unit: &str) -> Value {
+    match parse_int::<i64>(&args[1]) {
+        Some(n) if n > 0 => set(
+            db,
+            &[
+                args[0].clone(),
+                args[2].clone(),
+                unit.as_bytes().to_vec(),
+                args[1].clone(),
+            ],
+        ),
+        Some(_) => Value::error(format!("ERR invalid expire time in '{command}' command")),
+        None => not_an_integer(),
+    }
+}
+
+fn setex(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    set_with_ttl(db, args, "setex", "EX")
+}
+
+fn psetex(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    set_with_ttl(db, args, "psetex", "PX")
+}
+
+fn setnx(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match set(db, &[args[0].clone(), args[1].clone(), b"NX".to_vec()]) {
+        Value::Null => Value::Integer(0),
+        _ => Value::Integer(1),
+    }
+}
+
+fn getset(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    set(db, &[args[0].clone(), args[1].clone(), b"GET".to_vec()])
+}
+
+fn getdel(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match lookup(db, &args[0]) {
+        Some(val) => {
+            let Some(value) = db_val_to_value(val.data()) else {
+                return wrong_type();
+            };
+            db.remove(&args[0]);
+            notify::emit(Class::Generic, "del", &args[0]);
+            value
+        }
+        None => Value::Null,
+    }
+}
+
+fn getex(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let expiry = match args.len() {
+        1 => None,
+        2 if args[1].eq_ignore_ascii_case(b"persist") => Some(None),
+        3 => {
+            let unit = lower(&args[1]);
+            let Some(n) = parse_int::<i64>(&args[2]) else {
+                return not_an_integer();
+            };
+            if n <= 0 {
+                return Value::error("ERR invalid expire time in 'getex' command");
+            }
+            let n = n as u64;
+
+            let now = unix_millis();
+            match unit.as_str() {
+                "ex" => Some(Some(now.saturating_add(n.saturating_mul(1000)))),
+                "px" => Some(Some(now.saturating_add(n))),
+                "exat" => Some(Some(n.saturating_mul(1000))),
+                "pxat" => Some(Some(n)),
+                _ => return syntax_error(),
+            }
+        }
+        _ => return syntax_error(),
+    };
+
+    let Some(val) = lookup(db, &args[0]) else {
+        return Value::Null;
+    };
+    let Some(value) = db_val_to_value(val.data()) else {
+        return wrong_type();
+    };
+
+    match expiry {
+        Some(Some(at)) if at <= unix_millis() => {
+            db.remove(&args[0]);
+            notify::emit(Class::Generic, "del", &args[0]);
+        }
+        Some(expires_at) => {
+            db.set_expires_at(&args[0], expires_at);
+            let event = if expires_at.is_some() {
+                "expire"
+            } else {
+                "persist"
+            };
+            notify::emit(Class::Generic, event, &args[0]);
+        }
+        None => {}
+    }
+
+    value
+}
+
+/// Adds `increment` to the integer at `key`, which counts as 0 if it's missing, keeping its TTL.
+fn incr_by(db: &mut Keyspace, key: &[u8], increment: i64) -> Value {
+    let Some(val) = lookup(db, key) else {
+        db.insert(key.to_vec(), DBData::new(DBVal::Int(increment), None));
+        notify::emit(Class::String, "incrby", key);
+        return Value::Integer(increment);
+    };
+
+    let current = match val.data() {
+        DBVal::Int(n) => *n,
+        DBVal::String(bytes) => match parse_int::<i64>(bytes) {
+            Some(n) => n,
+            None => return not_an_integer(),
+        },
+        _ => return wrong_type(),
+    };
+    let Some(new) = current.checked_add(increment) else {
+        return Value::error("ERR increment or decrement would overflow");
+    };
+
+    *val.data_mut() = DBVal::Int(new);
+    notify::emit(Class::String, "incrby", key);
+
+    Value::Integer(new)
+}
+
+fn incrby(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match parse_int::<i64>(&args[1]) {
+        Some(increment) => incr_by(db, &args[0], increment),
+        None => not_an_integer(),
+    }
+}
+
+fn decrby(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match parse_int::<i64>(&args[1]).and_then(i64::checked_neg) {
+        Some(decrement) => incr_by(db, &args[0], decrement),
+        None => not_an_integer(),
+    }
+}
+
+fn append(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let len = match lookup(db, &args[0]) {
+        Some(val) => match val.data().string_bytes() {
+            Some(bytes) => bytes.len(),
+            None => return wrong_type(),
+        },
+        None => 0,
+    };
+    if (len + args[1].len()) as u64 > config::get().proto_max_bulk_len {
+        return Value::error("ERR string exceeds maximum allowed size (proto-max-bulk-len)");
+    }
+
+    let mut bytes = match get_or_create_bytes(db, &args[0]) {
+        Ok(bytes) => bytes,
+        Err(e) => return e,
+    };
+    bytes.extend_from_slice(&args[1]);
+    let len = bytes.len();
+    drop(bytes);
+    notify::emit(Class::String, "append", &args[0]);
+
+    Value::Integer(len as i64)
+}
+
+fn lcs(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let mut get_len = false;
+    let mut get_idx = false;
+    let mut with_match_len = false;
+    let mut min_match_len = 0;
+
+    let mut i = 2;
+    while i < args.len() {
+        match lower(&args[i]).as_str() {
+            "len" => get_len = true,
+            "idx" => get_idx = true,
+            "withmatchlen" => with_match_len = true,
+            "minmatchlen" if i + 1 < args.len() => {
+                let Some(n) = parse_int::<i64>(&args[i + 1]) else {
+                    return not_an_integer();
+                };
+                min_match_len = n.max(0) as usize;
+                i += 1;
+            }
+            _ => return syntax_error(),
+        }
+        i += 1;
+    }
+
+    if get_len && get_idx {
+        return Value::error("ERR If you want both the length and indexes, please just use IDX.");
+    }
+
+    let mut strings = Vec::with_capacity(2);
+    for key in &args[..2] {
+        strings.push(match lookup(db, key) {
+            None => Vec::new(),
+            Some(val) => match val.data().string_bytes() {
+                Some(s) => s.into_owned(),
+                None => {
+                    return Value::error("ERR The specified keys must contain string values");
+                }
+            },
+        });
+    }
+    let (a, b) = (&strings[0], &strings[1]);
+
+    // dp[i][j] is the LCS length of a[..i] and b[..j]
+    let width = b.len() + 1;
+    let mut dp = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            dp[i * width + j] = if a[i - 1] == b[j - 1] {
+                dp[(i - 1) * width + j - 1] + 1
+            } else {
+                dp[(i - 1) * width + j].max(dp[i * width + j - 1])
+            };
+        }
+    }
+    let lcs_len = dp[a.len() * width + b.len()] as usize;
+
+    if get_len {
+        return Value::Integer(lcs_len as i64);
+    }
+
+    // Walk back from the end of both strings, recovering the subsequence and the contiguous
+    // ranges it is made of (reported last match first, as Redis does)
+    let mut result = vec![0u8; lcs_len];
+    let mut matches = Vec::new();
+    let (mut i, mut j, mut idx) = (a.len(), b.len(), lcs_len);
+    let no_range = a.len();
+    let (mut a_start, mut a_end, mut b_start, mut b_end) = (no_range, 0, 0, 0);
+
+    while i > 0 && j > 0 {
+        let mut emit_range = false;
+
+        if a[i - 1] == b[j - 1] {
+            result[idx - 1] = a[i - 1];
+
+            if a_start == no_range {
+                (a_start, a_end, b_start, b_end) = (i - 1, i - 1, j - 1, j - 1);
+            } else if a_start == i && b_start == j {
+                a_start -= 1;
+                b_start -= 1;
+            } else {
+                emit_range = true;
+            }
+
+            if a_start == 0 || b_start == 0 {
+                emit_range = true;
+            }
+
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if dp[(i - 1) * width + j] > dp[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+
+            if a_start != no_range {
+                emit_range = true;
+            }
+        }
+
+        if emit_range {
+            let match_len = a_end - a_start + 1;
+            if get_idx && (min_match_len == 0 || match_len >= min_match_len) {
+                let mut entry = vec![
+                    Value::Array(vec![
+                        Value::Integer(a_start as i64),
+                        Value::Integer(a_end as i64),
+                    ]),
+                    Value::Array(vec![
+                        Value::Integer(b_start as i64),
+                        Value::Integer(b_end as i64),
+                    ]),
+                ];
+                if with_match_len {
+                    entry.push(Value::Integer(match_len as i64));
+                }
+                matches.push(Value::Array(entry));
+            }
+
+            a_start = no_range;
+        }
+    }
+
+    if get_idx {
+        Value::Array(vec![
+            Value::BulkString(Bytes::from_static(b"matches")),
+            Value::Array(matches),
+            Value::BulkString(Bytes::from_static(b"len")),
+            Value::Integer(lcs_len as i64),
+        ])
+    } else {
+        Value::BulkString(result.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn bulk(reply: Value) -> Option<Vec<u8>> {
+        match reply {
+            Value::BulkString(bytes) => Some(bytes.into()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn set_and_get() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            set(db, &args(&["k", "v"])),
+            Value::SimpleString(_)
+        ));
+        assert_eq!(bulk(get(db, &args(&["k"]))), Some(b"v".to_vec()));
+        assert!(matches!(set(db, &args(&["k", "w", "nx"])), Value::Null));
+        assert_eq!(bulk(getdel(db, &args(&["k"]))), Some(b"v".to_vec()));
+        assert!(matches!(get(db, &args(&["k"])), Value::Null));
+    }
+
+    #[tokio::test]
+    async fn mset_and_mget() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        mset(db, &args(&["a", "1", "b", "2"]));
+        let Value::Array(values) = mget(db, &args(&["a", "missing", "b"])) else {
+            panic!("MGET replies with an array");
+        };
+        let values: Vec<_> = values.into_iter().map(bulk).collect();
+        assert_eq!(values, [Some(b"1".to_vec()), None, Some(b"2".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn increments_integers_in_place() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(incr_by(db, b"n", 1), Value::Integer(1)));
+        assert!(matches!(
+            incrby(db, &args(&["n", "41"])),
+            Value::Integer(42)
+        ));
+        assert!(matches!(
+            decrby(db, &args(&["n", "50"])),
+            Value::Integer(-8)
+        ));
+        assert_eq!(bulk(get(db, &args(&["n"]))), Some(b"-8".to_vec()));
+
+        // The TTL stays, as it does for anything else that edits a string in place
+        set(db, &args(&["t", "10", "px", "100000"]));
+        incr_by(db, b"t", -1);
+        assert_eq!(bulk(get(db, &args(&["t"]))), Some(b"9".to_vec()));
+        assert!(db.get(b"t".as_slice()).unwrap().expires_at().is_some());
+
+        set(db, &args(&["s", "text"]));
+        assert!(incr_by(db, b"s", 1).error_message().is_some());
+        set(db, &args(&["max", &i64::MAX.to_string()]));
+        assert_eq!(
+            incr_by(db, b"max", 1).error_message(),
+            Some("ERR increment or decrement would overflow".to_string())
+        );
+        assert!(
+            decrby(db, &args(&["n", &i64::MIN.to_string()]))
+                .error_message()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn appends_to_strings_and_integers() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(append(db, &args(&["k", "ab"])), Value::Integer(2)));
+        assert!(matches!(append(db, &args(&["k", "cd"])), Value::Integer(4)));
+        assert_eq!(bulk(get(db, &args(&["k"]))), Some(b"abcd".to_vec()));
+
+        // An integer is appended to as its digits, and stops being one
+        set(db, &args(&["n", "12", "px", "100000"]));
+        assert!(matches!(append(db, &args(&["n", "x"])), Value::Integer(3)));
+        assert_eq!(bulk(get(db, &args(&["n"]))), Some(b"12x".to_vec()));
+        assert!(db.get(b"n".as_slice()).unwrap().expires_at().is_some());
+        assert!(incr_by(db, b"n", 1).error_message().is_some());
+
+        db.insert(
+            b"l".to_vec(),
+            DBData::new(DBVal::List(Default::default()), None),
+        );
+        assert!(append(db, &args(&["l", "x"])).error_message().is_some());
+    }
+}
@@ -0,0 +1,1307 @@
+use crate::blocking::{Block, Outcome};
+use crate::cmd::{
+    format_float, lookup, lower, normalize_range, not_an_integer, parse_int, parse_timeout, read,
+    registry::Spec, syntax_error, wrong_args, wrong_type,
+};
+use crate::db::{DBData, DBVal, Keyspace};
+use crate::notify::{self, Class};
+use crate::resp::Value;
+use crate::zset::ZSet;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// The sorted set commands.
+pub const COMMANDS: &[Spec] = &[
+    Spec::keyspace("zadd", -4, [1, 1, 1], zadd),
+    Spec::keyspace("zincrby", 4, [1, 1, 1], zincrby),
+    Spec::keyspace("zrem", -3, [1, 1, 1], zrem),
+    Spec::read("zscore", 3, [1, 1, 1], zscore),
+    Spec::read("zcard", 2, [1, 1, 1], zcard),
+    Spec::keyspace("zrange", -4, [1, 1, 1], zrange),
+    Spec::keyspace("zrevrange", -4, [1, 1, 1], zrevrange),
+    Spec::keyspace("zrangebyscore", -4, [1, 1, 1], zrangebyscore),
+    Spec::keyspace("zrevrangebyscore", -4, [1, 1, 1], zrevrangebyscore),
+    Spec::keyspace("zrangebylex", -4, [1, 1, 1], zrangebylex),
+    Spec::keyspace("zrevrangebylex", -4, [1, 1, 1], zrevrangebylex),
+    Spec::keyspace("zrank", -3, [1, 1, 1], zrank).at_most(4),
+    Spec::keyspace("zrevrank", -3, [1, 1, 1], zrevrank).at_most(4),
+    Spec::keyspace("zcount", 4, [1, 1, 1], zcount),
+    Spec::keyspace("zlexcount", 4, [1, 1, 1], zlexcount),
+    Spec::keyspace("zpopmin", -2, [1, 1, 1], zpopmin).at_most(3),
+    Spec::keyspace("zpopmax", -2, [1, 1, 1], zpopmax).at_most(3),
+    Spec::keyspace("zmpop", -4, [0, 0, 0], zmpop),
+    Spec::blocking("bzpopmin", -3, [1, -2, 1], |_, args| bzpopmin(args)),
+    Spec::blocking("bzpopmax", -3, [1, -2, 1], |_, args| bzpopmax(args)),
+    Spec::blocking("bzmpop", -5, [0, 0, 0], |_, args| bzmpop(args)),
+    Spec::keyspace("zunion", -3, [0, 0, 0], zunion),
+    Spec::keyspace("zinter", -3, [0, 0, 0], zinter),
+    Spec::keyspace("zdiff", -3, [0, 0, 0], zdiff),
+    Spec::keyspace("zunionstore", -4, [1, 1, 1], zunionstore),
+    Spec::keyspace("zinterstore", -4, [1, 1, 1], zinterstore),
+    Spec::keyspace("zdiffstore", -4, [1, 1, 1], zdiffstore),
+];
+
+/// Fetches the sorted set stored at `key`. `Ok(None)` means the key doesn't exist.
+pub fn get_zset<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<Option<&'a mut ZSet>, Value> {
+    match lookup(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data_mut() {
+            DBVal::ZSet(zset) => Ok(Some(zset)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+/// Like [`get_zset`], for a command that only reads.
+fn read_zset<'a>(db: &'a Keyspace, key: &[u8]) -> Result<Option<&'a ZSet>, Value> {
+    match read(db, key) {
+        None => Ok(None),
+        Some(val) => match val.data() {
+            DBVal::ZSet(zset) => Ok(Some(zset)),
+            _ => Err(wrong_type()),
+        },
+    }
+}
+
+fn get_or_create_zset<'a>(db: &'a mut Keyspace, key: &[u8]) -> Result<&'a mut ZSet, Value> {
+    if get_zset(db, key)?.is_none() {
+        db.insert(key.to_vec(), DBData::new(DBVal::ZSet(ZSet::new()), None));
+    }
+
+    Ok(get_zset(db, key)?.expect("sorted set was just created"))
+}
+
+/// Drops `key` if its sorted set has no members left.
+fn remove_if_empty(db: &mut Keyspace, key: &[u8]) {
+    if let Some(DBVal::ZSet(zset)) = db.get(key).map(|val| val.data())
+        && zset.is_empty()
+    {
+        db.remove(key);
+        notify::emit(Class::Generic, "del", key);
+    }
+}
+
+/// Parses a score, accepting `inf`/`+inf`/`-inf` but never NaN.
+pub fn parse_score(arg: &[u8]) -> Option<f64> {
+    parse_int::<f64>(arg).filter(|score| !score.is_nan())
+}
+
+fn not_a_float() -> Value {
+    Value::error("ERR value is not a valid float")
+}
+
+fn score_value(score: f64) -> Value {
+    Value::BulkString(format_float(score).into())
+}
+
+/// Flattens members (and optionally their scores) into a RESP2 reply.
+fn members_reply<'a>(members: impl Iterator<Item = (&'a [u8], f64)>, with_scores: bool) -> Value {
+    let mut reply = Vec::new();
+    for (member, score) in members {
+        reply.push(Value::BulkString(Bytes::copy_from_slice(member)));
+        if with_scores {
+            reply.push(score_value(score));
+        }
+    }
+
+    Value::Array(reply)
+}
+
+/// Flags accepted by ZADD ahead of its score/member pairs.
+#[derive(Default)]
+struct ZAddFlags {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+    ch: bool,
+    incr: bool,
+}
+
+/// What happened to a single member during ZADD.
+enum ZAddOutcome {
+    Added(f64),
+    Updated(f64),
+    Unchanged(f64),
+    Skipped,
+}
+
+fn resulting_nan() -> Value {
+    Value::error("ERR resulting score is not a number (NaN)")
+}
+
+/// Applies one score (or increment, with INCR) to `member` under the given flags.
+fn zadd_member(
+    zset: &mut ZSet,
+    member: &[u8],
+    score: f64,
+    flags: &ZAddFlags,
+) -> Result<ZAddOutcome, Value> {
+    let Some(current) = zset.score(member) else {
+        if flags.xx {
+            return Ok(ZAddOutcome::Skipped);
+        }
+        zset.insert(member.to_vec(), score);
+
+        return Ok(ZAddOutcome::Added(score));
+    };
+
+    if flags.nx {
+        return Ok(ZAddOutcome::Skipped);
+    }
+
+    let new = if flags.incr { current + score } else { score };
+    if new.is_nan() {
+        return Err(resulting_nan());
+    }
+    if (flags.gt && new <= current) || (flags.lt && new >= current) {
+        return Ok(ZAddOutcome::Skipped);
+    }
+    if new == current {
+        return Ok(ZAddOutcome::Unchanged(new));
+    }
+
+    zset.insert(member.to_vec(), new);
+
+    Ok(ZAddOutcome::Updated(new))
+}
+
+pub fn zadd(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    if args.len() < 3 {
+        return wrong_args("zadd");
+    }
+
+    let mut flags = ZAddFlags::default();
+    let mut i = 1;
+    while i < args.len() {
+        match lower(&args[i]).as_str() {
+            "nx" => flags.nx = true,
+            "xx" => flags.xx = true,
+            "gt" => flags.gt = true,
+            "lt" => flags.lt = true,
+            "ch" => flags.ch = true,
+            "incr" => flags.incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let elements = &args[i..];
+    if elements.is_empty() || !elements.len().is_multiple_of(2) {
+        return syntax_error();
+    }
+    if flags.nx && flags.xx {
+        return Value::error("ERR XX and NX options at the same time are not compatible");
+    }
+    if (flags.gt && flags.lt) || ((flags.gt || flags.lt) && flags.nx) {
+        return Value::error("ERR GT, LT, and/or NX options at the same time are not compatible");
+    }
+    if flags.incr && elements.len() > 2 {
+        return Value::error("ERR INCR option supports a single increment-element pair");
+    }
+
+    // Validate every score before touching the set, so a bad pair adds nothing
+    let mut pairs = Vec::with_capacity(elements.len() / 2);
+    for pair in elements.chunks(2) {
+        let Some(score) = parse_score(&pair[0]) else {
+            return not_a_float();
+        };
+        pairs.push((score, &pair[1]));
+    }
+
+    let skipped = if flags.incr {
+        Value::Null
+    } else {
+        Value::Integer(0)
+    };
+
+    // XX never creates the key
+    let zset = match get_zset(db, &args[0]) {
+        Ok(Some(zset)) => zset,
+        Ok(None) if flags.xx => return skipped,
+        Ok(None) => match get_or_create_zset(db, &args[0]) {
+            Ok(zset) => zset,
+            Err(e) => return e,
+        },
+        Err(e) => return e,
+    };
+
+    let mut added = 0;
+    let mut updated = 0;
+    let mut last = None;
+    for (score, member) in pairs {
+        match zadd_member(zset, member, score, &flags) {
+            Ok(ZAddOutcome::Added(score)) => {
+                added += 1;
+                last = Some(score);
+            }
+            Ok(ZAddOutcome::Updated(score)) => {
+                updated += 1;
+                last = Some(score);
+            }
+            Ok(ZAddOutcome::Unchanged(score)) => last = Some(score),
+            Ok(ZAddOutcome::Skipped) => {}
+            Err(e) => {
+                remove_if_empty(db, &args[0]);
+                return e;
+            }
+        }
+    }
+
+    if added + updated > 0 {
+        notify::emit(
+            Class::ZSet,
+            if flags.incr { "zincr" } else { "zadd" },
+            &args[0],
+        );
+    }
+
+    if flags.incr {
+        return last.map(score_value).unwrap_or(skipped);
+    }
+
+    Value::Integer(if flags.ch { added + updated } else { added })
+}
+
+fn zincrby(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let Some(increment) = parse_score(&args[1]) else {
+        return not_a_float();
+    };
+
+    let zset = match get_or_create_zset(db, &args[0]) {
+        Ok(zset) => zset,
+        Err(e) => return e,
+    };
+
+    let score = zset.score(&args[2]).unwrap_or(0.0) + increment;
+    if score.is_nan() {
+        remove_if_empty(db, &args[0]);
+        return resulting_nan();
+    }
+
+    zset.insert(args[2].clone(), score);
+    notify::emit(Class::ZSet, "zincr", &args[0]);
+
+    score_value(score)
+}
+
+fn zrem(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    let zset = match get_zset(db, &args[0]) {
+        Ok(Some(zset)) => zset,
+        Ok(None) => return Value::Integer(0),
+        Err(e) => return e,
+    };
+
+    let removed = args[1..]
+        .iter()
+        .filter(|member| zset.remove(member).is_some())
+        .count();
+
+    if removed > 0 {
+        notify::emit(Class::ZSet, "zrem", &args[0]);
+    }
+    remove_if_empty(db, &args[0]);
+
+    Value::Integer(removed as i64)
+}
+
+fn zscore(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    match read_zset(db, &args[0]) {
+        Ok(Some(zset)) => zset.score(&args[1]).map(score_value).unwrap_or(Value::Null),
+        Ok(None) => Value::Null,
+        Err(e) => e,
+    }
+}
+
+fn zcard(db: &Keyspace, args: &[Vec<u8>]) -> Value {
+    match read_zset(db, &args[0]) {
+        Ok(Some(zset)) => Value::Integer(zset.len() as i64),
+        Ok(None) => Value::Integer(0),
+        Err(e) => e,
+    }
+}
+
+/// One end of a score interval: `1.5`, `(1.5` (exclusive), `-inf` or `+inf`.
+#[derive(Clone, Copy)]
+struct ScoreBound {
+    value: f64,
+    exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(arg: &[u8]) -> Option<Self> {
+        let (value, exclusive) = match arg.strip_prefix(b"(") {
+            Some(rest) => (rest, true),
+            None => (arg, false),
+        };
+
+        Some(ScoreBound {
+            value: parse_score(value)?,
+            exclusive,
+        })
+    }
+
+    fn below(&self, score: f64) -> bool {
+        if self.exclusive {
+            self.value < score
+        } else {
+            self.value <= score
+        }
+    }
+
+    fn above(&self, score: f64) -> bool {
+        if self.exclusive {
+            self.value > score
+        } else {
+            self.value >= score
+        }
+    }
+}
+
+/// One end of a lexicographic interval: `[a` (inclusive), `(a` (exclusive), `-` or `+`.
+enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+impl LexBound {
+    fn parse(arg: &[u8]) -> Option<Self> {
+        match arg {
+            b"-" => Some(LexBound::Min),
+            b"+" => Some(LexBound::Max),
+            [b'[', rest @ ..] => Some(LexBound::Inclusive(rest.to_vec())),
+            [b'(', rest @ ..] => Some(LexBound::Exclusive(rest.to_vec())),
+            _ => None,
+        }
+    }
+
+    fn below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(bound) => bound.as_slice() <= member,
+            LexBound::Exclusive(bound) => bound.as_slice() < member,
+        }
+    }
+
+    fn above(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) => bound.as_slice() >= member,
+            LexBound::Exclusive(bound) => bound.as_slice() > member,
+        }
+    }
+}
+
+fn invalid_score_range() -> Value {
+    Value::error("ERR min or max is not a float")
+}
+
+fn invalid_lex_range() -> Value {
+    Value::error("ERR min or max not valid string range item")
+}
+
+/// How ZRANGE and friends interpret their `start`/`stop` arguments.
+#[derive(Clone, Copy, PartialEq)]
+enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+/// A fully parsed range request, shared by ZRANGE and its legacy variants.
+struct RangeSpec {
+    by: RangeBy,
+    rev: bool,
+    limit: Option<(i64, i64)>,
+    with_scores: bool,
+}
+
+impl RangeSpec {
+    /// Parses the options following `key start stop`. Only ZRANGE itself (`unified`) accepts
+    /// BYSCORE, BYLEX and REV; the legacy commands fix those up front.
+    fn parse(args: &[Vec<u8>], by: RangeBy, rev: bool, unified: bool) -> Result<Self, Value> {
+        let mut spec = RangeSpec {
+            by,
+            rev,
+            limit: None,
+            with_scores: false,
+        };
+
+        let mut i = 0;
+        while i < args.len() {
+            match lower(&args[i]).as_str() {
+                "withscores" => spec.with_scores = true,
+                "limit" if i + 2 < args.len() => {
+                    let (Some(offset), Some(count)) =
+                        (parse_int(&args[i + 1]), parse_int(&args[i + 2]))
+                    else {
+                        return Err(not_an_integer());
+                    };
+                    spec.limit = Some((offset, count));
+                    i += 2;
+                }
+                "byscore" if unified => spec.by = RangeBy::Score,
+                "bylex" if unified => spec.by = RangeBy::Lex,
+                "rev" if unified => spec.rev = true,
+                _ => return Err(syntax_error()),
+            }
+            i += 1;
+        }
+
+        if spec.limit.is_some() && spec.by == RangeBy::Rank {
+            return Err(Value::error(
+                "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+            ));
+        }
+        if spec.with_scores && spec.by == RangeBy::Lex {
+            return Err(Value::error(
+                "ERR syntax error, WITHSCORES not supported in combination with BYLEX",
+            ));
+        }
+
+        Ok(spec)
+    }
+}
+
+/// Selects the members of `zset` between `start` and `stop` as described by `spec`. With REV
+/// the bounds are given high-to-low, as Redis expects.
+fn range_members<'a>(
+    zset: &'a ZSet,
+    start: &[u8],
+    stop: &[u8],
+    spec: &RangeSpec,
+) -> Result<Vec<(&'a [u8], f64)>, Value> {
+    let (low, high) = if spec.rev {
+        (stop, start)
+    } else {
+        (start, stop)
+    };
+
+    let mut members: Vec<_> = match spec.by {
+        RangeBy::Rank => {
+            let (Some(start), Some(stop)) = (parse_int::<i64>(start), parse_int::<i64>(stop))
+            else {
+                return Err(not_an_integer());
+            };
+            let Some((start, stop)) = normalize_range(start, stop, zset.len()) else {
+                return Ok(Vec::new());
+            };
+
+            return Ok(if spec.rev {
+                zset.iter()
+                    .rev()
+                    .skip(start)
+                    .take(stop - start + 1)
+                    .collect()
+            } else {
+                zset.iter().skip(start).take(stop - start + 1).collect()
+            });
+        }
+        RangeBy::Score => {
+            let (Some(min), Some(max)) = (ScoreBound::parse(low), ScoreBound::parse(high)) else {
+                return Err(invalid_score_range());
+            };
+
+            zset.iter_from(min.value)
+                .skip_while(|(_, score)| !min.below(*score))
+                .take_while(|(_, score)| max.above(*score))
+                .collect()
+        }
+        RangeBy::Lex => {
+            let (Some(min), Some(max)) = (LexBound::parse(low), LexBound::parse(high)) else {
+                return Err(invalid_lex_range());
+            };
+
+            zset.iter()
+                .skip_while(|(member, _)| !min.below(member))
+                .take_while(|(member, _)| max.above(member))
+                .collect()
+        }
+    };
+
+    if spec.rev {
+        members.reverse();
+    }
+
+    if let Some((offset, count)) = spec.limit {
+        if offset < 0 {
+            return Ok(Vec::new());
+        }
+        let count = usize::try_from(count).unwrap_or(usize::MAX);
+        members = members
+            .into_iter()
+            .skip(offset as usize)
+            .take(count)
+            .collect();
+    }
+
+    Ok(members)
+}
+
+/// Shared implementation of ZRANGE, ZREVRANGE, Z[REV]RANGEBYSCORE and Z[REV]RANGEBYLEX.
+fn zrange_generic(db: &mut Keyspace, args: &[Vec<u8>], cmd: &str, by: RangeBy, rev: bool) -> Value {
+    if args.len() < 3 {
+        return wrong_args(cmd);
+    }
+
+    let spec = match RangeSpec::parse(&args[3..], by, rev, cmd == "zrange") {
+        Ok(spec) => spec,
+        Err(e) => return e,
+    };
+
+    // A missing key still validates its bounds, so errors don't depend on whether it exists
+    let empty = ZSet::new();
+    let zset = match get_zset(db, &args[0]) {
+        Ok(Some(zset)) => &*zset,
+        Ok(None) => &empty,
+        Err(e) => return e,
+    };
+
+    match range_members(zset, &args[1], &args[2], &spec) {
+        Ok(members) => members_reply(members.into_iter(), spec.with_scores),
+        Err(e) => e,
+    }
+}
+
+fn zrange(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    zrange_generic(db, args, "zrange", RangeBy::Rank, false)
+}
+
+fn zrevrange(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    zrange_generic(db, args, "zrevrange", RangeBy::Rank, true)
+}
+
+fn zrangebyscore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    zrange_generic(db, args, "zrangebyscore", RangeBy::Score, false)
+}
+
+fn zrevrangebyscore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    zrange_generic(db, args, "zrevrangebyscore", RangeBy::Score, true)
+}
+
+fn zrangebylex(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    zrange_generic(db, args, "zrangebylex", RangeBy::Lex, false)
+}
+
+fn zrevrangebylex(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    zrange_generic(db, args, "zrevrangebylex", RangeBy::Lex, true)
+}
+
+fn rank_generic(db: &mut Keyspace, args: &[Vec<u8>], rev: bool) -> Value {
+    let with_score = match args.get(2) {
+        None => false,
+        Some(arg) if lower(arg) == "withscore" => true,
+        Some(_) => return syntax_error(),
+    };
+
+    let zset = match get_zset(db, &args[0]) {
+        Ok(Some(zset)) => zset,
+        Ok(None) => return Value::Null,
+        Err(e) => return e,
+    };
+
+    let (Some(rank), Some(score)) = (zset.rank(&args[1]), zset.score(&args[1])) else {
+        return Value::Null;
+    };
+    let rank = if rev { zset.len() - 1 - rank } else { rank };
+
+    if with_score {
+        Value::Array(vec![Value::Integer(rank as i64), score_value(score)])
+    } else {
+        Value::Integer(rank as i64)
+    }
+}
+
+fn zrank(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    rank_generic(db, args, false)
+}
+
+fn zrevrank(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    rank_generic(db, args, true)
+}
+
+fn count_generic(db: &mut Keyspace, args: &[Vec<u8>], by: RangeBy) -> Value {
+    let spec = RangeSpec {
+        by,
+        rev: false,
+        limit: None,
+        with_scores: false,
+    };
+
+    let empty = ZSet::new();
+    let zset = match get_zset(db, &args[0]) {
+        Ok(Some(zset)) => &*zset,
+        Ok(None) => &empty,
+        Err(e) => return e,
+    };
+
+    match range_members(zset, &args[1], &args[2], &spec) {
+        Ok(members) => Value::Integer(members.len() as i64),
+        Err(e) => e,
+    }
+}
+
+fn zcount(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    count_generic(db, args, RangeBy::Score)
+}
+
+fn zlexcount(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    count_generic(db, args, RangeBy::Lex)
+}
+
+/// Pops up to `count` members from the low end of `key`, or the high end with `max`, dropping
+/// the key once it's empty. A missing key pops nothing.
+fn pop_members(
+    db: &mut Keyspace,
+    key: &[u8],
+    count: usize,
+    max: bool,
+) -> Result<Vec<(Vec<u8>, f64)>, Value> {
+    let Some(zset) = get_zset(db, key)? else {
+        return Ok(Vec::new());
+    };
+
+    let popped: Vec<_> = (0..count).map_while(|_| zset.pop(max)).collect();
+    if !popped.is_empty() {
+        notify::emit(Class::ZSet, if max { "zpopmax" } else { "zpopmin" }, key);
+    }
+    remove_if_empty(db, key);
+
+    Ok(popped)
+}
+
+fn pop_generic(db: &mut Keyspace, args: &[Vec<u8>], max: bool) -> Value {
+    let count = match args.get(1).map(|arg| parse_int::<i64>(arg)) {
+        None => 1,
+        Some(Some(n)) if n >= 0 => n as usize,
+        Some(_) => return Value::error("ERR value is out of range, must be positive"),
+    };
+
+    match pop_members(db, &args[0], count, max) {
+        Ok(popped) => members_reply(
+            popped
+                .iter()
+                .map(|(member, score)| (member.as_slice(), *score)),
+            true,
+        ),
+        Err(e) => e,
+    }
+}
+
+fn zpopmin(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    pop_generic(db, args, false)
+}
+
+fn zpopmax(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    pop_generic(db, args, true)
+}
+
+/// The keys, end and count of a ZMPOP, parsed from `numkeys key [key ...] MIN|MAX [COUNT count]`.
+struct MPopArgs {
+    keys: Vec<Vec<u8>>,
+    max: bool,
+    count: usize,
+}
+
+impl MPopArgs {
+    fn parse(args: &[Vec<u8>]) -> Result<Self, Value> {
+        let num_keys = match parse_int::<i64>(&args[0]) {
+            Some(n) if n > 0 => n as usize,
+            _ => return Err(Value::error("ERR numkeys should be greater than 0")),
+        };
+        if num_keys > args.len() - 2 {
+            return Err(syntax_error());
+        }
+
+        let keys = args[1..=num_keys].to_vec();
+        let max = match lower(&args[num_keys + 1]).as_str() {
+            "min" => false,
+            "max" => true,
+            _ => return Err(syntax_error()),
+        };
+
+        let count = match &args[num_keys + 2..] {
+            [] => 1,
+            [option, count] if option.eq_ignore_ascii_case(b"count") => {
+                match parse_int::<i64>(count) {
+                    Some(n) if n > 0 => n as usize,
+                    _ => return Err(Value::error("ERR count should be greater than 0")),
+                }
+            }
+            _ => return Err(syntax_error()),
+        };
+
+        Ok(MPopArgs { keys, max, count })
+    }
+
+    /// Pops from the first non-empty key, replying `[key, [[member, score], ...]]`.
+    fn attempt(&self, db: &mut Keyspace) -> Option<Value> {
+        for key in &self.keys {
+            let popped = match pop_members(db, key, self.count, self.max) {
+                Ok(popped) if popped.is_empty() => continue,
+                Ok(popped) => popped,
+                Err(e) => return Some(e),
+            };
+
+            let members = popped
+                .into_iter()
+                .map(|(member, score)| {
+                    Value::Array(vec![Value::BulkString(member.into()), score_value(score)])
+                })
+                .collect();
+
+            return Some(Value::Array(vec![
+                Value::BulkString(key.clone().into()),
+                Value::Array(members),
+            ]));
+        }
+
+        None
+    }
+}
+
+fn zmpop(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    match MPopArgs::parse(args) {
+        Ok(mpop) => mpop.attempt(db).unwrap_or(Value::NullArray),
+        Err(e) => e,
+    }
+}
+
+/// BZPOPMIN and BZPOPMAX: pops one member from the first non-empty sorted set, replying
+/// `[key, member, score]`.
+fn blocking_pop(args: &[Vec<u8>], cmd: &str, max: bool) -> Outcome {
+    if args.len() < 2 {
+        return wrong_args(cmd).into();
+    }
+
+    let (keys, timeout) = args.split_at(args.len() - 1);
+    let deadline = match parse_timeout(&timeout[0]) {
+        Ok(deadline) => deadline,
+        Err(e) => return e.into(),
+    };
+
+    let keys = keys.to_vec();
+    Outcome::Block(Block::new(
+        keys.clone(),
+        deadline,
+        Value::NullArray,
+        move |db| {
+            for key in &keys {
+                let (member, score) = match pop_members(db, key, 1, max) {
+                    Ok(mut popped) => match popped.pop() {
+                        Some(popped) => popped,
+                        None => continue,
+                    },
+                    Err(e) => return Some(e),
+                };
+
+                return Some(Value::Array(vec![
+                    Value::BulkString(key.clone().into()),
+                    Value::BulkString(member.into()),
+                    score_value(score),
+                ]));
+            }
+
+            None
+        },
+    ))
+}
+
+fn bzpopmin(args: &[Vec<u8>]) -> Outcome {
+    blocking_pop(args, "bzpopmin", false)
+}
+
+fn bzpopmax(args: &[Vec<u8>]) -> Outcome {
+    blocking_pop(args, "bzpopmax", true)
+}
+
+fn bzmpop(args: &[Vec<u8>]) -> Outcome {
+    if args.len() < 4 {
+        return wrong_args("bzmpop").into();
+    }
+
+    let deadline = match parse_timeout(&args[0]) {
+        Ok(deadline) => deadline,
+        Err(e) => return e.into(),
+    };
+    let mpop = match MPopArgs::parse(&args[1..]) {
+        Ok(mpop) => mpop,
+        Err(e) => return e.into(),
+    };
+
+    Outcome::Block(Block::new(
+        mpop.keys.clone(),
+        deadline,
+        Value::NullArray,
+        move |db| mpop.attempt(db),
+    ))
+}
+
+/// Reads a ZUNION/ZINTER/ZDIFF source as member scores. Plain sets count as every member
+/// scoring 1, as in Redis. `Ok(None)` means the key doesn't exist.
+fn load_source(db: &mut Keyspace, key: &[u8]) -> Result<Option<HashMap<Vec<u8>, f64>>, Value> {
+    match lookup(db, key).map(|val| val.data()) {
+        None => Ok(None),
+        Some(DBVal::ZSet(zset)) => Ok(Some(
+            zset.iter()
+                .map(|(member, score)| (member.to_vec(), score))
+                .collect(),
+        )),
+        Some(DBVal::Set(set)) => Ok(Some(set.iter().map(|m| (m.into_owned(), 1.0)).collect())),
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ZSetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is NaN, which Redis turns into 0
+            Aggregate::Sum => Some(a + b).filter(|sum| !sum.is_nan()).unwrap_or(0.0),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// A parsed `numkeys key [key ...] [WEIGHTS ...] [AGGREGATE ...] [WITHSCORES]` tail.
+struct CombineArgs<'a> {
+    keys: &'a [Vec<u8>],
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+    with_scores: bool,
+}
+
+impl<'a> CombineArgs<'a> {
+    /// WEIGHTS and AGGREGATE are rejected for ZDIFF, and WITHSCORES for the STORE variants.
+    fn parse(args: &'a [Vec<u8>], cmd: &str, op: ZSetOp, store: bool) -> Result<Self, Value> {
+        let num_keys = match parse_int::<i64>(&args[0]) {
+            Some(n) if n > 0 => n as usize,
+            Some(_) => {
+                return Err(Value::error(format!(
+                    "ERR at least 1 input key is needed for '{cmd}' command"
+                )));
+            }
+            None => return Err(not_an_integer()),
+        };
+        if num_keys > args.len() - 1 {
+            return Err(syntax_error());
+        }
+
+        let mut combine = CombineArgs {
+            keys: &args[1..=num_keys],
+            weights: vec![1.0; num_keys],
+            aggregate: Aggregate::Sum,
+            with_scores: false,
+        };
+
+        let mut i = num_keys + 1;
+        while i < args.len() {
+            let remaining = args.len() - i - 1;
+            match lower(&args[i]).as_str() {
+                "weights" if op != ZSetOp::Diff && remaining >= num_keys => {
+                    for (weight, arg) in combine.weights.iter_mut().zip(&args[i + 1..]) {
+                        *weight = parse_score(arg)
+                            .ok_or_else(|| Value::error("ERR weight value is not a float"))?;
+                    }
+                    i += num_keys;
+                }
+                "aggregate" if op != ZSetOp::Diff && remaining >= 1 => {
+                    combine.aggregate = match lower(&args[i + 1]).as_str() {
+                        "sum" => Aggregate::Sum,
+                        "min" => Aggregate::Min,
+                        "max" => Aggregate::Max,
+                        _ => return Err(syntax_error()),
+                    };
+                    i += 1;
+                }
+                "withscores" if !store => combine.with_scores = true,
+                _ => return Err(syntax_error()),
+            }
+            i += 1;
+        }
+
+        Ok(combine)
+    }
+
+    fn combine(&self, db: &mut Keyspace, op: ZSetOp) -> Result<ZSet, Value> {
+        let mut sources = Vec::with_capacity(self.keys.len());
+        for key in self.keys {
+            sources.push(load_source(db, key)?);
+        }
+
+        // A zero weight times an infinite score is NaN, which Redis treats as 0
+        let weigh = |score: f64, weight: f64| {
+            Some(score * weight)
+                .filter(|score| !score.is_nan())
+                .unwrap_or(0.0)
+        };
+
+        let mut result = ZSet::new();
+        match op {
+            ZSetOp::Union => {
+                let mut scores: HashMap<Vec<u8>, f64> = HashMap::new();
+                for (source, weight) in sources.iter().zip(&self.weights) {
+                    for (member, score) in source.iter().flatten() {
+                        let score = weigh(*score, *weight);
+                        scores
+                            .entry(member.clone())
+                            .and_modify(|acc| *acc = self.aggregate.apply(*acc, score))
+                            .or_insert(score);
+                    }
+                }
+                for (member, score) in scores {
+                    result.insert(member, score);
+                }
+            }
+            ZSetOp::Inter => {
+                let Some(sources) = sources.into_iter().collect::<Option<Vec<_>>>() else {
+                    return Ok(result);
+                };
+
+                'members: for (member, score) in &sources[0] {
+                    let mut acc = weigh(*score, self.weights[0]);
+                    for (source, weight) in sources[1..].iter().zip(&self.weights[1..]) {
+                        let Some(score) = source.get(member) else {
+                            continue 'members;
+                        };
+                        acc = self.aggregate.apply(acc, weigh(*score, *weight));
+                    }
+                    result.insert(member.clone(), acc);
+                }
+            }
+            ZSetOp::Diff => {
+                for (member, score) in sources[0].iter().flatten() {
+                    if !sources[1..]
+                        .iter()
+                        .flatten()
+                        .any(|s| s.contains_key(member))
+                    {
+                        result.insert(member.clone(), *score);
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+fn combine_reply(db: &mut Keyspace, args: &[Vec<u8>], cmd: &str, op: ZSetOp) -> Value {
+    if args.len() < 2 {
+        return wrong_args(cmd);
+    }
+
+    let combine = match CombineArgs::parse(args, cmd, op, false) {
+        Ok(combine) => combine,
+        Err(e) => return e,
+    };
+
+    match combine.combine(db, op) {
+        Ok(zset) => members_reply(zset.iter(), combine.with_scores),
+        Err(e) => e,
+    }
+}
+
+fn combine_store(db: &mut Keyspace, args: &[Vec<u8>], cmd: &str, op: ZSetOp) -> Value {
+    if args.len() < 3 {
+        return wrong_args(cmd);
+    }
+
+    let zset = match CombineArgs::parse(&args[1..], cmd, op, true)
+        .and_then(|combine| combine.combine(db, op))
+    {
+        Ok(zset) => zset,
+        Err(e) => return e,
+    };
+    let len = zset.len();
+
+    if zset.is_empty() {
+        if db.remove(&args[0]).is_some() {
+            notify::emit(Class::Generic, "del", &args[0]);
+        }
+    } else {
+        db.insert(args[0].clone(), DBData::new(DBVal::ZSet(zset), None));
+        notify::emit(Class::ZSet, cmd, &args[0]);
+    }
+
+    Value::Integer(len as i64)
+}
+
+fn zunion(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_reply(db, args, "zunion", ZSetOp::Union)
+}
+
+fn zinter(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_reply(db, args, "zinter", ZSetOp::Inter)
+}
+
+fn zdiff(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_reply(db, args, "zdiff", ZSetOp::Diff)
+}
+
+fn zunionstore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_store(db, args, "zunionstore", ZSetOp::Union)
+}
+
+fn zinterstore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_store(db, args, "zinterstore", ZSetOp::Inter)
+}
+
+fn zdiffstore(db: &mut Keyspace, args: &[Vec<u8>]) -> Value {
+    combine_store(db, args, "zdiffstore", ZSetOp::Diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn elements(reply: Value) -> Vec<Vec<u8>> {
+        let Value::Array(values) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        values
+            .into_iter()
+            .map(|value| match value {
+                Value::BulkString(bytes) => bytes.into(),
+                value => panic!("expected a bulk string, got {value:?}"),
+            })
+            .collect()
+    }
+
+    /// Serves a blocking command at once, the way a transaction would.
+    fn now(outcome: Outcome, db: &mut Keyspace) -> Value {
+        match outcome {
+            Outcome::Reply(reply) => reply,
+            Outcome::Block(block) => block.attempt_now(db),
+        }
+    }
+
+    #[tokio::test]
+    async fn add_score_and_range() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        assert!(matches!(
+            zadd(db, &args(&["z", "2", "b", "1", "a", "3", "c"])),
+            Value::Integer(3)
+        ));
+        assert!(matches!(
+            zadd(db, &args(&["z", "2.5", "a"])),
+            Value::Integer(0)
+        ));
+        assert!(matches!(
+            zscore(db, &args(&["z", "c"])),
+            Value::BulkString(s) if s == b"3"[..]
+        ));
+        assert!(matches!(zscore(db, &args(&["z", "missing"])), Value::Null));
+        assert_eq!(
+            elements(zrange(db, &args(&["z", "0", "-1", "withscores"]))),
+            args(&["b", "2", "a", "2.5", "c", "3"])
+        );
+        assert_eq!(
+            elements(zrange(db, &args(&["z", "-1", "-1"]))),
+            args(&["c"])
+        );
+
+        // The sorted set goes with its last member
+        assert!(matches!(
+            zrem(db, &args(&["z", "a", "missing"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(zcard(db, &args(&["z"])), Value::Integer(2)));
+        zrem(db, &args(&["z", "b", "c"]));
+        assert!(db.get(b"z".as_slice()).is_none());
+    }
+
+    #[tokio::test]
+    async fn adds_under_conditions_and_increments() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        zadd(db, &args(&["z", "1", "a"]));
+
+        // NX leaves existing members be, while INCR replies with the new score
+        assert!(matches!(
+            zadd(db, &args(&["z", "nx", "9", "a"])),
+            Value::Integer(0)
+        ));
+        assert!(matches!(
+            zadd(db, &args(&["z", "incr", "1.5", "a"])),
+            Value::BulkString(s) if s == b"2.5"[..]
+        ));
+        assert!(matches!(
+            zadd(db, &args(&["z", "gt", "ch", "1", "a", "5", "b"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(
+            zadd(db, &args(&["z", "xx", "ch", "7", "a", "1", "c"])),
+            Value::Integer(1)
+        ));
+        assert!(
+            zadd(db, &args(&["z", "nx", "xx", "1", "a"]))
+                .error_message()
+                .is_some()
+        );
+
+        assert!(matches!(
+            zincrby(db, &args(&["z", "-2", "a"])),
+            Value::BulkString(s) if s == b"5"[..]
+        ));
+        assert!(
+            zincrby(db, &args(&["z", "x", "a"]))
+                .error_message()
+                .is_some()
+        );
+        assert_eq!(
+            elements(zrange(db, &args(&["z", "0", "-1", "withscores"]))),
+            args(&["a", "5", "b", "5"])
+        );
+    }
+
+    #[tokio::test]
+    async fn ranges_ranks_and_counts() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        zadd(db, &args(&["z", "1", "a", "2", "b", "3", "c", "4", "d"]));
+        assert_eq!(
+            elements(zrangebyscore(db, &args(&["z", "(1", "3"]))),
+            args(&["b", "c"])
+        );
+        assert_eq!(
+            elements(zrangebyscore(
+                db,
+                &args(&["z", "-inf", "+inf", "limit", "1", "2"])
+            )),
+            args(&["b", "c"])
+        );
+        assert!(
+            zrangebyscore(db, &args(&["z", "x", "3"]))
+                .error_message()
+                .is_some()
+        );
+        assert_eq!(
+            elements(zrevrange(db, &args(&["z", "0", "0"]))),
+            args(&["d"])
+        );
+        assert!(matches!(
+            zcount(db, &args(&["z", "-inf", "+inf"])),
+            Value::Integer(4)
+        ));
+        assert!(matches!(zrank(db, &args(&["z", "c"])), Value::Integer(2)));
+        assert!(matches!(
+            zrevrank(db, &args(&["z", "c"])),
+            Value::Integer(1)
+        ));
+        assert!(matches!(zrank(db, &args(&["z", "missing"])), Value::Null));
+
+        // Lex ranges are meant for members sharing a score
+        zadd(db, &args(&["l", "0", "a", "0", "b", "0", "c"]));
+        assert_eq!(
+            elements(zrangebylex(db, &args(&["l", "(a", "+"]))),
+            args(&["b", "c"])
+        );
+        assert!(matches!(
+            zlexcount(db, &args(&["l", "-", "[b"])),
+            Value::Integer(2)
+        ));
+        assert!(
+            zrangebylex(db, &args(&["l", "a", "+"]))
+                .error_message()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn pops_from_either_end() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+
+        zadd(db, &args(&["z", "1", "a", "2", "b", "3", "c", "4", "d"]));
+        assert_eq!(
+            elements(zpopmin(db, &args(&["z", "2"]))),
+            args(&["a", "1", "b", "2"])
+        );
+        assert_eq!(elements(zpopmax(db, &args(&["z"]))), args(&["d", "4"]));
+
+        let Value::Array(popped) = zmpop(db, &args(&["2", "missing", "z", "min"])) else {
+            panic!("ZMPOP replies with the key and its members");
+        };
+        assert!(matches!(&popped[0], Value::BulkString(key) if *key == b"z"[..]));
+        assert!(db.get(b"z".as_slice()).is_none());
+        assert!(matches!(
+            zmpop(db, &args(&["1", "z", "max"])),
+            Value::NullArray
+        ));
+
+        // Blocking pops serve what is there, and time out on what isn't
+        zadd(db, &args(&["z", "5", "e"]));
+        assert_eq!(
+            elements(now(bzpopmin(&args(&["missing", "z", "0"])), db)),
+            args(&["z", "e", "5"])
+        );
+        assert!(matches!(
+            now(bzpopmax(&args(&["z", "0"])), db),
+            Value::NullArray
+        ));
+    }
+
+    #[tokio::test]
+    async fn combines_with_weights_and_aggregates() {
+        let storage = db::new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let db = &mut dbs[0];
+        zadd(db, &args(&["x", "1", "a", "2", "b"]));
+        zadd(db, &args(&["y", "10", "b", "20", "c"]));
+
+        assert!(matches!(
+            zunionstore(db, &args(&["u", "2", "x", "y", "weights", "2", "1"])),
+            Value::Integer(3)
+        ));
+        assert_eq!(
+            elements(zrange(db, &args(&["u", "0", "-1", "withscores"]))),
+            args(&["a", "2", "b", "14", "c", "20"])
+        );
+        assert_eq!(
+            elements(zinter(
+                db,
+                &args(&["2", "x", "y", "aggregate", "max", "withscores"])
+            )),
+            args(&["b", "10"])
+        );
+        assert_eq!(elements(zdiff(db, &args(&["2", "x", "y"]))), args(&["a"]));
+
+        // Storing an empty result removes the destination
+        assert!(matches!(
+            zinterstore(db, &args(&["u", "2", "x", "missing"])),
+            Value::Integer(0)
+        ));
+        assert!(db.get(b"u".as_slice()).is_none());
+    }
+}
@@ -0,0 +1,1691 @@
+use crate::acl;
+use crate::aof;
+use crate::audit;
+use crate::cluster;
+use crate::cmd;
+use crate::db;
+use crate::encoding;
+use crate::glob::glob_match;
+use crate::latency;
+use crate::logging;
+use crate::notify;
+use crate::output::{self, Class};
+use crate::ratelimit;
+use crate::replication;
+use crate::resp;
+use crate::shadow;
+use crate::slowlog;
+use crate::systemd;
+use crate::tls;
+use crate::trace;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{LazyLock, Mutex, RwLock, RwLockReadGuard};
+use tracing::warn;
+
+/// The server's tunables, as CONFIG GET and SET see them. Settings other modules read on hot
+/// paths are also pushed out to them by [`ServerConfig::apply`].
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// Addresses to listen on. `*` and `::*` stand for every IPv4 and IPv6 interface, and a
+    /// leading `-` makes an address optional, so failing to bind it isn't fatal.
+    pub bind: Vec<String>,
+    pub port: u16,
+    /// How many connections the kernel may queue for each listener before they're accepted.
+    pub tcp_backlog: u32,
+    /// Seconds a connection may go quiet before the kernel starts probing whether the other
+    /// end is still there, or 0 for no probes.
+    pub tcp_keepalive: u64,
+    /// Whether replies go out as soon as they're written, rather than being held back to be
+    /// sent along with more (Nagle's algorithm).
+    pub tcp_nodelay: bool,
+    /// A port to take TLS connections on as well, or 0 for none. With one set, a `port` of 0
+    /// leaves out the plaintext listeners.
+    pub tls_port: u16,
+    /// The certificate chain and private key TLS connections are served with, as PEM files.
+    pub tls_cert_file: String,
+    pub tls_key_file: String,
+    /// The CA certificates clients' certificates are checked against, as a PEM file.
+    pub tls_ca_cert_file: String,
+    /// One of [`tls::AUTH_CLIENTS`].
+    pub tls_auth_clients: String,
+    /// What drives connections' I/O: tokio's own driver, or io_uring where it's built in.
+    pub io_backend: String,
+    /// Threads the runtime serves connections on: 1 runs everything on the main thread, and 0
+    /// leaves it at one per CPU core.
+    pub io_threads: usize,
+    /// A port to serve Prometheus metrics over HTTP on, or 0 for none.
+    pub metrics_port: u16,
+    /// A port to serve the read-only JSON admin API over HTTP on, or 0 for none.
+    pub admin_port: u16,
+    /// A port to speak memcached's text protocol on, onto database 0, or 0 for none.
+    pub memcached_port: u16,
+    /// A port to serve the REST gateway to keys over HTTP on, or 0 for none.
+    pub rest_port: u16,
+    /// A port to serve pub/sub over WebSocket on, or 0 for none.
+    pub websocket_port: u16,
+    /// A Unix socket to listen on as well, or empty for none.
+    pub unixsocket: String,
+    /// The permissions to give the Unix socket, or 0 to leave them to the umask.
+    pub unixsocketperm: u32,
+    /// Whether to turn away connections from other hosts while the default user has no
+    /// password and `bind` is left at its default.
+    pub protected_mode: bool,
+    /// Whether to carry on in the background, detached from the terminal.
+    pub daemonize: bool,
+    /// A file to write the process's ID to, or empty for none unless daemonized.
+    pub pidfile: String,
+    /// Whether to tell a supervisor when the server's ready and when it's stopping, as one of
+    /// [`systemd::SUPERVISED`].
+    pub supervised: String,
+    /// How much is logged, as one of [`logging::LEVELS`].
+    pub loglevel: String,
+    /// A file to append the log to, or empty for standard output.
+    pub logfile: String,
+    /// An OTLP/HTTP collector to export a span for each command to, like
+    /// `http://localhost:4318/v1/traces`, or empty for none.
+    pub otlp_endpoint: String,
+    pub databases: usize,
+    /// Whether to run as a node of a cluster, serving only the hash slots assigned to it.
+    pub cluster_enabled: bool,
+    /// The port other nodes reach the cluster bus on, or 0 for the client port plus 10000.
+    pub cluster_port: u16,
+    /// Milliseconds a node may go without answering before it's suspected of having failed.
+    pub cluster_node_timeout: u64,
+    pub requirepass: String,
+    /// Bytes of data to hold before evicting, or 0 for no limit.
+    pub maxmemory: u64,
+    pub maxmemory_policy: String,
+    /// The longest bulk string a client may send, in bytes.
+    pub proto_max_bulk_len: u64,
+    /// How slowly a key's access frequency counter climbs as it keeps being used.
+    pub lfu_log_factor: u64,
+    /// Minutes for the access frequency counter of a key left alone to drop by one, or 0 for
+    /// it to never drop.
+    pub lfu_decay_time: u64,
+    /// Client connections to take at once, past which new ones are turned away.
+    pub maxclients: usize,
+    /// How much reply data may pile up for each class of client, in [`Class::ALL`] order.
+    pub client_output_buffer_limits: [output::Limit; 3],
+    /// Seconds a client may sit idle before it's disconnected, or 0 to never.
+    pub timeout: u64,
+    /// Commands a second each connection may run, or 0 for no limit.
+    pub client_rate_limit: u64,
+    /// Commands a second all of one user's connections may run between them, or 0 for no
+    /// limit.
+    pub user_rate_limit: u64,
+    /// Commands that may be run back to back before the rate limits apply, or 0 for a second's
+    /// worth.
+    pub rate_limit_burst: u64,
+    /// What happens to a command over the rate limit: `error` or `delay`.
+    pub rate_limit_action: String,
+    /// Snapshot after `changes` writes within `seconds`, for each `(seconds, changes)`.
+    pub save: Vec<(u64, u64)>,
+    pub appendonly: bool,
+    pub appendfsync: String,
+    /// Whether a rewritten append-only file starts with a snapshot of the dataset rather than
+    /// the commands to rebuild it.
+    pub aof_use_rdb_preamble: bool,
+    pub dir: String,
+    pub dbfilename: String,
+    pub appendfilename: String,
+    /// The directory in `dir` Redis 7 and later keep their append-only files in, which is
+    /// loaded from at startup when there's no `appendfilename` to: see [`crate::aof`].
+    pub appenddirname: String,
+    /// The key snapshots and the append-only file are encrypted with, as `<id>:<hex key>`, or
+    /// empty to take it from the environment, if it's there: see [`crate::crypt`].
+    pub encryption_key: String,
+    /// A file of more keys, a line each, for reading files written with older ones.
+    pub encryption_keyfile: String,
+    /// Where snapshots are kept: `file`, in `dir`, or `s3`, in the bucket the `s3_*` settings
+    /// describe.
+    pub snapshot_store: String,
+    /// The object store's URL, such as `https://s3.eu-west-1.amazonaws.com`.
+    pub s3_endpoint: String,
+    pub s3_bucket: String,
+    /// What snapshots' object names start with, ahead of `dbfilename`.
+    pub s3_prefix: String,
+    /// The region requests are signed for.
+    pub s3_region: String,
+    /// Credentials requests are signed with, or empty to take them from `AWS_ACCESS_KEY_ID`
+    /// and `AWS_SECRET_ACCESS_KEY`.
+    pub s3_access_key_id: String,
+    pub s3_secret_access_key: String,
+    /// Bytes of replication stream kept so a replica that loses its link can carry on from
+    /// where it was instead of syncing from scratch.
+    pub repl_backlog_size: u64,
+    /// Whether replicas get their initial snapshot straight over the connection rather than
+    /// from the dump file, waiting `repl_diskless_sync_delay` seconds first for more replicas to
+    /// share it, or until `repl_diskless_sync_max_replicas` have if that's not 0.
+    pub repl_diskless_sync: bool,
+    pub repl_diskless_sync_delay: u64,
+    pub repl_diskless_sync_max_replicas: usize,
+    /// Whether a replica turns away writes from anyone but its master.
+    pub replica_read_only: bool,
+    /// How much failover tooling should favour this replica for promotion, the lowest first,
+    /// or 0 for it never to be promoted. Only reported, in INFO replication.
+    pub replica_priority: u64,
+    /// Replicas a master needs, acknowledging within `min_replicas_max_lag` seconds, to take
+    /// writes; 0 to take them regardless.
+    pub min_replicas_to_write: u64,
+    pub min_replicas_max_lag: u64,
+    pub notify_keyspace_events: String,
+    /// Commands taking at least this many microseconds go in the slow log; negative for none.
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    /// Events taking at least this many milliseconds are tracked by LATENCY; 0 for none.
+    pub latency_monitor_threshold: u64,
+    /// Milliseconds a script may run before other clients are told the server's busy with it
+    /// and it can be stopped with SCRIPT KILL or FUNCTION KILL. With `io_threads` at 1 there's
+    /// no thread left to tell them on.
+    pub busy_reply_threshold: u64,
+    /// A file to record every write command in, one JSON object a line, or empty for none.
+    pub audit_log: String,
+    /// How big the audit log may grow before it's moved aside for a new one, or 0 for no limit.
+    pub audit_log_max_size: u64,
+    /// A stream in database 0 to record every write command in as well, or empty for none.
+    pub audit_stream: String,
+    /// How many entries the audit stream is trimmed to, or 0 to keep them all.
+    pub audit_stream_maxlen: u64,
+    /// A file to record every command clients send in, for `replay`, or empty for none.
+    pub record_file: String,
+    /// A server, as `host:port`, to send every command clients send on to as well, logging
+    /// where its replies differ, or empty for none.
+    pub shadow_target: String,
+    /// WebAssembly modules to load at startup, adding the commands they register, when built
+    /// with the `wasm` feature.
+    pub wasm_modules: Vec<String>,
+    /// `rename-command` rules, from a command's real name to the one clients call it by, or to
+    /// an empty name to disable it. They only take effect at startup.
+    pub rename_commands: Vec<(String, String)>,
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+    pub list_max_listpack_size: i64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec!["*".to_string(), "-::*".to_string()],
+            port: 6379,
+            tcp_backlog: 511,
+            tcp_keepalive: 300,
+            tcp_nodelay: true,
+            tls_port: 0,
+            tls_cert_file: String::new(),
+            tls_key_file: String::new(),
+            tls_ca_cert_file: String::new(),
+            tls_auth_clients: "yes".to_string(),
+            io_backend: "tokio".to_string(),
+            io_threads: 0,
+            metrics_port: 0,
+            admin_port: 0,
+            memcached_port: 0,
+            rest_port: 0,
+            websocket_port: 0,
+            unixsocket: String::new(),
+            unixsocketperm: 0,
+            protected_mode: true,
+            daemonize: false,
+            pidfile: String::new(),
+            supervised: "no".to_string(),
+            loglevel: "notice".to_string(),
+            logfile: String::new(),
+            otlp_endpoint: String::new(),
+            databases: 16,
+            cluster_enabled: false,
+            cluster_port: 0,
+            cluster_node_timeout: 15_000,
+            requirepass: String::new(),
+            maxmemory: 0,
+            maxmemory_policy: "noeviction".to_string(),
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            maxclients: 10_000,
+            client_output_buffer_limits: output::DEFAULT_LIMITS,
+            timeout: 0,
+            client_rate_limit: 0,
+            user_rate_limit: 0,
+            rate_limit_burst: 0,
+            rate_limit_action: "error".to_string(),
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            appendonly: false,
+            appendfsync: "everysec".to_string(),
+            aof_use_rdb_preamble: true,
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            appendfilename: "appendonly.aof".to_string(),
+            appenddirname: "appendonlydir".to_string(),
+            encryption_key: String::new(),
+            encryption_keyfile: String::new(),
+            snapshot_store: "file".to_string(),
+            s3_endpoint: String::new(),
+            s3_bucket: String::new(),
+            s3_prefix: String::new(),
+            s3_region: "us-east-1".to_string(),
+            s3_access_key_id: String::new(),
+            s3_secret_access_key: String::new(),
+            repl_backlog_size: 1024 * 1024,
+            repl_diskless_sync: true,
+            repl_diskless_sync_delay: 5,
+            repl_diskless_sync_max_replicas: 0,
+            replica_read_only: true,
+            replica_priority: 100,
+            min_replicas_to_write: 0,
+            min_replicas_max_lag: 10,
+            notify_keyspace_events: String::new(),
+            slowlog_log_slower_than: 10_000,
+            slowlog_max_len: 128,
+            latency_monitor_threshold: 0,
+            busy_reply_threshold: 5000,
+            audit_log: String::new(),
+            audit_log_max_size: 0,
+            audit_stream: String::new(),
+            audit_stream_maxlen: 0,
+            record_file: String::new(),
+            shadow_target: String::new(),
+            wasm_modules: Vec::new(),
+            rename_commands: Vec::new(),
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+        }
+    }
+}
+
+/// One setting: how to show it, how to change it, and whether CONFIG SET may change it once the
+/// server is running.
+struct Parameter {
+    name: &'static str,
+    mutable: bool,
+    get: fn(&ServerConfig) -> String,
+    set: fn(&mut ServerConfig, &str) -> Result<(), String>,
+}
+
+const MAXMEMORY_POLICIES: &[&str] = &[
+    "volatile-lru",
+    "volatile-lfu",
+    "volatile-random",
+    "volatile-ttl",
+    "allkeys-lru",
+    "allkeys-lfu",
+    "allkeys-random",
+    "noeviction",
+];
+
+/// Values `io-backend` can take, io_uring only when built with the `io-uring` feature.
+const IO_BACKENDS: &[&str] = if cfg!(feature = "io-uring") {
+    &["tokio", "io-uring"]
+} else {
+    &["tokio"]
+};
+
+const PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "bind",
+        mutable: false,
+        get: |c| c.bind.join(" "),
+        set: |c, v| {
+            c.bind = v.split_whitespace().map(str::to_string).collect();
+            if c.bind.is_empty() {
+                return Err("bind needs at least one address".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "port",
+        mutable: false,
+        get: |c| c.port.to_string(),
+        set: |c, v| {
+            c.port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tcp-backlog",
+        mutable: false,
+        get: |c| c.tcp_backlog.to_string(),
+        set: |c, v| {
+            c.tcp_backlog = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tcp-keepalive",
+        mutable: true,
+        get: |c| c.tcp_keepalive.to_string(),
+        set: |c, v| {
+            c.tcp_keepalive = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tcp-nodelay",
+        mutable: true,
+        get: |c| yes_no(c.tcp_nodelay),
+        set: |c, v| {
+            c.tcp_nodelay = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-port",
+        mutable: false,
+        get: |c| c.tls_port.to_string(),
+        set: |c, v| {
+            c.tls_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-cert-file",
+        mutable: false,
+        get: |c| c.tls_cert_file.clone(),
+        set: |c, v| {
+            c.tls_cert_file = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-key-file",
+        mutable: false,
+        get: |c| c.tls_key_file.clone(),
+        set: |c, v| {
+            c.tls_key_file = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-ca-cert-file",
+        mutable: false,
+        get: |c| c.tls_ca_cert_file.clone(),
+        set: |c, v| {
+            c.tls_ca_cert_file = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-auth-clients",
+        mutable: false,
+        get: |c| c.tls_auth_clients.clone(),
+        set: |c, v| {
+            c.tls_auth_clients = parse_enum(v, tls::AUTH_CLIENTS)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "io-backend",
+        mutable: false,
+        get: |c| c.io_backend.clone(),
+        set: |c, v| {
+            c.io_backend = parse_enum(v, IO_BACKENDS)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "io-threads",
+        mutable: false,
+        get: |c| c.io_threads.to_string(),
+        set: |c, v| {
+            c.io_threads = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "metrics-port",
+        mutable: false,
+        get: |c| c.metrics_port.to_string(),
+        set: |c, v| {
+            c.metrics_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "admin-port",
+        mutable: false,
+        get: |c| c.admin_port.to_string(),
+        set: |c, v| {
+            c.admin_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "memcached-port",
+        mutable: false,
+        get: |c| c.memcached_port.to_string(),
+        set: |c, v| {
+            c.memcached_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "rest-port",
+        mutable: false,
+        get: |c| c.rest_port.to_string(),
+        set: |c, v| {
+            c.rest_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "websocket-port",
+        mutable: false,
+        get: |c| c.websocket_port.to_string(),
+        set: |c, v| {
+            c.websocket_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "unixsocket",
+        mutable: false,
+        get: |c| c.unixsocket.clone(),
+        set: |c, v| {
+            c.unixsocket = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "unixsocketperm",
+        mutable: false,
+        get: |c| format!("{:o}", c.unixsocketperm),
+        set: |c, v| {
+            c.unixsocketperm = u32::from_str_radix(v, 8)
+                .ok()
+                .filter(|perm| *perm <= 0o777)
+                .ok_or_else(|| "argument must be an octal permission mode".to_string())?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "daemonize",
+        mutable: false,
+        get: |c| yes_no(c.daemonize),
+        set: |c, v| {
+            c.daemonize = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "pidfile",
+        mutable: false,
+        get: |c| c.pidfile.clone(),
+        set: |c, v| {
+            c.pidfile = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "supervised",
+        mutable: false,
+        get: |c| c.supervised.clone(),
+        set: |c, v| {
+            c.supervised = parse_enum(v, systemd::SUPERVISED)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "loglevel",
+        mutable: true,
+        get: |c| c.loglevel.clone(),
+        set: |c, v| {
+            c.loglevel = parse_enum(v, logging::LEVELS)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "logfile",
+        mutable: false,
+        get: |c| c.logfile.clone(),
+        set: |c, v| {
+            c.logfile = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "otlp-endpoint",
+        mutable: false,
+        get: |c| c.otlp_endpoint.clone(),
+        set: |c, v| {
+            c.otlp_endpoint = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "protected-mode",
+        mutable: true,
+        get: |c| yes_no(c.protected_mode),
+        set: |c, v| {
+            c.protected_mode = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "databases",
+        mutable: false,
+        get: |c| c.databases.to_string(),
+        set: |c, v| {
+            c.databases = parse_number(v)?;
+            if c.databases == 0 {
+                return Err("argument must be greater than 0".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "cluster-enabled",
+        mutable: false,
+        get: |c| yes_no(c.cluster_enabled),
+        set: |c, v| {
+            c.cluster_enabled = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "cluster-port",
+        mutable: false,
+        get: |c| c.cluster_port.to_string(),
+        set: |c, v| {
+            c.cluster_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "cluster-node-timeout",
+        mutable: true,
+        get: |c| c.cluster_node_timeout.to_string(),
+        set: |c, v| {
+            c.cluster_node_timeout = parse_number(v)?;
+            if c.cluster_node_timeout == 0 {
+                return Err("argument must be greater than 0".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "requirepass",
+        mutable: true,
+        get: |c| c.requirepass.clone(),
+        set: |c, v| {
+            c.requirepass = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory",
+        mutable: true,
+        get: |c| c.maxmemory.to_string(),
+        set: |c, v| {
+            c.maxmemory = parse_memory(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "proto-max-bulk-len",
+        mutable: true,
+        get: |c| c.proto_max_bulk_len.to_string(),
+        set: |c, v| {
+            // Less than a megabyte would leave no room for ordinary values
+            c.proto_max_bulk_len = parse_memory(v)?.max(1024 * 1024);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "repl-backlog-size",
+        mutable: true,
+        get: |c| c.repl_backlog_size.to_string(),
+        set: |c, v| {
+            // Too small a backlog would be of no use to anyone
+            c.repl_backlog_size = parse_memory(v)?.max(16 * 1024);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "repl-diskless-sync",
+        mutable: true,
+        get: |c| yes_no(c.repl_diskless_sync),
+        set: |c, v| {
+            c.repl_diskless_sync = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "repl-diskless-sync-delay",
+        mutable: true,
+        get: |c| c.repl_diskless_sync_delay.to_string(),
+        set: |c, v| {
+            c.repl_diskless_sync_delay = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "repl-diskless-sync-max-replicas",
+        mutable: true,
+        get: |c| c.repl_diskless_sync_max_replicas.to_string(),
+        set: |c, v| {
+            c.repl_diskless_sync_max_replicas = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "replica-read-only",
+        mutable: true,
+        get: |c| yes_no(c.replica_read_only),
+        set: |c, v| {
+            c.replica_read_only = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "replica-priority",
+        mutable: true,
+        get: |c| c.replica_priority.to_string(),
+        set: |c, v| {
+            c.replica_priority = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "min-replicas-to-write",
+        mutable: true,
+        get: |c| c.min_replicas_to_write.to_string(),
+        set: |c, v| {
+            c.min_replicas_to_write = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "min-replicas-max-lag",
+        mutable: true,
+        get: |c| c.min_replicas_max_lag.to_string(),
+        set: |c, v| {
+            c.min_replicas_max_lag = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory-policy",
+        mutable: true,
+        get: |c| c.maxmemory_policy.clone(),
+        set: |c, v| {
+            c.maxmemory_policy = parse_enum(v, MAXMEMORY_POLICIES)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "lfu-log-factor",
+        mutable: true,
+        get: |c| c.lfu_log_factor.to_string(),
+        set: |c, v| {
+            c.lfu_log_factor = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "lfu-decay-time",
+        mutable: true,
+        get: |c| c.lfu_decay_time.to_string(),
+        set: |c, v| {
+            c.lfu_decay_time = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxclients",
+        mutable: true,
+        get: |c| c.maxclients.to_string(),
+        set: |c, v| {
+            c.maxclients = parse_number(v)?;
+            if c.maxclients == 0 {
+                return Err("argument must be greater than 0".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "client-output-buffer-limit",
+        mutable: true,
+        get: |c| {
+            Class::ALL
+                .iter()
+                .zip(&c.client_output_buffer_limits)
+                .map(|(class, limit)| {
+                    let output::Limit {
+                        hard,
+                        soft,
+                        soft_seconds,
+                    } = limit;
+                    format!("{} {hard} {soft} {soft_seconds}", class.name())
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        set: |c, v| parse_output_limits(&mut c.client_output_buffer_limits, v),
+    },
+    Parameter {
+        name: "timeout",
+        mutable: true,
+        get: |c| c.timeout.to_string(),
+        set: |c, v| {
+            c.timeout = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "client-rate-limit",
+        mutable: true,
+        get: |c| c.client_rate_limit.to_string(),
+        set: |c, v| {
+            c.client_rate_limit = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "user-rate-limit",
+        mutable: true,
+        get: |c| c.user_rate_limit.to_string(),
+        set: |c, v| {
+            c.user_rate_limit = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "rate-limit-burst",
+        mutable: true,
+        get: |c| c.rate_limit_burst.to_string(),
+        set: |c, v| {
+            c.rate_limit_burst = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "rate-limit-action",
+        mutable: true,
+        get: |c| c.rate_limit_action.clone(),
+        set: |c, v| {
+            c.rate_limit_action = parse_enum(v, ratelimit::ACTIONS)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "save",
+        mutable: true,
+        get: |c| {
+            c.save
+                .iter()
+                .map(|(seconds, changes)| format!("{seconds} {changes}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        set: |c, v| {
+            c.save = parse_save(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "appendonly",
+        mutable: true,
+        get: |c| yes_no(c.appendonly),
+        set: |c, v| {
+            c.appendonly = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "appendfsync",
+        mutable: true,
+        get: |c| c.appendfsync.clone(),
+        set: |c, v| {
+            c.appendfsync = parse_enum(v, &["always", "everysec", "no"])?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "aof-use-rdb-preamble",
+        mutable: true,
+        get: |c| yes_no(c.aof_use_rdb_preamble),
+        set: |c, v| {
+            c.aof_use_rdb_preamble = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "dir",
+        mutable: true,
+        get: |c| c.dir.clone(),
+        set: |c, v| {
+            c.dir = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "dbfilename",
+        mutable: true,
+        get: |c| c.dbfilename.clone(),
+        set: |c, v| {
+            c.dbfilename = parse_filename(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "appendfilename",
+        mutable: false,
+        get: |c| c.appendfilename.clone(),
+        set: |c, v| {
+            c.appendfilename = parse_filename(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "appenddirname",
+        mutable: false,
+        get: |c| c.appenddirname.clone(),
+        set: |c, v| {
+            c.appenddirname = parse_filename(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "encryption-key",
+        mutable: false,
+        get: |c| c.encryption_key.clone(),
+        set: |c, v| {
+            c.encryption_key = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "encryption-keyfile",
+        mutable: false,
+        get: |c| c.encryption_keyfile.clone(),
+        set: |c, v| {
+            c.encryption_keyfile = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "snapshot-store",
+        mutable: false,
+        get: |c| c.snapshot_store.clone(),
+        set: |c, v| {
+            c.snapshot_store = parse_enum(v, &["file", "s3"])?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "s3-endpoint",
+        mutable: false,
+        get: |c| c.s3_endpoint.clone(),
+        set: |c, v| {
+            c.s3_endpoint = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "s3-bucket",
+        mutable: false,
+        get: |c| c.s3_bucket.clone(),
+        set: |c, v| {
+            c.s3_bucket = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "s3-prefix",
+        mutable: false,
+        get: |c| c.s3_prefix.clone(),
+        set: |c, v| {
+            c.s3_prefix = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "s3-region",
+        mutable: false,
+        get: |c| c.s3_region.clone(),
+        set: |c, v| {
+            c.s3_region = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "s3-access-key-id",
+        mutable: false,
+        get: |c| c.s3_access_key_id.clone(),
+        set: |c, v| {
+            c.s3_access_key_id = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "s3-secret-access-key",
+        mutable: false,
+        get: |c| c.s3_secret_access_key.clone(),
+        set: |c, v| {
+            c.s3_secret_access_key = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "notify-keyspace-events",
+        mutable: true,
+        get: |c| c.notify_keyspace_events.clone(),
+        set: |c, v| {
+            notify::parse_flags(v)
+                .ok_or("Invalid event class character. Use 'Ag$lshzxeKEtmdn'.")?;
+            c.notify_keyspace_events = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "slowlog-log-slower-than",
+        mutable: true,
+        get: |c| c.slowlog_log_slower_than.to_string(),
+        set: |c, v| {
+            c.slowlog_log_slower_than = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "slowlog-max-len",
+        mutable: true,
+        get: |c| c.slowlog_max_len.to_string(),
+        set: |c, v| {
+            c.slowlog_max_len = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "latency-monitor-threshold",
+        mutable: true,
+        get: |c| c.latency_monitor_threshold.to_string(),
+        set: |c, v| {
+            c.latency_monitor_threshold = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "busy-reply-threshold",
+        mutable: true,
+        get: |c| c.busy_reply_threshold.to_string(),
+        set: |c, v| {
+            c.busy_reply_threshold = parse_number(v)?;
+            Ok(())
+        },
+    },
+    // What busy-reply-threshold was called before it covered functions too
+    Parameter {
+        name: "lua-time-limit",
+        mutable: true,
+        get: |c| c.busy_reply_threshold.to_string(),
+        set: |c, v| {
+            c.busy_reply_threshold = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "audit-log",
+        mutable: true,
+        get: |c| c.audit_log.clone(),
+        set: |c, v| {
+            c.audit_log = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "audit-log-max-size",
+        mutable: true,
+        get: |c| c.audit_log_max_size.to_string(),
+        set: |c, v| {
+            c.audit_log_max_size = parse_memory(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "audit-stream",
+        mutable: true,
+        get: |c| c.audit_stream.clone(),
+        set: |c, v| {
+            c.audit_stream = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "audit-stream-maxlen",
+        mutable: true,
+        get: |c| c.audit_stream_maxlen.to_string(),
+        set: |c, v| {
+            c.audit_stream_maxlen = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "record-file",
+        mutable: true,
+        get: |c| c.record_file.clone(),
+        set: |c, v| {
+            c.record_file = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "shadow-target",
+        mutable: true,
+        get: |c| c.shadow_target.clone(),
+        set: |c, v| {
+            c.shadow_target = v.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "wasm-modules",
+        mutable: false,
+        get: |c| c.wasm_modules.join(" "),
+        set: |c, v| {
+            c.wasm_modules = v.split_whitespace().map(str::to_string).collect();
+            if !c.wasm_modules.is_empty() && !cfg!(feature = "wasm") {
+                return Err("loading WebAssembly modules needs the wasm feature".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "hash-max-listpack-entries",
+        mutable: true,
+        get: |c| c.hash_max_listpack_entries.to_string(),
+        set: |c, v| {
+            c.hash_max_listpack_entries = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "hash-max-listpack-value",
+        mutable: true,
+        get: |c| c.hash_max_listpack_value.to_string(),
+        set: |c, v| {
+            c.hash_max_listpack_value = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "set-max-intset-entries",
+        mutable: true,
+        get: |c| c.set_max_intset_entries.to_string(),
+        set: |c, v| {
+            c.set_max_intset_entries = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "set-max-listpack-entries",
+        mutable: true,
+        get: |c| c.set_max_listpack_entries.to_string(),
+        set: |c, v| {
+            c.set_max_listpack_entries = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "set-max-listpack-value",
+        mutable: true,
+        get: |c| c.set_max_listpack_value.to_string(),
+        set: |c, v| {
+            c.set_max_listpack_value = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "zset-max-listpack-entries",
+        mutable: true,
+        get: |c| c.zset_max_listpack_entries.to_string(),
+        set: |c, v| {
+            c.zset_max_listpack_entries = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "zset-max-listpack-value",
+        mutable: true,
+        get: |c| c.zset_max_listpack_value.to_string(),
+        set: |c, v| {
+            c.zset_max_listpack_value = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "list-max-listpack-size",
+        mutable: true,
+        get: |c| c.list_max_listpack_size.to_string(),
+        set: |c, v| {
+            c.list_max_listpack_size = parse_number(v)?;
+            Ok(())
+        },
+    },
+];
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn parse_enum(value: &str, allowed: &[&str]) -> Result<String, String> {
+    let value = value.to_lowercase();
+    if allowed.contains(&value.as_str()) {
+        Ok(value)
+    } else {
+        Err("argument(s) must be one of the following: ".to_string() + &allowed.join(", "))
+    }
+}
+
+fn parse_filename(value: &str) -> Result<String, String> {
+    if value.contains(['/', '\\']) {
+        return Err("dbfilename can't be a path, just a filename".to_string());
+    }
+
+    Ok(value.to_string())
+}
+
+/// A byte count with an optional unit: `k`/`m`/`g` are powers of 1000 and `kb`/`mb`/`gb`
+/// powers of 1024, as in redis.conf.
+pub fn parse_memory(value: &str) -> Result<u64, String> {
+    let value = value.to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &value[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("argument must be a memory value".to_string()),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+/// `seconds changes [seconds changes ...]`, or an empty string for no automatic saves.
+fn parse_save(value: &str) -> Result<Vec<(u64, u64)>, String> {
+    let numbers = value
+        .split_whitespace()
+        .map(|n| {
+            n.parse::<u64>()
+                .map_err(|_| "Invalid save parameters".to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() % 2 != 0 {
+        return Err("Invalid save parameters".to_string());
+    }
+
+    Ok(numbers.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+}
+
+/// `class hard soft seconds [class hard soft seconds ...]`, changing the limits of just the
+/// classes given. Nothing changes if any of it is invalid.
+fn parse_output_limits(limits: &mut [output::Limit; 3], value: &str) -> Result<(), String> {
+    let invalid = || "Invalid client-output-buffer-limit parameters".to_string();
+    let words = value.split_whitespace().collect::<Vec<_>>();
+    if words.is_empty() || words.len() % 4 != 0 {
+        return Err(invalid());
+    }
+
+    let mut parsed = *limits;
+    for group in words.chunks(4) {
+        let class = Class::parse(group[0]).ok_or_else(invalid)?;
+        parsed[class as usize] = output::Limit {
+            hard: parse_memory(group[1]).map_err(|_| invalid())?,
+            soft: parse_memory(group[2]).map_err(|_| invalid())?,
+            soft_seconds: parse_number(group[3]).map_err(|_| invalid())?,
+        };
+    }
+
+    *limits = parsed;
+    Ok(())
+}
+
+/// Splits a config file line into its directive and arguments, a command typed into the CLI
+/// into its name and arguments, or a line of a Redis append-only manifest into its fields.
+/// Arguments are separated by whitespace, and may be quoted: `"..."` with backslash escapes, or
+/// `'...'` taken literally.
+pub fn split_line(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(words);
+        };
+
+        let mut word = String::new();
+        if first == '"' || first == '\'' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    None => return Err("Unbalanced quotes in configuration line".to_string()),
+                    Some(c) if c == first => break,
+                    Some('\\') if first == '"' => match chars.next() {
+                        Some('n') => word.push('\n'),
+                        Some('r') => word.push('\r'),
+                        Some('t') => word.push('\t'),
+                        Some(c) => word.push(c),
+                        None => return Err("Unbalanced quotes in configuration line".to_string()),
+                    },
+                    Some(c) => word.push(c),
+                }
+            }
+            // A closing quote has to end the word
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err("Unbalanced quotes in configuration line".to_string());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+}
+
+impl ServerConfig {
+    /// The port the cluster bus listens on.
+    pub fn cluster_bus_port(&self) -> u16 {
+        match self.cluster_port {
+            0 => self.port.saturating_add(10000),
+            port => port,
+        }
+    }
+
+    /// Reads a redis.conf-style file of `directive value` lines on top of the defaults.
+    /// Directives this server doesn't have are skipped with a warning, so a stock redis.conf
+    /// loads; a bad value for one it does have is an error naming the line.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Can't open config file '{}': {e}", path.display()))?;
+
+        let mut config = Self::default();
+        // Each `save` line adds a rule to those before it rather than replacing them
+        let mut save: Option<Vec<String>> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let fatal = |reason: &str| {
+                format!(
+                    "Reading the configuration file, at line {}\n>>> '{}'\n{reason}",
+                    number + 1,
+                    line.trim()
+                )
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words = split_line(line).map_err(|reason| fatal(&reason))?;
+            let name = words[0].to_lowercase();
+
+            if name == "rename-command" {
+                let [command, new_name] = &words[1..] else {
+                    return Err(fatal("wrong number of arguments"));
+                };
+                config
+                    .rename_command(command, new_name)
+                    .map_err(|reason| fatal(&reason))?;
+                continue;
+            }
+            if !PARAMETERS.iter().any(|p| p.name == name) {
+                // Read before the log is set up, from the settings this finds
+                eprintln!(
+                    "Ignoring unsupported directive '{name}' at line {}",
+                    number + 1
+                );
+                continue;
+            }
+            let value = match (name.as_str(), &words[1..]) {
+                (_, [value]) => value.clone(),
+                ("save" | "bind" | "client-output-buffer-limit", values) if !values.is_empty() => {
+                    values.join(" ")
+                }
+                _ => return Err(fatal("wrong number of arguments")),
+            };
+
+            if name == "save" {
+                // Check the rule here, so a bad one is reported against its own line
+                parse_save(&value).map_err(|reason| fatal(&reason))?;
+                save.get_or_insert_default().push(value);
+            } else {
+                config.set(&name, &value).map_err(|reason| fatal(&reason))?;
+            }
+        }
+
+        if let Some(save) = save {
+            config.set("save", &save.join(" "))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Changes one parameter as the server starts, when even immutable ones may be set.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let parameter = PARAMETERS
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| format!("Bad directive '{name}'"))?;
+
+        (parameter.set)(self, value)
+    }
+
+    /// Adds a `rename-command` rule, checking that it names a command and that no other rule
+    /// already gave a command the new name.
+    pub fn rename_command(&mut self, command: &str, new_name: &str) -> Result<(), String> {
+        let command = command.to_lowercase();
+        let new_name = new_name.to_lowercase();
+        if !cmd::is_command(&command) {
+            return Err("No such command in rename-command".to_string());
+        }
+        if !new_name.is_empty() && self.rename_commands.iter().any(|(_, to)| *to == new_name) {
+            return Err("Target command name already exists".to_string());
+        }
+
+        self.rename_commands.push((command, new_name));
+        Ok(())
+    }
+
+    /// Pushes the settings other modules keep their own copies of out to them.
+    fn apply(&self) {
+        let limits = [
+            (
+                &encoding::HASH_MAX_LISTPACK_ENTRIES,
+                self.hash_max_listpack_entries,
+            ),
+            (
+                &encoding::HASH_MAX_LISTPACK_VALUE,
+                self.hash_max_listpack_value,
+            ),
+            (
+                &encoding::SET_MAX_INTSET_ENTRIES,
+                self.set_max_intset_entries,
+            ),
+            (
+                &encoding::SET_MAX_LISTPACK_ENTRIES,
+                self.set_max_listpack_entries,
+            ),
+            (
+                &encoding::SET_MAX_LISTPACK_VALUE,
+                self.set_max_listpack_value,
+            ),
+            (
+                &encoding::ZSET_MAX_LISTPACK_ENTRIES,
+                self.zset_max_listpack_entries,
+            ),
+            (
+                &encoding::ZSET_MAX_LISTPACK_VALUE,
+                self.zset_max_listpack_value,
+            ),
+        ];
+        for (setting, value) in limits {
+            setting.store(value, Ordering::Relaxed);
+        }
+        encoding::LIST_MAX_LISTPACK_SIZE.store(self.list_max_listpack_size, Ordering::Relaxed);
+
+        notify::set_flags(notify::parse_flags(&self.notify_keyspace_events).unwrap_or(0));
+        slowlog::LOG_SLOWER_THAN.store(self.slowlog_log_slower_than, Ordering::Relaxed);
+        slowlog::set_max_len(self.slowlog_max_len);
+        latency::THRESHOLD.store(self.latency_monitor_threshold, Ordering::Relaxed);
+        audit::set_log(&self.audit_log);
+        audit::MAX_SIZE.store(self.audit_log_max_size, Ordering::Relaxed);
+        audit::set_stream(&self.audit_stream);
+        audit::STREAM_MAXLEN.store(self.audit_stream_maxlen, Ordering::Relaxed);
+        trace::set_file(&self.record_file);
+        shadow::set_target(&self.shadow_target);
+        resp::MAX_BULK_LEN.store(self.proto_max_bulk_len, Ordering::Relaxed);
+        db::LFU_LOG_FACTOR.store(self.lfu_log_factor, Ordering::Relaxed);
+        db::LFU_DECAY_TIME.store(self.lfu_decay_time, Ordering::Relaxed);
+        logging::set_level(&self.loglevel);
+        output::set_limits(self.client_output_buffer_limits);
+        ratelimit::CLIENT_RATE.store(self.client_rate_limit, Ordering::Relaxed);
+        ratelimit::USER_RATE.store(self.user_rate_limit, Ordering::Relaxed);
+        ratelimit::BURST.store(self.rate_limit_burst, Ordering::Relaxed);
+        ratelimit::DELAY.store(self.rate_limit_action == "delay", Ordering::Relaxed);
+        aof::set_fsync(&self.appendfsync);
+        replication::set_backlog_size(self.repl_backlog_size as usize);
+        replication::READ_ONLY.store(self.replica_read_only, Ordering::Relaxed);
+        cluster::ENABLED.store(self.cluster_enabled, Ordering::Relaxed);
+        cluster::NODE_TIMEOUT.store(self.cluster_node_timeout, Ordering::Relaxed);
+        replication::MIN_REPLICAS.store(self.min_replicas_to_write, Ordering::Relaxed);
+        replication::MIN_REPLICAS_MAX_LAG.store(self.min_replicas_max_lag, Ordering::Relaxed);
+        if !self.appendonly {
+            aof::stop();
+        }
+    }
+}
+
+/// Quotes a value for a config file line if it would otherwise not read back as one word.
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The config file lines that set `parameter` to its value in `config`.
+fn directive_lines(parameter: &Parameter, config: &ServerConfig) -> Vec<String> {
+    match parameter.name {
+        "save" if config.save.is_empty() => vec!["save \"\"".to_string()],
+        "save" => config
+            .save
+            .iter()
+            .map(|(seconds, changes)| format!("save {seconds} {changes}"))
+            .collect(),
+        "bind" => vec![format!("bind {}", config.bind.join(" "))],
+        name => vec![format!("{name} {}", quote(&(parameter.get)(config)))],
+    }
+}
+
+static CONFIG: LazyLock<RwLock<ServerConfig>> =
+    LazyLock::new(|| RwLock::new(ServerConfig::default()));
+
+/// The config file the server was started with, and the settings it held when last read, for
+/// CONFIG REWRITE and reloading.
+static CONFIG_FILE: Mutex<Option<(PathBuf, ServerConfig)>> = Mutex::new(None);
+
+/// Installs the configuration the server starts with, and remembers the file it came from
+/// along with what was read from it.
+pub fn init(config: ServerConfig, file: Option<(PathBuf, ServerConfig)>) {
+    *CONFIG_FILE.lock().unwrap() = file;
+    config.apply();
+    cmd::set_renames(&config.rename_commands);
+    acl::set_requirepass(
+        (!config.requirepass.is_empty()).then(|| config.requirepass.clone().into_bytes()),
+    );
+
+    *CONFIG.write().unwrap() = config;
+}
+
+/// The configuration as it stands.
+pub fn get() -> RwLockReadGuard<'static, ServerConfig> {
+    CONFIG.read().unwrap()
+}
+
+/// CONFIG GET: every parameter whose name matches one of `patterns`, with its value.
+pub fn matching(patterns: &[Vec<u8>]) -> Vec<(&'static str, String)> {
+    let config = get();
+
+    PARAMETERS
+        .iter()
+        .filter(|p| {
+            patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, p.name.as_bytes(), true))
+        })
+        .map(|p| (p.name, (p.get)(&config)))
+        .collect()
+}
+
+/// CONFIG SET: changes each `(name, value)`, all or nothing, and puts the changes into effect.
+pub fn set(changes: &[(String, String)]) -> Result<(), String> {
+    let mut config = CONFIG.write().unwrap();
+    let mut updated = config.clone();
+
+    for (i, (name, value)) in changes.iter().enumerate() {
+        let failed = |reason: &str| {
+            format!("ERR CONFIG SET failed (possibly related to argument '{name}') - {reason}")
+        };
+
+        let Some(parameter) = PARAMETERS.iter().find(|p| p.name == name) else {
+            return Err(format!(
+                "ERR Unknown option or number of arguments for CONFIG SET - '{name}'"
+            ));
+        };
+        if !parameter.mutable {
+            return Err(failed("can't set immutable config"));
+        }
+        if changes[..i].iter().any(|(earlier, _)| earlier == name) {
+            return Err(failed("duplicate parameter"));
+        }
+        (parameter.set)(&mut updated, value).map_err(|reason| failed(&reason))?;
+    }
+
+    updated.apply();
+    if updated.requirepass != config.requirepass {
+        acl::set_requirepass(
+            (!updated.requirepass.is_empty()).then(|| updated.requirepass.clone().into_bytes()),
+        );
+    }
+    *config = updated;
+
+    Ok(())
+}
+
+/// CONFIG REWRITE: brings the config file up to date with the running configuration. Each
+/// setting's first line is rewritten in place and any repeats dropped, settings the file
+/// didn't mention are appended if they differ from the defaults, and everything else in the
+/// file, comments included, is kept as it was.
+pub fn rewrite() -> Result<(), String> {
+    let mut file = CONFIG_FILE.lock().unwrap();
+    let (path, loaded) = file
+        .as_mut()
+        .ok_or("ERR The server is running without a config file")?;
+    let text = fs::read_to_string(&*path).unwrap_or_default();
+    let config = get();
+
+    let mut written = BTreeSet::new();
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let parameter = split_line(line.trim())
+            .ok()
+            .and_then(|words| words.into_iter().next())
+            .filter(|_| !line.trim_start().starts_with('#'))
+            .and_then(|name| {
+                let name = name.to_lowercase();
+                PARAMETERS.iter().find(|p| p.name == name)
+            });
+
+        match parameter {
+            Some(parameter) if written.insert(parameter.name) => {
+                lines.extend(directive_lines(parameter, &config));
+            }
+            Some(_) => {}
+            None => lines.push(line.to_string()),
+        }
+    }
+
+    let defaults = ServerConfig::default();
+    let mut generated = PARAMETERS
+        .iter()
+        .filter(|p| !written.contains(p.name) && (p.get)(&config) != (p.get)(&defaults))
+        .flat_map(|p| directive_lines(p, &config))
+        .peekable();
+    if generated.peek().is_some() {
+        lines.push("# Generated by CONFIG REWRITE".to_string());
+        lines.extend(generated);
+    }
+
+    // Write a new file and move it into place, so a failure can't leave half a config behind
+    let temporary = path.with_extension("rewrite.tmp");
+    fs::write(&temporary, lines.join("\n") + "\n")
+        .and_then(|()| fs::rename(&temporary, &*path))
+        .map_err(|e| format!("ERR Rewriting config file: {e}"))?;
+
+    // The file now says what's running, so a reload shouldn't see any of it as changed
+    *loaded = config.clone();
+
+    Ok(())
+}
+
+/// Re-reads the config file and applies the settings changed in it since it was last read, as
+/// if by CONFIG SET, leaving those it didn't change as they are. Settings that can only be set
+/// at startup keep their values, with a warning.
+pub fn reload() -> Result<(), String> {
+    let mut file = CONFIG_FILE.lock().unwrap();
+    let (path, loaded) = file
+        .as_mut()
+        .ok_or("the server is running without a config file")?;
+    let fresh = ServerConfig::load(path)?;
+
+    let changes: Vec<(String, String)> = PARAMETERS
+        .iter()
+        .filter(|p| (p.get)(&fresh) != (p.get)(loaded))
+        .filter(|p| {
+            if !p.mutable {
+                warn!("Not reloading '{}': it can only be set at startup", p.name);
+            }
+            p.mutable
+        })
+        .map(|p| (p.name.to_string(), (p.get)(&fresh)))
+        .collect();
+
+    if fresh.rename_commands != loaded.rename_commands {
+        warn!("Not reloading 'rename-command': it can only be set at startup");
+    }
+
+    set(&changes)?;
+    *loaded = fresh;
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+/// CRC-16/XMODEM, the variant Redis Cluster hashes keys to slots with.
+const POLY: u16 = 0x1021;
+
+const TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ POLY
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &b| {
+        TABLE[((crc >> 8) as u8 ^ b) as usize] ^ (crc << 8)
+    })
+}
@@ -0,0 +1,207 @@
+//! Encryption at rest for snapshots and the append-only file, with AES-256-GCM, for
+//! deployments that mustn't leave the dataset readable on disk.
+//!
+//! Keys are written `<id>:<64 hex digits>`. The one in `encryption-key`, or the
+//! `REDIS_ENCRYPTION_KEY` environment variable when that's empty, encrypts what's written, or
+//! failing both the first line of `encryption-keyfile`; the rest of the keyfile's lines are kept
+//! to read files written with them. Rotating keys is putting the new one first and keeping the
+//! old one after it until the next save and rewrite have gone over to the new one.
+//!
+//! An encrypted file starts with a header naming the key it was written with, then holds one
+//! or more records, each sealed on its own: a snapshot is one, and the append-only file gets
+//! one for its base and one for each write after it, so a crash mid-write only loses the last.
+
+use crate::config::ServerConfig;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::borrow::Cow;
+use std::fs;
+use std::sync::RwLock;
+
+/// What an encrypted file starts with, before its format version and key ID.
+const MAGIC: &[u8] = b"REDISENC";
+const VERSION: u8 = 1;
+
+/// The environment variable the key is taken from when `encryption-key` isn't set.
+const KEY_VAR: &str = "REDIS_ENCRYPTION_KEY";
+
+/// The most a header can take up, with the longest key ID.
+pub const HEADER_MAX: usize = MAGIC.len() + 2 + u8::MAX as usize;
+
+/// Bytes of a record before its ciphertext: its length, then its nonce.
+const RECORD_HEADER: usize = 4 + NONCE_LEN;
+
+struct Key {
+    id: String,
+    key: LessSafeKey,
+}
+
+/// The keys files can be read with, the first of which new ones are written with. Empty when
+/// encryption is off.
+static KEYS: RwLock<Vec<Key>> = RwLock::new(Vec::new());
+
+/// Takes up the keys `config` names, failing if any of them is malformed or the keyfile can't
+/// be read. Every server in the process shares them.
+pub fn install(config: &ServerConfig) -> anyhow::Result<()> {
+    let mut keys = Vec::new();
+    let key = match config.encryption_key.as_str() {
+        "" => std::env::var(KEY_VAR).unwrap_or_default(),
+        key => key.to_string(),
+    };
+    if !key.is_empty() {
+        keys.push(parse_key(&key).map_err(|e| anyhow::anyhow!("Invalid encryption key: {e}"))?);
+    }
+    if !config.encryption_keyfile.is_empty() {
+        let path = &config.encryption_keyfile;
+        let file =
+            fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to load {path}: {e}"))?;
+        let lines = file
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        for (number, line) in lines.enumerate() {
+            let key = parse_key(line)
+                .map_err(|e| anyhow::anyhow!("Invalid key {} in {path}: {e}", number + 1))?;
+            keys.push(key);
+        }
+    }
+
+    *KEYS.write().unwrap() = keys;
+    Ok(())
+}
+
+fn parse_key(key: &str) -> Result<Key, String> {
+    let Some((id, hex)) = key.split_once(':') else {
+        return Err("expected <id>:<hex key>".to_string());
+    };
+    if id.is_empty() || id.len() > u8::MAX as usize || id.contains(char::is_whitespace) {
+        return Err(format!("'{id}' can't be a key ID"));
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|at| {
+            hex.get(at..at + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .filter(|bytes| bytes.len() == AES_256_GCM.key_len())
+        .ok_or_else(|| format!("key '{id}' isn't {} hex digits", AES_256_GCM.key_len() * 2))?;
+    let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| "unusable key".to_string())?;
+
+    Ok(Key {
+        id: id.to_string(),
+        key: LessSafeKey::new(key),
+    })
+}
+
+/// Whether `bytes` are an encrypted file, or its start.
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// The header a new file starts with, naming the key it's written with, or `None` if files are
+/// written in the clear.
+pub fn header() -> Option<Vec<u8>> {
+    let keys = KEYS.read().unwrap();
+    let key = keys.first()?;
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    header.push(key.id.len() as u8);
+    header.extend_from_slice(key.id.as_bytes());
+
+    Some(header)
+}
+
+/// Whether a file starting with `bytes` is written the way new writes to it would be: in the
+/// clear with encryption off, or with the key now in use. Anything written to the end of one
+/// that isn't couldn't be read back.
+pub fn written_as_configured(bytes: &[u8]) -> bool {
+    match header() {
+        Some(header) => bytes.starts_with(&header),
+        None => !is_encrypted(bytes),
+    }
+}
+
+/// `plaintext` sealed as a record of a file started with `header`, with the key that names.
+pub fn seal(header: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let keys = KEYS.read().unwrap();
+    let key = &keys.first().expect("encryption is on").key;
+
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .expect("the system's random source failed");
+    let mut sealed = plaintext.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(header),
+        &mut sealed,
+    )
+    .expect("records are well under the size AES-GCM can seal");
+
+    let mut record = Vec::with_capacity(RECORD_HEADER + sealed.len());
+    record.extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+    record.extend_from_slice(&nonce);
+    record.extend_from_slice(&sealed);
+    record
+}
+
+/// `plaintext` as a whole encrypted file, as a snapshot is written, or unchanged with encryption
+/// off.
+pub fn encrypt(plaintext: &[u8]) -> Cow<'_, [u8]> {
+    let Some(mut file) = header() else {
+        return Cow::Borrowed(plaintext);
+    };
+    let record = seal(&file, plaintext);
+    file.extend_from_slice(&record);
+    Cow::Owned(file)
+}
+
+/// An encrypted file, opened.
+pub struct Opened {
+    pub plaintext: Vec<u8>,
+    /// How many of the file's bytes were whole records. Any after them are a record cut short
+    /// at the end, as by a crash mid-write.
+    pub len: usize,
+}
+
+/// Decrypts the file `bytes`, with whichever key its header names. Fails if that's none of the
+/// keys there are, or a record was tampered with or corrupted.
+pub fn decrypt(bytes: &[u8]) -> anyhow::Result<Opened> {
+    let truncated = || anyhow::anyhow!("encrypted file header cut short");
+    let version = *bytes.get(MAGIC.len()).ok_or_else(truncated)?;
+    if version != VERSION {
+        anyhow::bail!("can't handle encrypted file format version {version}");
+    }
+    let id_len = *bytes.get(MAGIC.len() + 1).ok_or_else(truncated)? as usize;
+    let header_len = MAGIC.len() + 2 + id_len;
+    let header = bytes.get(..header_len).ok_or_else(truncated)?;
+    let id = String::from_utf8_lossy(&header[MAGIC.len() + 2..]);
+
+    let keys = KEYS.read().unwrap();
+    let Some(key) = keys.iter().find(|key| key.id == id) else {
+        anyhow::bail!("written with encryption key '{id}', which isn't configured");
+    };
+
+    let mut plaintext = Vec::new();
+    let mut pos = header_len;
+    while let Some(record) = bytes.get(pos..pos + RECORD_HEADER) {
+        let len = u32::from_le_bytes(record[..4].try_into().unwrap()) as usize;
+        let Some(sealed) = bytes.get(pos + RECORD_HEADER..pos + RECORD_HEADER + len) else {
+            break;
+        };
+        let nonce = Nonce::try_assume_unique_for_key(&record[4..]).unwrap();
+        let mut sealed = sealed.to_vec();
+        let opened = key
+            .key
+            .open_in_place(nonce, Aad::from(header), &mut sealed)
+            .map_err(|_| anyhow::anyhow!("record at offset {pos} failed authentication"))?;
+        plaintext.extend_from_slice(opened);
+        pos += RECORD_HEADER + len;
+    }
+
+    Ok(Opened {
+        plaintext,
+        len: pos,
+    })
+}
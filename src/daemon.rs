@@ -0,0 +1,56 @@
+use crate::config::ServerConfig;
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+/// Where the pid file goes when `daemonize` is on and `pidfile` isn't set, as in Redis.
+const DEFAULT_PIDFILE: &str = "/var/run/redis.pid";
+
+/// The pid file written at startup, removed again on shutdown.
+static PIDFILE: OnceLock<String> = OnceLock::new();
+
+/// Carries on in a child process detached from the terminal, the parent exiting. Standard
+/// input and output go to /dev/null, so without a `logfile` nothing is logged. Must be called
+/// before any threads are started, the runtime's included.
+pub fn daemonize() -> anyhow::Result<()> {
+    // SAFETY: the process has a single thread, so the child gets everything it needs
+    match unsafe { libc::fork() } {
+        -1 => anyhow::bail!("Can't fork: {}", std::io::Error::last_os_error()),
+        0 => {}
+        _ => std::process::exit(0),
+    }
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    // SAFETY: plain system calls on descriptors this process owns
+    unsafe {
+        libc::setsid();
+        for fd in 0..=2 {
+            libc::dup2(null.as_raw_fd(), fd);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the process's ID to `pidfile`, or to the default one if the server's daemonized.
+/// Failing to is only worth a warning, as in Redis.
+pub fn write_pidfile(config: &ServerConfig) {
+    let path = match config.pidfile.as_str() {
+        "" if config.daemonize => DEFAULT_PIDFILE,
+        "" => return,
+        path => path,
+    };
+    if let Err(e) = fs::write(path, format!("{}\n", std::process::id())) {
+        warn!("Failed to write PID file {path}: {e}");
+        return;
+    }
+    let _ = PIDFILE.set(path.to_string());
+}
+
+/// Removes the pid file written at startup, if there is one.
+pub fn remove_pidfile() {
+    if let Some(path) = PIDFILE.get() {
+        info!("Removing the pid file.");
+        let _ = fs::remove_file(path);
+    }
+}
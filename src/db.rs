@@ -1,27 +1,759 @@
-use std::collections::HashMap;
+use crate::cluster;
+use crate::cmd::memory;
+use crate::encoding;
+use crate::hash::Hash;
+use crate::latency;
+use crate::lazyfree;
+use crate::namespace;
+use crate::notify::{self, Class};
+use crate::propagate;
+use crate::rand;
+use crate::replication;
+use crate::set::Set;
+use crate::stats;
+use crate::stream::Stream;
+use crate::tracking;
+use crate::zset::ZSet;
+use bytes::Bytes;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
 
-pub type Db = Arc<RwLock<HashMap<String, DBData>>>;
+/// How many shards the keys are split between, by the hash slot they'd have in a cluster, each
+/// behind a lock of its own. Commands whose keys are in different shards run at once, as do
+/// commands that only read the same ones.
+pub const SHARDS: usize = 16;
 
+/// One database's part of a shard. Both maps are persistent ones, so a copy is made in constant
+/// time and shares everything with the original until one of them is written: see
+/// [`Keyspace::freeze`].
+#[derive(Clone, Default)]
+struct Shard {
+    /// Keys are reference counted, so the replies listing them share them rather than copy
+    /// them.
+    keys: imbl::HashMap<Bytes, DBData>,
+    /// The keys with a TTL, or hash fields with one, by the soonest of them, so the expire
+    /// cycle finds what's due without looking at anything else.
+    deadlines: imbl::OrdSet<(u64, Bytes)>,
+}
+
+impl Shard {
+    /// Files `key` under its deadline as it is now, if it moved since it was last filed.
+    fn reindex(&mut self, key: &[u8]) {
+        let Some((key, val)) = self.keys.get_key_value_mut(key) else {
+            return;
+        };
+        let deadline = val.deadline();
+        if deadline == val.indexed {
+            return;
+        }
+
+        if let Some(at) = std::mem::replace(&mut val.indexed, deadline) {
+            self.deadlines.remove(&(at, key.clone()));
+        }
+        if let Some(at) = deadline {
+            self.deadlines.insert((at, key.clone()));
+        }
+    }
+
+    /// Takes `key`'s deadline, if it has one, out of the index, as it goes.
+    fn unindex(&mut self, key: &[u8], val: &mut DBData) {
+        if let Some(at) = val.indexed.take() {
+            self.deadlines.remove(&(at, Bytes::copy_from_slice(key)));
+        }
+    }
+}
+
+/// How often the expire cycle runs: ten times a second, like Redis at its default `hz`.
+const EXPIRE_CYCLE_PERIOD: Duration = Duration::from_millis(100);
+
+/// The longest one run of the expire cycle may take, so it never takes more than a quarter of
+/// the server's time.
+const EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(25);
+
+/// How many due keys the expire cycle drops at a time, between checks of the time it has left.
+const EXPIRE_BATCH: usize = 20;
+
+fn shard_of(key: &[u8]) -> usize {
+    cluster::key_slot(key) as usize % SHARDS
+}
+
+/// The numbered databases SELECT chooses between. Each shard is locked for every database at
+/// once, so a command can move keys between databases with just the locks of their shards.
+pub struct Storage {
+    /// The shards, each with its part of every database.
+    shards: Vec<Arc<RwLock<Vec<Shard>>>>,
+    count: usize,
+}
+
+pub type Db = Arc<Storage>;
+
+pub fn new_databases(count: usize) -> Db {
+    let shards = (0..SHARDS)
+        .map(|_| Arc::new(RwLock::new((0..count).map(|_| Shard::default()).collect())))
+        .collect();
+
+    Arc::new(Storage { shards, count })
+}
+
+impl Storage {
+    /// Locks every shard, for a command that may touch any key.
+    pub async fn lock_all(&self) -> Locked {
+        self.lock_shards((0..SHARDS).collect(), false).await
+    }
+
+    /// Locks the shards `keys` are in.
+    pub async fn lock_keys(&self, keys: &[&[u8]]) -> Locked {
+        self.lock_shards(keys.iter().map(|key| shard_of(key)).collect(), false)
+            .await
+    }
+
+    /// Locks what command `name` needs to run with `args`: the shards of its keys if it keeps
+    /// to them, or every shard if it has none or may go beyond them. A command that only reads
+    /// shares them with any others that only read.
+    pub async fn lock_for(&self, name: &str, args: &[Vec<u8>]) -> Locked {
+        let keys = crate::cmd::command_keys(name, args);
+        let shared = crate::cmd::reads_only(name);
+        if keys.is_empty() || !crate::cmd::keeps_to_keys(name) {
+            self.lock_shards((0..SHARDS).collect(), shared).await
+        } else {
+            self.lock_shards(keys.iter().map(|key| shard_of(key)).collect(), shared)
+                .await
+        }
+    }
+
+    /// Every database as it stands, frozen, for a scan of the whole keyspace that mustn't hold
+    /// up clients: the locks are only held while it's copied, which takes constant time.
+    pub async fn freeze(&self) -> Vec<Keyspace> {
+        self.lock_all().await.iter().map(Keyspace::freeze).collect()
+    }
+
+    /// Drops the keys commands that only read found had expired, which they couldn't drop
+    /// themselves with the locks they had. Call it once the command's locks are let go of.
+    pub async fn drop_expired_reads(&self) {
+        let expired = EXPIRED_READS.take();
+        if expired.is_empty() {
+            return;
+        }
+
+        let keys: Vec<&[u8]> = expired.iter().map(|(_, key)| key.as_ref()).collect();
+        let mut dbs = self.lock_keys(&keys).await;
+        for (index, key) in &expired {
+            select(*index);
+            // Unless something wrote it in between
+            dbs[*index].get_live(key);
+        }
+        bump_versions(&mut dbs);
+    }
+
+    /// Takes the locks of `shards` in order, which is what keeps two commands that both want
+    /// some of the same ones from each holding what the other is waiting for. `shared` takes
+    /// read locks, for a command that only reads.
+    async fn lock_shards(&self, mut shards: Vec<usize>, shared: bool) -> Locked {
+        shards.sort_unstable();
+        shards.dedup();
+
+        let mut dbs: Vec<Keyspace> = (0..self.count)
+            .map(|index| Keyspace {
+                shards: (0..SHARDS).map(|_| None).collect(),
+                index,
+            })
+            .collect();
+        let mut guards = Vec::with_capacity(shards.len());
+        for index in shards {
+            if shared {
+                let guard = Arc::new(self.shards[index].clone().read_owned().await);
+                for keyspace in &mut dbs {
+                    keyspace.shards[index] = Some(Held::Read(guard.clone()));
+                }
+                continue;
+            }
+
+            let mut guard = self.shards[index].clone().write_owned().await;
+            for (keyspace, shard) in dbs.iter_mut().zip(guard.iter_mut()) {
+                keyspace.shards[index] = Some(Held::Written(std::mem::take(shard)));
+            }
+            guards.push((index, guard));
+        }
+
+        Locked { guards, dbs }
+    }
+}
+
+/// The databases, with the shards a command locked taken out for it to work on. They go back
+/// when it's dropped.
+pub struct Locked {
+    /// The write locks. Read locks are held by the shards they're for.
+    guards: Vec<(usize, OwnedRwLockWriteGuard<Vec<Shard>>)>,
+    dbs: Vec<Keyspace>,
+}
+
+impl Deref for Locked {
+    type Target = [Keyspace];
+
+    fn deref(&self) -> &[Keyspace] {
+        &self.dbs
+    }
+}
+
+impl DerefMut for Locked {
+    fn deref_mut(&mut self) -> &mut [Keyspace] {
+        &mut self.dbs
+    }
+}
+
+impl Drop for Locked {
+    fn drop(&mut self) {
+        for (index, guard) in &mut self.guards {
+            for (keyspace, shard) in self.dbs.iter_mut().zip(guard.iter_mut()) {
+                *shard = match keyspace.shards[*index].take() {
+                    Some(Held::Written(taken)) => taken,
+                    _ => Shard::default(),
+                };
+            }
+        }
+    }
+}
+
+/// A shard as a command locked it.
+enum Held {
+    /// Taken out from behind its write lock, to be put back once the command's done.
+    Written(Shard),
+    /// Every database's part of it, behind a read lock shared with the other databases.
+    Read(Arc<OwnedRwLockReadGuard<Vec<Shard>>>),
+}
+
+/// One database's keys, or those in the shards the running command locked. Touching a key in
+/// a shard it didn't lock, or writing one in a shard it only locked to read, is a bug, and
+/// panics.
+#[derive(Default)]
+pub struct Keyspace {
+    shards: Vec<Option<Held>>,
+    /// Which database it is.
+    index: usize,
+}
+
+impl Keyspace {
+    fn shard(&self, key: &[u8]) -> &Shard {
+        self.held(
+            self.shards[shard_of(key)]
+                .as_ref()
+                .expect("the key's shard is locked"),
+        )
+    }
+
+    fn shard_mut(&mut self, key: &[u8]) -> &mut Shard {
+        match self.shards[shard_of(key)].as_mut() {
+            Some(Held::Written(shard)) => shard,
+            Some(Held::Read(_)) => panic!("the key's shard is only locked for reading"),
+            None => panic!("the key's shard is locked"),
+        }
+    }
+
+    fn held<'a>(&self, held: &'a Held) -> &'a Shard {
+        match held {
+            Held::Written(shard) => shard,
+            Held::Read(guard) => &guard[self.index],
+        }
+    }
+
+    fn locked(&self) -> impl Iterator<Item = &Shard> {
+        self.shards.iter().flatten().map(|held| self.held(held))
+    }
+
+    fn locked_mut(&mut self) -> impl Iterator<Item = &mut Shard> {
+        self.shards.iter_mut().flatten().map(|held| match held {
+            Held::Written(shard) => shard,
+            Held::Read(_) => panic!("the shard is only locked for reading"),
+        })
+    }
+
+    /// Whether `key`'s shard is locked, so it can be touched.
+    #[cfg(feature = "wasm")]
+    pub fn holds(&self, key: &[u8]) -> bool {
+        self.shards.get(shard_of(key)).is_some_and(Option::is_some)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&DBData> {
+        self.shard(key).keys.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut DBData> {
+        self.shard_mut(key).keys.get_mut(key)
+    }
+
+    /// Looks up `key` the way every command reads it: one whose TTL has passed is dropped and
+    /// its expiry passed on first, so it's missing. A replica leaves that to its master's DEL,
+    /// and until it comes only the master's stream still sees the key.
+    pub fn get_live(&mut self, key: &[u8]) -> Option<&mut DBData> {
+        if self.get(key).is_some_and(DBData::is_expired) {
+            if !replication::is_replica() {
+                self.remove(key);
+                report_expired(key);
+            } else if !replication::applying() {
+                return None;
+            }
+        }
+
+        self.get_mut(key)
+    }
+
+    /// Like [`Keyspace::get_live`], for a look that can't change anything: a key whose TTL has
+    /// passed is missing, but left for the next write or the expire cycle to drop.
+    pub fn get_unexpired(&self, key: &[u8]) -> Option<&DBData> {
+        self.get(key).filter(|val| !val.is_expired())
+    }
+
+    /// Like [`Keyspace::get_live`], for a command that only reads, and may only hold a read
+    /// lock: a key whose TTL has passed is missing, and dropped once the lock is let go of.
+    pub fn get_shared(&self, key: &[u8]) -> Option<&DBData> {
+        let val = self.get(key)?;
+        if !val.is_expired() {
+            return Some(val);
+        }
+
+        if !replication::is_replica() {
+            EXPIRED_READS.with_borrow_mut(|expired| {
+                expired.push((self.index, Bytes::copy_from_slice(key)));
+            });
+        } else if replication::applying() {
+            return Some(val);
+        }
+        None
+    }
+
+    /// Sets `key` to `val`. Whatever it replaces is freed in the background if it's big.
+    pub fn insert(&mut self, key: impl Into<Bytes>, mut val: DBData) {
+        let key = key.into();
+        val.bytes = memory::key_bytes(&key, &val, memory::DEFAULT_SAMPLES);
+        USED_MEMORY.fetch_add(val.bytes as u64, Ordering::Relaxed);
+        val.indexed = None;
+        let shard = self.shard_mut(&key);
+        if let Some(mut old) = shard.keys.insert(key.clone(), val) {
+            shard.unindex(&key, &mut old);
+            USED_MEMORY.fetch_sub(old.bytes as u64, Ordering::Relaxed);
+            lazyfree::free(old);
+        }
+        shard.reindex(&key);
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<DBData> {
+        let shard = self.shard_mut(key);
+        let mut old = shard.keys.remove(key)?;
+        shard.unindex(key, &mut old);
+        USED_MEMORY.fetch_sub(old.bytes as u64, Ordering::Relaxed);
+
+        Some(old)
+    }
+
+    /// Gives `key` a TTL ending at `expires_at`, in Unix milliseconds, or drops its TTL for
+    /// `None`, returning whether it exists.
+    pub fn set_expires_at(&mut self, key: &[u8], expires_at: Option<u64>) -> bool {
+        let shard = self.shard_mut(key);
+        let Some(val) = shard.keys.get_mut(key) else {
+            return false;
+        };
+        val.expires_at = expires_at;
+        shard.reindex(key);
+
+        true
+    }
+
+    /// The soonest deadline of any key or hash field in the locked shards, in Unix
+    /// milliseconds.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.locked()
+            .filter_map(|shard| shard.deadlines.get_min())
+            .map(|(at, _)| *at)
+            .min()
+    }
+
+    /// Up to `count` keys in the locked shards whose deadline, or one of whose hash fields',
+    /// is at or before `now`, soonest first.
+    fn due(&self, now: u64, count: usize) -> Vec<Bytes> {
+        let mut due: Vec<&(u64, Bytes)> = self
+            .locked()
+            .flat_map(|shard| {
+                shard
+                    .deadlines
+                    .iter()
+                    .take_while(|(at, _)| *at <= now)
+                    .take(count)
+            })
+            .collect();
+        due.sort_unstable();
+        due.into_iter()
+            .take(count)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    /// Sizes `key` up again after a write changed its value, and files it under its new
+    /// deadline if that moved, as a write to its hash fields' TTLs does.
+    fn remeasure(&mut self, key: &[u8]) {
+        let shard = self.shard_mut(key);
+        shard.reindex(key);
+        let Some(val) = shard.keys.get_mut(key) else {
+            return;
+        };
+        let bytes = memory::key_bytes(key, val, memory::DEFAULT_SAMPLES);
+        USED_MEMORY.fetch_add(bytes as u64, Ordering::Relaxed);
+        USED_MEMORY.fetch_sub(
+            std::mem::replace(&mut val.bytes, bytes) as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// How many keys there are in the locked shards, which is all of them for a command that
+    /// locked every shard.
+    pub fn len(&self) -> usize {
+        self.locked().map(|shard| shard.keys.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locked().all(|shard| shard.keys.is_empty())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &DBData)> {
+        self.locked().flat_map(|shard| shard.keys.iter())
+    }
+
+    /// The keys the running command may see, named as its client knows them: all of them, or
+    /// for one confined to a namespace only those in it, without the namespace's prefix.
+    pub fn visible(&self) -> impl Iterator<Item = (&[u8], &DBData)> {
+        let prefix = namespace::current().unwrap_or_default();
+        self.iter()
+            .filter_map(move |(key, val)| Some((key.strip_prefix(prefix.as_slice())?, val)))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &DBData> {
+        self.locked().flat_map(|shard| shard.keys.values())
+    }
+
+    pub fn clear(&mut self) {
+        for shard in self.locked_mut() {
+            let bytes: usize = shard.keys.values().map(|val| val.bytes).sum();
+            USED_MEMORY.fetch_sub(bytes as u64, Ordering::Relaxed);
+            *shard = Shard::default();
+        }
+    }
+
+    /// Empties the locked shards like [`Keyspace::clear`], leaving the keys to be freed in the
+    /// background.
+    pub fn clear_lazily(&mut self) {
+        for shard in self.locked_mut().filter(|shard| !shard.keys.is_empty()) {
+            let shard = std::mem::take(shard);
+            lazyfree::spawn(shard.keys.len() as u64, move || {
+                let bytes: usize = shard.keys.values().map(|val| val.bytes).sum();
+                USED_MEMORY.fetch_sub(bytes as u64, Ordering::Relaxed);
+            });
+        }
+    }
+
+    /// A copy of the locked shards as they stand, made in constant time: it shares every key
+    /// and value with them, and writes made to them afterwards don't show up in it. It holds
+    /// no locks, so it can be read for as long as it takes while clients go on.
+    pub fn freeze(&self) -> Keyspace {
+        let shards = self
+            .shards
+            .iter()
+            .map(|held| {
+                held.as_ref()
+                    .map(|held| Held::Written(self.held(held).clone()))
+            })
+            .collect();
+
+        Keyspace {
+            shards,
+            index: self.index,
+        }
+    }
+
+    /// The `n`th key in the locked shards, in no particular order, for picking one at random.
+    pub fn nth(&self, mut n: usize) -> Option<(&Bytes, &DBData)> {
+        for shard in self.locked() {
+            if n < shard.keys.len() {
+                return shard.keys.iter().nth(n);
+            }
+            n -= shard.keys.len();
+        }
+
+        None
+    }
+}
+
+/// Source of key versions. Every value gets a fresh one, so a key that's deleted and recreated
+/// never ends up back on a version someone saw before.
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Index of the database the running command works on, which notifications and WATCH
+    /// report writes against. Commands run without yielding once their locks are taken, so
+    /// whoever runs one sets this on the thread it runs on first.
+    static SELECTED: Cell<usize> = const { Cell::new(0) };
+
+    /// Keys written since versions were last brought up to date, with their database. Writes
+    /// are reported from deep inside command handlers, which are still holding a borrow into
+    /// the keyspace, so the bumps are applied afterwards by [`bump_versions`].
+    static MODIFIED: RefCell<Vec<(usize, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+
+    /// Count of changes made on this thread, which tells whether the command it ran changed
+    /// anything.
+    static DIRTIED: Cell<u64> = const { Cell::new(0) };
+
+    /// Keys found expired by commands that only read, with their database, for
+    /// [`Storage::drop_expired_reads`] or [`drop_expired`] to drop.
+    static EXPIRED_READS: RefCell<Vec<(usize, Bytes)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Whether expired keys are swept out in the background as well as on access. DEBUG
+/// SET-ACTIVE-EXPIRE turns it off so tests can see expired keys still sitting in the keyspace.
+pub static ACTIVE_EXPIRE: AtomicBool = AtomicBool::new(true);
+
+/// How slowly access frequency counters climb, mirroring `lfu-log-factor`.
+pub static LFU_LOG_FACTOR: AtomicU64 = AtomicU64::new(10);
+
+/// Minutes for an idle key's access frequency counter to drop by one, or 0 for never, mirroring
+/// `lfu-decay-time`.
+pub static LFU_DECAY_TIME: AtomicU64 = AtomicU64::new(1);
+
+/// The access frequency counter a new key starts on, so it isn't evicted before it's had the
+/// chance to be used.
+const LFU_INIT: u8 = 5;
+
+/// Estimated bytes the keys in every database take up, which `maxmemory` limits.
+static USED_MEMORY: AtomicU64 = AtomicU64::new(0);
+
+/// Count of changes to the dataset since startup. A command that moves it needs writing to the
+/// append-only file.
+static DIRTY: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Makes `index` the database the next command's writes are reported against. Call it with the
+/// command's locks held.
+pub fn select(index: usize) {
+    SELECTED.set(index);
+}
+
+pub fn selected() -> usize {
+    SELECTED.get()
+}
+
+/// Records that `key` in the selected database was written, for WATCH. Call it with the
+/// command's locks held.
+pub fn signal_modified(key: &[u8]) {
+    MODIFIED.with_borrow_mut(|modified| modified.push((selected(), key.to_vec())));
+    mark_dirty();
+}
+
+/// Counts a change to the dataset that no single key's write covers, like swapping databases
+/// or loading a function library.
+pub fn mark_dirty() {
+    DIRTY.fetch_add(1, Ordering::Relaxed);
+    DIRTIED.set(DIRTIED.get() + 1);
+}
+
+pub fn used_memory() -> u64 {
+    USED_MEMORY.load(Ordering::Relaxed)
+}
+
+pub fn dirty() -> u64 {
+    DIRTY.load(Ordering::Relaxed)
+}
+
+/// Changes made on this thread, the one running the command, since startup.
+pub fn dirtied() -> u64 {
+    DIRTIED.get()
+}
+
+/// Gives every key written since the last call a new version, and sizes it up again.
+/// Drops what [`Storage::drop_expired_reads`] would, for a command run with write locks: the
+/// keys in shards `dbs` doesn't hold for writing are left for it.
+pub fn drop_expired(dbs: &mut [Keyspace]) {
+    let expired = EXPIRED_READS.take();
+    if expired.is_empty() {
+        return;
+    }
+
+    let selected = selected();
+    for (index, key) in expired {
+        if matches!(dbs[index].shards[shard_of(&key)], Some(Held::Written(_))) {
+            select(index);
+            dbs[index].get_live(&key);
+        } else {
+            EXPIRED_READS.with_borrow_mut(|expired| expired.push((index, key)));
+        }
+    }
+    select(selected);
+}
+
+pub fn bump_versions(dbs: &mut [Keyspace]) {
+    for (index, key) in MODIFIED.take() {
+        if let Some(val) = dbs[index].get_mut(&key) {
+            val.version = next_version();
+        }
+        dbs[index].remeasure(&key);
+    }
+}
+
+#[derive(Clone)]
 pub enum DBVal {
-    String(String),
+    /// Shared with the replies that read it, so a GET doesn't copy the value.
+    String(Bytes),
     Int(i64),
+    List(VecDeque<Vec<u8>>),
+    Hash(Hash),
+    Set(Set),
+    ZSet(ZSet),
+    Stream(Stream),
+}
+
+impl DBVal {
+    /// The value as a byte string, or `None` if it isn't a string type.
+    pub fn string_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            DBVal::String(s) => Some(Cow::Borrowed(s)),
+            DBVal::Int(n) => Some(Cow::Owned(n.to_string().into_bytes())),
+            _ => None,
+        }
+    }
+
+    /// The value as a byte string a reply can share, or `None` if it isn't a string type.
+    pub fn string_value(&self) -> Option<Bytes> {
+        match self {
+            DBVal::String(s) => Some(s.clone()),
+            DBVal::Int(n) => Some(n.to_string().into()),
+            _ => None,
+        }
+    }
+
+    /// The name reported by `TYPE`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DBVal::String(_) | DBVal::Int(_) => "string",
+            DBVal::List(_) => "list",
+            DBVal::Hash(_) => "hash",
+            DBVal::Set(_) => "set",
+            DBVal::ZSet(_) => "zset",
+            DBVal::Stream(_) => "stream",
+        }
+    }
+
+    /// The name reported by `OBJECT ENCODING`, using Redis' names for the equivalent layouts.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            DBVal::Int(_) => "int",
+            DBVal::String(s) if s.len() <= 44 => "embstr",
+            DBVal::String(_) => "raw",
+            DBVal::List(list) if encoding::list_is_compact(list) => "listpack",
+            DBVal::List(_) => "quicklist",
+            DBVal::Hash(hash) => hash.encoding(),
+            DBVal::Set(set) => set.encoding(),
+            DBVal::ZSet(zset) => zset.encoding(),
+            DBVal::Stream(_) => "stream",
+        }
+    }
+
+    /// Stores integers as `Int` so they can later be incremented in place.
+    pub fn parse(s: &[u8]) -> Self {
+        match std::str::from_utf8(s)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+        {
+            // Only canonical forms, so "007" or "+1" still read back byte-for-byte
+            Some(n) if n.to_string().as_bytes() == s => DBVal::Int(n),
+            _ => DBVal::String(Bytes::copy_from_slice(s)),
+        }
+    }
+}
+
+/// A string value taken out of its [`Bytes`] to be edited in place, and put back when dropped.
+/// It's only copied if a reply is still holding on to it.
+pub struct StringMut<'a> {
+    slot: &'a mut Bytes,
+    owned: Vec<u8>,
+}
+
+impl<'a> StringMut<'a> {
+    pub fn new(slot: &'a mut Bytes) -> Self {
+        let owned = std::mem::take(slot).into();
+        Self { slot, owned }
+    }
+}
+
+impl Deref for StringMut<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.owned
+    }
+}
+
+impl DerefMut for StringMut<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.owned
+    }
+}
+
+impl Drop for StringMut<'_> {
+    fn drop(&mut self) {
+        *self.slot = std::mem::take(&mut self.owned).into();
+    }
 }
 
 pub struct DBData {
-    data: DBVal,
-    created_at: Instant,
-    exp: Option<u64>, // Exp time in millis
+    /// Shared with any frozen copies of the keyspace, and only copied if it's written while
+    /// one still has it.
+    data: Arc<DBVal>,
+    /// When the key expires, in Unix milliseconds.
+    expires_at: Option<u64>,
+    /// When the key was last read or written, in Unix milliseconds. Reads record it under a
+    /// read lock, as they do `lfu`.
+    accessed_at: AtomicU64,
+    /// Logarithmic count of accesses, as of `accessed_at`.
+    lfu: AtomicU8,
+    version: u64,
+    /// What the key was estimated to take up when it was last written, as counted in
+    /// [`used_memory`].
+    bytes: usize,
+    /// The deadline its shard's index has it under, if any.
+    indexed: Option<u64>,
+}
+
+/// Copied when a keyspace shared with a frozen one is written, which only copies the value
+/// if it's written too.
+impl Clone for DBData {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            expires_at: self.expires_at,
+            accessed_at: AtomicU64::new(self.accessed_at.load(Ordering::Relaxed)),
+            lfu: AtomicU8::new(self.lfu.load(Ordering::Relaxed)),
+            version: self.version,
+            bytes: self.bytes,
+            indexed: self.indexed,
+        }
+    }
 }
 
 impl DBData {
-    pub fn new(data: DBVal, created_at: Instant, exp: Option<u64>) -> Self {
+    pub fn new(data: DBVal, expires_at: Option<u64>) -> Self {
         Self {
-            data,
-            created_at,
-            exp,
+            data: Arc::new(data),
+            expires_at,
+            accessed_at: AtomicU64::new(unix_millis()),
+            lfu: AtomicU8::new(LFU_INIT),
+            version: next_version(),
+            bytes: 0,
+            indexed: None,
         }
     }
 
@@ -29,11 +761,287 @@ impl DBData {
         &self.data
     }
 
-    pub fn created_at(&self) -> Instant {
-        self.created_at
+    pub fn data_mut(&mut self) -> &mut DBVal {
+        Arc::make_mut(&mut self.data)
+    }
+
+    /// When the key expires, in Unix milliseconds, if it has a TTL.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+
+    /// How many milliseconds the key has left, if it has a TTL.
+    pub fn ttl(&self) -> Option<u64> {
+        self.expires_at.map(|at| at.saturating_sub(unix_millis()))
+    }
+
+    /// How long since the key was last read or written.
+    pub fn idle(&self) -> Duration {
+        let accessed_at = self.accessed_at.load(Ordering::Relaxed);
+        Duration::from_millis(unix_millis().saturating_sub(accessed_at))
+    }
+
+    /// Changes whenever the key is written, so WATCH can tell if it was touched in between.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Records an access: the access time is refreshed, and the access frequency counter goes
+    /// up by one with a chance that shrinks the higher it already is.
+    pub fn touch(&self) {
+        let freq = self.freq();
+        let odds = (freq.saturating_sub(LFU_INIT) as u64)
+            .saturating_mul(LFU_LOG_FACTOR.load(Ordering::Relaxed))
+            .saturating_add(1);
+        let freq = if freq < u8::MAX && rand::below(odds as usize) == 0 {
+            freq + 1
+        } else {
+            freq
+        };
+        self.lfu.store(freq, Ordering::Relaxed);
+        self.accessed_at.store(unix_millis(), Ordering::Relaxed);
+    }
+
+    /// The access frequency counter, a logarithmic 0 to 255, after dropping by one for every
+    /// `lfu-decay-time` minutes since the key was last accessed.
+    pub fn freq(&self) -> u8 {
+        let period = LFU_DECAY_TIME.load(Ordering::Relaxed);
+        let lfu = self.lfu.load(Ordering::Relaxed);
+        if period == 0 {
+            return lfu;
+        }
+        let periods = self.idle().as_secs() / 60 / period;
+
+        lfu.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    pub fn set_freq(&mut self, freq: u8) {
+        self.lfu.store(freq, Ordering::Relaxed);
+    }
+
+    /// Backdates the access time, as if the key had gone untouched for `idle`.
+    pub fn set_idle(&mut self, idle: Duration) {
+        let accessed_at = unix_millis().saturating_sub(idle.as_millis() as u64);
+        self.accessed_at.store(accessed_at, Ordering::Relaxed);
+    }
+
+    /// The soonest the key or one of its hash fields expires, which the expire cycle goes by.
+    fn deadline(&self) -> Option<u64> {
+        let fields = match &*self.data {
+            DBVal::Hash(hash) => hash.expires().map(|(_, at)| *at).min(),
+            _ => None,
+        };
+        self.expires_at.into_iter().chain(fields).min()
     }
 
-    pub fn exp(&self) -> Option<u64> {
-        self.exp
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| unix_millis() >= at)
+    }
+
+    /// Drops the hash fields whose own TTL has passed, returning whether `key` should be kept:
+    /// it's dropped once it has expired itself or has no fields left. What goes is passed on as
+    /// deletes in the selected database.
+    pub fn sweep(&mut self, key: &[u8], now: u64) -> bool {
+        if self.is_expired() {
+            report_expired(key);
+            return false;
+        }
+
+        // Looked at first so a value a frozen keyspace shares isn't copied for nothing
+        let due =
+            matches!(&*self.data, DBVal::Hash(hash) if hash.expires().any(|(_, at)| *at <= now));
+        if due
+            && let DBVal::Hash(hash) = self.data_mut()
+            && let expired = hash.purge_expired(now)
+            && !expired.is_empty()
+        {
+            notify::emit(Class::Hash, "hexpired", key);
+            propagate::fields_expired(selected(), key, &expired);
+            if hash.is_empty() {
+                notify::emit(Class::Generic, "del", key);
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Counts `key` in the selected database as expired, notifies of it and passes it on as a DEL,
+/// once it's been dropped for it.
+fn report_expired(key: &[u8]) {
+    stats::EXPIRED_KEYS.fetch_add(1, Ordering::Relaxed);
+    notify::emit(Class::Expired, "expired", key);
+    propagate::expired(selected(), key);
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Reclaims the keys whose TTL has passed without anyone looking them up, the way Redis' active
+/// expire cycle does. Each run goes one shard at a time, taking the keys that are due from the
+/// front of its deadline index, soonest first, until it's been through every shard or its time
+/// is up, and the next run carries on from there. Replicas leave expiring keys to their master,
+/// which sends the deletes. Runs for as long as the server does.
+pub async fn expire_keys(db: Db) {
+    let mut interval = tokio::time::interval(EXPIRE_CYCLE_PERIOD);
+    let mut next = 0;
+    loop {
+        interval.tick().await;
+        if !ACTIVE_EXPIRE.load(Ordering::Relaxed) || replication::is_replica() {
+            continue;
+        }
+
+        let started = Instant::now();
+        let out_of_time = || started.elapsed() >= EXPIRE_CYCLE_BUDGET;
+        for _ in 0..SHARDS {
+            // One shard at a time, so clients only ever wait on the one being swept
+            let mut dbs = db.lock_shards(vec![next], false).await;
+            next = (next + 1) % SHARDS;
+            tracking::set_origin(0);
+            let now = unix_millis();
+            for (index, keyspace) in dbs.iter_mut().enumerate() {
+                select(index);
+                while expire_due(keyspace, now) && !out_of_time() {}
+            }
+            bump_versions(&mut dbs);
+            if out_of_time() {
+                break;
+            }
+        }
+        latency::record("expire-cycle", started.elapsed());
+    }
+}
+
+/// Sweeps the next batch of keys in `keyspace` that are due as of `now`, returning whether
+/// there may be more.
+fn expire_due(keyspace: &mut Keyspace, now: u64) -> bool {
+    if keyspace.next_deadline().is_none_or(|at| at > now) {
+        return false;
+    }
+
+    let due = keyspace.due(now, EXPIRE_BATCH);
+    for key in &due {
+        let val = keyspace.get_mut(key).expect("indexed keys exist");
+        if val.sweep(key, now) {
+            // Only hash fields went, so it's filed under the next one
+            keyspace.remeasure(key);
+        } else {
+            keyspace.remove(key);
+        }
+    }
+
+    due.len() == EXPIRE_BATCH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::registry::TestClient;
+    use crate::resp::Value;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[tokio::test]
+    async fn expired_keys_read_as_missing_and_are_dropped() {
+        let storage = new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        for key in ["s", "l"] {
+            let val = DBData::new(DBVal::parse(b"v"), Some(1));
+            dbs[0].insert(key.as_bytes().to_vec(), val);
+        }
+
+        let mut client = TestClient::new();
+        assert!(matches!(client.run(&mut dbs, &["get", "s"]), Value::Null));
+        assert!(matches!(
+            client.run(&mut dbs, &["llen", "l"]),
+            Value::Integer(0)
+        ));
+        // Commands that only read leave them to be dropped after
+        drop_expired(&mut dbs);
+        assert!(dbs[0].get(b"s".as_slice()).is_none());
+        assert!(dbs[0].get(b"l".as_slice()).is_none());
+    }
+
+    #[tokio::test]
+    async fn commands_that_only_read_share_their_locks() {
+        let storage = new_databases(1);
+        let (get, set) = (args(&["k"]), args(&["k", "v"]));
+        let wait = Duration::from_millis(50);
+
+        let reading = storage.lock_for("get", &get).await;
+        let also_reading = tokio::time::timeout(wait, storage.lock_for("mget", &get)).await;
+        assert!(also_reading.is_ok());
+        let writing = tokio::time::timeout(wait, storage.lock_for("set", &set)).await;
+        assert!(writing.is_err());
+
+        drop((reading, also_reading));
+        assert!(
+            tokio::time::timeout(wait, storage.lock_for("set", &set))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn frozen_keyspaces_keep_what_they_had_while_writes_go_on() {
+        let mut client = TestClient::new();
+        let storage = new_databases(1);
+        let mut write = async |parts: &[&str]| {
+            client.run(&mut storage.lock_all().await, parts);
+        };
+
+        write(&["set", "s", "before"]).await;
+        write(&["rpush", "l", "a"]).await;
+        write(&["set", "gone", "1"]).await;
+        let frozen = storage.freeze().await;
+        write(&["set", "s", "after"]).await;
+        write(&["rpush", "l", "b"]).await;
+        write(&["del", "gone"]).await;
+        write(&["set", "new", "1"]).await;
+
+        let value = |key: &str| frozen[0].get(key.as_bytes()).map(|val| val.data().clone());
+        assert!(matches!(value("s"), Some(DBVal::String(s)) if s == "before"));
+        assert!(matches!(value("l"), Some(DBVal::List(list)) if list.len() == 1));
+        assert!(value("gone").is_some());
+        assert!(value("new").is_none());
+        assert_eq!(frozen[0].len(), 3);
+
+        let dbs = storage.lock_all().await;
+        let now = |key: &str| dbs[0].get(key.as_bytes()).map(|val| val.data().clone());
+        assert!(matches!(now("s"), Some(DBVal::String(s)) if s == "after"));
+        assert!(matches!(now("l"), Some(DBVal::List(list)) if list.len() == 2));
+        assert!(now("gone").is_none());
+    }
+
+    #[tokio::test]
+    async fn keys_are_filed_under_their_soonest_deadline() {
+        let storage = new_databases(1);
+        let mut dbs = storage.lock_all().await;
+        let mut client = TestClient::new();
+
+        let later = (unix_millis() + 100_000).to_string();
+        let sooner = (unix_millis() + 50_000).to_string();
+        client.run(&mut dbs, &["set", "a", "1", "pxat", &later]);
+        client.run(&mut dbs, &["set", "b", "1"]);
+        assert_eq!(dbs[0].next_deadline(), later.parse().ok());
+
+        client.run(&mut dbs, &["hset", "h", "f", "v"]);
+        client.run(&mut dbs, &["hpexpireat", "h", &sooner, "fields", "1", "f"]);
+        bump_versions(&mut dbs);
+        assert_eq!(dbs[0].next_deadline(), sooner.parse().ok());
+        assert!(dbs[0].due(unix_millis(), 10).is_empty());
+        assert_eq!(dbs[0].due(u64::MAX, 10).len(), 2);
+
+        client.run(&mut dbs, &["del", "h"]);
+        assert_eq!(dbs[0].next_deadline(), later.parse().ok());
+        client.run(&mut dbs, &["getex", "a", "persist"]);
+        assert_eq!(dbs[0].next_deadline(), None);
     }
 }
@@ -0,0 +1,313 @@
+use crate::crc64::crc64;
+use crate::db::DBVal;
+use crate::hash::Hash;
+use crate::set::Set;
+use crate::stream::{ConsumerGroup, Stream, StreamId};
+use crate::zset::ZSet;
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+/// Bumped whenever the payload layout changes, so stale payloads are rejected rather than
+/// misread.
+const DUMP_VERSION: u16 = 2;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_INT: u8 = 1;
+const TYPE_LIST: u8 = 2;
+const TYPE_HASH: u8 = 3;
+const TYPE_SET: u8 = 4;
+const TYPE_ZSET: u8 = 5;
+const TYPE_STREAM: u8 = 6;
+const TYPE_FUNCTIONS: u8 = 7;
+
+/// Serialises a value as `<type><body><version: u16 LE><crc64: u64 LE>`, mirroring the
+/// layout of Redis' own DUMP payloads.
+pub fn dump_value(val: &DBVal) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    match val {
+        DBVal::String(s) => {
+            out.push(TYPE_STRING);
+            write_bytes(&mut out, s);
+        }
+        DBVal::Int(n) => {
+            out.push(TYPE_INT);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        DBVal::List(list) => {
+            out.push(TYPE_LIST);
+            write_len(&mut out, list.len());
+            for element in list {
+                write_bytes(&mut out, element);
+            }
+        }
+        DBVal::Hash(hash) => {
+            out.push(TYPE_HASH);
+            write_len(&mut out, hash.len());
+            for (field, value) in hash.iter() {
+                write_bytes(&mut out, field);
+                write_bytes(&mut out, value);
+            }
+            write_len(&mut out, hash.expires().len());
+            for (field, at) in hash.expires() {
+                write_bytes(&mut out, field);
+                out.extend_from_slice(&at.to_le_bytes());
+            }
+        }
+        DBVal::Set(set) => {
+            out.push(TYPE_SET);
+            write_len(&mut out, set.len());
+            for member in set.iter() {
+                write_bytes(&mut out, &member);
+            }
+        }
+        DBVal::ZSet(zset) => {
+            out.push(TYPE_ZSET);
+            write_len(&mut out, zset.len());
+            for (member, score) in zset.iter() {
+                write_bytes(&mut out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        DBVal::Stream(stream) => {
+            out.push(TYPE_STREAM);
+            write_id(&mut out, stream.last_id());
+            write_len(&mut out, stream.len());
+            for (id, fields) in stream.iter() {
+                write_id(&mut out, *id);
+                write_len(&mut out, fields.len());
+                for (field, value) in fields {
+                    write_bytes(&mut out, field);
+                    write_bytes(&mut out, value);
+                }
+            }
+            write_len(&mut out, stream.groups().count());
+            for (name, group) in stream.groups() {
+                write_bytes(&mut out, name);
+                write_id(&mut out, group.last_delivered);
+                write_len(&mut out, group.consumers().len());
+                for (name, consumer) in group.consumers() {
+                    write_bytes(&mut out, name);
+                    out.extend_from_slice(&consumer.seen_at.to_le_bytes());
+                }
+                write_len(&mut out, group.pending().len());
+                for (id, pending) in group.pending() {
+                    write_id(&mut out, *id);
+                    write_bytes(&mut out, &pending.consumer);
+                    out.extend_from_slice(&pending.delivered_at.to_le_bytes());
+                    out.extend_from_slice(&pending.delivery_count.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    frame(out)
+}
+
+pub fn restore_value(payload: &[u8]) -> anyhow::Result<DBVal> {
+    let body = unframe(payload)?;
+
+    let mut reader = Reader { buf: body, pos: 1 };
+    let val = match body[0] {
+        TYPE_STRING => DBVal::String(Bytes::copy_from_slice(reader.bytes()?)),
+        TYPE_INT => DBVal::Int(i64::from_le_bytes(reader.take(8)?.try_into()?)),
+        TYPE_LIST => {
+            let len = reader.len()?;
+            let mut list = VecDeque::with_capacity(len.min(1024));
+            for _ in 0..len {
+                list.push_back(reader.bytes()?.to_vec());
+            }
+            DBVal::List(list)
+        }
+        TYPE_HASH => {
+            let len = reader.len()?;
+            let mut hash = Hash::new();
+            for _ in 0..len {
+                let field = reader.bytes()?.to_vec();
+                hash.insert(field, reader.bytes()?.to_vec());
+            }
+            for _ in 0..reader.len()? {
+                let field = reader.bytes()?.to_vec();
+                hash.set_expire(&field, reader.u64()?);
+            }
+            DBVal::Hash(hash)
+        }
+        TYPE_SET => {
+            let len = reader.len()?;
+            let mut set = Set::new();
+            for _ in 0..len {
+                set.insert(reader.bytes()?.to_vec());
+            }
+            DBVal::Set(set)
+        }
+        TYPE_ZSET => {
+            let len = reader.len()?;
+            let mut zset = ZSet::new();
+            for _ in 0..len {
+                let member = reader.bytes()?.to_vec();
+                let score = f64::from_le_bytes(reader.take(8)?.try_into()?);
+                if score.is_nan() {
+                    return Err(anyhow::anyhow!("NaN score in payload"));
+                }
+                zset.insert(member, score);
+            }
+            DBVal::ZSet(zset)
+        }
+        TYPE_STREAM => {
+            let last_id = reader.id()?;
+            let len = reader.len()?;
+            let mut stream = Stream::new();
+            for _ in 0..len {
+                let id = reader.id()?;
+                if id <= stream.last_id() && stream.len() > 0 {
+                    return Err(anyhow::anyhow!("stream IDs out of order"));
+                }
+                let field_count = reader.len()?;
+                let mut fields = Vec::with_capacity(field_count.min(1024));
+                for _ in 0..field_count {
+                    let field = reader.bytes()?.to_vec();
+                    fields.push((field, reader.bytes()?.to_vec()));
+                }
+                stream.append(id, fields);
+            }
+            if stream.last_id() > last_id {
+                return Err(anyhow::anyhow!("stream last ID behind its entries"));
+            }
+            stream.set_last_id(last_id);
+            for _ in 0..reader.len()? {
+                let name = reader.bytes()?.to_vec();
+                let mut group = ConsumerGroup::new(reader.id()?);
+                for _ in 0..reader.len()? {
+                    let consumer = reader.bytes()?;
+                    group.create_consumer(consumer, reader.u64()?);
+                }
+                for _ in 0..reader.len()? {
+                    let id = reader.id()?;
+                    let consumer = reader.bytes()?;
+                    group.assign(id, consumer, reader.u64()?, reader.u64()?);
+                }
+                if !stream.create_group(name, group) {
+                    return Err(anyhow::anyhow!("duplicate consumer group"));
+                }
+            }
+            DBVal::Stream(stream)
+        }
+        t => return Err(anyhow::anyhow!("unknown value type {t}")),
+    };
+
+    if reader.pos != body.len() {
+        return Err(anyhow::anyhow!("trailing bytes in payload"));
+    }
+
+    Ok(val)
+}
+
+/// Serialises function libraries as their source code, framed like a DUMP payload.
+pub fn dump_functions(codes: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = vec![TYPE_FUNCTIONS];
+    write_len(&mut out, codes.len());
+    for code in codes {
+        write_bytes(&mut out, code);
+    }
+
+    frame(out)
+}
+
+/// The library sources in a payload made by [`dump_functions`].
+pub fn restore_functions(payload: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let body = unframe(payload)?;
+    if body[0] != TYPE_FUNCTIONS {
+        return Err(anyhow::anyhow!("not a function payload"));
+    }
+
+    let mut reader = Reader { buf: body, pos: 1 };
+    let codes = (0..reader.len()?)
+        .map(|_| Ok(reader.bytes()?.to_vec()))
+        .collect::<anyhow::Result<_>>()?;
+
+    if reader.pos != body.len() {
+        return Err(anyhow::anyhow!("trailing bytes in payload"));
+    }
+
+    Ok(codes)
+}
+
+/// Appends the version and checksum trailer.
+fn frame(mut out: Vec<u8>) -> Vec<u8> {
+    out.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+    let crc = crc64(0, &out);
+    out.extend_from_slice(&crc.to_le_bytes());
+
+    out
+}
+
+/// Checks and strips the trailer [`frame`] added, leaving the type byte and body.
+fn unframe(payload: &[u8]) -> anyhow::Result<&[u8]> {
+    if payload.len() < 11 {
+        return Err(anyhow::anyhow!("payload too short"));
+    }
+
+    let (body, crc) = payload.split_at(payload.len() - 8);
+    if crc64(0, body).to_le_bytes() != crc {
+        return Err(anyhow::anyhow!("checksum mismatch"));
+    }
+
+    let (body, version) = body.split_at(body.len() - 2);
+    if u16::from_le_bytes([version[0], version[1]]) != DUMP_VERSION {
+        return Err(anyhow::anyhow!("unsupported payload version"));
+    }
+
+    Ok(body)
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+fn write_id(out: &mut Vec<u8>, id: StreamId) {
+    out.extend_from_slice(&id.ms.to_le_bytes());
+    out.extend_from_slice(&id.seq.to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| anyhow::anyhow!("truncated payload"))?;
+
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn len(&mut self) -> anyhow::Result<usize> {
+        Ok(self.u64()? as usize)
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn id(&mut self) -> anyhow::Result<StreamId> {
+        Ok(StreamId::new(self.u64()?, self.u64()?))
+    }
+
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self.len()?;
+
+        self.take(len)
+    }
+}
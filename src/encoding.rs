@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+/// Thresholds past which small collections give up their compact encoding, named after the
+/// Redis settings they mirror. Conversion only goes one way, except for lists, which Redis also
+/// shrinks back down.
+pub static HASH_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+pub static HASH_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+pub static SET_MAX_INTSET_ENTRIES: AtomicUsize = AtomicUsize::new(512);
+pub static SET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+pub static SET_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+pub static ZSET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+pub static ZSET_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+
+/// Positive values cap a compact list's length; -1 to -5 cap its size at 4KB to 64KB.
+pub static LIST_MAX_LISTPACK_SIZE: AtomicI64 = AtomicI64::new(-2);
+
+pub fn limit(setting: &AtomicUsize) -> usize {
+    setting.load(Ordering::Relaxed)
+}
+
+/// Whether a collection of `len` entries, none longer than `longest` bytes, stays compact.
+pub fn fits(len: usize, longest: usize, entries: &AtomicUsize, value: &AtomicUsize) -> bool {
+    len <= limit(entries) && longest <= limit(value)
+}
+
+/// Lists are stored the same way at any size, so their encoding is just a matter of which side
+/// of `list-max-listpack-size` they're on.
+pub fn list_is_compact(list: &VecDeque<Vec<u8>>) -> bool {
+    match LIST_MAX_LISTPACK_SIZE.load(Ordering::Relaxed) {
+        size if size > 0 => list.len() <= size as usize,
+        size => {
+            let max_bytes = 4096 << (size.unsigned_abs().clamp(1, 5) - 1);
+            list.iter().map(Vec::len).sum::<usize>() <= max_bytes
+        }
+    }
+}
+
+/// Iterates whichever encoding a collection currently uses.
+pub enum EncodedIter<A, B> {
+    Compact(A),
+    Full(B),
+}
+
+impl<T, A: Iterator<Item = T>, B: Iterator<Item = T>> Iterator for EncodedIter<A, B> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            EncodedIter::Compact(iter) => iter.next(),
+            EncodedIter::Full(iter) => iter.next(),
+        }
+    }
+}
+
+impl<T, A: DoubleEndedIterator<Item = T>, B: DoubleEndedIterator<Item = T>> DoubleEndedIterator
+    for EncodedIter<A, B>
+{
+    fn next_back(&mut self) -> Option<T> {
+        match self {
+            EncodedIter::Compact(iter) => iter.next_back(),
+            EncodedIter::Full(iter) => iter.next_back(),
+        }
+    }
+}
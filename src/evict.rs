@@ -0,0 +1,120 @@
+use crate::config;
+use crate::db::{self, DBData, Keyspace};
+use crate::latency;
+use crate::lazyfree;
+use crate::notify::{self, Class};
+use crate::propagate;
+use crate::rand;
+use crate::stats;
+use crate::tracking;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+/// How many keys are looked at to pick each one to evict, like Redis' `maxmemory-samples`.
+const SAMPLES: usize = 5;
+
+/// How many random picks a volatile policy makes looking for keys with a TTL before it goes
+/// through the keys in order instead, for when there are few among many.
+const VOLATILE_TRIES: usize = SAMPLES * 10;
+
+/// Whether the keyspace has grown past `maxmemory`, if there is a limit.
+pub fn over_limit() -> bool {
+    let limit = config::get().maxmemory;
+    limit > 0 && db::used_memory() > limit
+}
+
+/// Whether the `maxmemory-policy` goes by how often keys are used, which OBJECT FREQ only
+/// answers for.
+pub fn by_frequency() -> bool {
+    config::get().maxmemory_policy.ends_with("-lfu")
+}
+
+/// Evicts keys the `maxmemory-policy` allows until the keyspace is back within `maxmemory`.
+/// Returns whether it is, which it can't be under `noeviction` or once the policy runs out of
+/// keys to evict. Call it with every shard locked.
+pub fn make_room(dbs: &mut [Keyspace]) -> bool {
+    let policy = config::get().maxmemory_policy.clone();
+    let started = Instant::now();
+    tracking::set_origin(0);
+
+    let mut fits = true;
+    while over_limit() {
+        let Some((index, key)) = choose(dbs, &policy) else {
+            fits = false;
+            break;
+        };
+        db::select(index);
+        if let Some(val) = dbs[index].remove(&key) {
+            lazyfree::free(val);
+        }
+        stats::EVICTED_KEYS.fetch_add(1, Ordering::Relaxed);
+        notify::emit(Class::Evicted, "evicted", &key);
+        propagate::evicted(index, &key);
+    }
+    db::bump_versions(dbs);
+    latency::record("eviction-cycle", started.elapsed());
+
+    fits
+}
+
+/// The key `policy` evicts next, with its database.
+fn choose(dbs: &[Keyspace], policy: &str) -> Option<(usize, Vec<u8>)> {
+    if policy == "noeviction" {
+        return None;
+    }
+    let candidates = sample(dbs, policy.starts_with("volatile-"));
+
+    let chosen = match policy {
+        "allkeys-random" | "volatile-random" => candidates.first(),
+        "volatile-ttl" => candidates.iter().min_by_key(|(_, _, val)| val.expires_at()),
+        "allkeys-lfu" | "volatile-lfu" => candidates.iter().min_by_key(|(_, _, val)| val.freq()),
+        _ => candidates.iter().max_by_key(|(_, _, val)| val.idle()),
+    };
+    chosen.map(|(index, key, _)| (*index, key.to_vec()))
+}
+
+/// Up to [`SAMPLES`] keys picked at random from across `dbs`, only ones with a TTL if
+/// `volatile`.
+fn sample(dbs: &[Keyspace], volatile: bool) -> Vec<(usize, &[u8], &DBData)> {
+    let total: usize = dbs.iter().map(Keyspace::len).sum();
+    let mut sample = Vec::with_capacity(SAMPLES);
+    if total == 0 {
+        return sample;
+    }
+
+    let tries = if volatile { VOLATILE_TRIES } else { SAMPLES };
+    for _ in 0..tries {
+        if sample.len() == SAMPLES {
+            break;
+        }
+        let (index, key, val) = nth(dbs, rand::below(total));
+        if !volatile || val.expires_at().is_some() {
+            sample.push((index, key, val));
+        }
+    }
+    if sample.is_empty() && volatile {
+        let with_ttl = dbs.iter().enumerate().flat_map(|(index, keyspace)| {
+            keyspace
+                .iter()
+                .filter(|(_, val)| val.expires_at().is_some())
+                .map(move |(key, val)| (index, &key[..], val))
+        });
+        sample.extend(with_ttl.take(SAMPLES));
+    }
+
+    sample
+}
+
+/// The `n`th key across all of `dbs`, with its database.
+fn nth(dbs: &[Keyspace], mut n: usize) -> (usize, &[u8], &DBData) {
+    for (index, keyspace) in dbs.iter().enumerate() {
+        let len = keyspace.len();
+        if n < len {
+            let (key, val) = keyspace.nth(n).expect("n is within the keyspace");
+            return (index, key, val);
+        }
+        n -= len;
+    }
+
+    unreachable!("n is below the number of keys")
+}
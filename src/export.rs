@@ -0,0 +1,350 @@
+//! The keyspace as JSON, for EXPORT and IMPORT: a record per key, with its database, type,
+//! deadline and value, either as one array (`json`) or a line each (`jsonl`).
+//!
+//! Strings that are valid UTF-8 are JSON strings, and any others `{"hex": "..."}`. Hashes
+//! and stream entries are objects of their fields, or `[name, value]` pairs if a name can't
+//! be an object key. Sorted sets are `[member, score]` pairs, with infinite scores as the
+//! strings `"inf"` and `"-inf"`.
+
+use crate::db::{DBVal, Keyspace};
+use crate::hash::Hash;
+use crate::json::Json;
+use crate::set::Set;
+use crate::stream::{ConsumerGroup, Stream, StreamId};
+use crate::zset::ZSet;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Jsonl,
+}
+
+/// A key as an export holds it.
+pub struct Record {
+    pub db: usize,
+    pub key: Vec<u8>,
+    /// Unix time in milliseconds the key expires, if it has a TTL.
+    pub expires_at: Option<u64>,
+    pub val: DBVal,
+}
+
+/// Every key in `dbs` that hasn't expired, and how many there were.
+pub fn export(dbs: &[Keyspace], format: Format) -> (String, usize) {
+    let records = dbs.iter().enumerate().flat_map(|(index, db)| {
+        db.iter()
+            .filter(|(_, val)| !val.is_expired())
+            .map(move |(key, val)| record(index, key, val.expires_at(), val.data()))
+    });
+
+    let mut out = String::new();
+    let mut count = 0;
+    for record in records {
+        let separator = match (format, count) {
+            (Format::Json, 0) => "[\n",
+            (Format::Json, _) => ",\n",
+            (Format::Jsonl, _) => "",
+        };
+        out.push_str(separator);
+        let _ = write!(out, "{record}");
+        if let Format::Jsonl = format {
+            out.push('\n');
+        }
+        count += 1;
+    }
+    if let Format::Json = format {
+        out.push_str(if count == 0 { "[]\n" } else { "\n]\n" });
+    }
+
+    (out, count)
+}
+
+fn record(db: usize, key: &[u8], expires_at: Option<u64>, val: &DBVal) -> Json {
+    let value = match val {
+        DBVal::String(_) | DBVal::Int(_) => bytes(&val.string_bytes().unwrap_or_default()),
+        DBVal::List(list) => Json::Array(list.iter().map(|e| bytes(e)).collect()),
+        DBVal::Set(set) => Json::Array(set.iter().map(|m| bytes(&m)).collect()),
+        DBVal::Hash(hash) => fields(hash.iter().map(|(name, value)| (name, bytes(value)))),
+        DBVal::ZSet(zset) => Json::Array(
+            zset.iter()
+                .map(|(member, score)| Json::Array(vec![bytes(member), Json::Float(score)]))
+                .collect(),
+        ),
+        DBVal::Stream(stream) => self::stream(stream),
+    };
+
+    let mut fields = vec![
+        ("db", Json::Int(db as i64)),
+        ("key", bytes(key)),
+        ("type", Json::str(val.type_name())),
+        (
+            "expires_at",
+            expires_at.map_or(Json::Null, |at| Json::Int(at as i64)),
+        ),
+        ("value", value),
+    ];
+    if let DBVal::Hash(hash) = val
+        && hash.expires().len() > 0
+    {
+        let expires = hash
+            .expires()
+            .map(|(name, at)| (name, Json::Int(*at as i64)));
+        fields.push(("field_expires_at", self::fields(expires)));
+    }
+    Json::object(fields)
+}
+
+fn stream(stream: &Stream) -> Json {
+    let entries = stream.iter().map(|(id, entry)| {
+        let entry = fields(entry.iter().map(|(name, value)| (name, bytes(value))));
+        Json::Array(vec![Json::str(id.to_string()), entry])
+    });
+    let groups = stream.groups().map(|(name, group)| {
+        let consumers = group.consumers().iter().map(|(name, consumer)| {
+            Json::object([
+                ("name", bytes(name)),
+                ("seen_at", Json::Int(consumer.seen_at as i64)),
+            ])
+        });
+        let pending = group.pending().iter().map(|(id, entry)| {
+            Json::object([
+                ("id", Json::str(id.to_string())),
+                ("consumer", bytes(&entry.consumer)),
+                ("delivered_at", Json::Int(entry.delivered_at as i64)),
+                ("delivery_count", Json::Int(entry.delivery_count as i64)),
+            ])
+        });
+        Json::object([
+            ("name", bytes(name)),
+            (
+                "last_delivered_id",
+                Json::str(group.last_delivered.to_string()),
+            ),
+            ("consumers", Json::Array(consumers.collect())),
+            ("pending", Json::Array(pending.collect())),
+        ])
+    });
+
+    Json::object([
+        ("last_id", Json::str(stream.last_id().to_string())),
+        ("entries", Json::Array(entries.collect())),
+        ("groups", Json::Array(groups.collect())),
+    ])
+}
+
+fn bytes(bytes: &[u8]) -> Json {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => Json::str(s),
+        Err(_) => {
+            let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+            Json::object([("hex", Json::Str(hex))])
+        }
+    }
+}
+
+/// Named values as an object, or as pairs if a name isn't UTF-8.
+fn fields<'a>(fields: impl Iterator<Item = (&'a Vec<u8>, Json)>) -> Json {
+    let fields: Vec<_> = fields.collect();
+    if fields
+        .iter()
+        .all(|(name, _)| std::str::from_utf8(name).is_ok())
+    {
+        Json::object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (String::from_utf8_lossy(name).into_owned(), value)),
+        )
+    } else {
+        Json::Array(
+            fields
+                .into_iter()
+                .map(|(name, value)| Json::Array(vec![bytes(name), value]))
+                .collect(),
+        )
+    }
+}
+
+/// Reads an export back, in either format: a JSON array of records, or a record per line.
+pub fn parse(text: &str) -> anyhow::Result<Vec<Record>> {
+    if text.trim_start().starts_with('[') {
+        let Json::Array(records) = Json::parse(text)? else {
+            unreachable!("the text starts an array");
+        };
+        records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| parse_record(record).map_err(|e| anyhow::anyhow!("record {i}: {e}")))
+            .collect()
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                Json::parse(line)
+                    .and_then(|record| parse_record(&record))
+                    .map_err(|e| anyhow::anyhow!("line {}: {e}", i + 1))
+            })
+            .collect()
+    }
+}
+
+fn parse_record(record: &Json) -> anyhow::Result<Record> {
+    let db = usize::try_from(int(field(record, "db")?)?)?;
+    let key = parse_bytes(field(record, "key")?)?;
+    let expires_at = match record.get("expires_at") {
+        None | Some(Json::Null) => None,
+        Some(at) => Some(u64::try_from(int(at)?)?),
+    };
+    let value = field(record, "value")?;
+
+    let val = match str(field(record, "type")?)? {
+        "string" => DBVal::parse(&parse_bytes(value)?),
+        "list" => DBVal::List(
+            array(value)?
+                .iter()
+                .map(parse_bytes)
+                .collect::<anyhow::Result<VecDeque<_>>>()?,
+        ),
+        "set" => {
+            let mut set = Set::new();
+            for member in array(value)? {
+                set.insert(parse_bytes(member)?);
+            }
+            DBVal::Set(set)
+        }
+        "hash" => {
+            let mut hash = Hash::new();
+            for (name, value) in parse_fields(value)? {
+                hash.insert(name, parse_bytes(value)?);
+            }
+            if let Some(expires) = record.get("field_expires_at") {
+                for (name, at) in parse_fields(expires)? {
+                    hash.set_expire(&name, u64::try_from(int(at)?)?);
+                }
+            }
+            DBVal::Hash(hash)
+        }
+        "zset" => {
+            let mut zset = ZSet::new();
+            for pair in array(value)? {
+                let [member, score] = array(pair)? else {
+                    anyhow::bail!("expected a [member, score] pair");
+                };
+                let score = match score {
+                    Json::Int(n) => *n as f64,
+                    Json::Float(n) => *n,
+                    Json::Str(n) => n.parse()?,
+                    _ => anyhow::bail!("expected a score"),
+                };
+                zset.insert(parse_bytes(member)?, score);
+            }
+            DBVal::ZSet(zset)
+        }
+        "stream" => DBVal::Stream(parse_stream(value)?),
+        other => anyhow::bail!("unknown type '{other}'"),
+    };
+
+    Ok(Record {
+        db,
+        key,
+        expires_at,
+        val,
+    })
+}
+
+fn parse_stream(value: &Json) -> anyhow::Result<Stream> {
+    let mut stream = Stream::new();
+    for entry in array(field(value, "entries")?)? {
+        let [id, fields] = array(entry)? else {
+            anyhow::bail!("expected an [id, fields] pair");
+        };
+        let fields = parse_fields(fields)?
+            .into_iter()
+            .map(|(name, value)| Ok((name, parse_bytes(value)?)))
+            .collect::<anyhow::Result<_>>()?;
+        stream.append(id_of(id)?, fields);
+    }
+    stream.set_last_id(id_of(field(value, "last_id")?)?);
+
+    for group in array(field(value, "groups")?)? {
+        let mut created = ConsumerGroup::new(id_of(field(group, "last_delivered_id")?)?);
+        for consumer in array(field(group, "consumers")?)? {
+            let seen_at = u64::try_from(int(field(consumer, "seen_at")?)?)?;
+            created.create_consumer(&parse_bytes(field(consumer, "name")?)?, seen_at);
+        }
+        for entry in array(field(group, "pending")?)? {
+            created.assign(
+                id_of(field(entry, "id")?)?,
+                &parse_bytes(field(entry, "consumer")?)?,
+                u64::try_from(int(field(entry, "delivered_at")?)?)?,
+                u64::try_from(int(field(entry, "delivery_count")?)?)?,
+            );
+        }
+        stream.create_group(parse_bytes(field(group, "name")?)?, created);
+    }
+    Ok(stream)
+}
+
+fn parse_bytes(json: &Json) -> anyhow::Result<Vec<u8>> {
+    match json {
+        Json::Str(s) => Ok(s.clone().into_bytes()),
+        Json::Object(_) => {
+            let hex = json.get("hex").map(str).transpose()?.unwrap_or_default();
+            if hex.len() % 2 != 0 {
+                anyhow::bail!("odd number of hex digits");
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+                .collect()
+        }
+        _ => anyhow::bail!("expected a string"),
+    }
+}
+
+fn parse_fields(json: &Json) -> anyhow::Result<Vec<(Vec<u8>, &Json)>> {
+    match json {
+        Json::Object(fields) => Ok(fields
+            .iter()
+            .map(|(name, value)| (name.clone().into_bytes(), value))
+            .collect()),
+        Json::Array(pairs) => pairs
+            .iter()
+            .map(|pair| match array(pair)? {
+                [name, value] => Ok((parse_bytes(name)?, value)),
+                _ => anyhow::bail!("expected a [name, value] pair"),
+            })
+            .collect(),
+        _ => anyhow::bail!("expected an object of fields"),
+    }
+}
+
+fn id_of(json: &Json) -> anyhow::Result<StreamId> {
+    StreamId::parse(str(json)?.as_bytes(), 0).ok_or_else(|| anyhow::anyhow!("invalid stream ID"))
+}
+
+fn field<'a>(json: &'a Json, name: &str) -> anyhow::Result<&'a Json> {
+    json.get(name).ok_or_else(|| anyhow::anyhow!("no '{name}'"))
+}
+
+fn str(json: &Json) -> anyhow::Result<&str> {
+    match json {
+        Json::Str(s) => Ok(s),
+        _ => anyhow::bail!("expected a string"),
+    }
+}
+
+fn int(json: &Json) -> anyhow::Result<i64> {
+    match json {
+        Json::Int(n) => Ok(*n),
+        _ => anyhow::bail!("expected an integer"),
+    }
+}
+
+fn array(json: &Json) -> anyhow::Result<&[Json]> {
+    match json {
+        Json::Array(values) => Ok(values),
+        _ => anyhow::bail!("expected an array"),
+    }
+}
@@ -0,0 +1,117 @@
+use crate::client;
+use crate::replication;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::info;
+
+/// How often a failover waiting for its target checks how far the target has got.
+const CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Long enough to stand for "until the failover is over", which lifts it.
+const PAUSE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Where a FAILOVER has got to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    None,
+    /// Writes are paused until a replica has everything the master has.
+    WaitingForSync,
+    /// The master is following the replica, asking it to take over.
+    InProgress,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::None => "no-failover",
+            State::WaitingForSync => "waiting-for-sync",
+            State::InProgress => "failover-in-progress",
+        }
+    }
+}
+
+static STATE: LazyLock<watch::Sender<State>> = LazyLock::new(|| watch::channel(State::None).0);
+
+pub fn state() -> State {
+    *STATE.borrow()
+}
+
+/// Pauses writes and hands the master's role over to `target`, or to whichever replica first
+/// has everything, once it does. If that takes longer than `timeout`, the failover is called
+/// off, unless `force` says to go ahead with `target` anyway.
+pub fn start(target: Option<(String, u16)>, timeout: Option<Duration>, force: bool) {
+    STATE.send_replace(State::WaitingForSync);
+    client::pause(PAUSE, false);
+    match &target {
+        Some((host, port)) => info!("FAILOVER requested to {host}:{port}."),
+        None => info!("FAILOVER requested to any replica."),
+    }
+
+    tokio::spawn(async move {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = STATE.subscribe();
+        let chosen = loop {
+            if *state.borrow_and_update() != State::WaitingForSync {
+                return;
+            }
+            if let Some(replica) = synced_replica(target.as_ref()) {
+                break replica;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                match &target {
+                    Some(target) if force => break target.clone(),
+                    _ => {
+                        info!("FAILOVER to replica timed out waiting for it to sync.");
+                        abort();
+                        return;
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_PERIOD) => {}
+                _ = state.changed() => {}
+            }
+        };
+
+        let (host, port) = chosen;
+        info!("Failover target {host}:{port} is synced, failing over.");
+        STATE.send_replace(State::InProgress);
+        replication::follow(Some((host, port)));
+    });
+}
+
+/// A replica matching `target`, or any if there's none, that has acknowledged everything the
+/// master has streamed.
+fn synced_replica(target: Option<&(String, u16)>) -> Option<(String, u16)> {
+    let offset = replication::offset();
+
+    replication::replicas().into_iter().find_map(|replica| {
+        let host = replica.addr.ip().to_canonical().to_string();
+        let wanted = target.is_none_or(|(target, port)| *target == host && *port == replica.port);
+        let synced = replica.state == "online" && replica.offset >= offset;
+        (wanted && synced).then_some((host, replica.port))
+    })
+}
+
+/// Whether a failover is under way.
+pub fn in_progress() -> bool {
+    state() != State::None
+}
+
+/// Calls the failover off, going back to being a master if it had already started following
+/// the target.
+pub fn abort() {
+    if STATE.send_replace(State::None) == State::InProgress {
+        replication::follow(None);
+    }
+    client::unpause();
+}
+
+/// Ends the failover now that the target has taken over.
+pub fn finished() {
+    STATE.send_replace(State::None);
+    client::unpause();
+}
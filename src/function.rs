@@ -0,0 +1,185 @@
+use crate::resp::Value;
+use crate::script::{self, is_valid_name};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// A function as FUNCTION LIST describes it.
+#[derive(Clone)]
+pub struct Function {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+impl Function {
+    /// Whether FCALL_RO may call it, which also keeps FCALL from letting it write.
+    pub fn is_read_only(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "no-writes")
+    }
+}
+
+/// A library loaded with FUNCTION LOAD: its source, which is run again on every FCALL, and
+/// the functions it registered.
+#[derive(Clone)]
+pub struct Library {
+    pub name: String,
+    pub code: Vec<u8>,
+    pub functions: Vec<Function>,
+}
+
+/// Libraries by name, shared by every connection.
+static LIBRARIES: Mutex<BTreeMap<String, Library>> = Mutex::new(BTreeMap::new());
+
+/// The library name from a `#!lua name=<library>` first line.
+fn parse_metadata(code: &[u8]) -> Result<String, Value> {
+    let code = String::from_utf8_lossy(code);
+    let Some(shebang) = code.lines().next().and_then(|line| line.strip_prefix("#!")) else {
+        return Err(Value::error("ERR Missing library metadata"));
+    };
+
+    let mut parts = shebang.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(Value::error(format!("ERR Engine '{engine}' not found")));
+    }
+
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => {
+                return Err(Value::error(format!(
+                    "ERR Invalid metadata value given: {part}"
+                )));
+            }
+        }
+    }
+
+    let name = name.ok_or_else(|| Value::error("ERR Library name was not given"))?;
+    if !is_valid_name(&name) {
+        return Err(Value::error(
+            "ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+        ));
+    }
+
+    Ok(name)
+}
+
+/// The library's Lua source, without the metadata line, which Lua wouldn't understand.
+fn body(code: &[u8]) -> &[u8] {
+    match code.iter().position(|b| *b == b'\n') {
+        Some(end) => &code[end + 1..],
+        None => &[],
+    }
+}
+
+/// Loads `code` into `libraries`, returning the library's name. Without `replace`, a library of
+/// the same name is an error; either way no other library may already own one of its
+/// functions.
+fn load_into(
+    libraries: &mut BTreeMap<String, Library>,
+    code: &[u8],
+    replace: bool,
+) -> Result<String, Value> {
+    let name = parse_metadata(code)?;
+    if !replace && libraries.contains_key(&name) {
+        return Err(Value::error(format!("ERR Library '{name}' already exists")));
+    }
+
+    let registered = script::load_library(body(code))?;
+    if registered.is_empty() {
+        return Err(Value::error("ERR No functions registered"));
+    }
+
+    for registration in &registered {
+        let taken = libraries
+            .values()
+            .filter(|library| library.name != name)
+            .any(|library| {
+                library
+                    .functions
+                    .iter()
+                    .any(|f| f.name == registration.name)
+            });
+        if taken {
+            return Err(Value::error(format!(
+                "ERR Function {} already exists",
+                registration.name
+            )));
+        }
+    }
+
+    let functions = registered
+        .into_iter()
+        .map(|registration| Function {
+            name: registration.name,
+            description: registration.description,
+            flags: registration.flags,
+        })
+        .collect();
+    libraries.insert(
+        name.clone(),
+        Library {
+            name: name.clone(),
+            code: code.to_vec(),
+            functions,
+        },
+    );
+
+    Ok(name)
+}
+
+/// FUNCTION LOAD: registers the library in `code`, returning its name.
+pub fn load(code: &[u8], replace: bool) -> Result<String, Value> {
+    load_into(&mut LIBRARIES.lock().unwrap(), code, replace)
+}
+
+/// The function called `name` and the Lua source of the library that registered it.
+pub fn find(name: &str) -> Option<(Function, Vec<u8>)> {
+    LIBRARIES.lock().unwrap().values().find_map(|library| {
+        let function = library.functions.iter().find(|f| f.name == name)?;
+
+        Some((function.clone(), body(&library.code).to_vec()))
+    })
+}
+
+/// Removes a library and its functions, returning whether it existed.
+pub fn delete(name: &str) -> bool {
+    LIBRARIES.lock().unwrap().remove(name).is_some()
+}
+
+pub fn flush() {
+    LIBRARIES.lock().unwrap().clear();
+}
+
+/// Every library, in name order.
+pub fn libraries() -> Vec<Library> {
+    LIBRARIES.lock().unwrap().values().cloned().collect()
+}
+
+/// How FUNCTION RESTORE treats the libraries already loaded.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RestorePolicy {
+    /// Keep them, failing if a restored library has the same name as one of them.
+    Append,
+    /// Keep them, but let restored libraries replace ones with the same name.
+    Replace,
+    /// Drop them all first.
+    Flush,
+}
+
+/// Loads each library in `codes`, all or nothing.
+pub fn restore(codes: &[Vec<u8>], policy: RestorePolicy) -> Result<(), Value> {
+    let mut libraries = LIBRARIES.lock().unwrap();
+    let mut restored = match policy {
+        RestorePolicy::Flush => BTreeMap::new(),
+        RestorePolicy::Append | RestorePolicy::Replace => libraries.clone(),
+    };
+
+    for code in codes {
+        load_into(&mut restored, code, policy == RestorePolicy::Replace)?;
+    }
+    *libraries = restored;
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Geohash encoding and distance maths, following Redis' geo implementation so scores and
+//! distances agree with a real server.
+
+/// Bits per coordinate; the interleaved hash is twice this.
+const STEP: u32 = 26;
+pub const LON_MIN: f64 = -180.0;
+pub const LON_MAX: f64 = 180.0;
+/// The Web Mercator limits Redis uses, beyond which a geohash can't represent points.
+pub const LAT_MIN: f64 = -85.051_128_78;
+pub const LAT_MAX: f64 = 85.051_128_78;
+const EARTH_RADIUS_METERS: f64 = 6_372_797.560_856;
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Whether a longitude/latitude pair can be stored.
+pub fn is_valid(lon: f64, lat: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+/// Spreads the low 32 bits of `x` out to the even bit positions.
+fn spread(x: u64) -> u64 {
+    let mut x = x & 0xffff_ffff;
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Gathers the even bit positions of `x` back into the low 32 bits.
+fn squash(x: u64) -> u64 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    (x | (x >> 16)) & 0x0000_0000_ffff_ffff
+}
+
+fn encode_in(lon: f64, lat: f64, lat_min: f64, lat_max: f64) -> u64 {
+    let cells = (1u64 << STEP) as f64;
+    let lat_offset = ((lat - lat_min) / (lat_max - lat_min) * cells) as u64;
+    let lon_offset = ((lon - LON_MIN) / (LON_MAX - LON_MIN) * cells) as u64;
+
+    spread(lat_offset) | (spread(lon_offset) << 1)
+}
+
+/// The 52-bit geohash stored as a member's score.
+pub fn encode(lon: f64, lat: f64) -> u64 {
+    encode_in(lon, lat, LAT_MIN, LAT_MAX)
+}
+
+/// The centre of the cell a geohash names, as `(longitude, latitude)`.
+pub fn decode(hash: u64) -> (f64, f64) {
+    let cells = (1u64 << STEP) as f64;
+    let lat_cell = squash(hash) as f64;
+    let lon_cell = squash(hash >> 1) as f64;
+
+    let lat_span = LAT_MAX - LAT_MIN;
+    let lon_span = LON_MAX - LON_MIN;
+    let lat = LAT_MIN + (lat_cell + 0.5) / cells * lat_span;
+    let lon = LON_MIN + (lon_cell + 0.5) / cells * lon_span;
+
+    (lon.clamp(LON_MIN, LON_MAX), lat.clamp(LAT_MIN, LAT_MAX))
+}
+
+/// The standard 11-character base32 geohash, which uses the full ±90° latitude range.
+pub fn to_base32(lon: f64, lat: f64) -> String {
+    let hash = encode_in(lon, lat, -90.0, 90.0);
+
+    (0..11)
+        .map(|i| {
+            // 52 bits only fill ten characters; like Redis, pad the last with zero
+            let index = if i == 10 {
+                0
+            } else {
+                (hash >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            BASE32[index as usize] as char
+        })
+        .collect()
+}
+
+/// Great-circle distance in metres between two `(longitude, latitude)` points.
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lon1, lat1) = (from.0.to_radians(), from.1.to_radians());
+    let (lon2, lat2) = (to.0.to_radians(), to.1.to_radians());
+
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// Distance in metres along a meridian between two latitudes.
+pub fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_METERS * (lat2.to_radians() - lat1.to_radians()).abs()
+}
@@ -0,0 +1,98 @@
+/// Glob-style matching with the same syntax as Redis' `stringmatchlen`: `*`, `?`, `[...]`
+/// classes (with `^` negation and `a-z` ranges) and `\` escapes.
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let (mut p, mut s) = (0, 0);
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                for start in s..=string.len() {
+                    if glob_match(&pattern[p + 1..], &string[start..], nocase) {
+                        return true;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if s >= string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                if s >= string.len() {
+                    return false;
+                }
+
+                p += 1;
+                let negate = pattern.get(p) == Some(&b'^');
+                if negate {
+                    p += 1;
+                }
+
+                let mut matched = false;
+                while p < pattern.len() && pattern[p] != b']' {
+                    if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        matched |= eq(pattern[p], string[s]);
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (mut lo, mut hi) = (pattern[p], pattern[p + 2]);
+                        if lo > hi {
+                            std::mem::swap(&mut lo, &mut hi);
+                        }
+                        let c = string[s];
+                        matched |= if nocase {
+                            (lo.to_ascii_lowercase()..=hi.to_ascii_lowercase())
+                                .contains(&c.to_ascii_lowercase())
+                        } else {
+                            (lo..=hi).contains(&c)
+                        };
+                        p += 2;
+                    } else {
+                        matched |= eq(pattern[p], string[s]);
+                    }
+                    p += 1;
+                }
+
+                // An unterminated class behaves as if it were closed at the end of the pattern
+                if p >= pattern.len() {
+                    p = pattern.len() - 1;
+                }
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if s >= string.len() || !eq(pattern[p], string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                if s >= string.len() || !eq(c, string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+
+    s == string.len()
+}
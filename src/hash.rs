@@ -0,0 +1,183 @@
+use crate::db::unix_millis;
+use crate::encoding::{self, EncodedIter, HASH_MAX_LISTPACK_ENTRIES, HASH_MAX_LISTPACK_VALUE};
+use std::collections::HashMap;
+
+/// Small hashes keep their fields in a flat list, in insertion order, and switch to a table once
+/// they outgrow the listpack thresholds.
+#[derive(Clone)]
+enum Fields {
+    Listpack(Vec<(Vec<u8>, Vec<u8>)>),
+    Table(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+/// A hash's fields, plus the unix-millisecond deadlines of any fields given their own TTL.
+#[derive(Clone)]
+pub struct Hash {
+    fields: Fields,
+    expires: HashMap<Vec<u8>, u64>,
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Self {
+            fields: Fields::Listpack(Vec::new()),
+            expires: HashMap::new(),
+        }
+    }
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.fields {
+            Fields::Listpack(pairs) => pairs.len(),
+            Fields::Table(table) => table.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The name reported by `OBJECT ENCODING`.
+    pub fn encoding(&self) -> &'static str {
+        match &self.fields {
+            Fields::Listpack(_) if self.expires.is_empty() => "listpack",
+            Fields::Listpack(_) => "listpackex",
+            Fields::Table(_) => "hashtable",
+        }
+    }
+
+    /// The field's value, treating a field whose TTL has elapsed as already gone.
+    pub fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        if self
+            .expires
+            .get(field)
+            .is_some_and(|at| *at <= unix_millis())
+        {
+            return None;
+        }
+
+        match &self.fields {
+            Fields::Listpack(pairs) => pairs.iter().find(|(f, _)| f == field).map(|(_, v)| v),
+            Fields::Table(table) => table.get(field),
+        }
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// Sets a field, returning its previous value. Overwriting a field clears its TTL, as `HSET`
+    /// does.
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.expires.remove(&field);
+        self.update(field, value)
+    }
+
+    /// Sets a field without touching its TTL, for in-place updates like `HINCRBY`.
+    pub fn update(&mut self, field: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        let pairs = match &mut self.fields {
+            Fields::Listpack(pairs) => pairs,
+            Fields::Table(table) => return table.insert(field, value),
+        };
+
+        let longest = field.len().max(value.len());
+        let old = match pairs.iter_mut().find(|(f, _)| *f == field) {
+            Some((_, old)) => Some(std::mem::replace(old, value)),
+            None => {
+                pairs.push((field, value));
+                None
+            }
+        };
+
+        if !encoding::fits(
+            pairs.len(),
+            longest,
+            &HASH_MAX_LISTPACK_ENTRIES,
+            &HASH_MAX_LISTPACK_VALUE,
+        ) {
+            self.fields = Fields::Table(std::mem::take(pairs).into_iter().collect());
+        }
+
+        old
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Vec<u8>> {
+        self.expires.remove(field);
+
+        match &mut self.fields {
+            Fields::Listpack(pairs) => {
+                let pos = pairs.iter().position(|(f, _)| f == field)?;
+                Some(pairs.remove(pos).1)
+            }
+            Fields::Table(table) => table.remove(field),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        match &self.fields {
+            Fields::Listpack(pairs) => {
+                EncodedIter::Compact(pairs.iter().map(|(field, value)| (field, value)))
+            }
+            Fields::Table(table) => EncodedIter::Full(table.iter()),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.iter().map(|(field, _)| field)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// The field's deadline in unix milliseconds, if it has one.
+    pub fn expire_at(&self, field: &[u8]) -> Option<u64> {
+        self.expires.get(field).copied()
+    }
+
+    /// Fields with a deadline, for persisting them alongside the values.
+    pub fn expires(&self) -> impl ExactSizeIterator<Item = (&Vec<u8>, &u64)> {
+        self.expires.iter()
+    }
+
+    /// Gives an existing field a deadline. Returns `false` if there's no such field.
+    pub fn set_expire(&mut self, field: &[u8], at: u64) -> bool {
+        if !self.iter().any(|(f, _)| f == field) {
+            return false;
+        }
+
+        self.expires.insert(field.to_vec(), at);
+
+        true
+    }
+
+    /// Clears a field's deadline, returning whether it had one.
+    pub fn persist(&mut self, field: &[u8]) -> bool {
+        self.expires.remove(field).is_some()
+    }
+
+    /// Drops every field whose deadline is at or before `now`, returning the ones that went.
+    pub fn purge_expired(&mut self, now: u64) -> Vec<Vec<u8>> {
+        if self.expires.is_empty() {
+            return Vec::new();
+        }
+
+        let expired: Vec<Vec<u8>> = self
+            .expires
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(field, _)| field.clone())
+            .collect();
+
+        for field in &expired {
+            self.remove(field);
+        }
+
+        expired
+    }
+}
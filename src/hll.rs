@@ -0,0 +1,186 @@
+//! Dense HyperLogLog sketches, laid out exactly like Redis' dense encoding so the strings
+//! PFADD produces are interchangeable with a real server's.
+
+const P: u32 = 14;
+const Q: u32 = 64 - P;
+const REGISTERS: usize = 1 << P;
+const BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << BITS) - 1;
+const HEADER_LEN: usize = 16;
+pub const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * BITS).div_ceil(8);
+const MAGIC: &[u8] = b"HYLL";
+const DENSE: u8 = 0;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// Whether `bytes` is a dense HLL this server can work with.
+pub fn is_valid(bytes: &[u8]) -> bool {
+    bytes.len() == DENSE_LEN && bytes.starts_with(MAGIC) && bytes[4] == DENSE
+}
+
+/// A fresh, empty sketch with a valid cached cardinality of zero.
+pub fn new() -> Vec<u8> {
+    let mut hll = vec![0; DENSE_LEN];
+    hll[..4].copy_from_slice(MAGIC);
+
+    hll
+}
+
+fn register(hll: &[u8], index: usize) -> u8 {
+    let bit = index * BITS;
+    let (byte, shift) = (HEADER_LEN + bit / 8, bit % 8);
+    let low = hll[byte] >> shift;
+    let high = hll
+        .get(byte + 1)
+        .map_or(0, |b| b.checked_shl(8 - shift as u32).unwrap_or(0));
+
+    (low | high) & REGISTER_MAX
+}
+
+fn set_register(hll: &mut [u8], index: usize, value: u8) {
+    let bit = index * BITS;
+    let (byte, shift) = (HEADER_LEN + bit / 8, bit % 8);
+
+    hll[byte] &= !(REGISTER_MAX << shift);
+    hll[byte] |= value << shift;
+    if let Some(next) = hll.get_mut(byte + 1) {
+        let spill = 8 - shift as u32;
+        *next &= !REGISTER_MAX.checked_shr(spill).unwrap_or(0);
+        *next |= value.checked_shr(spill).unwrap_or(0);
+    }
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[15] |= 0x80;
+}
+
+/// MurmurHash64A, the hash Redis uses to place elements into registers.
+fn murmur64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("chunk of 8"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+
+    h
+}
+
+/// The register an element maps to, and the run length of zeroes (plus one) it observed.
+fn position(element: &[u8]) -> (usize, u8) {
+    let hash = murmur64a(element, 0xadc8_3b19);
+    let index = (hash as usize) & (REGISTERS - 1);
+    // The sentinel bit caps the count at Q + 1
+    let rest = (hash >> P) | (1 << Q);
+
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// Adds an element, returning whether any register changed.
+pub fn add(hll: &mut [u8], element: &[u8]) -> bool {
+    let (index, count) = position(element);
+    if register(hll, index) >= count {
+        return false;
+    }
+
+    set_register(hll, index, count);
+    invalidate_cache(hll);
+
+    true
+}
+
+/// Raises every register of `into` to at least the matching one in `from`.
+pub fn merge(into: &mut [u8], from: &[u8]) {
+    for index in 0..REGISTERS {
+        let value = register(from, index);
+        if value > register(into, index) {
+            set_register(into, index, value);
+        }
+    }
+    invalidate_cache(into);
+}
+
+/// The cached cardinality, if it's still valid.
+pub fn cached_count(hll: &[u8]) -> Option<u64> {
+    let cache = u64::from_le_bytes(hll[8..16].try_into().expect("8 header bytes"));
+
+    (hll[15] & 0x80 == 0).then_some(cache)
+}
+
+pub fn set_cached_count(hll: &mut [u8], count: u64) {
+    hll[8..16].copy_from_slice(&count.to_le_bytes());
+}
+
+/// Estimates the cardinality with Ertl's improved estimator, as Redis does.
+pub fn count(hll: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for index in 0..REGISTERS {
+        histogram[register(hll, index) as usize] += 1;
+    }
+
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for j in (1..=Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Callbacks a program embedding the server has called as keys change, to keep indexes or
+//! caches of its own up to date or feed the changes on elsewhere, without polling for them.
+//! Each is handed to the [`Builder`](crate::Builder) as `on_write`, `on_expire` or `on_evict`.
+
+use crate::db;
+use crate::notify::Class;
+use std::sync::RwLock;
+
+/// A change to a key, as a hook is told of it.
+pub struct KeyEvent<'a> {
+    /// The database the key is in.
+    pub db: usize,
+    pub key: &'a [u8],
+    /// What happened to it, named as keyspace notifications name it: `set`, `lpush`, `del`,
+    /// `expired`, `evicted` and so on.
+    pub event: &'a str,
+}
+
+type Hook = Box<dyn Fn(&KeyEvent<'_>) + Send + Sync>;
+
+/// The hooks a [`Builder`](crate::Builder) was given, by what they're called for.
+#[derive(Default)]
+pub struct Hooks {
+    write: Vec<Hook>,
+    expire: Vec<Hook>,
+    evict: Vec<Hook>,
+}
+
+impl Hooks {
+    pub fn on_write(&mut self, hook: impl Fn(&KeyEvent<'_>) + Send + Sync + 'static) {
+        self.write.push(Box::new(hook));
+    }
+
+    pub fn on_expire(&mut self, hook: impl Fn(&KeyEvent<'_>) + Send + Sync + 'static) {
+        self.expire.push(Box::new(hook));
+    }
+
+    pub fn on_evict(&mut self, hook: impl Fn(&KeyEvent<'_>) + Send + Sync + 'static) {
+        self.evict.push(Box::new(hook));
+    }
+}
+
+/// The hooks of the server last built.
+static HOOKS: RwLock<Hooks> = RwLock::new(Hooks {
+    write: Vec::new(),
+    expire: Vec::new(),
+    evict: Vec::new(),
+});
+
+/// Starts calling `hooks`, in place of whatever the server before had.
+pub fn install(hooks: Hooks) {
+    *HOOKS.write().unwrap() = hooks;
+}
+
+/// Calls the hooks for `event` on `key` in the selected database, of `class`: expiry and
+/// eviction have hooks of their own, and the rest are writes.
+pub fn call(class: Class, event: &str, key: &[u8]) {
+    let hooks = HOOKS.read().unwrap();
+    let hooks = match class {
+        Class::Expired => &hooks.expire,
+        Class::Evicted => &hooks.evict,
+        _ => &hooks.write,
+    };
+    if hooks.is_empty() {
+        return;
+    }
+
+    let event = KeyEvent {
+        db: db::selected(),
+        key,
+        event,
+    };
+    for hook in hooks {
+        hook(&event);
+    }
+}
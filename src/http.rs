@@ -0,0 +1,216 @@
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest request head that's read; anything longer is refused.
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// A request, split into its method, path and query string, which is empty if there's none.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    /// Header names, in lower case, and their values.
+    headers: Vec<(String, String)>,
+    /// What's been read of the body: whatever came in with the head, until [`read_body`].
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of query parameter `name`, percent-decoded, if it was given.
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.params(name).into_iter().next()
+    }
+
+    /// Every value query parameter `name` was given, percent-decoded, in order.
+    pub fn params(&self, name: &str) -> Vec<String> {
+        self.query
+            .split('&')
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (key == name).then(|| String::from_utf8_lossy(&decode(value)).into_owned())
+            })
+            .collect()
+    }
+
+    /// The value of header `name`, given in lower case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The user name and password of Basic authorization, if the request has it.
+    pub fn basic_auth(&self) -> Option<(String, Vec<u8>)> {
+        let credentials = self.header("authorization")?.strip_prefix("Basic ")?;
+        let credentials = decode_base64(credentials.trim())?;
+        let colon = credentials.iter().position(|&b| b == b':')?;
+        let user = String::from_utf8_lossy(&credentials[..colon]).into_owned();
+        Some((user, credentials[colon + 1..].to_vec()))
+    }
+}
+
+/// Reads the head of a request, giving up on a client too slow to send it.
+pub async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    tokio::time::timeout(REQUEST_TIMEOUT, read_head(stream))
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn read_head(stream: &mut TcpStream) -> Option<Request> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    let end = loop {
+        if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if request.len() >= MAX_REQUEST {
+            return None;
+        }
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        request.extend_from_slice(&buf[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&request[..end]);
+    let mut lines = head.lines();
+    let mut parts = lines.next()?.split_whitespace();
+    let (method, target) = (parts.next()?, parts.next()?);
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body: request[end + 4..].to_vec(),
+    })
+}
+
+/// Reads the rest of the request's body, as long as its `Content-Length` says. Fails with the
+/// status to respond with if it's longer than `max` bytes, its length isn't given, or the
+/// client's too slow to send it.
+pub async fn read_body(
+    stream: &mut TcpStream,
+    request: &mut Request,
+    max: usize,
+) -> Result<(), &'static str> {
+    if request.header("transfer-encoding").is_some() {
+        return Err("411 Length Required");
+    }
+    let len = match request.header("content-length") {
+        Some(len) => len.parse().map_err(|_| "400 Bad Request")?,
+        None => 0,
+    };
+    if len > max {
+        return Err("413 Content Too Large");
+    }
+
+    let read = async {
+        let mut buf = [0; 8192];
+        while request.body.len() < len {
+            let n = stream.read(&mut buf).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            request.body.extend_from_slice(&buf[..n]);
+        }
+        Some(())
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, read)
+        .await
+        .ok()
+        .flatten()
+        .ok_or("408 Request Timeout")?;
+    request.body.truncate(len);
+    Ok(())
+}
+
+/// Writes a response with `body` and closes the connection.
+pub async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: impl AsRef<[u8]>,
+) {
+    let body = body.as_ref();
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    let _ = stream.write_all(&response).await;
+}
+
+/// Undoes the percent-encoding of a URL's path or query, `+` included.
+pub fn decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    decoded
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// `bytes` in standard base64, padded.
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes standard base64, with or without its padding, or `None` if `s` isn't base64.
+pub fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len() / 4 * 3);
+    let (mut bits, mut len) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        bits = (bits << 6 | value) & 0xffff;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            decoded.push((bits >> len) as u8);
+        }
+    }
+    Some(decoded)
+}
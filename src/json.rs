@@ -0,0 +1,300 @@
+use crate::resp::Value;
+use std::fmt::{self, Write};
+
+/// Just enough JSON for the admin API, the audit log and keyspace exports.
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn str(s: impl Into<String>) -> Json {
+        Json::Str(s.into())
+    }
+
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Parses `text`, which must hold one value and nothing else but whitespace.
+    pub fn parse(text: &str) -> anyhow::Result<Json> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.text.len() {
+            anyhow::bail!("unexpected text after the value at offset {}", parser.pos);
+        }
+        Ok(value)
+    }
+
+    /// The field called `name`, if this is an object with one.
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn error<T>(&self, expected: &str) -> anyhow::Result<T> {
+        anyhow::bail!("expected {expected} at offset {}", self.pos)
+    }
+
+    /// Moves past `c`, after any whitespace, if it's next.
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_whitespace();
+        let next = self.text.get(self.pos) == Some(&c);
+        if next {
+            self.pos += 1;
+        }
+        next
+    }
+
+    fn value(&mut self) -> anyhow::Result<Json> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        for (literal, value) in [
+            ("null", Json::Null),
+            ("true", Json::Bool(true)),
+            ("false", Json::Bool(false)),
+        ] {
+            if rest.starts_with(literal.as_bytes()) {
+                self.pos += literal.len();
+                return Ok(value);
+            }
+        }
+
+        match rest.first() {
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if self.eat(b']') {
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    if self.eat(b']') {
+                        return Ok(Json::Array(values));
+                    }
+                    if !self.eat(b',') {
+                        return self.error("',' or ']'");
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.eat(b'}') {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    if !self.eat(b':') {
+                        return self.error("':'");
+                    }
+                    fields.push((name, self.value()?));
+                    if self.eat(b'}') {
+                        return Ok(Json::Object(fields));
+                    }
+                    if !self.eat(b',') {
+                        return self.error("',' or '}'");
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => self.error("a value"),
+        }
+    }
+
+    fn number(&mut self) -> anyhow::Result<Json> {
+        let start = self.pos;
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|c| matches!(c, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        let number = std::str::from_utf8(&self.text[start..self.pos])?;
+        if let Ok(n) = number.parse() {
+            return Ok(Json::Int(n));
+        }
+        match number.parse() {
+            Ok(n) => Ok(Json::Float(n)),
+            Err(_) => {
+                self.pos = start;
+                self.error("a number")
+            }
+        }
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        if self.text.get(self.pos) != Some(&b'"') {
+            return self.error("a string");
+        }
+        self.pos += 1;
+
+        let mut s = Vec::new();
+        loop {
+            let Some(&c) = self.text.get(self.pos) else {
+                return self.error("'\"'");
+            };
+            self.pos += 1;
+            match c {
+                b'"' => return Ok(String::from_utf8(s)?),
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.pos) else {
+                        return self.error("an escape");
+                    };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return self.error("an escape"),
+                    };
+                    s.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// The character a `\u` escape stands for, the `u` already read. Characters outside the
+    /// basic multilingual plane take two, as a surrogate pair.
+    fn unicode_escape(&mut self) -> anyhow::Result<char> {
+        let high = self.hex()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.text[self.pos..].starts_with(b"\\u") {
+                return self.error("a low surrogate");
+            }
+            self.pos += 2;
+            let low = self.hex()?;
+            0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+        } else {
+            high
+        };
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.error("a valid character"),
+        }
+    }
+
+    fn hex(&mut self) -> anyhow::Result<u32> {
+        let n = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        let Some(n) = n else {
+            return self.error("4 hex digits");
+        };
+        self.pos += 4;
+        Ok(n)
+    }
+}
+
+impl From<Value> for Json {
+    fn from(value: Value) -> Json {
+        match value {
+            Value::SimpleString(s) => Json::Str(s),
+            Value::Error(msg) => Json::Object(vec![("error".to_string(), Json::Str(msg))]),
+            Value::BulkString(s) => Json::str(String::from_utf8_lossy(&s)),
+            Value::Integer(n) => Json::Int(n),
+            Value::Array(values) | Value::Push(values) => {
+                Json::Array(values.into_iter().map(Json::from).collect())
+            }
+            Value::Map(pairs) => Json::Array(
+                pairs
+                    .into_iter()
+                    .map(|(k, v)| Json::Array(vec![Json::from(k), Json::from(v)]))
+                    .collect(),
+            ),
+            Value::Null | Value::NullArray => Json::Null,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Int(n) => write!(f, "{n}"),
+            // JSON has no infinities, which scores can be
+            Json::Float(n) if n.is_finite() => write!(f, "{n}"),
+            Json::Float(n) => write!(f, "\"{n}\""),
+            Json::Str(s) => write_string(f, s),
+            Json::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
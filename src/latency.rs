@@ -0,0 +1,312 @@
+use crate::resp::Value;
+use bytes::Bytes;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Events taking at least this many milliseconds are recorded. 0 turns monitoring off.
+pub static THRESHOLD: AtomicU64 = AtomicU64::new(0);
+
+/// How many samples each event keeps, dropping the oldest beyond that.
+const HISTORY_LEN: usize = 160;
+
+/// The spikes seen for one event: `command`, `fast-command` or `expire-cycle`.
+#[derive(Default)]
+struct Event {
+    /// Unix time in seconds and latency in milliseconds, oldest first. Spikes in the same
+    /// second share a sample, which keeps the worst of them.
+    samples: VecDeque<(u64, u64)>,
+    /// The worst latency seen since the event was last reset.
+    max: u64,
+}
+
+static EVENTS: Mutex<BTreeMap<&'static str, Event>> = Mutex::new(BTreeMap::new());
+
+/// Each power of two of microseconds is split into this many buckets, as in an HDR histogram,
+/// so percentiles read off one are within a sixteenth of the latency they stand for.
+const SUB_BUCKETS: u64 = 16;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+/// Latencies from 2^MAX_MAGNITUDE microseconds, over nine hours, all share the last bucket.
+const MAX_MAGNITUDE: u32 = 35;
+const BUCKETS: usize = ((MAX_MAGNITUDE - SUB_BITS + 2) as u64 * SUB_BUCKETS) as usize;
+
+/// The percentiles LATENCY HISTOGRAM works out, and what it calls them.
+const PERCENTILES: &[(&str, f64)] = &[("p50", 50.0), ("p99", 99.0), ("p99.9", 99.9)];
+
+/// Every run of one command, by how many microseconds it took.
+struct Histogram {
+    calls: u64,
+    counts: Box<[u64; BUCKETS]>,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            calls: 0,
+            counts: Box::new([0; BUCKETS]),
+        }
+    }
+
+    /// The bucket `usec` is counted in: the first `2 * SUB_BUCKETS` hold a microsecond each,
+    /// and each power of two after them is split `SUB_BUCKETS` ways.
+    fn bucket(usec: u64) -> usize {
+        let usec = usec.min((1 << (MAX_MAGNITUDE + 1)) - 1);
+        if usec < SUB_BUCKETS {
+            return usec as usize;
+        }
+        let magnitude = 63 - usec.leading_zeros();
+        let top = usec >> (magnitude - SUB_BITS);
+        ((magnitude - SUB_BITS) as u64 * SUB_BUCKETS + top) as usize
+    }
+
+    /// The least latency counted in `bucket`.
+    fn lowest(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < 2 * SUB_BUCKETS {
+            return bucket;
+        }
+        let shift = bucket / SUB_BUCKETS - 1;
+        (bucket % SUB_BUCKETS + SUB_BUCKETS) << shift
+    }
+
+    /// The latency `percent` of calls took no longer than, as the top of its bucket.
+    fn percentile(&self, percent: f64) -> u64 {
+        let wanted = ((percent / 100.0 * self.calls as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= wanted {
+                return Histogram::lowest(bucket + 1) - 1;
+            }
+        }
+        0
+    }
+
+    /// How many calls took under each power of two of microseconds, from the first with any
+    /// to the first with them all, as Redis gives them.
+    fn powers_of_two(&self) -> Vec<(u64, u64)> {
+        let mut powers = Vec::new();
+        let mut seen = 0;
+        let mut buckets = self.counts.iter().enumerate().peekable();
+        for power in (0..=MAX_MAGNITUDE + 1).map(|magnitude| 1u64 << magnitude) {
+            while let Some((_, count)) =
+                buckets.next_if(|(bucket, _)| Histogram::lowest(*bucket) < power)
+            {
+                seen += count;
+            }
+            if seen > 0 {
+                powers.push((power, seen));
+            }
+            if seen == self.calls {
+                break;
+            }
+        }
+        powers
+    }
+}
+
+static HISTOGRAMS: Mutex<BTreeMap<String, Histogram>> = Mutex::new(BTreeMap::new());
+
+/// Counts a run of command `name` that took `elapsed` in its histogram.
+pub fn observe(name: &str, elapsed: Duration) {
+    let mut histograms = HISTOGRAMS.lock().unwrap();
+    let histogram = match histograms.get_mut(name) {
+        Some(histogram) => histogram,
+        None => histograms
+            .entry(name.to_string())
+            .or_insert_with(Histogram::new),
+    };
+    histogram.calls += 1;
+    histogram.counts[Histogram::bucket(elapsed.as_micros() as u64)] += 1;
+}
+
+/// LATENCY HISTOGRAM's reply: for each of `names` that's been run, or every command if none
+/// are named, how many calls there have been, how many took under each power of two of
+/// microseconds, and the percentiles of their latency.
+pub fn histograms(names: &[String]) -> Value {
+    let histograms = HISTOGRAMS.lock().unwrap();
+    let wanted = |name: &String| names.is_empty() || names.contains(name);
+    let integer = |n: u64| Value::Integer(n as i64);
+    let text = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+
+    Value::Map(
+        histograms
+            .iter()
+            .filter(|(name, _)| wanted(name))
+            .map(|(name, histogram)| {
+                let powers = histogram.powers_of_two();
+                let percentiles = PERCENTILES
+                    .iter()
+                    .map(|(label, percent)| (text(label), integer(histogram.percentile(*percent))))
+                    .collect();
+                let fields = vec![
+                    (text("calls"), integer(histogram.calls)),
+                    (
+                        text("histogram_usec"),
+                        Value::Map(
+                            powers
+                                .into_iter()
+                                .map(|(power, calls)| (integer(power), integer(calls)))
+                                .collect(),
+                        ),
+                    ),
+                    (text("percentiles_usec"), Value::Map(percentiles)),
+                ];
+                (text(name), Value::Map(fields))
+            })
+            .collect(),
+    )
+}
+
+/// CONFIG RESETSTAT: forgets every command's histogram.
+pub fn reset_histograms() {
+    HISTOGRAMS.lock().unwrap().clear();
+}
+
+/// Records a run of `event` that took `elapsed`, if monitoring is on and it took long enough.
+pub fn record(event: &'static str, elapsed: Duration) {
+    let threshold = THRESHOLD.load(Ordering::Relaxed);
+    let latency = elapsed.as_millis() as u64;
+    if threshold == 0 || latency < threshold {
+        return;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let mut events = EVENTS.lock().unwrap();
+    let event = events.entry(event).or_default();
+    event.max = event.max.max(latency);
+    match event.samples.back_mut() {
+        Some((time, worst)) if *time == now => *worst = (*worst).max(latency),
+        _ => {
+            if event.samples.len() == HISTORY_LEN {
+                event.samples.pop_front();
+            }
+            event.samples.push_back((now, latency));
+        }
+    }
+}
+
+/// LATENCY LATEST's reply: each event's name, the time and latency of its latest spike, and
+/// its worst latency.
+pub fn latest() -> Value {
+    Value::Array(
+        EVENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, event)| {
+                let (time, latency) = event.samples.back()?;
+                Some(Value::Array(vec![
+                    Value::BulkString(Bytes::copy_from_slice(name.as_bytes())),
+                    Value::Integer(*time as i64),
+                    Value::Integer(*latency as i64),
+                    Value::Integer(event.max as i64),
+                ]))
+            })
+            .collect(),
+    )
+}
+
+/// LATENCY HISTORY's reply: the time and latency of each of `event`'s spikes, oldest first.
+pub fn history(event: &str) -> Value {
+    let events = EVENTS.lock().unwrap();
+    let samples = events.get(event).map(|event| &event.samples);
+
+    Value::Array(
+        samples
+            .into_iter()
+            .flatten()
+            .map(|(time, latency)| {
+                Value::Array(vec![
+                    Value::Integer(*time as i64),
+                    Value::Integer(*latency as i64),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// Forgets the spikes of the named events, or of every event if none are named. Returns how
+/// many events had any.
+pub fn reset(names: &[String]) -> usize {
+    let mut events = EVENTS.lock().unwrap();
+    if names.is_empty() {
+        let count = events.len();
+        events.clear();
+        return count;
+    }
+
+    names
+        .iter()
+        .filter(|name| events.remove(name.as_str()).is_some())
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histograms_give_powers_of_two_and_percentiles() {
+        let mut histogram = Histogram::new();
+        for usec in 1..=100 {
+            histogram.calls += 1;
+            histogram.counts[Histogram::bucket(usec)] += 1;
+        }
+
+        assert_eq!(
+            histogram.powers_of_two(),
+            [
+                (2, 1),
+                (4, 3),
+                (8, 7),
+                (16, 15),
+                (32, 31),
+                (64, 63),
+                (128, 100)
+            ]
+        );
+        let percentiles: Vec<_> = PERCENTILES
+            .iter()
+            .map(|(_, percent)| histogram.percentile(*percent))
+            .collect();
+        assert_eq!(percentiles, [51, 99, 103]);
+    }
+
+    #[test]
+    fn buckets_hold_what_they_start_at() {
+        for usec in [0, 1, 15, 16, 31, 32, 33, 1000, 123_456_789] {
+            let bucket = Histogram::bucket(usec);
+            assert!(Histogram::lowest(bucket) <= usec, "{usec}");
+            assert!(Histogram::lowest(bucket + 1) > usec, "{usec}");
+        }
+    }
+
+    #[test]
+    fn histograms_are_given_only_for_commands_that_ran() {
+        for usec in [10, 20] {
+            observe("histogram-test", Duration::from_micros(usec));
+        }
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let Value::Map(ran) = histograms(&names(&["histogram-test", "never-run"])) else {
+            panic!("LATENCY HISTOGRAM replies with a map");
+        };
+        assert_eq!(ran.len(), 1);
+        let Value::Map(fields) = &ran[0].1 else {
+            panic!("a command's histogram is a map");
+        };
+        assert!(matches!(fields[0].1, Value::Integer(2)));
+    }
+}
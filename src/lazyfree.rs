@@ -0,0 +1,77 @@
+use crate::db::{DBData, DBVal};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+
+/// Values made of more allocations than this are freed in the background, like Redis'
+/// `LAZYFREE_THRESHOLD`. Smaller ones cost less to drop than to hand over.
+const THRESHOLD: usize = 64;
+
+/// A job, with how many objects it frees.
+type Job = (u64, Box<dyn FnOnce() + Send>);
+
+/// The background thread's queue, started on first use.
+static QUEUE: LazyLock<Mutex<Sender<Job>>> = LazyLock::new(|| {
+    let (sender, jobs) = mpsc::channel::<Job>();
+    thread::Builder::new()
+        .name("lazyfree".to_string())
+        .spawn(move || {
+            for (objects, job) in jobs {
+                job();
+                PENDING.fetch_sub(objects, Ordering::Relaxed);
+                FREED.fetch_add(objects, Ordering::Relaxed);
+            }
+        })
+        .expect("failed to start the lazyfree thread");
+    Mutex::new(sender)
+});
+
+/// Objects handed to the background thread that it hasn't freed yet.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Objects the background thread has freed since startup.
+static FREED: AtomicU64 = AtomicU64::new(0);
+
+pub fn pending() -> u64 {
+    PENDING.load(Ordering::Relaxed)
+}
+
+pub fn freed() -> u64 {
+    FREED.load(Ordering::Relaxed)
+}
+
+pub fn reset_freed() {
+    FREED.store(0, Ordering::Relaxed);
+}
+
+/// Drops `val`, on the background thread if it's big enough that dropping it here would hold
+/// up whoever has the keyspace locked.
+pub fn free(val: DBData) {
+    if effort(val.data()) > THRESHOLD {
+        spawn(1, move || drop(val));
+    }
+}
+
+/// Runs `job`, which frees `objects` things too big to free inline like a whole database, on
+/// the background thread.
+pub fn spawn(objects: u64, job: impl FnOnce() + Send + 'static) {
+    PENDING.fetch_add(objects, Ordering::Relaxed);
+    if let Err(mpsc::SendError((_, job))) = QUEUE.lock().unwrap().send((objects, Box::new(job))) {
+        // The thread is gone, so there's nothing for it but to do it here
+        job();
+        PENDING.fetch_sub(objects, Ordering::Relaxed);
+    }
+}
+
+/// Roughly how many allocations freeing `val` means.
+fn effort(val: &DBVal) -> usize {
+    match val {
+        DBVal::String(_) | DBVal::Int(_) => 1,
+        DBVal::List(list) => list.len(),
+        DBVal::Hash(hash) => hash.len(),
+        DBVal::Set(set) => set.len(),
+        DBVal::ZSet(zset) => zset.len(),
+        DBVal::Stream(stream) => stream.len(),
+    }
+}
@@ -0,0 +1,79 @@
+mod acl;
+mod admin;
+mod alloc;
+mod aof;
+mod audit;
+mod blocking;
+mod bus;
+pub mod check;
+mod client;
+mod cluster;
+mod cmd;
+pub mod config;
+mod crc16;
+mod crc64;
+mod crypt;
+mod daemon;
+mod db;
+mod dump;
+mod encoding;
+mod evict;
+mod export;
+mod failover;
+mod function;
+mod geo;
+mod glob;
+mod hash;
+mod hll;
+mod hooks;
+mod http;
+mod json;
+mod latency;
+mod lazyfree;
+mod logging;
+mod memcache;
+mod metrics;
+mod namespace;
+mod notify;
+mod output;
+mod plugin;
+mod propagate;
+mod pubsub;
+mod rand;
+mod ratelimit;
+mod rdb;
+mod replication;
+pub mod resp;
+mod rest;
+mod s3;
+mod script;
+mod server;
+mod set;
+mod sha1;
+mod sha256;
+mod shadow;
+mod shutdown;
+mod slowlog;
+mod snapshot;
+mod stats;
+mod store;
+mod stream;
+mod systemd;
+mod testing;
+mod tls;
+pub mod trace;
+mod tracking;
+#[cfg(feature = "io-uring")]
+mod uring;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod websocket;
+mod zset;
+
+pub use hooks::KeyEvent;
+pub use plugin::{CommandPlugin, Database};
+pub use s3::S3Store;
+pub use server::{Builder, Server};
+pub use snapshot::SnapshotStore;
+pub use store::Store;
+pub use testing::TestServer;
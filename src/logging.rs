@@ -0,0 +1,103 @@
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::fs::OpenOptions;
+use std::sync::{Mutex, OnceLock};
+use tracing::warn;
+use tracing_subscriber::filter::{self, LevelFilter};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry, reload};
+
+/// Values `loglevel` can take, from the most said to the least.
+pub const LEVELS: &[&str] = &["debug", "verbose", "notice", "warning", "nothing"];
+
+/// Changes the level of the subscriber [`init`] installed.
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Exports command spans over OTLP, when `otlp-endpoint` is set.
+static TRACER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Redis' names for the levels: `debug` has every command and reply, `verbose` connections
+/// coming and going, `notice` what the server does of its own accord, and `warning` only
+/// what went wrong.
+fn filter(level: &str) -> LevelFilter {
+    match level {
+        "debug" => LevelFilter::TRACE,
+        "verbose" => LevelFilter::DEBUG,
+        "warning" => LevelFilter::WARN,
+        "nothing" => LevelFilter::OFF,
+        _ => LevelFilter::INFO,
+    }
+}
+
+/// Logs at `level` to `logfile`, appending to it, or to standard output if it's empty, and
+/// exports a span for each command to the OTLP collector at `otlp_endpoint` if it isn't.
+/// Leaves things be if the process already has a subscriber, as when it embeds the server.
+pub fn init(level: &str, logfile: &str, otlp_endpoint: &str) -> anyhow::Result<()> {
+    let writer = if logfile.is_empty() {
+        BoxMakeWriter::new(std::io::stdout)
+    } else {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(logfile)
+            .map_err(|e| anyhow::anyhow!("Can't open the log file {logfile}: {e}"))?;
+        BoxMakeWriter::new(Mutex::new(file))
+    };
+
+    // The level only applies to what's logged, so that spans are exported whatever it is
+    let (level, handle) = reload::Layer::new(filter(level));
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .with_writer(writer)
+        .with_filter(level);
+    let provider = (!otlp_endpoint.is_empty())
+        .then(|| tracer(otlp_endpoint))
+        .transpose()?;
+    // Only commands are exported, each one the root of its own trace
+    let otel = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("redis"))
+            .with_filter(filter::filter_fn(|meta| meta.name() == "command"))
+    });
+    let subscriber = Registry::default().with(fmt).with(otel);
+    if tracing::subscriber::set_global_default(subscriber).is_ok() {
+        let _ = LEVEL.set(handle);
+        if let Some(provider) = provider {
+            let _ = TRACER.set(provider);
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies a new `loglevel`.
+pub fn set_level(level: &str) {
+    if let Some(handle) = LEVEL.get() {
+        let _ = handle.modify(|filter| *filter = self::filter(level));
+    }
+}
+
+/// Sends off any command spans still waiting to be exported, before the server exits.
+pub fn flush() {
+    if let Some(provider) = TRACER.get()
+        && let Err(e) = provider.shutdown()
+    {
+        warn!("Failed to export the last spans: {e}");
+    }
+}
+
+/// A provider batching spans off to the collector at `endpoint` over OTLP/HTTP.
+fn tracer(endpoint: &str) -> anyhow::Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Can't export spans to {endpoint}: {e}"))?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("redis").build())
+        .build())
+}
@@ -1,221 +1,340 @@
-mod db;
-mod resp;
-
-use crate::db::{DBData, DBVal, Db};
-use crate::resp::Value;
-use clap::Parser;
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
+use clap::{Parser, Subcommand};
+use redis::Server;
+use redis::check;
+use redis::config::ServerConfig;
+use std::path::PathBuf;
+
+mod backup;
+mod bench;
+mod cli;
+mod replay;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 /// Redis Clone
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {}
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-#[tokio::main]
-#[allow(unused)]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    /// A redis.conf-style file to read settings from. Options given here override it
+    #[arg(value_name = "CONFIG")]
+    config_file: Option<PathBuf>,
 
-    let listener = TcpListener::bind("localhost:6379").await?;
+    /// The same as giving the config file as the first argument
+    #[arg(long = "config", value_name = "CONFIG", conflicts_with = "config_file")]
+    config: Option<PathBuf>,
 
-    let db: Db = Arc::new(RwLock::new(HashMap::new()));
+    /// Hashes with more fields than this leave the listpack encoding [default: 128]
+    #[arg(long)]
+    hash_max_listpack_entries: Option<usize>,
 
-    loop {
-        let stream = listener.accept().await;
+    /// Hashes with a longer field or value than this leave the listpack encoding [default: 64]
+    #[arg(long)]
+    hash_max_listpack_value: Option<usize>,
 
-        match stream {
-            Ok((stream, _)) => {
-                println!("accepted new connection");
+    /// Integer sets with more members than this leave the intset encoding [default: 512]
+    #[arg(long)]
+    set_max_intset_entries: Option<usize>,
 
-                let db_thread = db.clone();
+    /// Sets with more members than this leave the listpack encoding [default: 128]
+    #[arg(long)]
+    set_max_listpack_entries: Option<usize>,
 
-                tokio::spawn(async move { handle_connection(stream, db_thread).await });
-            }
-            Err(e) => {
-                println!("error: {}", e);
-            }
-        }
-    }
-}
+    /// Sets with a longer member than this leave the listpack encoding [default: 64]
+    #[arg(long)]
+    set_max_listpack_value: Option<usize>,
 
-async fn handle_connection(stream: TcpStream, db: Db) {
-    let mut handler = resp::RespHandler::new(stream);
+    /// Sorted sets with more members than this leave the listpack encoding [default: 128]
+    #[arg(long)]
+    zset_max_listpack_entries: Option<usize>,
 
-    println!("Starting Loop");
+    /// Sorted sets with a longer member than this leave the listpack encoding [default: 64]
+    #[arg(long)]
+    zset_max_listpack_value: Option<usize>,
 
-    let mut i: usize = 0;
+    /// Longest list kept as a listpack: an entry count, or -1 to -5 for 4KB to 64KB
+    /// [default: -2]
+    #[arg(long, allow_hyphen_values = true)]
+    list_max_listpack_size: Option<i64>,
 
-    const CLEAR_TOKEN_ITERATIONS: usize = 1000;
+    /// Keyspace event classes to publish, as in Redis' notify-keyspace-events (e.g. "KEA")
+    #[arg(long)]
+    notify_keyspace_events: Option<String>,
 
-    loop {
-        i += 1;
+    /// Password clients must AUTH with before running any other command
+    #[arg(long)]
+    requirepass: Option<String>,
 
-        if i >= CLEAR_TOKEN_ITERATIONS {
-            let is_expired = |val: &DBData| {
-                val.exp()
-                    .map(|ms| val.created_at().elapsed() >= Duration::from_millis(ms))
-                    .unwrap_or(false)
-            };
+    /// Space-separated addresses to listen on, where * is every IPv4 interface, ::* every IPv6
+    /// one, and a leading - marks an address as optional [default: "* -::*"]
+    #[arg(long, allow_hyphen_values = true)]
+    bind: Option<String>,
 
-            let mut db_temp = db.write().await;
-            db_temp.retain(|_, val| !is_expired(val));
+    /// Port to listen on [default: 6379]
+    #[arg(long)]
+    port: Option<u16>,
 
-            i = 0;
-        }
+    /// Port to take TLS connections on as well, or 0 for none. With one set, a port of 0
+    /// turns off plaintext connections [default: 0]
+    #[arg(long)]
+    tls_port: Option<u16>,
 
-        let value = handler.read().await.unwrap_or_else(|e| {
-            eprintln!("Failed to read token: {e}");
-            Some(Value::Array(vec![
-                Value::BulkString("ECHO".to_string()),
-                Value::BulkString(format!("(error) Failed to read token: {e}")),
-            ]))
-        });
-
-        println!("Got Value: {value:?}");
-
-        let response = if let Some(v) = value {
-            let (command, args) = extract_command(v).unwrap_or_else(|e| {
-                eprintln!("Error extracting commands: {e}");
-                (
-                    "ECHO".to_string(),
-                    vec![Value::BulkString(format!(
-                        "(error) Error extracting commands: {e}"
-                    ))],
-                )
-            });
-            match command.to_lowercase().as_str() {
-                "ping" => Value::SimpleString("PONG".to_string()),
-                "echo" => args
-                    .first()
-                    .unwrap_or(&Value::BulkString(
-                        "You did not provide an argument to ECHO back".to_string(),
-                    ))
-                    .clone(),
-                "set" => {
-                    let ret = if args.len() == 2 {
-                        if let (Value::BulkString(key), value) = (&args[0], &args[1]) {
-                            let mut db_temp = db.write().await;
-                            db_temp.insert(
-                                key.to_string(),
-                                DBData::new(determine_type(value).unwrap(), Instant::now(), None),
-                            );
-                        }
-                        Value::SimpleString("OK".to_string())
-                    } else if args.len() == 4 {
-                        if let (
-                            Value::BulkString(key),
-                            value,
-                            Value::BulkString(exp_type),
-                            Value::BulkString(exp_time),
-                        ) = (&args[0], &args[1], &args[2], &args[3])
-                        {
-                            let exp_time = exp_time.parse::<u64>().unwrap_or_default();
-                            let expire_time = match exp_type.to_lowercase().as_str() {
-                                "ex" => exp_time * 1000,
-                                "px" => exp_time,
-                                _ => 0,
-                            };
-
-                            let mut db_temp = db.write().await;
-                            db_temp.insert(
-                                key.to_string(),
-                                DBData::new(
-                                    determine_type(value).unwrap(),
-                                    Instant::now(),
-                                    Some(expire_time),
-                                ),
-                            );
-                        }
-                        Value::SimpleString("OK".to_string())
-                    } else {
-                        Value::BulkString("(error) Invalid arguments for: SET".to_string())
-                    };
-
-                    ret
-                }
-                "get" => {
-                    if args.len() != 1 {
-                        Value::BulkString("(error) Invalid arguments for GET".to_string())
-                    } else {
-                        let ret: Value = if let Some(Value::BulkString(key)) = args.get(0) {
-                            let mut db = db.write().await;
-
-                            match db.get(key) {
-                                None => Value::BulkString("-1".to_string()),
-                                Some(val) => {
-                                    let expired = val
-                                        .exp()
-                                        .map(|ms| {
-                                            val.created_at().elapsed() >= Duration::from_millis(ms)
-                                        })
-                                        .unwrap_or(false);
-
-                                    if expired {
-                                        db.remove(key);
-                                        Value::BulkString("-1".to_string())
-                                    } else {
-                                        match val.data() {
-                                            DBVal::Int(n) => Value::BulkString(n.to_string()),
-                                            DBVal::String(s) => Value::BulkString(s.clone()),
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            Value::BulkString("-1".to_string())
-                        };
-
-                        ret
-                    }
-                }
-                c => Value::BulkString(format!("(error) Invalid command: {}", c)),
-            }
-        } else {
-            break;
-        };
+    /// PEM file with the certificate chain TLS connections are served with
+    #[arg(long, value_name = "PATH")]
+    tls_cert_file: Option<String>,
 
-        println!("Sending value {:?}", response);
+    /// PEM file with the private key for the TLS certificate
+    #[arg(long, value_name = "PATH")]
+    tls_key_file: Option<String>,
 
-        handler.write(response).await.expect("Failed to write")
-    }
+    /// PEM file with the CA certificates client certificates are checked against
+    #[arg(long, value_name = "PATH")]
+    tls_ca_cert_file: Option<String>,
+
+    /// Whether TLS clients must present a certificate [default: yes]
+    #[arg(long, value_name = "yes|no|optional")]
+    tls_auth_clients: Option<String>,
+
+    /// What drives connections' I/O: tokio, or io-uring when built with the io-uring feature
+    /// [default: tokio]
+    #[arg(long, value_name = "tokio|io-uring")]
+    io_backend: Option<String>,
+
+    /// Threads to serve connections on: 1 runs everything on a single thread, for
+    /// deterministic benchmarks, and 0 uses one per CPU core [default: 0]
+    #[arg(long)]
+    io_threads: Option<usize>,
+
+    /// A port to serve Prometheus metrics over HTTP on, at /metrics [default: none]
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// A port to serve a read-only JSON admin API over HTTP on, with /info, /clients,
+    /// /slowlog, /config and /keys/NAME [default: none]
+    #[arg(long)]
+    admin_port: Option<u16>,
+
+    /// A Unix socket to listen on as well as the TCP port
+    #[arg(long, value_name = "PATH")]
+    unixsocket: Option<String>,
+
+    /// Permissions for the Unix socket, in octal like 700 [default: left to the umask]
+    #[arg(long, value_name = "MODE")]
+    unixsocketperm: Option<String>,
+
+    /// Run in the background, detached from the terminal [default: no]
+    #[arg(long, value_name = "yes|no")]
+    daemonize: Option<String>,
+
+    /// A file to write the process ID to [default: /var/run/redis.pid when daemonized]
+    #[arg(long, value_name = "PATH")]
+    pidfile: Option<String>,
+
+    /// Tell systemd when the server's ready and when it's stopping, for units of Type=notify
+    /// [default: no]
+    #[arg(long, value_name = "no|systemd|auto")]
+    supervised: Option<String>,
+
+    /// How much to log: debug, verbose, notice, warning or nothing [default: notice]
+    #[arg(long, value_name = "LEVEL")]
+    loglevel: Option<String>,
+
+    /// A file to append the log to, in place of standard output
+    #[arg(long, value_name = "PATH")]
+    logfile: Option<String>,
+
+    /// An OpenTelemetry collector to export a span for each command to, over OTLP/HTTP, like
+    /// http://localhost:4318/v1/traces [default: none]
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// A file to record every write command in, with its client, user and keys, one JSON
+    /// object a line [default: none]
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<String>,
+
+    /// A stream key to record every write command in as well [default: none]
+    #[arg(long, value_name = "KEY")]
+    audit_stream: Option<String>,
+
+    /// A file to record every command clients send in, with when they sent it, for `replay`
+    /// [default: none]
+    #[arg(long = "record", value_name = "PATH")]
+    record_file: Option<String>,
+
+    /// Another server, like a real Redis, to send every command on to as well, as HOST:PORT,
+    /// logging any reply of its that differs [default: none]
+    #[arg(long, value_name = "HOST:PORT")]
+    shadow_target: Option<String>,
+
+    /// Space-separated WebAssembly modules to load, adding the commands they register, when
+    /// built with the wasm feature [default: none]
+    #[arg(long, value_name = "PATHS")]
+    wasm_modules: Option<String>,
+
+    /// Refuse connections from other hosts while there's no password and no bind address
+    /// [default: yes]
+    #[arg(long, value_name = "yes|no")]
+    protected_mode: Option<String>,
+
+    /// Run as a node of a cluster, serving the hash slots assigned to it [default: no]
+    #[arg(long, value_name = "yes|no")]
+    cluster_enabled: Option<String>,
+
+    /// Number of databases SELECT can choose from [default: 16]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    databases: Option<u32>,
+
+    /// Renames a command, or disables it if the new name is "". May be given more than once
+    #[arg(long, num_args = 2, value_names = ["COMMAND", "NEW_NAME"], allow_hyphen_values = true)]
+    rename_command: Vec<String>,
 }
 
-fn determine_type(value: &Value) -> anyhow::Result<DBVal> {
-    match value {
-        Value::BulkString(s) => {
-            if let Ok(num) = s.parse::<i64>() {
-                Ok(DBVal::Int(num))
-            } else {
-                Ok(DBVal::String(s.clone()))
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Connect to a server and run commands at a prompt, like redis-cli
+    Cli(cli::Options),
+    /// Measure a server's throughput and latency, like redis-benchmark
+    Bench(bench::Options),
+    /// Write every key on a server out to a JSON file
+    Export(backup::ExportOptions),
+    /// Load the keys in a file `export` wrote into a server
+    Import(backup::ImportOptions),
+    /// Send the commands a server recorded with --record to a server again
+    Replay(replay::Options),
+    /// Check a snapshot file, like redis-check-rdb
+    CheckRdb {
+        /// The RDB file to check
+        file: PathBuf,
+    },
+    /// Check an append-only file, like redis-check-aof
+    CheckAof {
+        /// Cut off a command left unfinished at the end of the file
+        #[arg(long)]
+        fix: bool,
+
+        /// The append-only file to check
+        file: PathBuf,
+    },
+}
+
+impl Args {
+    /// The settings to start with: the config file's if there is one, with any options given
+    /// on the command line on top. Also returns the config file with what was read from it.
+    fn server_config(self) -> anyhow::Result<(ServerConfig, Option<(PathBuf, ServerConfig)>)> {
+        let file = match self.config_file.or(self.config) {
+            Some(path) => {
+                let loaded = ServerConfig::load(&path)
+                    .map_err(|e| anyhow::anyhow!("*** FATAL CONFIG FILE ERROR ***\n{e}"))?;
+                Some((path, loaded))
+            }
+            None => None,
+        };
+        let mut config = file
+            .as_ref()
+            .map_or_else(ServerConfig::default, |(_, loaded)| loaded.clone());
+
+        let options = [
+            ("bind", self.bind),
+            ("port", self.port.map(|v| v.to_string())),
+            ("tls-port", self.tls_port.map(|v| v.to_string())),
+            ("tls-cert-file", self.tls_cert_file),
+            ("tls-key-file", self.tls_key_file),
+            ("tls-ca-cert-file", self.tls_ca_cert_file),
+            ("tls-auth-clients", self.tls_auth_clients),
+            ("io-backend", self.io_backend),
+            ("io-threads", self.io_threads.map(|v| v.to_string())),
+            ("metrics-port", self.metrics_port.map(|v| v.to_string())),
+            ("admin-port", self.admin_port.map(|v| v.to_string())),
+            ("unixsocket", self.unixsocket),
+            ("unixsocketperm", self.unixsocketperm),
+            ("daemonize", self.daemonize),
+            ("pidfile", self.pidfile),
+            ("supervised", self.supervised),
+            ("loglevel", self.loglevel),
+            ("logfile", self.logfile),
+            ("otlp-endpoint", self.otlp_endpoint),
+            ("audit-log", self.audit_log),
+            ("audit-stream", self.audit_stream),
+            ("record-file", self.record_file),
+            ("shadow-target", self.shadow_target),
+            ("wasm-modules", self.wasm_modules),
+            ("protected-mode", self.protected_mode),
+            ("cluster-enabled", self.cluster_enabled),
+            (
+                "hash-max-listpack-entries",
+                self.hash_max_listpack_entries.map(|v| v.to_string()),
+            ),
+            (
+                "hash-max-listpack-value",
+                self.hash_max_listpack_value.map(|v| v.to_string()),
+            ),
+            (
+                "set-max-intset-entries",
+                self.set_max_intset_entries.map(|v| v.to_string()),
+            ),
+            (
+                "set-max-listpack-entries",
+                self.set_max_listpack_entries.map(|v| v.to_string()),
+            ),
+            (
+                "set-max-listpack-value",
+                self.set_max_listpack_value.map(|v| v.to_string()),
+            ),
+            (
+                "zset-max-listpack-entries",
+                self.zset_max_listpack_entries.map(|v| v.to_string()),
+            ),
+            (
+                "zset-max-listpack-value",
+                self.zset_max_listpack_value.map(|v| v.to_string()),
+            ),
+            (
+                "list-max-listpack-size",
+                self.list_max_listpack_size.map(|v| v.to_string()),
+            ),
+            ("notify-keyspace-events", self.notify_keyspace_events),
+            ("requirepass", self.requirepass),
+            ("databases", self.databases.map(|v| v.to_string())),
+        ];
+        for (name, value) in options {
+            if let Some(value) = value {
+                config
+                    .set(name, &value)
+                    .map_err(|e| anyhow::anyhow!("invalid {name} value '{value}': {e}"))?;
             }
         }
-        _ => Err(anyhow::anyhow!("Expected input to be a bulk string")),
+        for rule in self.rename_command.chunks(2) {
+            config
+                .rename_command(&rule[0], &rule[1])
+                .map_err(|e| anyhow::anyhow!("invalid rename-command '{}': {e}", rule[0]))?;
+        }
+
+        Ok((config, file))
     }
 }
 
-fn extract_command(value: Value) -> anyhow::Result<(String, Vec<Value>)> {
-    match value {
-        Value::Array(a) => Ok((
-            unpack_bulk_str(
-                a.first()
-                    .unwrap_or(&Value::BulkString(
-                        "Received non-bulk-string input or empty input".to_string(),
-                    ))
-                    .clone(),
-            )?,
-            a.into_iter().skip(1).collect(),
-        )),
-        _ => Err(anyhow::anyhow!("Unexpected command format")),
+fn main() -> anyhow::Result<()> {
+    let mut args = Args::parse();
+    match args.command.take() {
+        Some(Command::Cli(options)) => return cli::run(options),
+        Some(Command::Bench(options)) => return bench::run(options),
+        Some(Command::Export(options)) => return backup::export(options),
+        Some(Command::Import(options)) => return backup::import(options),
+        Some(Command::Replay(options)) => return replay::run(options),
+        Some(Command::CheckRdb { file }) => return check::rdb(&file),
+        Some(Command::CheckAof { fix, file }) => return check::aof(&file, fix),
+        None => {}
     }
-}
+    let (config, config_file) = args.server_config()?;
 
-fn unpack_bulk_str(value: Value) -> anyhow::Result<String> {
-    match value {
-        Value::BulkString(s) => Ok(s),
-        _ => Err(anyhow::anyhow!("Expected command to be a bulk string")),
+    let mut server = Server::builder().config(config);
+    if let Some((path, loaded)) = config_file {
+        server = server.config_file(path, loaded);
     }
+    server.start()
 }
@@ -0,0 +1,385 @@
+//! memcached's text protocol, on a port of its own, so that memcached clients can use the
+//! server as they would memcached. Its commands are translated onto Redis commands against
+//! database 0 and run as any other connection's would be, so that they're checked against the
+//! ACL, propagated and notified of the same way.
+//!
+//! Items are plain strings: the flags a client stores with one aren't kept, and come back as 0.
+//! There's no CAS, so `gets` and `cas` aren't understood.
+
+use crate::blocking::BlockedClients;
+use crate::client::Client;
+use crate::db::{Db, Keyspace};
+use crate::pubsub::PubSub;
+use crate::resp::Value;
+use crate::server;
+use bytes::{Bytes, BytesMut};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The longest key memcached takes.
+const MAX_KEY: usize = 250;
+
+/// The longest command line read before giving up on the client.
+const MAX_LINE: usize = 2048;
+
+/// The largest value a storage command takes, memcached's default item size limit.
+const MAX_VALUE: usize = 1024 * 1024;
+
+/// Exptimes up to this many seconds are relative to now, and any later ones Unix times.
+const RELATIVE_EXPTIME: i64 = 30 * 24 * 60 * 60;
+
+const BAD_FORMAT: &str = "CLIENT_ERROR bad command line format";
+
+/// What's shared by the connections on a listener.
+#[derive(Clone)]
+struct State {
+    db: Db,
+    pubsub: Arc<PubSub>,
+    blocked: Arc<BlockedClients>,
+}
+
+/// Takes memcached connections on `listener`. Runs for as long as the server does.
+pub async fn serve(
+    listener: TcpListener,
+    db: Db,
+    pubsub: Arc<PubSub>,
+    blocked: Arc<BlockedClients>,
+) {
+    let state = State {
+        db,
+        pubsub,
+        blocked,
+    };
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(Session::serve(stream, state.clone()));
+    }
+}
+
+/// One memcached connection, with the client its commands run as.
+struct Session {
+    stream: TcpStream,
+    buf: BytesMut,
+    client: Client,
+    state: State,
+}
+
+impl Session {
+    async fn serve(stream: TcpStream, state: State) {
+        let (Ok(addr), Ok(laddr)) = (stream.peer_addr(), stream.local_addr()) else {
+            return;
+        };
+        let client = Client::new(state.pubsub.clone(), addr, laddr);
+        let mut killed = client.kill_signal();
+        let mut session = Session {
+            stream,
+            buf: BytesMut::with_capacity(1024),
+            client,
+            state,
+        };
+
+        loop {
+            let line = tokio::select! {
+                line = session.read_line() => line,
+                _ = killed.killed() => break,
+            };
+            let Some(line) = line else {
+                break;
+            };
+            let words: Vec<&[u8]> = line
+                .split(u8::is_ascii_whitespace)
+                .filter(|w| !w.is_empty())
+                .collect();
+            if words.first() == Some(&&b"quit"[..]) {
+                break;
+            }
+            let Some(reply) = session.command(&words).await else {
+                continue;
+            };
+            if session.stream.write_all(&reply).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Runs the command on a line, reading its data block if it has one. Returns the reply, or
+    /// `None` if `noreply` asked for none.
+    async fn command(&mut self, words: &[&[u8]]) -> Option<Vec<u8>> {
+        let (name, args) = words.split_first()?;
+        let noreply = args.last() == Some(&&b"noreply"[..]);
+        let reply = match *name {
+            b"get" => return Some(self.get(args).await),
+            b"set" | b"add" | b"replace" | b"append" | b"prepend" => self.store(name, args).await,
+            b"delete" => self.delete(args).await,
+            b"incr" | b"decr" => self.incr(name == b"incr", args).await,
+            b"touch" => self.touch(args).await,
+            b"flush_all" => self.flush_all(args).await,
+            b"version" => format!("VERSION {}", env!("CARGO_PKG_VERSION")),
+            b"verbosity" => "OK".to_string(),
+            _ => "ERROR".to_string(),
+        };
+        // Storage commands' formats are checked before `noreply` is, as memcached does
+        (!noreply || reply.starts_with("CLIENT_ERROR")).then(|| format!("{reply}\r\n").into_bytes())
+    }
+
+    async fn get(&mut self, keys: &[&[u8]]) -> Vec<u8> {
+        if keys.is_empty() || keys.iter().any(|key| !valid_key(key)) {
+            return format!("{BAD_FORMAT}\r\n").into_bytes();
+        }
+        let mut dbs = self.state.db.lock_keys(keys).await;
+        let mut reply = Vec::new();
+        for key in keys {
+            let value = match item(self.call(&mut dbs, "get", &[key.to_vec()])) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(e) => return format!("{e}\r\n").into_bytes(),
+            };
+            let header = format!(
+                "VALUE {} 0 {}\r\n",
+                String::from_utf8_lossy(key),
+                value.len()
+            );
+            reply.extend_from_slice(header.as_bytes());
+            reply.extend_from_slice(&value);
+            reply.extend_from_slice(b"\r\n");
+        }
+        reply.extend_from_slice(b"END\r\n");
+        reply
+    }
+
+    /// `set`, `add`, `replace`, `append` and `prepend`: `<key> <flags> <exptime> <bytes>`,
+    /// then the value in a data block.
+    async fn store(&mut self, name: &[u8], args: &[&[u8]]) -> String {
+        let parsed = match args {
+            [key, flags, exptime, bytes, ..] if valid_key(key) => {
+                number::<u32>(flags).and(number::<i64>(exptime).zip(number::<usize>(bytes)))
+            }
+            _ => None,
+        };
+        let Some((exptime, len)) = parsed else {
+            return BAD_FORMAT.to_string();
+        };
+        if len > MAX_VALUE {
+            // The data block that follows is skipped over, as it won't be stored
+            self.skip(len + 2).await;
+            return "SERVER_ERROR object too large for cache".to_string();
+        }
+        let Some(data) = self.read_block(len).await else {
+            return "CLIENT_ERROR bad data chunk".to_string();
+        };
+
+        let key = args[0].to_vec();
+        let mut dbs = self.state.db.lock_keys(&[&key]).await;
+        let mut set = vec![key.clone(), data];
+        match name {
+            b"append" | b"prepend" => {
+                let old = match item(self.call(&mut dbs, "get", &[key])) {
+                    Ok(Some(old)) => old,
+                    Ok(None) => return "NOT_STORED".to_string(),
+                    Err(e) => return e,
+                };
+                // They change only the value, keeping whatever TTL the item has
+                set[1] = match name {
+                    b"append" => [&old[..], &set[1]].concat(),
+                    _ => [&set[1][..], &old[..]].concat(),
+                };
+                set.push(b"KEEPTTL".to_vec());
+            }
+            _ => {
+                set.extend(expiry(exptime));
+                match name {
+                    b"add" => set.push(b"NX".to_vec()),
+                    b"replace" => set.push(b"XX".to_vec()),
+                    _ => {}
+                }
+            }
+        }
+
+        match self.call(&mut dbs, "set", &set) {
+            Value::SimpleString(_) => "STORED".to_string(),
+            Value::Null => "NOT_STORED".to_string(),
+            reply => server_error(&reply),
+        }
+    }
+
+    /// `delete <key>`, with a time of 0 allowed after it, as old clients send.
+    async fn delete(&mut self, args: &[&[u8]]) -> String {
+        let key = match args {
+            [key] | [key, b"noreply"] | [key, b"0"] | [key, b"0", b"noreply"] if valid_key(key) => {
+                key.to_vec()
+            }
+            _ => return BAD_FORMAT.to_string(),
+        };
+        let mut dbs = self.state.db.lock_keys(&[&key]).await;
+        match self.call(&mut dbs, "del", &[key]) {
+            Value::Integer(1) => "DELETED".to_string(),
+            Value::Integer(_) => "NOT_FOUND".to_string(),
+            reply => server_error(&reply),
+        }
+    }
+
+    /// `incr` and `decr <key> <delta>`, on values that are unsigned 64-bit decimals. `incr`
+    /// wraps around, and `decr` stops at 0.
+    async fn incr(&mut self, incr: bool, args: &[&[u8]]) -> String {
+        let (key, delta) = match args {
+            [key, delta, ..] if valid_key(key) => (key.to_vec(), delta),
+            _ => return BAD_FORMAT.to_string(),
+        };
+        let Some(delta) = number::<u64>(delta) else {
+            return "CLIENT_ERROR invalid numeric delta argument".to_string();
+        };
+
+        let mut dbs = self.state.db.lock_keys(&[&key]).await;
+        let value = match item(self.call(&mut dbs, "get", std::slice::from_ref(&key))) {
+            Ok(Some(value)) => value,
+            Ok(None) => return "NOT_FOUND".to_string(),
+            Err(e) => return e,
+        };
+        let Some(value) = number::<u64>(&value) else {
+            return "CLIENT_ERROR cannot increment or decrement non-numeric value".to_string();
+        };
+        let value = if incr {
+            value.wrapping_add(delta)
+        } else {
+            value.saturating_sub(delta)
+        };
+
+        let set = [key, value.to_string().into_bytes(), b"KEEPTTL".to_vec()];
+        match self.call(&mut dbs, "set", &set) {
+            Value::SimpleString(_) => value.to_string(),
+            reply => server_error(&reply),
+        }
+    }
+
+    /// `touch <key> <exptime>`: a new TTL, without reading the item.
+    async fn touch(&mut self, args: &[&[u8]]) -> String {
+        let (key, exptime) = match args {
+            [key, exptime, ..] if valid_key(key) => (key.to_vec(), exptime),
+            _ => return BAD_FORMAT.to_string(),
+        };
+        let Some(exptime) = number::<i64>(exptime) else {
+            return BAD_FORMAT.to_string();
+        };
+
+        let mut getex = vec![key];
+        getex.extend(match exptime {
+            0 => vec![b"PERSIST".to_vec()],
+            exptime => expiry(exptime),
+        });
+        let mut dbs = self.state.db.lock_keys(&[&getex[0]]).await;
+        match item(self.call(&mut dbs, "getex", &getex)) {
+            Ok(Some(_)) => "TOUCHED".to_string(),
+            Ok(None) => "NOT_FOUND".to_string(),
+            Err(e) => e,
+        }
+    }
+
+    /// `flush_all`, which empties database 0 at once. A delay isn't supported.
+    async fn flush_all(&mut self, args: &[&[u8]]) -> String {
+        match args {
+            [] | [b"noreply"] | [b"0"] | [b"0", b"noreply"] => {}
+            _ => return "CLIENT_ERROR delayed flush_all is not supported".to_string(),
+        }
+        let mut dbs = self.state.db.lock_all().await;
+        match self.call(&mut dbs, "flushdb", &[]) {
+            Value::SimpleString(_) => "OK".to_string(),
+            reply => server_error(&reply),
+        }
+    }
+
+    /// Runs a command translated from memcached's as the connection's client.
+    fn call(&mut self, dbs: &mut [Keyspace], name: &str, args: &[Vec<u8>]) -> Value {
+        let state = &self.state;
+        server::execute_checked(
+            &mut self.client,
+            &state.pubsub,
+            &state.blocked,
+            dbs,
+            name,
+            args,
+        )
+    }
+
+    /// The next line, without its line ending, or `None` once the client's gone or sent a
+    /// line too long to be a command.
+    async fn read_line(&mut self) -> Option<Vec<u8>> {
+        loop {
+            if let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+                let line = self.buf.split_to(end + 1);
+                let line = line.strip_suffix(b"\n")?;
+                return Some(line.strip_suffix(b"\r").unwrap_or(line).to_vec());
+            }
+            if self.buf.len() > MAX_LINE {
+                let _ = self
+                    .stream
+                    .write_all(b"CLIENT_ERROR line too long\r\n")
+                    .await;
+                return None;
+            }
+            if self.stream.read_buf(&mut self.buf).await.ok()? == 0 {
+                return None;
+            }
+        }
+    }
+
+    /// A data block of `len` bytes and the `\r\n` after it, or `None` if it doesn't end there.
+    async fn read_block(&mut self, len: usize) -> Option<Vec<u8>> {
+        while self.buf.len() < len + 2 {
+            if self.stream.read_buf(&mut self.buf).await.ok()? == 0 {
+                return None;
+            }
+        }
+        let block = self.buf.split_to(len + 2);
+        block.ends_with(b"\r\n").then(|| block[..len].to_vec())
+    }
+
+    /// Reads past `len` bytes without keeping them.
+    async fn skip(&mut self, mut len: usize) {
+        loop {
+            let skipped = len.min(self.buf.len());
+            let _ = self.buf.split_to(skipped);
+            len -= skipped;
+            if len == 0 || !matches!(self.stream.read_buf(&mut self.buf).await, Ok(1..)) {
+                return;
+            }
+        }
+    }
+}
+
+fn valid_key(key: &[u8]) -> bool {
+    key.len() <= MAX_KEY && !key.iter().any(u8::is_ascii_control)
+}
+
+fn number<T: std::str::FromStr>(word: &[u8]) -> Option<T> {
+    std::str::from_utf8(word).ok()?.parse().ok()
+}
+
+/// SET's arguments for a memcached exptime: none for 0, seconds from now for up to 30 days,
+/// and a Unix time past that. A negative one has already passed, which SET takes as a delete.
+fn expiry(exptime: i64) -> Vec<Vec<u8>> {
+    match exptime {
+        0 => Vec::new(),
+        ..0 => vec![b"EXAT".to_vec(), b"1".to_vec()],
+        1..=RELATIVE_EXPTIME => vec![b"EX".to_vec(), exptime.to_string().into_bytes()],
+        _ => vec![b"EXAT".to_vec(), exptime.to_string().into_bytes()],
+    }
+}
+
+/// A reply to GET or GETEX as memcached sees it: the item's value, or `None` if there's no
+/// item, as there isn't where the key holds something other than a string.
+fn item(reply: Value) -> Result<Option<Bytes>, String> {
+    match (reply.error_message(), reply) {
+        (Some(e), _) if e.starts_with("WRONGTYPE") => Ok(None),
+        (Some(_), reply) => Err(server_error(&reply)),
+        (None, Value::BulkString(value)) => Ok(Some(value)),
+        _ => Ok(None),
+    }
+}
+
+fn server_error(reply: &Value) -> String {
+    let message = reply.error_message().unwrap_or_default();
+    format!("SERVER_ERROR {message}")
+}
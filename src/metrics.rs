@@ -0,0 +1,222 @@
+use crate::client;
+use crate::config;
+use crate::db::{self, DBData, Db, Keyspace};
+use crate::http;
+use crate::replication::{self, Link};
+use crate::stats;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Answers Prometheus' scrapes of `/metrics` on `listener`. Runs for as long as the server does.
+pub async fn serve(listener: TcpListener, db: Db) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(answer(stream, db.clone()));
+    }
+}
+
+async fn answer(mut stream: TcpStream, db: Db) {
+    let Some(request) = http::read_request(&mut stream).await else {
+        return;
+    };
+
+    let (status, body) = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => ("200 OK", render(&db.freeze().await)),
+        (_, "/metrics") => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    http::respond(&mut stream, status, "text/plain; version=0.0.4", &body).await;
+}
+
+/// The server's metrics in Prometheus' text format.
+fn render(dbs: &[Keyspace]) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+        writeln!(text, "# HELP redis_{name} {help}").unwrap();
+        writeln!(text, "# TYPE redis_{name} {kind}").unwrap();
+        for (labels, value) in samples {
+            writeln!(text, "redis_{name}{labels} {value}").unwrap();
+        }
+    };
+    let counter =
+        |counter: &AtomicU64| vec![(String::new(), counter.load(Ordering::Relaxed) as f64)];
+    let value = |value: f64| vec![(String::new(), value)];
+
+    metric(
+        "uptime_in_seconds",
+        "gauge",
+        "Seconds since the server started.",
+        &value(stats::STARTED.elapsed().as_secs() as f64),
+    );
+    metric(
+        "connected_clients",
+        "gauge",
+        "Client connections open.",
+        &value(client::counts().0 as f64),
+    );
+    metric(
+        "connections_received_total",
+        "counter",
+        "Connections accepted.",
+        &counter(&stats::CONNECTIONS_RECEIVED),
+    );
+    metric(
+        "rejected_connections_total",
+        "counter",
+        "Connections turned away for maxclients.",
+        &counter(&stats::REJECTED_CONNECTIONS),
+    );
+    metric(
+        "commands_processed_total",
+        "counter",
+        "Commands run.",
+        &counter(&stats::COMMANDS_PROCESSED),
+    );
+
+    let commands = stats::commands();
+    let per_command = |field: fn(&stats::CommandStats) -> f64| {
+        commands
+            .iter()
+            .map(|(name, stats)| (format!("{{cmd=\"{name}\"}}"), field(stats)))
+            .collect::<Vec<_>>()
+    };
+    metric(
+        "commands_total",
+        "counter",
+        "Calls to each command.",
+        &per_command(|stats| stats.calls as f64),
+    );
+    metric(
+        "commands_failed_calls_total",
+        "counter",
+        "Calls to each command that replied with an error.",
+        &per_command(|stats| stats.failed_calls as f64),
+    );
+    metric(
+        "commands_duration_seconds_total",
+        "counter",
+        "Time spent running each command.",
+        &per_command(|stats| stats.usec as f64 / 1e6),
+    );
+
+    metric(
+        "keyspace_hits_total",
+        "counter",
+        "Lookups by reads that found their key.",
+        &counter(&stats::KEYSPACE_HITS),
+    );
+    metric(
+        "keyspace_misses_total",
+        "counter",
+        "Lookups by reads that didn't find their key.",
+        &counter(&stats::KEYSPACE_MISSES),
+    );
+    metric(
+        "expired_keys_total",
+        "counter",
+        "Keys deleted for having expired.",
+        &counter(&stats::EXPIRED_KEYS),
+    );
+    metric(
+        "evicted_keys_total",
+        "counter",
+        "Keys evicted for maxmemory.",
+        &counter(&stats::EVICTED_KEYS),
+    );
+
+    let databases: Vec<_> = dbs
+        .iter()
+        .enumerate()
+        .filter(|(_, db)| !db.is_empty())
+        .collect();
+    let per_db = |count: fn(&Keyspace) -> usize| {
+        databases
+            .iter()
+            .map(|(index, db)| (format!("{{db=\"db{index}\"}}"), count(db) as f64))
+            .collect::<Vec<_>>()
+    };
+    metric(
+        "db_keys",
+        "gauge",
+        "Keys in each database.",
+        &per_db(Keyspace::len),
+    );
+    metric(
+        "db_keys_expiring",
+        "gauge",
+        "Keys with a TTL in each database.",
+        &per_db(|db| db.values().filter(|val| DBData::ttl(val).is_some()).count()),
+    );
+
+    metric(
+        "memory_used_bytes",
+        "gauge",
+        "Memory the dataset is estimated to take up.",
+        &value(db::used_memory() as f64),
+    );
+    metric(
+        "memory_max_bytes",
+        "gauge",
+        "The maxmemory setting, or 0 for no limit.",
+        &value(config::get().maxmemory as f64),
+    );
+
+    metric(
+        "master_repl_offset",
+        "gauge",
+        "The replication offset.",
+        &value(replication::offset() as f64),
+    );
+    let replicas = replication::replicas();
+    metric(
+        "connected_slaves",
+        "gauge",
+        "Replicas connected to this server.",
+        &value(replicas.len() as f64),
+    );
+    let per_replica = |field: fn(&replication::ReplicaInfo) -> f64| {
+        replicas
+            .iter()
+            .map(|replica| {
+                let labels = format!(
+                    "{{slave_ip=\"{}\",slave_port=\"{}\"}}",
+                    replica.addr.ip().to_canonical(),
+                    replica.port
+                );
+                (labels, field(replica))
+            })
+            .collect::<Vec<_>>()
+    };
+    metric(
+        "connected_slave_lag_seconds",
+        "gauge",
+        "Seconds since each replica last acknowledged.",
+        &per_replica(|replica| replica.lag as f64),
+    );
+    metric(
+        "connected_slave_offset_bytes",
+        "gauge",
+        "The replication offset each replica last acknowledged.",
+        &per_replica(|replica| replica.offset as f64),
+    );
+    if replication::master().is_some() {
+        let (link, last_io) = replication::link();
+        metric(
+            "master_link_up",
+            "gauge",
+            "Whether the link with the master is up.",
+            &value((link == Link::Up) as u8 as f64),
+        );
+        metric(
+            "master_last_io_seconds_ago",
+            "gauge",
+            "Seconds since the master was last heard from, or -1 if it hasn't been.",
+            &value(last_io.map_or(-1.0, |secs| secs as f64)),
+        );
+    }
+
+    text
+}
@@ -0,0 +1,112 @@
+//! Namespaces, which confine an ACL user to keys of its own so that applications sharing one
+//! server can't see or touch each other's. A user given one with the `namespace:<name>` rule
+//! has every key it names stored as `<name>:<key>`: the prefix goes on the keys in its commands
+//! as they come in and comes off the keys their replies name, and the commands that go over
+//! the whole keyspace, like KEYS, SCAN, DBSIZE and FLUSHDB, only go over the keys under it.
+//!
+//! Everything past the connection sees keys as they're stored, so the append-only file,
+//! replicas, WATCH and notifications all get them with the prefix on.
+
+use crate::acl;
+use crate::cmd::{self, lower};
+use crate::resp::Value;
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+thread_local! {
+    /// The prefix of the namespace the running command is confined to, if any. Set like the
+    /// selected database, by whoever runs the command, on the thread it runs on.
+    static CURRENT: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// The prefix `user`'s keys are stored under, if it's confined to a namespace.
+pub fn prefix(user: Option<&str>) -> Option<Vec<u8>> {
+    user.and_then(acl::namespace)
+}
+
+pub fn set(prefix: Option<Vec<u8>>) {
+    CURRENT.set(prefix);
+}
+
+pub fn current() -> Option<Vec<u8>> {
+    CURRENT.with_borrow(Clone::clone)
+}
+
+/// Puts `prefix` on the keys in `args` to command `name`.
+pub fn qualify(prefix: &[u8], name: &str, args: &mut [Vec<u8>]) {
+    // A script's commands get the prefix as it runs them, so the keys it's handed don't
+    if matches!(name, "eval" | "evalsha" | "fcall" | "fcall_ro") {
+        return;
+    }
+
+    for at in cmd::key_positions(name, args) {
+        args[at].splice(..0, prefix.iter().copied());
+    }
+
+    // SORT's BY and GET patterns name keys of their own
+    if name == "sort" {
+        let mut i = 1;
+        while i + 1 < args.len() {
+            if matches!(lower(&args[i]).as_str(), "by" | "get") {
+                i += 1;
+                if args[i] != b"#" {
+                    args[i].splice(..0, prefix.iter().copied());
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+/// `args` with the prefix of `user`'s namespace on their keys, or as they are if it has none.
+pub fn qualified<'a>(user: Option<&str>, name: &str, args: &'a [Vec<u8>]) -> Cow<'a, [Vec<u8>]> {
+    match prefix(user) {
+        Some(prefix) => {
+            let mut args = args.to_vec();
+            qualify(&prefix, name, &mut args);
+            Cow::Owned(args)
+        }
+        None => Cow::Borrowed(args),
+    }
+}
+
+/// `reply` to command `name` with `prefix` taken back off the keys it names.
+pub fn strip(prefix: &[u8], name: &str, reply: Value) -> Value {
+    let unqualify = |item: &mut Value| {
+        if let Value::BulkString(key) = item
+            && key.starts_with(prefix)
+        {
+            *key = key.slice(prefix.len()..);
+        }
+    };
+
+    match (name, reply) {
+        // Led by the key they popped from
+        (
+            "blpop" | "brpop" | "bzpopmin" | "bzpopmax" | "zmpop" | "bzmpop",
+            Value::Array(mut items),
+        ) => {
+            if let Some(key) = items.first_mut() {
+                unqualify(key);
+            }
+            Value::Array(items)
+        }
+        // A `[key, entries]` pair per stream
+        ("xread" | "xreadgroup", Value::Array(mut streams)) => {
+            for stream in &mut streams {
+                if let Value::Array(stream) = stream
+                    && let Some(key) = stream.first_mut()
+                {
+                    unqualify(key);
+                }
+            }
+            Value::Array(streams)
+        }
+        // Errors that quote the key they were about
+        (_, Value::Error(message)) => {
+            let quoted = format!("key '{}", String::from_utf8_lossy(prefix));
+            Value::Error(message.replace(&quoted, "key '"))
+        }
+        (_, reply) => reply,
+    }
+}
@@ -0,0 +1,98 @@
+use crate::db;
+use crate::hooks;
+use crate::pubsub::PubSub;
+use crate::tracking;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Event classes, each enabled by its letter in `notify-keyspace-events`.
+#[derive(Clone, Copy)]
+pub enum Class {
+    Generic,
+    String,
+    List,
+    Set,
+    Hash,
+    ZSet,
+    Expired,
+    Stream,
+    Evicted,
+}
+
+/// Class letters in bit order. `n`, `m` and `d` are accepted for compatibility, though nothing
+/// here reports new keys, misses or module events yet.
+const CLASS_LETTERS: &[u8] = b"g$lshzxtenmd";
+
+/// What `A` stands for: every class except key misses, new keys and module events.
+const ALL_CLASSES: &[u8] = b"g$lshzxet";
+
+const KEYSPACE: u32 = 1 << 30;
+const KEYEVENT: u32 = 1 << 31;
+
+static FLAGS: AtomicU32 = AtomicU32::new(0);
+static PUBSUB: RwLock<Option<Arc<PubSub>>> = RwLock::new(None);
+
+fn class_bit(letter: u8) -> Option<u32> {
+    let pos = CLASS_LETTERS.iter().position(|l| *l == letter)?;
+
+    Some(1 << pos)
+}
+
+/// Parses a `notify-keyspace-events` value such as `KEA` or `Elg`, or `None` if it holds an
+/// unknown letter.
+pub fn parse_flags(spec: &str) -> Option<u32> {
+    spec.bytes().try_fold(0, |flags, letter| {
+        Some(
+            flags
+                | match letter {
+                    b'K' => KEYSPACE,
+                    b'E' => KEYEVENT,
+                    b'A' => ALL_CLASSES.iter().filter_map(|l| class_bit(*l)).sum(),
+                    letter => class_bit(letter)?,
+                },
+        )
+    })
+}
+
+/// Sets up notifications to go out through `pubsub`, the channels of the server last built.
+pub fn init(pubsub: Arc<PubSub>) {
+    *PUBSUB.write().unwrap() = Some(pubsub);
+}
+
+/// Chooses which events are published, as `flags` from [`parse_flags`].
+pub fn set_flags(flags: u32) {
+    FLAGS.store(flags, Ordering::Relaxed);
+}
+
+/// Publishes `event` on `key` to the `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>`
+/// channels for the selected database, as far as the configured flags allow. Call it once the
+/// change has been made.
+///
+/// Every event is a write, so this is also where keys get marked as modified for WATCH and
+/// invalidated for client-side caching, and where the embedding program's hooks hear of them.
+pub fn emit(class: Class, event: &str, key: &[u8]) {
+    db::signal_modified(key);
+    tracking::invalidate(key);
+    hooks::call(class, event, key);
+
+    let flags = FLAGS.load(Ordering::Relaxed);
+    let letter = CLASS_LETTERS[class as usize];
+
+    if flags & class_bit(letter).unwrap_or(0) == 0 {
+        return;
+    }
+    let Some(pubsub) = PUBSUB.read().unwrap().clone() else {
+        return;
+    };
+
+    let index = db::selected();
+    if flags & KEYSPACE != 0 {
+        let mut channel = format!("__keyspace@{index}__:").into_bytes();
+        channel.extend_from_slice(key);
+        pubsub.publish(&channel, event.as_bytes());
+    }
+    if flags & KEYEVENT != 0 {
+        let channel = format!("__keyevent@{index}__:{event}");
+        pubsub.publish(channel.as_bytes(), key);
+    }
+}
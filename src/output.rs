@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Which of the `client-output-buffer-limit` classes a connection's limits come from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Normal,
+    Replica,
+    PubSub,
+}
+
+impl Class {
+    pub const ALL: [Class; 3] = [Class::Normal, Class::Replica, Class::PubSub];
+
+    /// The class's name in `client-output-buffer-limit`, where replicas go by their old name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Normal => "normal",
+            Class::Replica => "slave",
+            Class::PubSub => "pubsub",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Class> {
+        match name.to_lowercase().as_str() {
+            "normal" => Some(Class::Normal),
+            "replica" | "slave" => Some(Class::Replica),
+            "pubsub" => Some(Class::PubSub),
+            _ => None,
+        }
+    }
+}
+
+/// How much may pile up for a connection: it's dropped past `hard` bytes, or once it's stayed
+/// past `soft` bytes for `soft_seconds`. A limit of 0 is no limit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Limit {
+    pub hard: u64,
+    pub soft: u64,
+    pub soft_seconds: u64,
+}
+
+/// Redis' defaults: nothing for normal clients, whose replies are only queued while they're
+/// written, and generous room for replicas catching up and subscribers falling behind.
+pub const DEFAULT_LIMITS: [Limit; 3] = [
+    Limit {
+        hard: 0,
+        soft: 0,
+        soft_seconds: 0,
+    },
+    Limit {
+        hard: 256 * 1024 * 1024,
+        soft: 64 * 1024 * 1024,
+        soft_seconds: 60,
+    },
+    Limit {
+        hard: 32 * 1024 * 1024,
+        soft: 8 * 1024 * 1024,
+        soft_seconds: 60,
+    },
+];
+
+/// The limits for each [`Class`], in [`Class::ALL`] order, mirroring
+/// `client-output-buffer-limit`.
+static LIMITS: RwLock<[Limit; 3]> = RwLock::new(DEFAULT_LIMITS);
+
+pub fn set_limits(limits: [Limit; 3]) {
+    *LIMITS.write().unwrap() = limits;
+}
+
+/// What's queued for one connection to write out and hasn't been taken off the queue yet,
+/// checked against its class's limits as it grows.
+pub struct Buffer {
+    class: AtomicU8,
+    /// Set by CLIENT NO-EVICT, which holds the connection to no limits.
+    exempt: AtomicBool,
+    bytes: AtomicU64,
+    /// When `bytes` last went over the soft limit, while it's still over.
+    over_soft_since: Mutex<Option<Instant>>,
+    /// Set once the buffer has gone past its limits, for good.
+    overflowed: watch::Sender<bool>,
+}
+
+impl Buffer {
+    pub fn new(class: Class) -> Self {
+        Self {
+            class: AtomicU8::new(class as u8),
+            exempt: AtomicBool::new(false),
+            bytes: AtomicU64::new(0),
+            over_soft_since: Mutex::new(None),
+            overflowed: watch::channel(false).0,
+        }
+    }
+
+    pub fn set_class(&self, class: Class) {
+        self.class.store(class as u8, Ordering::Relaxed);
+    }
+
+    pub fn set_exempt(&self, exempt: bool) {
+        self.exempt.store(exempt, Ordering::Relaxed);
+    }
+
+    fn class(&self) -> Class {
+        Class::ALL[self.class.load(Ordering::Relaxed) as usize]
+    }
+
+    pub fn len(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Counts `bytes` more as queued, returning whether they're within the limits. Once
+    /// they aren't, nothing more should be queued and the connection should be closed.
+    pub fn queue(&self, bytes: usize) -> bool {
+        if *self.overflowed.borrow() {
+            return false;
+        }
+        let len = self.bytes.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+        if self.exempt.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let limit = LIMITS.read().unwrap()[self.class() as usize];
+        let mut over_soft_since = self.over_soft_since.lock().unwrap();
+        let soft_expired = if limit.soft > 0 && len > limit.soft {
+            let since = *over_soft_since.get_or_insert_with(Instant::now);
+            since.elapsed() >= Duration::from_secs(limit.soft_seconds)
+        } else {
+            *over_soft_since = None;
+            false
+        };
+        if (limit.hard > 0 && len > limit.hard) || soft_expired {
+            self.overflowed.send_replace(true);
+            return false;
+        }
+
+        true
+    }
+
+    /// Counts `bytes` as taken off the queue to be written out.
+    pub fn dequeue(&self, bytes: usize) {
+        self.bytes.fetch_sub(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Resolves once the buffer has gone past its limits.
+    pub async fn overflowed(&self) {
+        let mut overflowed = self.overflowed.subscribe();
+        let _ = overflowed.wait_for(|overflowed| *overflowed).await;
+    }
+}
@@ -0,0 +1,258 @@
+//! Commands a program embedding the server adds to it, for its own domain, without touching the
+//! dispatcher: each is a [`CommandPlugin`] handed to [`Builder::command`](crate::Builder::command)
+//! and run against a [`Database`] much as the built-in commands run against theirs.
+
+use crate::blocking::Outcome;
+use crate::cmd::registry::{Command, Context};
+use crate::cmd::{self, wrong_type};
+use crate::db::{self, DBData, DBVal, Keyspace};
+use crate::resp::Value;
+use bytes::Bytes;
+use std::time::Duration;
+
+/// The flags a [`CommandPlugin`] may have, and the ACL category each puts it in.
+const FLAGS: &[(&str, &str)] = &[
+    ("write", "write"),
+    ("readonly", "read"),
+    ("fast", "fast"),
+    ("admin", "admin"),
+];
+
+/// A command of the embedding program's own. Clients call it like any other, in any case, and
+/// COMMAND, ACL rules, MULTI, scripts, replication and the AOF all see it as one.
+///
+/// Replicas and AOF loading run the writes it makes by calling it again, so a server reading
+/// them needs the same commands added.
+pub trait CommandPlugin: Send + Sync + 'static {
+    /// The name clients call it by. It can't be one the server already has.
+    fn name(&self) -> &str;
+
+    /// The Redis arity. Positive means exactly that many arguments counting the command name,
+    /// negative means at least that many.
+    fn arity(&self) -> i32;
+
+    /// The `[first, last, step]` key positions as in COMMAND INFO, counting the name as 0, with
+    /// a negative `last` counting from the end. Only the keys at these positions are locked, so
+    /// those are the only ones [`CommandPlugin::execute`] may touch. The default, `[0, 0, 0]`,
+    /// locks every key.
+    fn keys(&self) -> [i32; 3] {
+        [0, 0, 0]
+    }
+
+    /// Any of `write`, `readonly`, `fast` and `admin`, which put it in the ACL category of the
+    /// same name (`read` for `readonly`). A command that writes needs `write` to be kept from
+    /// replicas and read-only scripts.
+    fn flags(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Runs the command with `args`, the arguments after its name, which there are already
+    /// known to be the right number of, against the database the client has selected.
+    fn execute(&self, db: &mut Database<'_>, args: &[Vec<u8>]) -> Value;
+}
+
+/// The database a [`CommandPlugin`] runs against, with the shards holding its keys locked.
+/// Keys whose TTL has passed read as missing, and writes are seen by WATCH and sent on to
+/// replicas and the AOF as the command that made them.
+pub struct Database<'a> {
+    keyspace: &'a mut Keyspace,
+    /// Messages to publish once the command returns, as `(channel, message)`.
+    published: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> Database<'a> {
+    pub(crate) fn new(keyspace: &'a mut Keyspace) -> Database<'a> {
+        Database {
+            keyspace,
+            published: Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "wasm")]
+    pub(crate) fn keyspace(&mut self) -> &mut Keyspace {
+        self.keyspace
+    }
+
+    /// Takes the messages published so far, to be published somewhere else.
+    pub(crate) fn take_published(&mut self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        std::mem::take(&mut self.published)
+    }
+
+    /// The value of `key`, or `None` if it doesn't exist. If it holds something other than a
+    /// string, the error is the WRONGTYPE reply to give.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Bytes>, Value> {
+        let Some(val) = cmd::lookup(self.keyspace, key) else {
+            return Ok(None);
+        };
+        match val.data().string_value() {
+            Some(value) => Ok(Some(value)),
+            None => Err(wrong_type()),
+        }
+    }
+
+    /// Sets `key` to `value`, dropping any TTL it had.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) {
+        let val = DBData::new(DBVal::parse(value), None);
+        self.keyspace.insert(key.to_vec(), val);
+        db::signal_modified(key);
+    }
+
+    /// Sets `key` to `value`, to expire once `ttl` has passed.
+    pub fn set_ex(&mut self, key: &[u8], value: &[u8], ttl: Duration) {
+        let val = DBData::new(DBVal::parse(value), Some(expires_at(ttl)));
+        self.keyspace.insert(key.to_vec(), val);
+        db::signal_modified(key);
+    }
+
+    /// Gives `key` a TTL of `ttl`, returning whether it exists.
+    pub fn expire(&mut self, key: &[u8], ttl: Duration) -> bool {
+        if cmd::peek(self.keyspace, key).is_none() {
+            return false;
+        }
+        self.keyspace.set_expires_at(key, Some(expires_at(ttl)));
+        db::signal_modified(key);
+        true
+    }
+
+    /// Drops `key`'s TTL, returning whether it had one.
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        let Some(val) = cmd::peek(self.keyspace, key) else {
+            return false;
+        };
+        if val.expires_at().is_none() {
+            return false;
+        }
+        self.keyspace.set_expires_at(key, None);
+        db::signal_modified(key);
+        true
+    }
+
+    /// How long `key` has left, or `None` if it doesn't exist or has no TTL.
+    pub fn ttl(&mut self, key: &[u8]) -> Option<Duration> {
+        cmd::peek(self.keyspace, key)
+            .and_then(|val| val.ttl())
+            .map(Duration::from_millis)
+    }
+
+    /// Deletes `key`, returning whether it existed.
+    pub fn del(&mut self, key: &[u8]) -> bool {
+        if cmd::peek(self.keyspace, key).is_none() {
+            return false;
+        }
+        self.keyspace.remove(key);
+        db::signal_modified(key);
+        true
+    }
+
+    pub fn exists(&mut self, key: &[u8]) -> bool {
+        cmd::peek(self.keyspace, key).is_some()
+    }
+
+    /// How many keys there are, counting those that have expired but not yet been dropped. Only
+    /// a command that locks every key sees them all.
+    pub fn len(&self) -> usize {
+        self.keyspace.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Publishes `message` on `channel`, as PUBLISH does, once the command returns.
+    pub fn publish(&mut self, channel: &[u8], message: &[u8]) {
+        self.published.push((channel.to_vec(), message.to_vec()));
+    }
+}
+
+fn expires_at(ttl: Duration) -> u64 {
+    db::unix_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+/// A [`CommandPlugin`] as the registry has it.
+pub struct Plugin {
+    name: &'static str,
+    categories: Vec<&'static str>,
+    command: Box<dyn CommandPlugin>,
+}
+
+impl Plugin {
+    /// Checks `command` over, failing if its name, arity or flags aren't ones it can have.
+    pub fn new(command: Box<dyn CommandPlugin>) -> anyhow::Result<Plugin> {
+        let name = command.name().to_ascii_lowercase();
+        if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace() || c == '|') {
+            anyhow::bail!("Invalid command name '{name}'");
+        }
+        if command.arity() == 0 {
+            anyhow::bail!("Invalid arity 0 for command '{name}'");
+        }
+        let categories = command
+            .flags()
+            .iter()
+            .map(|flag| {
+                FLAGS
+                    .iter()
+                    .find(|(known, _)| flag.eq_ignore_ascii_case(known))
+                    .map(|(_, category)| *category)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid flag '{flag}' for command '{name}'"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Plugin {
+            name: String::leak(name),
+            categories,
+            command,
+        })
+    }
+
+    pub fn in_category(&self, category: &str) -> bool {
+        self.categories.contains(&category)
+    }
+
+    pub fn categories(&self) -> &[&'static str] {
+        &self.categories
+    }
+
+    /// Whether it said where its keys are, so only their shards need locking.
+    pub fn keeps_to_keys(&self) -> bool {
+        self.command.keys()[0] > 0
+    }
+
+    /// Its key positions in `args`, the arguments after its name.
+    pub fn key_positions(&self, args: &[Vec<u8>]) -> Vec<usize> {
+        let [first, last, step] = self.command.keys();
+        if first <= 0 {
+            return Vec::new();
+        }
+        let last = match last {
+            last if last < 0 => args.len() as i32 + 1 + last,
+            last => last,
+        };
+        (first..=last.min(args.len() as i32))
+            .step_by(step.max(1) as usize)
+            .map(|at| at as usize - 1)
+            .collect()
+    }
+}
+
+impl Command for Plugin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i32 {
+        self.command.arity()
+    }
+
+    fn keys(&self) -> [i32; 3] {
+        self.command.keys()
+    }
+
+    fn execute(&self, ctx: &mut Context<'_>, args: &[Vec<u8>]) -> Outcome {
+        let mut db = Database::new(ctx.db());
+        let reply = self.command.execute(&mut db, args);
+        for (channel, message) in db.take_published() {
+            ctx.pubsub.publish(&channel, &message);
+        }
+        Outcome::Reply(reply)
+    }
+}
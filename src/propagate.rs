@@ -0,0 +1,254 @@
+use crate::aof;
+use crate::cmd::{self, lower, parse_int};
+use crate::db;
+use crate::namespace;
+use crate::replication;
+use crate::resp::Value;
+use std::cell::RefCell;
+
+/// A command to pass on, with the database it was run in.
+type Fed = (usize, Vec<Vec<u8>>);
+
+thread_local! {
+    /// The commands a transaction has passed on so far, held back by [`hold`] to go out
+    /// together once it's done. A transaction runs without yielding, so they're all passed on
+    /// from the thread that holds them.
+    static HELD: RefCell<Option<Vec<Fed>>> = const { RefCell::new(None) };
+}
+
+/// Passes a command that changed the dataset in database `db` on to the append-only file and
+/// to replicas, given what it replied. It goes as a command that has the same effect whenever
+/// it's run again: relative TTLs become absolute, random choices become the ones made, and
+/// blocking commands their plain forms.
+pub fn propagate(db: usize, name: &str, args: &[Vec<u8>], reply: &Value) {
+    if let Some(command) = effect(name, args, reply) {
+        feed(db, &command);
+    }
+}
+
+/// Passes on `key` expiring in database `db` as a DEL. Replicas and the append-only file only
+/// ever drop keys where the master's stream says to, so they agree with it whatever their own
+/// clocks say.
+pub fn expired(db: usize, key: &[u8]) {
+    feed(db, &[b"del".to_vec(), key.to_vec()]);
+}
+
+/// Passes on `key` being evicted from database `db` to make room under `maxmemory` as a DEL,
+/// like [`expired`].
+pub fn evicted(db: usize, key: &[u8]) {
+    feed(db, &[b"del".to_vec(), key.to_vec()]);
+}
+
+/// Passes on `keys` being deleted from database `db` as a DEL, for a command that took them
+/// out some other way, as a namespace's FLUSHDB does.
+pub fn deleted(db: usize, keys: Vec<Vec<u8>>) {
+    let mut command = vec![b"del".to_vec()];
+    command.extend(keys);
+    feed(db, &command);
+}
+
+/// Passes on the fields of the hash at `key` that expired as an HDEL, like [`expired`].
+pub fn fields_expired(db: usize, key: &[u8], fields: &[Vec<u8>]) {
+    let mut command = vec![b"hdel".to_vec(), key.to_vec()];
+    command.extend_from_slice(fields);
+    feed(db, &command);
+}
+
+/// Holds back what's passed on from here on until [`release`], for a transaction.
+pub fn hold() {
+    HELD.set(Some(Vec::new()));
+}
+
+/// Passes on what was held back since [`hold`]. More than one command goes wrapped in
+/// MULTI and EXEC, as Redis does, so replicas and the append-only file apply all of them or,
+/// for a file cut short, none.
+pub fn release() {
+    let Some(held) = HELD.take() else {
+        return;
+    };
+    if held.len() < 2 {
+        for (db, command) in held {
+            send(db, &command);
+        }
+        return;
+    }
+
+    let (first, last) = (held[0].0, held[held.len() - 1].0);
+    send(first, &[b"multi".to_vec()]);
+    for (db, command) in held {
+        send(db, &command);
+    }
+    send(last, &[b"exec".to_vec()]);
+}
+
+fn feed(db: usize, command: &[Vec<u8>]) {
+    let held = HELD.with_borrow_mut(|held| match held {
+        Some(held) => {
+            held.push((db, command.to_vec()));
+            true
+        }
+        None => false,
+    });
+    if !held {
+        send(db, command);
+    }
+}
+
+fn send(db: usize, command: &[Vec<u8>]) {
+    aof::feed(db, command);
+    replication::feed(db, command);
+}
+
+/// The command to pass on for `name`, or `None` if it turned out not to need one.
+fn effect(name: &str, args: &[Vec<u8>], reply: &Value) -> Option<Vec<Vec<u8>>> {
+    let now = db::unix_millis();
+    let command = |name: &str, args: &[Vec<u8>]| {
+        let mut command = vec![name.as_bytes().to_vec()];
+        command.extend_from_slice(args);
+        command
+    };
+    let millis = |arg: &[u8], scale: i64, base: u64| {
+        let n = parse_int::<i64>(arg)?;
+        Some(
+            (base as i64)
+                .saturating_add(n.saturating_mul(scale))
+                .to_string()
+                .into_bytes(),
+        )
+    };
+    // The key a blocking pop was served from, which leads its reply
+    let served_key = || match reply {
+        Value::Array(items) => match items.first() {
+            Some(Value::BulkString(key)) => Some(key.to_vec()),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    Some(match name {
+        // Reads that expired a key, and scripts, whose commands are logged one by one
+        name if !cmd::is_write(name) && name != "function" => return None,
+        // Passed on key by key as it loaded them, since replicas don't have its file
+        "import" => return None,
+        // A namespace's flush passed on the keys it deleted, leaving the rest
+        "flushall" | "flushdb" if namespace::current().is_some() => return None,
+        "blpop" | "brpop" | "bzpopmin" | "bzpopmax" => command(&name[1..], &[served_key()?]),
+        "blmove" => command("lmove", &args[..4]),
+        "brpoplpush" => command("rpoplpush", &args[..2]),
+        "bzmpop" => command("zmpop", &args[1..]),
+        "xreadgroup" => {
+            let mut rest = Vec::new();
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                if lower(arg) == "block" {
+                    args.next();
+                } else {
+                    rest.push(arg.clone());
+                }
+            }
+            command(name, &rest)
+        }
+        // Only deleting the keys here has an effect, and a MIGRATE that changed anything
+        // deleted every one of them that was here
+        "migrate" => {
+            let keys: Vec<_> = cmd::command_keys(name, args)
+                .into_iter()
+                .map(<[u8]>::to_vec)
+                .collect();
+            command("del", &keys)
+        }
+        "spop" => {
+            let members = match reply {
+                Value::BulkString(member) => vec![member.to_vec()],
+                Value::Array(members) => members
+                    .iter()
+                    .filter_map(|member| match member {
+                        Value::BulkString(member) => Some(member.to_vec()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => return None,
+            };
+            let mut args = vec![args[0].clone()];
+            args.extend(members);
+            command("srem", &args)
+        }
+        "xadd" => {
+            // The ID follows the options, and may have been left for the server to pick
+            let Value::BulkString(id) = reply else {
+                return None;
+            };
+            let mut args = args.to_vec();
+            let mut i = 1;
+            while i < args.len() {
+                match lower(&args[i]).as_str() {
+                    "nomkstream" => i += 1,
+                    "maxlen" | "minid" => {
+                        i += 1;
+                        if matches!(args.get(i).map(|arg| arg.as_slice()), Some(b"=" | b"~")) {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                    "limit" => i += 2,
+                    _ => break,
+                }
+            }
+            args[i] = id.to_vec();
+            command(name, &args)
+        }
+        "set" | "getex" => {
+            let mut args = args.to_vec();
+            let first = if name == "set" { 2 } else { 1 };
+            for i in first..args.len().saturating_sub(1) {
+                let at = match lower(&args[i]).as_str() {
+                    "ex" => millis(&args[i + 1], 1000, now),
+                    "px" => millis(&args[i + 1], 1, now),
+                    "exat" => millis(&args[i + 1], 1000, 0),
+                    _ => continue,
+                };
+                args[i] = b"pxat".to_vec();
+                args[i + 1] = at?;
+            }
+            command(name, &args)
+        }
+        "setex" | "psetex" => {
+            let scale = if name == "setex" { 1000 } else { 1 };
+            let at = millis(&args[1], scale, now)?;
+            command(
+                "set",
+                &[args[0].clone(), args[2].clone(), b"pxat".to_vec(), at],
+            )
+        }
+        "restore" => {
+            let mut args = args.to_vec();
+            let absolute = args[3..].iter().any(|arg| lower(arg) == "absttl");
+            if !absolute && parse_int::<i64>(&args[1])? != 0 {
+                args[1] = millis(&args[1], 1, now)?;
+                args.push(b"absttl".to_vec());
+            }
+            command(name, &args)
+        }
+        "expire" | "pexpire" | "expireat" => {
+            let at = match name {
+                "expire" => millis(&args[1], 1000, now),
+                "pexpire" => millis(&args[1], 1, now),
+                _ => millis(&args[1], 1000, 0),
+            }?;
+            let mut args = args.to_vec();
+            args[1] = at;
+            command("pexpireat", &args)
+        }
+        "hexpire" | "hpexpire" | "hexpireat" => {
+            let at = match name {
+                "hexpire" => millis(&args[1], 1000, now),
+                "hpexpire" => millis(&args[1], 1, now),
+                _ => millis(&args[1], 1000, 0),
+            }?;
+            let mut args = args.to_vec();
+            args[1] = at;
+            command("hpexpireat", &args)
+        }
+        name => command(name, args),
+    })
+}
@@ -0,0 +1,301 @@
+use crate::glob::glob_match;
+use crate::output::{self, Buffer};
+use crate::resp::Value;
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc;
+
+/// Where a connection receives the frames pushed to it while it's subscribed, counting what
+/// piles up against its output buffer limits.
+#[derive(Clone)]
+pub struct Mailbox {
+    sender: mpsc::UnboundedSender<(Value, usize)>,
+    output: Arc<Buffer>,
+}
+
+impl Mailbox {
+    /// Queues `frame` for the connection to write out, returning whether it was. It isn't once
+    /// the connection has gone, or fallen so far behind it's past its limits.
+    pub fn send(&self, frame: Value) -> bool {
+        let size = frame.size();
+        self.output.queue(size) && self.sender.send((frame, size)).is_ok()
+    }
+
+    /// How many bytes are queued and not yet written out.
+    pub fn pending(&self) -> u64 {
+        self.output.len()
+    }
+}
+
+/// What a subscription is to: an exact channel name, a glob pattern over channel names, or a
+/// shard channel, which lives in its own namespace.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+/// Subscribers by channel (or pattern), keyed by connection id.
+#[derive(Default)]
+struct Registry(Mutex<HashMap<Vec<u8>, HashMap<u64, Mailbox>>>);
+
+impl Registry {
+    fn lock(&self) -> MutexGuard<'_, HashMap<Vec<u8>, HashMap<u64, Mailbox>>> {
+        self.0.lock().unwrap()
+    }
+
+    fn add(&self, name: &[u8], id: u64, mailbox: &Mailbox) {
+        self.lock()
+            .entry(name.to_vec())
+            .or_default()
+            .insert(id, mailbox.clone());
+    }
+
+    /// Pushes `frame` to everyone subscribed to `name`, returning how many received it.
+    fn deliver(&self, name: &[u8], frame: &Value) -> usize {
+        self.lock().get(name).map_or(0, |subscribers| {
+            subscribers
+                .values()
+                .filter(|mailbox| mailbox.send(frame.clone()))
+                .count()
+        })
+    }
+
+    fn remove(&self, name: &[u8], id: u64) {
+        let mut names = self.lock();
+
+        if let Some(subscribers) = names.get_mut(name) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                names.remove(name);
+            }
+        }
+    }
+}
+
+/// Channel, pattern and shard channel registries shared by every connection.
+#[derive(Default)]
+pub struct PubSub {
+    next_id: AtomicU64,
+    channels: Registry,
+    patterns: Registry,
+    shard_channels: Registry,
+}
+
+impl PubSub {
+    fn registry(&self, kind: Kind) -> &Registry {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::ShardChannel => &self.shard_channels,
+        }
+    }
+
+    /// Delivers `message` to everyone subscribed to `channel`, either directly or through a
+    /// matching pattern, returning how many deliveries were made.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let bulk = |bytes: &[u8]| Value::BulkString(Bytes::copy_from_slice(bytes));
+        let frame = Value::Push(vec![bulk(b"message"), bulk(channel), bulk(message)]);
+        let mut delivered = self.channels.deliver(channel, &frame);
+
+        for (pattern, subscribers) in self.patterns.lock().iter() {
+            if !glob_match(pattern, channel, false) {
+                continue;
+            }
+
+            let frame = Value::Push(vec![
+                bulk(b"pmessage"),
+                bulk(pattern),
+                bulk(channel),
+                bulk(message),
+            ]);
+            delivered += subscribers
+                .values()
+                .filter(|mailbox| mailbox.send(frame.clone()))
+                .count();
+        }
+
+        delivered
+    }
+
+    /// Delivers `message` to the subscribers of shard channel `channel`. Patterns never match
+    /// shard channels.
+    pub fn spublish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let bulk = |bytes: &[u8]| Value::BulkString(Bytes::copy_from_slice(bytes));
+        let frame = Value::Push(vec![bulk(b"smessage"), bulk(channel), bulk(message)]);
+
+        self.shard_channels.deliver(channel, &frame)
+    }
+
+    /// Channels (or shard channels) with at least one subscriber, optionally filtered by a glob
+    /// pattern.
+    pub fn channels(&self, kind: Kind, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        self.registry(kind)
+            .lock()
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel, false)))
+            .cloned()
+            .collect()
+    }
+
+    /// Number of subscribers to `channel`, not counting pattern subscribers.
+    pub fn subscriber_count(&self, kind: Kind, channel: &[u8]) -> usize {
+        self.registry(kind)
+            .lock()
+            .get(channel)
+            .map_or(0, HashMap::len)
+    }
+
+    /// Number of distinct patterns subscribed to across all connections.
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.lock().len()
+    }
+}
+
+/// One connection's side of pub/sub: what it's subscribed to and the inbox published messages
+/// land in. Dropping it unsubscribes from everything.
+pub struct Subscriber {
+    id: u64,
+    pubsub: Arc<PubSub>,
+    mailbox: Mailbox,
+    inbox: mpsc::UnboundedReceiver<(Value, usize)>,
+    channels: BTreeSet<Vec<u8>>,
+    patterns: BTreeSet<Vec<u8>>,
+    shard_channels: BTreeSet<Vec<u8>>,
+}
+
+impl Subscriber {
+    pub fn new(pubsub: Arc<PubSub>) -> Self {
+        let id = pubsub.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, inbox) = mpsc::unbounded_channel();
+        let mailbox = Mailbox {
+            sender,
+            output: Arc::new(Buffer::new(output::Class::Normal)),
+        };
+
+        Self {
+            id,
+            pubsub,
+            mailbox,
+            inbox,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+        }
+    }
+
+    fn names(&mut self, kind: Kind) -> &mut BTreeSet<Vec<u8>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    /// Whether the connection is in subscribed mode, where only pub/sub commands are allowed.
+    pub fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+
+    /// The count reported in (un)subscribe confirmations: channels plus patterns, while shard
+    /// channels are counted on their own.
+    pub fn subscription_count(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Channel | Kind::Pattern => self.channels.len() + self.patterns.len(),
+            Kind::ShardChannel => self.shard_channels.len(),
+        }
+    }
+
+    /// Where to send frames for the connection to write out, as if they were published to it.
+    pub fn mailbox(&self) -> Mailbox {
+        self.mailbox.clone()
+    }
+
+    /// What's queued in the mailbox, to watch for it outgrowing its limits.
+    pub fn output(&self) -> Arc<Buffer> {
+        self.mailbox.output.clone()
+    }
+
+    /// Subscribers are held to the pubsub limits, and go back to the normal ones once they
+    /// leave subscribed mode.
+    fn update_class(&self) {
+        let class = if self.is_subscribed() {
+            output::Class::PubSub
+        } else {
+            output::Class::Normal
+        };
+        self.mailbox.output.set_class(class);
+    }
+
+    /// How many channels, patterns or shard channels the connection is subscribed to.
+    pub fn count(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Channel => self.channels.len(),
+            Kind::Pattern => self.patterns.len(),
+            Kind::ShardChannel => self.shard_channels.len(),
+        }
+    }
+
+    /// Waits for the next message published to one of our channels or patterns.
+    pub async fn next_message(&mut self) -> Value {
+        // We hold a sender ourselves, so the inbox never closes
+        let (frame, size) = self.inbox.recv().await.expect("mailbox outlives its inbox");
+        self.mailbox.output.dequeue(size);
+        frame
+    }
+
+    /// Subscribes to a channel or pattern, returning whether it's a new subscription.
+    pub fn subscribe(&mut self, kind: Kind, name: &[u8]) -> bool {
+        if !self.names(kind).insert(name.to_vec()) {
+            return false;
+        }
+
+        self.pubsub.registry(kind).add(name, self.id, &self.mailbox);
+        self.update_class();
+
+        true
+    }
+
+    /// Unsubscribes from a channel or pattern, returning whether we were subscribed to it.
+    pub fn unsubscribe(&mut self, kind: Kind, name: &[u8]) -> bool {
+        if !self.names(kind).remove(name) {
+            return false;
+        }
+
+        self.pubsub.registry(kind).remove(name, self.id);
+        self.update_class();
+
+        true
+    }
+
+    pub fn subscriptions(&mut self, kind: Kind) -> Vec<Vec<u8>> {
+        self.names(kind).iter().cloned().collect()
+    }
+
+    /// Drops every subscription at once, without confirming each one.
+    pub fn unsubscribe_all(&mut self) {
+        for kind in [Kind::Channel, Kind::Pattern, Kind::ShardChannel] {
+            for name in std::mem::take(self.names(kind)) {
+                self.pubsub.registry(kind).remove(&name, self.id);
+            }
+        }
+        self.update_class();
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        for channel in &self.channels {
+            self.pubsub.channels.remove(channel, self.id);
+        }
+        for pattern in &self.patterns {
+            self.pubsub.patterns.remove(pattern, self.id);
+        }
+        for channel in &self.shard_channels {
+            self.pubsub.shard_channels.remove(channel, self.id);
+        }
+    }
+}
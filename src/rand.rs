@@ -0,0 +1,65 @@
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    let local = 0u8;
+
+    // Mix in a stack address so threads started in the same instant still diverge
+    nanos ^ (&local as *const u8 as u64).rotate_left(32) | 1
+}
+
+/// Starts this thread's numbers over from `seed`, for a test to draw the same ones every run.
+#[cfg(test)]
+pub fn reseed(seed: u64) {
+    STATE.with(|state| state.set(seed | 1));
+}
+
+/// A fast, non-cryptographic random number (xorshift64*), good enough for sampling keys and
+/// fields.
+pub fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// A random index in `0..n`. `n` must be non-zero.
+pub fn below(n: usize) -> usize {
+    // Scale by the high bits, which are much better mixed than the low ones
+    ((next_u64() as u128 * n as u128) >> 64) as usize
+}
+
+/// Picks indexes into a collection of `len` items the way Redis' random-member commands do:
+/// a non-negative `count` yields up to that many distinct indexes, a negative one yields
+/// exactly `-count` indexes that may repeat.
+pub fn sample_indexes(len: usize, count: i64) -> Vec<usize> {
+    if len == 0 {
+        return Vec::new();
+    }
+
+    if count < 0 {
+        return (0..count.unsigned_abs()).map(|_| below(len)).collect();
+    }
+
+    let n = (count as usize).min(len);
+    let mut indexes: Vec<usize> = (0..len).collect();
+    for i in 0..n {
+        let j = i + below(len - i);
+        indexes.swap(i, j);
+    }
+    indexes.truncate(n);
+
+    indexes
+}
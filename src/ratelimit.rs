@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Commands a second each connection may run, or 0 for no limit.
+pub static CLIENT_RATE: AtomicU64 = AtomicU64::new(0);
+/// Commands a second all of one ACL user's connections may run between them, or 0 for no limit.
+pub static USER_RATE: AtomicU64 = AtomicU64::new(0);
+/// How many commands may be run back to back before the rate applies, or 0 for a second's worth.
+pub static BURST: AtomicU64 = AtomicU64::new(0);
+/// Whether a command over the limit waits its turn rather than being turned away.
+pub static DELAY: AtomicBool = AtomicBool::new(false);
+
+/// Values `rate-limit-action` can take.
+pub const ACTIONS: &[&str] = &["error", "delay"];
+
+/// The buckets shared by each user's connections, by user name.
+static USERS: LazyLock<Mutex<HashMap<String, Bucket>>> = LazyLock::new(Default::default);
+
+/// A token bucket: refilled at the rate it's checked against, up to the burst, with one token
+/// taken for each command.
+#[derive(Default)]
+pub struct Bucket {
+    tokens: f64,
+    /// When `tokens` was last brought up to date, or `None` while the bucket is still full.
+    refilled: Option<Instant>,
+}
+
+impl Bucket {
+    /// Tops the bucket up for the time since it was last, returning how long until it holds a
+    /// token.
+    fn refill(&mut self, rate: u64) -> Duration {
+        let burst = match BURST.load(Ordering::Relaxed) {
+            0 => rate,
+            burst => burst,
+        } as f64;
+        let now = Instant::now();
+        self.tokens = match self.refilled {
+            None => burst,
+            Some(at) => (self.tokens + (now - at).as_secs_f64() * rate as f64).min(burst),
+        };
+        self.refilled = Some(now);
+
+        Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / rate as f64)
+    }
+}
+
+/// Takes a token for one command from the connection's bucket and from its user's, or returns
+/// how long until both have one, taking neither.
+pub fn admit(bucket: &mut Bucket, user: Option<&str>) -> Result<(), Duration> {
+    let client_rate = CLIENT_RATE.load(Ordering::Relaxed);
+    let user_rate = USER_RATE.load(Ordering::Relaxed);
+    let mut users = USERS.lock().unwrap();
+    let mut user = match user {
+        Some(user) if user_rate > 0 => Some(users.entry(user.to_string()).or_default()),
+        _ => None,
+    };
+
+    let mut wait = Duration::ZERO;
+    if client_rate > 0 {
+        wait = wait.max(bucket.refill(client_rate));
+    }
+    if let Some(user) = &mut user {
+        wait = wait.max(user.refill(user_rate));
+    }
+    if !wait.is_zero() {
+        return Err(wait);
+    }
+
+    if client_rate > 0 {
+        bucket.tokens -= 1.0;
+    }
+    if let Some(user) = user {
+        user.tokens -= 1.0;
+    }
+    Ok(())
+}
@@ -0,0 +1,874 @@
+use crate::db::DBVal;
+use crate::hash::Hash;
+use crate::set::Set;
+use crate::stream::{ConsumerGroup, Stream, StreamId};
+use crate::zset::ZSet;
+use std::collections::{HashMap, VecDeque};
+
+/// Value types, as the byte before each key. Only the plain ones are written, which every
+/// Redis since 2.x can load; the packed ones newer versions write are read too.
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+const TYPE_HASH_METADATA: u8 = 24;
+const TYPE_HASH_LISTPACK_EX: u8 = 25;
+
+/// The oldest format that can hold every value type written here, and the one that added
+/// hashes with field TTLs.
+pub const VERSION: u32 = 11;
+pub const VERSION_FIELD_TTLS: u32 = 12;
+
+/// Length prefixes whose top two bits are set hold a string in a special form instead.
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// How quicklist nodes hold their elements: one element on its own, or a listpack of them.
+const QUICKLIST_PLAIN: u64 = 1;
+const QUICKLIST_PACKED: u64 = 2;
+
+/// Flags on stream entries: deleted, and having the same fields as the node's first entry,
+/// which leaves out the field names.
+const STREAM_DELETED: i64 = 1;
+const STREAM_SAME_FIELDS: i64 = 2;
+
+/// The format `val` needs: hashes with field TTLs can't be written in older ones.
+pub fn version_for(val: &DBVal) -> u32 {
+    match val {
+        DBVal::Hash(hash) if hash.expires().len() > 0 => VERSION_FIELD_TTLS,
+        _ => VERSION,
+    }
+}
+
+/// Appends a key and its value, after the value's type.
+pub fn write_key(out: &mut Vec<u8>, key: &[u8], val: &DBVal) {
+    out.push(match val {
+        DBVal::String(_) | DBVal::Int(_) => TYPE_STRING,
+        DBVal::List(_) => TYPE_LIST,
+        DBVal::Set(_) => TYPE_SET,
+        DBVal::ZSet(_) => TYPE_ZSET_2,
+        DBVal::Hash(hash) if hash.expires().len() > 0 => TYPE_HASH_METADATA,
+        DBVal::Hash(_) => TYPE_HASH,
+        DBVal::Stream(_) => TYPE_STREAM_LISTPACKS_3,
+    });
+    write_string(out, key);
+
+    match val {
+        DBVal::String(s) => write_string(out, s),
+        DBVal::Int(n) => write_string(out, n.to_string().as_bytes()),
+        DBVal::List(list) => {
+            write_len(out, list.len() as u64);
+            for element in list {
+                write_string(out, element);
+            }
+        }
+        DBVal::Set(set) => {
+            write_len(out, set.len() as u64);
+            for member in set.iter() {
+                write_string(out, &member);
+            }
+        }
+        DBVal::ZSet(zset) => {
+            write_len(out, zset.len() as u64);
+            for (member, score) in zset.iter() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        DBVal::Hash(hash) => match hash.expires().map(|(_, &at)| at).min() {
+            None => {
+                write_len(out, hash.len() as u64);
+                for (field, value) in hash.iter() {
+                    write_string(out, field);
+                    write_string(out, value);
+                }
+            }
+            Some(min_expire) => {
+                write_millis(out, min_expire);
+                write_len(out, hash.len() as u64);
+                for (field, value) in hash.iter() {
+                    // Relative to the earliest, so most fit in a short length; 0 is no TTL
+                    let ttl = hash.expire_at(field).map_or(0, |at| at - min_expire + 1);
+                    write_len(out, ttl);
+                    write_string(out, field);
+                    write_string(out, value);
+                }
+            }
+        },
+        DBVal::Stream(stream) => write_stream(out, stream),
+    }
+}
+
+/// Streams are kept as a tree of listpacks. Everything here goes in one node, keyed by its
+/// first ID, whose first entry's fields the others can share.
+fn write_stream(out: &mut Vec<u8>, stream: &Stream) {
+    let mut entries = stream.iter();
+    match entries.next() {
+        None => write_len(out, 0),
+        Some((&master_id, master_fields)) => {
+            let mut node = Listpack::default();
+            node.push_int(stream.len() as i64);
+            // Nothing deleted is ever kept
+            node.push_int(0);
+            node.push_int(master_fields.len() as i64);
+            for (field, _) in master_fields {
+                node.push_string(field);
+            }
+            node.push_int(0);
+
+            for (id, fields) in std::iter::once((&master_id, master_fields)).chain(entries) {
+                let same_fields = fields.len() == master_fields.len()
+                    && fields
+                        .iter()
+                        .zip(master_fields)
+                        .all(|((field, _), (master, _))| field == master);
+
+                node.push_int(if same_fields { STREAM_SAME_FIELDS } else { 0 });
+                node.push_int(id.ms.wrapping_sub(master_id.ms) as i64);
+                node.push_int(id.seq.wrapping_sub(master_id.seq) as i64);
+                if same_fields {
+                    for (_, value) in fields {
+                        node.push_string(value);
+                    }
+                    node.push_int(fields.len() as i64 + 3);
+                } else {
+                    node.push_int(fields.len() as i64);
+                    for (field, value) in fields {
+                        node.push_string(field);
+                        node.push_string(value);
+                    }
+                    node.push_int(fields.len() as i64 * 2 + 4);
+                }
+            }
+
+            write_len(out, 1);
+            write_string(out, &raw_id(master_id));
+            write_string(out, &node.finish());
+        }
+    }
+
+    let first_id = stream.iter().next().map_or(StreamId::MIN, |(&id, _)| id);
+    write_len(out, stream.len() as u64);
+    write_stream_id(out, stream.last_id());
+    write_stream_id(out, first_id);
+    // The highest deleted ID and how many entries were ever added aren't tracked
+    write_stream_id(out, StreamId::MIN);
+    write_len(out, stream.len() as u64);
+
+    write_len(out, stream.groups().count() as u64);
+    for (name, group) in stream.groups() {
+        write_string(out, name);
+        write_stream_id(out, group.last_delivered);
+        // How many entries the group has read isn't tracked either, which -1 says
+        write_len(out, u64::MAX);
+
+        write_len(out, group.pending().len() as u64);
+        for (&id, pending) in group.pending() {
+            out.extend_from_slice(&raw_id(id));
+            write_millis(out, pending.delivered_at);
+            write_len(out, pending.delivery_count);
+        }
+
+        write_len(out, group.consumers().len() as u64);
+        for (name, consumer) in group.consumers() {
+            write_string(out, name);
+            write_millis(out, consumer.seen_at);
+            write_millis(out, consumer.seen_at);
+            write_len(out, consumer.pending.len() as u64);
+            for &id in &consumer.pending {
+                out.extend_from_slice(&raw_id(id));
+            }
+        }
+    }
+}
+
+fn write_stream_id(out: &mut Vec<u8>, id: StreamId) {
+    write_len(out, id.ms);
+    write_len(out, id.seq);
+}
+
+/// Stream IDs as tree keys and in PELs: both halves big-endian, so they sort bytewise.
+fn raw_id(id: StreamId) -> [u8; 16] {
+    let mut raw = [0; 16];
+    raw[..8].copy_from_slice(&id.ms.to_be_bytes());
+    raw[8..].copy_from_slice(&id.seq.to_be_bytes());
+    raw
+}
+
+/// Lengths take 1, 2, 5 or 9 bytes depending on size, flagged by the first byte's top bits.
+pub fn write_len(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.extend_from_slice(&((len as u16) | 0x4000).to_be_bytes());
+    } else if let Ok(len) = u32::try_from(len) {
+        out.push(0x80);
+        out.extend_from_slice(&len.to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Short strings holding a small enough integer are stored as the integer.
+pub fn write_string(out: &mut Vec<u8>, s: &[u8]) {
+    let n = match s.len() {
+        ..=11 => canonical_int(s).unwrap_or(i64::MAX),
+        _ => i64::MAX,
+    };
+    if let Ok(n) = i8::try_from(n) {
+        out.push(0xc0 | ENC_INT8);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else if let Ok(n) = i16::try_from(n) {
+        out.push(0xc0 | ENC_INT16);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else if let Ok(n) = i32::try_from(n) {
+        out.push(0xc0 | ENC_INT32);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else {
+        write_len(out, s.len() as u64);
+        out.extend_from_slice(s);
+    }
+}
+
+pub fn write_millis(out: &mut Vec<u8>, ms: u64) {
+    out.extend_from_slice(&ms.to_le_bytes());
+}
+
+/// The integer `s` spells, if it's written the way the integer would print.
+fn canonical_int(s: &[u8]) -> Option<i64> {
+    let n = std::str::from_utf8(s).ok()?.parse::<i64>().ok()?;
+    (n.to_string().as_bytes() == s).then_some(n)
+}
+
+/// The value of type `kind` that `reader` is positioned at.
+pub fn read_value(reader: &mut Reader, kind: u8) -> anyhow::Result<DBVal> {
+    let val = match kind {
+        TYPE_STRING => DBVal::parse(&reader.string()?),
+        TYPE_LIST => {
+            let len = reader.len()?;
+            let mut list = VecDeque::new();
+            for _ in 0..len {
+                list.push_back(reader.string()?);
+            }
+            DBVal::List(list)
+        }
+        TYPE_LIST_ZIPLIST => DBVal::List(ziplist(&reader.string()?)?.into()),
+        TYPE_LIST_QUICKLIST => {
+            let mut list = VecDeque::new();
+            for _ in 0..reader.len()? {
+                list.extend(ziplist(&reader.string()?)?);
+            }
+            DBVal::List(list)
+        }
+        TYPE_LIST_QUICKLIST_2 => {
+            let mut list = VecDeque::new();
+            for _ in 0..reader.len()? {
+                match reader.len()? {
+                    QUICKLIST_PLAIN => list.push_back(reader.string()?),
+                    QUICKLIST_PACKED => list.extend(listpack(&reader.string()?)?),
+                    container => anyhow::bail!("unknown quicklist container {container}"),
+                }
+            }
+            DBVal::List(list)
+        }
+        TYPE_SET => {
+            let mut set = Set::new();
+            for _ in 0..reader.len()? {
+                set.insert(reader.string()?);
+            }
+            DBVal::Set(set)
+        }
+        TYPE_SET_INTSET => {
+            let mut set = Set::new();
+            for n in intset(&reader.string()?)? {
+                set.insert(n.to_string().into_bytes());
+            }
+            DBVal::Set(set)
+        }
+        TYPE_SET_LISTPACK => {
+            let mut set = Set::new();
+            for member in listpack(&reader.string()?)? {
+                set.insert(member);
+            }
+            DBVal::Set(set)
+        }
+        TYPE_ZSET | TYPE_ZSET_2 => {
+            let mut zset = ZSet::new();
+            for _ in 0..reader.len()? {
+                let member = reader.string()?;
+                let score = match kind {
+                    TYPE_ZSET => reader.text_double()?,
+                    _ => f64::from_le_bytes(reader.take(8)?.try_into()?),
+                };
+                if score.is_nan() {
+                    anyhow::bail!("NaN score");
+                }
+                zset.insert(member, score);
+            }
+            DBVal::ZSet(zset)
+        }
+        TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+            let blob = reader.string()?;
+            let elements = match kind {
+                TYPE_ZSET_ZIPLIST => ziplist(&blob)?,
+                _ => listpack(&blob)?,
+            };
+            let mut zset = ZSet::new();
+            for pair in pairs(elements)? {
+                let (member, score) = pair;
+                let score = std::str::from_utf8(&score)?.parse::<f64>()?;
+                zset.insert(member, score);
+            }
+            DBVal::ZSet(zset)
+        }
+        TYPE_HASH => {
+            let mut hash = Hash::new();
+            for _ in 0..reader.len()? {
+                let field = reader.string()?;
+                hash.insert(field, reader.string()?);
+            }
+            DBVal::Hash(hash)
+        }
+        TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => {
+            let blob = reader.string()?;
+            let elements = match kind {
+                TYPE_HASH_ZIPLIST => ziplist(&blob)?,
+                _ => listpack(&blob)?,
+            };
+            let mut hash = Hash::new();
+            for (field, value) in pairs(elements)? {
+                hash.insert(field, value);
+            }
+            DBVal::Hash(hash)
+        }
+        TYPE_HASH_METADATA => {
+            let min_expire = reader.millis()?;
+            let mut hash = Hash::new();
+            for _ in 0..reader.len()? {
+                let ttl = reader.len()?;
+                let field = reader.string()?;
+                hash.insert(field.clone(), reader.string()?);
+                if ttl != 0 {
+                    hash.set_expire(&field, min_expire + ttl - 1);
+                }
+            }
+            DBVal::Hash(hash)
+        }
+        TYPE_HASH_LISTPACK_EX => {
+            // The earliest TTL comes first, but each field has its own absolute one anyway
+            reader.millis()?;
+            let elements = listpack(&reader.string()?)?;
+            if !elements.len().is_multiple_of(3) {
+                anyhow::bail!("hash listpack doesn't hold field, value, TTL triples");
+            }
+            let mut hash = Hash::new();
+            let mut elements = elements.into_iter();
+            while let (Some(field), Some(value), Some(ttl)) =
+                (elements.next(), elements.next(), elements.next())
+            {
+                hash.insert(field.clone(), value);
+                match parse_int(&ttl)? {
+                    0 => {}
+                    at => {
+                        hash.set_expire(&field, at as u64);
+                    }
+                }
+            }
+            DBVal::Hash(hash)
+        }
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+            DBVal::Stream(read_stream(reader, kind)?)
+        }
+        kind => anyhow::bail!("unsupported value type {kind}"),
+    };
+
+    Ok(val)
+}
+
+fn read_stream(reader: &mut Reader, kind: u8) -> anyhow::Result<Stream> {
+    let mut stream = Stream::new();
+    for _ in 0..reader.len()? {
+        let master_id = parse_raw_id(&reader.string()?)?;
+        let elements = listpack(&reader.string()?)?;
+        let mut elements = elements.iter();
+        let mut next = || {
+            elements
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("stream node cut short"))
+        };
+
+        // The node's first entry: live and deleted counts and the fields others can share
+        next()?;
+        next()?;
+        let master_fields = (0..parse_int(next()?)?)
+            .map(|_| next().cloned())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        next()?;
+
+        loop {
+            let flags = match next() {
+                Ok(flags) => parse_int(flags)?,
+                Err(_) => break,
+            };
+            let id = StreamId::new(
+                master_id.ms.wrapping_add(parse_int(next()?)? as u64),
+                master_id.seq.wrapping_add(parse_int(next()?)? as u64),
+            );
+            let fields = if flags & STREAM_SAME_FIELDS != 0 {
+                master_fields
+                    .iter()
+                    .map(|field| Ok((field.clone(), next()?.clone())))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            } else {
+                (0..parse_int(next()?)?)
+                    .map(|_| Ok((next()?.clone(), next()?.clone())))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            };
+            // How many elements the entry took, for walking the node backwards
+            next()?;
+
+            if flags & STREAM_DELETED == 0 {
+                if stream.len() > 0 && id <= stream.last_id() {
+                    anyhow::bail!("stream IDs out of order");
+                }
+                stream.append(id, fields);
+            }
+        }
+    }
+
+    reader.len()?;
+    let last_id = reader.stream_id()?;
+    if stream.last_id() > last_id {
+        anyhow::bail!("stream last ID behind its entries");
+    }
+    stream.set_last_id(last_id);
+    if kind >= TYPE_STREAM_LISTPACKS_2 {
+        // First ID, highest deleted ID and entries ever added
+        reader.stream_id()?;
+        reader.stream_id()?;
+        reader.len()?;
+    }
+
+    for _ in 0..reader.len()? {
+        let name = reader.string()?;
+        let mut group = ConsumerGroup::new(reader.stream_id()?);
+        if kind >= TYPE_STREAM_LISTPACKS_2 {
+            // Entries read
+            reader.len()?;
+        }
+
+        let mut pending = HashMap::new();
+        for _ in 0..reader.len()? {
+            let id = parse_raw_id(reader.take(16)?)?;
+            pending.insert(id, (reader.millis()?, reader.len()?));
+        }
+
+        for _ in 0..reader.len()? {
+            let consumer = reader.string()?;
+            let seen_at = reader.millis()?;
+            if kind >= TYPE_STREAM_LISTPACKS_3 {
+                // Last active
+                reader.millis()?;
+            }
+            group.create_consumer(&consumer, seen_at);
+            for _ in 0..reader.len()? {
+                let id = parse_raw_id(reader.take(16)?)?;
+                let (delivered_at, count) = pending
+                    .remove(&id)
+                    .ok_or_else(|| anyhow::anyhow!("consumer PEL entry missing from group PEL"))?;
+                group.assign(id, &consumer, delivered_at, count);
+            }
+        }
+        if !pending.is_empty() {
+            anyhow::bail!("group PEL entry without a consumer");
+        }
+
+        if !stream.create_group(name, group) {
+            anyhow::bail!("duplicate consumer group");
+        }
+    }
+
+    Ok(stream)
+}
+
+fn parse_raw_id(raw: &[u8]) -> anyhow::Result<StreamId> {
+    let raw: &[u8; 16] = raw.try_into()?;
+    let (ms, seq) = raw.split_at(8);
+
+    Ok(StreamId::new(
+        u64::from_be_bytes(ms.try_into()?),
+        u64::from_be_bytes(seq.try_into()?),
+    ))
+}
+
+fn parse_int(s: &[u8]) -> anyhow::Result<i64> {
+    Ok(std::str::from_utf8(s)?.parse()?)
+}
+
+fn pairs(elements: Vec<Vec<u8>>) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !elements.len().is_multiple_of(2) {
+        anyhow::bail!("odd number of elements in a paired encoding");
+    }
+
+    let mut elements = elements.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(a), Some(b)) = (elements.next(), elements.next()) {
+        pairs.push((a, b));
+    }
+
+    Ok(pairs)
+}
+
+/// The elements of a listpack, with integers spelled out: a 6 byte header, then each element
+/// as an encoding byte, its data and its length again for walking backwards, then 0xff.
+fn listpack(blob: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(blob);
+    reader.take(6)?;
+
+    let mut elements = Vec::new();
+    loop {
+        let start = reader.buf.len();
+        let encoding = reader.u8()?;
+        let element = match encoding {
+            0xff => break,
+            b if b & 0x80 == 0 => (b as i64).to_string().into_bytes(),
+            b if b & 0xc0 == 0x80 => reader.take((b & 0x3f) as usize)?.to_vec(),
+            b if b & 0xe0 == 0xc0 => {
+                let n = ((b as i64 & 0x1f) << 8) | reader.u8()? as i64;
+                // 13 bit two's complement
+                let n = if n >= 1 << 12 { n - (1 << 13) } else { n };
+                n.to_string().into_bytes()
+            }
+            b if b & 0xf0 == 0xe0 => {
+                let len = ((b as usize & 0x0f) << 8) | reader.u8()? as usize;
+                reader.take(len)?.to_vec()
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(reader.take(4)?.try_into()?);
+                reader.take(len as usize)?.to_vec()
+            }
+            0xf1 => i16::from_le_bytes(reader.take(2)?.try_into()?)
+                .to_string()
+                .into_bytes(),
+            0xf2 => {
+                let b = reader.take(3)?;
+                // Sign-extend from the top byte
+                let n = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+                n.to_string().into_bytes()
+            }
+            0xf3 => i32::from_le_bytes(reader.take(4)?.try_into()?)
+                .to_string()
+                .into_bytes(),
+            0xf4 => i64::from_le_bytes(reader.take(8)?.try_into()?)
+                .to_string()
+                .into_bytes(),
+            b => anyhow::bail!("bad listpack encoding {b:#x}"),
+        };
+        reader.take(backlen_size(start - reader.buf.len()))?;
+        elements.push(element);
+    }
+
+    Ok(elements)
+}
+
+/// How many bytes a listpack element's trailing length takes: 7 bits a byte.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..16383 => 2,
+        16383..2097151 => 3,
+        2097151..268435455 => 4,
+        _ => 5,
+    }
+}
+
+/// Builds a listpack, storing strings that spell integers as the integer like Redis does.
+#[derive(Default)]
+struct Listpack {
+    body: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    fn push_int(&mut self, n: i64) {
+        let mut element = Vec::new();
+        if (0..=127).contains(&n) {
+            element.push(n as u8);
+        } else if (-4096..=4095).contains(&n) {
+            let n = n as u16 & 0x1fff;
+            element.extend_from_slice(&[0xc0 | (n >> 8) as u8, n as u8]);
+        } else if let Ok(n) = i16::try_from(n) {
+            element.push(0xf1);
+            element.extend_from_slice(&n.to_le_bytes());
+        } else if (-(1 << 23)..1 << 23).contains(&n) {
+            element.push(0xf2);
+            element.extend_from_slice(&(n as i32).to_le_bytes()[..3]);
+        } else if let Ok(n) = i32::try_from(n) {
+            element.push(0xf3);
+            element.extend_from_slice(&n.to_le_bytes());
+        } else {
+            element.push(0xf4);
+            element.extend_from_slice(&n.to_le_bytes());
+        }
+        self.push(element);
+    }
+
+    fn push_string(&mut self, s: &[u8]) {
+        if let Some(n) = canonical_int(s) {
+            return self.push_int(n);
+        }
+
+        let mut element = Vec::with_capacity(s.len() + 5);
+        if s.len() < 64 {
+            element.push(0x80 | s.len() as u8);
+        } else if s.len() < 4096 {
+            element.extend_from_slice(&[0xe0 | (s.len() >> 8) as u8, s.len() as u8]);
+        } else {
+            element.push(0xf0);
+            element.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        }
+        element.extend_from_slice(s);
+        self.push(element);
+    }
+
+    fn push(&mut self, element: Vec<u8>) {
+        let len = element.len();
+        self.body.extend_from_slice(&element);
+        match backlen_size(len) {
+            1 => self.body.push(len as u8),
+            size => {
+                // Most significant group first, with the high bit set on all but that one
+                for i in (0..size).rev() {
+                    let group = (len >> (7 * i)) as u8 & 0x7f;
+                    self.body
+                        .push(if i + 1 == size { group } else { group | 0x80 });
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let total = self.body.len() + 7;
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        // Counts that don't fit say the elements have to be walked to count them
+        out.extend_from_slice(&(self.len.min(u16::MAX as usize) as u16).to_le_bytes());
+        out.extend_from_slice(&self.body);
+        out.push(0xff);
+        out
+    }
+}
+
+/// The elements of a ziplist, the listpack's predecessor: a 10 byte header, then each element
+/// as the previous one's length, an encoding byte and its data, then 0xff.
+fn ziplist(blob: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(blob);
+    reader.take(10)?;
+
+    let mut elements = Vec::new();
+    loop {
+        match reader.u8()? {
+            0xff => break,
+            0xfe => {
+                reader.take(4)?;
+            }
+            _ => {}
+        }
+
+        let encoding = reader.u8()?;
+        let element = match encoding >> 6 {
+            0 => reader.take((encoding & 0x3f) as usize)?.to_vec(),
+            1 => {
+                let len = ((encoding as usize & 0x3f) << 8) | reader.u8()? as usize;
+                reader.take(len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(reader.take(4)?.try_into()?);
+                reader.take(len as usize)?.to_vec()
+            }
+            _ => {
+                let n = match encoding {
+                    0xc0 => i16::from_le_bytes(reader.take(2)?.try_into()?) as i64,
+                    0xd0 => i32::from_le_bytes(reader.take(4)?.try_into()?) as i64,
+                    0xe0 => i64::from_le_bytes(reader.take(8)?.try_into()?),
+                    0xf0 => {
+                        let b = reader.take(3)?;
+                        (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as i64
+                    }
+                    0xfe => reader.u8()? as i8 as i64,
+                    // 0 to 12, stored as 1 to 13 in the low bits
+                    0xf1..=0xfd => (encoding & 0x0f) as i64 - 1,
+                    b => anyhow::bail!("bad ziplist encoding {b:#x}"),
+                };
+                n.to_string().into_bytes()
+            }
+        };
+        elements.push(element);
+    }
+
+    Ok(elements)
+}
+
+/// The members of an intset: the width of each, how many there are, then the sorted members.
+fn intset(blob: &[u8]) -> anyhow::Result<Vec<i64>> {
+    let mut reader = Reader::new(blob);
+    let width = u32::from_le_bytes(reader.take(4)?.try_into()?) as usize;
+    let len = u32::from_le_bytes(reader.take(4)?.try_into()?);
+
+    (0..len)
+        .map(|_| {
+            let bytes = reader.take(width)?;
+            Ok(match width {
+                2 => i16::from_le_bytes(bytes.try_into()?) as i64,
+                4 => i32::from_le_bytes(bytes.try_into()?) as i64,
+                8 => i64::from_le_bytes(bytes.try_into()?),
+                _ => anyhow::bail!("bad intset width {width}"),
+            })
+        })
+        .collect()
+}
+
+/// Undoes LZF compression, which Redis applies to longer strings: runs of literal bytes
+/// alternate with back-references into what's been decompressed so far.
+fn lzf_decompress(input: &[u8], len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut reader = Reader::new(input);
+
+    while !reader.buf.is_empty() {
+        let control = reader.u8()? as usize;
+        if control < 32 {
+            out.extend_from_slice(reader.take(control + 1)?);
+            continue;
+        }
+
+        let mut run = control >> 5;
+        if run == 7 {
+            run += reader.u8()? as usize;
+        }
+        let back = ((control & 0x1f) << 8) + reader.u8()? as usize + 1;
+        let start = out
+            .len()
+            .checked_sub(back)
+            .ok_or_else(|| anyhow::anyhow!("LZF reference before the start"))?;
+        // The reference can overlap what it's copying into, so go a byte at a time
+        for i in 0..run + 2 {
+            out.push(out[start + i]);
+        }
+    }
+
+    if out.len() != len {
+        anyhow::bail!("LZF string decompressed to the wrong length");
+    }
+
+    Ok(out)
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// How many bytes are left to read.
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            anyhow::bail!("unexpected end of file");
+        }
+        let (taken, rest) = self.buf.split_at(n);
+        self.buf = rest;
+
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn millis(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub fn len(&mut self) -> anyhow::Result<u64> {
+        match self.length()? {
+            Length::Plain(len) => Ok(len),
+            Length::Special(_) => anyhow::bail!("expected a length, found an encoded string"),
+        }
+    }
+
+    pub fn string(&mut self) -> anyhow::Result<Vec<u8>> {
+        let n = match self.length()? {
+            Length::Plain(len) => return Ok(self.take(usize::try_from(len)?)?.to_vec()),
+            Length::Special(ENC_INT8) => self.u8()? as i8 as i64,
+            Length::Special(ENC_INT16) => i16::from_le_bytes(self.take(2)?.try_into()?) as i64,
+            Length::Special(ENC_INT32) => i32::from_le_bytes(self.take(4)?.try_into()?) as i64,
+            Length::Special(ENC_LZF) => {
+                let compressed = usize::try_from(self.len()?)?;
+                let len = usize::try_from(self.len()?)?;
+                return lzf_decompress(self.take(compressed)?, len);
+            }
+            Length::Special(encoding) => anyhow::bail!("unknown string encoding {encoding}"),
+        };
+
+        Ok(n.to_string().into_bytes())
+    }
+
+    fn stream_id(&mut self) -> anyhow::Result<StreamId> {
+        Ok(StreamId::new(self.len()?, self.len()?))
+    }
+
+    /// Old sorted sets write scores as text behind a one byte length, with three lengths
+    /// standing for the non-finite values instead.
+    fn text_double(&mut self) -> anyhow::Result<f64> {
+        Ok(match self.u8()? {
+            253 => f64::NAN,
+            254 => f64::INFINITY,
+            255 => f64::NEG_INFINITY,
+            len => std::str::from_utf8(self.take(len as usize)?)?.parse()?,
+        })
+    }
+
+    fn length(&mut self) -> anyhow::Result<Length> {
+        let first = self.u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Plain((first & 0x3f) as u64),
+            1 => Length::Plain((((first & 0x3f) as u64) << 8) | self.u8()? as u64),
+            3 => Length::Special(first & 0x3f),
+            _ => match first {
+                0x80 => Length::Plain(u32::from_be_bytes(self.take(4)?.try_into()?) as u64),
+                0x81 => Length::Plain(u64::from_be_bytes(self.take(8)?.try_into()?)),
+                b => anyhow::bail!("bad length encoding {b:#x}"),
+            },
+        })
+    }
+}
+
+enum Length {
+    Plain(u64),
+    /// A string stored some other way than its bytes behind their length.
+    Special(u8),
+}
@@ -0,0 +1,123 @@
+use crate::backup::Server;
+use bytes::BytesMut;
+use redis::resp::{Stream, Value, parse_message};
+use redis::trace::{Entry, Reader};
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    #[command(flatten)]
+    server: Server,
+
+    /// How many times faster than it was recorded to send the trace, or 0 to send it as fast
+    /// as possible
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// The trace a server wrote to its record-file
+    file: PathBuf,
+}
+
+/// Runs `replay`: the commands in a trace sent to the server, each on a connection of its own
+/// for each client that sent them, as far apart as they were sent, over `speed`.
+pub fn run(options: Options) -> anyhow::Result<()> {
+    if !options.speed.is_finite() || options.speed < 0.0 {
+        anyhow::bail!("Invalid speed {}", options.speed);
+    }
+    let file = File::open(&options.file)
+        .map_err(|e| anyhow::anyhow!("Cannot open {}: {e}", options.file.display()))?;
+    let trace = Reader::new(file)?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(replay(&options, trace))
+}
+
+async fn replay(options: &Options, trace: Reader<File>) -> anyhow::Result<()> {
+    let started = Instant::now();
+    // When the entry was sent, counting from the first
+    let mut sent_at = Duration::ZERO;
+    let mut connections: HashMap<u64, WriteHalf<Box<dyn Stream>>> = HashMap::new();
+    let mut readers = JoinSet::new();
+    let mut sent = 0;
+
+    for entry in trace {
+        let Entry {
+            delay,
+            client,
+            args,
+        } = entry?;
+        sent_at += delay;
+        if options.speed > 0.0 {
+            tokio::time::sleep_until(started + sent_at.div_f64(options.speed)).await;
+        }
+
+        let writer = match connections.entry(client) {
+            hash_map::Entry::Occupied(writer) => writer.into_mut(),
+            hash_map::Entry::Vacant(slot) => {
+                let (stream, buf) = options.server.connect().await?.into_parts();
+                let (reader, writer) = tokio::io::split(stream);
+                readers.spawn(count_replies(reader, buf));
+                slot.insert(writer)
+            }
+        };
+        let command = args
+            .into_iter()
+            .map(|arg| Value::BulkString(arg.into()))
+            .collect();
+        // One that's gone, like after a QUIT, just doesn't get the rest
+        let _ = writer
+            .write_all(&Value::Array(command).serialise(false))
+            .await;
+        sent += 1;
+    }
+
+    // Each connection is closed once the server's answered all that was sent on it
+    for writer in connections.values_mut() {
+        let _ = writer.shutdown().await;
+    }
+    let (mut replies, mut errors) = (0, 0);
+    while let Some(counted) = readers.join_next().await {
+        let (connection_replies, connection_errors) = counted?;
+        replies += connection_replies;
+        errors += connection_errors;
+    }
+
+    eprintln!(
+        "Replayed {sent} commands from {} clients in {:.2} seconds",
+        connections.len(),
+        started.elapsed().as_secs_f64()
+    );
+    eprintln!("errors: {errors}, replies: {replies}");
+    Ok(())
+}
+
+/// Reads replies until the server closes the connection, returning how many there were and how
+/// many of them were errors.
+async fn count_replies(mut reader: ReadHalf<Box<dyn Stream>>, mut buf: BytesMut) -> (u64, u64) {
+    let (mut replies, mut errors) = (0, 0);
+    loop {
+        match parse_message(&buf) {
+            Ok(Some((reply, len))) => {
+                let _ = buf.split_to(len);
+                replies += 1;
+                if reply.error_message().is_some() {
+                    errors += 1;
+                }
+            }
+            Ok(None) => match reader.read_buf(&mut buf).await {
+                Ok(0) | Err(_) => return (replies, errors),
+                Ok(_) => {}
+            },
+            Err(_) => return (replies, errors),
+        }
+    }
+}
@@ -0,0 +1,637 @@
+use crate::cmd;
+use crate::config;
+use crate::db::{self, Db};
+use crate::failover;
+use crate::output::{self, Buffer};
+use crate::rand;
+use crate::resp::Value;
+use crate::snapshot;
+use bytes::Bytes;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, mpsc, oneshot, watch};
+use tracing::info;
+
+/// The reply to a write sent to a read-only replica.
+pub const READONLY: &str = "READONLY You can't write against a read only replica.";
+
+/// The reply to a write sent to a master without enough replicas keeping up.
+pub const NOREPLICAS: &str = "NOREPLICAS Not enough good replicas to write.";
+
+/// Whether a replica turns away writes from anyone but its master.
+pub static READ_ONLY: AtomicBool = AtomicBool::new(true);
+
+/// How many replicas have to have acknowledged within [`MIN_REPLICAS_MAX_LAG`] seconds for a
+/// master to take writes, or 0 to take them regardless.
+pub static MIN_REPLICAS: AtomicU64 = AtomicU64::new(0);
+pub static MIN_REPLICAS_MAX_LAG: AtomicU64 = AtomicU64::new(10);
+
+/// How often replicas are pinged, so they can tell the link is still up when there are no
+/// writes to send them.
+const PING_PERIOD: Duration = Duration::from_secs(10);
+
+/// The ID of the history of writes this server's offset counts through, and the one it
+/// followed before that.
+static IDS: LazyLock<Mutex<Ids>> = LazyLock::new(|| {
+    Mutex::new(Ids {
+        replid: random_id(),
+        replid2: "0".repeat(40),
+        second_offset: None,
+    })
+});
+
+struct Ids {
+    replid: String,
+    /// The ID this server's history went by until it was promoted or its master's was.
+    replid2: String,
+    /// The first offset that isn't part of the history `replid2` names.
+    second_offset: Option<u64>,
+}
+
+/// Woken whenever a replica acknowledges how far it's got.
+static ACKED: Notify = Notify::const_new();
+
+/// The database the master's stream last selected, kept for when the link comes back and the
+/// stream carries on from where it was.
+static STREAM_DB: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Whether the command running came down the master's stream. Set on the thread that runs
+    /// it, like the selected database.
+    static APPLYING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// How many bytes of replication stream there have been so far.
+static OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// The master REPLICAOF last named, or `None` while this server is a master itself.
+static FOLLOWING: LazyLock<watch::Sender<Option<(String, u16)>>> =
+    LazyLock::new(|| watch::Sender::new(None));
+
+/// How the link to the master is doing, and when anything last came over it.
+static LINK: Mutex<(Link, Option<Instant>)> = Mutex::new((Link::Down, None));
+
+/// When the link to the master last went down, or `None` if it's up or never was.
+static DOWN_SINCE: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Link {
+    Down,
+    Connecting,
+    /// Loading the snapshot the master sent.
+    Syncing,
+    Up,
+}
+
+/// The replicas being streamed to, and where that stream is up to.
+static MASTER: Mutex<Master> = Mutex::new(Master {
+    replicas: Vec::new(),
+    waiting: Vec::new(),
+    db: None,
+    backlog: None,
+    backlog_size: 1024 * 1024,
+});
+
+struct Master {
+    replicas: Vec<Replica>,
+    /// Replicas waiting for the next snapshot, with where to send it.
+    waiting: Vec<(Replica, oneshot::Sender<Snapshot>)>,
+    /// The database the stream last selected, if any.
+    db: Option<usize>,
+    /// The latest `backlog_size` bytes of the stream, for replicas that come back after losing
+    /// their link. There's none until the first replica attaches.
+    backlog: Option<VecDeque<u8>>,
+    backlog_size: usize,
+}
+
+struct Replica {
+    /// The CLIENT ID of the connection it synced over.
+    id: u64,
+    addr: SocketAddr,
+    /// The port it takes connections on, as it told us with REPLCONF listening-port.
+    port: u16,
+    stream: mpsc::UnboundedSender<Vec<u8>>,
+    /// What's been sent down `stream` and not yet written out.
+    output: Arc<Buffer>,
+    /// The offset it last acknowledged having processed up to, and when.
+    acked: u64,
+    acked_at: Instant,
+}
+
+/// Woken when a replica starts waiting for a snapshot.
+static SNAPSHOT_WANTED: Notify = Notify::const_new();
+
+/// How a replica starts off, and the stream of writes that follows.
+pub struct Sync {
+    pub start: Start,
+    pub writes: mpsc::UnboundedReceiver<Vec<u8>>,
+    /// Counts what's in `writes`, which is to be taken off as it's written out.
+    pub output: Arc<Buffer>,
+}
+
+pub enum Start {
+    /// It carries on from the backlog under this replication ID, and the stream starts with
+    /// what it missed.
+    Continue(String),
+    /// It loads a snapshot, once the next one is taken, and the stream picks up from there.
+    Full(oneshot::Receiver<Snapshot>),
+}
+
+/// A snapshot of the dataset as of `offset` in the history `replid` names. A diskless one never
+/// went through the dump file.
+pub struct Snapshot {
+    pub replid: String,
+    pub offset: u64,
+    pub bytes: Arc<Vec<u8>>,
+    pub diskless: bool,
+}
+
+/// A replica as INFO shows it.
+pub struct ReplicaInfo {
+    pub addr: SocketAddr,
+    pub port: u16,
+    /// `wait_bgsave` until it has a snapshot to start from, then `online`.
+    pub state: &'static str,
+    pub offset: u64,
+    /// Seconds since it last acknowledged.
+    pub lag: u64,
+}
+
+/// A fresh replication ID: 40 random hex digits.
+pub fn random_id() -> String {
+    (0..40)
+        .map(|_| char::from_digit(rand::below(16) as u32, 16).unwrap())
+        .collect()
+}
+
+pub fn replid() -> String {
+    IDS.lock().unwrap().replid.clone()
+}
+
+/// The ID this server's history went by before, and the offset it stopped applying at.
+pub fn second_id() -> (String, Option<u64>) {
+    let ids = IDS.lock().unwrap();
+    (ids.replid2.clone(), ids.second_offset)
+}
+
+/// Starts a new history from here, keeping the old ID for replicas that followed it up to now.
+fn shift_replid(new: String) {
+    let mut ids = IDS.lock().unwrap();
+    ids.replid2 = std::mem::replace(&mut ids.replid, new);
+    ids.second_offset = Some(offset() + 1);
+}
+
+/// The first offset the backlog holds, and how many bytes it holds, or `None` if there's no
+/// backlog.
+pub fn backlog() -> Option<(u64, u64)> {
+    let master = MASTER.lock().unwrap();
+    let len = master.backlog.as_ref()?.len() as u64;
+    Some((offset() - len + 1, len))
+}
+
+pub fn set_backlog_size(size: usize) {
+    let mut master = MASTER.lock().unwrap();
+    master.backlog_size = size;
+    master.trim_backlog();
+}
+
+pub fn stream_db() -> usize {
+    STREAM_DB.load(Ordering::Relaxed)
+}
+
+pub fn set_stream_db(db: usize) {
+    STREAM_DB.store(db, Ordering::Relaxed);
+}
+
+/// Marks the commands run from now on as coming from the master's stream, or not.
+pub fn set_applying(applying: bool) {
+    APPLYING.set(applying);
+}
+
+pub fn applying() -> bool {
+    APPLYING.get()
+}
+
+pub fn offset() -> u64 {
+    OFFSET.load(Ordering::Relaxed)
+}
+
+/// Makes this server follow `master`, or stop following one if `None`. Returns false if it
+/// was already following that master.
+pub fn follow(master: Option<(String, u16)>) -> bool {
+    let mut promoted = false;
+    let changed = FOLLOWING.send_if_modified(|following| {
+        promoted = following.is_some() && master.is_none();
+        let changed = *following != master;
+        *following = master.clone();
+        changed
+    });
+    // Replicas of the old master can carry on with this one up to here
+    if promoted {
+        shift_replid(random_id());
+    }
+
+    changed
+}
+
+/// The master this server follows, changing whenever REPLICAOF names another.
+pub fn following() -> watch::Receiver<Option<(String, u16)>> {
+    FOLLOWING.subscribe()
+}
+
+pub fn master() -> Option<(String, u16)> {
+    FOLLOWING.borrow().clone()
+}
+
+pub fn is_replica() -> bool {
+    FOLLOWING.borrow().is_some()
+}
+
+/// Why `name` has to be turned away, if it does: it's a write sent to a read-only replica by
+/// anyone but its master, or to a master without enough replicas keeping up.
+pub fn refusal(name: &str) -> Option<Value> {
+    if !cmd::is_write(name) {
+        return None;
+    }
+    if is_replica() {
+        return READ_ONLY
+            .load(Ordering::Relaxed)
+            .then(|| Value::error(READONLY));
+    }
+
+    let wanted = MIN_REPLICAS.load(Ordering::Relaxed);
+    (wanted > 0 && (good_replicas() as u64) < wanted).then(|| Value::error(NOREPLICAS))
+}
+
+/// How many replicas have acknowledged within [`MIN_REPLICAS_MAX_LAG`] seconds.
+pub fn good_replicas() -> usize {
+    let max_lag = MIN_REPLICAS_MAX_LAG.load(Ordering::Relaxed);
+    let master = MASTER.lock().unwrap();
+    master
+        .replicas
+        .iter()
+        .filter(|replica| replica.acked_at.elapsed().as_secs() <= max_lag)
+        .count()
+}
+
+pub fn set_link(link: Link) {
+    let mut current = LINK.lock().unwrap();
+    let mut down_since = DOWN_SINCE.lock().unwrap();
+    if link == Link::Up {
+        *down_since = None;
+    } else if current.0 == Link::Up {
+        *down_since = Some(Instant::now());
+    }
+    current.0 = link;
+}
+
+/// Notes that something just came over the link from the master.
+pub fn touch_link() {
+    LINK.lock().unwrap().1 = Some(Instant::now());
+}
+
+/// How the link to the master is doing, and seconds since anything last came over it.
+pub fn link() -> (Link, Option<u64>) {
+    let (link, last_io) = *LINK.lock().unwrap();
+    (link, last_io.map(|at| at.elapsed().as_secs()))
+}
+
+/// Seconds since the link to the master went down, or `None` if it's up or never came up.
+pub fn link_down_for() -> Option<u64> {
+    DOWN_SINCE
+        .lock()
+        .unwrap()
+        .map(|since| since.elapsed().as_secs())
+}
+
+/// Takes on the replication ID and offset of the master this server just synced with from
+/// scratch. Its own replicas were synced to a dataset that's now gone, so they're dropped to
+/// sync again.
+pub fn synced(replid: String, offset: u64) {
+    let mut ids = IDS.lock().unwrap();
+    ids.replid = replid;
+    ids.replid2 = "0".repeat(40);
+    ids.second_offset = None;
+    OFFSET.store(offset, Ordering::Relaxed);
+    STREAM_DB.store(0, Ordering::Relaxed);
+
+    let mut master = MASTER.lock().unwrap();
+    master.replicas.clear();
+    master.waiting.clear();
+    master.db = None;
+    master.backlog = Some(VecDeque::new());
+}
+
+/// Carries on following a master that took the stream up where it was, under `replid` if it
+/// has a new one since it was promoted.
+pub fn continued(replid: &str) {
+    if replid != self::replid() {
+        shift_replid(replid.to_string());
+    }
+}
+
+/// Passes `bytes` of the master's stream on to this server's own replicas as they are, once
+/// they've been applied here, so the offset stays the same all the way down.
+pub fn proxy(bytes: &[u8]) {
+    let mut master = MASTER.lock().unwrap();
+    master.send(bytes.to_vec());
+    // What the master had selected is unknown, so the next write of our own selects afresh
+    master.db = None;
+}
+
+/// Sends a command that changed the dataset in database `db` to every replica. It's in the
+/// form [`crate::propagate`] gives it, which has the same effect whenever it's run.
+pub fn feed(db: usize, command: &[Vec<u8>]) {
+    let mut master = MASTER.lock().unwrap();
+    // Nobody would ever see these bytes, so they aren't counted either. A replica's own
+    // replicas get the master's stream through `proxy` instead.
+    if master.backlog.is_none() || is_replica() {
+        return;
+    }
+
+    let mut out = Vec::new();
+    if master.db != Some(db) {
+        out.extend(encode(&[b"select".to_vec(), db.to_string().into_bytes()]));
+        master.db = Some(db);
+    }
+    out.extend(encode(command));
+    master.send(out);
+}
+
+/// Pings every replica once in a while. Runs for as long as the server does.
+pub async fn ping_replicas() {
+    let mut interval = tokio::time::interval(PING_PERIOD);
+    loop {
+        interval.tick().await;
+        let mut master = MASTER.lock().unwrap();
+        // A failover waits for the stream to stand still
+        if !master.replicas.is_empty() && !is_replica() && !failover::in_progress() {
+            master.send(encode(&[b"ping".to_vec()]));
+        }
+    }
+}
+
+/// Starts streaming to the replica on connection `id`. If it asked to carry on from `psync`, a
+/// replication ID and the offset after the last byte it has, and the backlog still holds
+/// everything since, it gets what it missed; otherwise it waits for [`take_snapshots`] to take
+/// the next snapshot.
+pub fn attach(id: u64, addr: SocketAddr, port: u16, psync: Option<(&str, u64)>) -> Sync {
+    let (stream, writes) = mpsc::unbounded_channel();
+    let output = Arc::new(Buffer::new(output::Class::Replica));
+    let replica = Replica {
+        id,
+        addr,
+        port,
+        stream,
+        output: output.clone(),
+        acked: 0,
+        acked_at: Instant::now(),
+    };
+
+    let mut master = MASTER.lock().unwrap();
+    if master.backlog.is_none() {
+        master.backlog = Some(VecDeque::new());
+    }
+    let missed = psync.and_then(|(replid, offset)| master.since(replid, offset));
+    let start = match missed {
+        Some(missed) => {
+            replica.send(missed);
+            master.replicas.push(replica);
+            info!("Partial resynchronization request from {addr} accepted");
+            Start::Continue(replid())
+        }
+        None => {
+            let (ready, snapshot) = oneshot::channel();
+            master.waiting.push((replica, ready));
+            SNAPSHOT_WANTED.notify_one();
+            info!("Full resync requested by replica {addr}");
+            Start::Full(snapshot)
+        }
+    };
+
+    Sync {
+        start,
+        writes,
+        output,
+    }
+}
+
+/// Takes a snapshot for the replicas waiting for one whenever there are any. With diskless sync
+/// on it first gives more replicas up to `repl-diskless-sync-delay` seconds to turn up, so they
+/// can all share it; otherwise it goes through the dump file, as a save. Runs for as long as
+/// the server does.
+pub async fn take_snapshots(db: Db) {
+    loop {
+        SNAPSHOT_WANTED.notified().await;
+
+        let (diskless, delay, max_replicas) = {
+            let config = config::get();
+            (
+                config.repl_diskless_sync,
+                Duration::from_secs(config.repl_diskless_sync_delay),
+                config.repl_diskless_sync_max_replicas,
+            )
+        };
+        if diskless {
+            let deadline = tokio::time::Instant::now() + delay;
+            while tokio::time::Instant::now() < deadline
+                && (max_replicas == 0 || MASTER.lock().unwrap().waiting.len() < max_replicas)
+            {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+
+        // No write falls between the snapshot and the stream that follows it
+        let (frozen, dirty, waiting) = {
+            let dbs = db.lock_all().await;
+            let mut master = MASTER.lock().unwrap();
+            if master.waiting.is_empty() {
+                continue;
+            }
+            let target = if diskless { "replicas sockets" } else { "disk" };
+            info!("Starting BGSAVE for SYNC with target: {target}");
+
+            let frozen = snapshot::freeze(&dbs);
+            // The snapshot says nothing of which database is selected
+            master.db = None;
+            let waiting = std::mem::take(&mut master.waiting);
+            let mut readies = Vec::with_capacity(waiting.len());
+            for (replica, ready) in waiting {
+                master.replicas.push(replica);
+                readies.push(ready);
+            }
+            (frozen, db::dirty(), readies)
+        };
+
+        let (replid, offset) = (replid(), offset());
+        // Serialised off the frozen dataset with the locks let go of, so clients go on meanwhile
+        let bytes = tokio::task::spawn_blocking(move || {
+            let bytes = snapshot::encode(&frozen, false);
+            if diskless {
+                Ok(bytes)
+            } else {
+                snapshot::save_for_sync(bytes, dirty)
+            }
+        })
+        .await
+        .expect("saving for replicas panicked");
+        // A replica whose snapshot failed is dropped when its sender is
+        let Ok(bytes) = bytes else {
+            continue;
+        };
+        let bytes = Arc::new(bytes);
+        for ready in waiting {
+            let _ = ready.send(Snapshot {
+                replid: replid.clone(),
+                offset,
+                bytes: bytes.clone(),
+                diskless,
+            });
+        }
+    }
+}
+
+/// Stops streaming to the replica on connection `id`, once its connection is gone.
+pub fn detach(id: u64) {
+    let mut master = MASTER.lock().unwrap();
+    master.waiting.retain(|(replica, _)| replica.id != id);
+    if let Some(at) = master.replicas.iter().position(|replica| replica.id == id) {
+        let replica = master.replicas.remove(at);
+        info!("Connection with replica {} lost.", replica.addr);
+    }
+}
+
+/// Records that the replica on connection `id` has processed the stream up to `offset`.
+pub fn ack(id: u64, offset: u64) {
+    let mut master = MASTER.lock().unwrap();
+    if let Some(replica) = master.replicas.iter_mut().find(|replica| replica.id == id) {
+        replica.acked = offset;
+        replica.acked_at = Instant::now();
+    }
+    ACKED.notify_waiters();
+}
+
+/// How many replicas have acknowledged the stream up to `offset`.
+pub fn acked(offset: u64) -> usize {
+    let master = MASTER.lock().unwrap();
+    master
+        .replicas
+        .iter()
+        .filter(|replica| replica.acked >= offset)
+        .count()
+}
+
+/// Waits for `wanted` replicas to acknowledge the stream up to `offset`, asking every replica
+/// to say how far it's got, and returns how many did before `timeout` passed.
+pub async fn wait_for_acks(offset: u64, wanted: usize, timeout: Option<Duration>) -> usize {
+    if acked(offset) >= wanted {
+        return acked(offset);
+    }
+    {
+        let mut master = MASTER.lock().unwrap();
+        if !master.replicas.is_empty() && !failover::in_progress() {
+            master.send(encode(&[
+                b"REPLCONF".to_vec(),
+                b"GETACK".to_vec(),
+                b"*".to_vec(),
+            ]));
+        }
+    }
+
+    let enough = async {
+        loop {
+            let notified = ACKED.notified();
+            tokio::pin!(notified);
+            // Registered before counting, so no acknowledgement slips in between
+            notified.as_mut().enable();
+            if acked(offset) >= wanted {
+                return;
+            }
+            notified.await;
+        }
+    };
+    if let Some(timeout) = timeout {
+        let _ = tokio::time::timeout(timeout, enough).await;
+    } else {
+        enough.await;
+    }
+
+    acked(offset)
+}
+
+pub fn replicas() -> Vec<ReplicaInfo> {
+    let master = MASTER.lock().unwrap();
+    let waiting = master
+        .waiting
+        .iter()
+        .map(|(replica, _)| (replica, "wait_bgsave"));
+    let online = master.replicas.iter().map(|replica| (replica, "online"));
+
+    online
+        .chain(waiting)
+        .map(|(replica, state)| ReplicaInfo {
+            addr: replica.addr,
+            port: replica.port,
+            state,
+            offset: replica.acked,
+            lag: replica.acked_at.elapsed().as_secs(),
+        })
+        .collect()
+}
+
+impl Replica {
+    /// Queues `bytes` for the replica, returning whether it's still there to take them and
+    /// within its limits.
+    fn send(&self, bytes: Vec<u8>) -> bool {
+        self.output.queue(bytes.len()) && self.stream.send(bytes).is_ok()
+    }
+}
+
+impl Master {
+    /// Adds `bytes` to the stream, dropping replicas whose connections have gone or that have
+    /// fallen past their output buffer limits.
+    fn send(&mut self, bytes: Vec<u8>) {
+        OFFSET.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        if let Some(backlog) = &mut self.backlog {
+            backlog.extend(&bytes);
+            self.trim_backlog();
+        }
+        self.replicas.retain(|replica| replica.send(bytes.clone()));
+    }
+
+    fn trim_backlog(&mut self) {
+        if let Some(backlog) = &mut self.backlog {
+            let excess = backlog.len().saturating_sub(self.backlog_size);
+            backlog.drain(..excess);
+        }
+    }
+
+    /// The stream from offset `from` on, if it belongs to the history `replid` names and the
+    /// backlog still reaches back that far.
+    fn since(&self, replid: &str, from: u64) -> Option<Vec<u8>> {
+        let ids = IDS.lock().unwrap();
+        let known = replid == ids.replid
+            || (replid == ids.replid2 && ids.second_offset.is_some_and(|second| from <= second));
+        let backlog = self.backlog.as_ref()?;
+        let first = offset() - backlog.len() as u64 + 1;
+        if !known || from < first || from > offset() + 1 {
+            return None;
+        }
+
+        Some(backlog.range((from - first) as usize..).copied().collect())
+    }
+}
+
+fn encode(command: &[Vec<u8>]) -> Vec<u8> {
+    Value::Array(
+        command
+            .iter()
+            .map(|part| Value::BulkString(Bytes::copy_from_slice(part)))
+            .collect(),
+    )
+    .serialise(false)
+}
@@ -1,113 +1,456 @@
-use bytes::BytesMut;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use bytes::{Bytes, BytesMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The longest bulk string a client may send, from `proto-max-bulk-len`.
+pub(crate) static MAX_BULK_LEN: AtomicU64 = AtomicU64::new(512 * 1024 * 1024);
+
+/// The longest a client's header line, like a bulk string's length, may get without ending.
+const MAX_LINE: usize = 64 * 1024;
+
+/// The most items a client's array may claim to have.
+const MAX_ITEMS: i64 = i32::MAX as i64;
 
 #[derive(Debug, Clone)]
 pub enum Value {
     SimpleString(String),
-    BulkString(String),
+    /// An error reply, its message starting with a code like `ERR` or `WRONGTYPE`.
+    Error(String),
+    /// Reference counted, so a reply can share a stored value rather than copy it.
+    BulkString(Bytes),
+    Integer(i64),
     Array(Vec<Value>),
+    /// Out-of-band data such as pub/sub messages: a RESP3 push, or a plain array under RESP2.
+    Push(Vec<Value>),
+    /// A RESP3 map, sent as a flat key/value array under RESP2.
+    Map(Vec<(Value, Value)>),
+    Null,
+    NullArray,
 }
 
 impl Value {
-    pub fn serialise(self) -> String {
+    /// An error reply with `msg`, whose line breaks, which it can't have on the wire, are sent
+    /// as spaces.
+    pub fn error(msg: impl AsRef<str>) -> Value {
+        Value::Error(msg.as_ref().replace(['\r', '\n'], " "))
+    }
+
+    /// The message of an error reply, or `None` if this isn't one.
+    pub fn error_message(&self) -> Option<String> {
         match self {
-            Value::SimpleString(s) => format!("+{s}\r\n"),
-            Value::BulkString(s) => format!("${}\r\n{}\r\n", s.chars().count(), s),
-            _ => panic!("Unsupported token"),
+            Value::Error(msg) => Some(msg.clone()),
+            _ => None,
+        }
+    }
+
+    /// Roughly how many bytes the value takes up on the wire, for output buffer limits.
+    pub fn size(&self) -> usize {
+        // Type byte, a length or the value itself up to 20 digits, and the CRLFs
+        const FRAMING: usize = 1 + 20 + 4;
+        match self {
+            Value::SimpleString(s) | Value::Error(s) => FRAMING + s.len(),
+            Value::BulkString(s) => FRAMING + s.len(),
+            Value::Array(items) | Value::Push(items) => {
+                FRAMING + items.iter().map(Value::size).sum::<usize>()
+            }
+            Value::Map(pairs) => {
+                FRAMING
+                    + pairs
+                        .iter()
+                        .map(|(k, v)| k.size() + v.size())
+                        .sum::<usize>()
+            }
+            Value::Integer(_) | Value::Null | Value::NullArray => FRAMING,
+        }
+    }
+
+    /// The value on the wire, in RESP3 if `resp3` and otherwise RESP2.
+    pub fn serialise(self, resp3: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.serialise_into(&mut out, resp3);
+        out
+    }
+
+    fn serialise_into(self, out: &mut Vec<u8>, resp3: bool) {
+        match self {
+            Value::SimpleString(s) => out.extend_from_slice(format!("+{s}\r\n").as_bytes()),
+            Value::Error(msg) => out.extend_from_slice(format!("-{msg}\r\n").as_bytes()),
+            Value::BulkString(s) => {
+                out.extend_from_slice(format!("${}\r\n", s.len()).as_bytes());
+                out.extend_from_slice(&s);
+                out.extend_from_slice(b"\r\n");
+            }
+            Value::Integer(n) => out.extend_from_slice(format!(":{n}\r\n").as_bytes()),
+            Value::Array(a) => {
+                out.extend_from_slice(format!("*{}\r\n", a.len()).as_bytes());
+                for v in a {
+                    v.serialise_into(out, resp3);
+                }
+            }
+            Value::Push(a) => {
+                let kind = if resp3 { '>' } else { '*' };
+                out.extend_from_slice(format!("{kind}{}\r\n", a.len()).as_bytes());
+                for v in a {
+                    v.serialise_into(out, resp3);
+                }
+            }
+            Value::Map(pairs) => {
+                let header = if resp3 {
+                    format!("%{}\r\n", pairs.len())
+                } else {
+                    format!("*{}\r\n", pairs.len() * 2)
+                };
+                out.extend_from_slice(header.as_bytes());
+                for (k, v) in pairs {
+                    k.serialise_into(out, resp3);
+                    v.serialise_into(out, resp3);
+                }
+            }
+            Value::Null | Value::NullArray if resp3 => out.extend_from_slice(b"_\r\n"),
+            Value::Null => out.extend_from_slice(b"$-1\r\n"),
+            Value::NullArray => out.extend_from_slice(b"*-1\r\n"),
         }
     }
 }
 
+/// A connection RESP can be spoken over, like a TCP or Unix socket.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
 pub struct RespHandler {
-    stream: TcpStream,
+    stream: Box<dyn Stream>,
     buf: BytesMut,
+    /// The arrays a message read so far is partway through, outermost first: how many items
+    /// each is still to have, and the ones it has. What they've taken is gone from `buf`, so
+    /// a message that comes in over many reads is only parsed once.
+    partial: Vec<(usize, Vec<Value>)>,
+    /// Hold what's read to the limits on what clients may send.
+    limited: bool,
+    /// Drop writes instead of sending them, as CLIENT REPLY OFF asks.
+    muted: bool,
+    /// Speak RESP3, as HELLO 3 asks.
+    resp3: bool,
 }
 
 impl RespHandler {
-    pub fn new(stream: TcpStream) -> RespHandler {
+    pub fn new(stream: impl Stream + 'static) -> RespHandler {
         RespHandler {
-            stream,
+            stream: Box::new(stream),
             buf: BytesMut::with_capacity(1024),
+            partial: Vec::new(),
+            limited: false,
+            muted: false,
+            resp3: false,
         }
     }
 
+    pub fn set_resp3(&mut self, resp3: bool) {
+        self.resp3 = resp3;
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Refuses messages past the limits on what a client may send: a bulk string longer than
+    /// `proto-max-bulk-len`, or a header line or array too long to be real.
+    pub fn limit(&mut self) {
+        self.limited = true;
+    }
+
+    /// The connection, and what's been read from it but not yet parsed, for those that need to
+    /// read and write it at once.
+    pub fn into_parts(self) -> (Box<dyn Stream>, BytesMut) {
+        (self.stream, self.buf)
+    }
+
+    /// Reads the next complete message, buffering partial frames and keeping any pipelined
+    /// data for subsequent calls. Returns `None` once the peer closes the connection.
     pub async fn read(&mut self) -> anyhow::Result<Option<Value>> {
-        let bytes_len = self.stream.read_buf(&mut self.buf).await?;
+        loop {
+            match self.parse_buffered() {
+                Ok(Some(v)) => return Ok(Some(v)),
+                Ok(None) => {}
+                Err(e) => {
+                    // There's no way to resync with a malformed stream, so drop what we have
+                    self.buf.clear();
+                    self.partial.clear();
 
-        if bytes_len == 0 {
-            return Ok(None);
+                    return Err(e);
+                }
+            }
+
+            let bytes_len = match self.stream.read_buf(&mut self.buf).await {
+                // TLS clients often hang up without saying so first, which is as good as closing
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+                read => read?,
+            };
+
+            if bytes_len == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Takes whatever of the next message is in the buffer off it, returning the message once
+    /// it's whole.
+    fn parse_buffered(&mut self) -> anyhow::Result<Option<Value>> {
+        let max_bulk_len = match self.limited {
+            true => Some(MAX_BULK_LEN.load(Ordering::Relaxed)),
+            false => None,
+        };
+        loop {
+            let Some(&kind) = self.buf.first() else {
+                return Ok(None);
+            };
+            let parsed = match kind {
+                b'*' => parse_array_header(&self.buf, self.limited)?,
+                _ => parse_scalar(&self.buf, max_bulk_len)?,
+            };
+            let Some((value, len)) = parsed else {
+                // Every message starts with a line, so one this long isn't coming
+                if self.limited && self.buf.len() > MAX_LINE && !has_crlf(&self.buf[..MAX_LINE]) {
+                    anyhow::bail!("too big header line");
+                }
+                return Ok(None);
+            };
+            let _ = self.buf.split_to(len);
+
+            // A non-empty array's items are what's parsed next
+            let mut value = match value {
+                Header::Array(len) => {
+                    self.partial.push((len, Vec::with_capacity(len.min(1024))));
+                    continue;
+                }
+                Header::Whole(value) => value,
+            };
+            // Into the innermost array being read, closing any it fills
+            loop {
+                let Some((left, items)) = self.partial.last_mut() else {
+                    return Ok(Some(value));
+                };
+                items.push(value);
+                *left -= 1;
+                if *left > 0 {
+                    break;
+                }
+                let (_, items) = self.partial.pop().expect("there's an innermost array");
+                value = Value::Array(items);
+            }
+        }
+    }
+
+    /// Resolves once the peer has closed the connection. Anything it sends in the meantime is
+    /// buffered for the next [`RespHandler::read`].
+    pub async fn closed(&mut self) {
+        loop {
+            match self.stream.read_buf(&mut self.buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Reads the snapshot a master sends a replica: a length line like a bulk string's, then
+    /// that many bytes, with nothing after them. One streamed as it was taken has `$EOF:` and a
+    /// 40-byte marker in place of the length, and ends with the marker instead.
+    pub async fn read_snapshot(&mut self) -> anyhow::Result<Vec<u8>> {
+        // How far into the buffer a marker has been looked for
+        let mut searched: usize = 0;
+        loop {
+            if let Some(end) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let header = &self.buf[..end];
+                if let Some(mark) = header.strip_prefix(b"$EOF:") {
+                    let mark = mark.to_vec();
+                    let body = &self.buf[end + 2..];
+                    let from = searched.saturating_sub(mark.len());
+                    if let Some(at) = body[from..].windows(mark.len()).position(|w| w == mark) {
+                        let _ = self.buf.split_to(end + 2);
+                        let snapshot = self.buf.split_to(from + at).to_vec();
+                        let _ = self.buf.split_to(mark.len());
+                        return Ok(snapshot);
+                    }
+                    searched = body.len();
+                } else {
+                    let Some(len) = header
+                        .strip_prefix(b"$")
+                        .and_then(|len| std::str::from_utf8(len).ok()?.parse::<usize>().ok())
+                    else {
+                        anyhow::bail!("bad snapshot length line");
+                    };
+                    if self.buf.len() >= end + 2 + len {
+                        let _ = self.buf.split_to(end + 2);
+                        return Ok(self.buf.split_to(len).to_vec());
+                    }
+                }
+            }
+
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                anyhow::bail!("connection closed mid-snapshot");
+            }
         }
+    }
 
-        let (v, _) = parse_message(self.buf.split())?;
+    /// Sends `bytes` as they are, for what isn't a RESP value, like the snapshot a replica
+    /// syncs from.
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.stream.write_all(bytes).await?;
 
-        Ok(Some(v))
+        Ok(())
     }
 
     pub async fn write(&mut self, value: Value) -> anyhow::Result<()> {
-        self.stream.write(value.serialise().as_bytes()).await?;
+        if self.muted {
+            return Ok(());
+        }
+
+        self.stream.write_all(&value.serialise(self.resp3)).await?;
 
         Ok(())
     }
 }
 
-fn parse_message(buf: BytesMut) -> anyhow::Result<(Value, usize)> {
-    match buf[0] as char {
-        '+' => parse_simple_string(buf),
-        '$' => parse_bulk_string(buf),
-        '*' => parse_array(buf),
-        _ => Err(anyhow::anyhow!("Invalid message: {:?}", buf)),
+/// Parses one message from the front of `buf`, returning it with the number of bytes it
+/// spans, or `None` if the buffer doesn't hold a complete message yet.
+pub fn parse_message(buf: &[u8]) -> anyhow::Result<Option<(Value, usize)>> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'*') => parse_array(buf),
+        Some(_) => Ok(parse_scalar(buf, None)?.map(|(header, len)| match header {
+            Header::Whole(value) => (value, len),
+            Header::Array(_) => unreachable!("arrays are parsed above"),
+        })),
     }
 }
 
-fn parse_simple_string(buf: BytesMut) -> anyhow::Result<(Value, usize)> {
-    if let Some((line, len)) = read_until_crlf(&buf) {
-        let string = String::from_utf8(line.to_vec())?;
+/// What the front of a buffer holds: a whole value, or the header of an array with this many
+/// items, which follow it.
+enum Header {
+    Whole(Value),
+    Array(usize),
+}
 
-        return Ok((Value::SimpleString(string), len));
-    }
+/// Parses a message that isn't an array from the front of `buf`, refusing a bulk string longer
+/// than `max_bulk_len`.
+fn parse_scalar(buf: &[u8], max_bulk_len: Option<u64>) -> anyhow::Result<Option<(Header, usize)>> {
+    let parsed = match buf[0] {
+        b'+' => parse_simple_string(buf)?,
+        b'-' => parse_error(buf)?,
+        b':' => parse_integer(buf)?,
+        b'$' => parse_bulk_string(buf, max_bulk_len)?,
+        _ => anyhow::bail!("Invalid message: {:?}", buf),
+    };
+
+    Ok(parsed.map(|(value, len)| (Header::Whole(value), len)))
+}
+
+/// Parses an array's header from the front of `buf`, with an empty or null array being whole
+/// in itself. A `limited` one may claim no more than [`MAX_ITEMS`].
+fn parse_array_header(buf: &[u8], limited: bool) -> anyhow::Result<Option<(Header, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buf[1..]) else {
+        return Ok(None);
+    };
+    let header = match parse_int(line)? {
+        -1 => Header::Whole(Value::NullArray),
+        0 => Header::Whole(Value::Array(Vec::new())),
+        items if items < 0 || (limited && items > MAX_ITEMS) => {
+            anyhow::bail!("invalid multibulk length")
+        }
+        items => Header::Array(items as usize),
+    };
+
+    Ok(Some((header, len + 1)))
+}
+
+fn parse_simple_string(buf: &[u8]) -> anyhow::Result<Option<(Value, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buf[1..]) else {
+        return Ok(None);
+    };
+
+    let string = String::from_utf8(line.to_vec())?;
 
-    Err(anyhow::anyhow!("Invalid string: {:?}", buf))
+    Ok(Some((Value::SimpleString(string), len + 1)))
 }
 
-fn parse_bulk_string(buf: BytesMut) -> anyhow::Result<(Value, usize)> {
-    let (bulk_str_len, bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buf[1..]) {
-        let bulk_str_len = parse_int(line)?;
+/// An error reply, as another server sends it.
+fn parse_error(buf: &[u8]) -> anyhow::Result<Option<(Value, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buf[1..]) else {
+        return Ok(None);
+    };
+
+    Ok(Some((
+        Value::Error(String::from_utf8_lossy(line).into_owned()),
+        len + 1,
+    )))
+}
+
+fn parse_integer(buf: &[u8]) -> anyhow::Result<Option<(Value, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buf[1..]) else {
+        return Ok(None);
+    };
 
-        (bulk_str_len, len + 1)
-    } else {
-        return Err(anyhow::anyhow!("Invalid bulk string format {:?}", buf));
+    Ok(Some((Value::Integer(parse_int(line)?), len + 1)))
+}
+
+fn parse_bulk_string(buf: &[u8], max_len: Option<u64>) -> anyhow::Result<Option<(Value, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buf[1..]) else {
+        return Ok(None);
     };
+    let bulk_str_len = parse_int(line)?;
+    let bytes_consumed = len + 1;
+
+    if bulk_str_len == -1 {
+        return Ok(Some((Value::Null, bytes_consumed)));
+    }
+    if bulk_str_len < 0 {
+        return Err(anyhow::anyhow!("Invalid bulk string length {bulk_str_len}"));
+    }
+    if max_len.is_some_and(|max| bulk_str_len as u64 > max) {
+        anyhow::bail!("invalid bulk length");
+    }
 
     let end_of_bulk_str = bytes_consumed + bulk_str_len as usize;
     let total_parsed = end_of_bulk_str + 2;
 
-    Ok((
-        Value::BulkString(String::from_utf8(
-            buf[bytes_consumed..end_of_bulk_str].to_vec(),
-        )?),
+    if buf.len() < total_parsed {
+        return Ok(None);
+    }
+
+    Ok(Some((
+        Value::BulkString(Bytes::copy_from_slice(
+            &buf[bytes_consumed..end_of_bulk_str],
+        )),
         total_parsed,
-    ))
+    )))
 }
 
-fn parse_array(buf: BytesMut) -> anyhow::Result<(Value, usize)> {
-    let (array_length, mut bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buf[1..]) {
-        let array_length = parse_int(line)?;
-
-        (array_length, len + 1)
-    } else {
-        return Err(anyhow::anyhow!("Invalid array format {:?}", buf));
+fn parse_array(buf: &[u8]) -> anyhow::Result<Option<(Value, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buf[1..]) else {
+        return Ok(None);
     };
+    let array_length = parse_int(line)?;
+    let mut bytes_consumed = len + 1;
+
+    if array_length == -1 {
+        return Ok(Some((Value::NullArray, bytes_consumed)));
+    }
 
     let mut items = vec![];
     for _ in 0..array_length {
-        let (array_item, len) = parse_message(BytesMut::from(&buf[bytes_consumed..]))?;
+        let Some((array_item, len)) = parse_message(&buf[bytes_consumed..])? else {
+            return Ok(None);
+        };
 
         items.push(array_item);
         bytes_consumed += len;
     }
 
-    Ok((Value::Array(items), bytes_consumed))
+    Ok(Some((Value::Array(items), bytes_consumed)))
+}
+
+fn has_crlf(buffer: &[u8]) -> bool {
+    buffer.windows(2).any(|pair| pair == b"\r\n")
 }
 
 fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
@@ -0,0 +1,169 @@
+//! The REST gateway: keys' string values over HTTP, for serverless functions, curl and other
+//! clients without a RESP client to hand.
+//!
+//! - `GET /keys/<key>` gives the value as it is,
+//! - `PUT /keys/<key>` sets it to the request's body, with `?ttl=` seconds to live if given,
+//! - `DELETE /keys/<key>` deletes the key.
+//!
+//! `?db=` picks a database other than 0. Each request runs as GET, SET or DEL would on a RESP
+//! connection, as the default user or one given with Basic authorization.
+
+use crate::acl;
+use crate::blocking::BlockedClients;
+use crate::client::Client;
+use crate::config;
+use crate::db::Db;
+use crate::http::{self, Request};
+use crate::pubsub::PubSub;
+use crate::resp::Value;
+use crate::server;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+
+/// The largest value PUT takes, Redis' largest string.
+const MAX_BODY: usize = 512 * 1024 * 1024;
+
+/// What's shared by the requests on a listener.
+#[derive(Clone)]
+struct State {
+    db: Db,
+    pubsub: Arc<PubSub>,
+    blocked: Arc<BlockedClients>,
+}
+
+/// A response's status, content type and body.
+type Response = (&'static str, &'static str, Vec<u8>);
+
+/// Answers the gateway's requests on `listener`. Runs for as long as the server does.
+pub async fn serve(
+    listener: TcpListener,
+    db: Db,
+    pubsub: Arc<PubSub>,
+    blocked: Arc<BlockedClients>,
+) {
+    let state = State {
+        db,
+        pubsub,
+        blocked,
+    };
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(answer(stream, state.clone()));
+    }
+}
+
+async fn answer(mut stream: TcpStream, state: State) {
+    let Some(mut request) = http::read_request(&mut stream).await else {
+        return;
+    };
+    let (status, content_type, body) = match route(&mut stream, &mut request, &state).await {
+        Ok(response) | Err(response) => response,
+    };
+    http::respond(&mut stream, status, content_type, body).await;
+}
+
+async fn route(
+    stream: &mut TcpStream,
+    request: &mut Request,
+    state: &State,
+) -> Result<Response, Response> {
+    let key = match request.path.strip_prefix("/keys/") {
+        Some(key) if !key.is_empty() => http::decode(key),
+        _ => {
+            return Err(text(
+                "404 Not Found",
+                format!("No such endpoint {}", request.path),
+            ));
+        }
+    };
+    let name = match request.method.as_str() {
+        "GET" => "get",
+        "PUT" => "set",
+        "DELETE" => "del",
+        method => {
+            let e = format!("{method} isn't allowed: only GET, PUT and DELETE are");
+            return Err(text("405 Method Not Allowed", e));
+        }
+    };
+
+    let databases = config::get().databases;
+    let index = match request.param("db") {
+        Some(db) => db
+            .parse()
+            .ok()
+            .filter(|index| *index < databases)
+            .ok_or_else(|| text("400 Bad Request", format!("Invalid db {db}")))?,
+        None => 0,
+    };
+    let (Ok(addr), Ok(laddr)) = (stream.peer_addr(), stream.local_addr()) else {
+        return Err(text("500 Internal Server Error", "Lost the connection"));
+    };
+    let mut client = Client::new(state.pubsub.clone(), addr, laddr);
+    client.db = index;
+    // Logged in before the body's read, so that only those who can log in can send one
+    client.user = match request.basic_auth() {
+        Some((user, password)) if acl::authenticate(&user, &password) => Some(user),
+        Some(_) => {
+            let e = "WRONGPASS invalid username-password pair or user is disabled.";
+            return Err(failure(e.to_string()));
+        }
+        None => acl::initial_user(),
+    };
+    if client.user.is_none() {
+        return Err(failure("NOAUTH Authentication required.".to_string()));
+    }
+
+    let mut args = vec![key];
+    if name == "set" {
+        http::read_body(stream, request, MAX_BODY)
+            .await
+            .map_err(|status| text(status, "Couldn't read the value"))?;
+        args.push(std::mem::take(&mut request.body));
+        if let Some(ttl) = request.param("ttl") {
+            match ttl.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => args.extend([b"EX".to_vec(), ttl.into_bytes()]),
+                _ => return Err(text("400 Bad Request", format!("Invalid ttl {ttl}"))),
+            }
+        }
+    }
+
+    let mut dbs = state.db.lock_for(name, &args).await;
+    let reply = server::execute_checked(
+        &mut client,
+        &state.pubsub,
+        &state.blocked,
+        &mut dbs,
+        name,
+        &args,
+    );
+    drop(dbs);
+    state.db.drop_expired_reads().await;
+    if let Some(e) = reply.error_message() {
+        return Err(failure(e));
+    }
+    match reply {
+        Value::BulkString(value) => Ok(("200 OK", "application/octet-stream", value.into())),
+        Value::Null | Value::Integer(0) => Err(text("404 Not Found", "No such key")),
+        _ => Ok(text("200 OK", "OK")),
+    }
+}
+
+fn text(status: &'static str, message: impl Into<String>) -> Response {
+    let body = format!("{}\n", message.into());
+    (status, "text/plain", body.into_bytes())
+}
+
+/// The response to an error reply, with a status for the kind of error it is.
+fn failure(e: String) -> Response {
+    let status = match e.split(' ').next() {
+        Some("NOAUTH" | "WRONGPASS") => "401 Unauthorized",
+        Some("NOPERM") => "403 Forbidden",
+        Some("WRONGTYPE") => "409 Conflict",
+        Some("ERR") => "500 Internal Server Error",
+        // Like READONLY and OOM, which a later try may get past
+        _ => "503 Service Unavailable",
+    };
+    text(status, e)
+}
@@ -0,0 +1,372 @@
+//! Snapshots kept in S3, or any object store that speaks its API, for deployments without a
+//! persistent volume to keep `dump.rdb` on. Requests are signed with AWS Signature Version 4
+//! and sent over plain HTTP/1.1, or TLS for `https://` endpoints, trusting the CA certificates
+//! in `SSL_CERT_FILE` or failing that the system's bundle.
+
+use crate::config::ServerConfig;
+use crate::db;
+use crate::sha256::sha256;
+use crate::snapshot::SnapshotStore;
+use ring::hmac;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{self, ClientConnection, RootCertStore, StreamOwned};
+
+/// How long connecting, and each read and write after it, may take before a request fails.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where CA bundles are found on the common distributions, tried in turn when `SSL_CERT_FILE`
+/// isn't set.
+const CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/cert.pem",
+];
+
+/// A bucket snapshots are stored in, each as an object named for `dbfilename` under a prefix.
+pub struct S3Store {
+    endpoint: Endpoint,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+/// Where requests go: an `http://` or `https://` URL's host and port, which the bucket is
+/// addressed under as a path, so object stores without per-bucket hostnames work too.
+struct Endpoint {
+    tls: Option<Arc<rustls::ClientConfig>>,
+    host: String,
+    port: u16,
+}
+
+impl S3Store {
+    /// A store for `bucket` at `endpoint`, such as `https://s3.eu-west-1.amazonaws.com` or
+    /// `http://minio:9000`, signing requests for `region` with the given credentials.
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key_id: &str,
+        secret_access_key: &str,
+    ) -> anyhow::Result<S3Store> {
+        if bucket.is_empty() {
+            anyhow::bail!("A bucket is needed to store snapshots in S3");
+        }
+
+        Ok(S3Store {
+            endpoint: Endpoint::parse(endpoint)?,
+            bucket: bucket.to_string(),
+            prefix: String::new(),
+            region: region.to_string(),
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+        })
+    }
+
+    /// Stores snapshots under `prefix`, such as `redis/`, in place of the bucket's top level.
+    pub fn prefix(mut self, prefix: &str) -> S3Store {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// The store the `s3-*` settings in `config` describe. Credentials left out of them are
+    /// taken from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+    pub(crate) fn from_config(config: &ServerConfig) -> anyhow::Result<S3Store> {
+        let or_env = |value: &str, var: &str| match value {
+            "" => std::env::var(var).unwrap_or_default(),
+            value => value.to_string(),
+        };
+        let store = S3Store::new(
+            &config.s3_endpoint,
+            &config.s3_bucket,
+            &config.s3_region,
+            &or_env(&config.s3_access_key_id, "AWS_ACCESS_KEY_ID"),
+            &or_env(&config.s3_secret_access_key, "AWS_SECRET_ACCESS_KEY"),
+        )?;
+
+        Ok(store.prefix(&config.s3_prefix))
+    }
+
+    /// Sends a signed `method` request for the object `name`, returning the response's status
+    /// and body.
+    fn request(&self, method: &str, name: &str, body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+        let path = format!(
+            "/{}/{}",
+            uri_encode(&self.bucket),
+            uri_encode(&format!("{}{name}", self.prefix))
+        );
+        let host = self.endpoint.host_header();
+        let now = db::unix_millis();
+        let amz_date = amz_date(now);
+        let date = &amz_date[..8];
+        let payload = hex(&sha256(body));
+
+        let signed = "host;x-amz-content-sha256;x-amz-date";
+        let canonical = format!(
+            "{method}\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload}\n\
+             x-amz-date:{amz_date}\n\n{signed}\n{payload}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&sha256(canonical.as_bytes()))
+        );
+        let key = [date, &self.region, "s3", "aws4_request"].iter().fold(
+            format!("AWS4{}", self.secret_access_key).into_bytes(),
+            |key, part| sign(&key, part.as_bytes()),
+        );
+        let signature = hex(&sign(&key, to_sign.as_bytes()));
+
+        let head = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {host}\r\nx-amz-date: {amz_date}\r\n\
+             x-amz-content-sha256: {payload}\r\nAuthorization: AWS4-HMAC-SHA256 \
+             Credential={}/{scope}, SignedHeaders={signed}, Signature={signature}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.access_key_id,
+            body.len()
+        );
+        let mut connection = self.endpoint.connect()?;
+        connection.write_all(head.as_bytes())?;
+        connection.write_all(body)?;
+        connection.flush()?;
+        read_response(BufReader::new(connection))
+    }
+}
+
+impl SnapshotStore for S3Store {
+    fn save(&self, name: &str, bytes: &[u8]) -> io::Result<()> {
+        // A PUT replaces the object whole or not at all
+        match self.request("PUT", name, bytes)? {
+            (200, _) => Ok(()),
+            (status, body) => Err(failed("PUT", name, status, &body)),
+        }
+    }
+
+    fn load(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.request("GET", name, &[])? {
+            (200, body) => Ok(Some(body)),
+            (404, _) => Ok(None),
+            (status, body) => Err(failed("GET", name, status, &body)),
+        }
+    }
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> anyhow::Result<Endpoint> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (Some(client_config()?), rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (None, rest)
+        } else {
+            anyhow::bail!("S3 endpoint '{url}' isn't an http:// or https:// URL");
+        };
+        let authority = rest.trim_end_matches('/');
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid port in S3 endpoint '{url}'"))?,
+            ),
+            _ if tls.is_some() => (authority, 443),
+            _ => (authority, 80),
+        };
+        if host.is_empty() || host.contains('/') {
+            anyhow::bail!("S3 endpoint '{url}' should name a host alone, without a path");
+        }
+
+        Ok(Endpoint {
+            tls,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// The Host header, which leaves out the scheme's default port as clients do, so the
+    /// signature matches what the server makes of it.
+    fn host_header(&self) -> String {
+        match (self.tls.is_some(), self.port) {
+            (true, 443) | (false, 80) => self.host.clone(),
+            _ => format!("{}:{}", self.host, self.port),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Connection>> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let addrs = std::net::ToSocketAddrs::to_socket_addrs(&(host, self.port))?;
+        let mut last = io::Error::new(io::ErrorKind::NotFound, format!("{host} has no address"));
+        for addr in addrs {
+            let stream = match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    last = e;
+                    continue;
+                }
+            };
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            let Some(tls) = &self.tls else {
+                return Ok(Box::new(stream));
+            };
+            let name = ServerName::try_from(host.to_string())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let connection = ClientConnection::new(tls.clone(), name).map_err(io::Error::other)?;
+            return Ok(Box::new(StreamOwned::new(connection, stream)));
+        }
+
+        Err(last)
+    }
+}
+
+trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// What `https://` endpoints are connected to with: their certificates have to be signed by
+/// one of the CAs in `SSL_CERT_FILE`, or the system's bundle.
+fn client_config() -> anyhow::Result<Arc<rustls::ClientConfig>> {
+    let bundle = match std::env::var("SSL_CERT_FILE") {
+        Ok(path) => path,
+        Err(_) => CA_BUNDLES
+            .iter()
+            .find(|path| std::path::Path::new(path).exists())
+            .ok_or_else(|| anyhow::anyhow!("No CA bundle found to check S3's certificate with"))?
+            .to_string(),
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(&bundle)
+        .map_err(|e| anyhow::anyhow!("Failed to load {bundle}: {e}"))?
+    {
+        roots.add(cert?)?;
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Reads a response's status and body, which comes with a length or in chunks.
+fn read_response(mut reader: impl BufRead) -> io::Result<(u16, Vec<u8>)> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("malformed HTTP status line"))?;
+
+    let mut len = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("HTTP response cut short"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            len = Some(value.parse().map_err(|_| invalid("bad Content-Length"))?);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16).map_err(|_| invalid("bad chunk size"))?;
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            reader.read_line(&mut line)?;
+            if size == 0 {
+                break;
+            }
+        }
+    } else if let Some(len) = len {
+        body.resize(len, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+
+    Ok((status, body))
+}
+
+/// A request that got an error response, with the message S3 put in its body.
+fn failed(method: &str, name: &str, status: u16, body: &[u8]) -> io::Error {
+    let body = String::from_utf8_lossy(body);
+    let message = body
+        .split_once("<Message>")
+        .and_then(|(_, rest)| rest.split_once("</Message>"))
+        .map_or("", |(message, _)| message);
+    io::Error::other(format!(
+        "S3 {method} of {name} failed with {status} {message}"
+    ))
+}
+
+fn sign(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// `s` with everything but unreserved characters and `/` percent-encoded, as the path of a
+/// signed request has to be.
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Unix time `millis` as requests are dated: `YYYYMMDDTHHMMSSZ`, in UTC.
+fn amz_date(millis: u64) -> String {
+    let secs = millis / 1000;
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // Days since the epoch to a civil date, after Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
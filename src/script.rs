@@ -0,0 +1,655 @@
+use crate::cmd::{self, lower};
+use crate::config;
+use crate::replication;
+use crate::resp::Value;
+use crate::sha1::sha1_hex;
+use bytes::Bytes;
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, Table, Variadic, VmState};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::watch;
+use tracing::warn;
+
+/// Runs a command on a script's behalf, given its lowercased name and arguments.
+pub type Call<'a> = dyn FnMut(&str, &[Vec<u8>]) -> Value + 'a;
+
+/// Script bodies by the hex SHA-1 EVALSHA refers to them by. EVAL and SCRIPT LOAD
+/// add to it, and SCRIPT FLUSH empties it.
+static SCRIPTS: Mutex<BTreeMap<String, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+/// Commands a script may not run: ones that would nest scripts or transactions, or that change
+/// how the connection is read from.
+const NOT_FROM_SCRIPTS: &[&str] = &[
+    "acl",
+    "auth",
+    "client",
+    "config",
+    "hello",
+    "monitor",
+    "debug",
+    "shutdown",
+    "save",
+    "bgsave",
+    "bgrewriteaof",
+    "export",
+    "import",
+    "replconf",
+    "psync",
+    "sync",
+    "replicaof",
+    "slaveof",
+    "wait",
+    "failover",
+    "cluster",
+    "asking",
+    "quit",
+    "reset",
+    "eval",
+    "evalsha",
+    "script",
+    "fcall",
+    "fcall_ro",
+    "function",
+    "multi",
+    "exec",
+    "discard",
+    "watch",
+    "unwatch",
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ssubscribe",
+    "sunsubscribe",
+];
+
+/// Whether a script may run command `name`.
+pub fn allowed(name: &str) -> bool {
+    !NOT_FROM_SCRIPTS.contains(&name)
+}
+
+/// Adds `body` to the script cache, returning its SHA-1.
+pub fn cache(body: &[u8]) -> String {
+    let sha = sha1_hex(body);
+    SCRIPTS.lock().unwrap().insert(sha.clone(), body.to_vec());
+
+    sha
+}
+
+/// The cached script with hex SHA-1 `sha`, in either case.
+pub fn cached(sha: &str) -> Option<Vec<u8>> {
+    SCRIPTS.lock().unwrap().get(&sha.to_lowercase()).cloned()
+}
+
+/// Empties the script cache.
+pub fn flush() {
+    SCRIPTS.lock().unwrap().clear();
+}
+
+/// How many Lua instructions a script runs between looks at whether it's been killed or has
+/// run past `busy-reply-threshold`.
+const CHECK_EVERY: u32 = 10_000;
+
+/// The script running now. Commands run holding their locks, and a script holds every one, so
+/// there's only ever one at a time.
+struct Running {
+    started: Instant,
+    /// Whether it's a function, which FUNCTION KILL stops rather than SCRIPT KILL.
+    function: bool,
+    /// Whether it's run a write, after which stopping it would leave its change half made.
+    wrote: bool,
+    killed: bool,
+}
+
+static RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+
+/// Whether the running script has gone past `busy-reply-threshold`, after which other clients
+/// are told the server's busy rather than left waiting on it.
+static BUSY: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+/// Marks a script as running for as long as it's kept.
+struct RunGuard;
+
+impl RunGuard {
+    fn start(function: bool) -> Self {
+        *RUNNING.lock().unwrap() = Some(Running {
+            started: Instant::now(),
+            function,
+            wrote: false,
+            killed: false,
+        });
+        RunGuard
+    }
+
+    fn killed(&self) -> bool {
+        RUNNING.lock().unwrap().as_ref().is_some_and(|r| r.killed)
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        *RUNNING.lock().unwrap() = None;
+        BUSY.send_replace(false);
+    }
+}
+
+/// What the command that stops a script of the kind `function` says is called.
+fn kill_command(function: bool) -> &'static str {
+    if function {
+        "FUNCTION KILL"
+    } else {
+        "SCRIPT KILL"
+    }
+}
+
+/// Looked in on every [`CHECK_EVERY`] instructions: stops the script if it's been killed, and
+/// tells other clients it's busy once it's run for `threshold`.
+fn check_in(threshold: Duration) -> mlua::Result<VmState> {
+    let running = RUNNING.lock().unwrap();
+    let Some(running) = running.as_ref() else {
+        return Ok(VmState::Continue);
+    };
+    if running.killed {
+        return Err(mlua::Error::RuntimeError("killed".to_string()));
+    }
+    if !*BUSY.borrow() && running.started.elapsed() >= threshold {
+        warn!(
+            "Slow script detected: still in execution after {} milliseconds. You can try killing \
+             the script using the {} command.",
+            running.started.elapsed().as_millis(),
+            kill_command(running.function),
+        );
+        BUSY.send_replace(true);
+    }
+
+    Ok(VmState::Continue)
+}
+
+/// Whether a script has run past `busy-reply-threshold` and is still running.
+pub fn busy() -> bool {
+    *BUSY.borrow()
+}
+
+/// Waits until a script runs past `busy-reply-threshold`.
+pub async fn gone_busy() {
+    let mut busy = BUSY.subscribe();
+    busy.wait_for(|busy| *busy).await.expect("sender is static");
+}
+
+/// The error a command gets while a script is busy.
+pub fn busy_error() -> Value {
+    let function = RUNNING.lock().unwrap().as_ref().is_some_and(|r| r.function);
+    Value::error(format!(
+        "BUSY Redis is busy running a script. You can only call {} or SHUTDOWN NOSAVE.",
+        kill_command(function)
+    ))
+}
+
+/// SCRIPT KILL, or FUNCTION KILL for `function`: stops the running script, unless it's written
+/// to the dataset already.
+pub fn kill(function: bool) -> Value {
+    let mut running = RUNNING.lock().unwrap();
+    let Some(running) = running.as_mut() else {
+        return Value::error("NOTBUSY No scripts in execution right now.");
+    };
+    if running.function != function {
+        return Value::error(format!(
+            "BUSY Redis is busy running a script. You can only call {} or SHUTDOWN NOSAVE.",
+            kill_command(running.function)
+        ));
+    }
+    if running.wrote {
+        return Value::error(
+            "UNKILLABLE Sorry the script already executed write commands against the dataset. \
+             You can either wait the script termination or kill the server in a hard way using \
+             the SHUTDOWN NOSAVE command.",
+        );
+    }
+
+    running.killed = true;
+    Value::SimpleString("OK".to_string())
+}
+
+/// What a connection does with command `name` while a script is busy, which holds every lock
+/// until it's done: `None` to run it as usual, or the reply to send instead. SCRIPT KILL and
+/// FUNCTION KILL are answered here, and SHUTDOWN NOSAVE stops the script whatever it's written,
+/// to go ahead with the shutdown.
+pub fn while_busy(name: &str, args: &[Vec<u8>]) -> Option<Value> {
+    if !busy() {
+        return None;
+    }
+
+    let subcommand = args.first().map(|arg| lower(arg));
+    match (name, subcommand.as_deref(), args.len()) {
+        ("script", Some("kill"), 1) => Some(kill(false)),
+        ("function", Some("kill"), 1) => Some(kill(true)),
+        ("shutdown", Some("nosave"), 1) => {
+            if let Some(running) = RUNNING.lock().unwrap().as_mut() {
+                running.killed = true;
+            }
+            None
+        }
+        ("quit" | "reset", _, _) => None,
+        _ => Some(busy_error()),
+    }
+}
+
+/// A function a library registered through `redis.register_function`.
+pub struct Registration {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+    callback: mlua::Function,
+}
+
+/// Flags `redis.register_function` accepts. Only `no-writes` changes anything here.
+const FUNCTION_FLAGS: &[&str] = &[
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// What to run: an EVAL script, or one function out of a library's code.
+enum Source<'a> {
+    Script(&'a [u8]),
+    Function { code: &'a [u8], name: &'a str },
+}
+
+/// Runs the Lua script `body` with `KEYS` and `ARGV` bound, handing the commands it issues
+/// through `redis.call` and `redis.pcall` to `call`. Whatever the script returns is converted
+/// back into a reply.
+pub fn run(body: &[u8], keys: &[Vec<u8>], argv: &[Vec<u8>], call: &mut Call<'_>) -> Value {
+    invoke(Source::Script(body), keys, argv, false, call)
+}
+
+/// Loads a library's `code` and calls its function `name` with the keys and arguments as its
+/// two parameters. A `read_only` call may not issue write commands.
+pub fn call_function(
+    code: &[u8],
+    name: &str,
+    keys: &[Vec<u8>],
+    argv: &[Vec<u8>],
+    read_only: bool,
+    call: &mut Call<'_>,
+) -> Value {
+    invoke(Source::Function { code, name }, keys, argv, read_only, call)
+}
+
+/// Runs a library's `code` just far enough to learn the functions it registers, without giving
+/// it access to the keyspace. The error is ready to send back.
+pub fn load_library(code: &[u8]) -> Result<Vec<Registration>, Value> {
+    let registered = RefCell::new(Vec::new());
+
+    let result = sandbox().and_then(|lua| {
+        lua.scope(|scope| {
+            let redis = helpers(&lua)?;
+            redis.set(
+                "register_function",
+                scope.create_function(|_, args| register(&registered, args))?,
+            )?;
+            lua.globals().set("redis", redis)?;
+
+            lua.load(code).set_name("=user_function").exec()
+        })
+    });
+
+    match result {
+        Ok(()) => Ok(registered.into_inner()),
+        Err(mlua::Error::SyntaxError { message, .. }) => Err(Value::error(format!(
+            "ERR Error compiling function: {message}"
+        ))),
+        Err(e) => Err(Value::error(format!(
+            "ERR Error registering functions: {}",
+            error_message(&e)
+        ))),
+    }
+}
+
+fn invoke(
+    source: Source<'_>,
+    keys: &[Vec<u8>],
+    argv: &[Vec<u8>],
+    read_only: bool,
+    call: &mut Call<'_>,
+) -> Value {
+    let call = RefCell::new(call);
+    let registered = RefCell::new(Vec::new());
+    let function = matches!(source, Source::Function { .. });
+    let running = RunGuard::start(function);
+    let threshold = Duration::from_millis(config::get().busy_reply_threshold);
+
+    let result = off_the_runtime(|| {
+        sandbox().and_then(|lua| {
+            lua.set_hook(
+                HookTriggers::new().every_nth_instruction(CHECK_EVERY),
+                move |_, _| check_in(threshold),
+            );
+            let keys = lua.create_sequence_from(strings(&lua, keys)?)?;
+            let argv = lua.create_sequence_from(strings(&lua, argv)?)?;
+
+            lua.scope(|scope| {
+                let redis = helpers(&lua)?;
+                redis.set(
+                    "call",
+                    scope.create_function(|lua, args| {
+                        let reply = command(&mut **call.borrow_mut(), args, read_only);
+                        match reply.error_message() {
+                            Some(msg) => Err(mlua::Error::RuntimeError(msg)),
+                            None => to_lua(lua, reply),
+                        }
+                    })?,
+                )?;
+                redis.set(
+                    "pcall",
+                    scope.create_function(|lua, args| {
+                        to_lua(lua, command(&mut **call.borrow_mut(), args, read_only))
+                    })?,
+                )?;
+
+                let value = match source {
+                    Source::Script(body) => {
+                        lua.globals().set("redis", redis)?;
+                        lua.globals().set("KEYS", keys)?;
+                        lua.globals().set("ARGV", argv)?;
+                        lua.load(body).set_name("=user_script").eval()?
+                    }
+                    Source::Function { code, name } => {
+                        redis.set(
+                            "register_function",
+                            scope.create_function(|_, args| register(&registered, args))?,
+                        )?;
+                        lua.globals().set("redis", redis)?;
+                        lua.load(code).set_name("=user_function").exec()?;
+
+                        let callback = registered
+                            .borrow()
+                            .iter()
+                            .find(|registration| registration.name == name)
+                            .map(|registration| registration.callback.clone());
+                        match callback {
+                            Some(callback) => callback.call((keys, argv))?,
+                            None => {
+                                return Err(mlua::Error::RuntimeError(format!(
+                                    "function '{name}' is no longer registered by its library"
+                                )));
+                            }
+                        }
+                    }
+                };
+                Ok(from_lua(&value))
+            })
+        })
+    });
+
+    if result.is_err() && running.killed() {
+        return Value::error(format!(
+            "ERR Script killed by user with {}...",
+            kill_command(function)
+        ));
+    }
+    result.unwrap_or_else(|e| match e {
+        mlua::Error::SyntaxError { message, .. } => {
+            Value::error(format!("ERR Error compiling script: {message}"))
+        }
+        // A failed redis.call is raised with the command's own error, which becomes the reply
+        mlua::Error::CallbackError { cause, .. }
+            if matches!(cause.as_ref(), mlua::Error::RuntimeError(_)) =>
+        {
+            Value::error(error_message(&cause))
+        }
+        e => Value::error(format!("ERR Error running script: {}", error_message(&e))),
+    })
+}
+
+/// Runs `script` with the runtime's worker thread handed over to another, so the tasks queued
+/// on it, like the connections that should be told the server's busy, aren't stuck behind it.
+/// A current-thread runtime has no other thread to hand it to.
+fn off_the_runtime<T>(script: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(script)
+        }
+        _ => script(),
+    }
+}
+
+/// A fresh interpreter with only the libraries a script is trusted with.
+fn sandbox() -> mlua::Result<Lua> {
+    Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )
+}
+
+/// The `redis` table with the helpers that don't touch the keyspace.
+fn helpers(lua: &Lua) -> mlua::Result<Table> {
+    let redis = lua.create_table()?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, msg: mlua::String| status_table(lua, "err", msg))?,
+    )?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, msg: mlua::String| status_table(lua, "ok", msg))?,
+    )?;
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, data: mlua::String| Ok(sha1_hex(&data.as_bytes())))?,
+    )?;
+
+    Ok(redis)
+}
+
+/// The first line of an error's message, unwrapping the layers a Rust callback adds. Lua appends
+/// a traceback, which isn't worth sending back.
+fn error_message(e: &mlua::Error) -> String {
+    let msg = match e {
+        mlua::Error::CallbackError { cause, .. } => return error_message(cause),
+        mlua::Error::RuntimeError(msg) => msg.clone(),
+        e => e.to_string(),
+    };
+
+    msg.lines().next().unwrap_or_default().to_string()
+}
+
+/// `redis.register_function(name, callback)`, or the table form with `function_name`,
+/// `callback`, `flags` and `description` fields.
+fn register(
+    registered: &RefCell<Vec<Registration>>,
+    args: Variadic<mlua::Value>,
+) -> mlua::Result<()> {
+    let fail = |msg: &str| Err(mlua::Error::RuntimeError(msg.to_string()));
+
+    let (name, callback, flags, description) = match args.as_slice() {
+        [name, callback] => (name.clone(), callback.clone(), None, None),
+        [mlua::Value::Table(table)] => {
+            for pair in table.pairs::<String, mlua::Value>() {
+                let (field, _) = pair?;
+                if !matches!(
+                    field.as_str(),
+                    "function_name" | "callback" | "flags" | "description"
+                ) {
+                    return fail("unknown argument given to redis.register_function");
+                }
+            }
+            (
+                table.raw_get("function_name")?,
+                table.raw_get("callback")?,
+                table.raw_get::<Option<Table>>("flags")?,
+                table.raw_get::<Option<String>>("description")?,
+            )
+        }
+        _ => return fail("wrong number of arguments to redis.register_function"),
+    };
+
+    let mlua::Value::String(name) = name else {
+        return fail("function_name argument given to redis.register_function must be a string");
+    };
+    let name = name.to_string_lossy();
+    let mlua::Value::Function(callback) = callback else {
+        return fail("callback argument given to redis.register_function must be a function");
+    };
+    if !is_valid_name(&name) {
+        return fail(
+            "Function names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+        );
+    }
+
+    let mut parsed = Vec::new();
+    if let Some(flags) = flags {
+        for flag in flags.sequence_values::<String>() {
+            let flag = flag?;
+            if !FUNCTION_FLAGS.contains(&flag.as_str()) {
+                return fail("unknown flag given");
+            }
+            parsed.push(flag);
+        }
+    }
+
+    let mut registered = registered.borrow_mut();
+    if registered
+        .iter()
+        .any(|registration| registration.name == name)
+    {
+        return fail("Function already exists in the library");
+    }
+    registered.push(Registration {
+        name,
+        description,
+        flags: parsed,
+        callback,
+    });
+
+    Ok(())
+}
+
+/// Whether `name` is usable as a library or function name: letters, digits and underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+fn strings(lua: &Lua, args: &[Vec<u8>]) -> mlua::Result<Vec<mlua::String>> {
+    args.iter().map(|arg| lua.create_string(arg)).collect()
+}
+
+/// Vets and runs one `redis.call`/`redis.pcall`, replying with an error if the script asked for
+/// something it can't have.
+fn command(call: &mut Call<'_>, args: Variadic<mlua::Value>, read_only: bool) -> Value {
+    if args.is_empty() {
+        return Value::error("ERR Please specify at least one argument for this redis lib call");
+    }
+
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        let part = match arg {
+            mlua::Value::String(s) => s.as_bytes().to_vec(),
+            mlua::Value::Integer(n) => n.to_string().into_bytes(),
+            mlua::Value::Number(n) => n.to_string().into_bytes(),
+            _ => {
+                return Value::error(
+                    "ERR Lua redis lib command arguments must be strings or integers",
+                );
+            }
+        };
+        parts.push(part);
+    }
+
+    let typed = cmd::lower(&parts[0]);
+    let Some(name) = cmd::resolve(&typed) else {
+        return Value::error("ERR Unknown Redis command called from script");
+    };
+    if !allowed(name) {
+        return Value::error("ERR This Redis command is not allowed from script");
+    }
+    if !cmd::is_command(name) {
+        return Value::error("ERR Unknown Redis command called from script");
+    }
+    if cmd::check_arity(name, &parts[1..]).is_err() {
+        return Value::error("ERR Wrong number of args calling Redis command from script");
+    }
+    if read_only && cmd::is_write(name) {
+        return Value::error("ERR Write commands are not allowed from read-only scripts.");
+    }
+    if let Some(refusal) = replication::refusal(name) {
+        return refusal;
+    }
+    if cmd::is_write(name)
+        && let Some(running) = RUNNING.lock().unwrap().as_mut()
+    {
+        running.wrote = true;
+    }
+
+    call(name, &parts[1..])
+}
+
+/// A `{ok = ...}` or `{err = ...}` table, the Lua form of status and error replies.
+fn status_table(lua: &Lua, field: &str, msg: mlua::String) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set(field, msg)?;
+
+    Ok(table)
+}
+
+/// Converts a reply into the Lua value a script sees: nulls become `false`, arrays become
+/// sequences, and status and error replies become `ok`/`err` tables.
+fn to_lua(lua: &Lua, reply: Value) -> mlua::Result<mlua::Value> {
+    Ok(match reply {
+        Value::SimpleString(s) => {
+            mlua::Value::Table(status_table(lua, "ok", lua.create_string(s)?)?)
+        }
+        Value::Error(msg) => mlua::Value::Table(status_table(lua, "err", lua.create_string(msg)?)?),
+        Value::BulkString(s) => mlua::Value::String(lua.create_string(s)?),
+        Value::Integer(n) => mlua::Value::Integer(n),
+        Value::Null | Value::NullArray => mlua::Value::Boolean(false),
+        Value::Array(items) | Value::Push(items) => {
+            let table = lua.create_table()?;
+            for item in items {
+                table.push(to_lua(lua, item)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+        // Scripts see replies the way a RESP2 client would, so maps are flattened
+        Value::Map(pairs) => {
+            let table = lua.create_table()?;
+            for (k, v) in pairs {
+                table.push(to_lua(lua, k)?)?;
+                table.push(to_lua(lua, v)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+    })
+}
+
+/// Converts what a script returned into a reply. Numbers are truncated to integers, `true` is 1,
+/// and a sequence stops at its first `nil`.
+fn from_lua(value: &mlua::Value) -> Value {
+    match value {
+        mlua::Value::Boolean(true) => Value::Integer(1),
+        mlua::Value::Integer(n) => Value::Integer(*n),
+        mlua::Value::Number(n) => Value::Integer(*n as i64),
+        mlua::Value::String(s) => Value::BulkString(Bytes::copy_from_slice(&s.as_bytes())),
+        mlua::Value::Table(table) => {
+            if let Ok(msg) = table.raw_get::<mlua::String>("err") {
+                return Value::error(msg.to_string_lossy());
+            }
+            if let Ok(msg) = table.raw_get::<mlua::String>("ok") {
+                return Value::SimpleString(msg.to_string_lossy());
+            }
+
+            let mut items = Vec::new();
+            for i in 1.. {
+                match table.raw_get::<mlua::Value>(i) {
+                    Ok(mlua::Value::Nil) | Err(_) => break,
+                    Ok(item) => items.push(from_lua(&item)),
+                }
+            }
+            Value::Array(items)
+        }
+        _ => Value::Null,
+    }
+}
@@ -0,0 +1,1821 @@
+use crate::admin;
+use crate::audit;
+use crate::blocking::{self, BlockedClients, Outcome};
+use crate::client::{self, Client, ReplyMode, Transaction};
+use crate::cmd::registry::{self, Context};
+use crate::config::{self, ServerConfig};
+use crate::crypt;
+use crate::daemon;
+use crate::db::{self, Db, Keyspace};
+use crate::hooks::{self, Hooks, KeyEvent};
+use crate::logging;
+use crate::memcache;
+use crate::metrics;
+use crate::plugin::CommandPlugin;
+use crate::pubsub::PubSub;
+use crate::ratelimit;
+use crate::resp::{self, Value};
+use crate::rest;
+use crate::snapshot::SnapshotStore;
+use crate::trace;
+#[cfg(feature = "io-uring")]
+use crate::uring;
+#[cfg(feature = "wasm")]
+use crate::wasm;
+use crate::websocket;
+use crate::{
+    acl, aof, bus, cluster, cmd, evict, failover, function, latency, namespace, notify, propagate,
+    replication, script, shadow, shutdown, slowlog, snapshot, stats, systemd, tls, tracking,
+};
+use bytes::Bytes;
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+use std::any::Any;
+use std::fs::{self, Permissions};
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream, UnixListener, lookup_host};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tracing::{Instrument, Span, debug, error, field, info, info_span, trace, warn};
+
+/// The addresses the plaintext and TLS listeners were bound to, for INFO.
+static BOUND: RwLock<Vec<SocketAddr>> = RwLock::new(Vec::new());
+
+/// The addresses clients can connect to, once the server's been built.
+pub fn bound_addrs() -> Vec<SocketAddr> {
+    BOUND.read().unwrap().clone()
+}
+
+/// Whether there's a server in the process, built and not yet done with.
+static LIVE: Mutex<bool> = Mutex::new(false);
+static GONE: Condvar = Condvar::new();
+
+/// Held by the one server a process can have at a time, since most of what it keeps is the
+/// process's own. Another can be built once it's let go.
+struct Live;
+
+impl Live {
+    /// Claims the process for a server, waiting for the one there is to go if `wait` says to.
+    fn claim(wait: bool) -> anyhow::Result<Live> {
+        let mut live = LIVE.lock().unwrap();
+        while *live {
+            if !wait {
+                anyhow::bail!("There's already a server in this process");
+            }
+            live = GONE.wait(live).unwrap();
+        }
+        *live = true;
+        Ok(Live)
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        *LIVE.lock().unwrap() = false;
+        GONE.notify_one();
+    }
+}
+
+/// Sets up a [`Server`]: its settings start from the defaults, or whatever [`Builder::config`]
+/// gives, with the other methods changing single settings on top.
+///
+/// A process has one server at a time, since much of what it keeps is the process's own: its
+/// settings, ACL users, scripts, statistics and the commands added with [`Builder::command`].
+/// Building a second fails while the first is still around, unless [`Builder::wait`] has it
+/// wait its turn, and it carries on with what the first left unless it's [`Builder::fresh`].
+#[derive(Default)]
+pub struct Builder {
+    pub(crate) config: ServerConfig,
+    config_file: Option<(PathBuf, ServerConfig)>,
+    commands: Vec<Box<dyn CommandPlugin>>,
+    hooks: Hooks,
+    snapshot_store: Option<Box<dyn SnapshotStore>>,
+    /// Wait for the process's server to go rather than fail to build while there is one.
+    wait: bool,
+    /// Forget what the server before left in the process.
+    fresh: bool,
+}
+
+impl Builder {
+    /// Starts from `config` in place of the defaults.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Names the file `loaded` was read from, which CONFIG REWRITE writes back to and a SIGHUP
+    /// reloads.
+    pub fn config_file(mut self, path: PathBuf, loaded: ServerConfig) -> Self {
+        self.config_file = Some((path, loaded));
+        self
+    }
+
+    /// Listens on `addr` alone, in place of the configured addresses. `*` and `::*` stand for
+    /// every IPv4 and IPv6 interface.
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.config.bind = vec![addr.into()];
+        self
+    }
+
+    /// Listens on `port`, where 0 picks a free one: [`Server::local_addrs`] says which. With a
+    /// `tls-port` set, 0 means there's no plaintext listener instead, as in Redis.
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    pub fn databases(mut self, databases: usize) -> Self {
+        self.config.databases = databases;
+        self
+    }
+
+    /// Adds `command` to the ones clients can call. Building fails if one has the name of
+    /// another.
+    pub fn command(mut self, command: impl CommandPlugin) -> Self {
+        self.commands.push(Box::new(command));
+        self
+    }
+
+    /// Calls `hook` whenever a command, or a master this server replicates, changes a key. It
+    /// runs as part of the command, with its keys locked, so it has to be quick and can't call
+    /// back into the server. FLUSHALL, FLUSHDB and SWAPDB, which change whole databases at
+    /// once, aren't told of key by key.
+    pub fn on_write(mut self, hook: impl Fn(&KeyEvent<'_>) + Send + Sync + 'static) -> Self {
+        self.hooks.on_write(hook);
+        self
+    }
+
+    /// Calls `hook` whenever a key is dropped for its TTL having passed, as
+    /// [`Builder::on_write`] calls its own.
+    pub fn on_expire(mut self, hook: impl Fn(&KeyEvent<'_>) + Send + Sync + 'static) -> Self {
+        self.hooks.on_expire(hook);
+        self
+    }
+
+    /// Calls `hook` whenever a key is evicted to keep within `maxmemory`, as
+    /// [`Builder::on_write`] calls its own.
+    pub fn on_evict(mut self, hook: impl Fn(&KeyEvent<'_>) + Send + Sync + 'static) -> Self {
+        self.hooks.on_evict(hook);
+        self
+    }
+
+    /// Keeps snapshots in `store` in place of files in `dir`, or the store `snapshot-store`
+    /// names: SAVE and BGSAVE write to it, and the dataset is loaded from it at startup.
+    pub fn snapshot_store(mut self, store: impl SnapshotStore + 'static) -> Self {
+        self.snapshot_store = Some(Box::new(store));
+        self
+    }
+
+    /// Waits for the server the process has, if there is one, to go before building, in place
+    /// of failing.
+    pub fn wait(mut self) -> Self {
+        self.wait = true;
+        self
+    }
+
+    /// Starts from a clean slate, forgetting what the server before left in the process: ACL
+    /// users, scripts and functions, statistics, slow log and latency entries, and the master
+    /// it followed.
+    pub fn fresh(mut self) -> Self {
+        self.fresh = true;
+        self
+    }
+
+    /// Binds the listeners, and the cluster bus's if the cluster is enabled, and loads the
+    /// dataset, leaving a server ready to [`Server::run`]. Fails while the process has another
+    /// server, built and not yet dropped or done running.
+    pub async fn build(mut self) -> anyhow::Result<Server> {
+        let live = Live::claim(self.wait)?;
+        shutdown::reset();
+        logging::init(
+            &self.config.loglevel,
+            &self.config.logfile,
+            &self.config.otlp_endpoint,
+        )?;
+        LazyLock::force(&stats::STARTED);
+        #[cfg(feature = "wasm")]
+        for path in &self.config.wasm_modules {
+            self.commands.extend(wasm::load(path)?);
+        }
+        registry::build(std::mem::take(&mut self.commands))?;
+        hooks::install(std::mem::take(&mut self.hooks));
+        // Users' rules are applied to the commands there are, so only once they're all added
+        if self.fresh {
+            acl::reset();
+            script::flush();
+            function::flush();
+            stats::reset();
+            slowlog::reset();
+            latency::reset(&[]);
+            replication::follow(None);
+        }
+        crypt::install(&self.config)?;
+        snapshot::install(self.snapshot_store.take(), &self.config)?;
+
+        let backlog = self.config.tcp_backlog;
+        let plaintext = self.config.port != 0 || self.config.tls_port == 0;
+        let (activated, mut activated_unix) = systemd::activated()?;
+        let passed_on = !activated.is_empty();
+        let listeners = if passed_on {
+            activated
+                .into_iter()
+                .map(TcpListener::from_std)
+                .collect::<io::Result<_>>()?
+        } else if plaintext {
+            listen(&self.config.bind, self.config.port, backlog).await?
+        } else {
+            Vec::new()
+        };
+        // INFO, CLUSTER and replicas are told the port that was actually bound, or passed on
+        if (self.config.port == 0 || passed_on)
+            && let Some(listener) = listeners.first()
+        {
+            self.config.port = listener.local_addr()?.port();
+        }
+        let tls = if self.config.tls_port != 0 {
+            let acceptor = tls::acceptor(&self.config)?;
+            let listeners = listen(&self.config.bind, self.config.tls_port, backlog).await?;
+            Some((acceptor, listeners))
+        } else {
+            None
+        };
+
+        let tls_listeners = tls.iter().flat_map(|(_, listeners)| listeners);
+        *BOUND.write().unwrap() = listeners
+            .iter()
+            .chain(tls_listeners)
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect();
+
+        let metrics = match self.config.metrics_port {
+            0 => Vec::new(),
+            port => listen(&self.config.bind, port, backlog).await?,
+        };
+        let admin = match self.config.admin_port {
+            0 => Vec::new(),
+            port => listen(&self.config.bind, port, backlog).await?,
+        };
+        let memcached = match self.config.memcached_port {
+            0 => Vec::new(),
+            port => listen(&self.config.bind, port, backlog).await?,
+        };
+        let rest = match self.config.rest_port {
+            0 => Vec::new(),
+            port => listen(&self.config.bind, port, backlog).await?,
+        };
+        let websocket = match self.config.websocket_port {
+            0 => Vec::new(),
+            port => listen(&self.config.bind, port, backlog).await?,
+        };
+
+        let unix_listener = match (activated_unix.pop(), self.config.unixsocket.as_str()) {
+            (Some(listener), _) => Some(UnixListener::from_std(listener)?),
+            (None, "") => None,
+            (None, path) => Some(listen_unix(path, self.config.unixsocketperm)?),
+        };
+
+        let server = Server {
+            listeners,
+            tls,
+            unix_listener,
+            metrics,
+            admin,
+            memcached,
+            rest,
+            websocket,
+            cluster_bus: Vec::new(),
+            db: db::new_databases(self.config.databases),
+            blocked: Arc::new(BlockedClients::default()),
+            pubsub: Arc::new(PubSub::default()),
+            live,
+        };
+        notify::init(server.pubsub.clone());
+        // Nodes of a cluster talk among themselves on a port of their own
+        let cluster_bus = if self.config.cluster_enabled {
+            let cport = self.config.cluster_bus_port();
+            cluster::init(self.config.port, cport);
+            listen(&self.config.bind, cport, backlog).await?
+        } else {
+            Vec::new()
+        };
+        daemon::write_pidfile(&self.config);
+        config::init(self.config, self.config_file);
+
+        load_dataset(&server.db, &server.blocked, &server.pubsub).await?;
+        snapshot::mark_saved();
+
+        Ok(Server {
+            cluster_bus,
+            ..server
+        })
+    }
+
+    /// Builds the server and runs it.
+    pub async fn run(self) -> anyhow::Result<()> {
+        self.build().await?.run().await
+    }
+
+    /// Builds the server and runs it on a runtime of its own, the kind `io-backend` and
+    /// `io-threads` pick, for a process that isn't running one already. With `daemonize` on,
+    /// it goes into the background first. Returns once the server has shut down, without
+    /// waiting on work left on the runtime's blocking threads.
+    pub fn start(self) -> anyhow::Result<()> {
+        if self.config.daemonize {
+            daemon::daemonize()?;
+        }
+        let threads = self.config.io_threads;
+        #[cfg(feature = "io-uring")]
+        if self.config.io_backend == "io-uring" {
+            if threads > 1 {
+                anyhow::bail!("io-uring runs on a single thread, so io-threads can't be above 1");
+            }
+            return uring::start(self.run());
+        }
+
+        let runtime = runtime(threads)?;
+        let ran = runtime.block_on(self.run());
+        runtime.shutdown_background();
+        ran
+    }
+}
+
+/// The runtime `io-threads` picks: one of a worker a core with 0, one on the calling thread
+/// with 1, or one of that many workers.
+pub(crate) fn runtime(threads: usize) -> io::Result<tokio::runtime::Runtime> {
+    match threads {
+        0 => tokio::runtime::Runtime::new(),
+        1 => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build(),
+        threads => tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(threads)
+            .enable_all()
+            .build(),
+    }
+}
+
+/// A server, listening but not yet taking connections.
+///
+/// There's one at a time to a process, as most of what a server keeps is the process's own.
+/// Its keys, listeners and tasks are its alone, and each build sets the settings, added
+/// commands, hooks and snapshot store anew. Everything else carries over from one server to
+/// the next: ACL users, scripts and functions, clients' names for commands, statistics, the
+/// slow log, and cluster and replication state.
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    /// The TLS listeners, with what they're accepted with.
+    tls: Option<(TlsAcceptor, Vec<TcpListener>)>,
+    unix_listener: Option<UnixListener>,
+    /// Where Prometheus scrapes the server's metrics.
+    metrics: Vec<TcpListener>,
+    /// Where the admin API is served.
+    admin: Vec<TcpListener>,
+    /// Where memcached clients connect.
+    memcached: Vec<TcpListener>,
+    /// Where the REST gateway is served.
+    rest: Vec<TcpListener>,
+    /// Where pub/sub is served over WebSocket.
+    websocket: Vec<TcpListener>,
+    cluster_bus: Vec<TcpListener>,
+    db: Db,
+    blocked: Arc<BlockedClients>,
+    pubsub: Arc<PubSub>,
+    live: Live,
+}
+
+impl Server {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// The addresses clients can connect to.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+
+    /// Takes connections and runs the server's background work until it shuts down, which
+    /// SHUTDOWN, SIGTERM and SIGINT ask it to. Its connections and tasks end with it, as they
+    /// do if this is dropped, and another server can then be built.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let Server {
+            listeners,
+            tls,
+            unix_listener,
+            metrics,
+            admin,
+            memcached,
+            rest,
+            websocket,
+            cluster_bus,
+            db,
+            blocked,
+            pubsub,
+            live: _live,
+        } = self;
+        // Aborted on the way out, however that's taken
+        let mut tasks = JoinSet::new();
+
+        tasks.spawn(db::expire_keys(db.clone()));
+        tasks.spawn(client::close_idle());
+        tasks.spawn(aof::sync_every_second());
+        tasks.spawn(trace::flush_every_second());
+        tasks.spawn(snapshot::save_on_schedule(db.clone()));
+        tasks.spawn(replication::ping_replicas());
+        tasks.spawn(replication::take_snapshots(db.clone()));
+        tasks.spawn(follow_master(db.clone(), blocked.clone(), pubsub.clone()));
+        tasks.spawn(audit::add_to_stream(
+            db.clone(),
+            pubsub.clone(),
+            blocked.clone(),
+        ));
+        if !cluster_bus.is_empty() {
+            tasks.spawn(bus::check_nodes());
+        }
+        for listener in cluster_bus {
+            tasks.spawn(bus::serve(listener));
+        }
+        for listener in metrics {
+            tasks.spawn(metrics::serve(listener, db.clone()));
+        }
+        for listener in admin {
+            tasks.spawn(admin::serve(
+                listener,
+                db.clone(),
+                pubsub.clone(),
+                blocked.clone(),
+            ));
+        }
+        for listener in memcached {
+            tasks.spawn(memcache::serve(
+                listener,
+                db.clone(),
+                pubsub.clone(),
+                blocked.clone(),
+            ));
+        }
+        for listener in rest {
+            tasks.spawn(rest::serve(
+                listener,
+                db.clone(),
+                pubsub.clone(),
+                blocked.clone(),
+            ));
+        }
+        for listener in websocket {
+            tasks.spawn(websocket::serve(listener, pubsub.clone()));
+        }
+
+        tasks.spawn(async {
+            let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+                return;
+            };
+            while hangups.recv().await.is_some() {
+                match config::reload() {
+                    Ok(()) => info!("reloaded config file"),
+                    Err(e) => warn!("error reloading config file: {e}"),
+                }
+            }
+        });
+
+        for kind in [SignalKind::terminate(), SignalKind::interrupt()] {
+            tasks.spawn(async move {
+                let Ok(mut signals) = signal(kind) else {
+                    return;
+                };
+                if signals.recv().await.is_some() {
+                    shutdown::request(None);
+                }
+            });
+        }
+
+        // Each listener accepts on its own task, feeding one queue of new connections
+        let (accepted, mut incoming) = mpsc::unbounded_channel();
+        for listener in listeners {
+            let acceptor = accept_tcp(listener, accepted.clone());
+            // Connections moved onto io_uring are served by tasks local to its thread
+            #[cfg(feature = "io-uring")]
+            if uring::running() {
+                tasks.spawn_local(acceptor);
+                continue;
+            }
+            tasks.spawn(acceptor);
+        }
+        if let Some((acceptor, listeners)) = tls {
+            for listener in listeners {
+                tasks.spawn(accept_tls(listener, acceptor.clone(), accepted.clone()));
+            }
+        }
+        if let Some(listener) = unix_listener {
+            // Taken from the socket, since systemd may have been the one to open it
+            let path = listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| Some(addr.as_pathname()?.display().to_string()))
+                .unwrap_or_default();
+            tasks.spawn(async move {
+                loop {
+                    let connection = listener.accept().await.map(|(stream, _)| {
+                        (resp::RespHandler::new(stream), Peer::Unix(path.clone()))
+                    });
+                    if accepted.send(connection).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        info!("Ready to accept connections");
+        systemd::notify(
+            &config::get().supervised,
+            "READY=1\nSTATUS=Ready to accept connections",
+        );
+
+        loop {
+            let connection = tokio::select! {
+                connection = incoming.recv() => connection.expect("acceptors outlive the loop"),
+                save = shutdown::requested() => {
+                    // Only comes back empty if the server has to keep running
+                    let Some(_locked) = shutdown::finish(&db, save).await else {
+                        continue;
+                    };
+                    tasks.abort_all();
+                    return Ok(());
+                }
+            };
+            // Connections that have closed are done with
+            while tasks.try_join_next().is_some() {}
+
+            match connection {
+                Ok((mut handler, Peer::Tcp { addr, .. })) if protected_mode_refuses(&addr) => {
+                    tasks.spawn(async move {
+                        let _ = handler.write(Value::error(PROTECTED_MODE_DENIED)).await;
+                    });
+                }
+                Ok((mut handler, peer)) => {
+                    stats::CONNECTIONS_RECEIVED.fetch_add(1, Ordering::Relaxed);
+                    let Some(admission) = client::admit(config::get().maxclients) else {
+                        stats::REJECTED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+                        tasks.spawn(async move {
+                            let e = Value::error("ERR max number of clients reached");
+                            let _ = handler.write(e).await;
+                        });
+                        continue;
+                    };
+                    let db_thread = db.clone();
+                    let blocked_thread = blocked.clone();
+                    let pubsub_thread = pubsub.clone();
+
+                    // The connection's CLIENT ID is filled in once it's registered
+                    let span = info_span!("client", id = field::Empty, addr = %peer.addr());
+                    tasks.spawn(
+                        async move {
+                            handle_connection(
+                                handler,
+                                peer,
+                                db_thread,
+                                blocked_thread,
+                                pubsub_thread,
+                            )
+                            .await;
+                            drop(admission);
+                        }
+                        .instrument(span),
+                    );
+                }
+                Err(e) => {
+                    warn!("Error accepting a connection: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// Rebuilds the dataset from the append-only file when `appendonly` is on and there is one, or
+/// failing that the append-only directory Redis left, and from the snapshot otherwise. With
+/// `appendonly` on, the server then appends to the file, starting one from what it loaded if
+/// need be.
+async fn load_dataset(
+    db: &Db,
+    blocked: &BlockedClients,
+    pubsub: &Arc<PubSub>,
+) -> anyhow::Result<()> {
+    let mut dbs = db.lock_all().await;
+
+    if config::get().appendonly {
+        let path = aof::path();
+        let nowhere = SocketAddr::from(([0, 0, 0, 0], 0));
+        let mut client = Client::new(pubsub.clone(), nowhere, nowhere);
+        let mut replay = |dbs: &mut [Keyspace], command: Vec<Vec<u8>>| {
+            let name = String::from_utf8_lossy(&command[0]).to_lowercase();
+            if !cmd::is_command(&name) {
+                anyhow::bail!("unknown command '{name}'");
+            }
+            if cmd::check_arity(&name, &command[1..]).is_err() {
+                anyhow::bail!("wrong number of arguments for '{name}'");
+            }
+            if let Outcome::Block(block) =
+                run(&mut client, pubsub, blocked, dbs, &name, &command[1..])
+            {
+                block.attempt_now(&mut dbs[client.db]);
+            }
+            Ok(())
+        };
+        let replayed = aof::load(&path, &mut dbs, &mut replay)
+            .map_err(|e| anyhow::anyhow!("error loading {}: {e}", path.display()))?;
+        // One written in the clear or with another key is started afresh with the current one
+        if replayed && aof::written_as_configured()? {
+            aof::open()?;
+            return Ok(());
+        } else if replayed {
+            aof::start(&dbs)?;
+            return Ok(());
+        }
+
+        // Redis's own files, for a server taking over from it, are carried on in one of ours
+        if aof::load_redis(&mut dbs, &mut replay)
+            .map_err(|e| anyhow::anyhow!("error loading Redis's append only files: {e}"))?
+        {
+            aof::start(&dbs)?;
+            return Ok(());
+        }
+    }
+
+    let name = snapshot::name();
+    snapshot::load(&name, &mut dbs).map_err(|e| anyhow::anyhow!("error loading {name}: {e}"))?;
+    if config::get().appendonly {
+        aof::start(&dbs)?;
+    }
+
+    Ok(())
+}
+
+/// Binds a listener for each of `addrs` on `port`. Failing to bind an address is fatal unless
+/// it's marked optional with a leading `-`. Each queues up to `backlog` connections.
+async fn listen(addrs: &[String], port: u16, backlog: u32) -> anyhow::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        let (optional, addr) = match addr.strip_prefix('-') {
+            Some(addr) => (true, addr),
+            None => (false, addr.as_str()),
+        };
+        let host = match addr {
+            "*" => "0.0.0.0",
+            "::*" => "::",
+            host => host,
+        };
+
+        match bind(host, port, backlog).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if optional => warn!("Skipping optional address {addr}:{port}: {e}"),
+            Err(e) => {
+                anyhow::bail!("Could not create server TCP listening socket {addr}:{port}: {e}")
+            }
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// A listener on `host`, which may be a name to look up. IPv6 listeners only take IPv6
+/// connections, so that `::` and `0.0.0.0` can be bound side by side.
+async fn bind(host: &str, port: u16, backlog: u32) -> io::Result<TcpListener> {
+    let addr = lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such host"))?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Where acceptors queue the connections they've accepted, with who they're from.
+type Accepted = mpsc::UnboundedSender<io::Result<(resp::RespHandler, Peer)>>;
+
+/// Accepts connections on a plaintext listener, queueing them on `accepted`. On io_uring, their
+/// reads and writes are moved onto it.
+async fn accept_tcp(listener: TcpListener, accepted: Accepted) {
+    loop {
+        let connection = listener.accept().await.and_then(|(stream, addr)| {
+            let laddr = stream.local_addr()?;
+            if let Err(e) = tune(&stream) {
+                warn!("Failed to set options on connection from {addr}: {e}");
+            }
+            #[cfg(feature = "io-uring")]
+            if uring::running() {
+                let handler = resp::RespHandler::new(uring::wrap(stream)?);
+                return Ok((handler, Peer::Tcp { addr, laddr }));
+            }
+            Ok((resp::RespHandler::new(stream), Peer::Tcp { addr, laddr }))
+        });
+        if accepted.send(connection).is_err() {
+            break;
+        }
+    }
+}
+
+/// Accepts connections on a TLS listener, queueing them on `accepted` once they've shaken
+/// hands. Each handshake happens on a task of its own, so a slow one holds up no one else.
+async fn accept_tls(listener: TcpListener, acceptor: TlsAcceptor, accepted: Accepted) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                if accepted.send(Err(e)).is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        let accepted = accepted.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let Ok(laddr) = stream.local_addr() else {
+                return;
+            };
+            if let Err(e) = tune(&stream) {
+                warn!("Failed to set options on connection from {addr}: {e}");
+            }
+            let handshake = acceptor.accept(stream);
+            match tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, handshake).await {
+                Ok(Ok(stream)) => {
+                    let handler = resp::RespHandler::new(stream);
+                    let _ = accepted.send(Ok((handler, Peer::Tcp { addr, laddr })));
+                }
+                Ok(Err(e)) => warn!("Error accepting a TLS connection from {addr}: {e}"),
+                Err(_) => warn!("TLS handshake with {addr} timed out"),
+            }
+        });
+    }
+}
+
+/// A listener on the Unix socket at `path`, replacing any left behind by an earlier run, with
+/// permissions `perm` unless that's 0.
+fn listen_unix(path: &str, perm: u32) -> anyhow::Result<UnixListener> {
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)
+        .and_then(|listener| {
+            if perm != 0 {
+                fs::set_permissions(path, Permissions::from_mode(perm))?;
+            }
+            Ok(listener)
+        })
+        .map_err(|e| anyhow::anyhow!("Failed opening Unix socket: {e}"))?;
+
+    Ok(listener)
+}
+
+/// Applies `tcp-nodelay` and `tcp-keepalive` to a newly made connection.
+fn tune(stream: &TcpStream) -> io::Result<()> {
+    let config = config::get();
+    stream.set_nodelay(config.tcp_nodelay)?;
+    if config.tcp_keepalive > 0 {
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(config.tcp_keepalive));
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+
+    Ok(())
+}
+
+const PROTECTED_MODE_DENIED: &str = "DENIED Redis is running in protected mode because protected \
+    mode is enabled and no password is set for the default user. In this mode connections are \
+    only accepted from the loopback interface. If you want to connect from external computers \
+    to Redis you may adopt one of the following solutions: 1) Just disable protected mode \
+    sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting \
+    to Redis from the same host the server is running, however MAKE SURE Redis is not publicly \
+    accessible from internet if you do so. 2) Set a bind address or an authentication password. \
+    NOTE: You only need to do one of the above things in order for the server to start \
+    accepting connections from the outside.";
+
+/// Whether protected mode turns away a connection from `addr`: it's on, nothing was done to
+/// secure the server, and the connection comes from another host.
+fn protected_mode_refuses(addr: &SocketAddr) -> bool {
+    let config = config::get();
+
+    config.protected_mode
+        && config.bind == ServerConfig::default().bind
+        && acl::initial_user().is_some()
+        && !addr.ip().to_canonical().is_loopback()
+}
+
+/// Who's on the other end of a newly accepted connection.
+enum Peer {
+    Tcp {
+        addr: SocketAddr,
+        laddr: SocketAddr,
+    },
+    /// A client of the Unix socket at this path.
+    Unix(String),
+}
+
+impl Peer {
+    /// The address to know the peer by. A Unix socket's clients are on this host.
+    fn addr(&self) -> SocketAddr {
+        match self {
+            Peer::Tcp { addr, .. } => *addr,
+            Peer::Unix(_) => SocketAddr::from(([127, 0, 0, 1], 0)),
+        }
+    }
+}
+
+/// A client's connection, as the commands that change what it does see it: the subscribe
+/// family, transaction control, and the commands that close it, hand it over or hold it up.
+pub struct Connection<'a> {
+    pub handler: &'a mut resp::RespHandler,
+    pub client: &'a mut Client,
+    pub killed: &'a mut client::KillSignal,
+    pub addr: SocketAddr,
+    pub db: &'a Db,
+    pub pubsub: &'a PubSub,
+    pub blocked: &'a BlockedClients,
+    started: Instant,
+    db_before: usize,
+    accounted: bool,
+}
+
+impl Connection<'_> {
+    /// Accounts for the command as [`execute`] does the rest, which the connection does once
+    /// it's done with it unless the command did so first, as one that goes on for as long as
+    /// the connection does has to.
+    pub fn account(&mut self, name: &str, args: &[Vec<u8>], failed: bool) {
+        if !std::mem::replace(&mut self.accounted, true) {
+            account(
+                self.client,
+                self.db_before,
+                name,
+                args,
+                self.started,
+                failed,
+            );
+        }
+    }
+}
+
+/// What a connection does once a command that changes it has run.
+pub enum Step {
+    /// Replies as to any other command.
+    Reply(Value),
+    /// Sends each of these, with nothing else done for the command, like the subscribe family's
+    /// frames.
+    Replies(Vec<Value>),
+    /// Closes the connection, the command having said whatever it had to.
+    Close,
+}
+
+impl Step {
+    /// The step, for a command with nothing to wait for.
+    pub fn now<'a>(self) -> Steps<'a> {
+        Box::pin(std::future::ready(self))
+    }
+}
+
+/// What a connection's command is run as, ending in the [`Step`] it takes.
+pub type Steps<'a> = Pin<Box<dyn Future<Output = Step> + Send + 'a>>;
+
+async fn handle_connection(
+    mut handler: resp::RespHandler,
+    peer: Peer,
+    db: Db,
+    blocked: Arc<BlockedClients>,
+    pubsub: Arc<PubSub>,
+) {
+    let addr = peer.addr();
+    let mut client = match &peer {
+        Peer::Tcp { addr, laddr } => Client::new(pubsub.clone(), *addr, *laddr),
+        Peer::Unix(path) => Client::unix(pubsub.clone(), path),
+    };
+    let mut killed = client.kill_signal();
+    let output = client.subscriber.output();
+    handler.limit();
+
+    // The client's connection to the shadow target, once it's sent a command there
+    let mut shadow = None;
+
+    Span::current().record("id", client.id);
+    debug!("Accepted a new connection");
+
+    loop {
+        client.sync();
+
+        let value = tokio::select! {
+            value = handler.read() => value,
+            message = client.subscriber.next_message() => {
+                handler.set_muted(client.reply_mode == ReplyMode::Off);
+                // A client that isn't reading can leave this stuck while its mailbox fills up
+                tokio::select! {
+                    written = handler.write(message) => if written.is_err() {
+                        break;
+                    },
+                    _ = output.overflowed() => {
+                        warn!("Closing client {addr} for overcoming its output buffer limits");
+                        break;
+                    }
+                }
+                continue;
+            }
+            _ = output.overflowed() => {
+                warn!("Closing client {addr} for overcoming its output buffer limits");
+                break;
+            }
+            _ = killed.killed() => break,
+        };
+
+        let value = match value {
+            Ok(value) => value,
+            Err(e) => {
+                // What's left of the message would only read as more of them, so like Redis,
+                // the connection is closed once the client's been told
+                warn!("Failed to read token: {e}");
+                let reply = Value::error(format!("ERR Protocol error: {e}"));
+                let _ = handler.write(reply).await;
+                break;
+            }
+        };
+
+        trace!("Got {value:?}");
+
+        let response = if let Some(v) = value {
+            let (command, mut args) = match extract_command(v) {
+                Ok(extracted) => extracted,
+                Err(e) => {
+                    warn!("Error extracting commands: {e}");
+                    let reply = Value::error(format!("ERR Protocol error: {e}"));
+                    if handler.write(reply).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            trace::record(client.id, &command, &args);
+            // Where this command's reply goes to be compared with the shadow target's
+            let shadowed = shadow::forward(&mut shadow, &command, &args);
+            let name = command.to_lowercase();
+            client.record(&name);
+            let skipped = client.take_skip();
+            handler.set_muted(skipped || client.reply_mode != ReplyMode::On);
+
+            // Renamed commands go by their real names from here on. One that doesn't exist is
+            // named in the error as it was sent, as Redis does.
+            let resolved = cmd::resolve(&name).filter(|name| cmd::is_command(name));
+            let Some(name) = resolved.map(str::to_string) else {
+                if let Some(transaction) = &mut client.transaction {
+                    transaction.abort();
+                }
+                if handler
+                    .write(cmd::unknown_command(&command, &args))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                continue;
+            };
+            // Counted before anything looks for keys in them
+            if let Err(e) = cmd::check_arity(&name, &args) {
+                if let Some(transaction) = &mut client.transaction {
+                    transaction.abort();
+                }
+                if handler.write(e).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            // AUTH and HELLO can log in, so they're the only commands that don't need it besides
+            // the ones that leave
+            let logs_in = matches!(name.as_str(), "auth" | "hello");
+            let leaves = matches!(name.as_str(), "quit" | "reset");
+            if client.user.is_none() && !logs_in && !leaves {
+                if handler
+                    .write(Value::error("NOAUTH Authentication required."))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                continue;
+            }
+
+            if let Some(user) = &client.user
+                && !logs_in
+                && !leaves
+            {
+                // ACL DELUSER logs out everyone connected as the user
+                if !acl::exists(user) {
+                    break;
+                }
+                if let Err(e) = acl::check(user, &name, &args) {
+                    if let Some(transaction) = &mut client.transaction {
+                        transaction.abort();
+                    }
+                    if handler.write(e).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            // A user confined to a namespace has its prefix put on its keys here, so that from
+            // here on they're as they're stored
+            if let Some(prefix) = namespace::prefix(client.user.as_deref()) {
+                namespace::qualify(&prefix, &name, &mut args);
+            }
+
+            // Leaving is never held up, so a throttled client can always go
+            if !leaves
+                && let Err(mut wait) = ratelimit::admit(&mut client.rate, client.user.as_deref())
+            {
+                stats::THROTTLED_COMMANDS.fetch_add(1, Ordering::Relaxed);
+                if !ratelimit::DELAY.load(Ordering::Relaxed) {
+                    if handler
+                        .write(Value::error(
+                            "THROTTLED Too many commands, over the rate limit",
+                        ))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                    continue;
+                }
+                loop {
+                    tokio::time::sleep(wait).await;
+                    match ratelimit::admit(&mut client.rate, client.user.as_deref()) {
+                        Ok(()) => break,
+                        Err(left) => wait = left,
+                    }
+                }
+            }
+
+            if client.subscriber.is_subscribed() && !cmd::pubsub::allowed_while_subscribed(&name) {
+                if handler
+                    .write(Value::error(format!(
+                        "ERR Can't execute '{name}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / \
+                         PING / QUIT / RESET are allowed in this context"
+                    )))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                continue;
+            }
+
+            // In cluster mode a command goes to whichever node serves its keys, which ASKING lets
+            // be this one for the next command while a slot is moving here
+            let asking = std::mem::take(&mut client.asking);
+            if cluster::enabled() {
+                // EXEC is sent on to wherever the whole transaction's keys are
+                let keys = match (&client.transaction, name.as_str()) {
+                    (Some(transaction), "exec") => transaction.keys(),
+                    _ => cmd::command_keys(&name, &args),
+                };
+                let redirect = if keys.is_empty() {
+                    None
+                } else {
+                    let dbs = db.lock_keys(&keys).await;
+                    cluster::redirect(&name, &keys, asking, |key| {
+                        dbs[client.db].get_unexpired(key).is_some()
+                    })
+                };
+                if let Some(redirect) = redirect {
+                    if name == "exec" && client.transaction.take().is_some() {
+                        client.unwatch();
+                    } else if let Some(transaction) = &mut client.transaction {
+                        transaction.abort();
+                    }
+                    if handler.write(redirect).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            // A script that's run past busy-reply-threshold holds every lock, so until it's done
+            // the only commands answered are the ones that stop it
+            if let Some(reply) = script::while_busy(&name, &args) {
+                if let Some(transaction) = &mut client.transaction {
+                    transaction.abort();
+                }
+                if handler.write(reply).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            if let Some(transaction) = &mut client.transaction
+                && !matches!(
+                    name.as_str(),
+                    "multi" | "exec" | "discard" | "watch" | "quit" | "reset"
+                )
+            {
+                let reply = transaction.queue(&name, args);
+                if let Some(local) = shadowed {
+                    let _ = local.send(reply.clone());
+                }
+                if handler.write(reply).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            // CLIENT itself isn't held up, so that a pause can always be lifted early
+            let may_write = match name.as_str() {
+                "exec" => client
+                    .transaction
+                    .as_ref()
+                    .is_some_and(Transaction::may_write),
+                name => cmd::may_write(name),
+            };
+            if name != "client" {
+                tokio::select! {
+                    _ = client::wait_unpaused(may_write) => {}
+                    _ = killed.killed() => break,
+                }
+            }
+            // Checked after any pause, which a failover may end with this server a replica
+            if let Some(refusal) = replication::refusal(&name) {
+                if handler.write(refusal).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            // Over maxmemory, anything that may grow the dataset has to make room for itself
+            let may_grow = match name.as_str() {
+                "exec" => client
+                    .transaction
+                    .as_ref()
+                    .is_some_and(Transaction::may_grow),
+                name => cmd::may_grow(name),
+            };
+            if may_grow && evict::over_limit() && !evict::make_room(&mut db.lock_all().await) {
+                let e = Value::error("OOM command not allowed when used memory > 'maxmemory'.");
+                if handler.write(e).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            let started = Instant::now();
+            let db_before = client.db;
+            let connection = registry::get(&name).and_then(|command| command.connection());
+            let response = if let Some(run) = connection {
+                let mut connection = Connection {
+                    handler: &mut handler,
+                    client: &mut client,
+                    killed: &mut killed,
+                    addr,
+                    db: &db,
+                    pubsub: &pubsub,
+                    blocked: &blocked,
+                    started,
+                    db_before,
+                    accounted: false,
+                };
+                let step = run(&mut connection, &args).await;
+                // execute() times every other command
+                let failed = matches!(&step, Step::Reply(reply) if reply.error_message().is_some());
+                connection.account(&name, &args, failed);
+                match step {
+                    Step::Reply(reply) => reply,
+                    Step::Replies(replies) => {
+                        for reply in replies {
+                            if handler.write(reply).await.is_err() {
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                    Step::Close => break,
+                }
+            } else {
+                let outcome = tokio::select! {
+                    biased;
+                    mut dbs = db.lock_for(&name, &args) => {
+                        execute(&mut client, &pubsub, &blocked, &mut dbs, &name, &args)
+                    }
+                    // Waiting on a script gets what arriving while it's busy would
+                    _ = script::gone_busy() => Outcome::Reply(script::busy_error()),
+                };
+                db.drop_expired_reads().await;
+                match outcome {
+                    Outcome::Reply(reply) => reply,
+                    Outcome::Block(block) => {
+                        let closed = async {
+                            tokio::select! {
+                                _ = handler.closed() => {}
+                                _ = killed.killed() => {}
+                            }
+                        };
+                        let reply = blocked.wait(&db, client.db, block, closed).await;
+                        if killed.is_killed() {
+                            break;
+                        }
+                        // Whatever served it may have written
+                        client.repl_offset = replication::offset();
+                        reply
+                    }
+                }
+            };
+
+            for key in blocking::ready_keys(&name, &args) {
+                blocked.signal(client.db, key);
+            }
+
+            // CLIENT REPLY OFF or SKIP goes unanswered itself, while HELLO answers in the
+            // protocol it switched to
+            handler.set_muted(skipped || client.reply_mode != ReplyMode::On);
+            handler.set_resp3(client.protocol == 3);
+
+            if let Some(local) = shadowed {
+                let _ = local.send(response.clone());
+            }
+            response
+        } else {
+            break;
+        };
+
+        trace!("Replying {response:?}");
+        if handler.write(response).await.is_err() {
+            break;
+        }
+    }
+
+    debug!("Client closed connection");
+}
+
+/// Keeps the server following whichever master REPLICAOF last named, syncing again a second
+/// after the link drops. Runs for as long as the server does.
+async fn follow_master(db: Db, blocked: Arc<BlockedClients>, pubsub: Arc<PubSub>) {
+    let mut following = replication::following();
+    loop {
+        let master = following.borrow_and_update().clone();
+        let Some((host, port)) = master else {
+            let _ = following.changed().await;
+            continue;
+        };
+
+        let link = async {
+            if let Err(e) = sync_with_master(&host, port, &db, &blocked, &pubsub).await {
+                warn!("Lost the link with MASTER {host}:{port}: {e}");
+                // The target never took over, so this server stays the master
+                if failover::state() == failover::State::InProgress {
+                    warn!("FAILOVER to {host}:{port} failed, aborting");
+                    failover::abort();
+                }
+            }
+            replication::set_link(replication::Link::Down);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        };
+        // Naming another master, or none, drops the link at once
+        tokio::select! {
+            _ = link => {}
+            _ = following.changed() => replication::set_link(replication::Link::Down),
+        }
+    }
+}
+
+/// Connects to the master and asks to carry on from this server's replication ID and offset.
+/// Unless the master can, it loads the snapshot the master sends in place of the dataset. Then
+/// it applies every write the master streams, acknowledging how far it's got once a second.
+/// Only returns once the link fails.
+async fn sync_with_master(
+    host: &str,
+    port: u16,
+    db: &Db,
+    blocked: &BlockedClients,
+    pubsub: &Arc<PubSub>,
+) -> anyhow::Result<()> {
+    info!("Connecting to MASTER {host}:{port}");
+    replication::set_link(replication::Link::Connecting);
+    let stream = TcpStream::connect((host, port)).await?;
+    tune(&stream)?;
+    let (addr, laddr) = (stream.peer_addr()?, stream.local_addr()?);
+    let mut master = resp::RespHandler::new(stream);
+    let command = |parts: &[&str]| {
+        Value::Array(
+            parts
+                .iter()
+                .map(|part| Value::BulkString(Bytes::copy_from_slice(part.as_bytes())))
+                .collect(),
+        )
+    };
+
+    let listening_port = config::get().port.to_string();
+    let replid = replication::replid();
+    let next = (replication::offset() + 1).to_string();
+    let mut psync = vec!["psync", &replid, &next];
+    // The replica takes over before answering
+    if failover::state() == failover::State::InProgress {
+        psync.push("failover");
+    }
+    let handshake = [
+        command(&["ping"]),
+        command(&["replconf", "listening-port", &listening_port]),
+        command(&["replconf", "capa", "eof", "capa", "psync2"]),
+        command(&psync),
+    ];
+    let mut reply = Value::NullArray;
+    for request in handshake {
+        master.write(request).await?;
+        reply = master
+            .read()
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("connection closed during the handshake"))?;
+        if let Some(e) = reply.error_message() {
+            anyhow::bail!("master refused the handshake: {e}");
+        }
+    }
+    let resync = match &reply {
+        Value::SimpleString(resync) => resync.split(' ').collect::<Vec<_>>(),
+        _ => Vec::new(),
+    };
+    let mut client = Client::new(pubsub.clone(), addr, laddr);
+    match resync.as_slice() {
+        ["CONTINUE", rest @ ..] => {
+            // Older masters don't say their ID, as it can't have changed
+            if let [replid] = rest {
+                replication::continued(replid);
+            }
+            client.db = replication::stream_db();
+            replication::touch_link();
+            info!("MASTER <-> REPLICA sync: Master accepted a Partial Resynchronization.");
+        }
+        ["FULLRESYNC", replid, offset] => {
+            let offset = offset.parse()?;
+            info!("MASTER <-> REPLICA sync: receiving the snapshot");
+            replication::set_link(replication::Link::Syncing);
+            let snapshot = master.read_snapshot().await?;
+            replication::touch_link();
+
+            let mut dbs = db.lock_all().await;
+            for keyspace in dbs.iter_mut() {
+                keyspace.clear();
+            }
+            function::flush();
+            snapshot::decode(&snapshot, &mut dbs)?;
+            replication::synced(replid.to_string(), offset);
+            for index in 0..dbs.len() {
+                blocked.signal_db(index);
+            }
+            info!("MASTER <-> REPLICA sync: Finished with success");
+        }
+        _ => anyhow::bail!("unexpected reply to PSYNC: {reply:?}"),
+    }
+    replication::set_link(replication::Link::Up);
+    if failover::state() == failover::State::InProgress {
+        info!("Failover successful");
+        failover::finished();
+    }
+
+    let mut acks = tokio::time::interval(Duration::from_secs(1));
+    // The commands of a transaction the master's begun, held until its EXEC
+    let mut transaction: Option<Vec<(String, Vec<Vec<u8>>)>> = None;
+    loop {
+        let value = tokio::select! {
+            value = master.read() => value?.ok_or_else(|| anyhow::anyhow!("connection closed"))?,
+            _ = acks.tick() => {
+                let offset = replication::offset().to_string();
+                master.write(command(&["replconf", "ack", &offset])).await?;
+                continue;
+            }
+        };
+        replication::touch_link();
+        let bytes = value.clone().serialise(false);
+        let (name, args) = extract_command(value)?;
+        let name = name.to_lowercase();
+
+        // The acknowledgement counts everything before the request, but not the request
+        if name == "replconf" && args.first().is_some_and(|arg| cmd::lower(arg) == "getack") {
+            let offset = replication::offset().to_string();
+            master.write(command(&["replconf", "ack", &offset])).await?;
+            replication::proxy(&bytes);
+            continue;
+        }
+
+        // A transaction is applied all at once at its EXEC, as the master ran it
+        let commands = match (name.as_str(), &mut transaction) {
+            ("multi", _) => {
+                transaction = Some(Vec::new());
+                replication::proxy(&bytes);
+                continue;
+            }
+            ("exec", transaction) => transaction.take().unwrap_or_default(),
+            (_, Some(queued)) => {
+                queued.push((name, args));
+                replication::proxy(&bytes);
+                continue;
+            }
+            (_, None) => vec![(name, args)],
+        };
+
+        let mut dbs = db.lock_all().await;
+        replication::set_applying(true);
+        propagate::hold();
+        for (name, args) in &commands {
+            let reply = match cmd::check_arity(name, args) {
+                Ok(()) => match execute(&mut client, pubsub, blocked, &mut dbs, name, args) {
+                    Outcome::Reply(reply) => reply,
+                    Outcome::Block(block) => block.attempt_now(&mut dbs[client.db]),
+                },
+                Err(e) => e,
+            };
+            if let Some(e) = reply.error_message() {
+                warn!("Error applying a command from MASTER: {e}");
+            }
+            for key in blocking::ready_keys(name, args) {
+                blocked.signal(client.db, key);
+            }
+        }
+        propagate::release();
+        replication::set_applying(false);
+        drop(dbs);
+        replication::set_stream_db(client.db);
+        replication::proxy(&bytes);
+    }
+}
+
+/// Sends a replica the snapshot it starts from, if it isn't carrying on from the backlog, and
+/// then the stream of writes, until its connection goes. SYNC, the older form, gets no
+/// FULLRESYNC line first.
+pub(crate) async fn serve_replica(
+    handler: &mut resp::RespHandler,
+    client: &Client,
+    addr: SocketAddr,
+    killed: &mut client::KillSignal,
+    psync: bool,
+    mut sync: replication::Sync,
+) {
+    handler.set_muted(false);
+    match sync.start {
+        replication::Start::Continue(replid) => {
+            let reply = format!("CONTINUE {replid}");
+            if handler.write(Value::SimpleString(reply)).await.is_err() {
+                return;
+            }
+        }
+        replication::Start::Full(mut ready) => {
+            let snapshot = loop {
+                tokio::select! {
+                    snapshot = &mut ready => match snapshot {
+                        Ok(snapshot) => break snapshot,
+                        Err(_) => return,
+                    },
+                    // Nothing the replica says means anything until it has synced
+                    value = handler.read() => {
+                        let Ok(Some(_)) = value else {
+                            return;
+                        };
+                    }
+                    _ = killed.killed() => return,
+                }
+            };
+
+            // The snapshot goes like a bulk string, but without the trailing CRLF. One
+            // that's streamed as it's taken can't say its length up front, so it ends with a
+            // marker instead, if the replica knows to look for one.
+            let mark = (snapshot.diskless && client.capa_eof).then(replication::random_id);
+            let header = match &mark {
+                Some(mark) => format!("$EOF:{mark}\r\n"),
+                None => format!("${}\r\n", snapshot.bytes.len()),
+            };
+            let mut sent = Ok(());
+            if psync {
+                let reply = format!("FULLRESYNC {} {}", snapshot.replid, snapshot.offset);
+                sent = handler.write(Value::SimpleString(reply)).await;
+            }
+            if sent.is_err()
+                || handler.write_bytes(header.as_bytes()).await.is_err()
+                || handler.write_bytes(&snapshot.bytes).await.is_err()
+                || handler
+                    .write_bytes(mark.unwrap_or_default().as_bytes())
+                    .await
+                    .is_err()
+            {
+                return;
+            }
+            if snapshot.diskless {
+                info!("Streamed RDB transfer with replica {addr} succeeded");
+            }
+        }
+    }
+
+    loop {
+        tokio::select! {
+            writes = sync.writes.recv() => {
+                let Some(writes) = writes else {
+                    return;
+                };
+                sync.output.dequeue(writes.len());
+                tokio::select! {
+                    written = handler.write_bytes(&writes) => if written.is_err() {
+                        return;
+                    },
+                    _ = sync.output.overflowed() => {
+                        warn!("Closing replica {addr} for overcoming its output buffer limits");
+                        return;
+                    }
+                }
+            }
+            _ = sync.output.overflowed() => {
+                warn!("Closing replica {addr} for overcoming its output buffer limits");
+                return;
+            }
+            // Replicas only ever say how far they've got, with REPLCONF ACK offset
+            value = handler.read() => {
+                let Ok(Some(value)) = value else {
+                    return;
+                };
+                if let Ok((name, args)) = extract_command(value)
+                    && name.eq_ignore_ascii_case("replconf")
+                    && let [option, offset] = args.as_slice()
+                    && cmd::lower(option) == "ack"
+                    && let Some(offset) = cmd::parse_int(offset)
+                {
+                    replication::ack(client.id, offset);
+                }
+            }
+            _ = killed.killed() => return,
+        }
+    }
+}
+
+/// Runs a transaction's queued commands with every shard locked, so no other client sees it half
+/// done. Replies with a null array instead if a watched key changed.
+pub(crate) async fn exec(
+    client: &mut Client,
+    pubsub: &PubSub,
+    db: &Db,
+    blocked: &BlockedClients,
+    transaction: Transaction,
+) -> Value {
+    let mut dbs = db.lock_all().await;
+    let aborted = client.watched_changed(&mut dbs);
+    client.unwatch();
+    if aborted {
+        return Value::NullArray;
+    }
+
+    // Its writes go to replicas and the append-only file together, once it's run
+    propagate::hold();
+    let replies = transaction
+        .into_commands()
+        .iter()
+        .map(|(name, args)| {
+            let reply = match execute(client, pubsub, blocked, &mut dbs, name, args) {
+                Outcome::Reply(reply) => reply,
+                Outcome::Block(block) => block.attempt_now(&mut dbs[client.db]),
+            };
+            // A queued SELECT moves the rest of the transaction, so signal as we go
+            for key in blocking::ready_keys(name, args) {
+                blocked.signal(client.db, key);
+            }
+            reply
+        })
+        .collect();
+    propagate::release();
+    client.repl_offset = replication::offset();
+
+    Value::Array(replies)
+}
+
+/// The reply to a command that panicked, with the panic's message logged.
+fn internal_error(name: &str, panic: Box<dyn Any + Send>) -> Outcome {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message");
+    error!("Command '{name}' panicked: {message}");
+    Outcome::Reply(Value::error(format!(
+        "ERR internal error running '{name}', check the server log"
+    )))
+}
+
+/// Runs a command that needs nothing from the connection beyond its state, which is every
+/// command except the subscribe family and transaction control. These are also the commands
+/// EXEC can run from its queue.
+pub(crate) fn execute(
+    client: &mut Client,
+    pubsub: &PubSub,
+    blocked: &BlockedClients,
+    dbs: &mut [Keyspace],
+    name: &str,
+    args: &[Vec<u8>],
+) -> Outcome {
+    // Exported over OTLP when that's set up, with its status filled in once it's run
+    let span = info_span!(
+        "command",
+        otel.name = name,
+        otel.kind = "server",
+        otel.status_code = field::Empty,
+        otel.status_description = field::Empty,
+        db.system = "redis",
+        db.operation = name,
+        db.namespace = client.db,
+        keys = cmd::command_keys(name, args).len(),
+    );
+    let _entered = span.enter();
+    let started = Instant::now();
+    let db_before = client.db;
+    stats::set_reading(cmd::is_read(name));
+    // TOUCH is what a NO-TOUCH connection uses to touch keys all the same
+    cmd::set_touching(!client.no_touch || name == "touch");
+    // The master's stream is applied as it was written, whatever namespace its link's user has
+    let prefix = match replication::applying() {
+        true => None,
+        false => namespace::prefix(client.user.as_deref()),
+    };
+    namespace::set(prefix.clone());
+
+    let dirty = db::dirtied();
+    // A bug in one command fails only that command, rather than the connection with it
+    let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+        run(client, pubsub, blocked, dbs, name, args)
+    }));
+    let outcome = match ran.unwrap_or_else(|panic| internal_error(name, panic)) {
+        Outcome::Reply(reply) => {
+            if db::dirtied() != dirty {
+                propagate::propagate(db_before, name, args, &reply);
+                client.repl_offset = replication::offset();
+            }
+            Outcome::Reply(reply)
+        }
+        // Whatever serves it changes the dataset
+        Outcome::Block(block) => {
+            let (name, args) = (name.to_string(), args.to_vec());
+            Outcome::Block(
+                block.on_served(move |reply| propagate::propagate(db_before, &name, &args, reply)),
+            )
+        }
+    };
+    // The keys a reply names go back to the names the client knows them by
+    let outcome = match (outcome, prefix) {
+        (Outcome::Reply(reply), Some(prefix)) => {
+            Outcome::Reply(namespace::strip(&prefix, name, reply))
+        }
+        (Outcome::Block(block), Some(prefix)) => {
+            let name = name.to_string();
+            Outcome::Block(block.map_reply(move |reply| namespace::strip(&prefix, &name, reply)))
+        }
+        (outcome, None) => outcome,
+    };
+    if cmd::is_command(name) {
+        let failed = matches!(&outcome, Outcome::Reply(reply) if reply.error_message().is_some());
+        account(client, db_before, name, args, started, failed);
+        audit::record(client, db_before, name, args, failed);
+    }
+    if let Outcome::Reply(reply) = &outcome {
+        match reply.error_message() {
+            Some(message) => {
+                span.record("otel.status_code", "error");
+                span.record("otel.status_description", message);
+            }
+            None => {
+                span.record("otel.status_code", "ok");
+            }
+        }
+    }
+
+    outcome
+}
+
+/// Runs a command for a client that isn't speaking RESP, like one of memcached's or the REST
+/// gateway's, with the checks a RESP connection makes first: that it has as many arguments as
+/// it takes, that the client's logged in, that its user may run the command and, for a write,
+/// that the server isn't a read-only replica. Only for commands that never block.
+pub(crate) fn execute_checked(
+    client: &mut Client,
+    pubsub: &PubSub,
+    blocked: &BlockedClients,
+    dbs: &mut [Keyspace],
+    name: &str,
+    args: &[Vec<u8>],
+) -> Value {
+    client.record(name);
+    if let Err(e) = cmd::check_arity(name, args) {
+        return e;
+    }
+    let Some(user) = &client.user else {
+        return Value::error("NOAUTH Authentication required.");
+    };
+    if let Err(e) = acl::check(user, name, args) {
+        return e;
+    }
+    if let Some(refusal) = replication::refusal(name) {
+        return refusal;
+    }
+    let args = &namespace::qualified(client.user.as_deref(), name, args);
+
+    let Outcome::Reply(reply) = execute(client, pubsub, blocked, dbs, name, args) else {
+        unreachable!("'{name}' blocked");
+    };
+    for key in blocking::ready_keys(name, args) {
+        blocked.signal(client.db, key);
+    }
+    reply
+}
+
+/// Accounts for a command `client` just ran on database `db`: it's counted and timed for INFO
+/// and LATENCY, logged if it was slow, and copied to any MONITORs. Commands that may carry a password are
+/// only counted.
+fn account(
+    client: &Client,
+    db: usize,
+    name: &str,
+    args: &[Vec<u8>],
+    started: Instant,
+    failed: bool,
+) {
+    let elapsed = started.elapsed();
+    stats::record_command(name, elapsed, failed);
+    latency::observe(name, elapsed);
+    let event = if cmd::is_fast(name) {
+        "fast-command"
+    } else {
+        "command"
+    };
+    latency::record(event, elapsed);
+    if matches!(name, "auth" | "hello") {
+        return;
+    }
+
+    slowlog::record(name, args, elapsed, || client::peer(client.id));
+    if name != "monitor" {
+        client::feed_monitors(client.id, db, name, args);
+    }
+}
+
+pub(crate) fn run(
+    client: &mut Client,
+    pubsub: &PubSub,
+    blocked: &BlockedClients,
+    dbs: &mut [Keyspace],
+    name: &str,
+    args: &[Vec<u8>],
+) -> Outcome {
+    db::select(client.db);
+    tracking::set_origin(client.id);
+
+    let mut ctx = Context {
+        client,
+        pubsub,
+        blocked,
+        dbs,
+    };
+    let reply = match registry::run(&mut ctx, name, args) {
+        Some(Outcome::Reply(reply)) => reply,
+        Some(Outcome::Block(block)) => return Outcome::Block(block),
+        None => Value::error(format!("Invalid command: {name}")),
+    };
+    db::drop_expired(dbs);
+    db::bump_versions(dbs);
+    tracking::remember(client.id, name, args);
+
+    reply.into()
+}
+
+fn extract_command(value: Value) -> anyhow::Result<(String, Vec<Vec<u8>>)> {
+    match value {
+        Value::Array(a) => {
+            let mut parts = a.into_iter().map(unpack_bulk_str);
+
+            let command = parts.next().unwrap_or_else(|| {
+                Err(anyhow::anyhow!(
+                    "Received non-bulk-string input or empty input"
+                ))
+            })?;
+
+            Ok((
+                String::from_utf8_lossy(&command).into_owned(),
+                parts.collect::<anyhow::Result<_>>()?,
+            ))
+        }
+        _ => Err(anyhow::anyhow!("Unexpected command format")),
+    }
+}
+
+fn unpack_bulk_str(value: Value) -> anyhow::Result<Vec<u8>> {
+    match value {
+        Value::BulkString(s) => Ok(s.into()),
+        _ => Err(anyhow::anyhow!("Expected command to be a bulk string")),
+    }
+}
@@ -0,0 +1,165 @@
+use crate::encoding::{
+    self, EncodedIter, SET_MAX_INTSET_ENTRIES, SET_MAX_LISTPACK_ENTRIES, SET_MAX_LISTPACK_VALUE,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+#[derive(Clone)]
+enum Members {
+    /// Sorted integers, for sets whose members are all canonical integers.
+    Intset(Vec<i64>),
+    /// Members in insertion order, searched linearly.
+    Listpack(Vec<Vec<u8>>),
+    Table(HashSet<Vec<u8>>),
+}
+
+/// An unordered set of byte strings. Small sets start out compact and move to a hash table once
+/// they outgrow the intset and listpack thresholds.
+#[derive(Clone)]
+pub struct Set {
+    members: Members,
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self {
+            members: Members::Intset(Vec::new()),
+        }
+    }
+}
+
+/// The member as an integer, if it's in the canonical form an intset can give back verbatim.
+fn as_int(member: &[u8]) -> Option<i64> {
+    let n: i64 = std::str::from_utf8(member).ok()?.parse().ok()?;
+
+    (n.to_string().as_bytes() == member).then_some(n)
+}
+
+impl Set {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.members {
+            Members::Intset(ints) => ints.len(),
+            Members::Listpack(list) => list.len(),
+            Members::Table(table) => table.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The name reported by `OBJECT ENCODING`.
+    pub fn encoding(&self) -> &'static str {
+        match &self.members {
+            Members::Intset(_) => "intset",
+            Members::Listpack(_) => "listpack",
+            Members::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.members {
+            Members::Intset(ints) => as_int(member).is_some_and(|n| ints.binary_search(&n).is_ok()),
+            Members::Listpack(list) => list.iter().any(|m| m == member),
+            Members::Table(table) => table.contains(member),
+        }
+    }
+
+    /// Adds `member`, returning `false` if it was already present.
+    pub fn insert(&mut self, member: Vec<u8>) -> bool {
+        if let Members::Intset(ints) = &mut self.members
+            && let Some(n) = as_int(&member)
+        {
+            let Err(pos) = ints.binary_search(&n) else {
+                return false;
+            };
+            if ints.len() < encoding::limit(&SET_MAX_INTSET_ENTRIES) {
+                ints.insert(pos, n);
+                return true;
+            }
+        }
+
+        if self.contains(&member) {
+            return false;
+        }
+
+        if let Members::Intset(ints) = &self.members {
+            let list: Vec<Vec<u8>> = ints.iter().map(|n| n.to_string().into_bytes()).collect();
+            self.members = Members::Listpack(list);
+        }
+
+        match &mut self.members {
+            Members::Listpack(list) => {
+                list.push(member);
+
+                let longest = list.iter().map(Vec::len).max().unwrap_or(0);
+                if !encoding::fits(
+                    list.len(),
+                    longest,
+                    &SET_MAX_LISTPACK_ENTRIES,
+                    &SET_MAX_LISTPACK_VALUE,
+                ) {
+                    self.members = Members::Table(std::mem::take(list).into_iter().collect());
+                }
+            }
+            Members::Table(table) => {
+                table.insert(member);
+            }
+            Members::Intset(_) => unreachable!("intsets were converted above"),
+        }
+
+        true
+    }
+
+    /// Removes `member`, returning whether it was present.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.members {
+            Members::Intset(ints) => {
+                let Some(Ok(pos)) = as_int(member).map(|n| ints.binary_search(&n)) else {
+                    return false;
+                };
+                ints.remove(pos);
+            }
+            Members::Listpack(list) => {
+                let Some(pos) = list.iter().position(|m| m == member) else {
+                    return false;
+                };
+                list.remove(pos);
+            }
+            Members::Table(table) => return table.remove(member),
+        }
+
+        true
+    }
+
+    /// Members in no particular order. Intset members are rendered back to strings on the way
+    /// out.
+    pub fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        match &self.members {
+            Members::Intset(ints) => EncodedIter::Compact(EncodedIter::Compact(
+                ints.iter().map(|n| Cow::Owned(n.to_string().into_bytes())),
+            )),
+            Members::Listpack(list) => EncodedIter::Compact(EncodedIter::Full(
+                list.iter().map(|m| Cow::Borrowed(m.as_slice())),
+            )),
+            Members::Table(table) => {
+                EncodedIter::Full(table.iter().map(|m| Cow::Borrowed(m.as_slice())))
+            }
+        }
+    }
+}
+
+impl FromIterator<Vec<u8>> for Set {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(iter: I) -> Self {
+        let mut set = Set::new();
+        for member in iter {
+            set.insert(member);
+        }
+
+        set
+    }
+}
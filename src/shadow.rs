@@ -0,0 +1,192 @@
+//! Shadowing: every command clients send is sent on to another server as well, a real Redis
+//! for one, while `shadow-target` names it, and any reply of its that differs from the one
+//! given here is logged and counted. Each client gets a connection of its own to the target, so
+//! SELECT, MULTI and the like carry over.
+
+use crate::cmd::lower;
+use crate::resp::{RespHandler, Value};
+use crate::stats;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+/// Commands that aren't sent on: those that change the connection's protocol or how it's
+/// answered, and those that would reach beyond the dataset to the target itself.
+const NOT_SENT: &[&str] = &[
+    "auth",
+    "hello",
+    "client",
+    "config",
+    "debug",
+    "monitor",
+    "shutdown",
+    "replicaof",
+    "slaveof",
+    "failover",
+    "sync",
+    "psync",
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ssubscribe",
+    "sunsubscribe",
+];
+
+/// Commands whose replies are sent on but not compared, since they're about the server that
+/// gives them or are picked at random.
+const NOT_COMPARED: &[&str] = &[
+    "info",
+    "time",
+    "lastsave",
+    "memory",
+    "object",
+    "latency",
+    "slowlog",
+    "command",
+    "role",
+    "lolwut",
+    "randomkey",
+    "spop",
+    "srandmember",
+    "hrandfield",
+    "zrandmember",
+    "scan",
+    "sscan",
+    "hscan",
+    "zscan",
+    "xadd",
+];
+
+/// Commands that reply with an array in no particular order, which is compared sorted.
+const UNORDERED: &[&str] = &[
+    "keys", "smembers", "sinter", "sunion", "sdiff", "hkeys", "hvals", "hgetall",
+];
+
+/// The server commands are sent on to, as `host:port`, while `shadow-target` names one.
+static TARGET: Mutex<String> = Mutex::new(String::new());
+
+/// Applies a new `shadow-target`. Clients already connected go on with the one they had.
+pub fn set_target(target: &str) {
+    *TARGET.lock().unwrap() = target.to_string();
+}
+
+/// A client's connection to the target, which is kept until the client's is closed.
+pub struct Session {
+    commands: mpsc::UnboundedSender<Sent>,
+}
+
+/// A command sent on, with where the reply given here is to come from.
+struct Sent {
+    args: Vec<Vec<u8>>,
+    local: oneshot::Receiver<Value>,
+}
+
+/// Sends `command` with `args` on to the target, connecting `session` to it first if need be.
+/// Returns where to send the reply given here to compare it with the target's, or `None` if
+/// there's nothing to compare it with. One that's never sent isn't compared.
+pub fn forward(
+    session: &mut Option<Session>,
+    command: &str,
+    args: &[Vec<u8>],
+) -> Option<oneshot::Sender<Value>> {
+    let name = lower(command.as_bytes());
+    if NOT_SENT.contains(&name.as_str()) {
+        return None;
+    }
+    if session.is_none() {
+        let target = TARGET.lock().unwrap().clone();
+        if target.is_empty() {
+            return None;
+        }
+        let (commands, queue) = mpsc::unbounded_channel();
+        tokio::spawn(relay(target, queue));
+        *session = Some(Session { commands });
+    }
+
+    let (reply, local) = oneshot::channel();
+    let mut sent = vec![command.as_bytes().to_vec()];
+    sent.extend_from_slice(args);
+    session
+        .as_ref()?
+        .commands
+        .send(Sent { args: sent, local })
+        .ok()?;
+    Some(reply)
+}
+
+/// Sends a client's commands to `target` one at a time, comparing each reply with the one
+/// given here. Runs until the client's connection closes or the target's fails.
+async fn relay(target: String, mut queue: mpsc::UnboundedReceiver<Sent>) {
+    let mut connection = match TcpStream::connect(&target).await {
+        Ok(stream) => RespHandler::new(stream),
+        Err(e) => {
+            warn!("Can't connect to the shadow target {target}: {e}");
+            return;
+        }
+    };
+
+    while let Some(Sent { args, local }) = queue.recv().await {
+        let name = lower(&args[0]);
+        let shown = String::from_utf8_lossy(&args[0]).into_owned();
+        let command = args
+            .into_iter()
+            .map(|arg| Value::BulkString(arg.into()))
+            .collect();
+        let remote = match connection.write(Value::Array(command)).await {
+            Ok(()) => connection.read().await,
+            Err(e) => Err(e),
+        };
+        let remote = match remote {
+            Ok(Some(remote)) => remote,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Lost the connection to the shadow target {target}: {e}");
+                return;
+            }
+        };
+
+        let Ok(local) = local.await else {
+            continue;
+        };
+        if NOT_COMPARED.contains(&name.as_str()) {
+            continue;
+        }
+        let unordered = UNORDERED.contains(&name.as_str());
+        let (local, remote) = (normalise(local, unordered), normalise(remote, unordered));
+        if local.clone().serialise(false) != remote.clone().serialise(false) {
+            stats::SHADOW_DIVERGENCES.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Shadow target {target} replied differently to {shown}: {local:?} here, \
+                 {remote:?} there"
+            );
+        }
+    }
+}
+
+/// A reply in a form that's the same whichever server gave it: errors by their code alone,
+/// simple and bulk strings alike, RESP3's types as RESP2 has them, and the arrays of unordered
+/// replies sorted.
+fn normalise(reply: Value, unordered: bool) -> Value {
+    if let Some(e) = reply.error_message() {
+        let code = e.split(' ').next().unwrap_or_default();
+        return Value::error(code);
+    }
+    let items = match reply {
+        Value::SimpleString(s) => return Value::BulkString(s.into()),
+        Value::NullArray => return Value::Null,
+        Value::Array(items) | Value::Push(items) => items,
+        Value::Map(pairs) => pairs.into_iter().flat_map(|(k, v)| [k, v]).collect(),
+        reply => return reply,
+    };
+    let mut items: Vec<Value> = items
+        .into_iter()
+        .map(|item| normalise(item, false))
+        .collect();
+    if unordered {
+        items.sort_by_cached_key(|item| item.clone().serialise(false));
+    }
+    Value::Array(items)
+}
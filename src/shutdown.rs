@@ -0,0 +1,115 @@
+use crate::aof;
+use crate::cmd::lower;
+use crate::config;
+use crate::daemon;
+use crate::db::{Db, Locked};
+use crate::logging;
+use crate::resp::Value;
+use crate::snapshot;
+use crate::systemd;
+use crate::trace;
+use std::fs;
+use std::sync::LazyLock;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Where the server is in shutting down.
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Running,
+    /// Asked to stop, saving the dataset first if set.
+    Requested(bool),
+    /// The last request couldn't save the dataset, so the server kept running.
+    Failed,
+}
+
+static STATE: LazyLock<watch::Sender<State>> = LazyLock::new(|| watch::Sender::new(State::Running));
+
+/// Starts a newly built server off running, whatever became of the one before.
+pub fn reset() {
+    STATE.send_replace(State::Running);
+}
+
+/// Asks the server to shut down, saving first if `save` says to. `None` saves if any save
+/// points are configured, the way a signal does.
+pub fn request(save: Option<bool>) {
+    let save = save.unwrap_or_else(|| !config::get().save.is_empty());
+    STATE.send_if_modified(|state| {
+        // A second request can't change one that's already under way, but can retry one
+        // that failed
+        let idle = !matches!(state, State::Requested(_));
+        if idle {
+            *state = State::Requested(save);
+        }
+        idle
+    });
+}
+
+/// SHUTDOWN [NOSAVE|SAVE]'s arguments: whether to save, or `None` to go by the save points.
+pub fn parse_args(args: &[Vec<u8>]) -> Result<Option<bool>, Value> {
+    match args {
+        [] => Ok(None),
+        [mode] => match lower(mode).as_str() {
+            "save" => Ok(Some(true)),
+            "nosave" => Ok(Some(false)),
+            _ => Err(Value::error("ERR syntax error")),
+        },
+        _ => Err(Value::error("ERR syntax error")),
+    }
+}
+
+/// Waits until shutdown is requested, returning whether to save.
+pub async fn requested() -> bool {
+    let mut state = STATE.subscribe();
+    let requested = state
+        .wait_for(|state| matches!(state, State::Requested(_)))
+        .await
+        .expect("sender is static");
+
+    *requested == State::Requested(true)
+}
+
+/// Waits until a shutdown has failed.
+pub async fn failed() {
+    let mut state = STATE.subscribe();
+    state
+        .wait_for(|state| *state == State::Failed)
+        .await
+        .expect("sender is static");
+}
+
+/// Readies the server to stop once running commands are done, saving the dataset if asked to.
+/// Returns the keyspace locked, so nothing else is let at it while the server stops, or `None`
+/// if that save fails, leaving the server running.
+pub async fn finish(db: &Db, save: bool) -> Option<Locked> {
+    info!("User requested shutdown...");
+    let supervised = config::get().supervised.clone();
+    systemd::notify(&supervised, "STOPPING=1");
+    // Commands run holding the locks of their shards, so holding them all means none are mid-way
+    let dbs = db.lock_all().await;
+
+    if save {
+        info!("Saving the final snapshot before exiting.");
+        if snapshot::save(&dbs).is_err() {
+            warn!("Error trying to save the DB, can't exit.");
+            systemd::notify(
+                &supervised,
+                "READY=1\nSTATUS=Shutdown failed, still running",
+            );
+            STATE.send_replace(State::Failed);
+            return None;
+        }
+    }
+    aof::stop();
+    let unixsocket = config::get().unixsocket.clone();
+    if !unixsocket.is_empty() {
+        info!("Removing the unix socket file.");
+        let _ = fs::remove_file(unixsocket);
+    }
+    daemon::remove_pidfile();
+    trace::flush();
+    logging::flush();
+    info!("Redis is now ready to exit, bye bye...");
+
+    Some(dbs)
+}
@@ -0,0 +1,121 @@
+use crate::resp::Value;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Commands taking at least this many microseconds are logged. Negative turns the log off.
+pub static LOG_SLOWER_THAN: AtomicI64 = AtomicI64::new(10_000);
+
+/// How many entries the log keeps, dropping the oldest beyond that.
+static MAX_LEN: AtomicUsize = AtomicUsize::new(128);
+
+/// Arguments past this many are summarised, as are the bytes of an argument past this many.
+const MAX_ARGS: usize = 32;
+const MAX_ARG_LEN: usize = 128;
+
+/// One slow command, as SLOWLOG GET reports it.
+struct Entry {
+    id: u64,
+    /// Unix time the command finished, in seconds.
+    timestamp: u64,
+    duration: Duration,
+    /// The command name and arguments, trimmed to keep the log small.
+    args: Vec<Vec<u8>>,
+    addr: String,
+    name: String,
+}
+
+/// The log, newest entry first.
+static LOG: Mutex<VecDeque<Entry>> = Mutex::new(VecDeque::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Logs command `name` with `args` if it took long enough. `client` gives the address and name
+/// of the connection that ran it, which are only looked up if it's logged.
+pub fn record(
+    name: &str,
+    args: &[Vec<u8>],
+    duration: Duration,
+    client: impl FnOnce() -> (String, String),
+) {
+    let threshold = LOG_SLOWER_THAN.load(Ordering::Relaxed);
+    if threshold < 0 || duration.as_micros() < threshold as u128 {
+        return;
+    }
+
+    let mut logged = vec![name.as_bytes().to_vec()];
+    // Past the limit, the last slot (counting the name) goes to saying how many were left out
+    let shown = if args.len() + 1 > MAX_ARGS {
+        MAX_ARGS - 2
+    } else {
+        args.len()
+    };
+    for arg in &args[..shown] {
+        logged.push(if arg.len() > MAX_ARG_LEN {
+            let mut trimmed = arg[..MAX_ARG_LEN].to_vec();
+            trimmed.extend(format!("... ({} more bytes)", arg.len() - MAX_ARG_LEN).bytes());
+            trimmed
+        } else {
+            arg.clone()
+        });
+    }
+    if shown < args.len() {
+        logged.push(format!("... ({} more arguments)", args.len() - shown).into_bytes());
+    }
+
+    let (addr, client_name) = client();
+    let entry = Entry {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        duration,
+        args: logged,
+        addr,
+        name: client_name,
+    };
+
+    let mut log = LOG.lock().unwrap();
+    log.push_front(entry);
+    log.truncate(MAX_LEN.load(Ordering::Relaxed));
+}
+
+/// SLOWLOG GET's reply: up to `count` of the newest entries, newest first.
+pub fn get(count: usize) -> Value {
+    let bulk = |bytes: &[u8]| Value::BulkString(Bytes::copy_from_slice(bytes));
+
+    Value::Array(
+        LOG.lock()
+            .unwrap()
+            .iter()
+            .take(count)
+            .map(|entry| {
+                Value::Array(vec![
+                    Value::Integer(entry.id as i64),
+                    Value::Integer(entry.timestamp as i64),
+                    Value::Integer(entry.duration.as_micros() as i64),
+                    Value::Array(entry.args.iter().map(|arg| bulk(arg)).collect()),
+                    bulk(entry.addr.as_bytes()),
+                    bulk(entry.name.as_bytes()),
+                ])
+            })
+            .collect(),
+    )
+}
+
+pub fn len() -> usize {
+    LOG.lock().unwrap().len()
+}
+
+pub fn reset() {
+    LOG.lock().unwrap().clear();
+}
+
+/// Drops the oldest entries beyond a newly lowered maximum length.
+pub fn set_max_len(max_len: usize) {
+    MAX_LEN.store(max_len, Ordering::Relaxed);
+    LOG.lock().unwrap().truncate(max_len);
+}
@@ -0,0 +1,430 @@
+use crate::config::{self, ServerConfig};
+use crate::crc64::crc64;
+use crate::crypt;
+use crate::db::{self, DBData, Db, Keyspace};
+use crate::function::{self, Library, RestorePolicy};
+use crate::rdb::{self, Reader};
+use crate::s3::S3Store;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Snapshots are RDB files, the format Redis itself saves in: a magic string and version, then
+/// records that each start with one of the opcodes below or, for keys, the value's type, then
+/// a checksum of everything before it.
+const MAGIC: &[u8] = b"REDIS";
+
+/// A function library's source.
+const OP_FUNCTION: u8 = 0xf5;
+/// Cluster slot sizes, which aren't needed here.
+const OP_SLOT_INFO: u8 = 0xf4;
+/// A module's own data, which there's nothing to load into here.
+const OP_MODULE_AUX: u8 = 0xf7;
+/// The next key's LRU idle time or LFU counter.
+const OP_IDLE: u8 = 0xf8;
+const OP_FREQ: u8 = 0xf9;
+/// A name and value about the file or the server that wrote it.
+const OP_AUX: u8 = 0xfa;
+/// How big the next database's tables are.
+const OP_RESIZE_DB: u8 = 0xfb;
+/// The next key's expiry, as unix milliseconds or seconds.
+const OP_EXPIRE_MS: u8 = 0xfc;
+const OP_EXPIRE_SECS: u8 = 0xfd;
+/// The keys after this belong to the database it names.
+const OP_SELECT_DB: u8 = 0xfe;
+const OP_EOF: u8 = 0xff;
+
+/// Whether a BGSAVE is writing a snapshot out right now.
+static SAVING: AtomicBool = AtomicBool::new(false);
+
+/// Unix time in seconds of the last successful save, or of startup if there hasn't been one.
+pub static LAST_SAVE: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the last BGSAVE succeeded, when it finished in unix seconds, and how many seconds it
+/// took, or -1 if there hasn't been one.
+pub static LAST_BGSAVE_OK: AtomicBool = AtomicBool::new(true);
+static LAST_BGSAVE_TRY: AtomicU64 = AtomicU64::new(0);
+pub static LAST_BGSAVE_SECS: AtomicI64 = AtomicI64::new(-1);
+
+/// `db::dirty()` as of the dataset the last successful save wrote out.
+static DIRTY_AT_SAVE: AtomicU64 = AtomicU64::new(0);
+
+/// How long to wait after a failed BGSAVE before a save point may try again.
+const RETRY_DELAY_SECS: u64 = 5;
+
+/// Where snapshots are kept: SAVE and BGSAVE store them there, and the dataset is loaded from
+/// there at startup. Each is named for `dbfilename`, and is already encrypted if encryption is
+/// on. Both methods block, and are called off the clients' tasks where it matters.
+pub trait SnapshotStore: Send + Sync {
+    /// Stores `bytes` as the snapshot `name`, replacing any there was. A failure must leave
+    /// the old one as it was, not part of the new one.
+    fn save(&self, name: &str, bytes: &[u8]) -> io::Result<()>;
+
+    /// The snapshot `name`, or `None` if there isn't one.
+    fn load(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// Snapshots as files in `dir`, which is where they're kept unless another store is set up.
+struct Files;
+
+impl SnapshotStore for Files {
+    fn save(&self, name: &str, bytes: &[u8]) -> io::Result<()> {
+        // A SAVE can run while a BGSAVE is still writing, so each gets its own temporary file
+        static NEXT_TEMPORARY: AtomicU64 = AtomicU64::new(0);
+        let dir = config::get().dir.clone();
+        let temporary = Path::new(&dir).join(format!(
+            "temp-{}-{}.rdb",
+            std::process::id(),
+            NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temporary, bytes)?;
+        fs::rename(&temporary, Path::new(&dir).join(name))
+    }
+
+    fn load(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(Path::new(&config::get().dir).join(name)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The store a [`Builder`](crate::Builder) was given or `snapshot-store` names, or `None` for
+/// files.
+static STORE: RwLock<Option<Arc<dyn SnapshotStore>>> = RwLock::new(None);
+
+/// Keeps snapshots in `store` if there is one, or else where `config` says.
+pub fn install(store: Option<Box<dyn SnapshotStore>>, config: &ServerConfig) -> anyhow::Result<()> {
+    let store: Option<Arc<dyn SnapshotStore>> = match (store, config.snapshot_store.as_str()) {
+        (Some(store), _) => Some(store.into()),
+        (None, "s3") => Some(Arc::new(S3Store::from_config(config)?)),
+        (None, _) => None,
+    };
+    *STORE.write().unwrap() = store;
+    Ok(())
+}
+
+fn store() -> Arc<dyn SnapshotStore> {
+    STORE
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| Arc::new(Files))
+}
+
+/// The name snapshots are saved and loaded under: `dbfilename`.
+pub fn name() -> String {
+    config::get().dbfilename.clone()
+}
+
+pub fn in_progress() -> bool {
+    SAVING.load(Ordering::Relaxed)
+}
+
+/// How many changes the dataset has had since it was last saved.
+pub fn changes_since_save() -> u64 {
+    db::dirty().saturating_sub(DIRTY_AT_SAVE.load(Ordering::Relaxed))
+}
+
+/// Counts the dataset as it stands as saved, as it is right after being loaded.
+pub fn mark_saved() {
+    DIRTY_AT_SAVE.store(db::dirty(), Ordering::Relaxed);
+}
+
+/// The dataset as of some moment: every database, frozen, and the function libraries. Taking
+/// it is quick, and serialising it can then take as long as it likes without holding up the
+/// clients writing meanwhile.
+pub struct Frozen {
+    pub dbs: Vec<Keyspace>,
+    pub libraries: Vec<Library>,
+}
+
+/// Freezes `dbs`, which must have every shard locked, along with the function libraries.
+pub fn freeze(dbs: &[Keyspace]) -> Frozen {
+    Frozen {
+        dbs: dbs.iter().map(Keyspace::freeze).collect(),
+        libraries: function::libraries(),
+    }
+}
+
+/// Writes the dataset out before returning.
+pub fn save(dbs: &[Keyspace]) -> io::Result<()> {
+    write(&name(), &encode(&freeze(dbs), false))
+        .inspect_err(|e| warn!("Failed saving the DB: {e}"))?;
+    mark_saved();
+    LAST_SAVE.store(unix_secs(), Ordering::Relaxed);
+    info!("DB saved on disk");
+
+    Ok(())
+}
+
+/// Writes out a snapshot taken for replicas to sync from, as of when `db::dirty()` was `dirty`,
+/// then hands it back to be sent to them once it's safely stored.
+pub fn save_for_sync(bytes: Vec<u8>, dirty: u64) -> io::Result<Vec<u8>> {
+    write(&name(), &bytes).inspect_err(|e| warn!("Failed saving the DB for SYNC: {e}"))?;
+    DIRTY_AT_SAVE.fetch_max(dirty, Ordering::Relaxed);
+    LAST_SAVE.store(unix_secs(), Ordering::Relaxed);
+    info!("DB saved on disk");
+
+    Ok(bytes)
+}
+
+/// Freezes the dataset, then serialises it and writes it out on another thread, so clients
+/// don't wait for either and the snapshot is still of the moment it started. Returns false
+/// without doing anything if a background save is already running.
+pub fn bgsave(dbs: &[Keyspace]) -> bool {
+    if SAVING.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+
+    let frozen = freeze(dbs);
+    let dirty = db::dirty();
+    let name = name();
+    info!("Background saving started");
+    let started = Instant::now();
+    tokio::task::spawn_blocking(move || {
+        let result = write(&name, &encode(&frozen, false));
+        match &result {
+            Ok(()) => {
+                // Only what was serialised counts as saved, not writes made since
+                DIRTY_AT_SAVE.fetch_max(dirty, Ordering::Relaxed);
+                LAST_SAVE.store(unix_secs(), Ordering::Relaxed);
+                info!("Background saving terminated with success");
+            }
+            Err(e) => warn!("Background saving error: {e}"),
+        }
+        LAST_BGSAVE_OK.store(result.is_ok(), Ordering::Relaxed);
+        LAST_BGSAVE_TRY.store(unix_secs(), Ordering::Relaxed);
+        LAST_BGSAVE_SECS.store(started.elapsed().as_secs() as i64, Ordering::Relaxed);
+        SAVING.store(false, Ordering::Relaxed);
+    });
+
+    true
+}
+
+/// Starts a BGSAVE whenever one of the `save` points is reached: at least `changes` changes,
+/// with `seconds` gone by since the last save. Runs for as long as the server does.
+pub async fn save_on_schedule(db: Db) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if in_progress() {
+            continue;
+        }
+
+        let now = unix_secs();
+        let since_save = now.saturating_sub(LAST_SAVE.load(Ordering::Relaxed));
+        // A failing disk gets a rest before the next try
+        let may_retry = LAST_BGSAVE_OK.load(Ordering::Relaxed)
+            || now.saturating_sub(LAST_BGSAVE_TRY.load(Ordering::Relaxed)) >= RETRY_DELAY_SECS;
+        let changes = changes_since_save();
+        let reached = config::get()
+            .save
+            .iter()
+            .find(|(seconds, at_least)| changes >= *at_least && since_save >= *seconds)
+            .copied();
+
+        if let Some((seconds, _)) = reached
+            && may_retry
+        {
+            info!("{changes} changes in {seconds} seconds. Saving...");
+            bgsave(&db.lock_all().await);
+        }
+    }
+}
+
+/// Reads the snapshot `name` from the store into `dbs`, restoring its function libraries too.
+/// Keys whose TTL ran out while the server was down are left out, and a missing snapshot is
+/// nothing to load.
+pub fn load(name: &str, dbs: &mut [Keyspace]) -> anyhow::Result<()> {
+    LAST_SAVE.store(unix_secs(), Ordering::Relaxed);
+
+    let Some(bytes) = store().load(name)? else {
+        return Ok(());
+    };
+    let started = Instant::now();
+
+    let bytes = if crypt::is_encrypted(&bytes) {
+        let opened = crypt::decrypt(&bytes)?;
+        if opened.len != bytes.len() {
+            anyhow::bail!("encrypted file cut short");
+        }
+        opened.plaintext
+    } else {
+        bytes
+    };
+    let (len, keys) = decode(&bytes, dbs)?;
+    if len != bytes.len() {
+        anyhow::bail!("trailing bytes after the end of the file");
+    }
+
+    info!(
+        "DB loaded from disk: {keys} keys in {:.3} seconds",
+        started.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
+
+/// Reads the snapshot at the start of `bytes` into `dbs`, returning how many bytes it took up
+/// and how many keys it held. Whatever follows it is left alone, as the commands after an
+/// append-only file's preamble are.
+pub fn decode(bytes: &[u8], dbs: &mut [Keyspace]) -> anyhow::Result<(usize, usize)> {
+    let mut reader = Reader::new(bytes);
+    if reader.take(MAGIC.len())? != MAGIC {
+        anyhow::bail!("not an RDB file");
+    }
+    let version: u32 = std::str::from_utf8(reader.take(4)?)?.parse()?;
+    if !(1..=rdb::VERSION_FIELD_TTLS).contains(&version) {
+        anyhow::bail!("can't handle RDB format version {version}");
+    }
+
+    let now = db::unix_millis();
+    let mut index = 0;
+    let mut expires_at = None;
+    let mut keys = 0;
+    loop {
+        match reader.u8()? {
+            OP_FUNCTION => {
+                let code = reader.string()?;
+                function::restore(&[code], RestorePolicy::Append)
+                    .map_err(|e| anyhow::anyhow!("bad function library: {e:?}"))?;
+            }
+            OP_SLOT_INFO => {
+                for _ in 0..3 {
+                    reader.len()?;
+                }
+            }
+            OP_MODULE_AUX => anyhow::bail!("module data isn't supported"),
+            OP_IDLE => {
+                reader.len()?;
+            }
+            OP_FREQ => {
+                reader.u8()?;
+            }
+            OP_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OP_RESIZE_DB => {
+                reader.len()?;
+                reader.len()?;
+            }
+            OP_EXPIRE_MS => expires_at = Some(reader.millis()?),
+            OP_EXPIRE_SECS => {
+                let secs = u32::from_le_bytes(reader.take(4)?.try_into()?);
+                expires_at = Some(secs as u64 * 1000);
+            }
+            OP_SELECT_DB => {
+                index = usize::try_from(reader.len()?)?;
+                if index >= dbs.len() {
+                    anyhow::bail!("database {index} is out of range");
+                }
+            }
+            OP_EOF => break,
+            kind => {
+                let key = reader.string()?;
+                let val = rdb::read_value(&mut reader, kind)?;
+                let expires_at = expires_at.take();
+                if expires_at.is_some_and(|at| at <= now) {
+                    continue;
+                }
+                dbs[index].insert(key, DBData::new(val, expires_at));
+                keys += 1;
+            }
+        }
+    }
+
+    // Versions before 5 had no checksum, and files saved with rdbchecksum off leave it zeroed
+    let mut len = bytes.len() - reader.remaining();
+    if version >= 5 {
+        let crc = reader.take(8)?;
+        if crc != [0; 8] && crc64(0, &bytes[..len]).to_le_bytes() != crc {
+            anyhow::bail!("checksum mismatch");
+        }
+        len += 8;
+    }
+
+    Ok((len, keys))
+}
+
+/// Serialises every database and the function libraries, as a file of its own or as the
+/// preamble of an append-only file.
+pub fn encode(frozen: &Frozen, aof_base: bool) -> Vec<u8> {
+    let dbs = &frozen.dbs;
+    let live = || {
+        dbs.iter()
+            .flat_map(|db| db.values())
+            .filter(|val| !val.is_expired())
+    };
+    // Only files holding hashes with field TTLs need the newer format
+    let version = live()
+        .map(|val| rdb::version_for(val.data()))
+        .max()
+        .unwrap_or(rdb::VERSION);
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(format!("{version:04}").as_bytes());
+
+    let now = db::unix_millis();
+    let used_memory = crate::cmd::info::resident_bytes().to_string();
+    let aux = [
+        ("redis-ver", env!("CARGO_PKG_VERSION").to_string()),
+        ("redis-bits", (usize::BITS).to_string()),
+        ("ctime", (now / 1000).to_string()),
+        ("used-mem", used_memory),
+        ("aof-base", (aof_base as u8).to_string()),
+    ];
+    for (name, value) in aux {
+        out.push(OP_AUX);
+        rdb::write_string(&mut out, name.as_bytes());
+        rdb::write_string(&mut out, value.as_bytes());
+    }
+
+    for library in &frozen.libraries {
+        out.push(OP_FUNCTION);
+        rdb::write_string(&mut out, &library.code);
+    }
+
+    for (index, db) in dbs.iter().enumerate().filter(|(_, db)| !db.is_empty()) {
+        let live: Vec<_> = db.iter().filter(|(_, val)| !val.is_expired()).collect();
+        out.push(OP_SELECT_DB);
+        rdb::write_len(&mut out, index as u64);
+        out.push(OP_RESIZE_DB);
+        rdb::write_len(&mut out, live.len() as u64);
+        rdb::write_len(
+            &mut out,
+            live.iter()
+                .filter(|(_, val)| val.expires_at().is_some())
+                .count() as u64,
+        );
+
+        for (key, val) in live {
+            if let Some(at) = val.expires_at() {
+                out.push(OP_EXPIRE_MS);
+                rdb::write_millis(&mut out, at);
+            }
+            rdb::write_key(&mut out, key, val.data());
+        }
+    }
+
+    out.push(OP_EOF);
+    let crc = crc64(0, &out);
+    out.extend_from_slice(&crc.to_le_bytes());
+
+    out
+}
+
+/// Stores the snapshot `bytes` as `name`, encrypted on the way if encryption is on.
+fn write(name: &str, bytes: &[u8]) -> io::Result<()> {
+    store().save(name, &crypt::encrypt(bytes))
+}
+
+fn unix_secs() -> u64 {
+    db::unix_millis() / 1000
+}
@@ -0,0 +1,110 @@
+use crate::latency;
+use crate::lazyfree;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+pub static CONNECTIONS_RECEIVED: AtomicU64 = AtomicU64::new(0);
+pub static REJECTED_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+/// Commands turned away or held back by the rate limits.
+pub static THROTTLED_COMMANDS: AtomicU64 = AtomicU64::new(0);
+/// Replies the shadow target gave that differed from this server's.
+pub static SHADOW_DIVERGENCES: AtomicU64 = AtomicU64::new(0);
+pub static COMMANDS_PROCESSED: AtomicU64 = AtomicU64::new(0);
+pub static KEYSPACE_HITS: AtomicU64 = AtomicU64::new(0);
+pub static KEYSPACE_MISSES: AtomicU64 = AtomicU64::new(0);
+pub static EXPIRED_KEYS: AtomicU64 = AtomicU64::new(0);
+pub static EVICTED_KEYS: AtomicU64 = AtomicU64::new(0);
+pub static CLUSTER_MESSAGES_SENT: AtomicU64 = AtomicU64::new(0);
+pub static CLUSTER_MESSAGES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+/// When the server started, for its uptime.
+pub static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+thread_local! {
+    /// Whether the running command only reads, so its lookups count as hits and misses. Set on
+    /// the thread that runs it, like the selected database.
+    static READING: Cell<bool> = const { Cell::new(false) };
+    /// The running command's keyspace hits and misses so far.
+    static LOOKUPS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+/// Calls to one command and the time spent running them, for INFO commandstats.
+#[derive(Clone, Copy, Default)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub usec: u64,
+    /// Calls that replied with an error.
+    pub failed_calls: u64,
+    /// Keys the command's calls found, and didn't, when it only reads.
+    pub keyspace_hits: u64,
+    pub keyspace_misses: u64,
+}
+
+static COMMANDS: Mutex<BTreeMap<String, CommandStats>> = Mutex::new(BTreeMap::new());
+
+/// Records a run of command `name` that took `elapsed`, and whether it failed.
+pub fn record_command(name: &str, elapsed: Duration, failed: bool) {
+    COMMANDS_PROCESSED.fetch_add(1, Ordering::Relaxed);
+    let (hits, misses) = LOOKUPS.take();
+
+    let mut commands = COMMANDS.lock().unwrap();
+    let stats = commands.entry(name.to_string()).or_default();
+    stats.calls += 1;
+    stats.usec += elapsed.as_micros() as u64;
+    stats.failed_calls += failed as u64;
+    stats.keyspace_hits += hits;
+    stats.keyspace_misses += misses;
+}
+
+/// Every command run so far with its stats, by name.
+pub fn commands() -> BTreeMap<String, CommandStats> {
+    COMMANDS.lock().unwrap().clone()
+}
+
+/// Says whether the command about to run only reads, which lookups are counted for.
+pub fn set_reading(reading: bool) {
+    READING.set(reading);
+    LOOKUPS.set((0, 0));
+}
+
+/// Counts a key lookup as a hit or a miss, if the running command only reads.
+pub fn record_lookup(hit: bool) {
+    if !READING.get() {
+        return;
+    }
+
+    let (hits, misses) = LOOKUPS.get();
+    let (counter, lookups) = if hit {
+        (&KEYSPACE_HITS, (hits + 1, misses))
+    } else {
+        (&KEYSPACE_MISSES, (hits, misses + 1))
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    LOOKUPS.set(lookups);
+}
+
+/// CONFIG RESETSTAT: zeroes the counters INFO stats and commandstats show, and the latency
+/// histograms.
+pub fn reset() {
+    let counters = [
+        &CONNECTIONS_RECEIVED,
+        &REJECTED_CONNECTIONS,
+        &THROTTLED_COMMANDS,
+        &COMMANDS_PROCESSED,
+        &KEYSPACE_HITS,
+        &KEYSPACE_MISSES,
+        &EXPIRED_KEYS,
+        &EVICTED_KEYS,
+        &CLUSTER_MESSAGES_SENT,
+        &CLUSTER_MESSAGES_RECEIVED,
+    ];
+    for counter in counters {
+        counter.store(0, Ordering::Relaxed);
+    }
+    COMMANDS.lock().unwrap().clear();
+    latency::reset_histograms();
+    lazyfree::reset_freed();
+}
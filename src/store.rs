@@ -0,0 +1,145 @@
+//! The storage engine on its own, for a process that wants Redis' keys in memory without
+//! running a server: no listeners, no persistence, no replication, just the keyspace.
+
+use crate::db::{self, DBData, DBVal, Db};
+use bytes::Bytes;
+use std::time::Duration;
+
+/// A keyspace of string values with TTLs, kept in the same sharded storage as a server's. Keys
+/// expire lazily: once their TTL has passed they read as missing, and are dropped the next time
+/// they're written or [`Store::purge_expired`] runs.
+///
+/// It's cheap to clone, and the clones share the keys.
+#[derive(Clone)]
+pub struct Store {
+    db: Db,
+}
+
+impl Store {
+    /// An empty store.
+    pub fn open() -> Store {
+        Store {
+            db: db::new_databases(1),
+        }
+    }
+
+    /// The value of `key`, or `None` if it doesn't exist. Fails if it holds something other
+    /// than a string, which only a server sharing the storage could have put there.
+    pub async fn get(&self, key: &[u8]) -> anyhow::Result<Option<Bytes>> {
+        let dbs = self.db.lock_keys(&[key]).await;
+        let Some(val) = dbs[0].get_unexpired(key) else {
+            return Ok(None);
+        };
+        match val.data().string_value() {
+            Some(value) => Ok(Some(value)),
+            None => anyhow::bail!("WRONGTYPE {key:?} holds a {}", val.data().type_name()),
+        }
+    }
+
+    /// Sets `key` to `value`, dropping any TTL it had.
+    pub async fn set(&self, key: &[u8], value: &[u8]) {
+        let mut dbs = self.db.lock_keys(&[key]).await;
+        dbs[0].insert(key.to_vec(), DBData::new(DBVal::parse(value), None));
+    }
+
+    /// Sets `key` to `value`, to expire once `ttl` has passed.
+    pub async fn set_ex(&self, key: &[u8], value: &[u8], ttl: Duration) {
+        let mut dbs = self.db.lock_keys(&[key]).await;
+        let val = DBData::new(DBVal::parse(value), Some(expires_at(ttl)));
+        dbs[0].insert(key.to_vec(), val);
+    }
+
+    /// Gives `key` a TTL of `ttl`, returning whether it exists.
+    pub async fn expire(&self, key: &[u8], ttl: Duration) -> bool {
+        let mut dbs = self.db.lock_keys(&[key]).await;
+        live(&mut dbs[0], key).is_some() && dbs[0].set_expires_at(key, Some(expires_at(ttl)))
+    }
+
+    /// Drops `key`'s TTL, returning whether it had one.
+    pub async fn persist(&self, key: &[u8]) -> bool {
+        let mut dbs = self.db.lock_keys(&[key]).await;
+        let had_ttl = live(&mut dbs[0], key).is_some_and(|val| val.expires_at().is_some());
+        dbs[0].set_expires_at(key, None);
+        had_ttl
+    }
+
+    /// How long `key` has left, or `None` if it doesn't exist or has no TTL.
+    pub async fn ttl(&self, key: &[u8]) -> Option<Duration> {
+        let dbs = self.db.lock_keys(&[key]).await;
+        dbs[0]
+            .get_unexpired(key)
+            .and_then(DBData::ttl)
+            .map(Duration::from_millis)
+    }
+
+    /// Deletes `key`, returning whether it existed.
+    pub async fn del(&self, key: &[u8]) -> bool {
+        let mut dbs = self.db.lock_keys(&[key]).await;
+        dbs[0].remove(key).is_some_and(|val| !val.is_expired())
+    }
+
+    pub async fn exists(&self, key: &[u8]) -> bool {
+        let dbs = self.db.lock_keys(&[key]).await;
+        dbs[0].get_unexpired(key).is_some()
+    }
+
+    /// How many keys there are, counting those that have expired but not yet been dropped.
+    pub async fn len(&self) -> usize {
+        self.db.lock_all().await[0].len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.db.lock_all().await[0].is_empty()
+    }
+
+    /// Every key with its value, in no particular order, as they were when it was called.
+    pub async fn entries(&self) -> Vec<(Bytes, Bytes)> {
+        let dbs = self.db.lock_all().await;
+        dbs[0]
+            .iter()
+            .filter(|(_, val)| !val.is_expired())
+            .filter_map(|(key, val)| Some((key.clone(), val.data().string_value()?)))
+            .collect()
+    }
+
+    /// Every key, in no particular order.
+    pub async fn keys(&self) -> Vec<Bytes> {
+        let dbs = self.db.lock_all().await;
+        dbs[0]
+            .iter()
+            .filter(|(_, val)| !val.is_expired())
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Drops the keys whose TTL has passed, returning how many there were.
+    pub async fn purge_expired(&self) -> usize {
+        let mut dbs = self.db.lock_all().await;
+        let expired: Vec<Bytes> = dbs[0]
+            .iter()
+            .filter(|(_, val)| val.is_expired())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            dbs[0].remove(key);
+        }
+        expired.len()
+    }
+
+    /// Deletes every key.
+    pub async fn clear(&self) {
+        self.db.lock_all().await[0].clear();
+    }
+}
+
+/// The key at `key` if it hasn't expired. One that has is dropped.
+fn live<'a>(keyspace: &'a mut db::Keyspace, key: &[u8]) -> Option<&'a mut DBData> {
+    if keyspace.get(key).is_some_and(DBData::is_expired) {
+        keyspace.remove(key);
+    }
+    keyspace.get_mut(key)
+}
+
+fn expires_at(ttl: Duration) -> u64 {
+    db::unix_millis().saturating_add(ttl.as_millis() as u64)
+}
@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::RangeBounds;
+
+/// A stream entry ID: a millisecond timestamp plus a sequence number within that millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// Parses `ms-seq`, or a bare `ms` with `missing_seq` filled in.
+    pub fn parse(arg: &[u8], missing_seq: u64) -> Option<Self> {
+        let arg = std::str::from_utf8(arg).ok()?;
+
+        let (ms, seq) = match arg.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().ok()?),
+            None => (arg, missing_seq),
+        };
+
+        Some(StreamId::new(ms.parse().ok()?, seq))
+    }
+
+    /// The smallest ID greater than this one, if there is one.
+    pub fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The field/value pairs of one stream entry, in the order they were added.
+pub type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// An append-only log of entries ordered by ID. Unlike other collections an empty stream is
+/// kept around, since it still remembers its last ID.
+#[derive(Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    last_id: StreamId,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The highest ID ever added, even if that entry has since been removed.
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = id;
+    }
+
+    /// Appends an entry. The caller must have checked that `id` is above [`Stream::last_id`].
+    pub fn append(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+    }
+
+    /// Deletes an entry. The last ID is unaffected, so its ID is never reused.
+    pub fn remove(&mut self, id: &StreamId) -> bool {
+        self.entries.remove(id).is_some()
+    }
+
+    /// Removes the oldest entries while `trim` says so, at most `limit` of them, returning how
+    /// many went.
+    pub fn trim(&mut self, mut trim: impl FnMut(&StreamId, usize) -> bool, limit: usize) -> usize {
+        let mut removed = 0;
+        while removed < limit
+            && let Some((id, _)) = self.entries.first_key_value()
+            && trim(id, self.entries.len())
+        {
+            self.entries.pop_first();
+            removed += 1;
+        }
+
+        removed
+    }
+
+    pub fn range(
+        &self,
+        range: impl RangeBounds<StreamId>,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        self.entries.range(range)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        self.entries.iter()
+    }
+
+    /// A group together with the entries it reads from, so both can be used at once.
+    pub fn group_with_entries(
+        &mut self,
+        name: &[u8],
+    ) -> Option<(&mut ConsumerGroup, &BTreeMap<StreamId, Fields>)> {
+        Some((self.groups.get_mut(name)?, &self.entries))
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&Vec<u8>, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    /// Adds a group, returning `false` if one by that name already exists.
+    pub fn create_group(&mut self, name: Vec<u8>, group: ConsumerGroup) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        self.groups.insert(name, group);
+
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+}
+
+/// A delivered-but-unacknowledged entry in a group's pending entries list.
+#[derive(Clone)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    /// Unix time in milliseconds of the last delivery.
+    pub delivered_at: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct Consumer {
+    /// The IDs in the group's pending entries list owned by this consumer.
+    pub pending: BTreeSet<StreamId>,
+    /// Unix time in milliseconds this consumer last interacted with the group.
+    pub seen_at: u64,
+}
+
+/// A consumer group: how far it has read, and which entries are still waiting for an XACK.
+#[derive(Clone, Default)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered: StreamId) -> Self {
+        ConsumerGroup {
+            last_delivered,
+            ..Self::default()
+        }
+    }
+
+    pub fn pending(&self) -> &BTreeMap<StreamId, PendingEntry> {
+        &self.pending
+    }
+
+    pub fn consumers(&self) -> &BTreeMap<Vec<u8>, Consumer> {
+        &self.consumers
+    }
+
+    pub fn consumer(&self, name: &[u8]) -> Option<&Consumer> {
+        self.consumers.get(name)
+    }
+
+    /// Adds a consumer, returning `false` if it already existed.
+    pub fn create_consumer(&mut self, name: &[u8], now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers.insert(
+            name.to_vec(),
+            Consumer {
+                seen_at: now,
+                ..Consumer::default()
+            },
+        );
+
+        true
+    }
+
+    /// Marks `name` as seen, creating it if needed.
+    pub fn touch_consumer(&mut self, name: &[u8], now: u64) {
+        if !self.create_consumer(name, now) {
+            self.consumers
+                .get_mut(name)
+                .expect("consumer exists")
+                .seen_at = now;
+        }
+    }
+
+    /// Removes a consumer along with its pending entries, returning how many it had.
+    pub fn delete_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+
+        Some(consumer.pending.len())
+    }
+
+    /// Records `id` as delivered to `consumer`, taking it over from any previous owner.
+    pub fn assign(&mut self, id: StreamId, consumer: &[u8], delivered_at: u64, count: u64) {
+        if let Some(old) = self.pending.get(&id)
+            && let Some(owner) = self.consumers.get_mut(&old.consumer)
+        {
+            owner.pending.remove(&id);
+        }
+
+        self.consumers
+            .entry(consumer.to_vec())
+            .or_insert_with(|| Consumer {
+                seen_at: delivered_at,
+                ..Consumer::default()
+            })
+            .pending
+            .insert(id);
+        self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_vec(),
+                delivered_at,
+                delivery_count: count,
+            },
+        );
+    }
+
+    /// Drops `id` from the pending entries list, returning whether it was there.
+    pub fn ack(&mut self, id: &StreamId) -> bool {
+        let Some(entry) = self.pending.remove(id) else {
+            return false;
+        };
+        if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+            owner.pending.remove(id);
+        }
+
+        true
+    }
+}
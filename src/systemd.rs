@@ -0,0 +1,63 @@
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use tracing::warn;
+
+/// Values `supervised` can take: `systemd` tells systemd when the server's ready and when it's
+/// stopping, and `auto` does so if it was started by systemd.
+pub const SUPERVISED: &[&str] = &["no", "systemd", "auto"];
+
+/// The first descriptor socket activation passes on; the rest follow it.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The listening sockets systemd passed on to this process by socket activation, TCP and Unix
+/// ones, which are served in place of those `bind`, `port` and `unixsocket` would open.
+pub fn activated() -> io::Result<(Vec<TcpListener>, Vec<UnixListener>)> {
+    let var = |name| std::env::var(name).ok().and_then(|v| v.parse::<u32>().ok());
+    // The variables may have been left for some other process
+    let count = match (var("LISTEN_PID"), var("LISTEN_FDS")) {
+        (Some(pid), Some(count)) if pid == std::process::id() => count,
+        _ => return Ok((Vec::new(), Vec::new())),
+    };
+
+    let mut tcp = Vec::new();
+    let mut unix = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count as RawFd {
+        // SAFETY: systemd hands these descriptors over for this process to own
+        let socket = unsafe { socket2::Socket::from_raw_fd(fd) };
+        socket.set_nonblocking(true)?;
+        if socket.local_addr()?.is_unix() {
+            unix.push(socket.into());
+        } else {
+            tcp.push(socket.into());
+        }
+    }
+
+    Ok((tcp, unix))
+}
+
+/// Tells systemd about a change of state, like `READY=1`, when `supervised` asks for it.
+pub fn notify(supervised: &str, state: &str) {
+    if supervised == "no" {
+        return;
+    }
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        if supervised == "systemd" {
+            warn!("systemd supervision requested, but NOTIFY_SOCKET not found!");
+        }
+        return;
+    };
+
+    // A leading @ names a socket in the abstract namespace
+    let addr = match path.as_bytes() {
+        [b'@', name @ ..] => SocketAddr::from_abstract_name(name),
+        _ => SocketAddr::from_pathname(&path),
+    };
+    let sent = addr.and_then(|addr| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr));
+    if let Err(e) = sent {
+        warn!("Can't notify systemd: {e}");
+    }
+}
@@ -0,0 +1,131 @@
+//! A server for integration tests to talk to, run in the test's own process.
+
+use crate::config::ServerConfig;
+use crate::server::{self, Builder, Server};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use tokio::sync::oneshot;
+
+/// Tells apart the directories of the servers a process starts.
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// A server on a free port of 127.0.0.1, running on a thread and runtime of its own so it
+/// outlives whichever runtime the test that started it is on. It only saves when told to, by
+/// SAVE say, and its working directory is a temporary one that goes when it does, unless it was
+/// given one. It's stopped by [`TestServer::shutdown`] or SHUTDOWN, or when it's dropped.
+///
+/// A process has one [`Server`] at a time, so one test's server waits for another's to stop
+/// before it starts, and a test can't have two at once. Each starts from a clean slate: what
+/// the server before left in the process, its ACL users, scripts and statistics, is forgotten.
+pub struct TestServer {
+    addr: SocketAddr,
+    dir: PathBuf,
+    /// Whether `dir` is a temporary one, to go with the server.
+    temporary: bool,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts a server with the default settings.
+    pub fn start() -> anyhow::Result<TestServer> {
+        TestServer::start_with(Server::builder())
+    }
+
+    /// Starts the server `builder` sets up, with its settings, commands, hooks and snapshot
+    /// store, short of where it listens and when it saves. Its files are kept in a temporary
+    /// directory unless its config names one.
+    pub fn start_with(builder: Builder) -> anyhow::Result<TestServer> {
+        let mut builder = builder.bind("127.0.0.1").port(0).wait().fresh();
+        builder.config.save = Vec::new();
+        let temporary = builder.config.dir == ServerConfig::default().dir;
+        if temporary {
+            let dir = std::env::temp_dir().join(format!(
+                "redis-test-{}-{}",
+                std::process::id(),
+                STARTED.fetch_add(1, Ordering::Relaxed)
+            ));
+            builder.config.dir = dir.display().to_string();
+        }
+        let dir = PathBuf::from(&builder.config.dir);
+        std::fs::create_dir_all(&dir)?;
+        let threads = builder.config.io_threads;
+
+        let (bound, bound_rx) = mpsc::channel();
+        let (stop, stop_rx) = oneshot::channel();
+        let thread = thread::spawn(move || {
+            let runtime = match server::runtime(threads) {
+                Ok(runtime) => runtime,
+                Err(e) => return drop(bound.send(Err(e.into()))),
+            };
+            runtime.block_on(async move {
+                let server = match builder.build().await {
+                    Ok(server) => server,
+                    Err(e) => return drop(bound.send(Err(e))),
+                };
+                let _ = bound.send(Ok(server.local_addrs()[0]));
+                tokio::select! {
+                    _ = server.run() => {}
+                    _ = stop_rx => {}
+                }
+            });
+            // Dropping the runtime drops what's left of the server's tasks, closing its listeners
+        });
+
+        let addr = match bound_rx.recv() {
+            Ok(Ok(addr)) => addr,
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                if temporary {
+                    let _ = std::fs::remove_dir_all(&dir);
+                }
+                return Err(e);
+            }
+            Err(_) => anyhow::bail!("The test server's thread panicked"),
+        };
+
+        Ok(TestServer {
+            addr,
+            dir,
+            temporary,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// The address clients connect to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The directory the server keeps its files in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stops the server, waiting until it has.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if self.temporary {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,57 @@
+use crate::config::ServerConfig;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{self, RootCertStore};
+
+/// How long a client gets to finish the TLS handshake before it's dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Values `tls-auth-clients` can take: whether clients must present a certificate signed by
+/// `tls-ca-cert-file`, may present one, or needn't.
+pub const AUTH_CLIENTS: &[&str] = &["yes", "no", "optional"];
+
+/// What `tls-port` connections are accepted with: the server's certificate and key, and what
+/// it asks of clients' certificates.
+pub fn acceptor(config: &ServerConfig) -> anyhow::Result<TlsAcceptor> {
+    if config.tls_cert_file.is_empty() || config.tls_key_file.is_empty() {
+        anyhow::bail!("tls-cert-file and tls-key-file are needed to take TLS connections");
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = if config.tls_auth_clients == "no" {
+        builder.with_no_client_auth()
+    } else {
+        if config.tls_ca_cert_file.is_empty() {
+            anyhow::bail!("tls-ca-cert-file is needed to authenticate clients");
+        }
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(&config.tls_ca_cert_file)
+            .map_err(|e| anyhow::anyhow!("Failed to load {}: {e}", config.tls_ca_cert_file))?
+        {
+            roots.add(cert?)?;
+        }
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+        let verifier = if config.tls_auth_clients == "optional" {
+            verifier.allow_unauthenticated().build()?
+        } else {
+            verifier.build()?
+        };
+        builder.with_client_cert_verifier(verifier)
+    };
+
+    let certs = CertificateDer::pem_file_iter(&config.tls_cert_file)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| anyhow::anyhow!("Failed to load {}: {e}", config.tls_cert_file))?;
+    let key = PrivateKeyDer::from_pem_file(&config.tls_key_file)
+        .map_err(|e| anyhow::anyhow!("Failed to load {}: {e}", config.tls_key_file))?;
+
+    Ok(TlsAcceptor::from(Arc::new(
+        builder.with_single_cert(certs, key)?,
+    )))
+}
@@ -0,0 +1,245 @@
+//! Command traces: every command clients send, with when they sent it, recorded while
+//! `record-file` names a file, for the `replay` subcommand to send to a server again.
+//!
+//! A trace starts with [`MAGIC`], then has an entry for each command: the microseconds since
+//! the one before, the sending client's ID, the number of arguments the command has counting
+//! its name, and each argument's length and bytes. Numbers are LEB128 varints. A trace cut off
+//! mid-entry, as one being written can be, reads up to the last whole one.
+
+use crate::cmd::lower;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// What a trace starts with: a name and a format version.
+pub const MAGIC: &[u8] = b"REDISTRACE\x01";
+
+/// Whether there's a recording, so commands needn't take the lock otherwise.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The trace being recorded, while `record-file` names one.
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+struct Recording {
+    path: String,
+    file: BufWriter<File>,
+    /// When the last entry was recorded, which the next one's time counts from.
+    last: Instant,
+}
+
+/// One recorded command.
+#[derive(Debug)]
+pub struct Entry {
+    /// How long after the entry before it the command was sent, or after the recording started.
+    pub delay: Duration,
+    /// The CLIENT ID of the connection that sent it.
+    pub client: u64,
+    /// Its name, as it was sent, and its arguments.
+    pub args: Vec<Vec<u8>>,
+}
+
+/// Applies a new `record-file`, starting a new trace there if it's changed. One already there
+/// is overwritten.
+pub fn set_file(path: &str) {
+    let mut recording = RECORDING.lock().unwrap();
+    if recording
+        .as_ref()
+        .map_or("", |recording| recording.path.as_str())
+        == path
+    {
+        return;
+    }
+    if let Some(mut old) = recording.take() {
+        flush_recording(&mut old);
+    }
+    *recording = match path {
+        "" => None,
+        path => start(path)
+            .inspect_err(|e| warn!("Can't record commands to {path}: {e}"))
+            .ok(),
+    };
+    ENABLED.store(recording.is_some(), Ordering::Relaxed);
+}
+
+fn start(path: &str) -> io::Result<Recording> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut file = BufWriter::new(file);
+    file.write_all(MAGIC)?;
+    Ok(Recording {
+        path: path.to_string(),
+        file,
+        last: Instant::now(),
+    })
+}
+
+/// Records that client `id` sent `command` with `args`. Passwords are left out: AUTH isn't
+/// recorded at all, and HELLO without its AUTH option.
+pub fn record(id: u64, command: &str, args: &[Vec<u8>]) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut recording = RECORDING.lock().unwrap();
+    let Some(recording) = recording.as_mut() else {
+        return;
+    };
+
+    let args: Vec<&[u8]> = match lower(command.as_bytes()).as_str() {
+        "auth" => return,
+        "hello" => without_auth(args),
+        _ => args.iter().map(Vec::as_slice).collect(),
+    };
+    let now = Instant::now();
+    let mut entry = Vec::new();
+    write_varint(&mut entry, (now - recording.last).as_micros() as u64);
+    write_varint(&mut entry, id);
+    write_varint(&mut entry, args.len() as u64 + 1);
+    for arg in std::iter::once(command.as_bytes()).chain(args) {
+        write_varint(&mut entry, arg.len() as u64);
+        entry.extend_from_slice(arg);
+    }
+    recording.last = now;
+    if let Err(e) = recording.file.write_all(&entry) {
+        warn!("Failed to record to {}: {e}", recording.path);
+    }
+}
+
+/// HELLO's arguments with AUTH and what follows it taken out.
+fn without_auth(args: &[Vec<u8>]) -> Vec<&[u8]> {
+    let mut kept = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if lower(arg) == "auth" {
+            args.nth(1);
+        } else {
+            kept.push(arg.as_slice());
+        }
+    }
+    kept
+}
+
+/// Writes out what's been recorded but is still buffered.
+pub fn flush() {
+    if let Some(recording) = RECORDING.lock().unwrap().as_mut() {
+        flush_recording(recording);
+    }
+}
+
+fn flush_recording(recording: &mut Recording) {
+    if let Err(e) = recording.file.flush() {
+        warn!("Failed to record to {}: {e}", recording.path);
+    }
+}
+
+/// Flushes the trace once a second, so what's on disk is never far behind. Runs for as long as
+/// the server does.
+pub async fn flush_every_second() {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if ENABLED.load(Ordering::Relaxed) {
+            flush();
+        }
+    }
+}
+
+/// Reads the entries of a trace from `reader`.
+pub struct Reader<R> {
+    reader: io::BufReader<R>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Starts reading a trace, failing if it doesn't start like one.
+    pub fn new(reader: R) -> anyhow::Result<Reader<R>> {
+        let mut reader = io::BufReader::new(reader);
+        let mut magic = vec![0; MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .ok()
+            .filter(|_| magic == MAGIC)
+            .ok_or_else(|| anyhow::anyhow!("Not a command trace"))?;
+        Ok(Reader { reader })
+    }
+
+    /// The next entry, or `None` once the trace ends.
+    fn entry(&mut self) -> io::Result<Option<Entry>> {
+        let Some(delay) = self.varint()? else {
+            return Ok(None);
+        };
+        let client = self.required_varint()?;
+        let count = self.required_varint()?;
+        let mut args = Vec::new();
+        for _ in 0..count {
+            let len = self.required_varint()?;
+            let mut arg = Vec::new();
+            (&mut self.reader).take(len).read_to_end(&mut arg)?;
+            if arg.len() as u64 != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            args.push(arg);
+        }
+
+        Ok(Some(Entry {
+            delay: Duration::from_micros(delay),
+            client,
+            args,
+        }))
+    }
+
+    /// A varint, or `None` if the trace ends before it starts.
+    fn varint(&mut self) -> io::Result<Option<u64>> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return match shift {
+                    0 => Ok(None),
+                    _ => Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+            }
+            if shift >= 64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "varint too long",
+                ));
+            }
+            value |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(value));
+            }
+            shift += 7;
+        }
+    }
+
+    fn required_varint(&mut self) -> io::Result<u64> {
+        self.varint()?
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Entry>;
+
+    /// The next entry. An entry cut off at the end isn't one, so it ends the trace too.
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        match self.entry() {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            entry => entry.transpose(),
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
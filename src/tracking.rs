@@ -0,0 +1,150 @@
+use crate::client;
+use crate::cmd;
+use crate::resp::Value;
+use bytes::Bytes;
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+/// How a connection asked CLIENT TRACKING to tell it about keys it may have cached.
+#[derive(Default)]
+pub struct Tracking {
+    /// Send invalidations to this connection instead, as `__redis__:invalidate` messages.
+    pub redirect: Option<u64>,
+    /// Hear about every key matching `prefixes` rather than just the keys read.
+    pub bcast: bool,
+    /// With `bcast`, the key prefixes to hear about. None means every key.
+    pub prefixes: Vec<Vec<u8>>,
+    /// Don't hear about keys the connection modified itself.
+    pub noloop: bool,
+}
+
+/// Connections with tracking on, by client ID.
+static TRACKERS: Mutex<BTreeMap<u64, Tracking>> = Mutex::new(BTreeMap::new());
+
+/// The keys connections not in BCAST mode have read since each was last invalidated, with who
+/// read them. A key is dropped once it's invalidated, until someone reads it again.
+static READERS: Mutex<BTreeMap<Vec<u8>, BTreeSet<u64>>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    /// The client running the current command, for NOLOOP, or 0 if it isn't a client's doing.
+    /// Set on the thread that runs it, like the selected database.
+    static ORIGIN: Cell<u64> = const { Cell::new(0) };
+}
+
+const INVALIDATE_CHANNEL: &[u8] = b"__redis__:invalidate";
+
+pub fn enable(id: u64, tracking: Tracking) {
+    TRACKERS.lock().unwrap().insert(id, tracking);
+}
+
+/// Turns tracking off for connection `id`, forgetting the keys it read.
+pub fn disable(id: u64) {
+    if TRACKERS.lock().unwrap().remove(&id).is_none() {
+        return;
+    }
+
+    READERS.lock().unwrap().retain(|_, readers| {
+        readers.remove(&id);
+        !readers.is_empty()
+    });
+}
+
+/// CLIENT GETREDIR's answer for connection `id`: -1 if it isn't tracking, 0 if it is but not
+/// redirecting, and otherwise the client invalidations go to.
+pub fn redirect(id: u64) -> i64 {
+    match TRACKERS.lock().unwrap().get(&id) {
+        None => -1,
+        Some(tracking) => tracking.redirect.map_or(0, |target| target as i64),
+    }
+}
+
+/// How many connections have tracking on.
+pub fn count() -> usize {
+    TRACKERS.lock().unwrap().len()
+}
+
+/// Sets the client whose command is about to run, so NOLOOP can tell its own writes apart.
+pub fn set_origin(id: u64) {
+    ORIGIN.set(id);
+}
+
+/// Remembers the keys read by command `name` for connection `id`, if it's tracking them.
+pub fn remember(id: u64, name: &str, args: &[Vec<u8>]) {
+    let trackers = TRACKERS.lock().unwrap();
+    let Some(tracking) = trackers.get(&id) else {
+        return;
+    };
+    if tracking.bcast || !cmd::is_read(name) {
+        return;
+    }
+
+    let mut readers = READERS.lock().unwrap();
+    for key in cmd::command_keys(name, args) {
+        readers.entry(key.to_vec()).or_default().insert(id);
+    }
+}
+
+/// Tells every connection tracking `key` that it's changed: those that read it, and those
+/// broadcasting a prefix of it.
+pub fn invalidate(key: &[u8]) {
+    let trackers = TRACKERS.lock().unwrap();
+    if trackers.is_empty() {
+        return;
+    }
+
+    let mut targets = READERS.lock().unwrap().remove(key).unwrap_or_default();
+    targets.extend(
+        trackers
+            .iter()
+            .filter(|(_, tracking)| {
+                tracking.bcast
+                    && (tracking.prefixes.is_empty()
+                        || tracking.prefixes.iter().any(|p| key.starts_with(p)))
+            })
+            .map(|(id, _)| *id),
+    );
+
+    let origin = ORIGIN.get();
+    for id in targets {
+        let Some(tracking) = trackers.get(&id) else {
+            continue;
+        };
+        if tracking.noloop && id == origin {
+            continue;
+        }
+
+        let keys = Value::Array(vec![Value::BulkString(Bytes::copy_from_slice(key))]);
+        send(id, tracking, keys);
+    }
+}
+
+/// Tells every tracking connection that all keys have changed, with a null in place of the
+/// keys, as when the databases are flushed.
+pub fn invalidate_all() {
+    let trackers = TRACKERS.lock().unwrap();
+    READERS.lock().unwrap().clear();
+    for (id, tracking) in trackers.iter() {
+        send(*id, tracking, Value::Null);
+    }
+}
+
+/// Sends an invalidation for `keys` to connection `id`: as a RESP3 push on its own connection,
+/// or as a pub/sub message to the client it redirects to. A RESP2 connection that doesn't
+/// redirect has no way of being told.
+fn send(id: u64, tracking: &Tracking, keys: Value) {
+    let bulk = |bytes: &[u8]| Value::BulkString(Bytes::copy_from_slice(bytes));
+
+    match tracking.redirect {
+        Some(target) => {
+            client::push(
+                target,
+                Value::Push(vec![bulk(b"message"), bulk(INVALIDATE_CHANNEL), keys]),
+            );
+        }
+        None if client::protocol(id) == Some(3) => {
+            client::push(id, Value::Push(vec![bulk(b"invalidate"), keys]));
+        }
+        None => {}
+    }
+}
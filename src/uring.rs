@@ -0,0 +1,77 @@
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpStream;
+use tokio_uring::buf::BoundedBuf;
+
+/// How much of a connection's traffic is read or written in one go.
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Set once the server is running on io_uring.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Runs `server` on an io_uring runtime: a single thread, whose connections are read from and
+/// written to through io_uring rather than epoll.
+pub fn start<F: Future>(server: F) -> F::Output {
+    RUNNING.store(true, Ordering::Relaxed);
+    let output = tokio_uring::start(server);
+    RUNNING.store(false, Ordering::Relaxed);
+    output
+}
+
+pub fn running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Moves `stream`'s reads and writes onto io_uring, returning the stream the connection is
+/// served over in its place. Data is passed between the two by a task that must run on the
+/// io_uring runtime's local set, as must whoever calls this.
+pub fn wrap(stream: TcpStream) -> io::Result<DuplexStream> {
+    let socket = tokio_uring::net::TcpStream::from_std(stream.into_std()?);
+    let (ours, theirs) = tokio::io::duplex(BUFFER_SIZE);
+
+    tokio_uring::spawn(async move {
+        let (mut from_server, mut to_server) = tokio::io::split(ours);
+        let inbound = async {
+            let mut buf = vec![0; BUFFER_SIZE];
+            loop {
+                let (read, returned) = socket.read(buf).await;
+                buf = returned;
+                match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if to_server.write_all(&buf[..n]).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            let _ = to_server.shutdown().await;
+            // The client hanging up only ends its side: replies to what it sent still go out
+            std::future::pending::<()>().await;
+        };
+        let outbound = async {
+            let mut buf = vec![0; BUFFER_SIZE];
+            loop {
+                let n = match from_server.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                let (written, returned) = socket.write_all(buf.slice(..n)).await;
+                buf = returned.into_inner();
+                if written.is_err() {
+                    break;
+                }
+            }
+        };
+
+        // The server closing its end closes the connection
+        tokio::select! {
+            _ = inbound => {}
+            _ = outbound => {}
+        }
+    });
+
+    Ok(theirs)
+}
@@ -0,0 +1,371 @@
+//! Commands from WebAssembly modules: each module `wasm-modules` names is loaded at startup and
+//! registers commands that run sandboxed, with a fuel budget and a memory limit, and nothing of
+//! the server's but what it imports from `redis`:
+//!
+//! - `register(name, name_len, arity, flags, first_key, last_key, key_step)`, while `init` runs,
+//!   adds a command. `flags` is any of 1 for `write`, 2 for `readonly` and 4 for `fast`.
+//! - `get(key, key_len) -> i64` copies the value of `key` into memory the module's `alloc` gives,
+//!   returning where as `ptr << 32 | len`, or -1 if there's no such key.
+//! - `set(key, key_len, value, value_len)` and `del(key, key_len) -> i32`, which only commands
+//!   flagged `write` may call.
+//! - `publish(channel, channel_len, message, message_len)`, delivered once the command returns.
+//! - `reply_bulk(ptr, len)`, `reply_simple(ptr, len)`, `reply_error(ptr, len)` and
+//!   `reply_integer(n)` set the reply, which is a null if none of them is called.
+//!
+//! Only the keys at a command's key positions can be read or written, as with any
+//! [`CommandPlugin`]. A module exports its `memory`, `alloc(len) -> ptr`, `init()`, and for each
+//! command a function `(args, args_len)` named as it was registered, whose arguments are written
+//! to memory from `alloc` as each one's length, a little-endian u32, then its bytes.
+
+use crate::db::Keyspace;
+use crate::plugin::{CommandPlugin, Database};
+use crate::resp::Value;
+use std::sync::{Arc, Mutex, PoisonError};
+use wasmtime::{
+    Caller, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, Trap,
+};
+
+/// How much a command may run for, in wasmtime's fuel, which is roughly an instruction each.
+const FUEL: u64 = 100_000_000;
+
+/// The most memory a module's instance may have.
+const MEMORY_LIMIT: usize = 64 << 20;
+
+/// The `register` flags, and the [`CommandPlugin`] flag each stands for.
+const FLAGS: &[(i32, &str)] = &[(1, "write"), (2, "readonly"), (4, "fast")];
+
+/// What the functions a module imports work on.
+#[derive(Default)]
+struct Host {
+    limits: StoreLimits,
+    /// Whether `init` is running, which is when commands are registered.
+    initialising: bool,
+    registered: Vec<Registration>,
+    /// The database of the command that's running, taken from it until it returns.
+    keyspace: Keyspace,
+    /// Whether the command that's running may write.
+    writes: bool,
+    published: Vec<(Vec<u8>, Vec<u8>)>,
+    reply: Option<Value>,
+    /// The reply to give in place of a trap's, like WRONGTYPE from `get`.
+    failed: Option<Value>,
+}
+
+/// A module's instance, which its commands take turns running in.
+struct Sandbox {
+    store: Store<Host>,
+    instance: Instance,
+}
+
+/// A command as a module registered it.
+struct Registration {
+    /// The name it was registered with, which is the name of the function it exports.
+    name: String,
+    arity: i32,
+    keys: [i32; 3],
+    flags: Vec<&'static str>,
+}
+
+struct WasmCommand {
+    command: Registration,
+    sandbox: Arc<Mutex<Sandbox>>,
+}
+
+/// Loads the module at `path`, returning the commands it registers.
+pub fn load(path: &str) -> anyhow::Result<Vec<Box<dyn CommandPlugin>>> {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::from_file(&engine, path)
+        .map_err(|e| anyhow::anyhow!("Can't load WebAssembly module {path}: {e}"))?;
+
+    let mut store = Store::new(
+        &engine,
+        Host {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build(),
+            ..Host::default()
+        },
+    );
+    store.limiter(|host| &mut host.limits);
+    store.set_fuel(FUEL)?;
+    let instance = linker(&engine)?
+        .instantiate(&mut store, &module)
+        .map_err(|e| anyhow::anyhow!("Can't start WebAssembly module {path}: {e}"))?;
+    let init = instance.get_typed_func::<(), ()>(&mut store, "init")?;
+    store.data_mut().initialising = true;
+    init.call(&mut store, ())
+        .map_err(|e| anyhow::anyhow!("WebAssembly module {path} failed to start: {e}"))?;
+    store.data_mut().initialising = false;
+
+    let registered = std::mem::take(&mut store.data_mut().registered);
+    if registered.is_empty() {
+        anyhow::bail!("WebAssembly module {path} registered no commands");
+    }
+    for command in &registered {
+        instance
+            .get_typed_func::<(i32, i32), ()>(&mut store, &command.name)
+            .map_err(|e| anyhow::anyhow!("{path} has no function for {}: {e}", command.name))?;
+    }
+
+    let sandbox = Arc::new(Mutex::new(Sandbox { store, instance }));
+    Ok(registered
+        .into_iter()
+        .map(|command| {
+            Box::new(WasmCommand {
+                command,
+                sandbox: sandbox.clone(),
+            }) as Box<dyn CommandPlugin>
+        })
+        .collect())
+}
+
+fn linker(engine: &Engine) -> anyhow::Result<Linker<Host>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        "redis",
+        "register",
+        |mut caller: Caller<'_, Host>,
+         name: i32,
+         name_len: i32,
+         arity: i32,
+         flags: i32,
+         first: i32,
+         last: i32,
+         step: i32|
+         -> wasmtime::Result<()> {
+            if !caller.data().initialising {
+                wasmtime::bail!("commands can only be registered by init");
+            }
+            let name = String::from_utf8(read(&mut caller, name, name_len)?)?;
+            let flags = FLAGS
+                .iter()
+                .filter(|(bit, _)| flags & bit != 0)
+                .map(|(_, flag)| *flag)
+                .collect();
+            caller.data_mut().registered.push(Registration {
+                name,
+                arity,
+                keys: [first, last, step],
+                flags,
+            });
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "redis",
+        "get",
+        |mut caller: Caller<'_, Host>, key: i32, key_len: i32| -> wasmtime::Result<i64> {
+            let key = read_key(&mut caller, key, key_len)?;
+            let host = caller.data_mut();
+            let value = match Database::new(&mut host.keyspace).get(&key) {
+                Ok(Some(value)) => value,
+                Ok(None) => return Ok(-1),
+                Err(e) => {
+                    host.failed = Some(e);
+                    wasmtime::bail!("WRONGTYPE");
+                }
+            };
+            let at = place(&mut caller, &value)?;
+            Ok(((at as i64) << 32) | value.len() as i64)
+        },
+    )?;
+    linker.func_wrap(
+        "redis",
+        "set",
+        |mut caller: Caller<'_, Host>,
+         key: i32,
+         key_len: i32,
+         value: i32,
+         value_len: i32|
+         -> wasmtime::Result<()> {
+            let key = read_key(&mut caller, key, key_len)?;
+            let value = read(&mut caller, value, value_len)?;
+            Database::new(&mut writable(&mut caller)?.keyspace).set(&key, &value);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "redis",
+        "del",
+        |mut caller: Caller<'_, Host>, key: i32, key_len: i32| -> wasmtime::Result<i32> {
+            let key = read_key(&mut caller, key, key_len)?;
+            let host = writable(&mut caller)?;
+            Ok(Database::new(&mut host.keyspace).del(&key) as i32)
+        },
+    )?;
+    linker.func_wrap(
+        "redis",
+        "publish",
+        |mut caller: Caller<'_, Host>,
+         channel: i32,
+         channel_len: i32,
+         message: i32,
+         message_len: i32|
+         -> wasmtime::Result<()> {
+            let channel = read(&mut caller, channel, channel_len)?;
+            let message = read(&mut caller, message, message_len)?;
+            caller.data_mut().published.push((channel, message));
+            Ok(())
+        },
+    )?;
+    reply_with(&mut linker, "reply_bulk", |bytes| {
+        Value::BulkString(bytes.into())
+    })?;
+    reply_with(&mut linker, "reply_simple", |bytes| {
+        Value::SimpleString(String::from_utf8_lossy(&bytes).into_owned())
+    })?;
+    reply_with(&mut linker, "reply_error", |bytes| {
+        Value::error(String::from_utf8_lossy(&bytes))
+    })?;
+    linker.func_wrap(
+        "redis",
+        "reply_integer",
+        |mut caller: Caller<'_, Host>, n: i64| {
+            caller.data_mut().reply = Some(Value::Integer(n));
+        },
+    )?;
+
+    Ok(linker)
+}
+
+/// Defines the import `name`, which replies with what `reply` makes of the bytes it's given.
+fn reply_with(
+    linker: &mut Linker<Host>,
+    name: &str,
+    reply: fn(Vec<u8>) -> Value,
+) -> anyhow::Result<()> {
+    linker.func_wrap(
+        "redis",
+        name,
+        move |mut caller: Caller<'_, Host>, at: i32, len: i32| -> wasmtime::Result<()> {
+            let bytes = read(&mut caller, at, len)?;
+            caller.data_mut().reply = Some(reply(bytes));
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::format_err!("the module exports no memory"))
+}
+
+/// The `len` bytes of the module's memory at `at`.
+fn read(caller: &mut Caller<'_, Host>, at: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let memory = memory(caller)?;
+    let mut bytes = vec![0; len as u32 as usize];
+    memory.read(&*caller, at as u32 as usize, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Like [`read`], for a key, which has to be one the running command locked.
+fn read_key(caller: &mut Caller<'_, Host>, at: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let key = read(caller, at, len)?;
+    if !caller.data().keyspace.holds(&key) {
+        wasmtime::bail!(
+            "key '{}' isn't one of the command's keys",
+            String::from_utf8_lossy(&key)
+        );
+    }
+    Ok(key)
+}
+
+/// The host, if the running command may write.
+fn writable<'a>(caller: &'a mut Caller<'_, Host>) -> wasmtime::Result<&'a mut Host> {
+    let host = caller.data_mut();
+    if !host.writes {
+        wasmtime::bail!("only commands flagged write may write");
+    }
+    Ok(host)
+}
+
+/// Copies `bytes` into memory the module's `alloc` gives, returning where.
+fn place(caller: &mut Caller<'_, Host>, bytes: &[u8]) -> wasmtime::Result<i32> {
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmtime::format_err!("the module exports no alloc"))?
+        .typed::<i32, i32>(&*caller)?;
+    let at = alloc.call(&mut *caller, bytes.len() as i32)?;
+    memory(caller)?.write(&mut *caller, at as u32 as usize, bytes)?;
+    Ok(at)
+}
+
+impl Sandbox {
+    /// Calls the function for command `name` with `args`.
+    fn call(&mut self, name: &str, args: &[Vec<u8>]) -> wasmtime::Result<()> {
+        self.store.set_fuel(FUEL)?;
+        let mut encoded = Vec::new();
+        for arg in args {
+            encoded.extend_from_slice(&(arg.len() as u32).to_le_bytes());
+            encoded.extend_from_slice(arg);
+        }
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&mut self.store, "alloc")?;
+        let at = alloc.call(&mut self.store, encoded.len() as i32)?;
+        let memory = self
+            .instance
+            .get_memory(&mut self.store, "memory")
+            .ok_or_else(|| wasmtime::format_err!("the module exports no memory"))?;
+        memory.write(&mut self.store, at as u32 as usize, &encoded)?;
+
+        self.instance
+            .get_typed_func::<(i32, i32), ()>(&mut self.store, name)?
+            .call(&mut self.store, (at, encoded.len() as i32))
+    }
+}
+
+impl CommandPlugin for WasmCommand {
+    fn name(&self) -> &str {
+        &self.command.name
+    }
+
+    fn arity(&self) -> i32 {
+        self.command.arity
+    }
+
+    fn keys(&self) -> [i32; 3] {
+        self.command.keys
+    }
+
+    fn flags(&self) -> &[&str] {
+        &self.command.flags
+    }
+
+    fn execute(&self, db: &mut Database<'_>, args: &[Vec<u8>]) -> Value {
+        let name = &self.command.name;
+        // A command that trapped leaves the instance as it was, and the next can go on with it
+        let mut sandbox = self.sandbox.lock().unwrap_or_else(PoisonError::into_inner);
+        let host = sandbox.store.data_mut();
+        host.keyspace = std::mem::take(db.keyspace());
+        host.writes = self.command.flags.contains(&"write");
+        host.reply = None;
+        host.failed = None;
+
+        let called = sandbox.call(name, args);
+
+        let host = sandbox.store.data_mut();
+        *db.keyspace() = std::mem::take(&mut host.keyspace);
+        for (channel, message) in host.published.drain(..) {
+            db.publish(&channel, &message);
+        }
+        match called {
+            Ok(()) => host.reply.take().unwrap_or(Value::Null),
+            Err(e) => host
+                .failed
+                .take()
+                .unwrap_or_else(|| match e.downcast_ref::<Trap>() {
+                    Some(Trap::OutOfFuel) => Value::error(format!("ERR '{name}' ran for too long")),
+                    _ => Value::error(format!("ERR '{name}' failed: {e}")),
+                }),
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//! Pub/sub over WebSocket, so that browsers can take PUBLISH traffic straight from the server.
+//!
+//! A connection to `/` can subscribe in its URL, with `?channel=` and `?pattern=` as many times
+//! as need be. Once it's open, it can send text messages like `{"subscribe": ["news"]}`, where
+//! the name is any of `subscribe`, `psubscribe`, `unsubscribe` and `punsubscribe`, or `auth` to
+//! log in as AUTH would, and `ping`. What the server sends back are text messages too, a JSON
+//! object for each RESP frame, like
+//! `{"type": "message", "channel": "news", "data": "..."}`.
+
+use crate::acl;
+use crate::client::Client;
+use crate::cmd;
+use crate::http::{self, Request};
+use crate::json::Json;
+use crate::pubsub::PubSub;
+use crate::resp::Value;
+use crate::sha1::sha1;
+use bytes::{Buf, BytesMut};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::warn;
+
+/// What's appended to a client's key to make the accept key, as RFC 6455 has it.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest message taken from a client, all its fragments together.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// Commands to subscribe with and the channels or patterns for each.
+type Subscriptions = Vec<(&'static str, Vec<Vec<u8>>)>;
+
+/// A frame from the other end, unmasked.
+struct Frame {
+    /// Whether it finishes its message.
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Takes WebSocket connections on `listener`. Runs for as long as the server does.
+pub async fn serve(listener: TcpListener, pubsub: Arc<PubSub>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(answer(stream, pubsub.clone()));
+    }
+}
+
+async fn answer(mut stream: TcpStream, pubsub: Arc<PubSub>) {
+    let Some(request) = http::read_request(&mut stream).await else {
+        return;
+    };
+    let (Ok(addr), Ok(laddr)) = (stream.peer_addr(), stream.local_addr()) else {
+        return;
+    };
+    let mut client = Client::new(pubsub, addr, laddr);
+
+    let subscriptions = match handshake(&request, &mut client) {
+        Ok(subscriptions) => subscriptions,
+        Err((status, message)) => {
+            http::respond(&mut stream, status, "text/plain", format!("{message}\n")).await;
+            return;
+        }
+    };
+    let key = request.header("sec-websocket-key").unwrap_or_default();
+    let accept = http::encode_base64(&sha1(format!("{key}{ACCEPT_GUID}").as_bytes()));
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    );
+    if stream.write_all(response.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let mut socket = Socket {
+        stream,
+        buf: BytesMut::from(&request.body[..]),
+    };
+    let mut replies = Vec::new();
+    for (command, names) in subscriptions {
+        replies.extend(run(&mut client, command, &names));
+    }
+    if socket.send_frames(replies).await.is_ok() {
+        bridge(&mut socket, &mut client).await;
+    }
+}
+
+/// Checks an upgrade request, logging `client` in with Basic authorization if it has any, and
+/// returns the subscriptions its URL asks for. Fails with the status and message to refuse it
+/// with.
+fn handshake(
+    request: &Request,
+    client: &mut Client,
+) -> Result<Subscriptions, (&'static str, String)> {
+    if request.path != "/" {
+        return Err((
+            "404 Not Found",
+            format!("No such endpoint {}", request.path),
+        ));
+    }
+    let upgrade = request.header("upgrade").unwrap_or_default();
+    if request.method != "GET"
+        || !upgrade.eq_ignore_ascii_case("websocket")
+        || request.header("sec-websocket-key").is_none()
+    {
+        return Err((
+            "426 Upgrade Required",
+            "Only WebSocket upgrades are served here".into(),
+        ));
+    }
+    if request.header("sec-websocket-version") != Some("13") {
+        return Err((
+            "426 Upgrade Required",
+            "Only WebSocket version 13 is supported".into(),
+        ));
+    }
+
+    if let Some((user, password)) = request.basic_auth() {
+        if !acl::authenticate(&user, &password) {
+            let e = "WRONGPASS invalid username-password pair or user is disabled.";
+            return Err(("401 Unauthorized", e.to_string()));
+        }
+        client.user = Some(user);
+    }
+
+    let names = |param| request.params(param).into_iter().map(String::into_bytes);
+    let subscriptions: Vec<_> = [("subscribe", "channel"), ("psubscribe", "pattern")]
+        .into_iter()
+        .map(|(command, param)| (command, names(param).collect::<Vec<_>>()))
+        .filter(|(_, names)| !names.is_empty())
+        .collect();
+    // With nothing to check them against yet, they'd only be refused once it's open
+    for (command, names) in &subscriptions {
+        let Some(user) = &client.user else {
+            return Err(("401 Unauthorized", "NOAUTH Authentication required.".into()));
+        };
+        if let Err(e) = acl::check(user, command, names) {
+            let e = e.error_message().unwrap_or_default();
+            return Err(("403 Forbidden", e));
+        }
+    }
+    Ok(subscriptions)
+}
+
+/// Forwards what `client`'s subscribed to until the connection closes, running the commands
+/// the other end sends meanwhile.
+async fn bridge(socket: &mut Socket, client: &mut Client) {
+    let mut killed = client.kill_signal();
+    let output = client.subscriber.output();
+    // The fragments of a message that's yet to be finished
+    let mut partial: Option<(u8, Vec<u8>)> = None;
+
+    loop {
+        client.sync();
+        let frame = tokio::select! {
+            frame = socket.read_frame() => frame,
+            message = client.subscriber.next_message() => {
+                if socket.send_frames(vec![message]).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            _ = output.overflowed() => {
+                let id = client.id;
+                warn!("Closing WebSocket client {id} for overcoming its output buffer limits");
+                return;
+            }
+            _ = killed.killed() => return,
+        };
+        let Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }) = frame
+        else {
+            return;
+        };
+
+        let message = match opcode {
+            PING => {
+                if socket.send(PONG, &payload).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            PONG => continue,
+            CLOSE => {
+                let _ = socket.send(CLOSE, &payload[..payload.len().min(2)]).await;
+                return;
+            }
+            TEXT | BINARY if partial.is_none() => (opcode, payload),
+            CONTINUATION if partial.is_some() => {
+                let (opcode, mut message) = partial.take().unwrap();
+                message.extend_from_slice(&payload);
+                (opcode, message)
+            }
+            _ => {
+                socket.close(1002).await;
+                return;
+            }
+        };
+        if message.1.len() > MAX_MESSAGE {
+            socket.close(1009).await;
+            return;
+        }
+        if !fin {
+            partial = Some(message);
+            continue;
+        }
+
+        let replies = match command(&message.1) {
+            Ok((name, args)) => run(client, &name, &args),
+            Err(e) => vec![Value::error(format!("ERR {e}"))],
+        };
+        if socket.send_frames(replies).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// A command sent as `{"name": [args...]}`.
+fn command(message: &[u8]) -> anyhow::Result<(String, Vec<Vec<u8>>)> {
+    let text = std::str::from_utf8(message)?;
+    let Json::Object(fields) = Json::parse(text)? else {
+        anyhow::bail!("expected an object like {{\"subscribe\": [\"channel\"]}}");
+    };
+    let [(name, args)] = fields.as_slice() else {
+        anyhow::bail!("expected an object with one command in it");
+    };
+    let args = match args {
+        Json::Array(args) => args
+            .iter()
+            .map(|arg| match arg {
+                Json::Str(arg) => Ok(arg.clone().into_bytes()),
+                _ => anyhow::bail!("expected '{name}' to have strings for arguments"),
+            })
+            .collect::<anyhow::Result<_>>()?,
+        _ => anyhow::bail!("expected '{name}' to have an array of arguments"),
+    };
+    Ok((name.to_lowercase(), args))
+}
+
+/// Runs a command the other end sent, returning the frames to reply with.
+fn run(client: &mut Client, name: &str, args: &[Vec<u8>]) -> Vec<Value> {
+    client.record(name);
+    if name == "auth" {
+        return vec![cmd::acl::auth(&mut client.user, args)];
+    }
+    if !matches!(
+        name,
+        "subscribe" | "psubscribe" | "unsubscribe" | "punsubscribe" | "ping"
+    ) {
+        return vec![Value::error(format!(
+            "ERR '{name}' isn't available over WebSocket"
+        ))];
+    }
+    let Some(user) = &client.user else {
+        return vec![Value::error("NOAUTH Authentication required.")];
+    };
+    if let Err(e) = acl::check(user, name, args) {
+        return vec![e];
+    }
+
+    let subscriber = &mut client.subscriber;
+    match name {
+        "subscribe" => cmd::pubsub::subscribe(subscriber, args),
+        "psubscribe" => cmd::pubsub::psubscribe(subscriber, args),
+        "unsubscribe" => cmd::pubsub::unsubscribe(subscriber, args),
+        "punsubscribe" => cmd::pubsub::punsubscribe(subscriber, args),
+        _ => vec![Value::SimpleString("PONG".to_string())],
+    }
+}
+
+/// A RESP frame as a JSON object: its `type`, then its fields by name, or an `error` with its
+/// `message`.
+fn to_json(frame: Value) -> Json {
+    if let Some(message) = frame.error_message() {
+        return Json::object([
+            ("type", Json::str("error")),
+            ("message", Json::str(message)),
+        ]);
+    }
+    let fields = match frame {
+        Value::Push(fields) | Value::Array(fields) => fields,
+        Value::SimpleString(s) => return Json::object([("type", Json::str(s.to_lowercase()))]),
+        frame => return Json::from(frame),
+    };
+
+    let mut fields = fields.into_iter().map(Json::from);
+    let kind = fields.next().unwrap_or(Json::Null);
+    let names: &[&str] = match &kind {
+        Json::Str(kind) => match kind.as_str() {
+            "message" | "smessage" => &["channel", "data"],
+            "pmessage" => &["pattern", "channel", "data"],
+            "subscribe" | "unsubscribe" | "ssubscribe" | "sunsubscribe" => &["channel", "count"],
+            "psubscribe" | "punsubscribe" => &["pattern", "count"],
+            _ => &[],
+        },
+        _ => &[],
+    };
+    if names.is_empty() {
+        return Json::Array(std::iter::once(kind).chain(fields).collect());
+    }
+    let named = names.iter().map(|name| name.to_string()).zip(fields);
+    Json::object(std::iter::once(("type".to_string(), kind)).chain(named))
+}
+
+/// The connection, once it's been upgraded.
+struct Socket {
+    stream: TcpStream,
+    /// What's been read but not yet made into frames.
+    buf: BytesMut,
+}
+
+impl Socket {
+    /// The next frame the other end sends, or `None` once the connection's closed or isn't
+    /// speaking WebSocket: every frame from a client has to be masked. Safe to cancel, as all it waits on is
+    /// reading into the buffer.
+    async fn read_frame(&mut self) -> Option<Frame> {
+        loop {
+            match parse_frame(&self.buf) {
+                Ok(Some((frame, len))) => {
+                    self.buf.advance(len);
+                    return Some(frame);
+                }
+                Ok(None) => {}
+                Err(code) => {
+                    self.close(code).await;
+                    return None;
+                }
+            }
+            if self.stream.read_buf(&mut self.buf).await.ok()? == 0 {
+                return None;
+            }
+        }
+    }
+
+    /// Sends each of `frames` as a JSON text message.
+    async fn send_frames(&mut self, frames: Vec<Value>) -> std::io::Result<()> {
+        for frame in frames {
+            self.send(TEXT, to_json(frame).to_string().as_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..126 => frame.push(len as u8),
+            len @ 126..65536 => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame).await
+    }
+
+    /// Closes the connection with status `code`, for the other end doing something it
+    /// shouldn't.
+    async fn close(&mut self, code: u16) {
+        let _ = self.send(CLOSE, &code.to_be_bytes()).await;
+    }
+}
+
+/// Parses a frame from the front of `buf`, returning it and the bytes it spans, or `None` if
+/// it isn't all there yet. Fails with the status to close the connection with if the frame's
+/// unmasked, or too large to take.
+fn parse_frame(buf: &[u8]) -> Result<Option<(Frame, usize)>, u16> {
+    let [first, second, ..] = *buf else {
+        return Ok(None);
+    };
+    let (fin, opcode) = (first & 0x80 != 0, first & 0x0f);
+    if second & 0x80 == 0 {
+        return Err(1002);
+    }
+    let (len, mut at) = match second & 0x7f {
+        126 => match buf.get(2..4) {
+            Some(len) => (u16::from_be_bytes([len[0], len[1]]) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match buf.get(2..10) {
+            Some(len) => (u64::from_be_bytes(len.try_into().unwrap()), 10),
+            None => return Ok(None),
+        },
+        len => (len as u64, 2),
+    };
+    if len > MAX_MESSAGE as u64 {
+        return Err(1009);
+    }
+    let Some(mask) = buf.get(at..at + 4) else {
+        return Ok(None);
+    };
+    let mask: [u8; 4] = mask.try_into().unwrap();
+    at += 4;
+    let Some(payload) = buf.get(at..at + len as usize) else {
+        return Ok(None);
+    };
+    let payload = payload
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+    let frame = Frame {
+        fin,
+        opcode,
+        payload,
+    };
+    Ok(Some((frame, at + len as usize)))
+}
@@ -0,0 +1,200 @@
+use crate::encoding::{self, EncodedIter, ZSET_MAX_LISTPACK_ENTRIES, ZSET_MAX_LISTPACK_VALUE};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// An `f64` with a total order, so scores can key an ordered set. NaN is never stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Clone)]
+enum Members {
+    /// Entries in `(score, member)` order, searched directly.
+    Listpack(Vec<(Score, Vec<u8>)>),
+    /// An ordered index plus a side map for O(1) score lookups by member.
+    Skiplist {
+        scores: HashMap<Vec<u8>, f64>,
+        ordered: BTreeSet<(Score, Vec<u8>)>,
+    },
+}
+
+/// A sorted set: members ordered by `(score, member)`. Small sets keep a flat sorted list and
+/// move to the indexed form once they outgrow the listpack thresholds.
+#[derive(Clone)]
+pub struct ZSet {
+    members: Members,
+}
+
+impl Default for ZSet {
+    fn default() -> Self {
+        Self {
+            members: Members::Listpack(Vec::new()),
+        }
+    }
+}
+
+impl ZSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.members {
+            Members::Listpack(entries) => entries.len(),
+            Members::Skiplist { scores, .. } => scores.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The name reported by `OBJECT ENCODING`.
+    pub fn encoding(&self) -> &'static str {
+        match &self.members {
+            Members::Listpack(_) => "listpack",
+            Members::Skiplist { .. } => "skiplist",
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        match &self.members {
+            Members::Listpack(entries) => entries
+                .iter()
+                .find(|(_, m)| m == member)
+                .map(|(score, _)| score.0),
+            Members::Skiplist { scores, .. } => scores.get(member).copied(),
+        }
+    }
+
+    /// Sets `member`'s score, returning the previous score if it was already present.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> Option<f64> {
+        // Fold -0 into 0 so the total order agrees with numeric comparisons
+        let score = score + 0.0;
+
+        let entries = match &mut self.members {
+            Members::Listpack(entries) => entries,
+            Members::Skiplist { scores, ordered } => {
+                let old = scores.insert(member.clone(), score);
+                if let Some(old) = old {
+                    ordered.remove(&(Score(old), member.clone()));
+                }
+                ordered.insert((Score(score), member));
+
+                return old;
+            }
+        };
+
+        let old = entries
+            .iter()
+            .position(|(_, m)| *m == member)
+            .map(|pos| entries.remove(pos).0.0);
+
+        let longest = member.len();
+        let entry = (Score(score), member);
+        let pos = entries.partition_point(|e| *e < entry);
+        entries.insert(pos, entry);
+
+        if !encoding::fits(
+            entries.len(),
+            longest,
+            &ZSET_MAX_LISTPACK_ENTRIES,
+            &ZSET_MAX_LISTPACK_VALUE,
+        ) {
+            let ordered: BTreeSet<(Score, Vec<u8>)> = std::mem::take(entries).into_iter().collect();
+            let scores = ordered
+                .iter()
+                .map(|(score, member)| (member.clone(), score.0))
+                .collect();
+            self.members = Members::Skiplist { scores, ordered };
+        }
+
+        old
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        match &mut self.members {
+            Members::Listpack(entries) => {
+                let pos = entries.iter().position(|(_, m)| m == member)?;
+                Some(entries.remove(pos).0.0)
+            }
+            Members::Skiplist { scores, ordered } => {
+                let score = scores.remove(member)?;
+                ordered.remove(&(Score(score), member.to_vec()));
+
+                Some(score)
+            }
+        }
+    }
+
+    /// Removes and returns the lowest-ranked member, or the highest-ranked with `max`.
+    pub fn pop(&mut self, max: bool) -> Option<(Vec<u8>, f64)> {
+        let (score, member) = match &mut self.members {
+            Members::Listpack(entries) if max => entries.pop()?,
+            Members::Listpack(entries) if entries.is_empty() => return None,
+            Members::Listpack(entries) => entries.remove(0),
+            Members::Skiplist { scores, ordered } => {
+                let entry = if max {
+                    ordered.pop_last()?
+                } else {
+                    ordered.pop_first()?
+                };
+                scores.remove(&entry.1);
+
+                entry
+            }
+        };
+
+        Some((member, score.0))
+    }
+
+    /// Zero-based position of `member` in ascending order.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        match &self.members {
+            Members::Listpack(entries) => entries.iter().position(|(_, m)| m == member),
+            Members::Skiplist { ordered, .. } => {
+                let score = self.score(member)?;
+
+                Some(ordered.range(..(Score(score), member.to_vec())).count())
+            }
+        }
+    }
+
+    /// Members in ascending order, starting at the first one scoring at least `min`.
+    pub fn iter_from(&self, min: f64) -> impl Iterator<Item = (&[u8], f64)> {
+        let entries = match &self.members {
+            Members::Listpack(entries) => {
+                let start = entries.partition_point(|(score, _)| *score < Score(min));
+                EncodedIter::Compact(entries[start..].iter())
+            }
+            Members::Skiplist { ordered, .. } => {
+                EncodedIter::Full(ordered.range((Score(min), Vec::new())..))
+            }
+        };
+
+        entries.map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Members in ascending `(score, member)` order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        let entries = match &self.members {
+            Members::Listpack(entries) => EncodedIter::Compact(entries.iter()),
+            Members::Skiplist { ordered, .. } => EncodedIter::Full(ordered.iter()),
+        };
+
+        entries.map(|(score, member)| (member.as_slice(), score.0))
+    }
+}
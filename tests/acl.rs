@@ -0,0 +1,89 @@
+use common::{call, command, connect};
+use redis::TestServer;
+use redis::resp::{RespHandler, Value};
+use std::time::Duration;
+
+mod common;
+
+async fn log_in(server: &TestServer, user: &str) -> RespHandler {
+    let mut client = connect(server).await;
+    let reply = call(&mut client, &["AUTH", user, "secret"]).await;
+    assert!(matches!(reply, Value::SimpleString(s) if s == "OK"));
+    client
+}
+
+// Every command's keys are looked for in its arguments, for the ACL check and the user's
+// namespace, before it runs. None of that may trip over there being none.
+#[tokio::test]
+async fn survives_every_command_sent_without_arguments() {
+    let server = TestServer::start().unwrap();
+    let mut admin = connect(&server).await;
+    let user = [
+        "ACL",
+        "SETUSER",
+        "tester",
+        "on",
+        ">secret",
+        "~*",
+        "&*",
+        "+@all",
+        "namespace:t",
+    ];
+    call(&mut admin, &user).await;
+
+    let Value::Array(commands) = call(&mut admin, &["COMMAND"]).await else {
+        panic!("COMMAND didn't reply with an array");
+    };
+    let names = commands.into_iter().map(|info| match info {
+        Value::Array(info) => match &info[0] {
+            Value::BulkString(name) => String::from_utf8_lossy(name).into_owned(),
+            name => panic!("a command named {name:?}"),
+        },
+        info => panic!("command info {info:?}"),
+    });
+    for name in names {
+        if name == "shutdown" {
+            continue;
+        }
+        // A fresh connection each, as some take the connection over or close it
+        let mut client = log_in(&server, "tester").await;
+        client.write(command(&[&name])).await.unwrap();
+        let _ = tokio::time::timeout(Duration::from_millis(100), client.read()).await;
+
+        let reply = call(&mut admin, &["PING"]).await;
+        assert!(
+            matches!(&reply, Value::SimpleString(s) if s == "PONG"),
+            "the server went after {name}: {reply:?}"
+        );
+    }
+
+    // Users are still there to log in as
+    let mut client = log_in(&server, "tester").await;
+    let reply = call(&mut client, &["MEMORY"]).await;
+    assert!(
+        matches!(&reply, Value::Error(e) if e == "ERR wrong number of arguments for 'memory' command")
+    );
+}
+
+#[tokio::test]
+async fn a_command_refused_inside_multi_discards_the_transaction() {
+    let server = TestServer::start().unwrap();
+    let mut admin = connect(&server).await;
+    let user = [
+        "ACL", "SETUSER", "limited", "on", ">secret", "~*", "+@all", "-set",
+    ];
+    call(&mut admin, &user).await;
+
+    let mut client = log_in(&server, "limited").await;
+    call(&mut client, &["MULTI"]).await;
+    let reply = call(&mut client, &["SET", "k", "v"]).await;
+    assert!(matches!(&reply, Value::Error(e) if e.starts_with("NOPERM")));
+    let reply = call(&mut client, &["RPUSH", "list", "x"]).await;
+    assert!(matches!(&reply, Value::SimpleString(s) if s == "QUEUED"));
+    let reply = call(&mut client, &["EXEC"]).await;
+    assert!(matches!(&reply, Value::Error(e) if e.starts_with("EXECABORT")));
+    assert!(matches!(
+        call(&mut admin, &["LLEN", "list"]).await,
+        Value::Integer(0)
+    ));
+}
@@ -0,0 +1,235 @@
+use common::{call, connect, text};
+use redis::config::ServerConfig;
+use redis::resp::Value;
+use redis::{Server, TestServer};
+
+mod common;
+
+/// Commands as Redis writes them to an append-only file.
+fn commands(commands: &[&[&str]]) -> String {
+    let mut out = String::new();
+    for parts in commands {
+        out += &format!("*{}\r\n", parts.len());
+        for part in *parts {
+            out += &format!("${}\r\n{part}\r\n", part.len());
+        }
+    }
+    out
+}
+
+#[tokio::test]
+async fn loads_the_append_only_directory_redis_left() {
+    let dir = std::env::temp_dir().join(format!("redis-aof-{}", std::process::id()));
+    let redis_dir = dir.join("appendonlydir");
+    std::fs::create_dir_all(&redis_dir).unwrap();
+    let write =
+        |name: &str, contents: &str| std::fs::write(redis_dir.join(name), contents).unwrap();
+
+    write(
+        "appendonly.aof.manifest",
+        "file appendonly.aof.2.base.aof seq 2 type b\n\
+         file appendonly.aof.1.incr.aof seq 1 type h\n\
+         file appendonly.aof.3.incr.aof seq 3 type i\n\
+         file appendonly.aof.2.incr.aof seq 2 type i\n",
+    );
+    write(
+        "appendonly.aof.2.base.aof",
+        &commands(&[
+            &["SELECT", "0"],
+            &["SET", "a", "base"],
+            &["SET", "b", "base"],
+        ]),
+    );
+    // Replaced by the rewrite that made the base, so never read
+    write("appendonly.aof.1.incr.aof", "not commands");
+    write(
+        "appendonly.aof.2.incr.aof",
+        &format!(
+            "#TS:1760000000\r\n{}",
+            commands(&[
+                &["SET", "a", "incr"],
+                &["MULTI"],
+                &["SELECT", "1"],
+                &["SET", "c", "transaction"],
+                &["EXEC"],
+            ])
+        ),
+    );
+    // Stopped partway through a transaction, which never took effect
+    let last = commands(&[&["SELECT", "0"], &["SET", "b", "incr"]]);
+    let unfinished = commands(&[&["MULTI"], &["SET", "a", "unfinished"]]);
+    write(
+        "appendonly.aof.3.incr.aof",
+        &format!("{last}{unfinished}*3\r\n$3\r\nSET"),
+    );
+
+    let config = ServerConfig {
+        dir: dir.display().to_string(),
+        appendonly: true,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with(Server::builder().config(config)).unwrap();
+
+    let mut client = connect(&server).await;
+    assert_eq!(text(call(&mut client, &["GET", "a"]).await), "incr");
+    assert_eq!(text(call(&mut client, &["GET", "b"]).await), "incr");
+    call(&mut client, &["SELECT", "1"]).await;
+    assert_eq!(text(call(&mut client, &["GET", "c"]).await), "transaction");
+
+    // Redis's files are left be, but for what a crash cut short, and the server carries on in
+    // a file of its own
+    let cut = std::fs::read_to_string(redis_dir.join("appendonly.aof.3.incr.aof")).unwrap();
+    assert_eq!(cut, last);
+    assert!(dir.join("appendonly.aof").exists());
+
+    server.shutdown();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn refuses_a_command_it_cannot_replay_and_says_where() {
+    let dir = std::env::temp_dir().join(format!("redis-aof-bad-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let good = commands(&[&["SET", "a", "1"]]);
+    let bad = commands(&[&["GET"]]);
+    std::fs::write(dir.join("appendonly.aof"), format!("{good}{bad}")).unwrap();
+
+    let config = ServerConfig {
+        dir: dir.display().to_string(),
+        appendonly: true,
+        ..ServerConfig::default()
+    };
+    let e = TestServer::start_with(Server::builder().config(config))
+        .err()
+        .expect("the server started from a file it couldn't replay");
+    assert!(
+        format!("{e:#}").contains(&format!(
+            "Bad file format at offset {}: wrong number of arguments for 'get'",
+            good.len()
+        )),
+        "{e:#}"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn carries_over_what_redis_wrote_with_deadlines_and_counters() {
+    let dir = std::env::temp_dir().join(format!("redis-aof-ttl-{}", std::process::id()));
+    let redis_dir = dir.join("appendonlydir");
+    std::fs::create_dir_all(&redis_dir).unwrap();
+    let write =
+        |name: &str, contents: &str| std::fs::write(redis_dir.join(name), contents).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let later = (now + 3_600_000).to_string();
+
+    write(
+        "appendonly.aof.manifest",
+        "file appendonly.aof.1.base.aof seq 1 type b\n\
+         file appendonly.aof.1.incr.aof seq 1 type i\n",
+    );
+    // A rewrite gives each volatile key its deadline right after it
+    write(
+        "appendonly.aof.1.base.aof",
+        &commands(&[
+            &["SELECT", "0"],
+            &["SET", "volatile", "v"],
+            &["PEXPIREAT", "volatile", &later],
+            &["SET", "gone", "v"],
+            &["PEXPIREAT", "gone", "1000"],
+            &["SET", "counter", "10"],
+        ]),
+    );
+    // And what came after has relative TTLs made absolute, as Redis 7 propagates them
+    write(
+        "appendonly.aof.1.incr.aof",
+        &commands(&[
+            &["SELECT", "0"],
+            &["INCR", "counter"],
+            &["INCRBY", "counter", "5"],
+            &["DECR", "counter"],
+            &["APPEND", "log", "a"],
+            &["APPEND", "log", "b"],
+            &["SET", "session", "s", "PXAT", &later],
+            &["SET", "kept", "k"],
+            &["PEXPIREAT", "kept", &later],
+            &["PERSIST", "kept"],
+        ]),
+    );
+
+    let config = ServerConfig {
+        dir: dir.display().to_string(),
+        appendonly: true,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with(Server::builder().config(config)).unwrap();
+
+    let mut client = connect(&server).await;
+    assert_eq!(text(call(&mut client, &["GET", "volatile"]).await), "v");
+    for key in ["volatile", "session"] {
+        let Value::Integer(ttl) = call(&mut client, &["PTTL", key]).await else {
+            panic!("PTTL replies with an integer");
+        };
+        assert!(ttl > 3_500_000 && ttl <= 3_600_000, "{key}: {ttl}");
+        assert_eq!(
+            text(call(&mut client, &["PEXPIRETIME", key]).await),
+            format!("{:?}", Value::Integer(later.parse().unwrap()))
+        );
+    }
+    assert!(matches!(
+        call(&mut client, &["TTL", "kept"]).await,
+        Value::Integer(-1)
+    ));
+    assert!(matches!(
+        call(&mut client, &["GET", "gone"]).await,
+        Value::Null
+    ));
+    assert_eq!(text(call(&mut client, &["GET", "counter"]).await), "15");
+    assert_eq!(text(call(&mut client, &["GET", "log"]).await), "ab");
+
+    server.shutdown();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn writes_a_transaction_as_one() {
+    let dir = std::env::temp_dir().join(format!("redis-aof-multi-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = ServerConfig {
+        dir: dir.display().to_string(),
+        appendonly: true,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with(Server::builder().config(config)).unwrap();
+
+    let mut client = connect(&server).await;
+    for transaction in [
+        &[&["SET", "a", "1"][..], &["GET", "a"], &["INCR", "n"]][..],
+        &[&["GET", "a"], &["SET", "b", "1"]],
+    ] {
+        call(&mut client, &["MULTI"]).await;
+        for command in transaction {
+            call(&mut client, command).await;
+        }
+        call(&mut client, &["EXEC"]).await;
+    }
+    server.shutdown();
+
+    // Only one with more than a single write needs wrapping to be applied all or nothing
+    // After the snapshot the file starts from
+    let written = std::fs::read(dir.join("appendonly.aof")).unwrap();
+    let written = String::from_utf8_lossy(&written);
+    let expected = commands(&[
+        &["multi"],
+        &["set", "a", "1"],
+        &["incr", "n"],
+        &["exec"],
+        &["set", "b", "1"],
+    ]);
+    assert!(written.ends_with(&expected), "{written:?}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
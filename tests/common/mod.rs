@@ -0,0 +1,35 @@
+//! What the integration tests share for talking to a [`TestServer`].
+
+// Each test crate uses only some of these
+#![allow(dead_code)]
+
+use redis::TestServer;
+use redis::resp::{RespHandler, Value};
+use tokio::net::TcpStream;
+
+pub fn command(parts: &[&str]) -> Value {
+    Value::Array(
+        parts
+            .iter()
+            .map(|part| Value::BulkString(part.to_string().into()))
+            .collect(),
+    )
+}
+
+pub async fn connect(server: &TestServer) -> RespHandler {
+    RespHandler::new(TcpStream::connect(server.addr()).await.unwrap())
+}
+
+pub async fn call(client: &mut RespHandler, parts: &[&str]) -> Value {
+    client.write(command(parts)).await.unwrap();
+    client.read().await.unwrap().unwrap()
+}
+
+/// A reply as text: a status, a bulk string or an error's message.
+pub fn text(reply: Value) -> String {
+    match reply {
+        Value::SimpleString(s) | Value::Error(s) => s,
+        Value::BulkString(s) => String::from_utf8_lossy(&s).into_owned(),
+        reply => format!("{reply:?}"),
+    }
+}
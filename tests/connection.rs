@@ -0,0 +1,75 @@
+use common::{call, command, connect, text};
+use redis::TestServer;
+use redis::resp::Value;
+
+mod common;
+
+#[tokio::test]
+async fn runs_transactions_and_their_watches() {
+    let server = TestServer::start().unwrap();
+    let mut client = connect(&server).await;
+    let mut other = connect(&server).await;
+
+    assert_eq!(text(call(&mut client, &["WATCH", "k"]).await), "OK");
+    call(&mut other, &["SET", "k", "changed"]).await;
+    call(&mut client, &["MULTI"]).await;
+    assert_eq!(
+        text(call(&mut client, &["MULTI"]).await),
+        "ERR MULTI calls can not be nested"
+    );
+    assert_eq!(text(call(&mut client, &["SET", "k", "v"]).await), "QUEUED");
+    assert!(matches!(
+        call(&mut client, &["EXEC"]).await,
+        Value::NullArray
+    ));
+
+    // A queued UNWATCH runs from the queue, the watches already gone
+    call(&mut client, &["MULTI"]).await;
+    assert_eq!(text(call(&mut client, &["UNWATCH"]).await), "QUEUED");
+    assert_eq!(
+        text(call(&mut client, &["WATCH", "k"]).await),
+        "ERR WATCH inside MULTI is not allowed"
+    );
+    let Value::Array(replies) = call(&mut client, &["EXEC"]).await else {
+        panic!("EXEC didn't reply with an array");
+    };
+    assert_eq!(text(replies[0].clone()), "OK");
+
+    call(&mut client, &["MULTI"]).await;
+    call(&mut client, &["SET", "k", "discarded"]).await;
+    assert_eq!(text(call(&mut client, &["DISCARD"]).await), "OK");
+    assert_eq!(
+        text(call(&mut client, &["DISCARD"]).await),
+        "ERR DISCARD without MULTI"
+    );
+    assert_eq!(text(call(&mut client, &["GET", "k"]).await), "changed");
+}
+
+#[tokio::test]
+async fn subscribes_waits_and_quits() {
+    let server = TestServer::start().unwrap();
+    let mut client = connect(&server).await;
+
+    // No replicas to wait for, so WAIT times out with none
+    assert!(matches!(
+        call(&mut client, &["WAIT", "1", "10"]).await,
+        Value::Integer(0)
+    ));
+
+    // Each channel gets a frame of its own
+    client
+        .write(command(&["SUBSCRIBE", "a", "b"]))
+        .await
+        .unwrap();
+    for (channel, count) in [("a", 1), ("b", 2)] {
+        let Some(Value::Array(frame)) = client.read().await.unwrap() else {
+            panic!("SUBSCRIBE didn't reply with a frame");
+        };
+        assert_eq!(text(frame[1].clone()), channel);
+        assert!(matches!(frame[2], Value::Integer(n) if n == count));
+    }
+    assert_eq!(text(call(&mut client, &["RESET"]).await), "RESET");
+
+    assert_eq!(text(call(&mut client, &["QUIT"]).await), "OK");
+    assert!(client.read().await.unwrap().is_none());
+}
@@ -0,0 +1,38 @@
+use common::{call, connect};
+use redis::config::ServerConfig;
+use redis::resp::Value;
+use redis::{Server, TestServer};
+use std::time::Duration;
+
+mod common;
+
+const KEY: &str = "primary:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+fn sealed(file: &[u8]) -> bool {
+    file.starts_with(b"REDISENC")
+        && !file
+            .windows(b"plaintext".len())
+            .any(|at| at == b"plaintext")
+}
+
+#[tokio::test]
+async fn encrypts_persistence_files_with_the_configured_key() {
+    let config = ServerConfig {
+        encryption_key: KEY.to_string(),
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with(Server::builder().config(config)).unwrap();
+    let dir = server.dir();
+
+    let mut client = connect(&server).await;
+    call(&mut client, &["SET", "a", "plaintext"]).await;
+    let reply = call(&mut client, &["SAVE"]).await;
+    assert!(matches!(reply, Value::SimpleString(s) if s == "OK"));
+    assert!(sealed(&std::fs::read(dir.join("dump.rdb")).unwrap()));
+
+    call(&mut client, &["CONFIG", "SET", "appendonly", "yes"]).await;
+    call(&mut client, &["SET", "b", "plaintext"]).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let aof = std::fs::read(dir.join("appendonly.aof")).unwrap();
+    assert!(sealed(&aof));
+}
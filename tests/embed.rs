@@ -0,0 +1,79 @@
+use common::command;
+use redis::Server;
+use redis::config::ServerConfig;
+use redis::resp::{RespHandler, Value};
+use tokio::net::TcpStream;
+
+mod common;
+
+#[tokio::test]
+async fn serves_clients_of_the_embedding_process() {
+    let dir = std::env::temp_dir().join(format!("redis-embed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = ServerConfig {
+        dir: dir.display().to_string(),
+        ..ServerConfig::default()
+    };
+
+    let server = Server::builder()
+        .config(config)
+        .bind("127.0.0.1")
+        .port(0)
+        .build()
+        .await
+        .unwrap();
+    let addr = server.local_addrs()[0];
+    let running = tokio::spawn(server.run());
+
+    let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+    for (request, expected) in [
+        (command(&["PING"]), "PONG"),
+        (command(&["SET", "key", "value"]), "OK"),
+        (command(&["ACL", "SETUSER", "someone", "on"]), "OK"),
+    ] {
+        client.write(request).await.unwrap();
+        let reply = client.read().await.unwrap();
+        assert!(matches!(reply, Some(Value::SimpleString(s)) if s == expected));
+    }
+    client.write(command(&["GET", "key"])).await.unwrap();
+    let reply = client.read().await.unwrap();
+    assert!(matches!(reply, Some(Value::BulkString(v)) if v == b"value"[..]));
+
+    // Shutting down hands the process back, free to build another
+    client
+        .write(command(&["SHUTDOWN", "NOSAVE"]))
+        .await
+        .unwrap();
+    running.await.unwrap().unwrap();
+    let builder = || {
+        Server::builder()
+            .config(ServerConfig {
+                dir: dir.display().to_string(),
+                ..ServerConfig::default()
+            })
+            .bind("127.0.0.1")
+            .port(0)
+    };
+    let next = builder().build().await.unwrap();
+    assert!(builder().build().await.is_err());
+    drop(next);
+
+    // One built fresh forgets what the last left in the process, like its ACL users
+    let fresh = builder().fresh().build().await.unwrap();
+    let addr = fresh.local_addrs()[0];
+    let running = tokio::spawn(fresh.run());
+    let mut client = RespHandler::new(TcpStream::connect(addr).await.unwrap());
+    client.write(command(&["ACL", "USERS"])).await.unwrap();
+    let reply = client.read().await.unwrap();
+    assert!(
+        matches!(&reply, Some(Value::Array(users)) if users.len() == 1),
+        "{reply:?}"
+    );
+    client
+        .write(command(&["SHUTDOWN", "NOSAVE"]))
+        .await
+        .unwrap();
+    running.await.unwrap().unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
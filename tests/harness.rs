@@ -0,0 +1,117 @@
+use common::{call, connect};
+use redis::resp::Value;
+use redis::{Server, TestServer};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+
+mod common;
+
+#[tokio::test]
+async fn starts_and_stops_servers_for_tests() {
+    let server = TestServer::start().unwrap();
+    let addr = server.addr();
+    let mut client = connect(&server).await;
+    let reply = call(&mut client, &["SET", "key", "value"]).await;
+    assert!(matches!(reply, Value::SimpleString(s) if s == "OK"));
+    let reply = call(&mut client, &["GET", "key"]).await;
+    assert!(matches!(reply, Value::BulkString(v) if v == b"value"[..]));
+
+    server.shutdown();
+    assert!(TcpStream::connect(addr).await.is_err());
+
+    // The next one starts out empty
+    let server = TestServer::start().unwrap();
+    let mut client = connect(&server).await;
+    let reply = call(&mut client, &["GET", "key"]).await;
+    assert!(matches!(reply, Value::Null));
+}
+
+#[tokio::test]
+async fn shutdown_stops_only_the_server() {
+    let server = TestServer::start().unwrap();
+    let mut client = connect(&server).await;
+    client
+        .write(common::command(&["SHUTDOWN", "NOSAVE"]))
+        .await
+        .unwrap();
+    assert!(client.read().await.unwrap().is_none());
+    drop(server);
+
+    let server = TestServer::start().unwrap();
+    let reply = call(&mut connect(&server).await, &["PING"]).await;
+    assert!(matches!(reply, Value::SimpleString(s) if s == "PONG"));
+}
+
+#[tokio::test]
+async fn starts_each_server_from_a_clean_slate() {
+    let server = TestServer::start().unwrap();
+    let mut client = connect(&server).await;
+    call(&mut client, &["ACL", "SETUSER", "left", "on"]).await;
+    call(&mut client, &["SCRIPT", "LOAD", "return 1"]).await;
+    call(&mut client, &["CONFIG", "SET", "maxmemory", "1mb"]).await;
+    drop(server);
+
+    let server = TestServer::start().unwrap();
+    let mut client = connect(&server).await;
+    let reply = call(&mut client, &["ACL", "USERS"]).await;
+    assert!(
+        matches!(&reply, Value::Array(users) if users.len() == 1),
+        "{reply:?}"
+    );
+    let reply = call(
+        &mut client,
+        &[
+            "SCRIPT",
+            "EXISTS",
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db",
+        ],
+    )
+    .await;
+    assert!(matches!(&reply, Value::Array(found) if matches!(found[..], [Value::Integer(0)])));
+    let reply = call(&mut client, &["CONFIG", "GET", "maxmemory"]).await;
+    assert!(format!("{reply:?}").contains("\"0\""), "{reply:?}");
+}
+
+#[tokio::test]
+async fn starts_what_a_builder_sets_up_in_the_directory_it_names() {
+    let dir = std::env::temp_dir().join(format!("redis-harness-{}", std::process::id()));
+    let config = redis::config::ServerConfig {
+        dir: dir.display().to_string(),
+        ..Default::default()
+    };
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let hook = {
+        let heard = heard.clone();
+        move |event: &redis::KeyEvent<'_>| heard.lock().unwrap().push(event.key.to_vec())
+    };
+    let server = TestServer::start_with(Server::builder().config(config).on_write(hook)).unwrap();
+    assert_eq!(server.dir(), dir);
+
+    let mut client = connect(&server).await;
+    call(&mut client, &["SET", "key", "value"]).await;
+    call(&mut client, &["SAVE"]).await;
+    assert_eq!(*heard.lock().unwrap(), [b"key".to_vec()]);
+    server.shutdown();
+
+    // It's the test's directory, so it's left for the test to clear up
+    assert!(dir.join("dump.rdb").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn replies_to_failed_commands_with_error_frames() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::start().unwrap();
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream
+        .write_all(b"*1\r\n$4\r\nFOOB\r\n*1\r\n$3\r\nGET\r\n*1\r\n$4\r\nPING\r\n")
+        .await
+        .unwrap();
+
+    let expected: &[u8] = b"-ERR unknown command 'FOOB', with args beginning with: \r\n\
+        -ERR wrong number of arguments for 'get' command\r\n+PONG\r\n";
+    let mut replies = vec![0; expected.len()];
+    stream.read_exact(&mut replies).await.unwrap();
+    assert_eq!(replies, expected);
+}
@@ -0,0 +1,67 @@
+use common::{call, connect};
+use redis::{KeyEvent, Server, TestServer};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+type Heard = Arc<Mutex<Vec<(&'static str, usize, String, String)>>>;
+
+fn listen(heard: &Heard, hook: &'static str) -> impl Fn(&KeyEvent<'_>) + Send + Sync + 'static {
+    let heard = heard.clone();
+    move |event| {
+        let key = String::from_utf8_lossy(event.key).into_owned();
+        let change = (hook, event.db, key, event.event.to_string());
+        heard.lock().unwrap().push(change);
+    }
+}
+
+fn saw(heard: &Heard, hook: &'static str, db: usize, key: &str, event: &str) -> bool {
+    heard
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|change| *change == (hook, db, key.to_string(), event.to_string()))
+}
+
+#[tokio::test]
+async fn tells_the_embedding_program_of_changes_to_keys() {
+    let heard = Heard::default();
+    let builder = Server::builder()
+        .on_write(listen(&heard, "write"))
+        .on_expire(listen(&heard, "expire"))
+        .on_evict(listen(&heard, "evict"));
+    let server = TestServer::start_with(builder).unwrap();
+
+    let mut client = connect(&server).await;
+    call(&mut client, &["SET", "a", "1"]).await;
+    call(&mut client, &["SELECT", "1"]).await;
+    call(&mut client, &["RPUSH", "list", "x"]).await;
+    assert!(saw(&heard, "write", 0, "a", "set"));
+    assert!(saw(&heard, "write", 1, "list", "rpush"));
+
+    // Reads aren't changes
+    let before = heard.lock().unwrap().len();
+    call(&mut client, &["LRANGE", "list", "0", "-1"]).await;
+    assert_eq!(heard.lock().unwrap().len(), before);
+
+    call(&mut client, &["SET", "short", "1", "PX", "10"]).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    call(&mut client, &["GET", "short"]).await;
+    assert!(saw(&heard, "expire", 1, "short", "expired"));
+
+    call(
+        &mut client,
+        &["CONFIG", "SET", "maxmemory-policy", "allkeys-random"],
+    )
+    .await;
+    call(&mut client, &["CONFIG", "SET", "maxmemory", "1"]).await;
+    call(&mut client, &["SET", "b", "2"]).await;
+    assert!(
+        heard
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(hook, _, _, event)| *hook == "evict" && event == "evicted")
+    );
+}
@@ -0,0 +1,80 @@
+use redis::TestServer;
+use redis::resp::{RespHandler, Value};
+
+mod common;
+
+/// Runs a command, with its reply written out the way `render` does.
+async fn call(client: &mut RespHandler, parts: &[&str]) -> String {
+    render(common::call(client, parts).await)
+}
+
+/// A reply as text, with a KEYS reply's keys sorted to compare it whatever order they came in.
+fn render(reply: Value) -> String {
+    match reply {
+        Value::SimpleString(s) => s,
+        Value::BulkString(s) => String::from_utf8_lossy(&s).into_owned(),
+        Value::Integer(n) => n.to_string(),
+        Value::Null => "nil".to_string(),
+        Value::Array(items) => {
+            let mut items: Vec<_> = items.into_iter().map(render).collect();
+            if items.iter().all(|item| !item.starts_with('[')) {
+                items.sort();
+            }
+            format!("[{}]", items.join(" "))
+        }
+        reply => format!("{reply:?}"),
+    }
+}
+
+async fn connect(server: &TestServer, user: Option<&str>) -> RespHandler {
+    let mut client = common::connect(server).await;
+    if let Some(user) = user {
+        assert_eq!(call(&mut client, &["AUTH", user, "secret"]).await, "OK");
+    }
+    client
+}
+
+#[tokio::test]
+async fn confines_users_to_their_namespaces() {
+    let server = TestServer::start().unwrap();
+
+    let mut admin = connect(&server, None).await;
+    for app in ["app1", "app2"] {
+        let namespace = format!("namespace:{app}");
+        let rules = ["ACL", "SETUSER", app, "on", ">secret", "~*", "&*", "+@all"];
+        let mut rules = rules.to_vec();
+        rules.push(&namespace);
+        assert_eq!(call(&mut admin, &rules).await, "OK");
+    }
+
+    let mut app1 = connect(&server, Some("app1")).await;
+    let mut app2 = connect(&server, Some("app2")).await;
+    call(&mut app1, &["SET", "k", "1"]).await;
+    call(&mut app1, &["RPUSH", "q", "x"]).await;
+    assert_eq!(call(&mut app2, &["GET", "k"]).await, "nil");
+    call(&mut app2, &["SET", "k", "2"]).await;
+    assert_eq!(call(&mut app1, &["GET", "k"]).await, "1");
+
+    // Going over the keyspace only goes over the user's own keys, named as it knows them
+    assert_eq!(call(&mut app1, &["DBSIZE"]).await, "2");
+    assert_eq!(call(&mut app2, &["DBSIZE"]).await, "1");
+    assert_eq!(call(&mut app1, &["KEYS", "*"]).await, "[k q]");
+    assert_eq!(call(&mut app2, &["SCAN", "0"]).await, "[0 [k]]");
+    assert_eq!(
+        call(&mut admin, &["KEYS", "*"]).await,
+        "[app1:k app1:q app2:k]"
+    );
+
+    // Replies name keys without the prefix, and scripts' commands get it too
+    assert_eq!(call(&mut app1, &["BLPOP", "q", "0"]).await, "[q x]");
+    let script = "return redis.call('GET', KEYS[1]) .. redis.call('GET', 'k')";
+    assert_eq!(call(&mut app2, &["EVAL", script, "1", "k"]).await, "22");
+    assert_eq!(
+        call(&mut app1, &["XPENDING", "stream", "group"]).await,
+        r#"Error("NOGROUP No such key 'stream' or consumer group 'group'")"#
+    );
+
+    // Flushing only takes the user's own keys
+    assert_eq!(call(&mut app2, &["FLUSHDB"]).await, "OK");
+    assert_eq!(call(&mut admin, &["KEYS", "*"]).await, "[app1:k]");
+}
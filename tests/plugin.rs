@@ -0,0 +1,82 @@
+use common::{call, connect};
+use redis::resp::Value;
+use redis::{CommandPlugin, Database, Server, TestServer};
+
+mod common;
+
+/// `BUMP key amount`: adds `amount` to the number at `key`, replying with the total.
+struct Bump;
+
+impl CommandPlugin for Bump {
+    fn name(&self) -> &str {
+        "BUMP"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn keys(&self) -> [i32; 3] {
+        [1, 1, 1]
+    }
+
+    fn flags(&self) -> &[&str] {
+        &["write", "fast"]
+    }
+
+    fn execute(&self, db: &mut Database<'_>, args: &[Vec<u8>]) -> Value {
+        let Some(amount) = std::str::from_utf8(&args[1])
+            .ok()
+            .and_then(|n| n.parse::<i64>().ok())
+        else {
+            return Value::error("ERR value is not an integer or out of range");
+        };
+        let current = match db.get(&args[0]) {
+            Ok(current) => current,
+            Err(e) => return e,
+        };
+        let total = current
+            .and_then(|n| std::str::from_utf8(&n).ok()?.parse::<i64>().ok())
+            .unwrap_or(0)
+            + amount;
+        db.set(&args[0], total.to_string().as_bytes());
+        Value::Integer(total)
+    }
+}
+
+#[tokio::test]
+async fn runs_commands_the_embedding_program_adds() {
+    let server = TestServer::start_with(Server::builder().command(Bump)).unwrap();
+    let mut client = connect(&server).await;
+    assert!(matches!(
+        call(&mut client, &["bump", "n", "2"]).await,
+        Value::Integer(2)
+    ));
+    assert!(matches!(
+        call(&mut client, &["BUMP", "n", "5"]).await,
+        Value::Integer(7)
+    ));
+    let reply = call(&mut client, &["GET", "n"]).await;
+    assert!(matches!(reply, Value::BulkString(v) if v == b"7"[..]));
+
+    let reply = call(&mut client, &["BUMP", "n"]).await;
+    assert!(
+        reply
+            .error_message()
+            .is_some_and(|e| e.contains("wrong number"))
+    );
+    call(&mut client, &["RPUSH", "list", "a"]).await;
+    let reply = call(&mut client, &["BUMP", "list", "1"]).await;
+    assert!(
+        reply
+            .error_message()
+            .is_some_and(|e| e.starts_with("WRONGTYPE"))
+    );
+
+    // It's listed like the server's own
+    let reply = call(&mut client, &["COMMAND", "INFO", "bump"]).await;
+    let Value::Array(info) = reply else {
+        panic!("COMMAND INFO replied {reply:?}");
+    };
+    assert!(format!("{info:?}").contains("write"));
+}
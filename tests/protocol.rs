@@ -0,0 +1,69 @@
+use redis::TestServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Reads until the server closes the connection, or what it's sent ends with `end`.
+async fn read_until(stream: &mut TcpStream, end: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut buf = [0; 1024];
+    while !out.ends_with(end) {
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        out.extend_from_slice(&buf[..n]);
+    }
+    out
+}
+
+#[tokio::test]
+async fn reads_a_command_sent_a_byte_at_a_time() {
+    let server = TestServer::start().unwrap();
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+
+    let set = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nhello\r\n";
+    for byte in set {
+        stream.write_all(&[*byte]).await.unwrap();
+        stream.flush().await.unwrap();
+    }
+    assert_eq!(read_until(&mut stream, b"\r\n").await, b"+OK\r\n");
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        read_until(&mut stream, b"hello\r\n").await,
+        b"$5\r\nhello\r\n"
+    );
+}
+
+#[tokio::test]
+async fn refuses_lengths_past_the_limits_and_closes_the_connection() {
+    let server = TestServer::start().unwrap();
+
+    // Longer than proto-max-bulk-len, refused before any of it is sent
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$600000000\r\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        read_until(&mut stream, b"never").await,
+        b"-ERR Protocol error: invalid bulk length\r\n"
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream.write_all(b"*3000000000\r\n").await.unwrap();
+    assert_eq!(
+        read_until(&mut stream, b"never").await,
+        b"-ERR Protocol error: invalid multibulk length\r\n"
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream.write_all(&[b'*'; 70 * 1024]).await.unwrap();
+    assert_eq!(
+        read_until(&mut stream, b"never").await,
+        b"-ERR Protocol error: too big header line\r\n"
+    );
+}
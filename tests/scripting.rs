@@ -0,0 +1,55 @@
+use common::{command, connect, text};
+use redis::config::ServerConfig;
+use redis::resp::RespHandler;
+use redis::{Server, TestServer};
+use std::time::Duration;
+
+mod common;
+
+/// Runs a command, with its reply as text.
+async fn call(client: &mut RespHandler, parts: &[&str]) -> String {
+    text(common::call(client, parts).await)
+}
+
+async fn reply(client: &mut RespHandler) -> String {
+    text(client.read().await.unwrap().unwrap())
+}
+
+#[tokio::test]
+async fn answers_busy_while_a_script_runs_long_and_kills_it() {
+    let config = ServerConfig {
+        busy_reply_threshold: 100,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with(Server::builder().config(config)).unwrap();
+
+    let mut looping = connect(&server).await;
+    let mut other = connect(&server).await;
+    assert_eq!(
+        call(&mut other, &["SCRIPT", "KILL"]).await,
+        "NOTBUSY No scripts in execution right now."
+    );
+
+    looping
+        .write(command(&["EVAL", "while true do end", "0"]))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(call(&mut other, &["GET", "k"]).await.starts_with("BUSY "));
+    assert!(
+        call(&mut other, &["FUNCTION", "KILL"])
+            .await
+            .starts_with("BUSY ")
+    );
+
+    assert_eq!(call(&mut other, &["SCRIPT", "KILL"]).await, "OK");
+    assert_eq!(
+        reply(&mut looping).await,
+        "ERR Script killed by user with SCRIPT KILL..."
+    );
+    assert_eq!(call(&mut other, &["PING"]).await, "PONG");
+    assert_eq!(
+        call(&mut looping, &["EVAL", "return 'done'", "0"]).await,
+        "done"
+    );
+}
@@ -0,0 +1,41 @@
+use common::{call, connect};
+use redis::resp::Value;
+use redis::{Server, SnapshotStore, TestServer};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+mod common;
+
+/// Snapshots kept in memory, in place of object storage.
+#[derive(Clone, Default)]
+struct Memory(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+impl SnapshotStore for Memory {
+    fn save(&self, name: &str, bytes: &[u8]) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.0.lock().unwrap().get(name).cloned())
+    }
+}
+
+#[tokio::test]
+async fn keeps_snapshots_in_the_store_it_is_given() {
+    let store = Memory::default();
+    let server = TestServer::start_with(Server::builder().snapshot_store(store.clone())).unwrap();
+
+    let mut client = connect(&server).await;
+    call(&mut client, &["SET", "a", "1"]).await;
+    let reply = call(&mut client, &["SAVE"]).await;
+    assert!(matches!(reply, Value::SimpleString(s) if s == "OK"));
+
+    let saved = store.load("dump.rdb").unwrap().unwrap();
+    assert!(saved.starts_with(b"REDIS"));
+    assert!(!server.dir().join("dump.rdb").exists());
+}
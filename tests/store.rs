@@ -0,0 +1,46 @@
+use redis::Store;
+use std::time::Duration;
+
+#[tokio::test]
+async fn keeps_keys_in_process() {
+    let store = Store::open();
+    store.set(b"name", b"value").await;
+    store.set(b"count", b"42").await;
+    assert_eq!(store.get(b"name").await.unwrap().unwrap(), b"value"[..]);
+    assert_eq!(store.get(b"count").await.unwrap().unwrap(), b"42"[..]);
+    assert!(store.get(b"missing").await.unwrap().is_none());
+
+    let mut keys = store.keys().await;
+    keys.sort();
+    assert_eq!(keys, [&b"count"[..], &b"name"[..]]);
+    assert_eq!(store.entries().await.len(), 2);
+
+    assert!(store.del(b"name").await);
+    assert!(!store.del(b"name").await);
+    assert!(!store.exists(b"name").await);
+    assert_eq!(store.len().await, 1);
+}
+
+#[tokio::test]
+async fn expires_keys() {
+    let store = Store::open();
+    store
+        .set_ex(b"short", b"lived", Duration::from_millis(50))
+        .await;
+    store.set(b"long", b"lived").await;
+    assert!(store.ttl(b"long").await.is_none());
+    assert!(store.expire(b"long", Duration::from_secs(60)).await);
+    assert!(store.ttl(b"long").await.unwrap() > Duration::from_secs(59));
+    assert!(store.persist(b"long").await);
+    assert!(!store.persist(b"long").await);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(store.get(b"short").await.unwrap().is_none());
+    assert!(!store.expire(b"short", Duration::from_secs(1)).await);
+    assert_eq!(store.keys().await, [&b"long"[..]]);
+    assert_eq!(store.purge_expired().await, 0);
+
+    store.set_ex(b"gone", b"soon", Duration::ZERO).await;
+    assert_eq!(store.purge_expired().await, 1);
+    assert_eq!(store.len().await, 1);
+}
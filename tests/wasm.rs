@@ -0,0 +1,113 @@
+#![cfg(feature = "wasm")]
+
+use common::{call, connect};
+use redis::config::ServerConfig;
+use redis::resp::Value;
+use redis::{Server, TestServer};
+
+mod common;
+
+/// GREET, which replies hello; STORE key value, which sets the key and publishes the value on
+/// `stored`; FETCH key, which replies with the key's value; and SPIN, which never returns.
+const MODULE: &str = r#"
+(module
+  (import "redis" "register" (func $register (param i32 i32 i32 i32 i32 i32 i32)))
+  (import "redis" "get" (func $get (param i32 i32) (result i64)))
+  (import "redis" "set" (func $set (param i32 i32 i32 i32)))
+  (import "redis" "publish" (func $publish (param i32 i32 i32 i32)))
+  (import "redis" "reply_simple" (func $reply_simple (param i32 i32)))
+  (import "redis" "reply_bulk" (func $reply_bulk (param i32 i32)))
+  (import "redis" "reply_integer" (func $reply_integer (param i64)))
+  (memory (export "memory") 2)
+  (data (i32.const 0) "greet")
+  (data (i32.const 16) "store")
+  (data (i32.const 32) "spin")
+  (data (i32.const 48) "fetch")
+  (data (i32.const 64) "hello")
+  (data (i32.const 80) "stored")
+  (global $next (mut i32) (i32.const 1024))
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $at i32)
+    (local.set $at (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $at))
+  (func (export "init")
+    (call $register (i32.const 0) (i32.const 5) (i32.const 1) (i32.const 4)
+      (i32.const 0) (i32.const 0) (i32.const 0))
+    (call $register (i32.const 16) (i32.const 5) (i32.const 3) (i32.const 1)
+      (i32.const 1) (i32.const 1) (i32.const 1))
+    (call $register (i32.const 32) (i32.const 4) (i32.const 1) (i32.const 0)
+      (i32.const 0) (i32.const 0) (i32.const 0))
+    (call $register (i32.const 48) (i32.const 5) (i32.const 2) (i32.const 2)
+      (i32.const 1) (i32.const 1) (i32.const 1)))
+  (func (export "greet") (param i32 i32)
+    (call $reply_simple (i32.const 64) (i32.const 5)))
+  (func (export "store") (param $args i32) (param i32)
+    (local $key_len i32) (local $value i32) (local $value_len i32)
+    (local.set $key_len (i32.load (local.get $args)))
+    (local.set $value (i32.add (i32.add (local.get $args) (i32.const 8)) (local.get $key_len)))
+    (local.set $value_len (i32.load (i32.sub (local.get $value) (i32.const 4))))
+    (call $set (i32.add (local.get $args) (i32.const 4)) (local.get $key_len)
+      (local.get $value) (local.get $value_len))
+    (call $publish (i32.const 80) (i32.const 6) (local.get $value) (local.get $value_len))
+    (call $reply_integer (i64.const 1)))
+  (func (export "spin") (param i32 i32)
+    (loop $forever (br $forever)))
+  (func (export "fetch") (param $args i32) (param i32)
+    (local $found i64)
+    (local.set $found
+      (call $get (i32.add (local.get $args) (i32.const 4)) (i32.load (local.get $args))))
+    (if (i64.ne (local.get $found) (i64.const -1))
+      (then (call $reply_bulk
+        (i32.wrap_i64 (i64.shr_u (local.get $found) (i64.const 32)))
+        (i32.wrap_i64 (i64.and (local.get $found) (i64.const 0xffffffff))))))))
+"#;
+
+#[tokio::test]
+async fn runs_commands_webassembly_modules_register() {
+    let module = std::env::temp_dir().join(format!("redis-wasm-{}.wat", std::process::id()));
+    std::fs::write(&module, MODULE).unwrap();
+    let config = ServerConfig {
+        wasm_modules: vec![module.display().to_string()],
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with(Server::builder().config(config)).unwrap();
+    std::fs::remove_file(&module).unwrap();
+
+    let mut client = connect(&server).await;
+    let mut subscriber = connect(&server).await;
+    call(&mut subscriber, &["SUBSCRIBE", "stored"]).await;
+
+    let reply = call(&mut client, &["GREET"]).await;
+    assert!(matches!(reply, Value::SimpleString(s) if s == "hello"));
+    let reply = call(&mut client, &["STORE", "key", "value"]).await;
+    assert!(matches!(reply, Value::Integer(1)));
+    let reply = call(&mut client, &["GET", "key"]).await;
+    assert!(matches!(reply, Value::BulkString(v) if v == b"value"[..]));
+    let message = subscriber.read().await.unwrap().unwrap();
+    assert!(format!("{message:?}").contains("value"));
+
+    let reply = call(&mut client, &["FETCH", "key"]).await;
+    assert!(matches!(reply, Value::BulkString(v) if v == b"value"[..]));
+    let reply = call(&mut client, &["FETCH", "missing"]).await;
+    assert!(matches!(reply, Value::Null));
+    call(&mut client, &["RPUSH", "list", "a"]).await;
+    let reply = call(&mut client, &["FETCH", "list"]).await;
+    assert!(
+        reply
+            .error_message()
+            .is_some_and(|e| e.starts_with("WRONGTYPE"))
+    );
+
+    // Its fuel runs out, and the server goes on
+    let reply = call(&mut client, &["SPIN"]).await;
+    assert!(
+        reply
+            .error_message()
+            .is_some_and(|e| e.contains("too long"))
+    );
+    let reply = call(&mut client, &["GREET"]).await;
+    assert!(matches!(reply, Value::SimpleString(s) if s == "hello"));
+    let reply = call(&mut client, &["GET", "key"]).await;
+    assert!(matches!(reply, Value::BulkString(v) if v == b"value"[..]));
+}